@@ -0,0 +1,173 @@
+use core::fmt::Write;
+
+use heapless::{String, Vec};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{MAX_BINARY_COMMAND_LENGTH, MAX_COMMAND_LENGTH},
+    fault::FaultCode,
+};
+
+/// What the machine is doing, the `state` of the JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RunState {
+    Menu,
+    StrokeEngine,
+    Demo,
+    // Motion commands are rejected until armed
+    Disarmed,
+}
+
+impl RunState {
+    pub fn name(self) -> &'static str {
+        match self {
+            RunState::Menu => "menu",
+            RunState::StrokeEngine => "strokeEngine",
+            RunState::Demo => "demo",
+            RunState::Disarmed => "disarmed",
+        }
+    }
+}
+
+/// The state like the JSON of `MotionState::as_json` without the firmware version,
+/// which the device information has
+/// Encoded with postcard for remotes that find the JSON too costly to parse, e.g. the M5 remote
+/// New fields only go at the end so that older decoders keep working
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BinaryState<'a> {
+    pub state: RunState,
+    // In %
+    pub depth: u32,
+    pub stroke: u32,
+    pub speed: u32,
+    pub sensation: u32,
+    pub pattern: u32,
+    pub bpm: u32,
+    pub load: u32,
+    // In mm and mm/s
+    pub position: f32,
+    pub velocity: f32,
+    pub ease_in: u32,
+    pub control: &'a str,
+    pub torque: u32,
+    pub fault: Option<FaultCode>,
+}
+
+/// The keys of `set:<key>:<value>` with a number as the value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SetKey {
+    Speed,
+    Stroke,
+    Depth,
+    Sensation,
+    Pattern,
+    Accel,
+    Jerk,
+    Bpm,
+    Shuffle,
+    Dwell,
+    Jitter,
+}
+
+impl SetKey {
+    pub fn name(self) -> &'static str {
+        match self {
+            SetKey::Speed => "speed",
+            SetKey::Stroke => "stroke",
+            SetKey::Depth => "depth",
+            SetKey::Sensation => "sensation",
+            SetKey::Pattern => "pattern",
+            SetKey::Accel => "accel",
+            SetKey::Jerk => "jerk",
+            SetKey::Bpm => "bpm",
+            SetKey::Shuffle => "shuffle",
+            SetKey::Dwell => "dwell",
+            SetKey::Jitter => "jitter",
+        }
+    }
+}
+
+/// The actions of `go:<action>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GoAction {
+    SimplePenetration,
+    StrokeEngine,
+    Pause,
+    Resume,
+    Stop,
+    Rearm,
+    Menu,
+    Hold,
+    Demo,
+    Calibrate,
+    Home,
+    Pair,
+    Unpair,
+    Keepalive,
+    Arm,
+    Disarm,
+}
+
+impl GoAction {
+    pub fn name(self) -> &'static str {
+        match self {
+            GoAction::SimplePenetration => "simplePenetration",
+            GoAction::StrokeEngine => "strokeEngine",
+            GoAction::Pause => "pause",
+            GoAction::Resume => "resume",
+            GoAction::Stop => "stop",
+            GoAction::Rearm => "rearm",
+            GoAction::Menu => "menu",
+            GoAction::Hold => "hold",
+            GoAction::Demo => "demo",
+            GoAction::Calibrate => "calibrate",
+            GoAction::Home => "home",
+            GoAction::Pair => "pair",
+            GoAction::Unpair => "unpair",
+            GoAction::Keepalive => "keepalive",
+            GoAction::Arm => "arm",
+            GoAction::Disarm => "disarm",
+        }
+    }
+}
+
+/// A command of the primary command characteristic
+/// New commands, keys and actions only go at the end of their enums
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BinaryCommand<'a> {
+    Set(SetKey, u32),
+    Go(GoAction),
+    // Any other command as text e.g. `profile:<name>` or `set:param:<idx>:<value>`
+    Text(&'a str),
+}
+
+impl BinaryCommand<'_> {
+    /// The text command it stands for. None if it does not fit
+    pub fn to_text(&self) -> Option<String<MAX_COMMAND_LENGTH>> {
+        let mut text = String::new();
+        let written = match self {
+            BinaryCommand::Set(key, value) => write!(text, "set:{}:{}", key.name(), value),
+            BinaryCommand::Go(action) => write!(text, "go:{}", action.name()),
+            BinaryCommand::Text(command) => text.push_str(command).map_err(|_| core::fmt::Error),
+        };
+        written.ok().map(|()| text)
+    }
+}
+
+/// Decode a command. Bytes after it are ignored
+pub fn decode_command(bytes: &[u8]) -> Result<BinaryCommand<'_>, postcard::Error> {
+    postcard::from_bytes(bytes)
+}
+
+/// The `ok:`/`fail:` response to a command as a postcard string
+pub fn encode_response(response: &str) -> Vec<u8, MAX_BINARY_COMMAND_LENGTH> {
+    let mut buffer = [0u8; MAX_BINARY_COMMAND_LENGTH];
+    match postcard::to_slice(response, &mut buffer) {
+        Ok(bytes) => Vec::from_slice(bytes).expect("Fits the buffer it was written to"),
+        Err(err) => {
+            error!("Could not encode the response {:?}", err);
+            Vec::new()
+        }
+    }
+}
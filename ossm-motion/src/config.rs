@@ -1,54 +1,394 @@
+use crate::{float::Real, motion_control::axis::AxisLimits};
+
 // ---- User Parameters ----
-const PULLEY_TOOTH_COUNT: f64 = 20.0;
-const BELT_PITCH: f64 = 2.0;
+// The defaults of the mechanics. Set at runtime with `pulleyToothCount` and `beltPitch`
+pub const PULLEY_TOOTH_COUNT: u32 = 20;
+// The distance between the teeth of the belt in mm, or of the rack of a rack and pinion
+pub const BELT_PITCH: Real = 2.0;
 // The minimum allowed move forward from the homing position
-pub const MIN_MOVE_MM: f64 = 10.0;
+pub const MIN_MOVE_MM: Real = 10.0;
 // The maximum allowed move forward from the homing position
-pub const MAX_MOVE_MM: f64 = 190.0;
+// Replaced at runtime by the travel measured by the calibration
+pub const MAX_MOVE_MM: Real = 190.0;
 // The max total travel distance of the machine
-pub const MAX_TRAVEL_MM: f64 = MAX_MOVE_MM - MIN_MOVE_MM;
+pub const MAX_TRAVEL_MM: Real = MAX_MOVE_MM - MIN_MOVE_MM;
+// The range of travel accepted from the calibration in mm
+pub const MIN_CALIBRATED_TRAVEL_MM: Real = 50.0;
+pub const MAX_CALIBRATED_TRAVEL_MM: Real = 500.0;
+// The motor current in A the calibration takes as pushing against the end of the rail
+// The default of `stallCurrentA`. Depends on the motor
+pub const CALIBRATION_STALL_CURRENT_A: Real = 1.0;
+pub const MIN_CALIBRATION_STALL_CURRENT_A: Real = 0.2;
+pub const MAX_CALIBRATION_STALL_CURRENT_A: Real = 10.0;
+// The smallest travel the soft limits set at runtime can restrict the machine to in mm
+pub const MIN_SOFT_LIMIT_TRAVEL_MM: Real = 20.0;
 // Retracts the machine when the motion is disabled if true or just stops it if false
+// The default of `retractOnDisable` in the runtime config
 pub const RETRACT_ON_MOTION_DISABLED: bool = true;
 // The velocity at which the machine retracts when it is turned off
-// or switching to a different a pattern in mm/s
-pub const RETRACT_VELOCITY: f64 = MOTION_CONTROL_MAX_VELOCITY / 4.0;
-// Change this if your machine is going the wrong way
+// or switching to a different a pattern in mm/s. The default of `retractVelocity`
+pub const RETRACT_VELOCITY: Real = MOTION_CONTROL_MAX_VELOCITY / 4.0;
+// Blends into the new pattern on a pattern change if true or retracts first if false
+pub const SEAMLESS_PATTERN_SWITCHING: bool = true;
+// The velocity limit in % of the set velocity for the move into the new pattern when blending
+pub const PATTERN_TRANSITION_VELOCITY_PCT: Real = 50.0;
+// The range of the tempo in strokes per minute. 0 turns the tempo off to set the velocity directly
+pub const MIN_BPM: u32 = 5;
+pub const MAX_BPM: u32 = 240;
+// The share of the velocity in % the ramp after enabling the motion starts from
+pub const EASE_IN_START_PCT: Real = 20.0;
+// The longest the velocity ramp can be set to in s
+pub const MAX_EASE_IN_S: u32 = 600;
+// How long the torque takes to rise from 0 to 100% in ms. Lowering it is always immediate
+pub const TORQUE_SLEW_TIME_MS: u32 = 300;
+// The longest the torque rise can be set to in ms
+pub const MAX_TORQUE_SLEW_TIME_MS: u32 = 5000;
+// Change this if your machine is going the wrong way, or set `reverseDirection` at runtime
 pub const REVERSE_DIRECTION: bool = false;
+// Maximum velocity in % used by the demo mode
+pub const DEMO_MAX_VELOCITY_PCT: u32 = 40;
+// Maximum depth in % used by the demo mode
+pub const DEMO_MAX_DEPTH_PCT: u32 = 70;
+// How long each step of the demo runs for in s
+pub const DEMO_STEP_DURATION_S: u64 = 30;
+// How long the shuffle mode plays each pattern for in minutes by default
+pub const SHUFFLE_INTERVAL_MIN: u32 = 5;
+// The longest the shuffle interval can be set to in minutes
+pub const MAX_SHUFFLE_INTERVAL_MIN: u32 = 60;
+// The longest hold at the ends of the strokes that can be set in ms
+pub const MAX_DWELL_MS: u32 = 5000;
+// The most the moves can be varied by at random in %
+pub const MAX_JITTER_PCT: u32 = 25;
+// How many strokes the edging pattern builds up the velocity over before it may pause by default
+pub const EDGING_BUILD_STROKES: u32 = 20;
 
 // ---- Critical parameters. No touchy unless you know what you are doing ----
-// Using the full encoder resolution
-const MOTOR_STEPS_PER_REVOLUTION: f64 = 32768.0;
+// Using the full encoder resolution. Set at runtime with `motorStepsPerRevolution`
+pub const MOTOR_STEPS_PER_REVOLUTION: u32 = 32768;
+// The range of the mechanics accepted at runtime
+pub const MIN_PULLEY_TOOTH_COUNT: u32 = 8;
+pub const MAX_PULLEY_TOOTH_COUNT: u32 = 100;
+pub const MIN_BELT_PITCH: Real = 0.5;
+pub const MAX_BELT_PITCH: Real = 20.0;
+pub const MIN_MOTOR_STEPS_PER_REVOLUTION: u32 = 200;
+pub const MAX_MOTOR_STEPS_PER_REVOLUTION: u32 = 1 << 20;
 // How often the motion control loop runs
 pub const MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS: u64 = 10;
+// How often the motor load is read during motion
+pub const LOAD_UPDATE_INTERVAL_MS: u64 = 100;
+// How often motors driving the same axis are checked for being in sync during motion
+pub const SYNC_CHECK_INTERVAL_MS: u64 = 500;
+// How often the motor settings are checked for having been reset by the drive
+pub const SETTINGS_CHECK_INTERVAL_MS: u64 = 1000;
+// How often the position is written again while holding
+pub const HOLD_REFRESH_INTERVAL_MS: u64 = 100;
+// How often the recorder samples the commanded trajectory
+pub const RECORDER_INTERVAL_MS: u64 = 40;
+// How many samples and faults the recorder keeps. 10 s at RECORDER_INTERVAL_MS
+pub const RECORDER_LENGTH: usize = 250;
+// How many events are kept for the remotes that did not read them yet
+pub const MAX_EVENTS: usize = 16;
+// The machine is stopped with a fault if the control loop did not run for this long during a move
+pub const MOTION_CONTROL_WATCHDOG_TIMEOUT_MS: u64 = 100;
+// The machine is stopped with a fault if the load stayed above this share of the torque limit
+// for OBSTRUCTION_TIME_MS during a move. 0 ms turns the detection off
+pub const OBSTRUCTION_LOAD_RATIO: Real = 0.95;
+pub const OBSTRUCTION_TIME_MS: u64 = 1000;
 // In mm/s
 // Has to be larger than 0
-pub const MOTION_CONTROL_MIN_VELOCITY: f64 = 0.001;
+pub const MOTION_CONTROL_MIN_VELOCITY: Real = 0.001;
 // In mm/s
-pub const MOTION_CONTROL_MAX_VELOCITY: f64 = 600.0;
+pub const MOTION_CONTROL_MAX_VELOCITY: Real = 600.0;
+// In mm/s²
+pub const MOTION_CONTROL_MAX_ACCELERATION: Real = 30000.0;
+// In mm/s³
+pub const MOTION_CONTROL_MAX_JERK: Real = 100000.0;
+// The lowest acceleration and jerk that can be set at runtime
 // In mm/s²
-pub const MOTION_CONTROL_MAX_ACCELERATION: f64 = 30000.0;
+pub const MOTION_CONTROL_MIN_ACCELERATION: Real = 1000.0;
 // In mm/s³
-pub const MOTION_CONTROL_MAX_JERK: f64 = 100000.0;
-// pub const MOTION_CONTROL_MAX_VELOCITY: f64 = 10000.0;
+pub const MOTION_CONTROL_MIN_JERK: Real = 5000.0;
+// pub const MOTION_CONTROL_MAX_VELOCITY: Real = 10000.0;
 // // In mm/s²
-// pub const MOTION_CONTROL_MAX_ACCELERATION: f64 = 100000.0;
+// pub const MOTION_CONTROL_MAX_ACCELERATION: Real = 100000.0;
 // // In mm/s³
-// pub const MOTION_CONTROL_MAX_JERK: f64 = 100000.0;
+// pub const MOTION_CONTROL_MAX_JERK: Real = 100000.0;
 // Turn the machine off after no heartbeat was received for this long
+// The default of `heartbeatTimeoutMs` in the runtime config
 pub const MAX_NO_REMOTE_HEARTBEAT_MS: u64 = 8000;
-// Min output in torque mode. 0-60
-pub const MOTOR_MIN_OUTPUT: f64 = 12.0;
-// Max output in torque mode. 0-60
-pub const MOTOR_MAX_OUTPUT: f64 = 60.0;
+// The range the heartbeat timeout can be set to at runtime in ms
+pub const MIN_HEARTBEAT_TIMEOUT_MS: u64 = 2000;
+pub const MAX_HEARTBEAT_TIMEOUT_MS: u64 = 60000;
+// How often the heartbeat with the machine limits and state is sent to the remote
+pub const REMOTE_HEARTBEAT_INTERVAL_MS: u64 = 5000;
+// A remote keeps the control of the motion for this long after its last command
+// The motion commands of the other remotes are rejected until then
+pub const CONTROL_TIMEOUT_MS: u64 = 10000;
+// Turn the motion off after it ran for this long without a break in minutes. 0 never does
+// The default of `autoOffMin` in the runtime config
+pub const AUTO_OFF_MIN: u32 = 0;
+pub const MAX_AUTO_OFF_MIN: u32 = 240;
+// Require the pair button to be pressed right before arming on the boards that have it
+pub const ARM_REQUIRES_BUTTON: bool = false;
+// How long after the button was pressed arming is accepted
+pub const ARM_BUTTON_WINDOW_MS: u64 = 10000;
+
+// ---- Additional axis parameters ----
+// The most axes motion control can plan together. The stroke is always the first one
+pub const MAX_AXES: usize = 2;
+// Limits of the axes planned after the stroke in the same order
+pub const ADDITIONAL_AXIS_LIMITS: [AxisLimits; MAX_AXES - 1] = [
+    // Twist attachment in degrees
+    AxisLimits {
+        min_position: -180.0,
+        max_position: 180.0,
+        max_velocity: 360.0,
+        max_acceleration: 3600.0,
+        max_jerk: 36000.0,
+        steps_per_unit: MOTOR_STEPS_PER_REVOLUTION as Real / 360.0,
+    },
+];
 
 // ---- BLE parameters ----
-pub const CONNECTIONS_MAX: usize = 1;
-pub const L2CAP_CHANNELS_MAX: usize = 2;
+// How many centrals can be connected at the same time e.g. a phone app and a dashboard
+pub const CONNECTIONS_MAX: usize = 3;
+// Signalling and ATT for each connection
+pub const L2CAP_CHANNELS_MAX: usize = 2 * CONNECTIONS_MAX;
 pub const MAX_COMMAND_LENGTH: usize = 64;
-pub const MAX_STATE_LENGTH: usize = 128;
-pub const MAX_PATTERN_LENGTH: usize = 256;
+// A central that wrote `go:keepalive` loses the control of the motion and the motion stops if it
+// writes nothing for this long. The others are only supervised by the BLE link
+pub const BLE_KEEPALIVE_TIMEOUT_MS: u64 = 3000;
+// How often the state is notified in ms. The least time between notifications when only
+// changes are notified
+pub const STATE_NOTIFY_INTERVAL_MS: u64 = 500;
+// The range the state interval can be set to at runtime in ms
+pub const MIN_STATE_NOTIFY_INTERVAL_MS: u64 = 50;
+pub const MAX_STATE_NOTIFY_INTERVAL_MS: u64 = 10000;
+// When only changes are notified the state is still notified this often while nothing changes
+pub const STATE_KEEPALIVE_MS: u64 = 5000;
+// Fits the state JSON with the longest values and firmware version
+pub const MAX_STATE_LENGTH: usize = 320;
+// Fits the binary state with the largest values. Most of them take a byte
+pub const MAX_BINARY_STATE_LENGTH: usize = 64;
+// Fits a binary text command of MAX_COMMAND_LENGTH with its tag and length
+pub const MAX_BINARY_COMMAND_LENGTH: usize = MAX_COMMAND_LENGTH + 4;
+// The longest a streamed position without a velocity takes to reach in ms. Scrubbing clients
+// send them at up to 60 Hz, so a longer gap is a pause rather than a slow move
+pub const MAX_POSITION_INTERVAL_MS: u64 = 100;
+// Fits the list of all patterns as JSON
+pub const MAX_PATTERN_LENGTH: usize = 512;
+pub const MAX_PATTERN_PARAMS_LENGTH: usize = 384;
+pub const MAX_CAPABILITIES_LENGTH: usize = 256;
+// The most a BLE attribute can hold
+pub const MAX_PROTOCOL_LENGTH: usize = 512;
+pub const MAX_CONFIG_LENGTH: usize = MAX_PROTOCOL_LENGTH;
+// Fits every issue found by the checks of the config
+pub const MAX_DIAGNOSTICS_LENGTH: usize = 256;
+// Fits the settings backup without the passwords. Imported a write at a time
+pub const MAX_SETTINGS_LENGTH: usize = 512;
+pub const MAX_PLAYLIST_LENGTH: usize = 512;
+pub const MAX_RECORD_LENGTH: usize = 48;
+pub const MAX_DEBUG_SAMPLE_LENGTH: usize = 48;
+// Fits the strings of the device information service e.g. the firmware version
+pub const MAX_DEVICE_INFO_LENGTH: usize = 32;
+pub const MAX_EVENT_LENGTH: usize = 64;
+// Every how many control loop updates a sample of the trajectory is streamed for debugging
+pub const DEBUG_STREAM_DECIMATION: u32 = 5;
+
+// ---- ESP-NOW parameters ----
+// Encrypt the link with the M5 remote. The remote has to use the same keys
+// Off by default as the stock M5 firmware does not encrypt
+pub const ESP_NOW_ENCRYPT: bool = false;
+// The primary and local master keys. Change both for your machine and remote
+pub const ESP_NOW_PMK: [u8; 16] = *b"ossm-rs-pmk-0001";
+pub const ESP_NOW_LMK: [u8; 16] = *b"ossm-rs-lmk-0001";
+// How long new remotes can pair after boot or opening the pairing window in s
+pub const PAIRING_WINDOW_S: u64 = 60;
+// How many remotes can be paired at the same time. They are kept in the settings
+pub const MAX_REMOTES: usize = 4;
+// Commands to remotes that acknowledge them are sent again if there was no ack for this long
+pub const ESP_NOW_ACK_TIMEOUT_MS: u64 = 100;
+// How many times a command is sent again before giving up
+pub const ESP_NOW_MAX_RETRIES: u32 = 5;
+// How often the full motion state is sent to the M5 remote
+pub const M5_STATE_INTERVAL_MS: u64 = 1000;
+// Longer pattern names are cut off in the pattern list sent to the M5 remote
+pub const M5_PATTERN_NAME_LENGTH: usize = 32;
+
+// ---- Sync parameters ----
+// Followers start the moves of the leader this long after it to absorb the radio jitter
+pub const SYNC_DELAY_MS: u64 = 100;
+// The longest extra delay a follower can be set to with `syncOffsetMs`
+pub const MAX_SYNC_OFFSET_MS: u64 = 2000;
+// How often the leader sends its clock when there is no move to send
+pub const SYNC_CLOCK_INTERVAL_MS: u64 = 1000;
+// The clock offset is the least of this many packets, so that it follows a drifting clock
+pub const SYNC_CLOCK_WINDOW: u32 = 16;
+// Moves a follower keeps until they are due. Enough for fast strokes with the largest offset
+pub const SYNC_QUEUE_LENGTH: usize = 64;
+
+// ---- Console parameters ----
+// The USB serial console counts as a connected remote for this long after the last line
+// Send empty lines to keep the motion running when nothing else is sent
+pub const CONSOLE_TIMEOUT_MS: u64 = 10000;
+// Fits `wifi:connect:<ssid>:<password>` with the longest SSID and password
+pub const MAX_CONSOLE_LINE_LENGTH: usize = MAX_WIFI_COMMAND_LENGTH + 8;
+// Fits the diagnostics and the register responses
+pub const MAX_CONSOLE_RESPONSE_LENGTH: usize = 192;
+
+// ---- WiFi parameters ----
+// How long to wait before joining the network again after the connection failed or dropped
+pub const WIFI_RECONNECT_DELAY_MS: u64 = 5000;
+// Asked for from DHCP and answered for as `<HOSTNAME>.local` over mDNS
+pub const HOSTNAME: &str = "ossm";
+// Sockets of the network stack. DHCP, mDNS, MQTT, T-Code, Buttplug and syslog take one each
+// and the web UI `HTTP_CONNECTIONS`
+pub const NETWORK_SOCKETS: usize = 8;
+// Fits `connect:<ssid>:<password>` and `mqtt:<ip>:<port>:<user>:<password>` with the
+// longest values
+pub const MAX_WIFI_COMMAND_LENGTH: usize = 128;
+// Fits the status JSON with the longest SSID
+pub const MAX_WIFI_STATUS_LENGTH: usize = 160;
+
+// How long the mDNS answers are cached
+pub const MDNS_TTL_S: u32 = 120;
+// Fits every record the machine announces and the queries it answers
+pub const MDNS_BUFFER_SIZE: usize = 512;
+
+// ---- Web UI parameters ----
+pub const HTTP_PORT: u16 = 80;
+// Requests served at the same time. Browsers open more than one connection
+pub const HTTP_CONNECTIONS: usize = 2;
+// Connections that send nothing for this long are closed
+pub const HTTP_TIMEOUT_MS: u64 = 5000;
+// The request line, the headers and the body together
+pub const MAX_HTTP_REQUEST_LENGTH: usize = 1024;
+// The TCP buffers of each connection in each direction
+pub const HTTP_BUFFER_SIZE: usize = 1024;
+// The web UI counts as a connected remote for this long after the last request
+// The page polls the state every second while it is open
+pub const WEB_TIMEOUT_MS: u64 = 5000;
+// Set when building to require `Authorization: Bearer <token>` for the `/api/` routes
+// Anyone on the network can use them without one
+pub const HTTP_API_TOKEN: Option<&str> = option_env!("OSSM_API_TOKEN");
+
+// ---- T-Code parameters ----
+// The TCP port players connect to for T-Code
+pub const TCODE_PORT: u16 = 8000;
+// Longer lines are dropped. Players send one line per update
+pub const MAX_TCODE_LINE_LENGTH: usize = 128;
+// Fits the answers to all the device commands in one line
+pub const MAX_TCODE_RESPONSE_LENGTH: usize = 96;
+
+// ---- Buttplug parameters ----
+// The name the machine gives the device websocket server of Intiface. The device config of
+// Intiface maps it to the T-Code v0.3 protocol
+pub const BUTTPLUG_IDENTIFIER: &str = "ossm";
+// How often the connection is checked while Intiface has nothing to send
+pub const BUTTPLUG_KEEPALIVE_S: u64 = 10;
+// How long to wait before connecting to Intiface again after the connection failed or dropped
+pub const BUTTPLUG_RECONNECT_DELAY_MS: u64 = 5000;
+// The TCP buffers in each direction and the largest message from Intiface
+pub const BUTTPLUG_BUFFER_SIZE: usize = 256;
+
+// ---- MQTT parameters ----
+// The topics are under `<MQTT_DEVICE_ID>/` and the Home Assistant entities are named after it
+// Change it when more than one machine uses the same broker
+pub const MQTT_DEVICE_ID: &str = "ossm";
+// The broker disconnects the machine if it sends nothing for 1.5 times this long
+pub const MQTT_KEEPALIVE_S: u16 = 60;
+// How often the state topics are checked for changes
+pub const MQTT_STATE_INTERVAL_MS: u64 = 1000;
+// How long to wait before connecting to the broker again after the connection failed or dropped
+pub const MQTT_RECONNECT_DELAY_MS: u64 = 5000;
+// Fits the largest packet sent or received, the Home Assistant config of the pattern select
+pub const MQTT_BUFFER_SIZE: usize = 1024;
+
+// ---- OTA parameters ----
+// The Ed25519 public key updates have to be signed with, 64 hex digits set when building
+// Updates are rejected without one
+pub const OTA_PUBLIC_KEY: Option<&str> = option_env!("OSSM_OTA_PUBLIC_KEY");
+// An update that does not run this long is rolled back on the next boot
+pub const OTA_CONFIRM_DELAY_MS: u64 = 60000;
+
+// ---- Log parameters ----
+// The most recent log lines kept for the BLE and syslog streams
+pub const LOG_BUFFER_LINES: usize = 32;
+// Longer lines are cut off in the streams but printed in full to the console
+pub const MAX_LOG_LINE_LENGTH: usize = 128;
+// How often the streams check for new lines
+pub const LOG_POLL_MS: u64 = 100;
+
+// ---- Power parameters ----
+// Boards that measure the supply rail do so through a voltage divider to an ADC pin
+// The ratio of the supply voltage to the voltage at the pin e.g. 11 for 100 kΩ over 10 kΩ
+pub const SUPPLY_DIVIDER_RATIO: u32 = 11;
+// The supply voltages reported as an empty and a full battery in mV. A 6S Li-ion pack by default
+pub const SUPPLY_EMPTY_MV: u32 = 19800;
+pub const SUPPLY_FULL_MV: u32 = 25200;
+// How often the supply voltage is measured
+pub const SUPPLY_MEASURE_INTERVAL_MS: u64 = 5000;
+
+// ---- Thermal parameters ----
+// In °C. Above a warning temperature the velocity and the torque are capped to
+// THERMAL_WARNING_LIMIT_PCT, above a critical one the machine stops
+// Set at runtime and saved with `motorWarningC`, `motorCriticalC`, `mcuWarningC` and `mcuCriticalC`
+pub const MOTOR_WARNING_C: Real = 70.0;
+pub const MOTOR_CRITICAL_C: Real = 85.0;
+pub const MCU_WARNING_C: Real = 80.0;
+pub const MCU_CRITICAL_C: Real = 95.0;
+// The range of the thresholds accepted at runtime
+pub const MIN_THERMAL_THRESHOLD_C: Real = 30.0;
+pub const MAX_THERMAL_THRESHOLD_C: Real = 125.0;
+pub const THERMAL_WARNING_LIMIT_PCT: Real = 50.0;
+// How far below a threshold the temperature has to drop again to clear it
+pub const THERMAL_HYSTERESIS_C: Real = 5.0;
+// How often the temperatures of the motor and the MCU are read
+pub const TEMPERATURE_UPDATE_INTERVAL_MS: u64 = 1000;
+
+// ---- Profile parameters ----
+pub const MAX_PROFILES: usize = 4;
+pub const MAX_PROFILE_NAME_LENGTH: usize = 16;
+pub const MAX_PROFILES_LENGTH: usize = 512;
+
+// ---- Preset parameters ----
+pub const MAX_PRESETS: usize = 4;
+pub const MAX_PRESET_NAME_LENGTH: usize = 16;
+// Fits MAX_PRESETS with the longest names and values as JSON
+pub const MAX_PRESETS_LENGTH: usize = 512;
+
+// ---- Session parameters ----
+// How often the session is checked for changes. It is saved once unchanged for a whole interval
+// so that turning a knob does not wear out the flash
+pub const SESSION_SAVE_INTERVAL_MS: u64 = 10000;
+
+// ---- Pattern parameters ----
+// The most patterns including the ones registered at runtime
+pub const MAX_PATTERNS: usize = 16;
+// The most parameters besides the sensation a pattern can have
+pub const MAX_PATTERN_PARAMS: usize = 4;
+
+// ---- Playlist parameters ----
+// The most patterns that can be queued. Sized so that the playlist JSON fits into a BLE read
+pub const MAX_PLAYLIST_ENTRIES: usize = 8;
+
+// ---- Funscript parameters ----
+// The most actions an uploaded script can have. 8 bytes of RAM each
+pub const MAX_FUNSCRIPT_ACTIONS: usize = 2048;
+
+// ---- Custom pattern parameters ----
+// Longest source of an expression uploaded for the custom pattern
+pub const MAX_EXPRESSION_LENGTH: usize = 128;
+// Operations and stack size of a compiled expression
+pub const MAX_EXPRESSION_OPS: usize = 48;
+pub const MAX_EXPRESSION_STACK: usize = 8;
+// The longest pause between the moves of the custom pattern
+pub const MAX_CUSTOM_DELAY_MS: u64 = 10000;
+pub const MAX_CUSTOM_PATTERN_LENGTH: usize = 3 * MAX_EXPRESSION_LENGTH + 64;
 
 // ---- Calculated parameters ----
-pub const STEPS_PER_MM: f64 = MOTOR_STEPS_PER_REVOLUTION / (PULLEY_TOOTH_COUNT * BELT_PITCH);
-pub const MM_PER_ROTATION: f64 = MOTOR_STEPS_PER_REVOLUTION / STEPS_PER_MM;
-pub const MAX_RPM: u16 = ((MOTION_CONTROL_MAX_VELOCITY / STEPS_PER_MM) * 60.0) as u16;
+// Of the default mechanics. Motion control uses `get_steps_per_mm()` of the ones set at boot
+pub const MM_PER_ROTATION: Real = PULLEY_TOOTH_COUNT as Real * BELT_PITCH;
+pub const STEPS_PER_MM: Real = MOTOR_STEPS_PER_REVOLUTION as Real / MM_PER_ROTATION;
+// The motor speed MOTION_CONTROL_MAX_VELOCITY takes with the default mechanics
+pub const MAX_RPM: u16 = (MOTION_CONTROL_MAX_VELOCITY / MM_PER_ROTATION * 60.0) as u16;
@@ -0,0 +1,179 @@
+use core::{
+    cell::RefCell,
+    fmt::{self, Write},
+};
+
+use critical_section::Mutex;
+use heapless::{String, Vec};
+use log::error;
+
+use crate::{
+    config::{
+        MAX_DIAGNOSTICS_LENGTH, MAX_MOVE_MM, MIN_CALIBRATED_TRAVEL_MM, MIN_MOVE_MM,
+        MIN_SOFT_LIMIT_TRAVEL_MM, MOTION_CONTROL_MAX_ACCELERATION, MOTION_CONTROL_MAX_JERK,
+        MOTION_CONTROL_MAX_VELOCITY, MOTION_CONTROL_MIN_ACCELERATION, MOTION_CONTROL_MIN_JERK,
+        MOTION_CONTROL_MIN_VELOCITY,
+    },
+    float::Real,
+    motion_control::{
+        get_max_move_mm, get_min_move_mm,
+        mechanics::{get_mechanics, get_steps_per_mm},
+    },
+    runtime_config::get_retract_velocity,
+};
+
+// Every issue at most once
+const MAX_CONFIG_ISSUES: usize = 9;
+
+/// Configuration that would make the machine move wrong without an error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigIssue {
+    MoveRange,
+    SoftLimitTravel,
+    VelocityRange,
+    AccelerationRange,
+    JerkRange,
+    RetractVelocity,
+    StepsPerMm,
+    MotorSpeed,
+    TorqueRange,
+}
+
+impl ConfigIssue {
+    /// Reported in the diagnostics JSON
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigIssue::MoveRange => "move_range",
+            ConfigIssue::SoftLimitTravel => "soft_limit_travel",
+            ConfigIssue::VelocityRange => "velocity_range",
+            ConfigIssue::AccelerationRange => "acceleration_range",
+            ConfigIssue::JerkRange => "jerk_range",
+            ConfigIssue::RetractVelocity => "retract_velocity",
+            ConfigIssue::StepsPerMm => "steps_per_mm",
+            ConfigIssue::MotorSpeed => "motor_speed",
+            ConfigIssue::TorqueRange => "torque_range",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            ConfigIssue::MoveRange => "MIN_MOVE_MM is not below the max move",
+            ConfigIssue::SoftLimitTravel => {
+                "MIN_SOFT_LIMIT_TRAVEL_MM is longer than the shortest calibrated travel"
+            }
+            ConfigIssue::VelocityRange => "The velocity range is empty or not above 0",
+            ConfigIssue::AccelerationRange => "The acceleration range is empty or not above 0",
+            ConfigIssue::JerkRange => "The jerk range is empty or not above 0",
+            ConfigIssue::RetractVelocity => "The retract velocity is outside the velocity range",
+            ConfigIssue::StepsPerMm => "The mechanics give no positive steps per mm",
+            ConfigIssue::MotorSpeed => {
+                "MOTION_CONTROL_MAX_VELOCITY is faster than the motor turns the pulley"
+            }
+            ConfigIssue::TorqueRange => {
+                "The motor output at 0% torque is not below the one at 100% and the drive limit"
+            }
+        }
+    }
+}
+
+/// What the motor can do, to check the configuration against
+pub struct MotorRatings {
+    // The fastest the motor turns
+    pub max_rpm: Real,
+    // The output of the motor at 0% and 100% torque and the most the drive accepts
+    pub min_output: Real,
+    pub max_output: Real,
+    pub output_limit: Real,
+}
+
+static ISSUES: Mutex<RefCell<Vec<ConfigIssue, MAX_CONFIG_ISSUES>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Check the configuration in use and log the issues found
+/// Replaces the issues of the previous check. Run at boot once the saved settings are applied
+pub fn check_config(motor: &MotorRatings) -> Vec<ConfigIssue, MAX_CONFIG_ISSUES> {
+    let max_velocity_of_motor = motor.max_rpm / 60.0 * get_mechanics().mm_per_rotation();
+    let retract_velocity = get_retract_velocity();
+    let steps_per_mm = get_steps_per_mm();
+
+    let checks = [
+        (
+            MIN_MOVE_MM < MAX_MOVE_MM && get_min_move_mm() < get_max_move_mm(),
+            ConfigIssue::MoveRange,
+        ),
+        (
+            MIN_SOFT_LIMIT_TRAVEL_MM <= MIN_CALIBRATED_TRAVEL_MM,
+            ConfigIssue::SoftLimitTravel,
+        ),
+        (
+            is_range(MOTION_CONTROL_MIN_VELOCITY, MOTION_CONTROL_MAX_VELOCITY),
+            ConfigIssue::VelocityRange,
+        ),
+        (
+            is_range(
+                MOTION_CONTROL_MIN_ACCELERATION,
+                MOTION_CONTROL_MAX_ACCELERATION,
+            ),
+            ConfigIssue::AccelerationRange,
+        ),
+        (
+            is_range(MOTION_CONTROL_MIN_JERK, MOTION_CONTROL_MAX_JERK),
+            ConfigIssue::JerkRange,
+        ),
+        (
+            (MOTION_CONTROL_MIN_VELOCITY..=MOTION_CONTROL_MAX_VELOCITY).contains(&retract_velocity),
+            ConfigIssue::RetractVelocity,
+        ),
+        (
+            steps_per_mm.is_finite() && steps_per_mm > 0.0,
+            ConfigIssue::StepsPerMm,
+        ),
+        (
+            MOTION_CONTROL_MAX_VELOCITY <= max_velocity_of_motor,
+            ConfigIssue::MotorSpeed,
+        ),
+        (
+            0.0 <= motor.min_output
+                && motor.min_output < motor.max_output
+                && motor.max_output <= motor.output_limit,
+            ConfigIssue::TorqueRange,
+        ),
+    ];
+
+    let issues: Vec<ConfigIssue, MAX_CONFIG_ISSUES> = checks
+        .iter()
+        .filter(|(ok, _)| !ok)
+        .map(|(_, issue)| *issue)
+        .collect();
+    for issue in &issues {
+        error!("Config issue {}: {}", issue.name(), issue.description());
+    }
+
+    critical_section::with(|cs| *ISSUES.borrow_ref_mut(cs) = issues.clone());
+    issues
+}
+
+fn is_range(min: Real, max: Real) -> bool {
+    min > 0.0 && min <= max
+}
+
+/// The issues found by the last check as JSON e.g. `{"issues":["motor_speed"]}`
+pub fn get_diagnostics_json() -> String<MAX_DIAGNOSTICS_LENGTH> {
+    let mut output = String::new();
+    let issues = critical_section::with(|cs| ISSUES.borrow_ref(cs).clone());
+    if write_issues(&mut output, &issues).is_err() {
+        error!("Could not write the diagnostics. Too long");
+    }
+    output
+}
+
+fn write_issues(output: &mut impl Write, issues: &[ConfigIssue]) -> fmt::Result {
+    output.write_str(r#"{"issues":["#)?;
+    for (i, issue) in issues.iter().enumerate() {
+        if i > 0 {
+            output.write_char(',')?;
+        }
+        write!(output, r#""{}""#, issue.name())?;
+    }
+    output.write_str("]}")
+}
@@ -0,0 +1,105 @@
+use core::{cell::RefCell, fmt::Write};
+
+use critical_section::Mutex;
+use heapless::{HistoryBuf, String};
+use log::error;
+
+use crate::{
+    config::{MAX_EVENT_LENGTH, MAX_EVENTS},
+    fault::FaultCode,
+};
+
+/// Discrete events for the remotes so that they do not have to compare states to notice them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    // A fault was latched. Also written to the recorder
+    Fault(FaultCode),
+    HomingComplete,
+    // The M5 remote stopped sending heartbeats during motion
+    HeartbeatLost,
+    // The motion was turned off after running for `autoOffMin`
+    AutoOff { minutes: u32 },
+    // A playlist entry ran for its duration or was skipped
+    PatternFinished { pattern: u32 },
+    PlaylistFinished,
+    // A pattern asked for more torque than the active profile allows. Only sent once until
+    // the torque is within the limit again
+    TorqueLimited { requested: u32, limit: u32 },
+}
+
+impl Event {
+    pub fn as_json(&self) -> String<MAX_EVENT_LENGTH> {
+        let mut output = String::new();
+
+        let result = match self {
+            Event::Fault(fault) => {
+                write!(output, r#"{{"event":"fault","fault":"{}"}}"#, fault.name())
+            }
+            Event::HomingComplete => write!(output, r#"{{"event":"homing_complete"}}"#),
+            Event::HeartbeatLost => write!(output, r#"{{"event":"heartbeat_lost"}}"#),
+            Event::AutoOff { minutes } => {
+                write!(output, r#"{{"event":"auto_off","minutes":{minutes}}}"#)
+            }
+            Event::PatternFinished { pattern } => write!(
+                output,
+                r#"{{"event":"pattern_finished","pattern":{pattern}}}"#
+            ),
+            Event::PlaylistFinished => write!(output, r#"{{"event":"playlist_finished"}}"#),
+            Event::TorqueLimited { requested, limit } => write!(
+                output,
+                r#"{{"event":"torque_limited","requested":{requested},"limit":{limit}}}"#
+            ),
+        };
+        if result.is_err() {
+            error!("Could not write the event. Too long");
+        }
+
+        output
+    }
+}
+
+/// Every reader keeps the sequence it read up to, so that several remotes each get all events
+struct EventLog {
+    events: HistoryBuf<Event, MAX_EVENTS>,
+    // The sequence number of the next event
+    next: u32,
+}
+
+static EVENTS: Mutex<RefCell<EventLog>> = Mutex::new(RefCell::new(EventLog {
+    events: HistoryBuf::new(),
+    next: 0,
+}));
+
+/// Overwrites the oldest event once full
+pub fn publish_event(event: Event) {
+    critical_section::with(|cs| {
+        let mut log = EVENTS.borrow_ref_mut(cs);
+        log.events.write(event);
+        log.next = log.next.wrapping_add(1);
+    });
+}
+
+/// The sequence of the next event to be published. Readers start here to skip the past ones
+pub fn get_event_sequence() -> u32 {
+    critical_section::with(|cs| EVENTS.borrow_ref(cs).next)
+}
+
+/// The first event from `sequence` on together with the sequence to read next
+/// Events that were overwritten before they were read are skipped
+pub fn next_event(sequence: u32) -> Option<(Event, u32)> {
+    critical_section::with(|cs| {
+        let log = EVENTS.borrow_ref(cs);
+        let unread = log.next.wrapping_sub(sequence) as usize;
+        if unread == 0 {
+            return None;
+        }
+        // Fell behind by more than the log holds
+        let skip = log.events.len().saturating_sub(unread);
+        let event = *log.events.oldest_ordered().nth(skip)?;
+        let read = log
+            .next
+            .wrapping_sub((log.events.len() - skip) as u32)
+            .wrapping_add(1);
+        Some((event, read))
+    })
+}
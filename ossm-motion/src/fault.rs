@@ -0,0 +1,153 @@
+use core::{cell::Cell, sync::atomic::Ordering};
+
+use critical_section::Mutex;
+use log::{error, info};
+use portable_atomic::{AtomicU8, AtomicU32};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    event::{Event, publish_event},
+    motion_control::{arming::disarm, stop_for_fault},
+};
+
+/// Latched until their `Recovery`. The state reports the most severe active one as `fault`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FaultCode {
+    // The motor stopped answering or failed in a way that it may have lost its settings
+    MotorTimeout,
+    // The updates kept taking longer than the update interval and the velocity was lowered
+    Overrun,
+    // The trajectory went past the bounds of the machine and was saturated
+    LimitExceeded,
+    // The motor was at its torque limit for OBSTRUCTION_TIME_MS during a move
+    Obstruction,
+    // The remote in control stopped sending heartbeats during the motion
+    HeartbeatLost,
+    // `go:stop`, the stop characteristic, the stop command of the remotes or the e-stop input
+    EStop,
+    // The control loop did not run for MOTION_CONTROL_WATCHDOG_TIMEOUT_MS during a move
+    LoopStalled,
+    // The motor or the MCU went above its critical temperature
+    Overheat,
+}
+
+/// What clears a fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    // Stops the machine as fast as it can decelerate and disarms it
+    Rearm,
+    // Pauses motion control
+    MotorReconnected,
+    // Keeps the machine moving, slower or within the bounds
+    MotionOff,
+}
+
+// Reported first if more than one is active
+const BY_SEVERITY: [FaultCode; 8] = [
+    FaultCode::EStop,
+    FaultCode::Overheat,
+    FaultCode::Obstruction,
+    FaultCode::HeartbeatLost,
+    FaultCode::LoopStalled,
+    FaultCode::MotorTimeout,
+    FaultCode::LimitExceeded,
+    FaultCode::Overrun,
+];
+
+impl FaultCode {
+    pub fn name(self) -> &'static str {
+        match self {
+            FaultCode::MotorTimeout => "motor_timeout",
+            FaultCode::Overrun => "overrun",
+            FaultCode::LimitExceeded => "limit_exceeded",
+            FaultCode::Obstruction => "obstruction",
+            FaultCode::HeartbeatLost => "heartbeat_lost",
+            FaultCode::EStop => "estop",
+            FaultCode::LoopStalled => "loop_stalled",
+            FaultCode::Overheat => "overheat",
+        }
+    }
+
+    pub fn recovery(self) -> Recovery {
+        match self {
+            FaultCode::EStop
+            | FaultCode::Obstruction
+            | FaultCode::HeartbeatLost
+            | FaultCode::LoopStalled
+            | FaultCode::Overheat => Recovery::Rearm,
+            FaultCode::MotorTimeout => Recovery::MotorReconnected,
+            FaultCode::Overrun | FaultCode::LimitExceeded => Recovery::MotionOff,
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+// One bit per active fault
+static ACTIVE: AtomicU8 = AtomicU8::new(0);
+// One bit per fault raised since the control loop last wrote them to the recorder
+static UNRECORDED: AtomicU8 = AtomicU8::new(0);
+// Faults latched since boot
+static COUNT: AtomicU32 = AtomicU32::new(0);
+static LAST: Mutex<Cell<Option<FaultCode>>> = Mutex::new(Cell::new(None));
+
+/// Latch the fault. The ones cleared by re-arming stop the machine and disarm it as well
+/// Raising a fault that is still active again is only logged once
+/// Counted, published as an event and written to the recorder by the next update
+pub fn raise_fault(code: FaultCode) {
+    let bit = code.bit();
+    if ACTIVE.fetch_or(bit, Ordering::AcqRel) & bit == 0 {
+        let count = COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+        error!("Fault {}: {}", count, code.name());
+        critical_section::with(|cs| LAST.borrow(cs).set(Some(code)));
+        UNRECORDED.fetch_or(bit, Ordering::AcqRel);
+        publish_event(Event::Fault(code));
+    }
+    if code.recovery() == Recovery::Rearm {
+        disarm();
+        stop_for_fault();
+    }
+}
+
+/// Clear the active faults with the recovery
+pub fn clear_faults(recovery: Recovery) {
+    let mask = BY_SEVERITY
+        .iter()
+        .filter(|code| code.recovery() == recovery)
+        .fold(0, |mask, code| mask | code.bit());
+    if ACTIVE.fetch_and(!mask, Ordering::AcqRel) & mask != 0 {
+        info!("Faults cleared by {:?}", recovery);
+    }
+}
+
+/// The most severe active fault. None if there is none
+pub fn get_fault() -> Option<FaultCode> {
+    let active = ACTIVE.load(Ordering::Acquire);
+    BY_SEVERITY
+        .into_iter()
+        .find(|code| active & code.bit() != 0)
+}
+
+pub fn is_fault_active(code: FaultCode) -> bool {
+    ACTIVE.load(Ordering::Acquire) & code.bit() != 0
+}
+
+/// The fault latched last. None if there was none since boot
+pub fn get_last_fault() -> Option<FaultCode> {
+    critical_section::with(|cs| LAST.borrow(cs).get())
+}
+
+/// How many faults were latched since boot
+pub fn get_fault_count() -> u32 {
+    COUNT.load(Ordering::Relaxed)
+}
+
+/// The faults raised since the last call, the most severe first
+pub(crate) fn take_unrecorded_faults() -> impl Iterator<Item = FaultCode> {
+    let unrecorded = UNRECORDED.swap(0, Ordering::AcqRel);
+    BY_SEVERITY
+        .into_iter()
+        .filter(move |code| unrecorded & code.bit() != 0)
+}
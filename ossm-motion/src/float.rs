@@ -0,0 +1,23 @@
+// The float type of the motion math around the trajectory planner. The `f32` feature makes it
+// cheaper on chips without a double precision FPU like the ESP32-C6. The planner stays f64
+#[cfg(not(feature = "f32"))]
+pub type Real = f64;
+#[cfg(not(feature = "f32"))]
+pub type AtomicReal = portable_atomic::AtomicF64;
+
+#[cfg(feature = "f32")]
+pub type Real = f32;
+#[cfg(feature = "f32")]
+pub type AtomicReal = portable_atomic::AtomicF32;
+
+/// Convert a value to pass it to the trajectory planner
+#[allow(clippy::useless_conversion)]
+pub(crate) fn to_f64(value: Real) -> f64 {
+    f64::from(value)
+}
+
+/// Convert a value returned by the trajectory planner
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn from_f64(value: f64) -> Real {
+    value as Real
+}
@@ -1,7 +1,20 @@
 #![no_std]
 
+pub mod binary;
 pub mod config;
+pub mod diagnostics;
+pub mod event;
+pub mod fault;
+pub mod float;
 pub mod motion;
 pub mod motion_control;
 pub mod pattern;
+pub mod preset;
+pub mod profile;
+pub mod runtime_config;
+pub mod session;
+pub mod session_limits;
+pub mod thermal;
+pub mod time;
 pub mod utils;
+pub mod validation;
@@ -0,0 +1,89 @@
+use core::sync::atomic::Ordering;
+
+use embassy_time::{Duration, Instant};
+use log::info;
+use portable_atomic::{AtomicBool, AtomicU32};
+
+use crate::{
+    config::{AUTO_OFF_MIN, MAX_AUTO_OFF_MIN},
+    event::{Event, publish_event},
+    motion::{
+        demo::stop_demo,
+        motion_state::{MotionState, set_motion_enabled},
+        playlist::stop_playlist,
+    },
+    time::elapsed_between,
+    validation::{ValueError, check_accepted},
+};
+
+static AUTO_OFF: AtomicU32 = AtomicU32::new(AUTO_OFF_MIN);
+// Start counting again from the next update
+static RESTART_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Set how long the motion runs for in minutes until it is turned off. 0 never turns it off
+/// Restarts the time of the running session
+pub fn set_auto_off_min(minutes: u32) -> Result<(), ValueError> {
+    let accepted = minutes.min(MAX_AUTO_OFF_MIN);
+    AUTO_OFF.store(accepted, Ordering::Release);
+    RESTART_REQUESTED.store(true, Ordering::Release);
+    info!("Auto-off set to {} min", accepted);
+    check_accepted(minutes as i64, accepted as i64)
+}
+
+pub fn get_auto_off_min() -> u32 {
+    AUTO_OFF.load(Ordering::Acquire)
+}
+
+/// Turns the motion off after it ran for `autoOffMin` without a break, to keep a session from
+/// running on unattended. The time restarts whenever the motion is disabled
+pub struct AutoOffRunner {
+    // How long the motion has been enabled for without a break
+    elapsed: Duration,
+    // Whether the motion was enabled on the last update
+    running: bool,
+    last_update: Instant,
+}
+
+impl Default for AutoOffRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AutoOffRunner {
+    pub fn new() -> Self {
+        Self {
+            elapsed: Duration::from_ticks(0),
+            running: false,
+            last_update: Instant::from_ticks(0),
+        }
+    }
+
+    /// Disable the motion once it ran for longer than `autoOffMin`
+    /// Returns true when it did so that the machine is retracted
+    pub fn apply(&mut self, motion_state: &mut MotionState, now: Instant) -> bool {
+        let restart = RESTART_REQUESTED.swap(false, Ordering::AcqRel);
+        if restart || !motion_state.motion_enabled {
+            self.elapsed = Duration::from_ticks(0);
+        } else if self.running {
+            self.elapsed += elapsed_between(self.last_update, now);
+        }
+        self.running = motion_state.motion_enabled;
+        self.last_update = now;
+
+        let minutes = get_auto_off_min();
+        if minutes == 0 || self.elapsed < Duration::from_secs(minutes as u64 * 60) {
+            return false;
+        }
+
+        info!("The motion ran for {} min. Turning it off", minutes);
+        publish_event(Event::AutoOff { minutes });
+        stop_demo();
+        stop_playlist();
+        set_motion_enabled(false);
+        motion_state.motion_enabled = false;
+        self.elapsed = Duration::from_ticks(0);
+        self.running = false;
+        true
+    }
+}
@@ -0,0 +1,151 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use embassy_time::{Duration, Instant};
+use log::info;
+
+use crate::{
+    config::{DEMO_MAX_DEPTH_PCT, DEMO_MAX_VELOCITY_PCT, DEMO_STEP_DURATION_S},
+    motion::motion_state::{
+        MotionState, get_input_generation, get_motion_state, set_motion_enabled,
+    },
+    motion_control::{arming::is_armed, is_faulted},
+    profile::get_active_limits,
+    time::elapsed_between,
+};
+
+static DEMO_ACTIVE: AtomicBool = AtomicBool::new(false);
+// The input generation once the demo enabled the motion. Any input after it takes over
+static DEMO_INPUT_GENERATION: AtomicU32 = AtomicU32::new(0);
+
+/// One step of the demo scenario. All values are in %
+struct DemoStep {
+    pattern: u32,
+    velocity: u32,
+    depth: u32,
+    motion_length: u32,
+    sensation: u32,
+}
+
+impl DemoStep {
+    const fn new(
+        pattern: u32,
+        velocity: u32,
+        depth: u32,
+        motion_length: u32,
+        sensation: u32,
+    ) -> Self {
+        Self {
+            pattern,
+            velocity,
+            depth,
+            motion_length,
+            sensation,
+        }
+    }
+}
+
+// A gentle showcase of the built-in patterns
+const DEMO_SCRIPT: [DemoStep; 6] = [
+    DemoStep::new(0, 20, 50, 40, 50),
+    DemoStep::new(1, 30, 60, 50, 70),
+    DemoStep::new(3, 30, 60, 60, 30),
+    DemoStep::new(4, 25, 70, 60, 50),
+    DemoStep::new(5, 35, 60, 50, 0),
+    DemoStep::new(0, 40, 70, 70, 50),
+];
+
+/// Start the demo. Only possible while armed and the motion is disabled
+pub fn start_demo() -> bool {
+    if get_motion_state().motion_enabled || !is_armed() {
+        return false;
+    }
+
+    // Enabling counts as an input itself. Only active once it is taken into account
+    set_motion_enabled(true);
+    DEMO_INPUT_GENERATION.store(get_input_generation(), Ordering::Release);
+    DEMO_ACTIVE.store(true, Ordering::Release);
+    info!("Demo started");
+
+    true
+}
+
+/// Stop the demo and disable the motion
+pub fn stop_demo() {
+    if DEMO_ACTIVE.swap(false, Ordering::AcqRel) {
+        set_motion_enabled(false);
+        info!("Demo stopped");
+    }
+}
+
+pub fn is_demo_active() -> bool {
+    DEMO_ACTIVE.load(Ordering::Acquire)
+}
+
+/// Steps through the demo script. Owned by `run_motion`
+pub struct DemoRunner {
+    running: bool,
+    step: usize,
+    step_started: Instant,
+}
+
+impl DemoRunner {
+    pub fn new() -> Self {
+        Self {
+            running: false,
+            step: 0,
+            step_started: Instant::now(),
+        }
+    }
+
+    /// Override the motion state with the current demo step if the demo is active
+    pub fn apply(&mut self, motion_state: &mut MotionState) {
+        if !is_demo_active() {
+            self.running = false;
+            return;
+        }
+
+        if !self.running {
+            self.running = true;
+            self.step = 0;
+            self.step_started = Instant::now();
+        }
+
+        // Any input from a remote takes over from the demo
+        if get_input_generation() != DEMO_INPUT_GENERATION.load(Ordering::Acquire) {
+            info!("Input received during the demo");
+            self.stop(motion_state);
+            return;
+        }
+        // Not restarted by itself once re-armed
+        if is_faulted() {
+            info!("Fault during the demo");
+            self.stop(motion_state);
+            return;
+        }
+
+        let step_elapsed = elapsed_between(self.step_started, Instant::now());
+        if step_elapsed > Duration::from_secs(DEMO_STEP_DURATION_S) {
+            self.step = (self.step + 1) % DEMO_SCRIPT.len();
+            self.step_started = Instant::now();
+            info!("Demo step {}", self.step);
+        }
+
+        let step = &DEMO_SCRIPT[self.step];
+        let limits = get_active_limits();
+        motion_state.pattern = step.pattern;
+        motion_state.velocity = step
+            .velocity
+            .min(DEMO_MAX_VELOCITY_PCT)
+            .min(limits.velocity);
+        motion_state.depth = step.depth.min(DEMO_MAX_DEPTH_PCT).min(limits.depth);
+        motion_state.motion_length = step.motion_length;
+        motion_state.sensation = step.sensation;
+        motion_state.motion_enabled = true;
+    }
+
+    fn stop(&mut self, motion_state: &mut MotionState) {
+        self.running = false;
+        stop_demo();
+        motion_state.motion_enabled = false;
+    }
+}
@@ -0,0 +1,312 @@
+use core::{
+    cell::RefCell,
+    fmt::{self, Display},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use critical_section::Mutex;
+use embassy_time::{Duration, Instant, Ticker};
+use heapless::Vec;
+use log::{error, info};
+use portable_atomic::AtomicU32;
+
+use crate::{
+    config::{MAX_FUNSCRIPT_ACTIONS, MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS},
+    float::Real,
+    motion::{
+        demo::is_demo_active,
+        motion_state::get_motion_state,
+        stream::{StreamError, get_velocity_envelope, stream_target_at},
+    },
+    motion_control::{arming::is_armed, hold, is_faulted},
+    time::elapsed_between,
+    utils::scale,
+};
+
+// The position of funscripts is in % from 0 to 100
+const MAX_ACTION_POSITION: u8 = 100;
+
+/// Go to `position` in % of the stroke at `at_ms` into the script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Action {
+    pub at_ms: u32,
+    pub position: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FunscriptError {
+    // More than MAX_FUNSCRIPT_ACTIONS actions
+    Full,
+    // Not `<at ms>,<position %>`
+    Malformed,
+    // The actions have to be uploaded in the order of their time
+    NotAscending,
+    // The script cannot be changed while it is played
+    Playing,
+    Empty,
+    Stream(StreamError),
+}
+
+impl Display for FunscriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FunscriptError::Full => write!(f, "full"),
+            FunscriptError::Malformed => write!(f, "malformed"),
+            FunscriptError::NotAscending => write!(f, "not_ascending"),
+            FunscriptError::Playing => write!(f, "playing"),
+            FunscriptError::Empty => write!(f, "empty"),
+            FunscriptError::Stream(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<StreamError> for FunscriptError {
+    fn from(value: StreamError) -> Self {
+        FunscriptError::Stream(value)
+    }
+}
+
+static ACTIONS: Mutex<RefCell<Vec<Action, MAX_FUNSCRIPT_ACTIONS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+static PLAYING: AtomicBool = AtomicBool::new(false);
+// Where the playback is in the script in ms. Written by the player while playing
+static PLAYBACK_POSITION_MS: AtomicU32 = AtomicU32::new(0);
+// The player starts over from PLAYBACK_POSITION_MS
+static SEEK_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Remove all the actions
+pub fn clear_funscript() -> Result<(), FunscriptError> {
+    if is_funscript_playing() {
+        return Err(FunscriptError::Playing);
+    }
+
+    critical_section::with(|cs| ACTIONS.borrow_ref_mut(cs).clear());
+    PLAYBACK_POSITION_MS.store(0, Ordering::Release);
+    Ok(())
+}
+
+/// Append a chunk of actions formatted as `<at ms>,<position %>;<at ms>,<position %>...`
+/// The chunk is added completely or not at all. Returns the number of actions in the script
+pub fn add_funscript_actions(chunk: &str) -> Result<usize, FunscriptError> {
+    if is_funscript_playing() {
+        return Err(FunscriptError::Playing);
+    }
+
+    critical_section::with(|cs| {
+        let mut actions = ACTIONS.borrow_ref_mut(cs);
+        let len = actions.len();
+
+        let result = chunk
+            .split(';')
+            .filter(|action| !action.is_empty())
+            .try_for_each(|action| {
+                let action = parse_action(action)?;
+                if actions
+                    .last()
+                    .is_some_and(|last| last.at_ms >= action.at_ms)
+                {
+                    return Err(FunscriptError::NotAscending);
+                }
+                actions.push(action).map_err(|_| FunscriptError::Full)
+            });
+
+        if let Err(err) = result {
+            actions.truncate(len);
+            return Err(err);
+        }
+        Ok(actions.len())
+    })
+}
+
+fn parse_action(action: &str) -> Result<Action, FunscriptError> {
+    let (at_ms, position) = action.split_once(',').ok_or(FunscriptError::Malformed)?;
+    let at_ms = at_ms.parse().map_err(|_| FunscriptError::Malformed)?;
+    let position = position
+        .parse()
+        .ok()
+        .filter(|position| *position <= MAX_ACTION_POSITION)
+        .ok_or(FunscriptError::Malformed)?;
+
+    Ok(Action { at_ms, position })
+}
+
+pub fn get_funscript_length() -> usize {
+    critical_section::with(|cs| ACTIONS.borrow_ref(cs).len())
+}
+
+fn get_action(index: usize) -> Option<Action> {
+    critical_section::with(|cs| ACTIONS.borrow_ref(cs).get(index).copied())
+}
+
+/// Start or continue playing from the current playback position
+/// Only possible while the motion is disabled
+pub fn play_funscript() -> Result<(), FunscriptError> {
+    if get_motion_state().motion_enabled || is_demo_active() {
+        return Err(StreamError::MotionEnabled.into());
+    }
+    if is_faulted() {
+        return Err(StreamError::Faulted.into());
+    }
+    if !is_armed() {
+        return Err(StreamError::NotArmed.into());
+    }
+    if get_funscript_length() == 0 {
+        return Err(FunscriptError::Empty);
+    }
+
+    info!(
+        "Playing the funscript from {} ms",
+        get_funscript_position_ms()
+    );
+    SEEK_REQUESTED.store(true, Ordering::Release);
+    PLAYING.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Stop at the nearest point the machine can decelerate to and keep the playback position
+pub fn pause_funscript() {
+    if PLAYING.swap(false, Ordering::AcqRel) {
+        info!("Funscript paused at {} ms", get_funscript_position_ms());
+        hold();
+    }
+}
+
+/// Continue from `position_ms` into the script. Keeps playing if it was
+pub fn seek_funscript(position_ms: u32) {
+    PLAYBACK_POSITION_MS.store(position_ms, Ordering::Release);
+    SEEK_REQUESTED.store(true, Ordering::Release);
+}
+
+/// Pause and rewind to the start
+pub fn stop_funscript() {
+    pause_funscript();
+    seek_funscript(0);
+}
+
+pub fn is_funscript_playing() -> bool {
+    PLAYING.load(Ordering::Acquire)
+}
+
+/// Where the playback is in the script in ms
+pub fn get_funscript_position_ms() -> u32 {
+    PLAYBACK_POSITION_MS.load(Ordering::Acquire)
+}
+
+/// Map a funscript position in % to the stroke allowed by the active profile in mm
+fn action_position_mm(position: u8) -> Real {
+    let envelope = get_velocity_envelope();
+    scale(
+        position as Real,
+        0.0,
+        MAX_ACTION_POSITION as Real,
+        envelope.min_position,
+        envelope.max_position,
+    )
+}
+
+/// Streams the moves between the actions to motion control while playing
+pub struct FunscriptPlayer {
+    running: bool,
+    // The time the playback position was started from
+    started: Instant,
+    started_position_ms: u32,
+    // The action being moved to
+    next: usize,
+}
+
+impl Default for FunscriptPlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FunscriptPlayer {
+    pub fn new() -> Self {
+        Self {
+            running: false,
+            started: Instant::from_ticks(0),
+            started_position_ms: 0,
+            next: 0,
+        }
+    }
+
+    /// Send the move to the next action once the previous one is due
+    pub fn update(&mut self, now: Instant) {
+        if !is_funscript_playing() {
+            self.running = false;
+            return;
+        }
+
+        if SEEK_REQUESTED.swap(false, Ordering::AcqRel) || !self.running {
+            self.running = true;
+            self.started = now;
+            self.started_position_ms = get_funscript_position_ms();
+            self.next = self.find_next(self.started_position_ms);
+            if let Err(err) = self.move_to_next(self.started_position_ms, now) {
+                self.fail(err);
+            }
+            return;
+        }
+
+        let elapsed_ms = elapsed_between(self.started, now).as_millis();
+        let position_ms = self.started_position_ms.saturating_add(elapsed_ms as u32);
+        PLAYBACK_POSITION_MS.store(position_ms, Ordering::Release);
+
+        let Some(action) = get_action(self.next) else {
+            info!("Funscript finished");
+            PLAYING.store(false, Ordering::Release);
+            PLAYBACK_POSITION_MS.store(0, Ordering::Release);
+            self.running = false;
+            return;
+        };
+        if action.at_ms > position_ms {
+            return;
+        }
+
+        // Actions closer together than the update interval are skipped
+        self.next = self.find_next(position_ms);
+        if let Err(err) = self.move_to_next(position_ms, now) {
+            self.fail(err);
+        }
+    }
+
+    /// The first action after `position_ms`
+    fn find_next(&self, position_ms: u32) -> usize {
+        critical_section::with(|cs| {
+            ACTIONS
+                .borrow_ref(cs)
+                .partition_point(|action| action.at_ms <= position_ms)
+        })
+    }
+
+    fn move_to_next(&self, position_ms: u32, now: Instant) -> Result<(), FunscriptError> {
+        let Some(action) = get_action(self.next) else {
+            return Ok(());
+        };
+
+        let duration_ms = action.at_ms - position_ms;
+        stream_target_at(action_position_mm(action.position), duration_ms as u64, now)?;
+        Ok(())
+    }
+
+    fn fail(&mut self, err: FunscriptError) {
+        error!("Funscript playback stopped: {}", err);
+        PLAYING.store(false, Ordering::Release);
+        self.running = false;
+    }
+}
+
+/// Forever running task that plays the funscript when started
+pub async fn run_funscript() {
+    let mut ticker = Ticker::every(Duration::from_millis(
+        MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS,
+    ));
+    let mut player = FunscriptPlayer::new();
+
+    info!("Task Funscript Started");
+
+    loop {
+        player.update(Instant::now());
+        ticker.next().await;
+    }
+}
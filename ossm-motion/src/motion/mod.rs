@@ -1,23 +1,86 @@
-use core::f64::INFINITY;
-
+use embassy_time::{Duration, Instant, Ticker, Timer};
 use log::info;
-use embassy_time::{Duration, Ticker, Timer};
+pub mod auto_off;
+pub mod demo;
+pub mod funscript;
 pub mod motion_state;
+pub mod playlist;
+pub mod shuffle;
+pub mod stream;
+pub mod sync;
+pub mod tcode;
 
 use crate::{
     config::{
-        MIN_MOVE_MM, MOTION_CONTROL_MIN_VELOCITY, RETRACT_ON_MOTION_DISABLED, RETRACT_VELOCITY,
+        MOTION_CONTROL_MIN_VELOCITY, PATTERN_TRANSITION_VELOCITY_PCT, SEAMLESS_PATTERN_SWITCHING,
+    },
+    fault::{Recovery, clear_faults},
+    float::Real,
+    motion::{
+        auto_off::AutoOffRunner,
+        demo::{DemoRunner, stop_demo},
+        motion_state::{
+            MachineMotionState, get_max_velocity_mm_s, get_motion_state, get_motion_torque_pct,
+            set_motion_enabled,
+        },
+        playlist::{PlaylistRunner, stop_playlist},
+        shuffle::ShuffleRunner,
+        sync::lead_move,
     },
-    motion::motion_state::{MachineMotionState, get_motion_state},
-    motion_control::{self, set_max_velocity, set_target_position, set_torque},
+    motion_control::{self, arming::is_armed, set_max_velocity, set_target_position, set_torque},
     pattern::{Pattern, PatternExecutor, PatternInput, PatternMove},
+    runtime_config::{get_retract_on_disable, get_retract_velocity},
+    time::elapsed_between,
+    utils::{saturate_range, scale},
 };
 
+/// Move a target planned for one depth and stroke length to where it would be with the new ones
+/// The target keeps its relative place within the stroke. All values are in mm
+fn retarget_position(
+    position: Real,
+    prev_depth: Real,
+    prev_motion_length: Real,
+    depth: Real,
+    motion_length: Real,
+) -> Real {
+    let min_move = motion_control::get_min_move_mm();
+    let position = position - min_move;
+
+    let retargeted = if prev_motion_length > 0.0 {
+        scale(
+            position,
+            prev_depth - prev_motion_length,
+            prev_depth,
+            depth - motion_length,
+            depth,
+        )
+    } else {
+        position + depth - prev_depth
+    };
+
+    saturate_range(retargeted, 0.0, depth) + min_move
+}
+
+/// Plan the move from wherever the machine is into the first move of a new pattern
+/// Goes there directly but no faster than the transition velocity
+fn plan_transition(first_move: PatternMove, velocity: Real) -> PatternMove {
+    let transition_velocity = velocity * PATTERN_TRANSITION_VELOCITY_PCT / 100.0;
+    PatternMove {
+        velocity: first_move.velocity.min(transition_velocity),
+        ..first_move
+    }
+}
+
 async fn retract() {
     let motion_state: MachineMotionState = get_motion_state().into();
 
-    set_target_position(MIN_MOVE_MM);
-    set_max_velocity(RETRACT_VELOCITY);
+    lead_move(
+        motion_control::get_min_move_mm(),
+        get_retract_velocity(),
+        Instant::now(),
+    );
+    set_target_position(motion_control::get_min_move_mm());
+    set_max_velocity(get_retract_velocity());
     while motion_control::is_move_in_progress() {
         Timer::after(Duration::from_millis(10)).await;
     }
@@ -25,26 +88,101 @@ async fn retract() {
     set_max_velocity(motion_state.velocity);
 }
 
+/// The input for the pattern from the motion state at this moment
+/// `stroke_index` and `pattern_started` are the progress of the pattern since it was started
+fn pattern_input(
+    motion_state: &MachineMotionState,
+    stroke_index: u32,
+    pattern_started: Instant,
+) -> PatternInput {
+    // With a tempo the executor picks the velocity within the limit of the profile
+    let velocity = if motion_state.bpm > 0.0 {
+        get_max_velocity_mm_s()
+    } else {
+        motion_state.velocity
+    };
+
+    let now = Instant::now();
+
+    PatternInput {
+        velocity,
+        depth: motion_state.depth,
+        motion_length: motion_state.motion_length,
+        sensation: motion_state.sensation,
+        load: motion_state.load,
+        bpm: motion_state.bpm,
+        now,
+        stroke_index,
+        elapsed_ms: elapsed_between(pattern_started, now).as_millis(),
+    }
+}
+
 pub async fn run_motion() {
     let mut ticker = Ticker::every(Duration::from_millis(10));
     let mut prev_motion_enabled = false;
 
     let mut pattern_executor = PatternExecutor::new();
+    let mut demo = DemoRunner::new();
+    let mut playlist = PlaylistRunner::new();
+    let mut shuffle = ShuffleRunner::new();
+    let mut auto_off = AutoOffRunner::new();
     let mut prev_pattern: u32 = 0;
     let mut pattern_move = PatternMove::default();
     let mut prev_pattern_move = PatternMove::default();
     // Values to be overriden on the first move
-    prev_pattern_move.velocity = INFINITY;
-    prev_pattern_move.torque = INFINITY;
+    prev_pattern_move.velocity = Real::INFINITY;
+    prev_pattern_move.torque = Real::INFINITY;
+    // The depth and stroke length in mm the move in progress was planned with
+    let mut move_depth = 0.0;
+    let mut move_motion_length = 0.0;
+    // Blending into a newly selected pattern. The move in progress is replaced
+    let mut transition = false;
+    // The next move waits until then for the delay of the previous one. Set once that move is
+    // complete and checked every iteration so that commands take effect during the delay
+    let mut delay_until: Option<Instant> = None;
+    // Restarted with the pattern when it is selected while enabled or the motion is enabled
+    let mut stroke_index: u32 = 0;
+    let mut pattern_started = Instant::now();
 
     info!("Task Motion Started");
 
     loop {
-        let motion_state: MachineMotionState = get_motion_state().into();
+        let mut motion_state = get_motion_state();
+        playlist.apply(&mut motion_state, Instant::now());
+        demo.apply(&mut motion_state);
+        // After the playlist so that an entry can shuffle
+        shuffle.apply(&mut motion_state, Instant::now());
+        let timed_out = auto_off.apply(&mut motion_state, Instant::now());
+        // After an emergency stop or disarming the motion has to be enabled again once armed
+        let faulted = motion_control::is_faulted();
+        if !is_armed() && motion_state.motion_enabled {
+            stop_demo();
+            stop_playlist();
+            set_motion_enabled(false);
+            motion_state.motion_enabled = false;
+        }
+        // Pause until the motor is reconnected. Resumes afterwards if still enabled
+        let motor_connected = motion_control::is_motor_connected();
+        if !motor_connected {
+            motion_state.motion_enabled = false;
+        }
+        let motion_state: MachineMotionState = motion_state.into();
 
         // Retract the machine if motion was disabled
         if !motion_state.motion_enabled && prev_motion_enabled {
-            if RETRACT_ON_MOTION_DISABLED {
+            pattern_executor.on_stop();
+            // A pause of the pattern ends with it
+            pattern_move.delay_ms = 0;
+            delay_until = None;
+            // Disabling the motion ends a pause and lifts the limit set by the overruns
+            // Their faults are cleared with it
+            motion_control::resume();
+            motion_control::reset_overrun_velocity_limit();
+            clear_faults(Recovery::MotionOff);
+            // Holding keeps the machine where it stopped
+            if !motor_connected || faulted || motion_control::is_holding() {
+                pattern_executor.reset();
+            } else if get_retract_on_disable() || timed_out {
                 pattern_executor.reset();
                 retract().await;
             } else {
@@ -52,16 +190,33 @@ pub async fn run_motion() {
             }
         }
 
-        if motion_state.motion_enabled && !prev_motion_enabled {
-            // Restore the previous velocity
-            if !RETRACT_ON_MOTION_DISABLED {
-                set_max_velocity(pattern_move.velocity);
-            }
-        }
-
         if motion_state.pattern != prev_pattern {
+            // Only a running pattern is stopped and the new one started
+            // Otherwise it is started once the motion is enabled
+            let running = motion_state.motion_enabled && prev_motion_enabled;
+            if running {
+                pattern_executor.on_stop();
+            }
             pattern_executor.set_pattern(motion_state.pattern);
             pattern_executor.reset();
+            if running {
+                // The delay belonged to the previous pattern
+                pattern_move.delay_ms = 0;
+                delay_until = None;
+                if SEAMLESS_PATTERN_SWITCHING {
+                    transition = true;
+                } else {
+                    retract().await;
+                    prev_pattern_move.velocity = Real::INFINITY;
+                }
+                stroke_index = 0;
+                pattern_started = Instant::now();
+                pattern_executor.on_start(&pattern_input(
+                    &motion_state,
+                    stroke_index,
+                    pattern_started,
+                ));
+            }
             info!(
                 "Pattern set to: {}",
                 pattern_executor.get_current_pattern_name()
@@ -69,19 +224,71 @@ pub async fn run_motion() {
             prev_pattern = motion_state.pattern;
         }
 
-        if !motion_control::is_move_in_progress() && motion_state.motion_enabled {
+        if motion_state.motion_enabled && !prev_motion_enabled {
+            motion_control::start_ease_in();
+            stroke_index = 0;
+            pattern_started = Instant::now();
+            pattern_executor.on_start(&pattern_input(&motion_state, stroke_index, pattern_started));
+            // Restore the previous velocity
+            if !get_retract_on_disable() {
+                set_max_velocity(pattern_move.velocity);
+            }
+            // Re-apply the velocity and torque in case the profile limits changed
+            // or a streaming client moved the machine in between
+            prev_pattern_move.velocity = Real::INFINITY;
+            prev_pattern_move.torque = Real::INFINITY;
+            // Only moves started by the pattern are retargeted
+            move_depth = motion_state.depth;
+            move_motion_length = motion_state.motion_length;
+        }
+
+        // Apply depth and stroke changes to the move in progress instead of the next one
+        if motion_state.motion_enabled
+            && motion_control::is_move_in_progress()
+            && (motion_state.depth != move_depth
+                || motion_state.motion_length != move_motion_length)
+        {
+            let position = retarget_position(
+                pattern_move.position,
+                move_depth,
+                move_motion_length,
+                motion_state.depth,
+                motion_state.motion_length,
+            );
+            if position != pattern_move.position {
+                pattern_move.position = position;
+                set_target_position(position);
+            }
+            move_depth = motion_state.depth;
+            move_motion_length = motion_state.motion_length;
+        }
+
+        let mut next_move_due = false;
+        if (transition || !motion_control::is_move_in_progress()) && motion_state.motion_enabled {
             // Apply the delay from the previous move before executing the next one
-            Timer::after_millis(pattern_move.delay_ms).await;
+            let now = Instant::now();
+            let deadline =
+                *delay_until.get_or_insert(now + Duration::from_millis(pattern_move.delay_ms));
+            next_move_due = now >= deadline;
+        }
 
-            let input = PatternInput {
-                velocity: motion_state.velocity,
-                depth: motion_state.depth,
-                motion_length: motion_state.motion_length,
-                sensation: motion_state.sensation,
-            };
+        if next_move_due {
+            delay_until = None;
 
             // A move with all the constraints met
+            let input = pattern_input(&motion_state, stroke_index, pattern_started);
             pattern_move = pattern_executor.next_move(&input);
+            // A stroke starts with every move deeper than where the machine was headed
+            let forward = pattern_move.position > motion_control::get_target_position();
+            if forward {
+                stroke_index = stroke_index.wrapping_add(1);
+            }
+            if transition {
+                pattern_move = plan_transition(pattern_move, motion_state.velocity);
+                transition = false;
+            }
+            // The remotes can lower the torque of each direction separately
+            pattern_move.torque *= get_motion_torque_pct(forward) as Real / 100.0;
 
             if pattern_move.velocity != prev_pattern_move.velocity {
                 set_max_velocity(pattern_move.velocity);
@@ -89,7 +296,10 @@ pub async fn run_motion() {
             if pattern_move.torque != prev_pattern_move.torque {
                 set_torque(pattern_move.torque);
             }
+            lead_move(pattern_move.position, pattern_move.velocity, Instant::now());
             set_target_position(pattern_move.position);
+            move_depth = motion_state.depth;
+            move_motion_length = motion_state.motion_length;
 
             prev_pattern_move = pattern_move;
         } else {
@@ -1,17 +1,32 @@
 use crate::{
+    binary::{BinaryState, RunState},
     config::{
-        MAX_STATE_LENGTH, MAX_TRAVEL_MM, MOTION_CONTROL_MAX_VELOCITY, MOTION_CONTROL_MIN_VELOCITY,
+        MAX_BINARY_STATE_LENGTH, MAX_BPM, MAX_STATE_LENGTH, MIN_BPM,
+        MOTION_CONTROL_MAX_ACCELERATION, MOTION_CONTROL_MAX_JERK, MOTION_CONTROL_MAX_VELOCITY,
+        MOTION_CONTROL_MIN_ACCELERATION, MOTION_CONTROL_MIN_JERK, MOTION_CONTROL_MIN_VELOCITY,
     },
-    motion_control::set_max_velocity_scaled,
-    pattern::{MAX_SENSATION, MIN_SENSATION},
-    utils::{saturate_range, scale},
+    fault::{FaultCode, get_fault},
+    float::Real,
+    motion::demo::is_demo_active,
+    motion_control::{
+        arming::is_armed, get_actual_position_mm, get_actual_velocity_mm_s, get_ease_in_pct,
+        get_load_pct, get_max_travel_mm, get_torque_pct, set_max_acceleration, set_max_jerk,
+        set_max_velocity_scaled,
+    },
+    pattern::{MAX_SENSATION, MIN_SENSATION, PatternExecutor},
+    profile::get_active_limits,
+    session_limits::get_session_limits,
+    utils::scale,
+    validation::{ValueError, check_accepted, validate_pct},
 };
 use core::{
+    cell::Cell,
     fmt::Write,
     sync::atomic::{AtomicBool, AtomicU32, Ordering},
 };
-use log::error;
-use heapless::String;
+use critical_section::Mutex;
+use heapless::{String, Vec};
+use log::{error, warn};
 
 #[allow(dead_code)]
 use num_traits::float::Float;
@@ -22,16 +37,31 @@ struct MotionStateStorage {
     velocity: AtomicU32,
     sensation: AtomicU32,
     pattern: AtomicU32,
+    bpm: AtomicU32,
     motion_enabled: AtomicBool,
+    // Torque of the moves out and back in in % of the torque of the pattern
+    torque_forward: AtomicU32,
+    torque_reverse: AtomicU32,
 }
 
+// Name of the remote in control of the motion. Arbitrated by the crate handling the remotes
+static CONTROL_SOURCE: Mutex<Cell<&'static str>> = Mutex::new(Cell::new("none"));
+// Version of the firmware running the motion. Set by the crate using it
+static FIRMWARE_VERSION: Mutex<Cell<&'static str>> = Mutex::new(Cell::new("unknown"));
+
+// Incremented on every change made through the setters to detect remote input
+static INPUT_GENERATION: AtomicU32 = AtomicU32::new(0);
+
 static MOTION_STATE: MotionStateStorage = MotionStateStorage {
     depth: AtomicU32::new(0),
     motion_length: AtomicU32::new(0),
     velocity: AtomicU32::new(0),
     sensation: AtomicU32::new(50),
     pattern: AtomicU32::new(0),
+    bpm: AtomicU32::new(0),
     motion_enabled: AtomicBool::new(false),
+    torque_forward: AtomicU32::new(100),
+    torque_reverse: AtomicU32::new(100),
 };
 
 /// Motion state representation in %
@@ -44,30 +74,52 @@ pub struct MotionState {
     pub velocity: u32,
     // Sensation in %
     pub sensation: u32,
-    // Pattern index
+    // Pattern ID
     pub pattern: u32,
+    // Tempo in strokes per minute. 0 if the velocity is set directly
+    pub bpm: u32,
     // Whether or not to enable the motion
     pub motion_enabled: bool,
+    // Estimated motor load in %. Read only
+    pub load: u32,
+    // Position of the machine in mm from home. Read only
+    pub position: Real,
+    // Velocity of the machine in mm/s. Read only
+    pub velocity_mm_s: Real,
+    // Progress of the velocity ramp after enabling the motion in %. Read only
+    pub ease_in: u32,
+    // Name of the remote in control of the motion e.g. "ble". Read only
+    pub control: &'static str,
+    // Maximum torque set for the move in %. Read only
+    pub torque: Real,
+    // The most severe active fault. Read only
+    pub fault: Option<FaultCode>,
+    // Read only
+    pub firmware_version: &'static str,
 }
 
 impl MotionState {
     pub fn as_json(&self) -> String<MAX_STATE_LENGTH> {
         let mut output = String::new();
-
-        let state_name = if self.motion_enabled {
-            "strokeEngine"
-        } else {
-            "menu"
-        };
+        let state_name = self.run_state().name();
 
         if write!(
             output,
-            r#"{{"state":"{state_name}","depth":{},"stroke":{},"speed":{},"sensation":{},"pattern":{}}}"#,
+            r#"{{"state":"{state_name}","depth":{},"stroke":{},"speed":{},"sensation":{},"pattern":{},"bpm":{},"load":{},"position":{:.1},"velocity":{:.1},"easeIn":{},"control":"{}","torque":{:.0},"fault":"{}","firmware":"{}"}}"#,
             self.depth,
             self.motion_length,
             self.velocity,
             self.sensation,
-            self.pattern
+            self.pattern,
+            self.bpm,
+            self.load,
+            self.position,
+            self.velocity_mm_s,
+            self.ease_in,
+            self.control,
+            self.torque,
+            self.fault.map_or("none", FaultCode::name),
+            self.firmware_version
         )
         .is_err()
         {
@@ -76,33 +128,77 @@ impl MotionState {
 
         output
     }
+
+    /// The state encoded with postcard, see `binary`
+    // `Real` is already f32 with the `f32` feature
+    #[allow(clippy::unnecessary_cast)]
+    pub fn as_binary(&self) -> Vec<u8, MAX_BINARY_STATE_LENGTH> {
+        let state = BinaryState {
+            state: self.run_state(),
+            depth: self.depth,
+            stroke: self.motion_length,
+            speed: self.velocity,
+            sensation: self.sensation,
+            pattern: self.pattern,
+            bpm: self.bpm,
+            load: self.load,
+            position: self.position as f32,
+            velocity: self.velocity_mm_s as f32,
+            ease_in: self.ease_in,
+            control: self.control,
+            torque: self.torque.round() as u32,
+            fault: self.fault,
+        };
+
+        let mut buffer = [0u8; MAX_BINARY_STATE_LENGTH];
+        match postcard::to_slice(&state, &mut buffer) {
+            Ok(bytes) => Vec::from_slice(bytes).expect("Fits the buffer it was written to"),
+            Err(err) => {
+                error!("Could not encode the state {:?}", err);
+                Vec::new()
+            }
+        }
+    }
+
+    /// What the machine is doing, the `state` of the JSON
+    pub fn run_state(&self) -> RunState {
+        if is_demo_active() {
+            RunState::Demo
+        } else if self.motion_enabled {
+            RunState::StrokeEngine
+        } else if !is_armed() {
+            RunState::Disarmed
+        } else {
+            RunState::Menu
+        }
+    }
 }
 
 /// Set the motion depth in %
-pub fn set_motion_depth_pct(mut depth: u32) {
-    if depth > 100 {
-        depth = 100;
-    }
+/// Capped by the limits of the active profile
+pub fn set_motion_depth_pct(depth: u32) -> Result<(), ValueError> {
+    let (depth, result) = validate_pct(depth, get_active_limits().depth);
     MOTION_STATE.depth.store(depth, Ordering::Release);
+    input_received();
+    result
 }
 
 /// Set the motion length in %
-pub fn set_motion_length_pct(mut length: u32) {
-    if length > 100 {
-        length = 100;
-    }
+pub fn set_motion_length_pct(length: u32) -> Result<(), ValueError> {
+    let (length, result) = validate_pct(length, 100);
     MOTION_STATE.motion_length.store(length, Ordering::Release);
+    input_received();
+    result
 }
 
 /// Set the motion velocity in %
-pub fn set_motion_velocity_pct(mut velocity: u32) {
-    if velocity > 100 {
-        velocity = 100;
-    }
+/// Capped by the limits of the active profile
+pub fn set_motion_velocity_pct(velocity: u32) -> Result<(), ValueError> {
+    let (velocity, result) = validate_pct(velocity, get_active_limits().velocity);
 
     let current_velocity = MOTION_STATE.velocity.load(Ordering::Acquire);
     let current_motion_velocity_mm_s = scale(
-        current_velocity as f64,
+        current_velocity as Real,
         0.0,
         100.0,
         MOTION_CONTROL_MIN_VELOCITY,
@@ -110,7 +206,7 @@ pub fn set_motion_velocity_pct(mut velocity: u32) {
     );
 
     let new_motion_velocity_mm_s = scale(
-        velocity as f64,
+        velocity as Real,
         0.0,
         100.0,
         MOTION_CONTROL_MIN_VELOCITY,
@@ -122,26 +218,126 @@ pub fn set_motion_velocity_pct(mut velocity: u32) {
     set_max_velocity_scaled(current_motion_velocity_mm_s, new_motion_velocity_mm_s);
 
     MOTION_STATE.velocity.store(velocity, Ordering::Release);
+    input_received();
+    result
 }
 
 /// Set the motion sensation in %
-pub fn set_motion_sensation_pct(mut sensation: u32) {
-    if sensation > 100 {
-        sensation = 100;
+pub fn set_motion_sensation_pct(sensation: u32) -> Result<(), ValueError> {
+    let (sensation, result) = validate_pct(sensation, 100);
+    MOTION_STATE.sensation.store(sensation, Ordering::Release);
+    input_received();
+    result
+}
+
+/// Set the pattern by its ID. Unknown IDs are rejected and the pattern is kept
+pub fn set_motion_pattern(id: u32) -> Result<(), ValueError> {
+    if !PatternExecutor::new().has_pattern(id) {
+        return Err(ValueError::Unknown);
     }
 
-    MOTION_STATE.sensation.store(sensation, Ordering::Release);
+    MOTION_STATE.pattern.store(id, Ordering::Release);
+    input_received();
+    Ok(())
+}
+
+/// Set the tempo in strokes per minute
+/// The velocity then follows from the stroke length. 0 goes back to setting the velocity directly
+pub fn set_motion_bpm(bpm: u32) -> Result<(), ValueError> {
+    let accepted = if bpm == 0 {
+        0
+    } else {
+        bpm.clamp(MIN_BPM, MAX_BPM)
+    };
+    MOTION_STATE.bpm.store(accepted, Ordering::Release);
+    input_received();
+    check_accepted(bpm as i64, accepted as i64)
+}
+
+/// Set the maximum acceleration in % from MOTION_CONTROL_MIN_ACCELERATION to the max
+pub fn set_motion_acceleration_pct(acceleration: u32) -> Result<(), ValueError> {
+    let (acceleration, result) = validate_pct(acceleration, 100);
+    set_max_acceleration(scale(
+        acceleration as Real,
+        0.0,
+        100.0,
+        MOTION_CONTROL_MIN_ACCELERATION,
+        MOTION_CONTROL_MAX_ACCELERATION,
+    ));
+    input_received();
+    result
+}
+
+/// Set the maximum jerk in % from MOTION_CONTROL_MIN_JERK to the max
+pub fn set_motion_jerk_pct(jerk: u32) -> Result<(), ValueError> {
+    let (jerk, result) = validate_pct(jerk, 100);
+    set_max_jerk(scale(
+        jerk as Real,
+        0.0,
+        100.0,
+        MOTION_CONTROL_MIN_JERK,
+        MOTION_CONTROL_MAX_JERK,
+    ));
+    input_received();
+    result
+}
+
+/// Set the torque of the moves out of the machine in % of the torque of the pattern
+pub fn set_motion_torque_forward_pct(torque: u32) -> Result<(), ValueError> {
+    let (torque, result) = validate_pct(torque, 100);
+    MOTION_STATE.torque_forward.store(torque, Ordering::Release);
+    input_received();
+    result
 }
 
-pub fn set_motion_pattern(index: u32) {
-    MOTION_STATE.pattern.store(index, Ordering::Release);
+/// Set the torque of the moves back into the machine in % of the torque of the pattern
+pub fn set_motion_torque_reverse_pct(torque: u32) -> Result<(), ValueError> {
+    let (torque, result) = validate_pct(torque, 100);
+    MOTION_STATE.torque_reverse.store(torque, Ordering::Release);
+    input_received();
+    result
+}
+
+/// The torque of the moves in one direction in % of the torque of the pattern
+pub fn get_motion_torque_pct(forward: bool) -> u32 {
+    if forward {
+        MOTION_STATE.torque_forward.load(Ordering::Acquire)
+    } else {
+        MOTION_STATE.torque_reverse.load(Ordering::Acquire)
+    }
 }
 
 /// Set whether the motion is enabled
+/// Enabling it has no effect until the machine is armed
 pub fn set_motion_enabled(enabled: bool) {
+    if enabled && !is_armed() {
+        warn!("Not enabling the motion. Arm the machine first");
+        return;
+    }
     MOTION_STATE
         .motion_enabled
         .store(enabled, Ordering::Release);
+    input_received();
+}
+
+/// Set the version of the firmware reported in the state
+pub fn set_firmware_version(version: &'static str) {
+    critical_section::with(|cs| FIRMWARE_VERSION.borrow(cs).set(version));
+}
+
+/// Set the name of the remote in control of the motion reported in the state
+/// Not an input, so it does not end the demo
+pub fn set_control_source(source: &'static str) {
+    critical_section::with(|cs| CONTROL_SOURCE.borrow(cs).set(source));
+}
+
+fn input_received() {
+    INPUT_GENERATION.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Get a counter that changes every time the motion state is set
+pub fn get_input_generation() -> u32 {
+    INPUT_GENERATION.load(Ordering::Acquire)
 }
 
 pub fn get_motion_state() -> MotionState {
@@ -151,90 +347,145 @@ pub fn get_motion_state() -> MotionState {
         velocity: MOTION_STATE.velocity.load(Ordering::Acquire),
         sensation: MOTION_STATE.sensation.load(Ordering::Acquire),
         pattern: MOTION_STATE.pattern.load(Ordering::Acquire),
+        bpm: MOTION_STATE.bpm.load(Ordering::Acquire),
         motion_enabled: MOTION_STATE.motion_enabled.load(Ordering::Acquire),
+        load: get_load_pct(),
+        position: get_actual_position_mm(),
+        velocity_mm_s: get_actual_velocity_mm_s(),
+        ease_in: get_ease_in_pct(),
+        control: critical_section::with(|cs| CONTROL_SOURCE.borrow(cs).get()),
+        torque: get_torque_pct(),
+        fault: get_fault(),
+        firmware_version: critical_section::with(|cs| FIRMWARE_VERSION.borrow(cs).get()),
     }
 }
 
 /// Motion state representation in machine values e.g. mm instead of %
 pub struct MachineMotionState {
     // Depth in mm
-    pub depth: f64,
+    pub depth: Real,
     // The length of the motion in mm
-    pub motion_length: f64,
+    pub motion_length: Real,
     // Maximum velocity in mm/s
-    pub velocity: f64,
+    pub velocity: Real,
     // Sensation from -100 to 100
-    pub sensation: f64,
-    // Pattern index
+    pub sensation: Real,
+    // Pattern ID
     pub pattern: u32,
+    // Tempo in strokes per minute. 0 if the velocity is set directly
+    pub bpm: Real,
     // Whether or not to enable the motion
     pub motion_enabled: bool,
+    // Estimated motor load in %
+    pub load: Real,
 }
 
 impl From<MotionState> for MachineMotionState {
     fn from(value: MotionState) -> Self {
         Self {
-            depth: scale(value.depth as f64, 0.0, 100.0, 0.0, MAX_TRAVEL_MM),
-            motion_length: scale(value.motion_length as f64, 0.0, 100.0, 0.0, MAX_TRAVEL_MM),
+            // Still capped in mm if the travel grew since the depth was set
+            depth: scale(value.depth as Real, 0.0, 100.0, 0.0, get_max_travel_mm())
+                .min(get_session_limits().depth_mm),
+            motion_length: scale(
+                value.motion_length as Real,
+                0.0,
+                100.0,
+                0.0,
+                get_max_travel_mm(),
+            ),
             velocity: scale(
-                value.velocity as f64,
+                value.velocity as Real,
                 0.0,
                 100.0,
                 MOTION_CONTROL_MIN_VELOCITY,
                 MOTION_CONTROL_MAX_VELOCITY,
             ),
             sensation: scale(
-                value.sensation as f64,
+                value.sensation as Real,
                 0.0,
                 100.0,
                 MIN_SENSATION,
                 MAX_SENSATION,
             ),
             pattern: value.pattern,
+            bpm: value.bpm as Real,
             motion_enabled: value.motion_enabled,
+            load: value.load as Real,
         }
     }
 }
 
-/// Set the motion depth in mm
-pub fn set_motion_depth_mm(depth: u32) {
-    let depth = saturate_range(depth as f64, 0.0, MAX_TRAVEL_MM);
+/// The maximum velocity in mm/s allowed by the active limits
+pub fn get_max_velocity_mm_s() -> Real {
+    scale(
+        get_active_limits().velocity as Real,
+        0.0,
+        100.0,
+        MOTION_CONTROL_MIN_VELOCITY,
+        MOTION_CONTROL_MAX_VELOCITY,
+    )
+}
 
-    let depth_pct = scale(depth, 0.0, MAX_TRAVEL_MM, 0.0, 100.0) as u32;
+/// The maximum depth in mm allowed by the active limits
+pub fn get_max_depth_mm() -> Real {
+    scale(
+        get_active_limits().depth as Real,
+        0.0,
+        100.0,
+        0.0,
+        get_max_travel_mm(),
+    )
+}
 
-    set_motion_depth_pct(depth_pct);
+/// Set the motion depth in mm
+pub fn set_motion_depth_mm(depth: u32) -> Result<(), ValueError> {
+    let max_travel = get_max_travel_mm();
+    let depth_pct = scale(depth as Real, 0.0, max_travel, 0.0, 100.0) as u32;
+
+    set_motion_depth_pct(depth_pct).map_err(|err| {
+        err.map_accepted(|pct| scale(pct as Real, 0.0, 100.0, 0.0, max_travel) as i32)
+    })
 }
 
 /// Set the motion length in mm
-pub fn set_motion_length_mm(length: u32) {
-    let length_pct = scale(length as f64, 0.0, MAX_TRAVEL_MM, 0.0, 100.0) as u32;
+pub fn set_motion_length_mm(length: u32) -> Result<(), ValueError> {
+    let max_travel = get_max_travel_mm();
+    let length_pct = scale(length as Real, 0.0, max_travel, 0.0, 100.0) as u32;
 
-    set_motion_length_pct(length_pct);
+    set_motion_length_pct(length_pct).map_err(|err| {
+        err.map_accepted(|pct| scale(pct as Real, 0.0, 100.0, 0.0, max_travel) as i32)
+    })
 }
 
 /// Set the motion velocity in mm/s
-pub fn set_motion_velocity_mm_s(velocity: u32) {
+pub fn set_motion_velocity_mm_s(velocity: u32) -> Result<(), ValueError> {
     let velocity_pct = scale(
-        velocity as f64,
+        velocity as Real,
         MOTION_CONTROL_MIN_VELOCITY,
         MOTION_CONTROL_MAX_VELOCITY,
         0.0,
         100.0,
     ) as u32;
 
-    set_motion_velocity_pct(velocity_pct);
+    set_motion_velocity_pct(velocity_pct).map_err(|err| {
+        err.map_accepted(|pct| {
+            scale(
+                pct as Real,
+                0.0,
+                100.0,
+                MOTION_CONTROL_MIN_VELOCITY,
+                MOTION_CONTROL_MAX_VELOCITY,
+            ) as i32
+        })
+    })
 }
 
 /// Set the motion sensation in a range from -100 to 100
-pub fn set_motion_sensation_neg_pos_100(mut sensation: i32) {
-    if (sensation as f64) > MAX_SENSATION {
-        sensation = MAX_SENSATION.floor() as i32;
-    }
-    if (sensation as f64) < MIN_SENSATION {
-        sensation = MIN_SENSATION.ceil() as i32;
-    }
+pub fn set_motion_sensation_neg_pos_100(sensation: i32) -> Result<(), ValueError> {
+    let accepted = sensation.clamp(MIN_SENSATION.ceil() as i32, MAX_SENSATION.floor() as i32);
 
-    let sensation_pct = scale(sensation as f64, MIN_SENSATION, MAX_SENSATION, 0.0, 100.0) as u32;
+    let sensation_pct = scale(accepted as Real, MIN_SENSATION, MAX_SENSATION, 0.0, 100.0) as u32;
 
-    set_motion_sensation_pct(sensation_pct);
+    set_motion_sensation_pct(sensation_pct)?;
+    check_accepted(sensation as i64, accepted as i64)
 }
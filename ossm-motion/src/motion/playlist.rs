@@ -0,0 +1,295 @@
+use core::{
+    cell::RefCell,
+    fmt::{self, Display, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use critical_section::Mutex;
+use embassy_time::{Duration, Instant};
+use heapless::{String, Vec};
+use log::{error, info};
+use portable_atomic::AtomicU32;
+
+use crate::{
+    config::{MAX_PLAYLIST_ENTRIES, MAX_PLAYLIST_LENGTH},
+    event::{Event, publish_event},
+    motion::{
+        demo::is_demo_active,
+        motion_state::{MotionState, set_motion_enabled},
+    },
+    pattern::PatternExecutor,
+    time::elapsed_between,
+};
+
+/// Run `pattern` with `sensation` in % for `duration_s` of motion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaylistEntry {
+    pub pattern: u32,
+    pub duration_s: u32,
+    pub sensation: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaylistError {
+    // More than MAX_PLAYLIST_ENTRIES entries
+    Full,
+    // Not a command or arguments the playlist understands
+    Malformed,
+    UnknownPattern,
+    // A duration of 0 or a sensation above 100%
+    InvalidEntry,
+    NoSuchEntry,
+    Empty,
+    DemoActive,
+}
+
+impl Display for PlaylistError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlaylistError::Full => write!(f, "full"),
+            PlaylistError::Malformed => write!(f, "malformed"),
+            PlaylistError::UnknownPattern => write!(f, "unknown_pattern"),
+            PlaylistError::InvalidEntry => write!(f, "invalid_entry"),
+            PlaylistError::NoSuchEntry => write!(f, "no_such_entry"),
+            PlaylistError::Empty => write!(f, "empty"),
+            PlaylistError::DemoActive => write!(f, "demo_active"),
+        }
+    }
+}
+
+static ENTRIES: Mutex<RefCell<Vec<PlaylistEntry, MAX_PLAYLIST_ENTRIES>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+static PLAYLIST_ACTIVE: AtomicBool = AtomicBool::new(false);
+// Start over from the first entry after the last one instead of stopping
+static PLAYLIST_REPEAT: AtomicBool = AtomicBool::new(false);
+// The entry being played. Written by the runner
+static CURRENT_ENTRY: AtomicU32 = AtomicU32::new(0);
+// The runner starts over from CURRENT_ENTRY
+static START_REQUESTED: AtomicBool = AtomicBool::new(false);
+static SKIP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+fn validate_entry(entry: &PlaylistEntry) -> Result<(), PlaylistError> {
+    if !PatternExecutor::new().has_pattern(entry.pattern) {
+        return Err(PlaylistError::UnknownPattern);
+    }
+    if entry.duration_s == 0 || entry.sensation > 100 {
+        return Err(PlaylistError::InvalidEntry);
+    }
+    Ok(())
+}
+
+/// Append an entry. Returns the number of entries in the playlist
+pub fn add_playlist_entry(entry: PlaylistEntry) -> Result<usize, PlaylistError> {
+    validate_entry(&entry)?;
+
+    critical_section::with(|cs| {
+        let mut entries = ENTRIES.borrow_ref_mut(cs);
+        entries.push(entry).map_err(|_| PlaylistError::Full)?;
+        Ok(entries.len())
+    })
+}
+
+/// Replace the entry at `index`. Takes effect the next time the entry is played
+pub fn set_playlist_entry(index: usize, entry: PlaylistEntry) -> Result<(), PlaylistError> {
+    validate_entry(&entry)?;
+
+    critical_section::with(|cs| {
+        let mut entries = ENTRIES.borrow_ref_mut(cs);
+        let slot = entries.get_mut(index).ok_or(PlaylistError::NoSuchEntry)?;
+        *slot = entry;
+        Ok(())
+    })
+}
+
+/// Remove the entry at `index`. The following entries move up
+pub fn remove_playlist_entry(index: usize) -> Result<(), PlaylistError> {
+    critical_section::with(|cs| {
+        let mut entries = ENTRIES.borrow_ref_mut(cs);
+        if index >= entries.len() {
+            return Err(PlaylistError::NoSuchEntry);
+        }
+        entries.remove(index);
+        Ok(())
+    })
+}
+
+/// Stop and remove all the entries
+pub fn clear_playlist() {
+    stop_playlist();
+    critical_section::with(|cs| ENTRIES.borrow_ref_mut(cs).clear());
+}
+
+pub fn get_playlist_length() -> usize {
+    critical_section::with(|cs| ENTRIES.borrow_ref(cs).len())
+}
+
+fn get_playlist_entry(index: usize) -> Option<PlaylistEntry> {
+    critical_section::with(|cs| ENTRIES.borrow_ref(cs).get(index).copied())
+}
+
+/// Play the entries from the first one, overriding the pattern and sensation of the motion state
+/// The time of an entry only counts while the motion is enabled
+pub fn start_playlist() -> Result<(), PlaylistError> {
+    if is_demo_active() {
+        return Err(PlaylistError::DemoActive);
+    }
+    if get_playlist_length() == 0 {
+        return Err(PlaylistError::Empty);
+    }
+
+    info!("Playlist started");
+    CURRENT_ENTRY.store(0, Ordering::Release);
+    SKIP_REQUESTED.store(false, Ordering::Release);
+    START_REQUESTED.store(true, Ordering::Release);
+    PLAYLIST_ACTIVE.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Stop the playlist. The pattern and sensation set by the remote apply again
+pub fn stop_playlist() {
+    if PLAYLIST_ACTIVE.swap(false, Ordering::AcqRel) {
+        info!("Playlist stopped");
+    }
+}
+
+/// Continue with the next entry right away
+pub fn skip_playlist_entry() {
+    SKIP_REQUESTED.store(true, Ordering::Release);
+}
+
+pub fn set_playlist_repeat(repeat: bool) {
+    PLAYLIST_REPEAT.store(repeat, Ordering::Release);
+}
+
+pub fn is_playlist_active() -> bool {
+    PLAYLIST_ACTIVE.load(Ordering::Acquire)
+}
+
+/// The index of the entry being played
+pub fn get_current_playlist_entry() -> usize {
+    CURRENT_ENTRY.load(Ordering::Acquire) as usize
+}
+
+/// Returns the playlist as JSON. The duration is in s and the sensation in %
+pub fn get_playlist_json() -> String<MAX_PLAYLIST_LENGTH> {
+    let mut output = String::new();
+
+    let result = critical_section::with(|cs| {
+        write!(
+            output,
+            r#"{{"active":{},"repeat":{},"current":{},"entries":["#,
+            is_playlist_active(),
+            PLAYLIST_REPEAT.load(Ordering::Acquire),
+            get_current_playlist_entry()
+        )?;
+        for (index, entry) in ENTRIES.borrow_ref(cs).iter().enumerate() {
+            if index > 0 {
+                output.write_char(',')?;
+            }
+            write!(
+                output,
+                r#"{{"pattern":{},"duration":{},"sensation":{}}}"#,
+                entry.pattern, entry.duration_s, entry.sensation
+            )?;
+        }
+        output.write_str("]}")
+    });
+    if result.is_err() {
+        error!("Could not write the playlist. Too long");
+    }
+
+    output
+}
+
+/// Steps through the playlist. Owned by `run_motion`
+pub struct PlaylistRunner {
+    running: bool,
+    entry: usize,
+    // How long the current entry has been played with the motion enabled
+    entry_elapsed: Duration,
+    last_update: Instant,
+}
+
+impl Default for PlaylistRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PlaylistRunner {
+    pub fn new() -> Self {
+        Self {
+            running: false,
+            entry: 0,
+            entry_elapsed: Duration::from_ticks(0),
+            last_update: Instant::from_ticks(0),
+        }
+    }
+
+    /// Override the pattern and sensation of the motion state with the current entry
+    /// if the playlist is active
+    pub fn apply(&mut self, motion_state: &mut MotionState, now: Instant) {
+        if !is_playlist_active() {
+            self.running = false;
+            return;
+        }
+
+        if START_REQUESTED.swap(false, Ordering::AcqRel) || !self.running {
+            self.running = true;
+            self.entry = get_current_playlist_entry();
+            self.entry_elapsed = Duration::from_ticks(0);
+            self.last_update = now;
+        }
+
+        if motion_state.motion_enabled {
+            self.entry_elapsed += elapsed_between(self.last_update, now);
+        }
+        self.last_update = now;
+
+        let skip = SKIP_REQUESTED.swap(false, Ordering::AcqRel);
+        // Entries may have been removed while playing
+        let entry = match get_playlist_entry(self.entry) {
+            Some(entry)
+                if !skip && self.entry_elapsed < Duration::from_secs(entry.duration_s as u64) =>
+            {
+                entry
+            }
+            finished => {
+                if let Some(finished) = finished {
+                    publish_event(Event::PatternFinished {
+                        pattern: finished.pattern,
+                    });
+                }
+                match self.next_entry() {
+                    Some(entry) => entry,
+                    None => {
+                        info!("Playlist finished");
+                        publish_event(Event::PlaylistFinished);
+                        stop_playlist();
+                        self.running = false;
+                        set_motion_enabled(false);
+                        motion_state.motion_enabled = false;
+                        return;
+                    }
+                }
+            }
+        };
+
+        motion_state.pattern = entry.pattern;
+        motion_state.sensation = entry.sensation;
+    }
+
+    /// Move on to the next entry. None once the last one is done and the playlist does not repeat
+    fn next_entry(&mut self) -> Option<PlaylistEntry> {
+        self.entry += 1;
+        self.entry_elapsed = Duration::from_ticks(0);
+        if self.entry >= get_playlist_length() && PLAYLIST_REPEAT.load(Ordering::Acquire) {
+            self.entry = 0;
+        }
+
+        let entry = get_playlist_entry(self.entry)?;
+        CURRENT_ENTRY.store(self.entry as u32, Ordering::Release);
+        info!("Playlist entry {}", self.entry);
+        Some(entry)
+    }
+}
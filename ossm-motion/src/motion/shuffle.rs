@@ -0,0 +1,108 @@
+use core::sync::atomic::Ordering;
+
+use embassy_time::{Duration, Instant};
+use log::info;
+use portable_atomic::AtomicU32;
+
+use crate::{
+    config::{MAX_SHUFFLE_INTERVAL_MIN, SHUFFLE_INTERVAL_MIN},
+    motion::motion_state::MotionState,
+    pattern::{
+        PATTERN_ID_DEEPER, PATTERN_ID_EDGING, PATTERN_ID_HALF_HALF, PATTERN_ID_INSIST,
+        PATTERN_ID_JACK_HAMMER, PATTERN_ID_SHUFFLE, PATTERN_ID_SIMPLE, PATTERN_ID_STOP_N_GO,
+        PATTERN_ID_TEASING_POUNDING,
+    },
+    time::elapsed_between,
+    utils::rng::Rng,
+    validation::{ValueError, check_accepted},
+};
+
+// The custom pattern needs an upload, the torque pattern runs on the torque
+// and the warm-up would start over each time. They are left out
+const SHUFFLE_PATTERNS: [u32; 8] = [
+    PATTERN_ID_SIMPLE,
+    PATTERN_ID_TEASING_POUNDING,
+    PATTERN_ID_HALF_HALF,
+    PATTERN_ID_DEEPER,
+    PATTERN_ID_STOP_N_GO,
+    PATTERN_ID_INSIST,
+    PATTERN_ID_JACK_HAMMER,
+    PATTERN_ID_EDGING,
+];
+const RNG_STREAM: u32 = 0x5348_5546;
+
+static SHUFFLE_INTERVAL: AtomicU32 = AtomicU32::new(SHUFFLE_INTERVAL_MIN);
+
+/// Set how long each pattern is played for in minutes
+pub fn set_shuffle_interval_min(minutes: u32) -> Result<(), ValueError> {
+    let accepted = minutes.clamp(1, MAX_SHUFFLE_INTERVAL_MIN);
+    SHUFFLE_INTERVAL.store(accepted, Ordering::Release);
+    check_accepted(minutes as i64, accepted as i64)
+}
+
+pub fn get_shuffle_interval_min() -> u32 {
+    SHUFFLE_INTERVAL.load(Ordering::Acquire)
+}
+
+/// Plays a random pattern for a few minutes at a time while the shuffle pattern is selected
+pub struct ShuffleRunner {
+    // The pattern being played. None while the shuffle pattern is not selected
+    current: Option<u32>,
+    // How long the current pattern has been played with the motion enabled
+    elapsed: Duration,
+    last_update: Instant,
+    rng: Rng,
+}
+
+impl Default for ShuffleRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShuffleRunner {
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            elapsed: Duration::from_ticks(0),
+            last_update: Instant::from_ticks(0),
+            rng: Rng::seeded(RNG_STREAM),
+        }
+    }
+
+    /// Override the pattern of the motion state with the one being played
+    /// if the shuffle pattern is selected
+    pub fn apply(&mut self, motion_state: &mut MotionState, now: Instant) {
+        if motion_state.pattern != PATTERN_ID_SHUFFLE {
+            self.current = None;
+            return;
+        }
+
+        if motion_state.motion_enabled && self.current.is_some() {
+            self.elapsed += elapsed_between(self.last_update, now);
+        }
+        self.last_update = now;
+
+        let interval = Duration::from_secs(get_shuffle_interval_min() as u64 * 60);
+        motion_state.pattern = match self.current {
+            Some(pattern) if self.elapsed < interval => pattern,
+            _ => self.next_pattern(),
+        };
+    }
+
+    /// Pick a different pattern than the one being played
+    fn next_pattern(&mut self) -> u32 {
+        let pattern = loop {
+            let index = self.rng.next_u32() as usize % SHUFFLE_PATTERNS.len();
+            let pattern = SHUFFLE_PATTERNS[index];
+            if self.current != Some(pattern) {
+                break pattern;
+            }
+        };
+        self.current = Some(pattern);
+        self.elapsed = Duration::from_ticks(0);
+        info!("Shuffled to pattern {}", pattern);
+
+        pattern
+    }
+}
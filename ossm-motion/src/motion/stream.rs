@@ -0,0 +1,284 @@
+use core::{
+    fmt::{self, Display, Write},
+    sync::atomic::Ordering,
+};
+
+use embassy_time::{Duration, Instant};
+use heapless::String;
+use log::{debug, error};
+use portable_atomic::AtomicU64;
+
+use crate::{
+    config::{
+        MAX_CAPABILITIES_LENGTH, MAX_POSITION_INTERVAL_MS, MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS,
+        MOTION_CONTROL_MIN_VELOCITY,
+    },
+    float::Real,
+    motion::{
+        demo::is_demo_active,
+        motion_state::{get_max_depth_mm, get_max_velocity_mm_s, get_motion_state},
+    },
+    motion_control::{
+        arming::is_armed, get_max_acceleration, get_min_move_mm, get_target_position, is_faulted,
+        is_move_in_progress, set_max_velocity, set_target_position, set_target_velocity,
+    },
+    time::AtomicTimestamp,
+    utils::saturate_range,
+};
+
+// When the last streamed target was received and how long it was supposed to take
+static LAST_TARGET: AtomicTimestamp = AtomicTimestamp::never();
+static LAST_TARGET_DURATION_MS: AtomicU64 = AtomicU64::new(0);
+
+/// What the machine can currently do with the active profile
+/// Streaming clients should scale their inputs to it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VelocityEnvelope {
+    // In mm/s
+    pub min_velocity: Real,
+    pub max_velocity: Real,
+    // In mm/s²
+    pub max_acceleration: Real,
+    // In mm
+    pub min_position: Real,
+    pub max_position: Real,
+}
+
+impl VelocityEnvelope {
+    pub fn as_json(&self) -> String<MAX_CAPABILITIES_LENGTH> {
+        let mut output = String::new();
+
+        if write!(
+            output,
+            r#"{{"minVelocity":{:.3},"maxVelocity":{:.1},"maxAcceleration":{:.0},"minPosition":{:.1},"maxPosition":{:.1}}}"#,
+            self.min_velocity,
+            self.max_velocity,
+            self.max_acceleration,
+            self.min_position,
+            self.max_position
+        )
+        .is_err()
+        {
+            error!("Could not write the velocity envelope. Too long");
+        }
+
+        output
+    }
+}
+
+/// The effective envelope for the active profile limits
+pub fn get_velocity_envelope() -> VelocityEnvelope {
+    VelocityEnvelope {
+        min_velocity: MOTION_CONTROL_MIN_VELOCITY,
+        max_velocity: get_max_velocity_mm_s(),
+        max_acceleration: get_max_acceleration(),
+        min_position: get_min_move_mm(),
+        max_position: get_min_move_mm() + get_max_depth_mm(),
+    }
+}
+
+/// What happened to the previous target when a new one arrived
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Replaced {
+    // The previous target was still being approached. The move was replanned to the new one
+    Merged,
+    // The previous target arrived less than a control loop ago and was never executed
+    Dropped,
+}
+
+/// How a streamed target was adjusted to fit the envelope
+/// Empty if it was executed as sent
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StreamFeedback {
+    pub replaced: Option<Replaced>,
+    // The velocity in mm/s used instead of the one needed to arrive in time
+    pub clamped_velocity: Option<Real>,
+    // The position in mm used instead of the requested one
+    pub clamped_position: Option<Real>,
+}
+
+impl StreamFeedback {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Formatted as `[merged|dropped][:velocity:<mm/s>][:position:<mm>]` to be sent to the client
+impl Display for StreamFeedback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = "";
+        match self.replaced {
+            Some(Replaced::Merged) => {
+                write!(f, "merged")?;
+                separator = ":";
+            }
+            Some(Replaced::Dropped) => {
+                write!(f, "dropped")?;
+                separator = ":";
+            }
+            None => {}
+        }
+        if let Some(velocity) = self.clamped_velocity {
+            write!(f, "{separator}velocity:{velocity:.1}")?;
+            separator = ":";
+        }
+        if let Some(position) = self.clamped_position {
+            write!(f, "{separator}position:{position:.1}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamError {
+    // Patterns and the demo own the machine while they run
+    MotionEnabled,
+    NotANumber,
+    // The machine was emergency stopped and not re-armed yet
+    Faulted,
+    NotArmed,
+}
+
+impl Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::MotionEnabled => write!(f, "motion_enabled"),
+            StreamError::NotANumber => write!(f, "not_a_number"),
+            StreamError::Faulted => write!(f, "faulted"),
+            StreamError::NotArmed => write!(f, "not_armed"),
+        }
+    }
+}
+
+/// Move to `position` in mm so that it is reached in `duration_ms`
+/// Only possible while the motion is disabled
+/// Targets that cannot be reached in time are clamped to the envelope instead of
+/// queueing up. The feedback tells the client how its target was adjusted
+pub fn stream_target(position: Real, duration_ms: u64) -> Result<StreamFeedback, StreamError> {
+    stream_target_at(position, duration_ms, Instant::now())
+}
+
+/// Move to `position` in mm at `velocity` in mm/s, for clients that send positions many times
+/// a second e.g. to scrub the machine. Without a velocity the position is reached by when the
+/// next one is expected going by the time since the previous one, at most
+/// `MAX_POSITION_INTERVAL_MS`. Velocities that are not positive count as none
+pub fn stream_position(
+    position: Real,
+    velocity: Option<Real>,
+) -> Result<StreamFeedback, StreamError> {
+    stream_position_at(position, velocity, Instant::now())
+}
+
+/// `stream_position` for a position received at `now`
+pub fn stream_position_at(
+    position: Real,
+    velocity: Option<Real>,
+    now: Instant,
+) -> Result<StreamFeedback, StreamError> {
+    let duration_ms = match velocity {
+        Some(velocity) if velocity > 0.0 => {
+            let distance = (position - get_target_position()).abs();
+            (distance / velocity * 1000.0) as u64
+        }
+        _ => LAST_TARGET
+            .elapsed_at(now)
+            .map_or(MAX_POSITION_INTERVAL_MS, |since_last| {
+                since_last.as_millis().min(MAX_POSITION_INTERVAL_MS)
+            }),
+    };
+
+    stream_target_at(position, duration_ms, now)
+}
+
+/// `stream_target` for a target received at `now`
+pub fn stream_target_at(
+    position: Real,
+    duration_ms: u64,
+    now: Instant,
+) -> Result<StreamFeedback, StreamError> {
+    check_streaming_allowed()?;
+    if !position.is_finite() {
+        return Err(StreamError::NotANumber);
+    }
+
+    let envelope = get_velocity_envelope();
+    let mut feedback = StreamFeedback::default();
+
+    if let Some(since_last) = LAST_TARGET.elapsed_at(now) {
+        let last_duration = Duration::from_millis(LAST_TARGET_DURATION_MS.load(Ordering::Acquire));
+        if since_last < Duration::from_millis(MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS) {
+            feedback.replaced = Some(Replaced::Dropped);
+        } else if since_last < last_duration && is_move_in_progress() {
+            feedback.replaced = Some(Replaced::Merged);
+        }
+    }
+
+    let target = saturate_range(position, envelope.min_position, envelope.max_position);
+    if target != position {
+        feedback.clamped_position = Some(target);
+    }
+
+    let distance = (target - get_target_position()).abs();
+    let velocity = if duration_ms == 0 {
+        // As fast as allowed
+        envelope.max_velocity
+    } else {
+        let required = distance / (duration_ms as Real / 1000.0);
+        if required > envelope.max_velocity {
+            feedback.clamped_velocity = Some(envelope.max_velocity);
+        }
+        saturate_range(required, envelope.min_velocity, envelope.max_velocity)
+    };
+
+    debug!(
+        "Streamed target {} mm in {} ms at {} mm/s",
+        target, duration_ms, velocity
+    );
+
+    set_max_velocity(velocity);
+    set_target_position(target);
+
+    LAST_TARGET.store(now);
+    LAST_TARGET_DURATION_MS.store(duration_ms, Ordering::Release);
+
+    Ok(feedback)
+}
+
+/// Move continuously with `velocity` in mm/s, negative towards the retracted end
+/// Only possible while the motion is disabled. The machine stops by itself before the
+/// stroke allowed by the active profile. A streamed target ends it
+pub fn stream_velocity(velocity: Real) -> Result<StreamFeedback, StreamError> {
+    check_streaming_allowed()?;
+    if !velocity.is_finite() {
+        return Err(StreamError::NotANumber);
+    }
+
+    let envelope = get_velocity_envelope();
+    let mut feedback = StreamFeedback::default();
+
+    let clamped = saturate_range(velocity, -envelope.max_velocity, envelope.max_velocity);
+    if clamped != velocity {
+        feedback.clamped_velocity = Some(clamped);
+    }
+
+    debug!("Streamed velocity {} mm/s", clamped);
+    set_target_velocity(clamped, envelope.max_position);
+
+    Ok(feedback)
+}
+
+/// Patterns and the demo own the machine while they run
+fn check_streaming_allowed() -> Result<(), StreamError> {
+    if get_motion_state().motion_enabled || is_demo_active() {
+        return Err(StreamError::MotionEnabled);
+    }
+    if is_faulted() {
+        return Err(StreamError::Faulted);
+    }
+    if !is_armed() {
+        return Err(StreamError::NotArmed);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,257 @@
+use core::{
+    cell::RefCell,
+    fmt::{self, Display},
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use critical_section::Mutex;
+use embassy_time::Instant;
+use heapless::Deque;
+use log::{debug, info};
+use portable_atomic::AtomicU64;
+
+use crate::{
+    config::{MAX_SYNC_OFFSET_MS, SYNC_CLOCK_WINDOW, SYNC_DELAY_MS, SYNC_QUEUE_LENGTH},
+    float::Real,
+    motion::stream::{get_velocity_envelope, stream_target_at},
+    motion_control::get_target_position,
+    utils::{saturate_range, scale},
+};
+
+static ROLE: AtomicU8 = AtomicU8::new(SyncRole::Off as u8);
+// The extra delay of a follower on top of SYNC_DELAY_MS
+static OFFSET_MS: AtomicU64 = AtomicU64::new(0);
+// The moves of the leader that were not sent yet
+static LEAD_MOVES: Mutex<RefCell<Deque<SyncMove, SYNC_QUEUE_LENGTH>>> =
+    Mutex::new(RefCell::new(Deque::new()));
+static FOLLOWER: Mutex<RefCell<Follower>> = Mutex::new(RefCell::new(Follower::new()));
+
+/// The leader sends the moves of its patterns with the time it started them on its own clock
+/// Followers stream them `SYNC_DELAY_MS` and their own offset later on theirs
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum SyncRole {
+    Off = 0,
+    Leader = 1,
+    Follower = 2,
+}
+
+impl SyncRole {
+    pub fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(SyncRole::Off),
+            1 => Some(SyncRole::Leader),
+            2 => Some(SyncRole::Follower),
+            _ => None,
+        }
+    }
+}
+
+impl Display for SyncRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncRole::Off => write!(f, "off"),
+            SyncRole::Leader => write!(f, "leader"),
+            SyncRole::Follower => write!(f, "follower"),
+        }
+    }
+}
+
+/// A move of the leader
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SyncMove {
+    // When the leader started it on its own clock
+    pub at: Instant,
+    // The target from 0 at the minimum to 1 at the maximum position allowed by the profile, so
+    // that machines with a different travel cover the same part of theirs
+    pub fraction: Real,
+    pub duration_ms: u32,
+}
+
+/// How far the clock of the follower is ahead of the one of the leader in ms
+/// Every packet is late by the time it took to arrive, so the least offset seen is the closest
+struct ClockOffset {
+    offset: Option<i64>,
+    // The least offset of the packets since the window started
+    window: Option<i64>,
+    samples: u32,
+}
+
+impl ClockOffset {
+    const fn new() -> Self {
+        Self {
+            offset: None,
+            window: None,
+            samples: 0,
+        }
+    }
+
+    fn sample(&mut self, offset: i64) {
+        self.window = Some(self.window.map_or(offset, |least| least.min(offset)));
+        self.samples += 1;
+        // A packet that arrived quicker is taken right away
+        if self.offset.is_none_or(|least| offset < least) {
+            self.offset = Some(offset);
+        }
+        // Starting over follows a drifting clock and a leader that rebooted
+        if self.samples == SYNC_CLOCK_WINDOW {
+            self.offset = self.window.take();
+            self.samples = 0;
+        }
+    }
+}
+
+struct Follower {
+    // Only the first leader heard is followed until the role is set again
+    leader: Option<[u8; 6]>,
+    clock: ClockOffset,
+    // The moves with when they are due on the clock of the follower
+    moves: Deque<(Instant, SyncMove), SYNC_QUEUE_LENGTH>,
+}
+
+impl Follower {
+    const fn new() -> Self {
+        Self {
+            leader: None,
+            clock: ClockOffset::new(),
+            moves: Deque::new(),
+        }
+    }
+}
+
+pub fn get_sync_role() -> SyncRole {
+    SyncRole::from_id(ROLE.load(Ordering::Acquire) as u32).unwrap_or(SyncRole::Off)
+}
+
+/// Lead, follow or neither. A follower listens for a new leader
+pub fn set_sync_role(role: SyncRole) {
+    info!("Sync role set to {}", role);
+    ROLE.store(role as u8, Ordering::Release);
+    critical_section::with(|cs| {
+        LEAD_MOVES.borrow_ref_mut(cs).clear();
+        *FOLLOWER.borrow_ref_mut(cs) = Follower::new();
+    });
+}
+
+/// The extra delay of a follower in ms
+pub fn get_sync_offset_ms() -> u64 {
+    OFFSET_MS.load(Ordering::Acquire)
+}
+
+/// Clamped to `MAX_SYNC_OFFSET_MS`. Returns the applied offset
+pub fn set_sync_offset_ms(offset_ms: u64) -> u64 {
+    let accepted = offset_ms.min(MAX_SYNC_OFFSET_MS);
+    OFFSET_MS.store(accepted, Ordering::Release);
+    accepted
+}
+
+/// Queue the move to `position` in mm at `velocity` in mm/s to be sent if this is the leader
+/// Call before the move is started
+pub fn lead_move(position: Real, velocity: Real, now: Instant) {
+    if get_sync_role() != SyncRole::Leader {
+        return;
+    }
+
+    let envelope = get_velocity_envelope();
+    let fraction = saturate_range(
+        scale(
+            position,
+            envelope.min_position,
+            envelope.max_position,
+            0.0,
+            1.0,
+        ),
+        0.0,
+        1.0,
+    );
+    let distance = (position - get_target_position()).abs();
+    let duration_ms = if velocity > 0.0 {
+        (distance / velocity * 1000.0) as u32
+    } else {
+        0
+    };
+
+    let sync_move = SyncMove {
+        at: now,
+        fraction,
+        duration_ms,
+    };
+    critical_section::with(|cs| {
+        let mut moves = LEAD_MOVES.borrow_ref_mut(cs);
+        // The followers missing a move is better than all of them falling behind
+        if moves.is_full() {
+            moves.pop_front();
+        }
+        moves.push_back(sync_move).ok();
+    });
+}
+
+/// The oldest move of the leader that was not sent yet
+pub fn take_lead_move() -> Option<SyncMove> {
+    critical_section::with(|cs| LEAD_MOVES.borrow_ref_mut(cs).pop_front())
+}
+
+/// Take a packet of a leader that was sent at `sent` on its clock and received at `now`
+/// The move, if it carries one, is queued until it is due
+/// False if this is not a follower or it follows another leader
+pub fn follow(leader: [u8; 6], sent: Instant, sync_move: Option<SyncMove>, now: Instant) -> bool {
+    if get_sync_role() != SyncRole::Follower {
+        return false;
+    }
+
+    critical_section::with(|cs| {
+        let mut follower = FOLLOWER.borrow_ref_mut(cs);
+        if *follower.leader.get_or_insert(leader) != leader {
+            return false;
+        }
+
+        follower
+            .clock
+            .sample(now.as_millis() as i64 - sent.as_millis() as i64);
+        let Some(sync_move) = sync_move else {
+            return true;
+        };
+        let offset = follower.clock.offset.unwrap_or_default();
+        let due_ms = sync_move.at.as_millis() as i64
+            + offset
+            + (SYNC_DELAY_MS + get_sync_offset_ms()) as i64;
+        let due = Instant::from_millis(due_ms.max(0) as u64);
+        if follower.moves.is_full() {
+            follower.moves.pop_front();
+        }
+        follower.moves.push_back((due, sync_move)).ok();
+        true
+    })
+}
+
+/// Stream the latest move of the leader that is due at `now`
+/// The ones before it are skipped as the machine could not have made them anyway
+pub fn run_due_moves(now: Instant) {
+    let due = critical_section::with(|cs| {
+        let mut follower = FOLLOWER.borrow_ref_mut(cs);
+        let mut latest = None;
+        while let Some(&(due, sync_move)) = follower.moves.front() {
+            if due > now {
+                break;
+            }
+            latest = Some(sync_move);
+            follower.moves.pop_front();
+        }
+        latest
+    });
+    let Some(sync_move) = due else {
+        return;
+    };
+
+    let envelope = get_velocity_envelope();
+    let position = scale(
+        sync_move.fraction,
+        0.0,
+        1.0,
+        envelope.min_position,
+        envelope.max_position,
+    );
+    if let Err(err) = stream_target_at(position, sync_move.duration_ms as u64, now) {
+        debug!("Sync move not accepted: {}", err);
+    }
+}
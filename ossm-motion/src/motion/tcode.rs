@@ -0,0 +1,148 @@
+use core::fmt::Write;
+
+use embassy_time::Instant;
+use heapless::String;
+use log::debug;
+
+use crate::{
+    config::MAX_TCODE_RESPONSE_LENGTH,
+    float::Real,
+    motion::{
+        motion_state::get_motion_state,
+        stream::{get_velocity_envelope, stream_target_at, stream_velocity},
+    },
+    motion_control::{get_actual_position_mm, get_target_position},
+    utils::scale,
+};
+
+// Answers `D1`
+const TCODE_VERSION: &str = "TCode v0.3";
+// The range speeds are given in. Magnitudes are fractions of 1 of any number of digits
+const SPEED_RANGE: Real = 10000.0;
+
+/// Run the space separated T-Code v0.3 commands of a line
+/// `L0` is streamed to the stroke allowed by the active profile and `V0` as a velocity with
+/// the standstill at `V05`. Other axes are ignored
+/// Answers the device commands `D0`, `D1` and `D2` one line each
+pub fn process_tcode_line(line: &str) -> String<MAX_TCODE_RESPONSE_LENGTH> {
+    process_tcode_line_at(line, Instant::now())
+}
+
+/// `process_tcode_line` for a line received at `now`
+pub fn process_tcode_line_at(line: &str, now: Instant) -> String<MAX_TCODE_RESPONSE_LENGTH> {
+    let mut response = String::new();
+    for command in line.split_whitespace() {
+        let is = |name: &str| command.eq_ignore_ascii_case(name);
+        let written = if is("D0") {
+            writeln!(response, "OSSM-RS {}", get_motion_state().firmware_version)
+        } else if is("D1") {
+            writeln!(response, "{}", TCODE_VERSION)
+        } else if is("D2") {
+            writeln!(response, "L0 0 9999 Stroke")
+        } else if is("DSTOP") {
+            // Stay where the machine is
+            if let Err(err) = stream_target_at(get_actual_position_mm(), 0, now) {
+                debug!("T-Code stop not accepted: {}", err);
+            }
+            Ok(())
+        } else {
+            match command.get(..2) {
+                Some(axis) if axis.eq_ignore_ascii_case("L0") => {
+                    process_linear_axis(&command[2..], now);
+                }
+                Some(axis) if axis.eq_ignore_ascii_case("V0") => {
+                    process_velocity_axis(&command[2..]);
+                }
+                // Rotation, the other vibration and auxiliary axes as well as the settings
+                _ => debug!("Ignoring the T-Code command {}", command),
+            }
+            Ok(())
+        };
+        if written.is_err() {
+            debug!("The T-Code response does not fit");
+        }
+    }
+
+    response
+}
+
+/// `<magnitude>[I<interval ms>|S<speed>]` e.g. `L0500I1000` to the middle in 1000 ms
+fn process_linear_axis(axis: &str, now: Instant) {
+    let (magnitude, extension) = axis
+        .find(['I', 'i', 'S', 's'])
+        .map_or((axis, None), |index| {
+            let (magnitude, extension) = axis.split_at(index);
+            (magnitude, Some(extension.split_at(1)))
+        });
+
+    let Some(fraction) = parse_magnitude(magnitude) else {
+        debug!("Could not parse the T-Code magnitude {}", axis);
+        return;
+    };
+    let envelope = get_velocity_envelope();
+    let position = scale(
+        fraction,
+        0.0,
+        1.0,
+        envelope.min_position,
+        envelope.max_position,
+    );
+
+    let duration_ms = match extension {
+        None => 0,
+        Some(("I" | "i", interval)) => match interval.parse::<u64>() {
+            Ok(interval) => interval,
+            Err(_) => {
+                debug!("Could not parse the T-Code interval {}", axis);
+                return;
+            }
+        },
+        Some((_, speed)) => match speed.parse::<Real>() {
+            Ok(speed) if speed > 0.0 => {
+                let stroke = envelope.max_position - envelope.min_position;
+                // The speed is in ten-thousandths of the stroke per 100 ms
+                let velocity = speed / SPEED_RANGE * stroke * 10.0;
+                let distance = (position - get_target_position()).abs();
+                (distance / velocity * 1000.0) as u64
+            }
+            _ => {
+                debug!("Could not parse the T-Code speed {}", axis);
+                return;
+            }
+        },
+    };
+
+    if let Err(err) = stream_target_at(position, duration_ms, now) {
+        debug!("T-Code target {} not accepted: {}", axis, err);
+    }
+}
+
+/// `<magnitude>` with the standstill in the middle. Intervals and speeds are ignored
+fn process_velocity_axis(axis: &str) {
+    let magnitude = axis
+        .find(['I', 'i', 'S', 's'])
+        .map_or(axis, |index| &axis[..index]);
+
+    let Some(fraction) = parse_magnitude(magnitude) else {
+        debug!("Could not parse the T-Code magnitude {}", axis);
+        return;
+    };
+    let max_velocity = get_velocity_envelope().max_velocity;
+    let velocity = scale(fraction, 0.0, 1.0, -max_velocity, max_velocity);
+
+    if let Err(err) = stream_velocity(velocity) {
+        debug!("T-Code velocity {} not accepted: {}", axis, err);
+    }
+}
+
+/// The digits after the decimal point, e.g. `5` and `500` are both 0.5
+fn parse_magnitude(digits: &str) -> Option<Real> {
+    if digits.is_empty() || !digits.bytes().all(|digit| digit.is_ascii_digit()) {
+        return None;
+    }
+
+    let fraction = digits.bytes().rev().fold(0.0, |fraction: Real, digit| {
+        (fraction + (digit - b'0') as Real) / 10.0
+    });
+    Some(fraction)
+}
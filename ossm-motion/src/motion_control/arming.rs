@@ -0,0 +1,84 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_time::{Duration, Instant};
+use log::{info, warn};
+
+use crate::{
+    config::ARM_BUTTON_WINDOW_MS,
+    motion_control::{is_faulted, rearm},
+    thermal::{ThermalLevel, get_thermal_level},
+    time::AtomicTimestamp,
+};
+
+// Boots disarmed and is disarmed again by every fault that stops the machine
+static ARMED: AtomicBool = AtomicBool::new(false);
+// Arming needs a press of the button within ARM_BUTTON_WINDOW_MS
+static BUTTON_REQUIRED: AtomicBool = AtomicBool::new(false);
+static BUTTON_PRESSED: AtomicTimestamp = AtomicTimestamp::never();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArmError {
+    // Still stopping after an emergency stop or the e-stop input is active
+    Stopping,
+    // The button was not pressed within ARM_BUTTON_WINDOW_MS
+    ButtonNotPressed,
+    // The motor or the MCU did not cool down below its critical temperature yet
+    Overheated,
+}
+
+/// Arm the machine so that it accepts the motion commands
+/// Re-arms after an emergency stop as well
+pub fn arm() -> Result<(), ArmError> {
+    arm_at(Instant::now())
+}
+
+/// `arm` at `now`
+pub fn arm_at(now: Instant) -> Result<(), ArmError> {
+    if BUTTON_REQUIRED.load(Ordering::Acquire)
+        && !BUTTON_PRESSED.is_within_at(Duration::from_millis(ARM_BUTTON_WINDOW_MS), now)
+    {
+        warn!("Press the button before arming");
+        return Err(ArmError::ButtonNotPressed);
+    }
+    if get_thermal_level() == ThermalLevel::Critical {
+        warn!("Let the machine cool down before arming");
+        return Err(ArmError::Overheated);
+    }
+    if !rearm() {
+        return Err(ArmError::Stopping);
+    }
+
+    // A press arms once
+    BUTTON_PRESSED.clear();
+    if !ARMED.swap(true, Ordering::AcqRel) {
+        info!("Armed");
+    }
+    Ok(())
+}
+
+/// Reject the motion commands until armed again
+pub fn disarm() {
+    if ARMED.swap(false, Ordering::AcqRel) {
+        info!("Disarmed");
+    }
+}
+
+/// Whether the motion commands are accepted
+pub fn is_armed() -> bool {
+    ARMED.load(Ordering::Acquire) && !is_faulted()
+}
+
+/// Require the button to be pressed before arming. Set by the firmware for boards with one
+pub fn set_arm_button_required(required: bool) {
+    BUTTON_REQUIRED.store(required, Ordering::Release);
+}
+
+/// Called by the firmware when the button is pressed
+pub fn arm_button_pressed() {
+    arm_button_pressed_at(Instant::now());
+}
+
+/// `arm_button_pressed` at `now`
+pub fn arm_button_pressed_at(now: Instant) {
+    BUTTON_PRESSED.store(now);
+}
@@ -0,0 +1,14 @@
+use crate::float::Real;
+
+/// Limits of an additional axis planned together with the stroke e.g. a twist attachment
+/// In the units of the axis e.g. degrees, degrees/s, degrees/s² and degrees/s³
+#[derive(Debug, Clone, Copy)]
+pub struct AxisLimits {
+    pub min_position: Real,
+    pub max_position: Real,
+    pub max_velocity: Real,
+    pub max_acceleration: Real,
+    pub max_jerk: Real,
+    // Motor steps per unit of the axis
+    pub steps_per_unit: Real,
+}
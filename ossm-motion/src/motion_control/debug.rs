@@ -1,11 +1,13 @@
+use crate::float::Real;
+
 pub trait DebugOut {
-    fn new_position(&mut self, position: f64);
+    fn new_position(&mut self, position: Real);
 
-    fn new_velocity(&mut self, velocity: f64);
+    fn new_velocity(&mut self, velocity: Real);
 
-    fn new_acceleration(&mut self, acceleration: f64);
+    fn new_acceleration(&mut self, acceleration: Real);
 
-    fn new_jerk(&mut self, jerk: f64);
+    fn new_jerk(&mut self, jerk: Real);
 }
 
 pub struct DummyDebugOut {}
@@ -17,11 +19,11 @@ impl DummyDebugOut {
 }
 
 impl DebugOut for DummyDebugOut {
-    fn new_position(&mut self, _position: f64) {}
+    fn new_position(&mut self, _position: Real) {}
 
-    fn new_velocity(&mut self, _velocity: f64) {}
+    fn new_velocity(&mut self, _velocity: Real) {}
 
-    fn new_acceleration(&mut self, _acceleration: f64) {}
+    fn new_acceleration(&mut self, _acceleration: Real) {}
 
-    fn new_jerk(&mut self, _jerk: f64) {}
+    fn new_jerk(&mut self, _jerk: Real) {}
 }
@@ -0,0 +1,114 @@
+use core::{cell::Cell, sync::atomic::Ordering};
+
+use critical_section::Mutex;
+use log::info;
+
+use crate::{
+    config::{
+        BELT_PITCH, MAX_BELT_PITCH, MAX_MOTOR_STEPS_PER_REVOLUTION, MAX_PULLEY_TOOTH_COUNT,
+        MIN_BELT_PITCH, MIN_MOTOR_STEPS_PER_REVOLUTION, MIN_PULLEY_TOOTH_COUNT,
+        MOTOR_STEPS_PER_REVOLUTION, PULLEY_TOOTH_COUNT, STEPS_PER_MM,
+    },
+    float::{AtomicReal, Real},
+    utils::saturate_range,
+    validation::{ValueError, check_accepted},
+};
+
+// The keys of the runtime config the mechanics are set with
+pub const MECHANICS_KEYS: [&str; 3] = ["pulleyToothCount", "beltPitch", "motorStepsPerRevolution"];
+
+/// How the turns of the motor translate to the moves of the stroke
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mechanics {
+    pub pulley_tooth_count: u32,
+    // In mm. The travel per tooth of the pinion for a rack and pinion
+    pub belt_pitch: Real,
+    pub motor_steps_per_revolution: u32,
+}
+
+impl Mechanics {
+    // As set in `config`
+    pub const DEFAULT: Self = Self {
+        pulley_tooth_count: PULLEY_TOOTH_COUNT,
+        belt_pitch: BELT_PITCH,
+        motor_steps_per_revolution: MOTOR_STEPS_PER_REVOLUTION,
+    };
+
+    pub fn mm_per_rotation(&self) -> Real {
+        self.pulley_tooth_count as Real * self.belt_pitch
+    }
+
+    pub fn steps_per_mm(&self) -> Real {
+        self.motor_steps_per_revolution as Real / self.mm_per_rotation()
+    }
+
+    /// Change a value by its key in `MECHANICS_KEYS`
+    /// Unknown keys are rejected. Out of range values are clamped and applied
+    pub fn set_value(&mut self, key: &str, value: Real) -> Result<(), ValueError> {
+        if !value.is_finite() {
+            return Err(ValueError::NotANumber);
+        }
+
+        match key {
+            "pulleyToothCount" => {
+                self.pulley_tooth_count =
+                    clamp_count(value, MIN_PULLEY_TOOTH_COUNT, MAX_PULLEY_TOOTH_COUNT);
+                check_accepted(value as i64, self.pulley_tooth_count as i64)
+            }
+            "beltPitch" => {
+                self.belt_pitch = saturate_range(value, MIN_BELT_PITCH, MAX_BELT_PITCH);
+                check_accepted(value as i64, self.belt_pitch as i64)
+            }
+            "motorStepsPerRevolution" => {
+                self.motor_steps_per_revolution = clamp_count(
+                    value,
+                    MIN_MOTOR_STEPS_PER_REVOLUTION,
+                    MAX_MOTOR_STEPS_PER_REVOLUTION,
+                );
+                check_accepted(value as i64, self.motor_steps_per_revolution as i64)
+            }
+            _ => Err(ValueError::Unknown),
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        (MIN_PULLEY_TOOTH_COUNT..=MAX_PULLEY_TOOTH_COUNT).contains(&self.pulley_tooth_count)
+            && (MIN_BELT_PITCH..=MAX_BELT_PITCH).contains(&self.belt_pitch)
+            && (MIN_MOTOR_STEPS_PER_REVOLUTION..=MAX_MOTOR_STEPS_PER_REVOLUTION)
+                .contains(&self.motor_steps_per_revolution)
+    }
+}
+
+static MECHANICS: Mutex<Cell<Mechanics>> = Mutex::new(Cell::new(Mechanics::DEFAULT));
+// Of MECHANICS. Read by the control loop on every update
+static STEPS_PER_MM_IN_USE: AtomicReal = AtomicReal::new(STEPS_PER_MM);
+
+fn clamp_count(value: Real, min: u32, max: u32) -> u32 {
+    saturate_range(value, min as Real, max as Real) as u32
+}
+
+/// The mechanics in use
+pub fn get_mechanics() -> Mechanics {
+    critical_section::with(|cs| MECHANICS.borrow(cs).get())
+}
+
+/// Motor steps per mm of the stroke
+pub fn get_steps_per_mm() -> Real {
+    STEPS_PER_MM_IN_USE.load(Ordering::Acquire)
+}
+
+/// Use other mechanics. Only before the machine homes at boot
+/// Mechanics out of the accepted ranges are rejected and the defaults kept
+pub fn set_mechanics(mechanics: Mechanics) -> Result<(), ValueError> {
+    if !mechanics.is_valid() {
+        return Err(ValueError::Unknown);
+    }
+
+    info!(
+        "Mechanics set to a {} tooth pulley, {} mm pitch and {} steps per revolution",
+        mechanics.pulley_tooth_count, mechanics.belt_pitch, mechanics.motor_steps_per_revolution
+    );
+    critical_section::with(|cs| MECHANICS.borrow(cs).set(mechanics));
+    STEPS_PER_MM_IN_USE.store(mechanics.steps_per_mm(), Ordering::Release);
+    Ok(())
+}
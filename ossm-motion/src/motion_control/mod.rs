@@ -1,5 +1,10 @@
+pub mod arming;
+pub mod axis;
 pub mod debug;
+pub mod mechanics;
 pub mod motor;
+pub mod recorder;
+pub mod sinusoid;
 pub mod timer;
 
 use core::{
@@ -8,20 +13,32 @@ use core::{
 };
 
 use log::{debug, error, info};
-use portable_atomic::{AtomicF64, AtomicU16};
+use portable_atomic::{AtomicU32, AtomicU64};
 use rsruckig::prelude::*;
 
 use crate::{
     config::*,
+    event::{Event, publish_event},
+    fault::{FaultCode, Recovery, clear_faults, raise_fault, take_unrecorded_faults},
+    float::{AtomicReal, Real, from_f64, to_f64},
     motion_control::{
         debug::{DebugOut, DummyDebugOut},
+        mechanics::get_steps_per_mm,
         motor::Motor,
+        recorder::{Record, record},
+        sinusoid::Sinusoid,
         timer::{Duration, Instant, Timer},
     },
+    profile::get_active_limits,
+    thermal::{ThermalLevel, get_thermal_level, get_thermal_limit_pct, set_motor_temperature_c},
+    time::timer_elapsed,
     utils::{saturate_range, scale},
+    validation::{ValueError, check_accepted},
 };
 
 static MOVE_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+// Continuous velocity streaming instead of position targets
+static VELOCITY_MODE: AtomicBool = AtomicBool::new(false);
 
 // Whether to panic on the thresholds being exceeded by motion control
 // If false the values will be capped to the allowed limits, but the execution will continue
@@ -29,50 +46,195 @@ const PANIC_ON_EXCEEEDED: bool = false;
 
 const VELOCITY_UPDATE_COOLDOWN_MS: u64 = 30;
 
+// Multiplier for the braking distance used to stop before the bounds in velocity mode
+const VELOCITY_MODE_BRAKING_MARGIN: Real = 1.5;
+
+// The motor is considered disconnected after this many failed writes in a row
+const MAX_CONSECUTIVE_MOTOR_ERRORS: u32 = 10;
+
+// The velocity is lowered after this many updates in a row took longer than the update interval
+const MAX_CONSECUTIVE_OVERRUNS: u32 = 5;
+// Applied to the velocity every time the overruns persist
+const OVERRUN_VELOCITY_FACTOR: Real = 0.8;
+
+// A sinusoid is only started below this velocity in mm/s. Ruckig plans the other moves
+const SINUSOID_MAX_START_VELOCITY: Real = 1.0;
+
+/// How the moves to the target positions are planned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    // Jerk limited. Replans smoothly when the target changes mid-move
+    Ruckig,
+    // Half cosine between standstills like StrokeEngine. Cheaper to compute
+    Sinusoidal,
+}
+
+impl Interpolation {
+    /// The ID used by the runtime config
+    pub fn id(self) -> u32 {
+        match self {
+            Interpolation::Ruckig => 0,
+            Interpolation::Sinusoidal => 1,
+        }
+    }
+
+    pub fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(Interpolation::Ruckig),
+            1 => Some(Interpolation::Sinusoidal),
+            _ => None,
+        }
+    }
+}
+
 struct MotionControlStateStorage {
-    position: AtomicF64,
-    velocity: AtomicF64,
-    torque: AtomicU16,
+    position: AtomicReal,
+    velocity: AtomicReal,
+    target_velocity: AtomicReal,
+    // The velocity mode stops before it
+    velocity_max_position: AtomicReal,
+    // In %
+    torque: AtomicReal,
+    acceleration: AtomicReal,
+    jerk: AtomicReal,
+    // Where the trajectory is now. Read only
+    actual_position: AtomicReal,
+    actual_velocity: AtomicReal,
 }
 
 static MOTION_CONTROL_STATE_UPDATED: AtomicBool = AtomicBool::new(false);
+// Estimated motor load in % as last read from the motor
+static LOAD_PCT: AtomicU32 = AtomicU32::new(0);
+// The maximum allowed move forward from the homing position in mm. Set by the calibration
+static MAX_MOVE: AtomicReal = AtomicReal::new(MAX_MOVE_MM);
+// Restrict the travel within MIN_MOVE_MM-MAX_MOVE for the session in mm
+// Infinite if the max is not restricted so that it follows the calibration
+static SOFT_MIN_MOVE: AtomicReal = AtomicReal::new(MIN_MOVE_MM);
+static SOFT_MAX_MOVE: AtomicReal = AtomicReal::new(Real::INFINITY);
+// Positive positions are positive steps of the motor if set. Starts out as REVERSE_DIRECTION
+static DIRECTION_REVERSED: AtomicBool = AtomicBool::new(REVERSE_DIRECTION);
+// Cleared when the motor stops responding. The control loop is paused until it reconnects
+static MOTOR_CONNECTED: AtomicBool = AtomicBool::new(true);
+// Target positions of the axes after the stroke in the units of the axis
+static ADDITIONAL_AXIS_TARGETS: [AtomicReal; MAX_AXES - 1] =
+    [const { AtomicReal::new(0.0) }; MAX_AXES - 1];
+// Set by an emergency stop. New targets are rejected until the machine is re-armed
+static FAULTED: AtomicBool = AtomicBool::new(false);
+// The control loop has not started stopping yet
+static EMERGENCY_STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+// The hardware e-stop input reads active. The machine can not be re-armed until it clears
+static ESTOP_INPUT_ACTIVE: AtomicBool = AtomicBool::new(false);
+// Set by the e-stop input until re-armed. The torque is dropped once the machine stopped
+static ESTOP_LATCHED: AtomicBool = AtomicBool::new(false);
+// Hold the machine mid-move. The move continues to its target when resumed
+static PAUSED: AtomicBool = AtomicBool::new(false);
+// Stop and keep the position the machine stopped at until a new target is set
+static HOLDING: AtomicBool = AtomicBool::new(false);
+// Lowered when the control loop keeps overrunning. Infinite if not limited
+static OVERRUN_VELOCITY_LIMIT: AtomicReal = AtomicReal::new(Real::INFINITY);
+// When the control loop last ran in timer ticks. Checked by the watchdog
+static LAST_LOOP_RUN: AtomicU64 = AtomicU64::new(0);
+// Position moves are planned as sinusoids instead of with ruckig
+static SINUSOIDAL_INTERPOLATION: AtomicBool = AtomicBool::new(false);
+// How long the velocity ramps up for after the motion is enabled in s. 0 if disabled
+static EASE_IN_DURATION_S: AtomicU32 = AtomicU32::new(0);
+// The ramp is restarted by the control loop
+static EASE_IN_REQUESTED: AtomicBool = AtomicBool::new(false);
+// Progress of the ramp in %. 100 if not ramping
+static EASE_IN_PCT: AtomicU32 = AtomicU32::new(100);
+// How long the torque takes to rise from 0 to 100% in ms
+static TORQUE_SLEW_MS: AtomicU32 = AtomicU32::new(TORQUE_SLEW_TIME_MS);
+// The last torque set was capped by the profile. The event is only published when it starts
+static TORQUE_LIMITED: AtomicBool = AtomicBool::new(false);
 static MOTION_CONTROL_STATE: MotionControlStateStorage = MotionControlStateStorage {
-    position: AtomicF64::new(MIN_MOVE_MM),
-    velocity: AtomicF64::new(MOTION_CONTROL_MIN_VELOCITY),
-    torque: AtomicU16::new(0),
+    position: AtomicReal::new(MIN_MOVE_MM),
+    velocity: AtomicReal::new(MOTION_CONTROL_MIN_VELOCITY),
+    target_velocity: AtomicReal::new(0.0),
+    velocity_max_position: AtomicReal::new(MAX_MOVE_MM),
+    // The motors start out with their full output
+    torque: AtomicReal::new(100.0),
+    acceleration: AtomicReal::new(MOTION_CONTROL_MAX_ACCELERATION),
+    jerk: AtomicReal::new(MOTION_CONTROL_MAX_JERK),
+    actual_position: AtomicReal::new(MIN_MOVE_MM),
+    actual_velocity: AtomicReal::new(0.0),
 };
 
-pub struct MotionControl<M: Motor, T: Timer, D: DebugOut> {
+/// Plans the stroke and up to MAX_AXES - 1 additional axes together
+/// The stroke is axis 0. The others use ADDITIONAL_AXIS_LIMITS
+pub struct MotionControl<M: Motor, T: Timer, D: DebugOut, const DOF: usize = 1> {
     motor: M,
     timer: T,
     debug: D,
-    ruckig: Ruckig<1, ThrowErrorHandler>,
-    input: InputParameter<1>,
-    output: OutputParameter<1>,
+    ruckig: Ruckig<DOF, ThrowErrorHandler>,
+    input: InputParameter<DOF>,
+    output: OutputParameter<DOF>,
     last_update: Instant,
-    velocity_setpoint: f64,
-    torque_setpoint: u16,
+    velocity_setpoint: Real,
+    // In %
+    torque_setpoint: Real,
+    // The torque written to the motor in %. None if not known
+    torque_output: Option<Real>,
     last_velocity_update: Instant,
     last_motor_write: Instant,
+    last_load_update: Instant,
+    last_temperature_update: Instant,
+    // Since when the load is at the torque limit during a move
+    obstructed_since: Option<Instant>,
+    last_sync_check: Instant,
+    last_settings_check: Instant,
+    velocity_mode: bool,
+    // Decelerating to a standstill after an emergency stop
+    stopping: bool,
+    // Decelerating to or holding a standstill until resumed
+    paused: bool,
+    // Decelerating to a standstill and keeping the position afterwards
+    holding: bool,
+    // The standstill was reached while holding
+    hold_reached: bool,
+    last_hold_write: Instant,
+    consecutive_motor_errors: u32,
+    consecutive_overruns: u32,
+    // The move in progress with the sinusoidal interpolation
+    sinusoid: Option<Sinusoid>,
+    // Time into the sinusoid in s
+    sinusoid_time: Real,
+    // When the velocity ramp started. None if not ramping
+    ease_in_start: Option<Instant>,
+    last_record: Instant,
 }
 
-impl<M: Motor, T: Timer> MotionControl<M, T, DummyDebugOut> {
+impl<M: Motor, T: Timer, const DOF: usize> MotionControl<M, T, DummyDebugOut, DOF> {
     /// Initialises the MotionControl and allows the use of attached functions
     pub fn new(motor: M, timer: T) -> Self {
         Self::new_with_debug(motor, timer, DummyDebugOut::new())
     }
 }
 
-impl<M: Motor, T: Timer, D: DebugOut> MotionControl<M, T, D> {
+impl<M: Motor, T: Timer, D: DebugOut, const DOF: usize> MotionControl<M, T, D, DOF> {
     pub fn new_with_debug(motor: M, timer: T, debug: D) -> Self {
-        info!("Motion Control Init");
+        const { assert!(DOF >= 1 && DOF <= MAX_AXES, "DOF has to be 1-MAX_AXES") };
+        info!("Motion Control Init with {} axes", DOF);
 
         let mut input = InputParameter::new(None);
 
-        input.current_position[0] = MIN_MOVE_MM;
-        input.max_velocity[0] = MOTION_CONTROL_MIN_VELOCITY;
-        input.max_acceleration[0] = MOTION_CONTROL_MAX_ACCELERATION;
-        input.max_jerk[0] = MOTION_CONTROL_MAX_JERK;
+        input.current_position[0] = to_f64(MIN_MOVE_MM);
+        input.max_velocity[0] = to_f64(MOTION_CONTROL_MIN_VELOCITY);
+        input.max_acceleration[0] = to_f64(get_max_acceleration());
+        input.max_jerk[0] = to_f64(get_max_jerk());
+        for axis in 1..DOF {
+            let limits = &ADDITIONAL_AXIS_LIMITS[axis - 1];
+            let position = to_f64(saturate_range(
+                0.0,
+                limits.min_position,
+                limits.max_position,
+            ));
+            input.current_position[axis] = position;
+            input.target_position[axis] = position;
+            input.max_velocity[axis] = to_f64(limits.max_velocity);
+            input.max_acceleration[axis] = to_f64(limits.max_acceleration);
+            input.max_jerk[axis] = to_f64(limits.max_jerk);
+        }
+        // Each axis moves at its own pace instead of all of them arriving together
         input.synchronization = Synchronization::None;
         input.duration_discretization = DurationDiscretization::Discrete;
 
@@ -82,7 +244,7 @@ impl<M: Motor, T: Timer, D: DebugOut> MotionControl<M, T, D> {
             motor,
             timer,
             debug,
-            ruckig: Ruckig::<1, ThrowErrorHandler>::new(
+            ruckig: Ruckig::<DOF, ThrowErrorHandler>::new(
                 None,
                 MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS as f64 / 1000.0,
             ),
@@ -90,9 +252,27 @@ impl<M: Motor, T: Timer, D: DebugOut> MotionControl<M, T, D> {
             output: OutputParameter::new(None),
             last_update: now,
             velocity_setpoint: MOTION_CONTROL_MIN_VELOCITY,
-            torque_setpoint: 0,
+            torque_setpoint: MOTION_CONTROL_STATE.torque.load(Ordering::Acquire),
+            torque_output: None,
             last_velocity_update: now,
             last_motor_write: now,
+            last_load_update: now,
+            last_temperature_update: now,
+            obstructed_since: None,
+            last_sync_check: now,
+            last_settings_check: now,
+            velocity_mode: false,
+            stopping: false,
+            paused: false,
+            holding: false,
+            hold_reached: false,
+            last_hold_write: now,
+            consecutive_motor_errors: 0,
+            consecutive_overruns: 0,
+            sinusoid: None,
+            sinusoid_time: 0.0,
+            ease_in_start: None,
+            last_record: now,
         };
 
         motion_control
@@ -100,46 +280,148 @@ impl<M: Motor, T: Timer, D: DebugOut> MotionControl<M, T, D> {
 
     /// The handler that must be called every MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS
     pub fn update_handler(&mut self) {
-        if MOTION_CONTROL_STATE_UPDATED.load(Ordering::Acquire) {
+        let now = self.timer.now();
+        LAST_LOOP_RUN.store(now.ticks(), Ordering::Release);
+        // Also the ones raised from outside of the loop, e.g. while it was stalled
+        for fault in take_unrecorded_faults() {
+            record(Record::Fault {
+                time_ms: now.duration_since_epoch().to_millis(),
+                fault,
+            });
+        }
+
+        if !MOTOR_CONNECTED.load(Ordering::Acquire) {
+            // Nothing is driven until the motor is reconnected, which holds the position anyway.
+            // An emergency stop in the meantime is complete, so that the machine can be re-armed
+            EMERGENCY_STOP_REQUESTED.store(false, Ordering::Release);
+            MOVE_IN_PROGRESS.store(false, Ordering::Release);
+            return;
+        }
+
+        if EASE_IN_REQUESTED.swap(false, Ordering::AcqRel) {
+            self.ease_in_start = Some(self.timer.now());
+        }
+        let ease_in = self.update_ease_in();
+
+        if EMERGENCY_STOP_REQUESTED.swap(false, Ordering::AcqRel) {
+            self.begin_emergency_stop();
+        }
+
+        let paused = PAUSED.load(Ordering::Acquire);
+        if paused != self.paused && !self.stopping {
+            info!("Motion paused: {}", paused);
+            self.paused = paused;
+            if paused {
+                for axis in 0..DOF {
+                    self.input.target_velocity[axis] = 0.0;
+                }
+            }
+            self.update_control_interface();
+        }
+
+        let holding = HOLDING.load(Ordering::Acquire);
+        if holding != self.holding && !self.stopping {
+            info!("Holding: {}", holding);
+            self.holding = holding;
+            self.hold_reached = false;
+            if holding {
+                for axis in 0..DOF {
+                    self.input.target_velocity[axis] = 0.0;
+                }
+                // Also run when standing still to reach the hold
+                MOVE_IN_PROGRESS.store(true, Ordering::Release);
+            }
+            self.update_control_interface();
+        }
+
+        // Updates are applied once the machine stands still
+        if !self.stopping && MOTION_CONTROL_STATE_UPDATED.load(Ordering::Acquire) {
             MOTION_CONTROL_STATE_UPDATED.store(false, Ordering::Release);
-            let position = MOTION_CONTROL_STATE.position.load(Ordering::Acquire) as f64;
-            if position != self.input.target_position[0] {
+            let velocity_mode = VELOCITY_MODE.load(Ordering::Acquire);
+            if velocity_mode != self.velocity_mode {
+                info!("Velocity mode: {}", velocity_mode);
+                self.velocity_mode = velocity_mode;
+                self.update_control_interface();
+            }
+
+            let position = MOTION_CONTROL_STATE.position.load(Ordering::Acquire);
+            let position = saturate_range(position, get_min_move_mm(), get_max_move_mm());
+            if !velocity_mode && to_f64(position) != self.input.target_position[0] {
                 info!("Going to a new target position: {} mm", position);
-                self.input.target_position[0] = position;
+                self.input.target_position[0] = to_f64(position);
                 self.output.time = 0.0;
             }
 
-            let velocity = MOTION_CONTROL_STATE.velocity.load(Ordering::Acquire) as f64;
+            for axis in 1..DOF {
+                let position = to_f64(ADDITIONAL_AXIS_TARGETS[axis - 1].load(Ordering::Acquire));
+                if position != self.input.target_position[axis] {
+                    info!(
+                        "Going to a new target position on axis {}: {}",
+                        axis, position
+                    );
+                    self.input.target_position[axis] = position;
+                    self.output.time = 0.0;
+                }
+            }
+
+            let velocity = MOTION_CONTROL_STATE.velocity.load(Ordering::Acquire);
             if velocity != self.velocity_setpoint {
                 self.velocity_setpoint = velocity;
                 self.last_velocity_update = self.timer.now();
             }
 
+            let acceleration = to_f64(get_max_acceleration());
+            let jerk = to_f64(get_max_jerk());
+            if acceleration != self.input.max_acceleration[0] || jerk != self.input.max_jerk[0] {
+                info!("Acceleration {} mm/s² Jerk {} mm/s³", acceleration, jerk);
+                self.input.max_acceleration[0] = acceleration;
+                self.input.max_jerk[0] = jerk;
+                self.output.time = 0.0;
+            }
+
             let torque = MOTION_CONTROL_STATE.torque.load(Ordering::Acquire);
             if torque != self.torque_setpoint {
-                info!("Torque set to {}", torque);
+                info!("Torque set to {} %", torque);
                 self.torque_setpoint = torque;
-                self.motor
-                    .set_max_allowed_output(torque as u16)
-                    .expect("Failed to set max allowed output (torque)");
             }
         }
 
+        self.update_torque();
+
         if MOVE_IN_PROGRESS.load(Ordering::Acquire) {
             let start = self.timer.now();
 
             // Restrict how often the velocity can be updated
             // Updating it too often can lead to unstable motion
-            if self.velocity_setpoint != self.input.max_velocity[0]
+            let velocity_setpoint = (self.velocity_setpoint * ease_in)
+                .min(get_overrun_velocity_limit())
+                .min(MOTION_CONTROL_MAX_VELOCITY * get_thermal_limit_pct() / 100.0)
+                .max(MOTION_CONTROL_MIN_VELOCITY);
+            if !self.stopping
+                && to_f64(velocity_setpoint) != self.input.max_velocity[0]
                 && self.elapsed(self.last_velocity_update).to_millis() > VELOCITY_UPDATE_COOLDOWN_MS
             {
-                self.input.max_velocity[0] = self.velocity_setpoint;
+                self.input.max_velocity[0] = to_f64(velocity_setpoint);
                 self.output.time = 0.0;
                 self.last_velocity_update = self.timer.now();
-                info!("Set velocity to {} mm/s", self.velocity_setpoint);
+                info!("Set velocity to {} mm/s", velocity_setpoint);
             }
 
-            let res = self.ruckig.update(&self.input, &mut self.output);
+            if self.velocity_mode && !self.stopping && !self.paused && !self.holding {
+                let target_velocity = to_f64(self.bounded_target_velocity(
+                    MOTION_CONTROL_STATE.target_velocity.load(Ordering::Acquire),
+                ));
+                if target_velocity != self.input.target_velocity[0] {
+                    debug!("Target velocity {} mm/s", target_velocity);
+                    self.input.target_velocity[0] = target_velocity;
+                    self.output.time = 0.0;
+                }
+            }
+
+            let res = match self.update_sinusoid() {
+                Some(res) => Ok(res),
+                None => self.ruckig.update(&self.input, &mut self.output),
+            };
 
             let since_last = self.elapsed(self.last_update).to_micros();
             self.last_update = self.timer.now();
@@ -148,59 +430,43 @@ impl<M: Motor, T: Timer, D: DebugOut> MotionControl<M, T, D> {
                 Ok(ok) => {
                     match ok {
                         RuckigResult::Working => {
-                            let mut new_position = self.output.new_position[0];
-
-                            // Saturate the position if out of bounds
-                            let mut exceeded = false;
-                            if new_position < MIN_MOVE_MM {
-                                error!(
-                                    "Motion control exceeded the min allowed move ({} < {})",
-                                    new_position, MIN_MOVE_MM
-                                );
-                                new_position = MIN_MOVE_MM;
-                                exceeded = true;
-                            }
-
-                            if new_position > MAX_MOVE_MM {
-                                error!(
-                                    "Motion control exceeded the max allowed move ({} > {})",
-                                    new_position, MAX_MOVE_MM
-                                );
-                                new_position = MAX_MOVE_MM;
-                                exceeded = true;
-                            }
-
-                            if exceeded && PANIC_ON_EXCEEEDED {
-                                panic!("Motion control thresholds were exceeded. See above ^");
-                            }
-
-                            let mut new_steps = new_position * STEPS_PER_MM;
-                            if !REVERSE_DIRECTION {
-                                new_steps = -new_steps;
-                            }
-
-                            // Avoid writing to the motor too often to prevent a timeout
-                            let since_last_motor_write = self.elapsed(self.last_motor_write);
-                            if since_last_motor_write < M::min_consecutive_write_delay() {
-                                self.motor.delay(
-                                    M::min_consecutive_write_delay() - since_last_motor_write,
-                                );
-                            }
-
-                            if let Err(err) = self.motor.set_absolute_position(new_steps as i32) {
-                                error!("Failed to set motor position {:?}", err);
-                            }
-                            self.last_motor_write = self.timer.now();
-
-                            debug!("Set motor position to {} mm", new_position);
+                            let new_position =
+                                self.write_position(from_f64(self.output.new_position[0]));
+                            self.write_additional_axes();
 
                             self.debug.new_position(new_position);
-                            self.debug.new_velocity(self.output.new_velocity[0]);
-                            self.debug.new_acceleration(self.output.new_acceleration[0]);
-                            self.debug.new_jerk(self.output.new_jerk[0]);
+                            self.debug
+                                .new_velocity(from_f64(self.output.new_velocity[0]));
+                            self.debug
+                                .new_acceleration(from_f64(self.output.new_acceleration[0]));
+                            self.debug.new_jerk(from_f64(self.output.new_jerk[0]));
 
                             self.output.pass_to_input(&mut self.input);
                         }
+                        RuckigResult::Finished if self.stopping => {
+                            self.finish_emergency_stop();
+                        }
+                        RuckigResult::Finished if self.holding && !self.hold_reached => {
+                            self.finish_hold();
+                        }
+                        RuckigResult::Finished if self.paused => {
+                            // Hold until resumed
+                        }
+                        RuckigResult::Finished if self.velocity_mode => {
+                            // The target velocity has been reached. Keep moving with it
+                            let velocity = self.input.current_velocity[0];
+                            if velocity != 0.0 {
+                                let dt = MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS as f64 / 1000.0;
+                                let new_position = self.input.current_position[0] + velocity * dt;
+                                self.input.current_position[0] = new_position;
+                                let new_position = self.write_position(from_f64(new_position));
+
+                                self.debug.new_position(new_position);
+                                self.debug.new_velocity(from_f64(velocity));
+                                self.debug.new_acceleration(0.0);
+                                self.debug.new_jerk(0.0);
+                            }
+                        }
                         RuckigResult::Finished => {
                             MOVE_IN_PROGRESS.store(false, Ordering::Release);
                         }
@@ -214,6 +480,17 @@ impl<M: Motor, T: Timer, D: DebugOut> MotionControl<M, T, D> {
                 }
             }
 
+            MOTION_CONTROL_STATE
+                .actual_position
+                .store(from_f64(self.input.current_position[0]), Ordering::Release);
+            MOTION_CONTROL_STATE
+                .actual_velocity
+                .store(from_f64(self.input.current_velocity[0]), Ordering::Release);
+
+            self.update_load();
+            self.check_sync();
+            self.verify_settings();
+
             let duration_ms = self.elapsed(start).to_millis();
 
             debug!(
@@ -226,17 +503,591 @@ impl<M: Motor, T: Timer, D: DebugOut> MotionControl<M, T, D> {
                     "Update took longer than the update interval {} > {}",
                     duration_ms, MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS
                 );
+                self.overrun();
+            } else {
+                self.consecutive_overruns = 0;
+            }
+        } else {
+            // Only read during moves. Not the load of the last one anymore
+            LOAD_PCT.store(0, Ordering::Release);
+
+            if self.hold_reached
+                && self.elapsed(self.last_hold_write).to_millis() >= HOLD_REFRESH_INTERVAL_MS
+            {
+                // Keep commanding the position so that the shaft resists being pushed
+                self.write_position(from_f64(self.input.current_position[0]));
+                self.last_hold_write = self.timer.now();
+            }
+
+            self.debug
+                .new_position(from_f64(self.output.new_position[0]));
+            self.debug
+                .new_velocity(from_f64(self.output.new_velocity[0]));
+            self.debug
+                .new_acceleration(from_f64(self.output.new_acceleration[0]));
+            self.debug.new_jerk(from_f64(self.output.new_jerk[0]));
+        }
+
+        // Also while standing still as the motor keeps its torque
+        self.update_temperature(now);
+
+        if self.elapsed(self.last_record).to_millis() >= RECORDER_INTERVAL_MS {
+            self.record_sample();
+        }
+    }
+
+    /// Add the commanded trajectory and torque to the recorder
+    fn record_sample(&mut self) {
+        let now = self.timer.now();
+        record(Record::Sample {
+            time_ms: now.duration_since_epoch().to_millis(),
+            position: from_f64(self.input.current_position[0]),
+            velocity: from_f64(self.input.current_velocity[0]),
+            torque: self.torque_output.unwrap_or(0.0),
+        });
+        self.last_record = now;
+    }
+
+    /// Saturate the position to the bounds of the machine and write it to the motor
+    /// The soft limits are applied to the targets instead. Changing them never makes it jump
+    /// Returns the position that was written
+    fn write_position(&mut self, mut new_position: Real) -> Real {
+        // Saturate the position if out of bounds
+        let mut exceeded = false;
+        if new_position < MIN_MOVE_MM {
+            error!(
+                "Motion control exceeded the min allowed move ({} < {})",
+                new_position, MIN_MOVE_MM
+            );
+            new_position = MIN_MOVE_MM;
+            exceeded = true;
+        }
+
+        let max_move = MAX_MOVE.load(Ordering::Acquire);
+        if new_position > max_move {
+            error!(
+                "Motion control exceeded the max allowed move ({} > {})",
+                new_position, max_move
+            );
+            new_position = max_move;
+            exceeded = true;
+        }
+
+        if exceeded {
+            raise_fault(FaultCode::LimitExceeded);
+        }
+        if exceeded && PANIC_ON_EXCEEEDED {
+            panic!("Motion control thresholds were exceeded. See above ^");
+        }
+
+        let mut new_steps = new_position * get_steps_per_mm();
+        if !is_direction_reversed() {
+            new_steps = -new_steps;
+        }
+
+        // Avoid writing to the motor too often to prevent a timeout
+        let since_last_motor_write = self.elapsed(self.last_motor_write);
+        if since_last_motor_write < M::min_consecutive_write_delay() {
+            self.motor
+                .delay(M::min_consecutive_write_delay() - since_last_motor_write);
+        }
+
+        match self.motor.set_absolute_position(new_steps as i32) {
+            Ok(()) => self.consecutive_motor_errors = 0,
+            Err(err) => {
+                error!("Failed to set motor position {:?}", err);
+                self.motor_error();
+            }
+        }
+        self.last_motor_write = self.timer.now();
+
+        debug!("Set motor position to {} mm", new_position);
+
+        new_position
+    }
+
+    /// Write the planned positions of the axes after the stroke to the motor
+    fn write_additional_axes(&mut self) {
+        for axis in 1..DOF {
+            let limits = &ADDITIONAL_AXIS_LIMITS[axis - 1];
+            let position = saturate_range(
+                from_f64(self.output.new_position[axis]),
+                limits.min_position,
+                limits.max_position,
+            );
+            let steps = position * limits.steps_per_unit;
+
+            if let Err(err) = self.motor.set_axis_position(axis, steps as i32) {
+                error!("Failed to set the position of axis {} {:?}", axis, err);
+                self.motor_error();
+            }
+        }
+    }
+
+    /// Read the motor load every LOAD_UPDATE_INTERVAL_MS
+    fn update_load(&mut self) {
+        if self.elapsed(self.last_load_update).to_millis() < LOAD_UPDATE_INTERVAL_MS {
+            return;
+        }
+        self.last_load_update = self.timer.now();
+
+        let since_last_motor_write = self.elapsed(self.last_motor_write);
+        if since_last_motor_write < M::min_consecutive_write_delay() {
+            self.motor
+                .delay(M::min_consecutive_write_delay() - since_last_motor_write);
+        }
+
+        match self.motor.get_load_pct() {
+            Ok(Some(load)) => {
+                let load = saturate_range(load, 0.0, 100.0);
+                LOAD_PCT.store(load as u32, Ordering::Release);
+                self.check_obstruction(load);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                error!("Failed to read the motor load {:?}", err);
+            }
+        }
+        self.last_motor_write = self.timer.now();
+    }
+
+    /// Read the motor temperature every TEMPERATURE_UPDATE_INTERVAL_MS
+    /// The read blocks, so during a move it waits for an update with half of the interval left,
+    /// e.g. one that did not read the load as well
+    fn update_temperature(&mut self, update_start: Instant) {
+        if self.elapsed(self.last_temperature_update).to_millis() < TEMPERATURE_UPDATE_INTERVAL_MS {
+            return;
+        }
+        if is_move_in_progress()
+            && self.elapsed(update_start).to_millis() >= MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS / 2
+        {
+            return;
+        }
+        self.last_temperature_update = self.timer.now();
+
+        let since_last_motor_write = self.elapsed(self.last_motor_write);
+        if since_last_motor_write < M::min_consecutive_write_delay() {
+            self.motor
+                .delay(M::min_consecutive_write_delay() - since_last_motor_write);
+        }
+
+        match self.motor.get_temperature_c() {
+            Ok(Some(temperature)) => set_motor_temperature_c(temperature),
+            Ok(None) => {}
+            Err(err) => {
+                error!("Failed to read the motor temperature {:?}", err);
+            }
+        }
+        self.last_motor_write = self.timer.now();
+    }
+
+    /// Stop if the load stayed at the torque limit for OBSTRUCTION_TIME_MS during a move
+    /// The limit is the torque written to the motor on the scale of the load it reports
+    fn check_obstruction(&mut self, load: Real) {
+        let Some(torque) = self.torque_output else {
+            self.obstructed_since = None;
+            return;
+        };
+        let limit = M::load_limit_pct(torque) * OBSTRUCTION_LOAD_RATIO;
+        if OBSTRUCTION_TIME_MS == 0 || self.stopping || !is_move_in_progress() || load < limit {
+            self.obstructed_since = None;
+            return;
+        }
+
+        let since = *self.obstructed_since.get_or_insert(self.timer.now());
+        if self.elapsed(since).to_millis() >= OBSTRUCTION_TIME_MS {
+            error!("The load stayed at {}% during the move. Stopping", load);
+            self.obstructed_since = None;
+            raise_fault(FaultCode::Obstruction);
+        }
+    }
+
+    /// Check the motors for being in sync every SYNC_CHECK_INTERVAL_MS
+    /// Motion is paused until the motors are reconnected if they are not
+    fn check_sync(&mut self) {
+        if self.elapsed(self.last_sync_check).to_millis() < SYNC_CHECK_INTERVAL_MS {
+            return;
+        }
+        self.last_sync_check = self.timer.now();
+
+        let since_last_motor_write = self.elapsed(self.last_motor_write);
+        if since_last_motor_write < M::min_consecutive_write_delay() {
+            self.motor
+                .delay(M::min_consecutive_write_delay() - since_last_motor_write);
+        }
+
+        if let Err(err) = self.motor.check_sync() {
+            error!("Motor sync check failed {:?}. Pausing motion control", err);
+            MOTOR_CONNECTED.store(false, Ordering::Release);
+            raise_fault(FaultCode::MotorTimeout);
+        }
+        self.last_motor_write = self.timer.now();
+    }
+
+    /// Check the motor settings every SETTINGS_CHECK_INTERVAL_MS
+    fn verify_settings(&mut self) {
+        if self.elapsed(self.last_settings_check).to_millis() < SETTINGS_CHECK_INTERVAL_MS {
+            return;
+        }
+        self.last_settings_check = self.timer.now();
+
+        let since_last_motor_write = self.elapsed(self.last_motor_write);
+        if since_last_motor_write < M::min_consecutive_write_delay() {
+            self.motor
+                .delay(M::min_consecutive_write_delay() - since_last_motor_write);
+        }
+
+        if let Err(err) = self.motor.verify_settings() {
+            error!("Failed to verify the motor settings {:?}", err);
+            self.motor_error();
+        }
+        self.last_motor_write = self.timer.now();
+    }
+
+    /// Limit the streamed velocity so that the machine can still stop before the bounds
+    fn bounded_target_velocity(&self, target_velocity: Real) -> Real {
+        let target_velocity = saturate_range(
+            target_velocity,
+            -MOTION_CONTROL_MAX_VELOCITY,
+            MOTION_CONTROL_MAX_VELOCITY,
+        );
+
+        let position = from_f64(self.input.current_position[0]);
+        let velocity = from_f64(self.input.current_velocity[0]);
+        // Extra margin for the jerk limited deceleration
+        let braking_distance = velocity * velocity
+            / (2.0 * from_f64(self.input.max_acceleration[0]))
+            * VELOCITY_MODE_BRAKING_MARGIN;
+
+        let max_position = MOTION_CONTROL_STATE
+            .velocity_max_position
+            .load(Ordering::Acquire)
+            .min(get_max_move_mm());
+        if target_velocity > 0.0 && position + braking_distance >= max_position {
+            return 0.0;
+        }
+        if target_velocity < 0.0 && position - braking_distance <= get_min_move_mm() {
+            return 0.0;
+        }
+
+        target_velocity
+    }
+
+    /// Step the sinusoid to the target if the sinusoidal interpolation is selected
+    /// None if ruckig has to plan the move instead e.g. for a new target mid-move
+    fn update_sinusoid(&mut self) -> Option<RuckigResult> {
+        // Ruckig is needed for the velocity interface and to plan the axes together
+        if DOF > 1
+            || self.velocity_mode
+            || self.paused
+            || self.stopping
+            || self.holding
+            || get_interpolation() != Interpolation::Sinusoidal
+        {
+            self.sinusoid = None;
+            return None;
+        }
+
+        let target = from_f64(self.input.target_position[0]);
+        let max_velocity = from_f64(self.input.max_velocity[0]);
+        let planned = self.sinusoid.is_some_and(|sinusoid| {
+            sinusoid.target() == target && sinusoid.max_velocity() == max_velocity
+        });
+        if !planned {
+            if from_f64(self.input.current_velocity[0]).abs() > SINUSOID_MAX_START_VELOCITY {
+                // Ruckig continues from the current velocity without a jump
+                self.sinusoid = None;
+                return None;
             }
+            self.sinusoid = Some(Sinusoid::new(
+                from_f64(self.input.current_position[0]),
+                target,
+                max_velocity,
+                from_f64(self.input.max_acceleration[0]),
+            ));
+            self.sinusoid_time = 0.0;
+        }
+        let sinusoid = self.sinusoid?;
+
+        // The last step already wrote the target
+        if self.sinusoid_time >= sinusoid.duration() {
+            return Some(RuckigResult::Finished);
+        }
+
+        self.sinusoid_time += MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS as Real / 1000.0;
+        let state = sinusoid.at(self.sinusoid_time);
+        self.output.new_position[0] = to_f64(state.position);
+        self.output.new_velocity[0] = to_f64(state.velocity);
+        self.output.new_acceleration[0] = to_f64(state.acceleration);
+        self.output.new_jerk[0] = to_f64(state.jerk);
+
+        Some(RuckigResult::Working)
+    }
+
+    /// Decelerate to a standstill while paused or stopping and follow the targets otherwise
+    fn update_control_interface(&mut self) {
+        let hold_stopping = self.holding && !self.hold_reached;
+        self.input.control_interface =
+            if self.velocity_mode || self.paused || self.stopping || hold_stopping {
+                ControlInterface::Velocity
+            } else {
+                ControlInterface::Position
+            };
+        self.output.time = 0.0;
+    }
+
+    /// Replan from the current state to a standstill with the maximum deceleration
+    fn begin_emergency_stop(&mut self) {
+        error!(
+            "Emergency stop at {} mm with {} mm/s",
+            self.input.current_position[0], self.input.current_velocity[0]
+        );
+        self.stopping = true;
+        self.input.control_interface = ControlInterface::Velocity;
+        for axis in 0..DOF {
+            self.input.target_velocity[axis] = 0.0;
+        }
+        self.input.max_acceleration[0] = to_f64(MOTION_CONTROL_MAX_ACCELERATION);
+        self.input.max_jerk[0] = to_f64(MOTION_CONTROL_MAX_JERK);
+        self.output.time = 0.0;
+
+        // Also run when standing still so that pending targets are dropped
+        MOVE_IN_PROGRESS.store(true, Ordering::Release);
+    }
+
+    /// Hold the position the machine stopped at
+    fn finish_emergency_stop(&mut self) {
+        let position = self.input.current_position[0];
+        info!("Emergency stop finished at {} mm", position);
+
+        self.stopping = false;
+        self.paused = false;
+        self.holding = false;
+        self.hold_reached = false;
+        self.velocity_mode = false;
+        self.input.control_interface = ControlInterface::Position;
+        self.input.target_position[0] = position;
+        self.input.target_velocity[0] = 0.0;
+        // Back to the limits set by the user
+        self.input.max_acceleration[0] = to_f64(get_max_acceleration());
+        self.input.max_jerk[0] = to_f64(get_max_jerk());
+        self.hold_additional_axes();
+        self.output.time = 0.0;
+
+        PAUSED.store(false, Ordering::Release);
+        HOLDING.store(false, Ordering::Release);
+        VELOCITY_MODE.store(false, Ordering::Release);
+        MOTION_CONTROL_STATE
+            .position
+            .store(from_f64(position), Ordering::Release);
+        MOVE_IN_PROGRESS.store(false, Ordering::Release);
+    }
+
+    /// Keep the position the machine stopped at instead of the target
+    fn finish_hold(&mut self) {
+        let position = self.input.current_position[0];
+        info!("Holding at {} mm", position);
+
+        self.hold_reached = true;
+        self.update_control_interface();
+        self.input.target_position[0] = position;
+        self.hold_additional_axes();
+        self.last_hold_write = self.timer.now();
+
+        MOTION_CONTROL_STATE
+            .position
+            .store(from_f64(position), Ordering::Release);
+        MOVE_IN_PROGRESS.store(false, Ordering::Release);
+    }
+
+    /// Make the axes after the stroke stay where they are
+    fn hold_additional_axes(&mut self) {
+        for axis in 1..DOF {
+            let position = self.input.current_position[axis];
+            self.input.target_position[axis] = position;
+            self.input.target_velocity[axis] = 0.0;
+            ADDITIONAL_AXIS_TARGETS[axis - 1].store(from_f64(position), Ordering::Release);
+        }
+    }
+
+    /// The share of the velocity allowed by the ramp after the motion was enabled
+    /// Rises in whole % from EASE_IN_START_PCT so that the velocity is not replanned every update
+    fn update_ease_in(&mut self) -> Real {
+        let Some(start) = self.ease_in_start else {
+            return 1.0;
+        };
+
+        let duration_ms = EASE_IN_DURATION_S.load(Ordering::Acquire) as u64 * 1000;
+        let elapsed_ms = self.elapsed(start).to_millis();
+        if elapsed_ms >= duration_ms {
+            info!("Velocity ramp complete");
+            self.ease_in_start = None;
+            EASE_IN_PCT.store(100, Ordering::Release);
+            return 1.0;
+        }
+
+        let progress = (elapsed_ms * 100 / duration_ms) as u32;
+        EASE_IN_PCT.store(progress, Ordering::Release);
+        scale(progress as Real, 0.0, 100.0, EASE_IN_START_PCT, 100.0) / 100.0
+    }
+
+    /// Move the torque written to the motor towards the setpoint
+    /// Rises by at most the slew rate per update to avoid a thunk. Drops are written right away
+    fn update_torque(&mut self) {
+        // The motor is let go after the e-stop input once it stands still
+        let setpoint = if ESTOP_LATCHED.load(Ordering::Acquire) && !self.stopping {
+            0.0
+        } else {
+            self.torque_setpoint.min(get_thermal_limit_pct())
+        };
+        if Some(setpoint) == self.torque_output {
+            return;
+        }
+        // Unknown outputs ramp from 0
+        let output = self.torque_output.unwrap_or(0.0);
+
+        let slew_ms = TORQUE_SLEW_MS.load(Ordering::Acquire);
+        let torque = if setpoint < output || slew_ms == 0 {
+            setpoint
         } else {
-            self.debug.new_position(self.output.new_position[0]);
-            self.debug.new_velocity(self.output.new_velocity[0]);
-            self.debug.new_acceleration(self.output.new_acceleration[0]);
-            self.debug.new_jerk(self.output.new_jerk[0]);
+            let step = 100.0 * MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS as Real / slew_ms as Real;
+            (output + step).min(setpoint)
+        };
+
+        let since_last_motor_write = self.elapsed(self.last_motor_write);
+        if since_last_motor_write < M::min_consecutive_write_delay() {
+            self.motor
+                .delay(M::min_consecutive_write_delay() - since_last_motor_write);
+        }
+
+        match self.motor.set_torque_pct(torque) {
+            Ok(()) => {
+                self.consecutive_motor_errors = 0;
+                self.torque_output = Some(torque);
+            }
+            Err(err) => {
+                error!("Failed to set the torque {:?}", err);
+                self.motor_error();
+            }
         }
+        self.last_motor_write = self.timer.now();
+    }
+
+    /// Lower the velocity if the update keeps taking longer than the update interval
+    /// The late updates then move the machine by less each
+    fn overrun(&mut self) {
+        self.consecutive_overruns += 1;
+        if self.consecutive_overruns < MAX_CONSECUTIVE_OVERRUNS || self.stopping {
+            return;
+        }
+        self.consecutive_overruns = 0;
+
+        let limit = (from_f64(self.input.max_velocity[0]) * OVERRUN_VELOCITY_FACTOR)
+            .max(MOTION_CONTROL_MIN_VELOCITY);
+        error!(
+            "The update keeps overrunning. Velocity limited to {} mm/s",
+            limit
+        );
+        OVERRUN_VELOCITY_LIMIT.store(limit, Ordering::Release);
+        raise_fault(FaultCode::Overrun);
+
+        self.input.max_velocity[0] = to_f64(limit);
+        self.output.time = 0.0;
+        self.last_velocity_update = self.timer.now();
+    }
+
+    /// Pause motion control if the motor keeps failing
+    fn motor_error(&mut self) {
+        self.consecutive_motor_errors += 1;
+        if self.consecutive_motor_errors >= MAX_CONSECUTIVE_MOTOR_ERRORS {
+            error!("Motor not responding. Pausing motion control");
+            MOTOR_CONNECTED.store(false, Ordering::Release);
+            raise_fault(FaultCode::MotorTimeout);
+        }
+    }
+
+    /// Access the motor while motion control is paused e.g. to reconnect it
+    pub fn motor_mut(&mut self) -> &mut M {
+        &mut self.motor
+    }
+
+    /// Resume motion control after the motor was reconnected
+    /// `position` is where the motor is now in mm. The machine stays there
+    pub fn resume_after_reconnect(&mut self, position: Real) {
+        let position = saturate_range(position, MIN_MOVE_MM, MAX_MOVE.load(Ordering::Acquire));
+        info!("Motor reconnected at {} mm", position);
+
+        self.input.current_position[0] = to_f64(position);
+        self.input.current_velocity[0] = 0.0;
+        self.input.current_acceleration[0] = 0.0;
+        self.input.target_position[0] = to_f64(position);
+        self.input.target_velocity[0] = 0.0;
+        for axis in 1..DOF {
+            self.input.current_velocity[axis] = 0.0;
+            self.input.current_acceleration[axis] = 0.0;
+        }
+        self.hold_additional_axes();
+        self.output.time = 0.0;
+        self.sinusoid = None;
+        // The machine holds where it is. Any emergency stop is complete
+        self.stopping = false;
+        self.paused = false;
+        self.holding = false;
+        self.hold_reached = false;
+        self.velocity_mode = false;
+        self.input.control_interface = ControlInterface::Position;
+
+        PAUSED.store(false, Ordering::Release);
+        HOLDING.store(false, Ordering::Release);
+        VELOCITY_MODE.store(false, Ordering::Release);
+        MOTION_CONTROL_STATE
+            .position
+            .store(position, Ordering::Release);
+        MOVE_IN_PROGRESS.store(false, Ordering::Release);
+
+        // The motor settings were re-applied. Force the torque to be written again
+        self.torque_output = None;
+        MOTION_CONTROL_STATE_UPDATED.store(true, Ordering::Release);
+
+        let now = self.timer.now();
+        self.last_update = now;
+        self.last_motor_write = now;
+        self.last_load_update = now;
+        self.obstructed_since = None;
+        self.last_sync_check = now;
+        self.last_settings_check = now;
+        self.consecutive_motor_errors = 0;
+        MOTOR_CONNECTED.store(true, Ordering::Release);
+        clear_faults(Recovery::MotorReconnected);
     }
 
     pub fn elapsed(&mut self, since: Instant) -> Duration {
-        self.timer.now() - since
+        timer_elapsed(since, self.timer.now())
+    }
+
+    /// Emergency stop if the control loop stalled for MOTION_CONTROL_WATCHDOG_TIMEOUT_MS during a
+    /// move. To be called periodically from outside of the control loop. Returns true if it tripped
+    /// The stalled loop can not decelerate, so the torque is dropped on the motor right away
+    pub fn check_loop_watchdog(&mut self) -> bool {
+        if !is_move_in_progress() || !is_motor_connected() || is_faulted() {
+            return false;
+        }
+
+        let now = self.timer.now();
+        let last_run = Instant::from_ticks(LAST_LOOP_RUN.load(Ordering::Acquire));
+        let stalled_ms = timer_elapsed(last_run, now).to_millis();
+        if stalled_ms < MOTION_CONTROL_WATCHDOG_TIMEOUT_MS {
+            return false;
+        }
+
+        error!("The control loop stalled for {} ms. Stopping", stalled_ms);
+        raise_fault(FaultCode::LoopStalled);
+        match self.motor.set_torque_pct(0.0) {
+            // Ramps up again once re-armed
+            Ok(()) => self.torque_output = Some(0.0),
+            Err(err) => error!("Failed to drop the torque {:?}", err),
+        }
+        true
     }
 }
 
@@ -244,7 +1095,15 @@ pub fn is_move_in_progress() -> bool {
     MOVE_IN_PROGRESS.load(Ordering::Acquire)
 }
 
-pub fn set_target_position(position: f64) {
+/// Ignored after an emergency stop until the machine is re-armed
+pub fn set_target_position(position: Real) {
+    if is_faulted() {
+        error!("Target position ignored. Re-arm after the emergency stop first");
+        return;
+    }
+
+    VELOCITY_MODE.store(false, Ordering::Release);
+    HOLDING.store(false, Ordering::Release);
     MOTION_CONTROL_STATE
         .position
         .store(position, Ordering::Release);
@@ -255,8 +1114,382 @@ pub fn set_target_position(position: f64) {
     }
 }
 
+/// Why the target of an axis was not applied as is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AxisTargetError {
+    // The machine was emergency stopped and not re-armed yet
+    Faulted,
+    Value(ValueError),
+}
+
+impl From<ValueError> for AxisTargetError {
+    fn from(err: ValueError) -> Self {
+        AxisTargetError::Value(err)
+    }
+}
+
+/// Set the target position of an axis in its units. Axis 0 is the stroke in mm
+/// Positions outside of the axis limits are clamped
+/// Rejected after an emergency stop until the machine is re-armed
+pub fn set_axis_target_position(axis: usize, position: Real) -> Result<(), AxisTargetError> {
+    if !position.is_finite() {
+        return Err(ValueError::NotANumber.into());
+    }
+    // Axis 0 is not in the additional ones
+    if axis > ADDITIONAL_AXIS_LIMITS.len() {
+        return Err(ValueError::Unknown.into());
+    }
+    if is_faulted() {
+        error!("Target position rejected. Re-arm after the emergency stop first");
+        return Err(AxisTargetError::Faulted);
+    }
+    if axis == 0 {
+        set_target_position(position);
+        return Ok(());
+    }
+    let limits = &ADDITIONAL_AXIS_LIMITS[axis - 1];
+
+    let accepted = saturate_range(position, limits.min_position, limits.max_position);
+    ADDITIONAL_AXIS_TARGETS[axis - 1].store(accepted, Ordering::Release);
+    HOLDING.store(false, Ordering::Release);
+    MOTION_CONTROL_STATE_UPDATED.store(true, Ordering::Release);
+
+    if !MOVE_IN_PROGRESS.load(Ordering::Acquire) {
+        MOVE_IN_PROGRESS.store(true, Ordering::Release);
+    }
+
+    if accepted != position {
+        return Err(ValueError::OutOfRange {
+            accepted: accepted as i32,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// The minimum allowed move forward from the homing position in mm
+/// Raised by the soft limits
+pub fn get_min_move_mm() -> Real {
+    SOFT_MIN_MOVE.load(Ordering::Acquire)
+}
+
+/// The maximum allowed move forward from the homing position in mm
+/// Lowered by the soft limits
+pub fn get_max_move_mm() -> Real {
+    let max_move = MAX_MOVE.load(Ordering::Acquire);
+    SOFT_MAX_MOVE.load(Ordering::Acquire).min(max_move)
+}
+
+/// Whether the motor turns the other way than the stock machine for a move forward
+pub fn is_direction_reversed() -> bool {
+    DIRECTION_REVERSED.load(Ordering::Acquire)
+}
+
+/// Change the way the motor turns for a move forward
+/// Only while the machine homes again. The positions of the motor flip with it
+pub fn set_direction_reversed(reversed: bool) {
+    info!("Direction reversed: {}", reversed);
+    DIRECTION_REVERSED.store(reversed, Ordering::Release);
+}
+
+/// The total travel distance of the machine in mm
+pub fn get_max_travel_mm() -> Real {
+    get_max_move_mm() - get_min_move_mm()
+}
+
+/// Restrict the minimum move in mm
+/// Kept above MIN_MOVE_MM and at least MIN_SOFT_LIMIT_TRAVEL_MM below the max
+/// The clamped value is applied if it is out of range
+pub fn set_min_move_mm(min_move: Real) -> Result<(), ValueError> {
+    if !min_move.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let upper = (get_max_move_mm() - MIN_SOFT_LIMIT_TRAVEL_MM).max(MIN_MOVE_MM);
+    let accepted = saturate_range(min_move, MIN_MOVE_MM, upper);
+
+    info!("Soft min move set to {} mm", accepted);
+    SOFT_MIN_MOVE.store(accepted, Ordering::Release);
+    MOTION_CONTROL_STATE_UPDATED.store(true, Ordering::Release);
+    check_accepted(min_move as i64, accepted as i64)
+}
+
+/// Restrict the maximum move in mm
+/// Kept within the calibrated travel and at least MIN_SOFT_LIMIT_TRAVEL_MM above the min
+/// The clamped value is applied if it is out of range
+pub fn set_max_move_mm(max_move: Real) -> Result<(), ValueError> {
+    if !max_move.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let hard_max = MAX_MOVE.load(Ordering::Acquire);
+    let lower = (get_min_move_mm() + MIN_SOFT_LIMIT_TRAVEL_MM).min(hard_max);
+    let accepted = saturate_range(max_move, lower, hard_max);
+
+    info!("Soft max move set to {} mm", accepted);
+    // Follow the calibration again if not restricted
+    let soft_max = if accepted >= hard_max {
+        Real::INFINITY
+    } else {
+        accepted
+    };
+    SOFT_MAX_MOVE.store(soft_max, Ordering::Release);
+    MOTION_CONTROL_STATE_UPDATED.store(true, Ordering::Release);
+    check_accepted(max_move as i64, accepted as i64)
+}
+
+/// Set the travel measured by the calibration in mm
+/// Travel outside of MIN_CALIBRATED_TRAVEL_MM-MAX_CALIBRATED_TRAVEL_MM is rejected
+pub fn set_max_travel_mm(travel: Real) -> Result<(), ValueError> {
+    if !travel.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let accepted = saturate_range(travel, MIN_CALIBRATED_TRAVEL_MM, MAX_CALIBRATED_TRAVEL_MM);
+    if accepted != travel {
+        return Err(ValueError::OutOfRange {
+            accepted: accepted as i32,
+        });
+    }
+
+    info!("Max travel set to {} mm", travel);
+    MAX_MOVE.store(MIN_MOVE_MM + travel, Ordering::Release);
+    // May not fit the new travel
+    SOFT_MIN_MOVE.store(MIN_MOVE_MM, Ordering::Release);
+    SOFT_MAX_MOVE.store(Real::INFINITY, Ordering::Release);
+    Ok(())
+}
+
+/// The last target position in mm
+pub fn get_target_position() -> Real {
+    MOTION_CONTROL_STATE.position.load(Ordering::Acquire)
+}
+
+/// Continuously move with the given velocity in mm/s instead of going to a position
+/// The sign sets the direction. The machine stops by itself before reaching the bounds
+/// or `max_position` in mm, e.g. the depth allowed by the profile
+/// Setting a target position exits the velocity mode
+/// Ignored after an emergency stop until the machine is re-armed
+pub fn set_target_velocity(velocity: Real, max_position: Real) {
+    if is_faulted() {
+        error!("Target velocity ignored. Re-arm after the emergency stop first");
+        return;
+    }
+
+    MOTION_CONTROL_STATE
+        .target_velocity
+        .store(velocity, Ordering::Release);
+    MOTION_CONTROL_STATE
+        .velocity_max_position
+        .store(max_position, Ordering::Release);
+    VELOCITY_MODE.store(true, Ordering::Release);
+    HOLDING.store(false, Ordering::Release);
+    MOTION_CONTROL_STATE_UPDATED.store(true, Ordering::Release);
+
+    if !MOVE_IN_PROGRESS.load(Ordering::Acquire) {
+        MOVE_IN_PROGRESS.store(true, Ordering::Release);
+    }
+}
+
+/// The position of the trajectory in mm. Only updated during motion
+pub fn get_actual_position_mm() -> Real {
+    MOTION_CONTROL_STATE.actual_position.load(Ordering::Acquire)
+}
+
+/// The velocity of the trajectory in mm/s. Positive when moving away from home
+pub fn get_actual_velocity_mm_s() -> Real {
+    MOTION_CONTROL_STATE.actual_velocity.load(Ordering::Acquire)
+}
+
+/// Estimated motor load in % of the maximum output. Only updated during motion
+pub fn get_load_pct() -> u32 {
+    LOAD_PCT.load(Ordering::Acquire)
+}
+
+/// The maximum torque set for the move in %. Already capped by the active profile
+pub fn get_torque_pct() -> Real {
+    MOTION_CONTROL_STATE.torque.load(Ordering::Acquire)
+}
+
+/// Stop the current move as fast as the machine allows instead of finishing it
+/// Raises the `EStop` fault. New targets are rejected until `rearm` is called
+pub fn emergency_stop() {
+    raise_fault(FaultCode::EStop);
+}
+
+/// Stop for a fault that is cleared by re-arming
+pub(crate) fn stop_for_fault() {
+    FAULTED.store(true, Ordering::Release);
+    EMERGENCY_STOP_REQUESTED.store(true, Ordering::Release);
+}
+
+/// Set by the firmware from the hardware e-stop input
+/// Stops like `emergency_stop` when it becomes active and drops the torque once the machine
+/// stands still. Re-arming is refused until it is inactive again
+pub fn set_estop_input(active: bool) {
+    let was_active = ESTOP_INPUT_ACTIVE.swap(active, Ordering::AcqRel);
+    if active && !was_active {
+        // Before the torque is dropped so that the machine is stopped first
+        emergency_stop();
+        ESTOP_LATCHED.store(true, Ordering::Release);
+    }
+}
+
+/// Whether the hardware e-stop input reads active
+pub fn is_estop_input_active() -> bool {
+    ESTOP_INPUT_ACTIVE.load(Ordering::Acquire)
+}
+
+/// Whether the e-stop input stopped the machine since it was last re-armed
+/// The torque stays dropped while it is set, also while the motor is out of the control loop
+pub fn is_estop_latched() -> bool {
+    ESTOP_LATCHED.load(Ordering::Acquire)
+}
+
+/// Decelerate smoothly and hold the position mid-move
+/// Targets set while paused are only executed after resuming
+pub fn pause() {
+    PAUSED.store(true, Ordering::Release);
+}
+
+/// Continue the paused move to its target
+pub fn resume() {
+    PAUSED.store(false, Ordering::Release);
+}
+
+/// Stop at the nearest point the machine can decelerate to and keep that position
+/// Unlike disabling the motion it does not retract. Ends with the next target
+pub fn hold() {
+    HOLDING.store(true, Ordering::Release);
+}
+
+/// True from `hold` until a new target is set
+pub fn is_holding() -> bool {
+    HOLDING.load(Ordering::Acquire)
+}
+
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::Acquire)
+}
+
+/// Accept new targets again after an emergency stop
+/// Returns false while the machine is still stopping, the e-stop input is active or it is
+/// overheated
+pub fn rearm() -> bool {
+    if !is_faulted() {
+        return true;
+    }
+    if EMERGENCY_STOP_REQUESTED.load(Ordering::Acquire)
+        || is_move_in_progress()
+        || is_estop_input_active()
+        || get_thermal_level() == ThermalLevel::Critical
+    {
+        return false;
+    }
+
+    info!("Re-armed after the emergency stop");
+    ESTOP_LATCHED.store(false, Ordering::Release);
+    FAULTED.store(false, Ordering::Release);
+    clear_faults(Recovery::Rearm);
+    true
+}
+
+/// True after an emergency stop until the machine is re-armed
+pub fn is_faulted() -> bool {
+    FAULTED.load(Ordering::Acquire)
+}
+
+/// The velocity the machine is limited to because the control loop kept overrunning
+/// Infinite if it is not limited
+pub fn get_overrun_velocity_limit() -> Real {
+    OVERRUN_VELOCITY_LIMIT.load(Ordering::Acquire)
+}
+
+/// Ramp the velocity up from EASE_IN_START_PCT over the ease-in duration
+/// Called when the motion is enabled. Does nothing if the ramp is disabled
+pub fn start_ease_in() {
+    if EASE_IN_DURATION_S.load(Ordering::Acquire) > 0 {
+        EASE_IN_REQUESTED.store(true, Ordering::Release);
+    }
+}
+
+/// Progress of the velocity ramp in %. 100 if not ramping
+pub fn get_ease_in_pct() -> u32 {
+    EASE_IN_PCT.load(Ordering::Acquire)
+}
+
+/// How long the velocity ramps up for after the motion is enabled in s. 0 if disabled
+pub fn get_ease_in_duration_s() -> u32 {
+    EASE_IN_DURATION_S.load(Ordering::Acquire)
+}
+
+/// Set how long the velocity ramps up for after the motion is enabled in s. 0 disables it
+/// Applies the next time the motion is enabled. Clamped to MAX_EASE_IN_S
+pub fn set_ease_in_duration_s(duration: u32) -> Result<(), ValueError> {
+    let accepted = duration.min(MAX_EASE_IN_S);
+    info!("Velocity ramp set to {} s", accepted);
+    EASE_IN_DURATION_S.store(accepted, Ordering::Release);
+    check_accepted(duration as i64, accepted as i64)
+}
+
+/// How long the torque takes to rise from 0 to 100% in ms
+pub fn get_torque_slew_ms() -> u32 {
+    TORQUE_SLEW_MS.load(Ordering::Acquire)
+}
+
+/// Set how long the torque takes to rise from 0 to 100% in ms. 0 applies it right away
+/// Lowering the torque is never delayed
+pub fn set_torque_slew_ms(slew_ms: u32) -> Result<(), ValueError> {
+    let accepted = slew_ms.min(MAX_TORQUE_SLEW_TIME_MS);
+    info!("Torque slew set to {} ms", accepted);
+    TORQUE_SLEW_MS.store(accepted, Ordering::Release);
+    check_accepted(slew_ms as i64, accepted as i64)
+}
+
+/// Lift the velocity limit set because of the overruns
+pub fn reset_overrun_velocity_limit() {
+    OVERRUN_VELOCITY_LIMIT.store(Real::INFINITY, Ordering::Release);
+    MOTION_CONTROL_STATE_UPDATED.store(true, Ordering::Release);
+}
+
+/// Pause motion control until the motor is reconnected
+/// e.g. after the motor failed in a way that it may have lost its settings or position
+pub fn pause_for_reconnect() {
+    MOTOR_CONNECTED.store(false, Ordering::Release);
+    raise_fault(FaultCode::MotorTimeout);
+}
+
+/// False while the motor is not responding and motion control is paused
+pub fn is_motor_connected() -> bool {
+    MOTOR_CONNECTED.load(Ordering::Acquire)
+}
+
+pub fn is_velocity_mode() -> bool {
+    VELOCITY_MODE.load(Ordering::Acquire)
+}
+
+/// The last target velocity in mm/s. Only followed in velocity mode
+pub fn get_target_velocity() -> Real {
+    MOTION_CONTROL_STATE.target_velocity.load(Ordering::Acquire)
+}
+
+pub fn get_interpolation() -> Interpolation {
+    if SINUSOIDAL_INTERPOLATION.load(Ordering::Acquire) {
+        Interpolation::Sinusoidal
+    } else {
+        Interpolation::Ruckig
+    }
+}
+
+/// Select how the position moves are planned
+/// Takes effect with the next move that starts from a standstill
+pub fn set_interpolation(interpolation: Interpolation) {
+    info!("Interpolation set to {:?}", interpolation);
+    SINUSOIDAL_INTERPOLATION.store(
+        interpolation == Interpolation::Sinusoidal,
+        Ordering::Release,
+    );
+}
+
 /// Set the maximum velocity for the move
-pub fn set_max_velocity(mut max_velocity: f64) {
+pub fn set_max_velocity(mut max_velocity: Real) {
     // A velocity of 0 breaks motion control
     // Set some small minimum velocity
     if max_velocity < MOTION_CONTROL_MIN_VELOCITY {
@@ -282,23 +1515,63 @@ pub fn set_max_velocity(mut max_velocity: f64) {
 ///
 /// This is to ensure that the updated velocity sent to motion control
 /// follows the velocity scaling done by the pattern
-pub fn set_max_velocity_scaled(current_velocity: f64, new_max_velocity: f64) {
-    let velocity_setpoint = MOTION_CONTROL_STATE.velocity.load(Ordering::Acquire) as f64;
+pub fn set_max_velocity_scaled(current_velocity: Real, new_max_velocity: Real) {
+    let velocity_setpoint = MOTION_CONTROL_STATE.velocity.load(Ordering::Acquire) as Real;
     let ratio = velocity_setpoint / current_velocity;
     let scaled_velocity = new_max_velocity * ratio;
 
     set_max_velocity(scaled_velocity);
 }
 
+/// The maximum acceleration of the moves in mm/s²
+pub fn get_max_acceleration() -> Real {
+    MOTION_CONTROL_STATE.acceleration.load(Ordering::Acquire)
+}
+
+/// Set the maximum acceleration in mm/s². Also applies to the move in progress
+/// Lower values are gentler, higher values more aggressive
+pub fn set_max_acceleration(acceleration: Real) {
+    let acceleration = saturate_range(
+        acceleration,
+        MOTION_CONTROL_MIN_ACCELERATION,
+        MOTION_CONTROL_MAX_ACCELERATION,
+    );
+
+    MOTION_CONTROL_STATE
+        .acceleration
+        .store(acceleration, Ordering::Release);
+    MOTION_CONTROL_STATE_UPDATED.store(true, Ordering::Release);
+}
+
+/// The maximum jerk of the moves in mm/s³
+pub fn get_max_jerk() -> Real {
+    MOTION_CONTROL_STATE.jerk.load(Ordering::Acquire)
+}
+
+/// Set the maximum jerk in mm/s³. Also applies to the move in progress
+/// Lower values give smoother changes of the acceleration
+pub fn set_max_jerk(jerk: Real) {
+    let jerk = saturate_range(jerk, MOTION_CONTROL_MIN_JERK, MOTION_CONTROL_MAX_JERK);
+
+    MOTION_CONTROL_STATE.jerk.store(jerk, Ordering::Release);
+    MOTION_CONTROL_STATE_UPDATED.store(true, Ordering::Release);
+}
+
 /// Set the maximum torque for the move in %
-pub fn set_torque(max_torque: f64) {
-    let mut torque = saturate_range(max_torque, 0.0, 100.0);
-    torque = scale(torque, 0.0, 100.0, MOTOR_MIN_OUTPUT, MOTOR_MAX_OUTPUT);
-    // TODO: Refactor to not depend on the specific motor
-    // The last digit is 0 for no alarm
-    torque = torque * 10.0;
-
-    let torque = torque as u16;
+/// Capped by the limits of the active profile
+pub fn set_torque(max_torque: Real) {
+    let max_allowed_torque = get_active_limits().torque as Real;
+    let torque = saturate_range(max_torque, 0.0, max_allowed_torque);
+
+    let limited = max_torque > max_allowed_torque;
+    if limited && !TORQUE_LIMITED.swap(true, Ordering::AcqRel) {
+        publish_event(Event::TorqueLimited {
+            requested: max_torque as u32,
+            limit: max_allowed_torque as u32,
+        });
+    } else if !limited {
+        TORQUE_LIMITED.store(false, Ordering::Release);
+    }
 
     MOTION_CONTROL_STATE.torque.store(torque, Ordering::Release);
     MOTION_CONTROL_STATE_UPDATED.store(true, Ordering::Release);
@@ -1,6 +1,6 @@
 use core::fmt::Debug;
 
-use crate::motion_control::timer::Duration;
+use crate::{float::Real, motion_control::timer::Duration};
 
 pub trait Motor {
     type MotorError: Debug;
@@ -11,8 +11,48 @@ pub trait Motor {
     /// Absolute position in steps
     fn set_absolute_position(&mut self, steps: i32) -> Result<(), Self::MotorError>;
 
-    /// Torque
-    fn set_max_allowed_output(&mut self, output: u16) -> Result<(), Self::MotorError>;
+    /// Position in steps of the given axis. Axis 0 is the stroke
+    /// Only needed by motors that drive more than one axis. The others ignore the additional axes
+    fn set_axis_position(&mut self, axis: usize, steps: i32) -> Result<(), Self::MotorError> {
+        if axis == 0 {
+            return self.set_absolute_position(steps);
+        }
+        Ok(())
+    }
+
+    /// Maximum torque in % of what the motor allows during motion. 0-100
+    /// Each motor maps it to its own output range
+    fn set_torque_pct(&mut self, torque: Real) -> Result<(), Self::MotorError>;
+
+    /// The load in % of `get_load_pct` the motor is at its limit with the torque in %
+    /// Motors that map the torque to only part of their output override it
+    fn load_limit_pct(torque: Real) -> Real {
+        torque
+    }
+
+    /// Estimated load in % of the maximum output
+    /// None if the motor cannot report it
+    fn get_load_pct(&mut self) -> Result<Option<Real>, Self::MotorError> {
+        Ok(None)
+    }
+
+    /// Temperature of the drive in °C
+    /// None if the motor cannot report it
+    fn get_temperature_c(&mut self) -> Result<Option<Real>, Self::MotorError> {
+        Ok(None)
+    }
+
+    /// Check that all the motors driving the axis still agree on the position
+    /// Only meaningful for machines with more than one motor
+    fn check_sync(&mut self) -> Result<(), Self::MotorError> {
+        Ok(())
+    }
+
+    /// Check that the settings written at startup are still in effect and restore them if not
+    /// Drives may silently reset some of them
+    fn verify_settings(&mut self) -> Result<(), Self::MotorError> {
+        Ok(())
+    }
 
     /// Blocking delay function
     /// Provided by the motor to not waste an extra timer just for this
@@ -0,0 +1,71 @@
+use core::{
+    cell::RefCell,
+    fmt::{self, Display},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use critical_section::Mutex;
+use heapless::HistoryBuf;
+
+use crate::{config::RECORDER_LENGTH, fault::FaultCode, float::Real};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Record {
+    // The trajectory as commanded to the motor
+    Sample {
+        time_ms: u64,
+        position: Real,
+        velocity: Real,
+        torque: Real,
+    },
+    // Written by the first update after `raise_fault` latched it
+    Fault {
+        time_ms: u64,
+        fault: FaultCode,
+    },
+}
+
+/// Formatted as `s:<time ms>:<position mm>:<velocity mm/s>:<torque %>` or `f:<time ms>:<fault>`
+impl Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Record::Sample {
+                time_ms,
+                position,
+                velocity,
+                torque,
+            } => write!(f, "s:{time_ms}:{position:.1}:{velocity:.1}:{torque:.0}"),
+            Record::Fault { time_ms, fault } => write!(f, "f:{time_ms}:{}", fault.name()),
+        }
+    }
+}
+
+static RECORDS: Mutex<RefCell<HistoryBuf<Record, RECORDER_LENGTH>>> =
+    Mutex::new(RefCell::new(HistoryBuf::new()));
+// Nothing is recorded while the records are read out
+static FROZEN: AtomicBool = AtomicBool::new(false);
+
+/// Overwrites the oldest record once full
+pub(crate) fn record(record: Record) {
+    if FROZEN.load(Ordering::Acquire) {
+        return;
+    }
+    critical_section::with(|cs| RECORDS.borrow_ref_mut(cs).write(record));
+}
+
+/// Stop recording so that the records can be read out one by one
+/// Returns how many there are
+pub fn freeze() -> usize {
+    FROZEN.store(true, Ordering::Release);
+    critical_section::with(|cs| RECORDS.borrow_ref(cs).len())
+}
+
+/// The record at `index` with the oldest first
+pub fn get_record(index: usize) -> Option<Record> {
+    critical_section::with(|cs| RECORDS.borrow_ref(cs).oldest_ordered().nth(index).copied())
+}
+
+/// Continue recording after the records were read out
+pub fn unfreeze() {
+    FROZEN.store(false, Ordering::Release);
+}
@@ -0,0 +1,78 @@
+use num_traits::{Float, FloatConst};
+
+use crate::float::Real;
+
+/// A half cosine move between two standstills
+/// The classic StrokeEngine feel. Much cheaper than a jerk limited trajectory
+#[derive(Debug, Clone, Copy)]
+pub struct Sinusoid {
+    start: Real,
+    target: Real,
+    max_velocity: Real,
+    // In s
+    duration: Real,
+}
+
+/// The state of a sinusoid at a point in time
+pub struct SinusoidState {
+    pub position: Real,
+    pub velocity: Real,
+    pub acceleration: Real,
+    pub jerk: Real,
+}
+
+impl Sinusoid {
+    /// The peak velocity and acceleration stay within the given ones
+    pub fn new(start: Real, target: Real, max_velocity: Real, max_acceleration: Real) -> Self {
+        let distance = (target - start).abs();
+        // The peak velocity of a half cosine is pi / 2 * distance / duration
+        // and the peak acceleration pi² / 2 * distance / duration²
+        let velocity_duration = Real::PI() * distance / (2.0 * max_velocity);
+        // Called through the trait so that libm is used without std
+        let acceleration_duration =
+            Float::sqrt(Real::PI() * Real::PI() * distance / (2.0 * max_acceleration));
+
+        Self {
+            start,
+            target,
+            max_velocity,
+            duration: velocity_duration.max(acceleration_duration),
+        }
+    }
+
+    pub fn target(&self) -> Real {
+        self.target
+    }
+
+    pub fn max_velocity(&self) -> Real {
+        self.max_velocity
+    }
+
+    /// In s
+    pub fn duration(&self) -> Real {
+        self.duration
+    }
+
+    /// The state at `time` s after the start. Holds the target after the end
+    pub fn at(&self, time: Real) -> SinusoidState {
+        if time >= self.duration {
+            return SinusoidState {
+                position: self.target,
+                velocity: 0.0,
+                acceleration: 0.0,
+                jerk: 0.0,
+            };
+        }
+
+        let half_distance = (self.target - self.start) / 2.0;
+        let rate = Real::PI() / self.duration;
+        let (sin, cos) = Float::sin_cos(rate * time);
+
+        SinusoidState {
+            position: self.start + half_distance * (1.0 - cos),
+            velocity: half_distance * rate * sin,
+            acceleration: half_distance * rate * rate * cos,
+            jerk: -half_distance * rate * rate * rate * sin,
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use core::{cell::RefCell, fmt::Write};
+
+use critical_section::Mutex;
+use heapless::String;
+use log::{error, info};
+
+use super::{
+    Pattern, PatternInput, PatternMove,
+    expression::{Expression, ExpressionError, ExpressionInput},
+};
+use crate::{
+    config::{MAX_CUSTOM_DELAY_MS, MAX_CUSTOM_PATTERN_LENGTH, MAX_EXPRESSION_LENGTH},
+    float::Real,
+};
+
+// Simple in and out until something is uploaded
+const DEFAULT_POSITION: &str = "if(index % 2, depth - stroke, depth)";
+const DEFAULT_VELOCITY: &str = "velocity";
+const DEFAULT_DELAY: &str = "0";
+
+/// An output of the custom pattern computed by an expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CustomOutput {
+    // In mm from 0 to depth
+    Position,
+    // In mm/s
+    Velocity,
+    // In ms after the move
+    Delay,
+}
+
+impl CustomOutput {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "position" => Some(CustomOutput::Position),
+            "velocity" => Some(CustomOutput::Velocity),
+            "delay" => Some(CustomOutput::Delay),
+            _ => None,
+        }
+    }
+
+    fn default_source(self) -> &'static str {
+        match self {
+            CustomOutput::Position => DEFAULT_POSITION,
+            CustomOutput::Velocity => DEFAULT_VELOCITY,
+            CustomOutput::Delay => DEFAULT_DELAY,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CustomPatternError {
+    // Not `<output>=<expression>`
+    Malformed,
+    UnknownOutput,
+    // Longer than MAX_EXPRESSION_LENGTH
+    TooLong,
+    Expression(ExpressionError),
+}
+
+impl core::fmt::Display for CustomPatternError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CustomPatternError::Malformed => write!(f, "malformed"),
+            CustomPatternError::UnknownOutput => write!(f, "unknown_output"),
+            CustomPatternError::TooLong => write!(f, "too_long"),
+            CustomPatternError::Expression(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// The source and the compiled expression of an output
+struct CustomExpression {
+    source: String<MAX_EXPRESSION_LENGTH>,
+    expression: Expression,
+}
+
+impl CustomExpression {
+    fn compile(source: &str) -> Result<Self, CustomPatternError> {
+        let expression = Expression::compile(source).map_err(CustomPatternError::Expression)?;
+        let source = String::try_from(source).map_err(|_| CustomPatternError::TooLong)?;
+
+        Ok(Self { source, expression })
+    }
+
+    fn default_for(output: CustomOutput) -> Self {
+        Self::compile(output.default_source()).expect("The defaults compile")
+    }
+}
+
+struct CustomExpressions {
+    position: CustomExpression,
+    velocity: CustomExpression,
+    delay: CustomExpression,
+}
+
+impl CustomExpressions {
+    fn new() -> Self {
+        Self {
+            position: CustomExpression::default_for(CustomOutput::Position),
+            velocity: CustomExpression::default_for(CustomOutput::Velocity),
+            delay: CustomExpression::default_for(CustomOutput::Delay),
+        }
+    }
+
+    fn get_mut(&mut self, output: CustomOutput) -> &mut CustomExpression {
+        match output {
+            CustomOutput::Position => &mut self.position,
+            CustomOutput::Velocity => &mut self.velocity,
+            CustomOutput::Delay => &mut self.delay,
+        }
+    }
+}
+
+// None until the first upload. The pattern then moves like DEFAULT_POSITION, DEFAULT_VELOCITY and DEFAULT_DELAY
+static UPLOADED: Mutex<RefCell<Option<CustomExpressions>>> = Mutex::new(RefCell::new(None));
+
+/// Replace one output of the custom pattern with `<output>=<expression>`
+/// e.g. `position=depth - stroke * (index % 2)`
+pub fn set_custom_expression(command: &str) -> Result<(), CustomPatternError> {
+    let (output, source) = command
+        .split_once('=')
+        .ok_or(CustomPatternError::Malformed)?;
+    let output = CustomOutput::from_name(output.trim()).ok_or(CustomPatternError::UnknownOutput)?;
+    let expression = CustomExpression::compile(source.trim())?;
+
+    info!("Custom pattern {:?} set to {}", output, expression.source);
+    critical_section::with(|cs| {
+        let mut uploaded = UPLOADED.borrow_ref_mut(cs);
+        *uploaded
+            .get_or_insert_with(CustomExpressions::new)
+            .get_mut(output) = expression;
+    });
+
+    Ok(())
+}
+
+/// Go back to the simple in and out
+pub fn reset_custom_pattern() {
+    critical_section::with(|cs| UPLOADED.borrow_ref_mut(cs).take());
+}
+
+/// The sources of the outputs as JSON
+pub fn get_custom_pattern_json() -> String<MAX_CUSTOM_PATTERN_LENGTH> {
+    let mut output = String::new();
+
+    let result = critical_section::with(|cs| match UPLOADED.borrow_ref(cs).as_ref() {
+        Some(expressions) => write_custom_pattern_json(
+            &mut output,
+            &expressions.position.source,
+            &expressions.velocity.source,
+            &expressions.delay.source,
+        ),
+        None => write_custom_pattern_json(
+            &mut output,
+            DEFAULT_POSITION,
+            DEFAULT_VELOCITY,
+            DEFAULT_DELAY,
+        ),
+    });
+    if result.is_err() {
+        error!("Could not write the custom pattern. Too long");
+    }
+
+    output
+}
+
+fn write_custom_pattern_json(
+    output: &mut String<MAX_CUSTOM_PATTERN_LENGTH>,
+    position: &str,
+    velocity: &str,
+    delay: &str,
+) -> core::fmt::Result {
+    write!(
+        output,
+        r#"{{"position":"{position}","velocity":"{velocity}","delay":"{delay}"}}"#
+    )
+}
+
+#[derive(Default)]
+pub struct Custom {
+    index: u32,
+}
+
+impl Custom {
+    pub fn new() -> Self {
+        let mut pattern = Self::default();
+        pattern.reset();
+        pattern
+    }
+}
+
+impl Pattern for Custom {
+    fn get_name(&self) -> &'static str {
+        "Custom"
+    }
+
+    fn get_description(&self) -> &'static str {
+        "Uploaded over BLE as expressions of depth, stroke, velocity, sensation and the stroke index. Simple in and out until then."
+    }
+
+    fn reset(&mut self) {
+        self.index = 0;
+    }
+
+    fn next_move(&mut self, input: &PatternInput) -> PatternMove {
+        let variables = ExpressionInput {
+            depth: input.depth,
+            stroke: input.motion_length,
+            velocity: input.velocity,
+            sensation: input.sensation,
+            index: self.index as Real,
+        };
+        let out_stroke = self.index.is_multiple_of(2);
+        self.index = self.index.wrapping_add(1);
+
+        // Evaluated in place. The expressions are too large to be copied for every move
+        let uploaded = critical_section::with(|cs| {
+            UPLOADED.borrow_ref(cs).as_ref().map(|expressions| {
+                (
+                    expressions.position.expression.evaluate(&variables),
+                    expressions.velocity.expression.evaluate(&variables),
+                    expressions.delay.expression.evaluate(&variables),
+                )
+            })
+        });
+        let in_stroke_depth = input.depth - input.motion_length;
+        let (position, velocity, delay) = uploaded.unwrap_or_else(|| {
+            let position = if out_stroke {
+                input.depth
+            } else {
+                in_stroke_depth
+            };
+            (position, input.velocity, 0.0)
+        });
+
+        // Anything that is not a number stays at the in stroke depth without a delay
+        let position = if position.is_finite() {
+            position
+        } else {
+            in_stroke_depth
+        };
+        let velocity = if velocity.is_finite() { velocity } else { 0.0 };
+        let delay_ms = if delay.is_finite() {
+            delay.clamp(0.0, MAX_CUSTOM_DELAY_MS as Real) as u64
+        } else {
+            0
+        };
+
+        PatternMove::new_with_delay(velocity, position, delay_ms)
+    }
+}
@@ -1,21 +1,22 @@
 use log::info;
 
 use crate::{
+    float::Real,
     pattern::{MAX_SENSATION, MIN_SENSATION},
     utils::scale,
 };
 
 use super::{Pattern, PatternInput, PatternMove};
 
-const MIN_STEPS: f64 = 2.0;
-const MAX_STEPS: f64 = 22.0;
+const MIN_STEPS: Real = 2.0;
+const MAX_STEPS: Real = 22.0;
 
 #[derive(Default)]
 pub struct Deeper {
     out_stroke: bool,
     num_steps: usize,
     current_step: usize,
-    previous_sensation: f64,
+    previous_sensation: Real,
 }
 
 impl Deeper {
@@ -60,11 +61,11 @@ impl Pattern for Deeper {
         let in_stroke_depth = input.depth - input.motion_length;
 
         let new_move = if self.out_stroke {
-            let increment = input.motion_length / self.num_steps as f64;
+            let increment = input.motion_length / self.num_steps as Real;
             if self.current_step > self.num_steps {
                 self.current_step = 1;
             }
-            let out_stroke_depth = in_stroke_depth + increment * self.current_step as f64;
+            let out_stroke_depth = in_stroke_depth + increment * self.current_step as Real;
             self.current_step += 1;
             PatternMove::new(input.velocity, out_stroke_depth)
         } else {
@@ -0,0 +1,50 @@
+use core::sync::atomic::Ordering;
+
+use portable_atomic::AtomicU32;
+
+use crate::{
+    config::MAX_DWELL_MS,
+    validation::{ValueError, check_accepted},
+};
+
+static DWELL_DEPTH_MS: AtomicU32 = AtomicU32::new(0);
+static DWELL_RETRACT_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Holds at the ends of the strokes, added to the delay of the moves by the `PatternExecutor`
+/// Patterns can change it for themselves with `Pattern::get_dwell`
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Dwell {
+    // How long to hold at the depth in ms
+    pub depth_ms: u32,
+    // How long to hold at the retracted end of the stroke in ms
+    pub retract_ms: u32,
+}
+
+/// Set the hold at both ends of the strokes in ms
+pub fn set_dwell_ms(dwell_ms: u32) -> Result<(), ValueError> {
+    // Both are clamped the same
+    let result = set_dwell_depth_ms(dwell_ms);
+    set_dwell_retract_ms(dwell_ms).and(result)
+}
+
+/// Set the hold at the depth in ms
+pub fn set_dwell_depth_ms(dwell_ms: u32) -> Result<(), ValueError> {
+    let accepted = dwell_ms.min(MAX_DWELL_MS);
+    DWELL_DEPTH_MS.store(accepted, Ordering::Release);
+    check_accepted(dwell_ms as i64, accepted as i64)
+}
+
+/// Set the hold at the retracted end of the strokes in ms
+pub fn set_dwell_retract_ms(dwell_ms: u32) -> Result<(), ValueError> {
+    let accepted = dwell_ms.min(MAX_DWELL_MS);
+    DWELL_RETRACT_MS.store(accepted, Ordering::Release);
+    check_accepted(dwell_ms as i64, accepted as i64)
+}
+
+/// The hold set for all patterns
+pub fn get_dwell() -> Dwell {
+    Dwell {
+        depth_ms: DWELL_DEPTH_MS.load(Ordering::Acquire),
+        retract_ms: DWELL_RETRACT_MS.load(Ordering::Acquire),
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{
+    config::EDGING_BUILD_STROKES,
+    float::Real,
+    pattern::{MAX_SENSATION, MIN_SENSATION},
+    utils::{rng::Rng, scale},
+};
+
+use super::{Pattern, PatternInput, PatternMove, params::PatternParam};
+
+// The share of the velocity the build up starts from
+const START_VELOCITY_FRACTION: Real = 0.3;
+// The chance of a pause after the build up. Higher sensation pauses more often
+const MIN_PAUSE_PROBABILITY: Real = 0.2;
+const MAX_PAUSE_PROBABILITY: Real = 1.0;
+// The average pause. Higher sensation pauses longer
+const MIN_PAUSE_MS: Real = 2000.0;
+const MAX_PAUSE_MS: Real = 15000.0;
+// The pause is randomized by this much in either direction
+const PAUSE_SPREAD: Real = 0.5;
+const RNG_STREAM: u32 = 0x0055_4d45;
+
+const PARAMS: [PatternParam; 1] = [PatternParam::new(
+    "buildStrokes",
+    2.0,
+    100.0,
+    EDGING_BUILD_STROKES as Real,
+)];
+
+pub struct Edging {
+    out_stroke: bool,
+    // Strokes since the build up started
+    stroke: u32,
+    build_strokes: u32,
+    // Still speeding up. Stays at the full velocity if there was no pause
+    building: bool,
+    rng: Rng,
+}
+
+impl Default for Edging {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Edging {
+    pub fn new() -> Self {
+        let mut pattern = Self {
+            out_stroke: true,
+            stroke: 0,
+            build_strokes: EDGING_BUILD_STROKES,
+            building: true,
+            rng: Rng::seeded(RNG_STREAM),
+        };
+        pattern.reset();
+        pattern
+    }
+}
+
+impl Pattern for Edging {
+    fn get_name(&self) -> &'static str {
+        "Edging"
+    }
+
+    fn get_description(&self) -> &'static str {
+        "Builds up the speed, then may pause at the depth for a random time. Sensation controls how often and how long it pauses"
+    }
+
+    fn reset(&mut self) {
+        self.out_stroke = true;
+        self.stroke = 0;
+        self.building = true;
+    }
+
+    fn get_params(&self) -> &'static [PatternParam] {
+        &PARAMS
+    }
+
+    fn set_param(&mut self, index: usize, value: Real) {
+        if index == 0 {
+            self.build_strokes = value as u32;
+        }
+    }
+
+    fn next_move(&mut self, input: &PatternInput) -> PatternMove {
+        let fraction = if self.building {
+            scale(
+                self.stroke as Real,
+                0.0,
+                (self.build_strokes - 1).max(1) as Real,
+                START_VELOCITY_FRACTION,
+                1.0,
+            )
+            .min(1.0)
+        } else {
+            1.0
+        };
+        let velocity = input.velocity * fraction;
+
+        if !self.out_stroke {
+            self.out_stroke = true;
+            let in_stroke_depth = input.depth - input.motion_length;
+            return PatternMove::new(velocity, in_stroke_depth);
+        }
+        self.out_stroke = false;
+
+        self.stroke += 1;
+        if self.stroke < self.build_strokes {
+            return PatternMove::new(velocity, input.depth);
+        }
+        self.stroke = 0;
+
+        let probability = scale(
+            input.sensation,
+            MIN_SENSATION,
+            MAX_SENSATION,
+            MIN_PAUSE_PROBABILITY,
+            MAX_PAUSE_PROBABILITY,
+        );
+        if !self.rng.chance(probability) {
+            // Keep going at the full velocity for another round
+            self.building = false;
+            return PatternMove::new(velocity, input.depth);
+        }
+
+        let pause_ms = scale(
+            input.sensation,
+            MIN_SENSATION,
+            MAX_SENSATION,
+            MIN_PAUSE_MS,
+            MAX_PAUSE_MS,
+        ) * self.rng.range(1.0 - PAUSE_SPREAD, 1.0 + PAUSE_SPREAD);
+        // Build up again from the start after the pause
+        self.building = true;
+        PatternMove::new_with_delay(velocity, input.depth, pause_ms as u64)
+    }
+}
@@ -0,0 +1,351 @@
+use core::fmt::{self, Display};
+
+use heapless::Vec;
+use num_traits::Float;
+
+use crate::{
+    config::{MAX_EXPRESSION_OPS, MAX_EXPRESSION_STACK},
+    float::Real,
+};
+
+/// The values an expression can refer to
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExpressionInput {
+    // In mm
+    pub depth: Real,
+    pub stroke: Real,
+    // In mm/s
+    pub velocity: Real,
+    // -100 to 100
+    pub sensation: Real,
+    // Moves since the pattern was started
+    pub index: Real,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExpressionError {
+    // More than MAX_EXPRESSION_OPS operations
+    TooLong,
+    // Needs more than MAX_EXPRESSION_STACK values at once
+    TooDeep,
+    UnknownVariable,
+    UnknownFunction,
+    // Wrong number of arguments for a function
+    Arguments,
+    // Anything that does not parse
+    Syntax,
+}
+
+impl Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpressionError::TooLong => write!(f, "too_long"),
+            ExpressionError::TooDeep => write!(f, "too_deep"),
+            ExpressionError::UnknownVariable => write!(f, "unknown_variable"),
+            ExpressionError::UnknownFunction => write!(f, "unknown_function"),
+            ExpressionError::Arguments => write!(f, "arguments"),
+            ExpressionError::Syntax => write!(f, "syntax"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Variable {
+    Depth,
+    Stroke,
+    Velocity,
+    Sensation,
+    Index,
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "depth" => Some(Variable::Depth),
+            "stroke" => Some(Variable::Stroke),
+            "velocity" => Some(Variable::Velocity),
+            "sensation" => Some(Variable::Sensation),
+            "index" => Some(Variable::Index),
+            _ => None,
+        }
+    }
+
+    fn value(self, input: &ExpressionInput) -> Real {
+        match self {
+            Variable::Depth => input.depth,
+            Variable::Stroke => input.stroke,
+            Variable::Velocity => input.velocity,
+            Variable::Sensation => input.sensation,
+            Variable::Index => input.index,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Number(Real),
+    Variable(Variable),
+    Neg,
+    Abs,
+    Sin,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Less,
+    Greater,
+    Min,
+    Max,
+    If,
+}
+
+impl Op {
+    fn function(name: &str) -> Option<(Self, usize)> {
+        match name {
+            "abs" => Some((Op::Abs, 1)),
+            "sin" => Some((Op::Sin, 1)),
+            "min" => Some((Op::Min, 2)),
+            "max" => Some((Op::Max, 2)),
+            "if" => Some((Op::If, 3)),
+            _ => None,
+        }
+    }
+
+    /// How many values the operation takes from the stack. It always pushes one
+    fn arguments(self) -> usize {
+        match self {
+            Op::Number(_) | Op::Variable(_) => 0,
+            Op::Neg | Op::Abs | Op::Sin => 1,
+            Op::If => 3,
+            _ => 2,
+        }
+    }
+}
+
+/// An expression of a pattern uploaded at runtime, compiled to a stack based bytecode once
+/// Supports numbers, the variables of `ExpressionInput`, `+ - * / %`, `<` and `>` giving 1 or 0,
+/// parentheses and `min(a, b)`, `max(a, b)`, `abs(x)`, `sin(x)` and `if(condition, then, else)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    // In reverse polish notation
+    ops: Vec<Op, MAX_EXPRESSION_OPS>,
+}
+
+impl Expression {
+    pub fn compile(source: &str) -> Result<Self, ExpressionError> {
+        let mut parser = Parser {
+            source: source.as_bytes(),
+            offset: 0,
+            ops: Vec::new(),
+        };
+        parser.comparison()?;
+        parser.skip_whitespace();
+        if parser.offset != parser.source.len() {
+            return Err(ExpressionError::Syntax);
+        }
+
+        let mut depth = 0;
+        for op in parser.ops.iter() {
+            depth = depth - op.arguments() + 1;
+            if depth > MAX_EXPRESSION_STACK {
+                return Err(ExpressionError::TooDeep);
+            }
+        }
+
+        Ok(Self { ops: parser.ops })
+    }
+
+    /// Never fails once compiled. The result may not be finite e.g. after a division by 0
+    pub fn evaluate(&self, input: &ExpressionInput) -> Real {
+        let mut stack = [0.0; MAX_EXPRESSION_STACK];
+        let mut len = 0;
+
+        for op in self.ops.iter() {
+            let arguments = op.arguments();
+            len -= arguments;
+            let [a, b, c] = [0, 1, 2].map(|i| stack.get(len + i).copied().unwrap_or(0.0));
+
+            stack[len] = match *op {
+                Op::Number(value) => value,
+                Op::Variable(variable) => variable.value(input),
+                Op::Neg => -a,
+                Op::Abs => a.abs(),
+                Op::Sin => Float::sin(a),
+                Op::Add => a + b,
+                Op::Sub => a - b,
+                Op::Mul => a * b,
+                Op::Div => a / b,
+                Op::Rem => a - b * Float::trunc(a / b),
+                Op::Less => (a < b) as u8 as Real,
+                Op::Greater => (a > b) as u8 as Real,
+                Op::Min => a.min(b),
+                Op::Max => a.max(b),
+                Op::If => {
+                    if a != 0.0 {
+                        b
+                    } else {
+                        c
+                    }
+                }
+            };
+            len += 1;
+        }
+
+        stack[0]
+    }
+}
+
+/// Recursive descent parser emitting the operations in reverse polish notation
+struct Parser<'a> {
+    source: &'a [u8],
+    offset: usize,
+    ops: Vec<Op, MAX_EXPRESSION_OPS>,
+}
+
+impl<'a> Parser<'a> {
+    fn emit(&mut self, op: Op) -> Result<(), ExpressionError> {
+        self.ops.push(op).map_err(|_| ExpressionError::TooLong)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .source
+            .get(self.offset)
+            .is_some_and(u8::is_ascii_whitespace)
+        {
+            self.offset += 1;
+        }
+    }
+
+    /// The next character that is not whitespace
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.source.get(self.offset).copied()
+    }
+
+    fn expect(&mut self, character: u8) -> Result<(), ExpressionError> {
+        if self.peek() != Some(character) {
+            return Err(ExpressionError::Syntax);
+        }
+        self.offset += 1;
+        Ok(())
+    }
+
+    fn comparison(&mut self) -> Result<(), ExpressionError> {
+        self.sum()?;
+        loop {
+            let op = match self.peek() {
+                Some(b'<') => Op::Less,
+                Some(b'>') => Op::Greater,
+                _ => return Ok(()),
+            };
+            self.offset += 1;
+            self.sum()?;
+            self.emit(op)?;
+        }
+    }
+
+    fn sum(&mut self) -> Result<(), ExpressionError> {
+        self.product()?;
+        loop {
+            let op = match self.peek() {
+                Some(b'+') => Op::Add,
+                Some(b'-') => Op::Sub,
+                _ => return Ok(()),
+            };
+            self.offset += 1;
+            self.product()?;
+            self.emit(op)?;
+        }
+    }
+
+    fn product(&mut self) -> Result<(), ExpressionError> {
+        self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(b'*') => Op::Mul,
+                Some(b'/') => Op::Div,
+                Some(b'%') => Op::Rem,
+                _ => return Ok(()),
+            };
+            self.offset += 1;
+            self.unary()?;
+            self.emit(op)?;
+        }
+    }
+
+    fn unary(&mut self) -> Result<(), ExpressionError> {
+        if self.peek() == Some(b'-') {
+            self.offset += 1;
+            self.unary()?;
+            return self.emit(Op::Neg);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<(), ExpressionError> {
+        match self.peek() {
+            Some(b'(') => {
+                self.offset += 1;
+                self.comparison()?;
+                self.expect(b')')
+            }
+            Some(character) if character.is_ascii_digit() || character == b'.' => {
+                let number =
+                    self.take_while(|character| character.is_ascii_digit() || character == b'.');
+                let value = number.parse().map_err(|_| ExpressionError::Syntax)?;
+                self.emit(Op::Number(value))
+            }
+            Some(character) if character.is_ascii_alphabetic() => {
+                let name = self.take_while(|character| character.is_ascii_alphabetic());
+                if self.peek() == Some(b'(') {
+                    self.offset += 1;
+                    self.call(name)
+                } else {
+                    let variable =
+                        Variable::from_name(name).ok_or(ExpressionError::UnknownVariable)?;
+                    self.emit(Op::Variable(variable))
+                }
+            }
+            _ => Err(ExpressionError::Syntax),
+        }
+    }
+
+    /// The arguments of a function after the opening parenthesis
+    fn call(&mut self, name: &str) -> Result<(), ExpressionError> {
+        let (op, expected) = Op::function(name).ok_or(ExpressionError::UnknownFunction)?;
+
+        let mut arguments = 0;
+        loop {
+            self.comparison()?;
+            arguments += 1;
+            match self.peek() {
+                Some(b',') => self.offset += 1,
+                Some(b')') => {
+                    self.offset += 1;
+                    break;
+                }
+                _ => return Err(ExpressionError::Syntax),
+            }
+        }
+        if arguments != expected {
+            return Err(ExpressionError::Arguments);
+        }
+
+        self.emit(op)
+    }
+
+    fn take_while(&mut self, f: impl Fn(u8) -> bool) -> &'a str {
+        let source = self.source;
+        let start = self.offset;
+        while source
+            .get(self.offset)
+            .is_some_and(|character| f(*character))
+        {
+            self.offset += 1;
+        }
+        // Only ASCII was taken
+        core::str::from_utf8(&source[start..self.offset]).unwrap_or_default()
+    }
+}
@@ -1,6 +1,6 @@
 use crate::utils::scale;
 
-use super::{Pattern, PatternInput, PatternMove, MAX_SENSATION};
+use super::{MAX_SENSATION, Pattern, PatternInput, PatternMove};
 
 #[derive(Default)]
 pub struct HalfHalf {
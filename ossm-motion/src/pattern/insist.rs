@@ -0,0 +1,76 @@
+use crate::{
+    float::Real,
+    pattern::{MAX_SENSATION, MIN_SENSATION},
+    utils::scale,
+};
+
+use super::{Pattern, PatternInput, PatternMove};
+
+// Strokes until the full length is reached. Higher sensation grows faster
+const MIN_STEPS: Real = 2.0;
+const MAX_STEPS: Real = 20.0;
+
+#[derive(Default)]
+pub struct Insist {
+    out_stroke: bool,
+    num_steps: usize,
+    current_step: usize,
+    previous_sensation: Real,
+}
+
+impl Insist {
+    pub fn new() -> Self {
+        let mut pattern = Self::default();
+        pattern.reset();
+        pattern
+    }
+}
+
+impl Pattern for Insist {
+    fn get_name(&self) -> &'static str {
+        "Insist"
+    }
+
+    fn get_description(&self) -> &'static str {
+        "Short strokes at the depth that lengthen while taking the same time. Sensation controls how fast they grow"
+    }
+
+    fn reset(&mut self) {
+        self.out_stroke = true;
+        self.num_steps = scale(0.0, MIN_SENSATION, MAX_SENSATION, MAX_STEPS, MIN_STEPS) as usize;
+        self.current_step = 1;
+        // Some random value for it to be overwritten
+        self.previous_sensation = -420.0;
+    }
+
+    fn next_move(&mut self, input: &PatternInput) -> PatternMove {
+        if input.sensation != self.previous_sensation {
+            self.num_steps = scale(
+                input.sensation,
+                MIN_SENSATION,
+                MAX_SENSATION,
+                MAX_STEPS,
+                MIN_STEPS,
+            ) as usize;
+            // Start short again every time sensation changes
+            self.current_step = 1;
+            self.previous_sensation = input.sensation;
+        }
+
+        // Every stroke takes as long as the full stroke at the set velocity
+        let fraction = self.current_step as Real / self.num_steps as Real;
+        let velocity = input.velocity * fraction;
+
+        let new_move = if self.out_stroke {
+            PatternMove::new(velocity, input.depth)
+        } else {
+            let in_stroke_depth = input.depth - input.motion_length * fraction;
+            // The stroke is complete. The next one is longer
+            self.current_step = self.current_step % self.num_steps + 1;
+            PatternMove::new(velocity, in_stroke_depth)
+        };
+        self.out_stroke = !self.out_stroke;
+
+        new_move
+    }
+}
@@ -0,0 +1,122 @@
+use crate::{
+    float::Real,
+    pattern::{MAX_SENSATION, MIN_SENSATION},
+    utils::scale,
+};
+
+use super::{Pattern, PatternInput, PatternMove, dwell::Dwell, params::PatternParam};
+
+const MIN_THRUSTS: Real = 2.0;
+const MAX_THRUSTS: Real = 10.0;
+// The length of a rapid thrust in % of the stroke
+const THRUST_LENGTH_PCT: Real = 20.0;
+// How much slower than the set velocity the withdrawal is
+const WITHDRAWAL_SLOWDOWN: Real = 4.0;
+
+const PARAMS: [PatternParam; 1] = [PatternParam::new(
+    "thrustLength",
+    5.0,
+    50.0,
+    THRUST_LENGTH_PCT,
+)];
+
+pub struct JackHammer {
+    num_thrusts: usize,
+    // In % of the stroke
+    thrust_length: Real,
+    // The move within the current series of thrusts
+    current_move: usize,
+    // Just started. The first thrust approaches the depth slowly
+    approaching: bool,
+}
+
+impl Default for JackHammer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JackHammer {
+    pub fn new() -> Self {
+        let mut pattern = Self {
+            num_thrusts: 0,
+            thrust_length: THRUST_LENGTH_PCT,
+            current_move: 0,
+            approaching: false,
+        };
+        pattern.reset();
+        pattern
+    }
+}
+
+impl Pattern for JackHammer {
+    fn get_name(&self) -> &'static str {
+        "Jack Hammer"
+    }
+
+    fn get_description(&self) -> &'static str {
+        "Rapid short thrusts at the depth followed by one long slow withdrawal. Sensation controls the number of thrusts"
+    }
+
+    fn reset(&mut self) {
+        self.num_thrusts = MIN_THRUSTS as usize;
+        self.current_move = 0;
+    }
+
+    fn on_start(&mut self, _input: &PatternInput) {
+        self.approaching = true;
+    }
+
+    fn on_stop(&mut self) {
+        self.approaching = false;
+    }
+
+    fn get_params(&self) -> &'static [PatternParam] {
+        &PARAMS
+    }
+
+    fn set_param(&mut self, index: usize, value: Real) {
+        if index == 0 {
+            self.thrust_length = value;
+        }
+    }
+
+    // A hold at the depth would break up the thrusts
+    fn get_dwell(&self, global: Dwell) -> Dwell {
+        Dwell {
+            depth_ms: 0,
+            ..global
+        }
+    }
+
+    fn next_move(&mut self, input: &PatternInput) -> PatternMove {
+        // Only changed between the series so that a series is never cut short
+        if self.current_move == 0 {
+            self.num_thrusts = scale(
+                input.sensation,
+                MIN_SENSATION,
+                MAX_SENSATION,
+                MIN_THRUSTS,
+                MAX_THRUSTS,
+            ) as usize;
+        }
+
+        // The first thrust comes from the withdrawal. Every other takes two moves
+        let withdrawal_move = 2 * self.num_thrusts - 1;
+        let new_move = if self.current_move == withdrawal_move {
+            let in_stroke_depth = input.depth - input.motion_length;
+            PatternMove::new(input.velocity / WITHDRAWAL_SLOWDOWN, in_stroke_depth)
+        } else if self.current_move == 0 && self.approaching {
+            self.approaching = false;
+            PatternMove::new(input.velocity / WITHDRAWAL_SLOWDOWN, input.depth)
+        } else if self.current_move.is_multiple_of(2) {
+            PatternMove::new(input.velocity, input.depth)
+        } else {
+            let thrust_depth = input.depth - input.motion_length * self.thrust_length / 100.0;
+            PatternMove::new(input.velocity, thrust_depth)
+        };
+        self.current_move = (self.current_move + 1) % (withdrawal_move + 1);
+
+        new_move
+    }
+}
@@ -0,0 +1,23 @@
+use core::sync::atomic::Ordering;
+
+use portable_atomic::AtomicU32;
+
+use crate::{
+    config::MAX_JITTER_PCT,
+    validation::{ValueError, check_accepted},
+};
+
+// Applied to every pattern by the `PatternExecutor` so that repetitive ones feel less mechanical
+static JITTER_PCT: AtomicU32 = AtomicU32::new(0);
+
+/// Set by how much in % the depth and the velocity of each move vary either way
+/// The depth varies by a share of the stroke length. 0 turns it off
+pub fn set_jitter_pct(jitter_pct: u32) -> Result<(), ValueError> {
+    let accepted = jitter_pct.min(MAX_JITTER_PCT);
+    JITTER_PCT.store(accepted, Ordering::Release);
+    check_accepted(jitter_pct as i64, accepted as i64)
+}
+
+pub fn get_jitter_pct() -> u32 {
+    JITTER_PCT.load(Ordering::Acquire)
+}
@@ -0,0 +1,79 @@
+use crate::{
+    float::Real,
+    pattern::{MAX_SENSATION, MIN_SENSATION},
+    utils::{saturate_range, scale},
+};
+
+use super::{Pattern, PatternInput, PatternMove};
+
+// The load in % the pattern pushes against. Higher sensation pushes against more resistance
+const MIN_TARGET_LOAD: Real = 20.0;
+const MAX_TARGET_LOAD: Real = 80.0;
+// The depth is kept while the load is within this many % of the target
+const LOAD_DEADBAND: Real = 10.0;
+// Backs off faster than it pushes deeper again
+const BACK_OFF_STEP_MM: Real = 4.0;
+const PUSH_STEP_MM: Real = 1.0;
+// The most the depth is reduced by as a share of the stroke
+const MAX_BACK_OFF_FRACTION: Real = 0.5;
+
+#[derive(Default)]
+pub struct LoadAdaptive {
+    out_stroke: bool,
+    // How far short of the set depth the strokes end in mm
+    back_off: Real,
+}
+
+impl LoadAdaptive {
+    pub fn new() -> Self {
+        let mut pattern = Self::default();
+        pattern.reset();
+        pattern
+    }
+}
+
+impl Pattern for LoadAdaptive {
+    fn get_name(&self) -> &'static str {
+        "Load Adaptive"
+    }
+
+    fn get_description(&self) -> &'static str {
+        "Backs off the depth when the resistance rises and pushes deeper when it drops. Sensation sets how much resistance it pushes against"
+    }
+
+    fn reset(&mut self) {
+        self.out_stroke = true;
+        self.back_off = 0.0;
+    }
+
+    fn next_move(&mut self, input: &PatternInput) -> PatternMove {
+        let in_stroke_depth = input.depth - input.motion_length;
+
+        if !self.out_stroke {
+            self.out_stroke = true;
+            // The load was read while pushing to the depth
+            let target_load = scale(
+                input.sensation,
+                MIN_SENSATION,
+                MAX_SENSATION,
+                MIN_TARGET_LOAD,
+                MAX_TARGET_LOAD,
+            );
+            if input.load > target_load + LOAD_DEADBAND {
+                self.back_off += BACK_OFF_STEP_MM;
+            } else if input.load < target_load - LOAD_DEADBAND {
+                self.back_off -= PUSH_STEP_MM;
+            }
+            self.back_off = saturate_range(
+                self.back_off,
+                0.0,
+                input.motion_length * MAX_BACK_OFF_FRACTION,
+            );
+
+            return PatternMove::new(input.velocity, in_stroke_depth);
+        }
+        self.out_stroke = false;
+
+        PatternMove::new(input.velocity, input.depth - self.back_off)
+    }
+}
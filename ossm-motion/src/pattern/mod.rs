@@ -1,54 +1,145 @@
+pub mod custom;
 mod deeper;
+pub mod dwell;
+mod edging;
+pub mod expression;
 mod halfhalf;
+mod insist;
+mod jackhammer;
+pub mod jitter;
+mod loadadaptive;
+pub mod params;
+pub mod registry;
+mod shuffle;
 mod simple;
 mod stopngo;
 mod teasingpounding;
+pub mod tempo;
 mod torque;
+mod warmup;
 
+use custom::Custom;
 use deeper::Deeper;
-use log::error;
+use dwell::{Dwell, get_dwell};
+use edging::Edging;
+use embassy_time::Instant;
 use halfhalf::HalfHalf;
-use heapless::String;
+use heapless::{String, Vec};
+use insist::Insist;
+use jackhammer::JackHammer;
+use jitter::get_jitter_pct;
+use loadadaptive::LoadAdaptive;
+use log::error;
+use params::{PatternParam, apply_params, get_params_generation};
+use registry::{BUILT_IN_PATTERNS, get_registered_patterns};
+use shuffle::Shuffle;
 use simple::Simple;
 use stopngo::StopNGo;
 use teasingpounding::TeasingPounding;
+use tempo::tempo_velocity;
 use torque::Torque;
+use warmup::WarmUp;
 
 use crate::{
-    config::{MAX_PATTERN_LENGTH, MIN_MOVE_MM},
-    utils::saturate_range,
+    config::{MAX_PATTERN_LENGTH, MAX_PATTERNS},
+    float::Real,
+    motion_control::{get_max_acceleration, get_min_move_mm},
+    utils::{rng::Rng, saturate_range},
 };
 use core::fmt::Write;
 
-pub const MIN_SENSATION: f64 = -100.0;
-pub const MAX_SENSATION: f64 = 100.0;
+pub const MIN_SENSATION: Real = -100.0;
+pub const MAX_SENSATION: Real = 100.0;
+
+// The stream of the generator behind the jitter
+const JITTER_RNG_STREAM: u32 = 0x4a49_5454;
+// How close in mm to an end of the stroke a move has to end for the dwell to apply
+const DWELL_END_TOLERANCE_MM: Real = 0.5;
+
+// Stable pattern IDs used by the remotes to select a pattern
+// Independent of the order of the patterns. Never change or reuse an ID
+// The patterns of the first release keep their slot in the pattern list as their ID
+// The first six are numbered like in StrokeEngine
+pub const PATTERN_ID_SIMPLE: u32 = 0;
+pub const PATTERN_ID_TEASING_POUNDING: u32 = 1;
+pub const PATTERN_ID_ROBO_STROKE: u32 = 2;
+pub const PATTERN_ID_HALF_HALF: u32 = 3;
+pub const PATTERN_ID_DEEPER: u32 = 4;
+pub const PATTERN_ID_STOP_N_GO: u32 = 5;
+pub const PATTERN_ID_TORQUE: u32 = 6;
+pub const PATTERN_ID_INSIST: u32 = 7;
+pub const PATTERN_ID_CUSTOM: u32 = 8;
+pub const PATTERN_ID_JACK_HAMMER: u32 = 9;
+pub const PATTERN_ID_EDGING: u32 = 10;
+pub const PATTERN_ID_WARM_UP: u32 = 11;
+// Not a pattern of its own. `run_motion` switches among the other patterns instead
+pub const PATTERN_ID_SHUFFLE: u32 = 12;
+pub const PATTERN_ID_LOAD_ADAPTIVE: u32 = 13;
+
+// Pattern menu of the M5 remote. Its indices are translated to the IDs above
+// The indices the remotes already use must not change. New patterns are only appended
+const M5_LEGACY_PATTERNS: [u32; 8] = [
+    PATTERN_ID_SIMPLE,
+    PATTERN_ID_TEASING_POUNDING,
+    PATTERN_ID_ROBO_STROKE,
+    PATTERN_ID_HALF_HALF,
+    PATTERN_ID_DEEPER,
+    PATTERN_ID_STOP_N_GO,
+    PATTERN_ID_TORQUE,
+    PATTERN_ID_INSIST,
+];
+
+/// The pattern ID for an index in the pattern menu of the M5 remote
+pub fn pattern_id_from_m5_index(index: u32) -> Option<u32> {
+    M5_LEGACY_PATTERNS.get(index as usize).copied()
+}
+
+/// The index in the pattern menu of the M5 remote for a pattern ID
+/// None if the M5 remote does not know the pattern
+pub fn m5_index_from_pattern_id(id: u32) -> Option<u32> {
+    M5_LEGACY_PATTERNS
+        .iter()
+        .position(|legacy_id| *legacy_id == id)
+        .map(|index| index as u32)
+}
 
 pub struct PatternInput {
     // The maximum depth in mm
-    pub depth: f64,
+    pub depth: Real,
     // The maximum length of the motion in mm
-    pub motion_length: f64,
+    pub motion_length: Real,
     // The maximum velocity in mm/s
-    pub velocity: f64,
+    pub velocity: Real,
     // Sensation from -100 to 100
-    pub sensation: f64,
+    pub sensation: Real,
+    // Estimated motor load in %. 0 if the motor cannot report it
+    pub load: Real,
+    // Tempo in strokes per minute. 0 if the velocity is set directly
+    // The velocity is then only the limit the executor picks the velocity for the tempo within
+    pub bpm: Real,
+    // Monotonic time the move is requested at
+    pub now: Instant,
+    // Strokes since the pattern was started. Counts the moves towards the depth
+    pub stroke_index: u32,
+    // Time since the pattern was started in ms
+    pub elapsed_ms: u64,
 }
 
 #[derive(Default, Clone, Copy)]
 pub struct PatternMove {
     // The maximum velocity for the move
-    pub velocity: f64,
+    pub velocity: Real,
     // The position for the move
-    pub position: f64,
+    pub position: Real,
     // How much to delay after this move
     pub delay_ms: u64,
     // The maximum torque in %
-    pub torque: f64,
+    pub torque: Real,
 }
 
 impl PatternMove {
     /// Create a new pattern move
-    pub fn new(velocity: f64, position: f64) -> Self {
+    pub fn new(velocity: Real, position: Real) -> Self {
         Self {
             velocity,
             position,
@@ -58,7 +149,7 @@ impl PatternMove {
     }
 
     /// Create a new pattern move that would delay by this much after a pattern is done
-    pub fn new_with_delay(velocity: f64, position: f64, delay_ms: u64) -> Self {
+    pub fn new_with_delay(velocity: Real, position: Real, delay_ms: u64) -> Self {
         Self {
             velocity,
             position,
@@ -68,7 +159,7 @@ impl PatternMove {
     }
 
     /// Create a new pattern move with the given torque
-    pub fn new_with_torque(velocity: f64, position: f64, torque: f64) -> Self {
+    pub fn new_with_torque(velocity: Real, position: Real, torque: Real) -> Self {
         Self {
             velocity,
             position,
@@ -90,15 +181,38 @@ pub trait Pattern {
     /// Get the next position for the pattern with the given input
     /// Will be called when the move to the previously given position is complete
     fn next_move(&mut self, input: &PatternInput) -> PatternMove;
+
+    /// Called when the motion is enabled with this pattern or it is selected while enabled
+    /// Before the first move. Can be used to make the first move an entry move
+    fn on_start(&mut self, _input: &PatternInput) {}
+
+    /// Called when the motion is disabled or a different pattern is selected
+    fn on_stop(&mut self) {}
+
+    /// The parameters the pattern can be tuned with besides the sensation
+    fn get_params(&self) -> &'static [PatternParam] {
+        &[]
+    }
+
+    /// Set the parameter at `index` of `get_params`
+    /// Only called with values within the range of the parameter
+    fn set_param(&mut self, _index: usize, _value: Real) {}
+
+    /// The hold at the ends of the strokes given the one set for all patterns
+    fn get_dwell(&self, global: Dwell) -> Dwell {
+        global
+    }
 }
 
 pub struct PatternExecutor {
-    patterns: [Option<AvailablePatterns>; NUM_PATTERNS],
+    // The patterns with their IDs. Built-in first, then the ones registered at runtime
+    patterns: Vec<(u32, AvailablePatterns), MAX_PATTERNS>,
     current_pattern: usize,
+    // The generation of the parameters last passed to the current pattern
+    params_generation: Option<u32>,
+    jitter_rng: Rng,
 }
 
-const NUM_PATTERNS: usize = 7;
-
 #[enum_dispatch::enum_dispatch]
 pub enum AvailablePatterns {
     Simple,
@@ -106,68 +220,102 @@ pub enum AvailablePatterns {
     HalfHalf,
     Deeper,
     StopNGo,
+    Insist,
     Torque,
+    JackHammer,
+    Edging,
+    WarmUp,
+    LoadAdaptive,
+    Shuffle,
+    Custom,
 }
 
 impl PatternExecutor {
     pub fn new() -> Self {
-        let patterns = [
-            Some(Simple::new().into()),
-            Some(TeasingPounding::new().into()),
-            None,
-            Some(HalfHalf::new().into()),
-            Some(Deeper::new().into()),
-            Some(StopNGo::new().into()),
-            Some(Torque::new().into()),
-        ];
-
-        Self {
-            patterns,
+        let mut executor = Self {
+            patterns: Vec::new(),
             current_pattern: 0,
+            params_generation: None,
+            jitter_rng: Rng::seeded(JITTER_RNG_STREAM),
+        };
+        for (id, factory) in BUILT_IN_PATTERNS {
+            let added = executor.patterns.push((id, factory())).is_ok();
+            assert!(added, "Sized for all patterns");
         }
+        executor.add_registered_patterns();
+
+        executor
+    }
+
+    /// Add the patterns registered since the last call
+    fn add_registered_patterns(&mut self) {
+        let known = self.patterns.len() - BUILT_IN_PATTERNS.len();
+        for (id, factory) in get_registered_patterns(known) {
+            let added = self.patterns.push((id, factory())).is_ok();
+            assert!(added, "Sized for all patterns");
+        }
+    }
+
+    fn index_of(&self, id: u32) -> Option<usize> {
+        self.patterns
+            .iter()
+            .position(|(pattern_id, _)| *pattern_id == id)
     }
 
-    pub fn set_pattern(&mut self, pattern_index: u32) {
-        let mut selected_pattern = pattern_index as usize;
+    /// Whether a pattern with this ID is implemented
+    pub fn has_pattern(&self, id: u32) -> bool {
+        self.index_of(id).is_some()
+    }
 
-        if let Some(new_pattern) = self.patterns.get(selected_pattern) {
-            if new_pattern.is_none() {
-                error!(
-                    "Pattern at index {} not implemented. Switching to the simple pattern",
-                    pattern_index
-                );
-                selected_pattern = 0;
+    /// Select the pattern with the given ID
+    /// Falls back to the simple pattern if there is no such pattern
+    pub fn set_pattern(&mut self, id: u32) {
+        self.add_registered_patterns();
+        self.current_pattern = match self.index_of(id) {
+            Some(index) => index,
+            None => {
+                error!("Unknown pattern ID {}. Switching to the simple pattern", id);
+                self.index_of(PATTERN_ID_SIMPLE)
+                    .expect("The simple pattern is built in")
             }
-        } else {
-            error!(
-                "Unknown pattern index {}. Switching to the simple pattern",
-                pattern_index
-            );
-            selected_pattern = 0;
         };
+        self.params_generation = None;
+    }
+
+    /// The parameters of the pattern with the given ID. Empty for unknown IDs
+    pub fn get_params(&self, id: u32) -> &'static [PatternParam] {
+        self.index_of(id)
+            .map_or(&[], |index| self.patterns[index].1.get_params())
+    }
 
-        self.current_pattern = selected_pattern;
+    /// How many patterns there are including the ones registered at runtime
+    pub fn get_pattern_count(&mut self) -> usize {
+        self.add_registered_patterns();
+        self.patterns.len()
+    }
+
+    /// The ID and name of every pattern in the order of the pattern list
+    pub fn get_pattern_list(&mut self) -> impl Iterator<Item = (u32, &'static str)> + '_ {
+        self.add_registered_patterns();
+        self.patterns
+            .iter()
+            .map(|(id, pattern)| (*id, pattern.get_name()))
     }
 
     pub fn get_current_pattern_name(&self) -> &'static str {
-        if let Some(pattern) = &self.patterns[self.current_pattern] {
-            pattern.get_name()
-        } else {
-            "Not Implemented"
-        }
+        self.patterns[self.current_pattern].1.get_name()
     }
 
     /// Returns all patterns as json
+    /// `idx` is the ID of the pattern for compatibility with the BLE protocol
     pub fn get_all_patterns_json(&mut self) -> String<MAX_PATTERN_LENGTH> {
         let mut output = String::new();
         output.write_char('[').ok();
-        for (i, pattern) in self.patterns.iter().enumerate() {
-            if let Some(pattern) = pattern {
-                let name = pattern.get_name();
-                if write!(output, r#"{{"name":"{name}","idx":{i}}},"#).is_err() {
-                    error!("Patterns too long. Returning unfinished string");
-                    break;
-                }
+        for (id, pattern) in self.patterns.iter() {
+            let name = pattern.get_name();
+            if write!(output, r#"{{"name":"{name}","idx":{id}}},"#).is_err() {
+                error!("Patterns too long. Returning unfinished string");
+                break;
             }
         }
         // Remove the last comma
@@ -180,26 +328,18 @@ impl PatternExecutor {
         output
     }
 
-    pub fn get_pattern_description(&self, index: usize) -> String<MAX_PATTERN_LENGTH> {
+    pub fn get_pattern_description(&self, id: u32) -> String<MAX_PATTERN_LENGTH> {
         let mut output = String::new();
 
-        if let Some(pattern) = self.patterns.get(index) {
-            if let Some(pattern) = pattern {
-                let description = pattern.get_description();
-                if output.push_str(description).is_err() {
-                    output
-                        .push_str("Pattern Description Too Long")
-                        .expect("Always fits");
-                }
-            } else {
+        if let Some(index) = self.index_of(id) {
+            let description = self.patterns[index].1.get_description();
+            if output.push_str(description).is_err() {
                 output
-                    .push_str("Pattern Not Implemented")
+                    .push_str("Pattern Description Too Long")
                     .expect("Always fits");
             }
         } else {
-            output
-                .push_str("Invalid Pattern Index")
-                .expect("Always fits");
+            output.push_str("Unknown Pattern ID").expect("Always fits");
         }
 
         output
@@ -216,26 +356,64 @@ impl Pattern for PatternExecutor {
     }
 
     fn reset(&mut self) {
-        let pattern = self.patterns[self.current_pattern]
-            .as_mut()
-            .expect("Checked in set_pattern");
+        self.patterns[self.current_pattern].1.reset();
+    }
 
-        pattern.reset();
+    fn on_start(&mut self, input: &PatternInput) {
+        self.patterns[self.current_pattern].1.on_start(input);
+    }
+
+    fn on_stop(&mut self) {
+        self.patterns[self.current_pattern].1.on_stop();
     }
 
     fn next_move(&mut self, input: &PatternInput) -> PatternMove {
-        let pattern = self.patterns[self.current_pattern]
-            .as_mut()
-            .expect("Checked in set_pattern");
+        let generation = get_params_generation();
+        if self.params_generation != Some(generation) {
+            let (id, pattern) = &mut self.patterns[self.current_pattern];
+            apply_params(*id, pattern);
+            self.params_generation = Some(generation);
+        }
+
+        // The velocity follows from the stroke length so that the tempo stays the same
+        let tempo_input;
+        let input = if input.bpm > 0.0 {
+            let velocity = tempo_velocity(input.motion_length, input.bpm, get_max_acceleration());
+            tempo_input = PatternInput {
+                velocity: velocity.min(input.velocity),
+                ..*input
+            };
+            &tempo_input
+        } else {
+            input
+        };
 
+        let pattern = &mut self.patterns[self.current_pattern].1;
         let mut next_move = pattern.next_move(input);
 
+        // Hold at the ends of the stroke on top of any delay of the pattern
+        // The end is the one the pattern moves to before the jitter
+        let dwell = pattern.get_dwell(get_dwell());
+        let position = saturate_range(next_move.position, 0.0, input.depth);
+        let retracted = (input.depth - input.motion_length).max(0.0);
+        if position >= input.depth - DWELL_END_TOLERANCE_MM {
+            next_move.delay_ms += dwell.depth_ms as u64;
+        } else if position <= retracted + DWELL_END_TOLERANCE_MM {
+            next_move.delay_ms += dwell.retract_ms as u64;
+        }
+
+        let jitter = get_jitter_pct() as Real / 100.0;
+        if jitter > 0.0 {
+            next_move.position += input.motion_length * self.jitter_rng.range(-jitter, jitter);
+            next_move.velocity *= 1.0 + self.jitter_rng.range(-jitter, jitter);
+        }
+
         // Verify that all the input constraints have been met and saturate if not
         next_move.position = saturate_range(next_move.position, 0.0, input.depth);
         next_move.velocity = saturate_range(next_move.velocity, 0.0, input.velocity);
 
-        // Each move is from 0 to depth. Add MIN_MOVE_MM to start from the minimum allowed position
-        next_move.position += MIN_MOVE_MM;
+        // Each move is from 0 to depth. Add the min move to start from the minimum allowed position
+        next_move.position += get_min_move_mm();
 
         next_move
     }
@@ -0,0 +1,130 @@
+use core::{cell::RefCell, fmt::Write, sync::atomic::Ordering};
+
+use critical_section::Mutex;
+use heapless::{LinearMap, String};
+use log::error;
+use portable_atomic::AtomicU32;
+
+use super::{Pattern, PatternExecutor};
+use crate::{
+    config::{MAX_PATTERN_PARAMS, MAX_PATTERN_PARAMS_LENGTH, MAX_PATTERNS},
+    float::Real,
+    utils::saturate_range,
+    validation::ValueError,
+};
+
+/// Describes a parameter of a pattern
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PatternParam {
+    // camelCase like the other keys sent to the remotes
+    pub name: &'static str,
+    pub min: Real,
+    pub max: Real,
+    pub default: Real,
+}
+
+impl PatternParam {
+    pub const fn new(name: &'static str, min: Real, max: Real, default: Real) -> Self {
+        Self {
+            name,
+            min,
+            max,
+            default,
+        }
+    }
+}
+
+type ParamValues = [Option<Real>; MAX_PATTERN_PARAMS];
+
+// Values set by the remotes per pattern ID. None uses the default
+static VALUES: Mutex<RefCell<LinearMap<u32, ParamValues, MAX_PATTERNS>>> =
+    Mutex::new(RefCell::new(LinearMap::new()));
+// Incremented on every change so that the executor knows to pass the values on again
+static GENERATION: AtomicU32 = AtomicU32::new(0);
+
+pub(crate) fn get_params_generation() -> u32 {
+    GENERATION.load(Ordering::Acquire)
+}
+
+/// The value of the parameter at `index` of the pattern with the given ID
+pub(crate) fn get_param_value(pattern_id: u32, index: usize, param: &PatternParam) -> Real {
+    critical_section::with(|cs| {
+        VALUES
+            .borrow_ref(cs)
+            .get(&pattern_id)
+            .and_then(|values| values.get(index).copied().flatten())
+    })
+    .unwrap_or(param.default)
+}
+
+/// Set the parameter at `index` of the pattern with the given ID
+/// Out of range values are clamped and applied
+pub fn set_pattern_param(pattern_id: u32, index: usize, value: Real) -> Result<(), ValueError> {
+    let executor = PatternExecutor::new();
+    let param = executor
+        .get_params(pattern_id)
+        .get(index)
+        .ok_or(ValueError::Unknown)?;
+    if !value.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let accepted = saturate_range(value, param.min, param.max);
+
+    critical_section::with(|cs| {
+        let mut values = VALUES.borrow_ref_mut(cs);
+        if !values.contains_key(&pattern_id) {
+            values
+                .insert(pattern_id, [None; MAX_PATTERN_PARAMS])
+                .expect("Sized for all patterns");
+        }
+        values[&pattern_id][index] = Some(accepted);
+    });
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+
+    if accepted != value {
+        return Err(ValueError::OutOfRange {
+            accepted: accepted as i32,
+        });
+    }
+    Ok(())
+}
+
+/// Go back to the defaults for all the patterns
+pub fn reset_pattern_params() {
+    critical_section::with(|cs| VALUES.borrow_ref_mut(cs).clear());
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Returns the parameters of the pattern with the given ID and their values as JSON
+pub fn get_pattern_params_json(pattern_id: u32) -> String<MAX_PATTERN_PARAMS_LENGTH> {
+    let executor = PatternExecutor::new();
+    let mut output = String::new();
+
+    let mut result = write!(output, r#"{{"pattern":{pattern_id},"params":["#);
+    for (index, param) in executor.get_params(pattern_id).iter().enumerate() {
+        let separator = if index > 0 { "," } else { "" };
+        result = result.and_then(|()| {
+            write!(
+                output,
+                r#"{separator}{{"name":"{}","min":{},"max":{},"default":{},"value":{}}}"#,
+                param.name,
+                param.min,
+                param.max,
+                param.default,
+                get_param_value(pattern_id, index, param)
+            )
+        });
+    }
+    if result.and_then(|()| output.write_str("]}")).is_err() {
+        error!("Could not write the pattern parameters. Too long");
+    }
+
+    output
+}
+
+/// Pass the values of all the parameters to a pattern
+pub(crate) fn apply_params(pattern_id: u32, pattern: &mut impl Pattern) {
+    for (index, param) in pattern.get_params().iter().enumerate() {
+        pattern.set_param(index, get_param_value(pattern_id, index, param));
+    }
+}
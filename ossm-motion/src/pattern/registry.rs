@@ -0,0 +1,94 @@
+use core::{
+    cell::RefCell,
+    fmt::{self, Display},
+};
+
+use critical_section::Mutex;
+use heapless::Vec;
+
+use super::{
+    AvailablePatterns, PATTERN_ID_CUSTOM, PATTERN_ID_DEEPER, PATTERN_ID_EDGING,
+    PATTERN_ID_HALF_HALF, PATTERN_ID_INSIST, PATTERN_ID_JACK_HAMMER, PATTERN_ID_LOAD_ADAPTIVE,
+    PATTERN_ID_SHUFFLE, PATTERN_ID_SIMPLE, PATTERN_ID_STOP_N_GO, PATTERN_ID_TEASING_POUNDING,
+    PATTERN_ID_TORQUE, PATTERN_ID_WARM_UP, custom::Custom, deeper::Deeper, edging::Edging,
+    halfhalf::HalfHalf, insist::Insist, jackhammer::JackHammer, loadadaptive::LoadAdaptive,
+    shuffle::Shuffle, simple::Simple, stopngo::StopNGo, teasingpounding::TeasingPounding,
+    torque::Torque, warmup::WarmUp,
+};
+use crate::config::MAX_PATTERNS;
+
+/// Creates a pattern in its initial state
+pub type PatternFactory = fn() -> AvailablePatterns;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegistryError {
+    // The ID is already taken by another pattern
+    DuplicateId,
+    // Already MAX_PATTERNS patterns
+    Full,
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::DuplicateId => write!(f, "duplicate_id"),
+            RegistryError::Full => write!(f, "full"),
+        }
+    }
+}
+
+// In the order they are listed to the remotes
+pub(crate) const BUILT_IN_PATTERNS: [(u32, PatternFactory); 13] = [
+    (PATTERN_ID_SIMPLE, || Simple::new().into()),
+    (PATTERN_ID_TEASING_POUNDING, || {
+        TeasingPounding::new().into()
+    }),
+    (PATTERN_ID_HALF_HALF, || HalfHalf::new().into()),
+    (PATTERN_ID_DEEPER, || Deeper::new().into()),
+    (PATTERN_ID_STOP_N_GO, || StopNGo::new().into()),
+    (PATTERN_ID_INSIST, || Insist::new().into()),
+    (PATTERN_ID_TORQUE, || Torque::new().into()),
+    (PATTERN_ID_JACK_HAMMER, || JackHammer::new().into()),
+    (PATTERN_ID_EDGING, || Edging::new().into()),
+    (PATTERN_ID_WARM_UP, || WarmUp::new().into()),
+    (PATTERN_ID_LOAD_ADAPTIVE, || LoadAdaptive::new().into()),
+    (PATTERN_ID_SHUFFLE, || Shuffle::new().into()),
+    (PATTERN_ID_CUSTOM, || Custom::new().into()),
+];
+
+// Listed after the built-in patterns in the order they were registered. Never removed
+static REGISTERED: Mutex<RefCell<Vec<(u32, PatternFactory), MAX_PATTERNS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Add a pattern under the given ID
+/// Executors that already exist pick it up the next time a pattern is selected
+pub fn register_pattern(id: u32, factory: PatternFactory) -> Result<(), RegistryError> {
+    critical_section::with(|cs| {
+        let mut registered = REGISTERED.borrow_ref_mut(cs);
+        let taken = BUILT_IN_PATTERNS
+            .iter()
+            .chain(registered.iter())
+            .any(|(registered_id, _)| *registered_id == id);
+        if taken {
+            return Err(RegistryError::DuplicateId);
+        }
+        if BUILT_IN_PATTERNS.len() + registered.len() >= MAX_PATTERNS {
+            return Err(RegistryError::Full);
+        }
+        registered
+            .push((id, factory))
+            .map_err(|_| RegistryError::Full)
+    })
+}
+
+/// The patterns registered at runtime starting from the one at `start`
+pub(crate) fn get_registered_patterns(start: usize) -> Vec<(u32, PatternFactory), MAX_PATTERNS> {
+    critical_section::with(|cs| {
+        REGISTERED
+            .borrow_ref(cs)
+            .iter()
+            .skip(start)
+            .copied()
+            .collect()
+    })
+}
@@ -0,0 +1,34 @@
+use super::{Pattern, PatternInput, PatternMove, simple::Simple};
+
+/// Lets the shuffle mode be selected like any other pattern
+/// `run_motion` replaces it with the patterns it shuffles through. Only goes in and out itself
+#[derive(Default)]
+pub struct Shuffle {
+    fallback: Simple,
+}
+
+impl Shuffle {
+    pub fn new() -> Self {
+        let mut pattern = Self::default();
+        pattern.reset();
+        pattern
+    }
+}
+
+impl Pattern for Shuffle {
+    fn get_name(&self) -> &'static str {
+        "Shuffle"
+    }
+
+    fn get_description(&self) -> &'static str {
+        "Switches to another pattern every few minutes. Sensation is passed on to the patterns"
+    }
+
+    fn reset(&mut self) {
+        self.fallback.reset();
+    }
+
+    fn next_move(&mut self, input: &PatternInput) -> PatternMove {
+        self.fallback.next_move(input)
+    }
+}
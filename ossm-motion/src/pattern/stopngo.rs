@@ -1,6 +1,7 @@
 use log::info;
 
 use crate::{
+    float::Real,
     pattern::{MAX_SENSATION, MIN_SENSATION},
     utils::scale,
 };
@@ -8,8 +9,8 @@ use crate::{
 use super::{Pattern, PatternInput, PatternMove};
 
 const MAX_STROKES: usize = 5;
-const MIN_DELAY_MS: f64 = 100.0;
-const MAX_DELAY_MS: f64 = 10000.0;
+const MIN_DELAY_MS: Real = 100.0;
+const MAX_DELAY_MS: Real = 10000.0;
 
 #[derive(Default)]
 pub struct StopNGo {
@@ -17,7 +18,7 @@ pub struct StopNGo {
     num_strokes: usize,
     current_stroke: usize,
     counting_up: bool,
-    previous_sensation: f64,
+    previous_sensation: Real,
 }
 
 impl StopNGo {
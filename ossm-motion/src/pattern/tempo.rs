@@ -0,0 +1,20 @@
+use num_traits::Float;
+
+use crate::float::Real;
+
+/// The velocity in mm/s at which a stroke of the given length in and out matches the tempo
+/// Accounts for accelerating and decelerating at `acceleration` in mm/s² on every move
+/// Returns the highest velocity that can be reached if the tempo is too fast for the stroke
+pub fn tempo_velocity(motion_length: Real, bpm: Real, acceleration: Real) -> Real {
+    // Each stroke is one move in and one out
+    let move_time = 60.0 / bpm / 2.0;
+    // Accelerating to v and back takes v/a longer than going at v the whole way
+    // Solves d = v * (t - v / a) for v
+    let reach = acceleration * move_time;
+    let discriminant = reach * reach - 4.0 * acceleration * motion_length;
+    if discriminant <= 0.0 {
+        return reach / 2.0;
+    }
+
+    (reach - Float::sqrt(discriminant)) / 2.0
+}
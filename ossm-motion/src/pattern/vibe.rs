@@ -1,9 +1,9 @@
-use crate::{pattern::{MAX_SENSATION, MIN_SENSATION}, utils::scale};
+use crate::{float::Real, pattern::{MAX_SENSATION, MIN_SENSATION}, utils::scale};
 
 use super::{Pattern, PatternInput, PatternMove};
 
-const MIN_VIBE_MM: f64 = 1.0;
-const MAX_VIBE_MM: f64 = 10.0;
+const MIN_VIBE_MM: Real = 1.0;
+const MAX_VIBE_MM: Real = 10.0;
 
 #[derive(Default)]
 enum VibeState {
@@ -17,7 +17,7 @@ enum VibeState {
 pub struct Vibe {
     out_stroke: bool,
     vibe_state: VibeState,
-    current_depth: f64,
+    current_depth: Real,
 }
 
 impl Vibe {
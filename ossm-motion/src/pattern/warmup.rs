@@ -0,0 +1,76 @@
+use embassy_time::Instant;
+
+use crate::{
+    float::Real,
+    pattern::{MAX_SENSATION, MIN_SENSATION},
+    time::elapsed_between,
+    utils::{saturate_range, scale},
+};
+
+use super::{Pattern, PatternInput, PatternMove};
+
+// How long it takes to reach the set depth and velocity. Higher sensation warms up faster
+const MIN_DURATION_S: Real = 60.0;
+const MAX_DURATION_S: Real = 600.0;
+// The share of the stroke and velocity the warm-up starts from
+const START_LENGTH_FRACTION: Real = 0.3;
+const START_VELOCITY_FRACTION: Real = 0.2;
+
+#[derive(Default)]
+pub struct WarmUp {
+    out_stroke: bool,
+    // The time of the first move. None until the pattern is started
+    started: Option<Instant>,
+}
+
+impl WarmUp {
+    pub fn new() -> Self {
+        let mut pattern = Self::default();
+        pattern.reset();
+        pattern
+    }
+}
+
+impl Pattern for WarmUp {
+    fn get_name(&self) -> &'static str {
+        "Warm-Up"
+    }
+
+    fn get_description(&self) -> &'static str {
+        "Starts with short slow strokes and grows to the set depth and speed over minutes. Sensation controls how fast"
+    }
+
+    fn reset(&mut self) {
+        self.out_stroke = true;
+        self.started = None;
+    }
+
+    fn next_move(&mut self, input: &PatternInput) -> PatternMove {
+        let started = *self.started.get_or_insert(input.now);
+        let elapsed_s = elapsed_between(started, input.now).as_millis() as Real / 1000.0;
+        let duration_s = scale(
+            input.sensation,
+            MIN_SENSATION,
+            MAX_SENSATION,
+            MAX_DURATION_S,
+            MIN_DURATION_S,
+        );
+        let progress = saturate_range(elapsed_s / duration_s, 0.0, 1.0);
+
+        let length_fraction = scale(progress, 0.0, 1.0, START_LENGTH_FRACTION, 1.0);
+        let velocity_fraction = scale(progress, 0.0, 1.0, START_VELOCITY_FRACTION, 1.0);
+        let velocity = input.velocity * velocity_fraction;
+        let in_stroke_depth = input.depth - input.motion_length;
+
+        // Shallow strokes from the in stroke depth that reach deeper over time
+        let new_move = if self.out_stroke {
+            let out_stroke_depth = in_stroke_depth + input.motion_length * length_fraction;
+            PatternMove::new(velocity, out_stroke_depth)
+        } else {
+            PatternMove::new(velocity, in_stroke_depth)
+        };
+        self.out_stroke = !self.out_stroke;
+
+        new_move
+    }
+}
@@ -0,0 +1,218 @@
+use core::{
+    cell::RefCell,
+    fmt::{self, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use critical_section::Mutex;
+use heapless::{String, Vec};
+use log::{error, info};
+
+use crate::{
+    config::{MAX_PRESET_NAME_LENGTH, MAX_PRESETS, MAX_PRESETS_LENGTH},
+    motion::motion_state::{
+        get_motion_state, get_motion_torque_pct, set_motion_depth_pct, set_motion_length_pct,
+        set_motion_pattern, set_motion_sensation_pct, set_motion_torque_forward_pct,
+        set_motion_torque_reverse_pct, set_motion_velocity_pct,
+    },
+};
+
+// No preset was loaded yet
+const NO_PRESET: usize = usize::MAX;
+
+/// A named snapshot of the motion settings. Unlike a profile it does not restrict anything,
+/// loading it sets the values like a remote would
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preset {
+    pub name: String<MAX_PRESET_NAME_LENGTH>,
+    // All in % except for the pattern ID
+    pub depth: u32,
+    pub stroke: u32,
+    pub speed: u32,
+    pub sensation: u32,
+    pub pattern: u32,
+    // Torque of the moves out and back in
+    pub torque_out: u32,
+    pub torque_in: u32,
+}
+
+impl Preset {
+    fn apply(&self) {
+        // Clamped by the limits of the active profile like any other input
+        set_motion_length_pct(self.stroke).ok();
+        set_motion_depth_pct(self.depth).ok();
+        set_motion_velocity_pct(self.speed).ok();
+        set_motion_sensation_pct(self.sensation).ok();
+        set_motion_torque_forward_pct(self.torque_out).ok();
+        set_motion_torque_reverse_pct(self.torque_in).ok();
+        // A custom pattern may not be registered anymore. The current one is kept then
+        if set_motion_pattern(self.pattern).is_err() {
+            error!(
+                "Preset {} has the unknown pattern {}",
+                self.name, self.pattern
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PresetError {
+    // There is no preset with the name, or none at all
+    Unknown,
+    // MAX_PRESETS are saved already
+    Full,
+    // Empty, too long or with a character that can not be in a command or JSON
+    InvalidName,
+}
+
+static PRESETS: Mutex<RefCell<Vec<Preset, MAX_PRESETS>>> = Mutex::new(RefCell::new(Vec::new()));
+// The index of the preset loaded last. Cycling continues after it
+static LAST_PRESET: AtomicUsize = AtomicUsize::new(NO_PRESET);
+
+fn with_presets<R>(f: impl FnOnce(&mut Vec<Preset, MAX_PRESETS>) -> R) -> R {
+    critical_section::with(|cs| f(&mut PRESETS.borrow_ref_mut(cs)))
+}
+
+fn parse_name(name: &str) -> Result<String<MAX_PRESET_NAME_LENGTH>, PresetError> {
+    let valid = !name.is_empty()
+        && !name
+            .chars()
+            .any(|c| c == ':' || c == '"' || c == '\\' || c.is_control());
+    if !valid {
+        return Err(PresetError::InvalidName);
+    }
+    String::try_from(name).map_err(|_| PresetError::InvalidName)
+}
+
+/// Save the current motion settings under the name. Replaces the preset with the same name
+pub fn save_preset(name: &str) -> Result<(), PresetError> {
+    let name = parse_name(name)?;
+    let state = get_motion_state();
+    let preset = Preset {
+        name,
+        depth: state.depth,
+        stroke: state.motion_length,
+        speed: state.velocity,
+        sensation: state.sensation,
+        pattern: state.pattern,
+        torque_out: get_motion_torque_pct(true),
+        torque_in: get_motion_torque_pct(false),
+    };
+
+    with_presets(|presets| {
+        match presets.iter_mut().find(|saved| saved.name == preset.name) {
+            Some(saved) => *saved = preset.clone(),
+            None => presets
+                .push(preset.clone())
+                .map_err(|_| PresetError::Full)?,
+        }
+        Ok(())
+    })?;
+
+    info!("Preset {} saved", preset.name);
+    Ok(())
+}
+
+/// Apply the preset with the name
+pub fn load_preset(name: &str) -> Result<(), PresetError> {
+    let (index, preset) = with_presets(|presets| {
+        presets
+            .iter()
+            .enumerate()
+            .find(|(_, preset)| preset.name == name)
+            .map(|(index, preset)| (index, preset.clone()))
+            .ok_or(PresetError::Unknown)
+    })?;
+
+    preset.apply();
+    LAST_PRESET.store(index, Ordering::Release);
+    info!("Preset {} loaded", preset.name);
+    Ok(())
+}
+
+/// Apply the preset after the one loaded last, starting over after the last one
+pub fn load_next_preset() -> Result<(), PresetError> {
+    let last = LAST_PRESET.load(Ordering::Acquire);
+    let (index, preset) = with_presets(|presets| {
+        let index = match last {
+            NO_PRESET => 0,
+            last => (last + 1) % presets.len().max(1),
+        };
+        presets
+            .get(index)
+            .map(|preset| (index, preset.clone()))
+            .ok_or(PresetError::Unknown)
+    })?;
+
+    preset.apply();
+    LAST_PRESET.store(index, Ordering::Release);
+    info!("Preset {} loaded", preset.name);
+    Ok(())
+}
+
+/// Remove the preset with the name
+pub fn delete_preset(name: &str) -> Result<(), PresetError> {
+    with_presets(|presets| {
+        let index = presets
+            .iter()
+            .position(|preset| preset.name == name)
+            .ok_or(PresetError::Unknown)?;
+        presets.remove(index);
+        Ok(())
+    })?;
+
+    // The indices after it moved
+    LAST_PRESET.store(NO_PRESET, Ordering::Release);
+    info!("Preset {} deleted", name);
+    Ok(())
+}
+
+/// All presets, e.g. to store them
+pub fn get_presets() -> Vec<Preset, MAX_PRESETS> {
+    with_presets(|presets| presets.clone())
+}
+
+/// Replace all presets, e.g. with the stored ones at boot
+pub fn set_presets(new_presets: &[Preset]) {
+    with_presets(|presets| {
+        presets.clear();
+        for preset in new_presets.iter().take(MAX_PRESETS) {
+            presets
+                .push(preset.clone())
+                .expect("Limited to MAX_PRESETS");
+        }
+    });
+    LAST_PRESET.store(NO_PRESET, Ordering::Release);
+}
+
+/// All presets as JSON e.g.
+/// `[{"name":"slow","depth":80,"stroke":50,"speed":20,"sensation":50,"pattern":0,"torqueOut":100,"torqueIn":100}]`
+pub fn get_presets_json() -> String<MAX_PRESETS_LENGTH> {
+    let mut output = String::new();
+    if with_presets(|presets| write_presets(&mut output, presets)).is_err() {
+        error!("Presets too long. Returning unfinished string");
+    }
+    output
+}
+
+fn write_presets(output: &mut impl Write, presets: &[Preset]) -> fmt::Result {
+    output.write_char('[')?;
+    for (i, preset) in presets.iter().enumerate() {
+        if i > 0 {
+            output.write_char(',')?;
+        }
+        write!(
+            output,
+            r#"{{"name":"{}","depth":{},"stroke":{},"speed":{},"sensation":{},"pattern":{},"torqueOut":{},"torqueIn":{}}}"#,
+            preset.name,
+            preset.depth,
+            preset.stroke,
+            preset.speed,
+            preset.sensation,
+            preset.pattern,
+            preset.torque_out,
+            preset.torque_in,
+        )?;
+    }
+    output.write_char(']')
+}
@@ -0,0 +1,363 @@
+use core::{
+    cell::RefCell,
+    fmt::Write,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+
+use critical_section::Mutex;
+use heapless::{String, Vec};
+use log::{error, info};
+
+use crate::{
+    config::{MAX_PROFILE_NAME_LENGTH, MAX_PROFILES, MAX_PROFILES_LENGTH},
+    motion::motion_state::{
+        get_motion_state, set_motion_depth_pct, set_motion_pattern, set_motion_sensation_pct,
+        set_motion_velocity_pct,
+    },
+    session_limits::get_session_limits,
+};
+
+/// Limits enforced while a profile is active. All values are in %
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProfileLimits {
+    // Maximum velocity in %
+    pub velocity: u32,
+    // Maximum depth in %
+    pub depth: u32,
+    // Maximum torque in %
+    pub torque: u32,
+}
+
+impl ProfileLimits {
+    pub const fn unrestricted() -> Self {
+        Self {
+            velocity: 100,
+            depth: 100,
+            torque: 100,
+        }
+    }
+
+    fn saturated(self) -> Self {
+        Self {
+            velocity: self.velocity.min(100),
+            depth: self.depth.min(100),
+            torque: self.torque.min(100),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub name: String<MAX_PROFILE_NAME_LENGTH>,
+    pub limits: ProfileLimits,
+    // Sensation in % applied when the profile is selected
+    pub sensation: u32,
+    // Pattern index applied when the profile is selected
+    pub pattern: u32,
+    // The profile can only be selected or modified with this PIN if set
+    pub pin: Option<u32>,
+}
+
+impl Profile {
+    fn new(name: &str, limits: ProfileLimits) -> Self {
+        let mut profile_name = String::new();
+        if profile_name.push_str(name).is_err() {
+            error!("Profile name {} too long", name);
+        }
+
+        Self {
+            name: profile_name,
+            limits,
+            sensation: 50,
+            pattern: 0,
+            pin: None,
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.pin.is_some()
+    }
+
+    fn check_pin(&self, pin: Option<u32>) -> Result<(), ProfileError> {
+        match self.pin {
+            Some(expected) if pin != Some(expected) => Err(ProfileError::WrongPin),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProfileError {
+    InvalidIndex,
+    WrongPin,
+    // Profiles can only be switched while the motion is disabled
+    MotionEnabled,
+    NameTooLong,
+    // Quotes, backslashes and control characters would break the profiles JSON
+    InvalidName,
+}
+
+struct ProfileLimitsStorage {
+    velocity: AtomicU32,
+    depth: AtomicU32,
+    torque: AtomicU32,
+}
+
+// Kept separately from the profile list to allow lock-free reads from the setters
+static ACTIVE_LIMITS: ProfileLimitsStorage = ProfileLimitsStorage {
+    velocity: AtomicU32::new(100),
+    depth: AtomicU32::new(100),
+    torque: AtomicU32::new(100),
+};
+static ACTIVE_PROFILE: AtomicUsize = AtomicUsize::new(0);
+
+static PROFILES: Mutex<RefCell<Vec<Profile, MAX_PROFILES>>> = Mutex::new(RefCell::new(Vec::new()));
+
+/// Run a function with the list of profiles populating the defaults on first use
+fn with_profiles<R>(f: impl FnOnce(&mut Vec<Profile, MAX_PROFILES>) -> R) -> R {
+    critical_section::with(|cs| {
+        let mut profiles = PROFILES.borrow_ref_mut(cs);
+        if profiles.is_empty() {
+            let defaults = [
+                Profile::new("Default", ProfileLimits::unrestricted()),
+                Profile::new(
+                    "Guest",
+                    ProfileLimits {
+                        velocity: 50,
+                        depth: 50,
+                        torque: 50,
+                    },
+                ),
+            ];
+            for profile in defaults {
+                if profiles.push(profile).is_err() {
+                    break;
+                }
+            }
+        }
+        f(&mut profiles)
+    })
+}
+
+/// Get the limits of the currently active profile, lowered by the session limits
+pub fn get_active_limits() -> ProfileLimits {
+    let session_limits = get_session_limits();
+    ProfileLimits {
+        velocity: ACTIVE_LIMITS
+            .velocity
+            .load(Ordering::Acquire)
+            .min(session_limits.velocity),
+        depth: ACTIVE_LIMITS
+            .depth
+            .load(Ordering::Acquire)
+            .min(session_limits.depth_pct()),
+        torque: ACTIVE_LIMITS.torque.load(Ordering::Acquire),
+    }
+}
+
+/// Get the index of the currently active profile
+pub fn get_active_profile() -> usize {
+    ACTIVE_PROFILE.load(Ordering::Acquire)
+}
+
+fn store_active_limits(limits: ProfileLimits) {
+    ACTIVE_LIMITS
+        .velocity
+        .store(limits.velocity, Ordering::Release);
+    ACTIVE_LIMITS.depth.store(limits.depth, Ordering::Release);
+    ACTIVE_LIMITS.torque.store(limits.torque, Ordering::Release);
+}
+
+/// Re-apply the current motion state so that it is clamped by the new limits
+pub(crate) fn reapply_limits() {
+    let motion_state = get_motion_state();
+    // Being clamped by the new limits is expected here
+    set_motion_velocity_pct(motion_state.velocity).ok();
+    set_motion_depth_pct(motion_state.depth).ok();
+}
+
+/// Select the profile at the given index. The PIN is required if the profile has one
+pub fn select_profile(index: usize, pin: Option<u32>) -> Result<(), ProfileError> {
+    if get_motion_state().motion_enabled {
+        return Err(ProfileError::MotionEnabled);
+    }
+
+    let profile = with_profiles(|profiles| {
+        let profile = profiles.get(index).ok_or(ProfileError::InvalidIndex)?;
+        profile.check_pin(pin)?;
+        Ok(profile.clone())
+    })?;
+
+    ACTIVE_PROFILE.store(index, Ordering::Release);
+    store_active_limits(profile.limits);
+
+    set_motion_sensation_pct(profile.sensation).ok();
+    set_motion_pattern(profile.pattern).ok();
+    reapply_limits();
+
+    info!("Profile {} selected", profile.name);
+
+    Ok(())
+}
+
+/// Set the limits of a profile. The PIN is required if the profile has one
+pub fn set_profile_limits(
+    index: usize,
+    limits: ProfileLimits,
+    pin: Option<u32>,
+) -> Result<(), ProfileError> {
+    let limits = limits.saturated();
+
+    with_profiles(|profiles| {
+        let profile = profiles.get_mut(index).ok_or(ProfileError::InvalidIndex)?;
+        profile.check_pin(pin)?;
+        profile.limits = limits;
+        Ok(())
+    })?;
+
+    if index == get_active_profile() {
+        store_active_limits(limits);
+        reapply_limits();
+    }
+
+    Ok(())
+}
+
+/// Set the preferences applied when a profile is selected
+pub fn set_profile_preferences(
+    index: usize,
+    sensation: u32,
+    pattern: u32,
+    pin: Option<u32>,
+) -> Result<(), ProfileError> {
+    with_profiles(|profiles| {
+        let profile = profiles.get_mut(index).ok_or(ProfileError::InvalidIndex)?;
+        profile.check_pin(pin)?;
+        profile.sensation = sensation.min(100);
+        profile.pattern = pattern;
+        Ok(())
+    })
+}
+
+/// Set or clear (with None) the PIN of a profile. The current PIN is required if set
+pub fn set_profile_pin(
+    index: usize,
+    new_pin: Option<u32>,
+    pin: Option<u32>,
+) -> Result<(), ProfileError> {
+    with_profiles(|profiles| {
+        let profile = profiles.get_mut(index).ok_or(ProfileError::InvalidIndex)?;
+        profile.check_pin(pin)?;
+        profile.pin = new_pin;
+        Ok(())
+    })
+}
+
+/// Rename a profile or create a new one if the index is one past the last profile
+/// Creating one requires the PIN of the active profile. The new profile gets its limits and PIN
+/// so that it cannot be used to get around them
+pub fn set_profile_name(index: usize, name: &str, pin: Option<u32>) -> Result<(), ProfileError> {
+    if name
+        .chars()
+        .any(|c| c == '"' || c == '\\' || c.is_control())
+    {
+        return Err(ProfileError::InvalidName);
+    }
+
+    let mut new_name: String<MAX_PROFILE_NAME_LENGTH> = String::new();
+    new_name
+        .push_str(name)
+        .map_err(|_| ProfileError::NameTooLong)?;
+
+    with_profiles(|profiles| {
+        if index == profiles.len() {
+            let active = profiles
+                .get(get_active_profile())
+                .ok_or(ProfileError::InvalidIndex)?;
+            active.check_pin(pin)?;
+
+            let mut profile = Profile::new("", active.limits);
+            profile.name = new_name;
+            profile.pin = active.pin;
+            return profiles
+                .push(profile)
+                .map_err(|_| ProfileError::InvalidIndex);
+        }
+
+        let profile = profiles.get_mut(index).ok_or(ProfileError::InvalidIndex)?;
+        profile.check_pin(pin)?;
+        profile.name = new_name;
+        Ok(())
+    })
+}
+
+/// All profiles, e.g. to store them
+pub fn get_profiles() -> Vec<Profile, MAX_PROFILES> {
+    with_profiles(|profiles| profiles.clone())
+}
+
+/// Replace all profiles and activate the one at `active`, e.g. with the stored ones at boot
+/// The defaults are kept if there are none. Only the limits of the active profile are applied
+pub fn restore_profiles(new_profiles: &[Profile], active: usize) {
+    if new_profiles.is_empty() {
+        return;
+    }
+
+    let active = if active < new_profiles.len().min(MAX_PROFILES) {
+        active
+    } else {
+        error!("Stored active profile {} does not exist", active);
+        0
+    };
+    let limits = with_profiles(|profiles| {
+        profiles.clear();
+        for profile in new_profiles.iter().take(MAX_PROFILES) {
+            profiles
+                .push(profile.clone())
+                .expect("Limited to MAX_PROFILES");
+        }
+        profiles[active].limits.saturated()
+    });
+
+    ACTIVE_PROFILE.store(active, Ordering::Release);
+    store_active_limits(limits);
+    reapply_limits();
+    info!("Restored {} profiles", new_profiles.len());
+}
+
+/// Returns all profiles as json
+pub fn get_all_profiles_json() -> String<MAX_PROFILES_LENGTH> {
+    let active = get_active_profile();
+    let mut output = String::new();
+
+    with_profiles(|profiles| {
+        output.write_char('[').ok();
+        for (i, profile) in profiles.iter().enumerate() {
+            if write!(
+                output,
+                r#"{{"name":"{}","idx":{i},"active":{},"locked":{},"speed":{},"depth":{},"torque":{}}},"#,
+                profile.name,
+                i == active,
+                profile.is_locked(),
+                profile.limits.velocity,
+                profile.limits.depth,
+                profile.limits.torque,
+            )
+            .is_err()
+            {
+                error!("Profiles too long. Returning unfinished string");
+                break;
+            }
+        }
+    });
+    // Remove the last comma
+    if output.ends_with(',') {
+        output.pop();
+    }
+
+    if output.write_char(']').is_err() {
+        error!("Profiles too long. Returning unfinished string");
+    }
+
+    output
+}
@@ -0,0 +1,325 @@
+use core::{fmt::Write, sync::atomic::Ordering};
+
+use heapless::String;
+use log::{LevelFilter, error, info};
+use portable_atomic::{AtomicBool, AtomicU64};
+
+use crate::{
+    config::{
+        CALIBRATION_STALL_CURRENT_A, MAX_CALIBRATION_STALL_CURRENT_A, MAX_CONFIG_LENGTH,
+        MAX_HEARTBEAT_TIMEOUT_MS, MAX_NO_REMOTE_HEARTBEAT_MS, MAX_STATE_NOTIFY_INTERVAL_MS,
+        MIN_CALIBRATION_STALL_CURRENT_A, MIN_HEARTBEAT_TIMEOUT_MS, MIN_STATE_NOTIFY_INTERVAL_MS,
+        MOTION_CONTROL_MAX_ACCELERATION, MOTION_CONTROL_MAX_JERK, MOTION_CONTROL_MAX_VELOCITY,
+        MOTION_CONTROL_MIN_ACCELERATION, MOTION_CONTROL_MIN_JERK, MOTION_CONTROL_MIN_VELOCITY,
+        RETRACT_ON_MOTION_DISABLED, RETRACT_VELOCITY, STATE_NOTIFY_INTERVAL_MS,
+    },
+    float::{AtomicReal, Real},
+    motion::{
+        auto_off::{get_auto_off_min, set_auto_off_min},
+        sync::{SyncRole, get_sync_offset_ms, get_sync_role, set_sync_offset_ms, set_sync_role},
+    },
+    motion_control::{
+        Interpolation, get_ease_in_duration_s, get_interpolation, get_max_acceleration,
+        get_max_jerk, get_max_move_mm, get_min_move_mm, get_torque_slew_ms, is_direction_reversed,
+        mechanics::get_mechanics, set_ease_in_duration_s, set_interpolation, set_max_acceleration,
+        set_max_jerk, set_max_move_mm, set_min_move_mm, set_torque_slew_ms,
+    },
+    pattern::dwell::{get_dwell, set_dwell_depth_ms, set_dwell_retract_ms},
+    thermal::{THERMAL_KEYS, get_thermal_limits, set_thermal_value},
+    utils::saturate_range,
+    validation::{ValueError, check_accepted},
+};
+
+// The tunables without a home elsewhere. Start out as set in `config`
+static RETRACT_ON_DISABLE: AtomicBool = AtomicBool::new(RETRACT_ON_MOTION_DISABLED);
+static RETRACT_VELOCITY_MM_S: AtomicReal = AtomicReal::new(RETRACT_VELOCITY);
+static HEARTBEAT_TIMEOUT_MS: AtomicU64 = AtomicU64::new(MAX_NO_REMOTE_HEARTBEAT_MS);
+static STATE_INTERVAL_MS: AtomicU64 = AtomicU64::new(STATE_NOTIFY_INTERVAL_MS);
+static STATE_ON_CHANGE: AtomicBool = AtomicBool::new(false);
+static STALL_CURRENT_A: AtomicReal = AtomicReal::new(CALIBRATION_STALL_CURRENT_A);
+
+/// Whether the machine retracts when the motion is disabled or just stops
+pub fn get_retract_on_disable() -> bool {
+    RETRACT_ON_DISABLE.load(Ordering::Acquire)
+}
+
+/// The velocity the machine retracts with in mm/s
+pub fn get_retract_velocity() -> Real {
+    RETRACT_VELOCITY_MM_S.load(Ordering::Acquire)
+}
+
+/// How long without a heartbeat until the remote is considered lost in ms
+pub fn get_heartbeat_timeout_ms() -> u64 {
+    HEARTBEAT_TIMEOUT_MS.load(Ordering::Acquire)
+}
+
+/// How often the BLE state is notified in ms, or at most how often if only on change
+pub fn get_state_interval_ms() -> u64 {
+    STATE_INTERVAL_MS.load(Ordering::Acquire)
+}
+
+/// Whether the BLE state is only notified when it changed
+pub fn get_state_on_change() -> bool {
+    STATE_ON_CHANGE.load(Ordering::Acquire)
+}
+
+/// The motor current in A the calibration stops at the end of the rail with
+pub fn get_stall_current_a() -> Real {
+    STALL_CURRENT_A.load(Ordering::Acquire)
+}
+
+/// Set a tunable by its key in the config JSON
+/// Unknown keys are rejected. Out of range values are clamped and applied
+pub fn set_config_value(key: &str, value: Real) -> Result<(), ValueError> {
+    match key {
+        "minPosition" => set_min_move_mm(value),
+        "maxPosition" => set_max_move_mm(value),
+        "interpolation" => set_interpolation_id(value),
+        "easeInSeconds" => set_ease_in_seconds(value),
+        "torqueSlewMs" => set_torque_slew(value),
+        "dwellDepthMs" => set_dwell(value, set_dwell_depth_ms),
+        "dwellRetractMs" => set_dwell(value, set_dwell_retract_ms),
+        "retractOnDisable" => set_retract_on_disable(value),
+        "retractVelocity" => set_retract_velocity(value),
+        "maxAcceleration" => set_acceleration(value),
+        "maxJerk" => set_jerk(value),
+        "heartbeatTimeoutMs" => set_heartbeat_timeout(value),
+        "logLevel" => set_log_level(value),
+        "stateIntervalMs" => set_state_interval(value),
+        "stateOnChange" => set_state_on_change(value),
+        "syncRole" => set_sync_role_id(value),
+        "syncOffsetMs" => set_sync_offset(value),
+        "autoOffMin" => set_auto_off(value),
+        "stallCurrentA" => set_stall_current(value),
+        key if THERMAL_KEYS.contains(&key) => set_thermal_value(key, value),
+        _ => Err(ValueError::Unknown),
+    }
+}
+
+fn set_ease_in_seconds(seconds: Real) -> Result<(), ValueError> {
+    if !seconds.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let duration = saturate_range(seconds, 0.0, u32::MAX as Real) as u32;
+    set_ease_in_duration_s(duration)?;
+    check_accepted(seconds as i64, duration as i64)
+}
+
+fn set_torque_slew(slew_ms: Real) -> Result<(), ValueError> {
+    if !slew_ms.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let slew = saturate_range(slew_ms, 0.0, u32::MAX as Real) as u32;
+    set_torque_slew_ms(slew)?;
+    check_accepted(slew_ms as i64, slew as i64)
+}
+
+fn set_dwell(dwell_ms: Real, setter: fn(u32) -> Result<(), ValueError>) -> Result<(), ValueError> {
+    if !dwell_ms.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let dwell = saturate_range(dwell_ms, 0.0, u32::MAX as Real) as u32;
+    setter(dwell)?;
+    check_accepted(dwell_ms as i64, dwell as i64)
+}
+
+/// 0 to stop where the machine is and 1 to retract
+fn set_retract_on_disable(retract: Real) -> Result<(), ValueError> {
+    if !retract.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let retract = match retract as u32 {
+        0 => false,
+        1 => true,
+        _ => return Err(ValueError::Unknown),
+    };
+    RETRACT_ON_DISABLE.store(retract, Ordering::Release);
+    Ok(())
+}
+
+fn set_retract_velocity(velocity: Real) -> Result<(), ValueError> {
+    if !velocity.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let accepted = saturate_range(
+        velocity,
+        MOTION_CONTROL_MIN_VELOCITY,
+        MOTION_CONTROL_MAX_VELOCITY,
+    );
+    info!("Retract velocity set to {} mm/s", accepted);
+    RETRACT_VELOCITY_MM_S.store(accepted, Ordering::Release);
+    check_accepted(velocity as i64, accepted as i64)
+}
+
+fn set_acceleration(acceleration: Real) -> Result<(), ValueError> {
+    if !acceleration.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let accepted = saturate_range(
+        acceleration,
+        MOTION_CONTROL_MIN_ACCELERATION,
+        MOTION_CONTROL_MAX_ACCELERATION,
+    );
+    set_max_acceleration(accepted);
+    check_accepted(acceleration as i64, accepted as i64)
+}
+
+fn set_jerk(jerk: Real) -> Result<(), ValueError> {
+    if !jerk.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let accepted = saturate_range(jerk, MOTION_CONTROL_MIN_JERK, MOTION_CONTROL_MAX_JERK);
+    set_max_jerk(accepted);
+    check_accepted(jerk as i64, accepted as i64)
+}
+
+fn set_heartbeat_timeout(timeout_ms: Real) -> Result<(), ValueError> {
+    if !timeout_ms.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let accepted = saturate_range(
+        timeout_ms,
+        MIN_HEARTBEAT_TIMEOUT_MS as Real,
+        MAX_HEARTBEAT_TIMEOUT_MS as Real,
+    ) as u64;
+    info!("Heartbeat timeout set to {} ms", accepted);
+    HEARTBEAT_TIMEOUT_MS.store(accepted, Ordering::Release);
+    check_accepted(timeout_ms as i64, accepted as i64)
+}
+
+fn set_state_interval(interval_ms: Real) -> Result<(), ValueError> {
+    if !interval_ms.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let accepted = saturate_range(
+        interval_ms,
+        MIN_STATE_NOTIFY_INTERVAL_MS as Real,
+        MAX_STATE_NOTIFY_INTERVAL_MS as Real,
+    ) as u64;
+    STATE_INTERVAL_MS.store(accepted, Ordering::Release);
+    check_accepted(interval_ms as i64, accepted as i64)
+}
+
+/// 0 to notify the state every interval and 1 to only notify changes
+fn set_state_on_change(on_change: Real) -> Result<(), ValueError> {
+    if !on_change.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let on_change = match on_change as u32 {
+        0 => false,
+        1 => true,
+        _ => return Err(ValueError::Unknown),
+    };
+    STATE_ON_CHANGE.store(on_change, Ordering::Release);
+    Ok(())
+}
+
+fn set_stall_current(current_a: Real) -> Result<(), ValueError> {
+    if !current_a.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let accepted = saturate_range(
+        current_a,
+        MIN_CALIBRATION_STALL_CURRENT_A,
+        MAX_CALIBRATION_STALL_CURRENT_A,
+    );
+    info!("Calibration stall current set to {} A", accepted);
+    STALL_CURRENT_A.store(accepted, Ordering::Release);
+    check_accepted(current_a as i64, accepted as i64)
+}
+
+/// 0 for off, 1 to lead and 2 to follow the other machines
+fn set_sync_role_id(id: Real) -> Result<(), ValueError> {
+    if !id.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let role = SyncRole::from_id(id as u32).ok_or(ValueError::Unknown)?;
+    set_sync_role(role);
+    Ok(())
+}
+
+fn set_sync_offset(offset_ms: Real) -> Result<(), ValueError> {
+    if !offset_ms.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let accepted = set_sync_offset_ms(saturate_range(offset_ms, 0.0, u64::MAX as Real) as u64);
+    check_accepted(offset_ms as i64, accepted as i64)
+}
+
+fn set_auto_off(minutes: Real) -> Result<(), ValueError> {
+    if !minutes.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let accepted = saturate_range(minutes, 0.0, u32::MAX as Real) as u32;
+    set_auto_off_min(accepted)?;
+    check_accepted(minutes as i64, accepted as i64)
+}
+
+/// 0 for ruckig and 1 for sinusoidal
+fn set_interpolation_id(id: Real) -> Result<(), ValueError> {
+    if !id.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let interpolation = Interpolation::from_id(id as u32).ok_or(ValueError::Unknown)?;
+    set_interpolation(interpolation);
+    Ok(())
+}
+
+/// 0 for off, then error, warn, info, debug and trace. Applies to the console and the streams
+fn set_log_level(level: Real) -> Result<(), ValueError> {
+    if !level.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let filter = LevelFilter::iter()
+        .nth(level as usize)
+        .ok_or(ValueError::Unknown)?;
+    log::set_max_level(filter);
+    info!("Log level set to {}", filter);
+    Ok(())
+}
+
+/// The current value of all the tunables
+/// Except for `stallCurrentA`, which does not fit into a BLE attribute with the others anymore
+pub fn get_config_json() -> String<MAX_CONFIG_LENGTH> {
+    let mut output = String::new();
+    let dwell = get_dwell();
+    let mechanics = get_mechanics();
+    let thermal = get_thermal_limits();
+
+    if write!(
+        output,
+        r#"{{"minPosition":{:.1},"maxPosition":{:.1},"interpolation":{},"easeInSeconds":{},"torqueSlewMs":{},"dwellDepthMs":{},"dwellRetractMs":{},"retractOnDisable":{},"retractVelocity":{:.1},"maxAcceleration":{:.0},"maxJerk":{:.0},"logLevel":{},"stateIntervalMs":{},"stateOnChange":{},"syncRole":{},"syncOffsetMs":{},"heartbeatTimeoutMs":{},"autoOffMin":{},"motorWarningC":{:.0},"motorCriticalC":{:.0},"mcuWarningC":{:.0},"mcuCriticalC":{:.0},"pulleyToothCount":{},"beltPitch":{:.2},"motorStepsPerRevolution":{},"reverseDirection":{}}}"#,
+        get_min_move_mm(),
+        get_max_move_mm(),
+        get_interpolation().id(),
+        get_ease_in_duration_s(),
+        get_torque_slew_ms(),
+        dwell.depth_ms,
+        dwell.retract_ms,
+        get_retract_on_disable() as u32,
+        get_retract_velocity(),
+        get_max_acceleration(),
+        get_max_jerk(),
+        log::max_level() as usize,
+        get_state_interval_ms(),
+        get_state_on_change() as u32,
+        get_sync_role() as u8,
+        get_sync_offset_ms(),
+        get_heartbeat_timeout_ms(),
+        get_auto_off_min(),
+        thermal.motor_warning_c,
+        thermal.motor_critical_c,
+        thermal.mcu_warning_c,
+        thermal.mcu_critical_c,
+        mechanics.pulley_tooth_count,
+        mechanics.belt_pitch,
+        mechanics.motor_steps_per_revolution,
+        is_direction_reversed() as u32
+    )
+    .is_err()
+    {
+        error!("Could not write the config. Too long");
+    }
+
+    output
+}
@@ -0,0 +1,55 @@
+use log::{error, info};
+
+use crate::{
+    float::Real,
+    motion::motion_state::{
+        get_motion_state, set_motion_depth_pct, set_motion_length_pct, set_motion_pattern,
+        set_motion_velocity_pct,
+    },
+    motion_control::{get_max_move_mm, get_min_move_mm, set_max_move_mm, set_min_move_mm},
+};
+
+/// The motion settings of the running session, restored at boot so that a power loss does not
+/// reset them. The motion stays disabled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Session {
+    pub pattern: u32,
+    // All in %
+    pub depth: u32,
+    pub stroke: u32,
+    pub speed: u32,
+    // The soft limits in mm from home
+    pub min_position: Real,
+    pub max_position: Real,
+}
+
+impl Session {
+    /// The settings in use right now
+    pub fn current() -> Self {
+        let state = get_motion_state();
+        Self {
+            pattern: state.pattern,
+            depth: state.depth,
+            stroke: state.motion_length,
+            speed: state.velocity,
+            min_position: get_min_move_mm(),
+            max_position: get_max_move_mm(),
+        }
+    }
+
+    /// Apply the settings after the calibrated travel was set
+    /// Soft limits that do not fit the travel anymore are clamped
+    pub fn restore(&self) {
+        // Before depth and stroke which are relative to them
+        set_min_move_mm(self.min_position).ok();
+        set_max_move_mm(self.max_position).ok();
+        set_motion_length_pct(self.stroke).ok();
+        set_motion_depth_pct(self.depth).ok();
+        set_motion_velocity_pct(self.speed).ok();
+        // An uploaded pattern is gone after a reboot. The default one is kept then
+        if set_motion_pattern(self.pattern).is_err() {
+            error!("Session has the unknown pattern {}", self.pattern);
+        }
+        info!("Restored the last session");
+    }
+}
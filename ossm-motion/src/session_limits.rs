@@ -0,0 +1,115 @@
+use core::{
+    cell::Cell,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use critical_section::Mutex;
+use log::info;
+
+use crate::{
+    float::{AtomicReal, Real},
+    motion_control::get_max_travel_mm,
+    profile::reapply_limits,
+};
+
+/// Caps below the limits of the machine and the profiles, e.g. for a guest
+/// Unlike a profile they can not be switched away from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SessionLimits {
+    // Maximum velocity in %
+    pub velocity: u32,
+    // Maximum depth in mm from the retracted end of the travel. Infinite if not capped
+    pub depth_mm: Real,
+}
+
+impl SessionLimits {
+    pub const UNRESTRICTED: Self = Self {
+        velocity: 100,
+        depth_mm: Real::INFINITY,
+    };
+
+    /// The maximum depth in % of the travel in use
+    pub fn depth_pct(&self) -> u32 {
+        // Saturates for an infinite depth
+        ((self.depth_mm / get_max_travel_mm() * 100.0) as u32).min(100)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SessionLimitsError {
+    WrongPin,
+    // The depth is negative or not a number
+    InvalidDepth,
+}
+
+// Atomics so that the limits can be read from the control loop
+static VELOCITY: AtomicU32 = AtomicU32::new(100);
+static DEPTH_MM: AtomicReal = AtomicReal::new(Real::INFINITY);
+// The limits can only be changed with this PIN if set
+static PIN: Mutex<Cell<Option<u32>>> = Mutex::new(Cell::new(None));
+
+/// The limits in use
+pub fn get_session_limits() -> SessionLimits {
+    SessionLimits {
+        velocity: VELOCITY.load(Ordering::Acquire),
+        depth_mm: DEPTH_MM.load(Ordering::Acquire),
+    }
+}
+
+/// The PIN required to change the limits, e.g. to store it
+pub fn get_session_limits_pin() -> Option<u32> {
+    critical_section::with(|cs| PIN.borrow(cs).get())
+}
+
+fn check_pin(pin: Option<u32>) -> Result<(), SessionLimitsError> {
+    match get_session_limits_pin() {
+        Some(expected) if pin != Some(expected) => Err(SessionLimitsError::WrongPin),
+        _ => Ok(()),
+    }
+}
+
+fn store_session_limits(limits: SessionLimits) -> Result<(), SessionLimitsError> {
+    if limits.depth_mm.is_nan() || limits.depth_mm < 0.0 {
+        return Err(SessionLimitsError::InvalidDepth);
+    }
+
+    VELOCITY.store(limits.velocity.min(100), Ordering::Release);
+    DEPTH_MM.store(limits.depth_mm, Ordering::Release);
+    // The current speed and depth are lowered right away
+    reapply_limits();
+    Ok(())
+}
+
+/// Set the limits. The PIN is required if one is set
+pub fn set_session_limits(
+    limits: SessionLimits,
+    pin: Option<u32>,
+) -> Result<(), SessionLimitsError> {
+    check_pin(pin)?;
+    store_session_limits(limits)?;
+    info!(
+        "Session limits set to {}% and {} mm",
+        limits.velocity, limits.depth_mm
+    );
+    Ok(())
+}
+
+/// Set or clear (with None) the PIN. The current PIN is required if set
+pub fn set_session_limits_pin(
+    new_pin: Option<u32>,
+    pin: Option<u32>,
+) -> Result<(), SessionLimitsError> {
+    check_pin(pin)?;
+    critical_section::with(|cs| PIN.borrow(cs).set(new_pin));
+    Ok(())
+}
+
+/// Apply the stored limits and PIN at boot without the PIN check
+pub fn restore_session_limits(
+    limits: SessionLimits,
+    pin: Option<u32>,
+) -> Result<(), SessionLimitsError> {
+    store_session_limits(limits)?;
+    critical_section::with(|cs| PIN.borrow(cs).set(pin));
+    Ok(())
+}
@@ -0,0 +1,254 @@
+use core::{cell::Cell, sync::atomic::Ordering};
+
+use critical_section::Mutex;
+use log::{error, info, warn};
+use portable_atomic::AtomicU8;
+
+use crate::{
+    config::{
+        MAX_THERMAL_THRESHOLD_C, MCU_CRITICAL_C, MCU_WARNING_C, MIN_THERMAL_THRESHOLD_C,
+        MOTOR_CRITICAL_C, MOTOR_WARNING_C, THERMAL_HYSTERESIS_C, THERMAL_WARNING_LIMIT_PCT,
+    },
+    fault::{FaultCode, raise_fault},
+    float::{AtomicReal, Real},
+    utils::saturate_range,
+    validation::{ValueError, check_accepted},
+};
+
+// The keys of the runtime config the thresholds are set with
+pub const THERMAL_KEYS: [&str; 4] = [
+    "motorWarningC",
+    "motorCriticalC",
+    "mcuWarningC",
+    "mcuCriticalC",
+];
+
+/// The temperatures in °C the motion is limited and stopped at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThermalLimits {
+    pub motor_warning_c: Real,
+    pub motor_critical_c: Real,
+    pub mcu_warning_c: Real,
+    pub mcu_critical_c: Real,
+}
+
+impl ThermalLimits {
+    // As set in `config`
+    pub const DEFAULT: Self = Self {
+        motor_warning_c: MOTOR_WARNING_C,
+        motor_critical_c: MOTOR_CRITICAL_C,
+        mcu_warning_c: MCU_WARNING_C,
+        mcu_critical_c: MCU_CRITICAL_C,
+    };
+
+    /// Change a threshold by its key in `THERMAL_KEYS`
+    /// Unknown keys are rejected. Out of range values are clamped and applied.
+    /// A warning at or above the critical threshold is rejected with the one in use as accepted
+    pub fn set_value(&mut self, key: &str, value: Real) -> Result<(), ValueError> {
+        if !value.is_finite() {
+            return Err(ValueError::NotANumber);
+        }
+
+        let mut limits = *self;
+        let threshold = match key {
+            "motorWarningC" => &mut limits.motor_warning_c,
+            "motorCriticalC" => &mut limits.motor_critical_c,
+            "mcuWarningC" => &mut limits.mcu_warning_c,
+            "mcuCriticalC" => &mut limits.mcu_critical_c,
+            _ => return Err(ValueError::Unknown),
+        };
+        let previous = *threshold;
+        *threshold = saturate_range(value, MIN_THERMAL_THRESHOLD_C, MAX_THERMAL_THRESHOLD_C);
+        let accepted = *threshold;
+        if !limits.is_ordered() {
+            return Err(ValueError::OutOfRange {
+                accepted: previous as i32,
+            });
+        }
+
+        *self = limits;
+        check_accepted(value as i64, accepted as i64)
+    }
+
+    fn is_valid(&self) -> bool {
+        [
+            self.motor_warning_c,
+            self.motor_critical_c,
+            self.mcu_warning_c,
+            self.mcu_critical_c,
+        ]
+        .iter()
+        .all(|threshold| (MIN_THERMAL_THRESHOLD_C..=MAX_THERMAL_THRESHOLD_C).contains(threshold))
+            && self.is_ordered()
+    }
+
+    // The warning would never cap the motion if it was not below the critical threshold
+    fn is_ordered(&self) -> bool {
+        self.motor_warning_c < self.motor_critical_c && self.mcu_warning_c < self.mcu_critical_c
+    }
+}
+
+/// Above `Warning` the control loop caps the velocity and the torque to
+/// `THERMAL_WARNING_LIMIT_PCT`. `Critical` stops the machine with the `overheat` fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThermalLevel {
+    Normal,
+    // The velocity and the torque are capped
+    Warning,
+    // The machine is stopped and can not be re-armed
+    Critical,
+}
+
+impl ThermalLevel {
+    pub fn name(self) -> &'static str {
+        match self {
+            ThermalLevel::Normal => "normal",
+            ThermalLevel::Warning => "warning",
+            ThermalLevel::Critical => "critical",
+        }
+    }
+
+    fn from_u8(level: u8) -> Self {
+        match level {
+            1 => ThermalLevel::Warning,
+            2 => ThermalLevel::Critical,
+            _ => ThermalLevel::Normal,
+        }
+    }
+}
+
+/// A temperature and the level it was last found at
+struct Sensor {
+    name: &'static str,
+    // NaN until read
+    temperature_c: AtomicReal,
+    level: AtomicU8,
+}
+
+impl Sensor {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            temperature_c: AtomicReal::new(Real::NAN),
+            level: AtomicU8::new(ThermalLevel::Normal as u8),
+        }
+    }
+
+    fn temperature_c(&self) -> Option<Real> {
+        let temperature = self.temperature_c.load(Ordering::Acquire);
+        (!temperature.is_nan()).then_some(temperature)
+    }
+
+    fn level(&self) -> ThermalLevel {
+        ThermalLevel::from_u8(self.level.load(Ordering::Acquire))
+    }
+
+    fn update(&self, temperature: Real, warning: Real, critical: Real) {
+        self.temperature_c.store(temperature, Ordering::Release);
+
+        let previous = self.level();
+        // A level is only left once the temperature dropped THERMAL_HYSTERESIS_C below it
+        let reached = |level: ThermalLevel, threshold: Real| {
+            temperature >= threshold
+                || (previous >= level && temperature > threshold - THERMAL_HYSTERESIS_C)
+        };
+        let level = if reached(ThermalLevel::Critical, critical) {
+            ThermalLevel::Critical
+        } else if reached(ThermalLevel::Warning, warning) {
+            ThermalLevel::Warning
+        } else {
+            ThermalLevel::Normal
+        };
+        if self.level.swap(level as u8, Ordering::AcqRel) == level as u8 {
+            return;
+        }
+
+        match level {
+            ThermalLevel::Critical => {
+                error!(
+                    "The {} is at {}°C above {}°C. Stopping",
+                    self.name, temperature, critical
+                );
+                raise_fault(FaultCode::Overheat);
+            }
+            ThermalLevel::Warning => warn!(
+                "The {} is at {}°C. Limiting the velocity and the torque to {}%",
+                self.name, temperature, THERMAL_WARNING_LIMIT_PCT
+            ),
+            ThermalLevel::Normal => info!("The {} cooled down to {}°C", self.name, temperature),
+        }
+    }
+}
+
+static LIMITS: Mutex<Cell<ThermalLimits>> = Mutex::new(Cell::new(ThermalLimits::DEFAULT));
+static MOTOR: Sensor = Sensor::new("motor");
+static MCU: Sensor = Sensor::new("MCU");
+
+/// The thresholds in use
+pub fn get_thermal_limits() -> ThermalLimits {
+    critical_section::with(|cs| LIMITS.borrow(cs).get())
+}
+
+/// Use other thresholds. Applied with the next temperature read
+/// Thresholds out of the accepted range are rejected and the ones in use kept
+pub fn set_thermal_limits(limits: ThermalLimits) -> Result<(), ValueError> {
+    if !limits.is_valid() {
+        return Err(ValueError::Unknown);
+    }
+
+    info!(
+        "Thermal limits set to {}/{}°C for the motor and {}/{}°C for the MCU",
+        limits.motor_warning_c,
+        limits.motor_critical_c,
+        limits.mcu_warning_c,
+        limits.mcu_critical_c
+    );
+    critical_section::with(|cs| LIMITS.borrow(cs).set(limits));
+    Ok(())
+}
+
+/// Set a threshold by its key in `THERMAL_KEYS`
+pub(crate) fn set_thermal_value(key: &str, value: Real) -> Result<(), ValueError> {
+    let mut limits = get_thermal_limits();
+    let result = limits.set_value(key, value);
+    if let Err(ValueError::NotANumber | ValueError::Unknown) = result {
+        return result;
+    }
+    set_thermal_limits(limits)?;
+    result
+}
+
+/// Called by the control loop with the temperature the motor reports
+pub fn set_motor_temperature_c(temperature: Real) {
+    let limits = get_thermal_limits();
+    MOTOR.update(temperature, limits.motor_warning_c, limits.motor_critical_c);
+}
+
+/// Called by the firmware with the temperature of the internal sensor
+pub fn set_mcu_temperature_c(temperature: Real) {
+    let limits = get_thermal_limits();
+    MCU.update(temperature, limits.mcu_warning_c, limits.mcu_critical_c);
+}
+
+/// The last temperature of the motor in °C. None if it was never read
+pub fn get_motor_temperature_c() -> Option<Real> {
+    MOTOR.temperature_c()
+}
+
+/// The last temperature of the MCU in °C. None if it was never read
+pub fn get_mcu_temperature_c() -> Option<Real> {
+    MCU.temperature_c()
+}
+
+/// The higher of the levels of the motor and the MCU
+pub fn get_thermal_level() -> ThermalLevel {
+    MOTOR.level().max(MCU.level())
+}
+
+/// What the velocity and the torque are capped to in % of their maximum
+pub fn get_thermal_limit_pct() -> Real {
+    match get_thermal_level() {
+        ThermalLevel::Normal => 100.0,
+        ThermalLevel::Warning | ThermalLevel::Critical => THERMAL_WARNING_LIMIT_PCT,
+    }
+}
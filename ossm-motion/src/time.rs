@@ -0,0 +1,81 @@
+use core::sync::atomic::Ordering;
+
+use embassy_time::{Duration, Instant};
+use portable_atomic::AtomicU64;
+
+use crate::motion_control::timer::{TimerDuration, TimerInstant};
+
+// Ticks of a timestamp that was never set
+// Not reachable in practice as u64 microseconds last for more than 500000 years
+const NEVER: u64 = u64::MAX;
+
+/// Time elapsed from `since` until `now` for the motion control timer
+/// Zero instead of a panic if `since` is later than `now`
+pub fn timer_elapsed(since: TimerInstant, now: TimerInstant) -> TimerDuration {
+    now.checked_duration_since(since)
+        .unwrap_or(TimerDuration::from_ticks(0))
+}
+
+/// Time elapsed from `since` until `now`
+/// Zero instead of a panic if `since` is later than `now`
+pub fn elapsed_between(since: Instant, now: Instant) -> Duration {
+    now.saturating_duration_since(since)
+}
+
+/// An instant that can be shared between tasks
+/// Starts out unset, so that an event that never happened is not mistaken
+/// for one that happened right at boot
+pub struct AtomicTimestamp {
+    ticks: AtomicU64,
+}
+
+impl AtomicTimestamp {
+    pub const fn never() -> Self {
+        Self {
+            ticks: AtomicU64::new(NEVER),
+        }
+    }
+
+    pub fn store(&self, instant: Instant) {
+        // Keep the sentinel reserved
+        let ticks = instant.as_ticks().min(NEVER - 1);
+        self.ticks.store(ticks, Ordering::Release);
+    }
+
+    pub fn store_now(&self) {
+        self.store(Instant::now());
+    }
+
+    pub fn clear(&self) {
+        self.ticks.store(NEVER, Ordering::Release);
+    }
+
+    /// Returns None if the timestamp was never set
+    pub fn load(&self) -> Option<Instant> {
+        match self.ticks.load(Ordering::Acquire) {
+            NEVER => None,
+            ticks => Some(Instant::from_ticks(ticks)),
+        }
+    }
+
+    /// Time elapsed since the timestamp until `now`. None if it was never set
+    pub fn elapsed_at(&self, now: Instant) -> Option<Duration> {
+        self.load().map(|instant| elapsed_between(instant, now))
+    }
+
+    /// Time elapsed since the timestamp. None if it was never set
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.elapsed_at(Instant::now())
+    }
+
+    /// Whether the timestamp was set no longer than `timeout` before `now`
+    pub fn is_within_at(&self, timeout: Duration, now: Instant) -> bool {
+        self.elapsed_at(now)
+            .is_some_and(|elapsed| elapsed <= timeout)
+    }
+
+    /// Whether the timestamp was set no longer than `timeout` ago
+    pub fn is_within(&self, timeout: Duration) -> bool {
+        self.is_within_at(timeout, Instant::now())
+    }
+}
@@ -1,15 +1,19 @@
+pub mod rng;
+
+use crate::float::Real;
+
 pub fn scale(
-    input: f64,
-    input_start: f64,
-    input_end: f64,
-    output_start: f64,
-    output_end: f64,
-) -> f64 {
+    input: Real,
+    input_start: Real,
+    input_end: Real,
+    output_start: Real,
+    output_end: Real,
+) -> Real {
     let slope = (output_end - output_start) / (input_end - input_start);
     output_start + slope * (input - input_start)
 }
 
-pub fn saturate_range(input: f64, min: f64, max: f64) -> f64 {
+pub fn saturate_range(input: Real, min: Real, max: Real) -> Real {
     let mut output = input;
 
     if output < min {
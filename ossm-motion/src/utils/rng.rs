@@ -0,0 +1,91 @@
+use core::{cell::RefCell, sync::atomic::Ordering};
+
+use critical_section::Mutex;
+use portable_atomic::AtomicU32;
+
+use crate::float::Real;
+
+// Xorshift never leaves 0. Used instead of a seed of 0
+const FALLBACK_SEED: u32 = 0x2545_f491;
+// The stream of the generator behind `rand_range` and friends
+const SHARED_STREAM: u32 = 0x5348_4152;
+
+// Set by the caller. Fixed until then so that the tests are repeatable
+static SEED: AtomicU32 = AtomicU32::new(0);
+static SHARED: Mutex<RefCell<Rng>> =
+    Mutex::new(RefCell::new(Rng::new(stream_seed(0, SHARED_STREAM))));
+
+/// Seed all the generators created with `Rng::seeded` from now on and the shared one
+/// Call once at startup before the motion is started
+pub fn seed_rng(seed: u32) {
+    SEED.store(seed, Ordering::Release);
+    critical_section::with(|cs| *SHARED.borrow_ref_mut(cs) = Rng::seeded(SHARED_STREAM));
+}
+
+/// Murmur3 finalizer. Spreads every bit of the input over the output
+const fn mix(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x85eb_ca6b);
+    x ^= x >> 13;
+    x = x.wrapping_mul(0xc2b2_ae35);
+    x ^ (x >> 16)
+}
+
+const fn stream_seed(seed: u32, stream: u32) -> u32 {
+    mix(seed ^ mix(stream))
+}
+
+/// Xorshift32. Good enough to make a pattern less predictable, not for anything security related
+pub struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    pub const fn new(seed: u32) -> Self {
+        Self {
+            state: if seed == 0 { FALLBACK_SEED } else { seed },
+        }
+    }
+
+    /// Seeded from the seed set with `seed_rng`
+    /// Different `stream`s give different sequences for the same seed
+    pub fn seeded(stream: u32) -> Self {
+        Self::new(stream_seed(SEED.load(Ordering::Acquire), stream))
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    /// Uniformly distributed from `min` up to but excluding `max`
+    pub fn range(&mut self, min: Real, max: Real) -> Real {
+        // 24 bits are exact in f32 too so that `max` is never reached
+        let unit = (self.next_u32() >> 8) as Real / (1u32 << 24) as Real;
+        min + (max - min) * unit
+    }
+
+    /// True with a probability from 0 to 1
+    pub fn chance(&mut self, probability: Real) -> bool {
+        self.range(0.0, 1.0) < probability
+    }
+}
+
+/// A random number from the shared generator
+pub fn rand_u32() -> u32 {
+    critical_section::with(|cs| SHARED.borrow_ref_mut(cs).next_u32())
+}
+
+/// Uniformly distributed from `min` up to but excluding `max` from the shared generator
+pub fn rand_range(min: Real, max: Real) -> Real {
+    critical_section::with(|cs| SHARED.borrow_ref_mut(cs).range(min, max))
+}
+
+/// True with a probability from 0 to 1 from the shared generator
+pub fn rand_chance(probability: Real) -> bool {
+    critical_section::with(|cs| SHARED.borrow_ref_mut(cs).chance(probability))
+}
@@ -0,0 +1,79 @@
+use core::fmt::{self, Display};
+
+/// Why a value sent by a remote was not applied as is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueError {
+    // The value was outside of the allowed range
+    // The clamped value was applied instead. In the units of the setter that was called
+    OutOfRange { accepted: i32 },
+    // The value is not a finite number. Nothing was applied
+    NotANumber,
+    // The value does not identify anything the machine knows. Nothing was applied
+    Unknown,
+}
+
+impl ValueError {
+    /// Convert the accepted value to different units
+    pub fn map_accepted(self, f: impl FnOnce(i32) -> i32) -> Self {
+        match self {
+            ValueError::OutOfRange { accepted } => ValueError::OutOfRange {
+                accepted: f(accepted),
+            },
+            ValueError::NotANumber => ValueError::NotANumber,
+            ValueError::Unknown => ValueError::Unknown,
+        }
+    }
+}
+
+/// Formatted as `<reason>[:<accepted value>]` to be appended to command responses
+impl Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueError::OutOfRange { accepted } => write!(f, "out_of_range:{accepted}"),
+            ValueError::NotANumber => write!(f, "not_a_number"),
+            ValueError::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Report if the value that was applied differs from the requested one
+pub fn check_accepted(requested: i64, accepted: i64) -> Result<(), ValueError> {
+    if requested == accepted {
+        Ok(())
+    } else {
+        Err(ValueError::OutOfRange {
+            accepted: accepted as i32,
+        })
+    }
+}
+
+/// Clamp a value in % to 0-100 and the given limit
+/// Returns the value to apply and whether it had to be clamped
+pub fn validate_pct(value: u32, limit: u32) -> (u32, Result<(), ValueError>) {
+    let accepted = value.min(100).min(limit);
+    (accepted, check_accepted(value as i64, accepted as i64))
+}
+
+/// Convert a float sent by a remote to an integer
+/// Negative values are reported as out of range with 0 as the value to apply instead
+pub fn remote_value_to_u32(value: f32) -> Result<u32, ValueError> {
+    if !value.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    if value < 0.0 {
+        return Err(ValueError::OutOfRange { accepted: 0 });
+    }
+
+    // The cast saturates at u32::MAX
+    Ok(value as u32)
+}
+
+/// Convert a signed float sent by a remote to an integer
+pub fn remote_value_to_i32(value: f32) -> Result<i32, ValueError> {
+    if !value.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+
+    // The cast saturates at the i32 bounds
+    Ok(value as i32)
+}
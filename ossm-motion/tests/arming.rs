@@ -0,0 +1,73 @@
+mod common;
+
+use embassy_time::{Duration, Instant};
+use ossm_motion::{
+    motion::{
+        demo::start_demo,
+        motion_state::{get_motion_state, set_motion_enabled},
+        stream::{StreamError, stream_target_at},
+    },
+    motion_control::arming::{
+        ArmError, arm_at, arm_button_pressed_at, disarm, is_armed, set_arm_button_required,
+    },
+};
+
+use common::lock;
+
+#[test]
+fn motion_is_rejected_until_armed() {
+    let _lock = lock();
+    disarm();
+
+    set_motion_enabled(true);
+    assert!(!get_motion_state().motion_enabled);
+    assert!(
+        get_motion_state()
+            .as_json()
+            .contains(r#""state":"disarmed","#)
+    );
+    assert!(!start_demo());
+    assert_eq!(
+        stream_target_at(10.0, 100, Instant::from_secs(1)).err(),
+        Some(StreamError::NotArmed)
+    );
+
+    arm_at(Instant::from_ticks(0)).unwrap();
+    assert!(is_armed());
+    set_motion_enabled(true);
+    assert!(get_motion_state().motion_enabled);
+
+    // Disarming does not turn the motion off by itself. The motion task does
+    set_motion_enabled(false);
+    disarm();
+    assert!(!is_armed());
+}
+
+#[test]
+fn arming_can_require_the_button() {
+    let _lock = lock();
+    disarm();
+    set_arm_button_required(true);
+
+    assert_eq!(
+        arm_at(Instant::from_secs(10)),
+        Err(ArmError::ButtonNotPressed)
+    );
+    arm_button_pressed_at(Instant::from_secs(10));
+    arm_at(Instant::from_secs(15)).unwrap();
+    assert!(is_armed());
+
+    // A press arms once
+    disarm();
+    assert_eq!(
+        arm_at(Instant::from_secs(16)),
+        Err(ArmError::ButtonNotPressed)
+    );
+    // And only for a while
+    arm_button_pressed_at(Instant::from_secs(20));
+    let late = Instant::from_secs(20) + Duration::from_secs(11);
+    assert_eq!(arm_at(late), Err(ArmError::ButtonNotPressed));
+    assert!(!is_armed());
+
+    set_arm_button_required(false);
+}
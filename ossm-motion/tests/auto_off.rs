@@ -0,0 +1,73 @@
+mod common;
+
+use embassy_time::Instant;
+use ossm_motion::{
+    config::MAX_AUTO_OFF_MIN,
+    event::{Event, get_event_sequence, next_event},
+    motion::{
+        auto_off::{AutoOffRunner, set_auto_off_min},
+        motion_state::{get_motion_state, set_motion_enabled},
+    },
+    motion_control::arming::arm_at,
+    pattern::PATTERN_ID_SIMPLE,
+    runtime_config::{get_config_json, set_config_value},
+    validation::ValueError,
+};
+
+use common::{lock, motion_state};
+
+#[test]
+fn auto_off_disables_the_motion_after_running_without_a_break() {
+    let _lock = lock();
+    arm_at(Instant::from_ticks(0)).unwrap();
+    set_config_value("autoOffMin", 1.0).unwrap();
+    set_motion_enabled(true);
+    let sequence = get_event_sequence();
+
+    let mut runner = AutoOffRunner::new();
+    let mut apply = |motion_enabled: bool, at_s: u64| {
+        let mut state = motion_state(PATTERN_ID_SIMPLE, motion_enabled);
+        let timed_out = runner.apply(&mut state, Instant::from_secs(at_s));
+        assert_eq!(state.motion_enabled, motion_enabled && !timed_out);
+        timed_out
+    };
+
+    assert!(!apply(true, 0));
+    assert!(!apply(true, 40));
+    // A break restarts the time
+    assert!(!apply(false, 50));
+    assert!(!apply(true, 60));
+    assert!(!apply(true, 110));
+    // So does setting it again
+    set_auto_off_min(1).unwrap();
+    assert!(!apply(true, 115));
+    assert!(!apply(true, 170));
+    assert!(apply(true, 176));
+    assert!(!get_motion_state().motion_enabled);
+    assert_eq!(
+        next_event(sequence).map(|(event, _)| event),
+        Some(Event::AutoOff { minutes: 1 })
+    );
+
+    // Never with 0
+    set_auto_off_min(0).unwrap();
+    assert!(!apply(true, 200));
+    assert!(!apply(true, 100_000));
+}
+
+#[test]
+fn auto_off_is_a_runtime_config_value() {
+    let _lock = lock();
+    assert_eq!(
+        set_config_value("autoOffMin", 1000.0),
+        Err(ValueError::OutOfRange {
+            accepted: MAX_AUTO_OFF_MIN as i32
+        })
+    );
+    let config = get_config_json();
+    assert!(config.contains(r#""autoOffMin":240,"#), "{config}");
+    assert_eq!(
+        set_config_value("autoOffMin", -1.0),
+        Err(ValueError::OutOfRange { accepted: 0 })
+    );
+}
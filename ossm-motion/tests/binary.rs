@@ -0,0 +1,71 @@
+use embassy_time::Instant;
+use ossm_motion::{
+    binary::{
+        BinaryCommand, BinaryState, GoAction, RunState, SetKey, decode_command, encode_response,
+    },
+    config::MAX_COMMAND_LENGTH,
+    motion::motion_state::{get_motion_state, set_motion_pattern},
+    motion_control::arming::arm_at,
+};
+
+fn encode(command: &BinaryCommand) -> Vec<u8> {
+    let mut buffer = [0u8; 128];
+    postcard::to_slice(command, &mut buffer).unwrap().to_vec()
+}
+
+#[test]
+fn state_decodes_to_the_values_of_the_json() {
+    arm_at(Instant::from_ticks(0)).unwrap();
+    set_motion_pattern(3).unwrap();
+    let motion_state = get_motion_state();
+    let bytes = motion_state.as_binary();
+    let state: BinaryState = postcard::from_bytes(&bytes).unwrap();
+
+    assert_eq!(state.state, RunState::Menu);
+    assert_eq!(state.pattern, 3);
+    assert_eq!(state.sensation, motion_state.sensation);
+    assert_eq!(state.control, motion_state.control);
+    assert_eq!(state.fault, None);
+    // Much smaller than the JSON
+    assert!(bytes.len() < 40, "{} bytes", bytes.len());
+    assert!(
+        motion_state
+            .as_json()
+            .contains(r#""state":"menu","depth":"#)
+    );
+}
+
+#[test]
+fn commands_decode_to_the_text_commands() {
+    let set = encode(&BinaryCommand::Set(SetKey::Speed, 42));
+    assert_eq!(set, [0, 0, 42]);
+    let command = decode_command(&set).unwrap();
+    assert_eq!(command.to_text().unwrap().as_str(), "set:speed:42");
+
+    let go = encode(&BinaryCommand::Go(GoAction::StrokeEngine));
+    let command = decode_command(&go).unwrap();
+    assert_eq!(command.to_text().unwrap().as_str(), "go:strokeEngine");
+
+    let mut text = encode(&BinaryCommand::Text("set:param:1:2.5"));
+    // Bytes added by a newer remote are ignored
+    text.push(7);
+    let command = decode_command(&text).unwrap();
+    assert_eq!(command.to_text().unwrap().as_str(), "set:param:1:2.5");
+
+    let response = encode_response("ok:set:speed:42");
+    assert_eq!(
+        postcard::from_bytes::<&str>(&response),
+        Ok("ok:set:speed:42")
+    );
+}
+
+#[test]
+fn invalid_commands_are_rejected() {
+    assert!(decode_command(&[]).is_err());
+    // No such variant
+    assert!(decode_command(&[9]).is_err());
+    assert!(decode_command(&[1, 200]).is_err());
+
+    let long = "x".repeat(MAX_COMMAND_LENGTH + 1);
+    assert_eq!(BinaryCommand::Text(&long).to_text(), None);
+}
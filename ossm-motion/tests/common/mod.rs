@@ -0,0 +1,177 @@
+#![allow(dead_code)]
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    sync::{Mutex, MutexGuard},
+};
+
+use ossm_motion::{
+    config::MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS,
+    float::Real,
+    motion::motion_state::MotionState,
+    motion_control::{
+        self, MotionControl,
+        debug::DebugOut,
+        motor::Motor,
+        timer::{Duration, Instant, Timer},
+    },
+};
+
+// Moves longer than this are considered stuck
+const MAX_UPDATES: usize = 10_000;
+
+// Motion control keeps its state in statics
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Serialise the tests that use motion control
+pub fn lock() -> MutexGuard<'static, ()> {
+    // A failed test must not fail all the others
+    LOCK.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Half of everything to pass to the runners
+pub fn motion_state(pattern: u32, motion_enabled: bool) -> MotionState {
+    MotionState {
+        depth: 50,
+        motion_length: 50,
+        velocity: 50,
+        sensation: 50,
+        pattern,
+        bpm: 0,
+        motion_enabled,
+        load: 0,
+        position: 0.0,
+        velocity_mm_s: 0.0,
+        ease_in: 100,
+        control: "none",
+        torque: 100.0,
+        fault: None,
+        firmware_version: "test",
+    }
+}
+
+/// Only advances when told to
+#[derive(Clone, Default)]
+pub struct FakeTimer {
+    now_us: Rc<Cell<u64>>,
+}
+
+impl FakeTimer {
+    pub fn advance_ms(&self, ms: u64) {
+        self.now_us.set(self.now_us.get() + ms * 1000);
+    }
+}
+
+impl Timer for FakeTimer {
+    fn now(&self) -> Instant {
+        Instant::from_ticks(self.now_us.get())
+    }
+}
+
+/// Records everything written to it
+#[derive(Default)]
+pub struct RecordingMotor {
+    pub steps: Vec<i32>,
+    // In %
+    pub torques: Vec<Real>,
+    // Reported as the load in %. None like a motor that cannot report it
+    pub load: Option<Real>,
+    // Reported as the temperature in °C
+    pub temperature: Option<Real>,
+}
+
+impl Motor for RecordingMotor {
+    type MotorError = ();
+
+    fn min_consecutive_write_delay() -> Duration {
+        Duration::from_ticks(0)
+    }
+
+    fn set_absolute_position(&mut self, steps: i32) -> Result<(), Self::MotorError> {
+        self.steps.push(steps);
+        Ok(())
+    }
+
+    fn set_torque_pct(&mut self, torque: Real) -> Result<(), Self::MotorError> {
+        self.torques.push(torque);
+        Ok(())
+    }
+
+    fn get_load_pct(&mut self) -> Result<Option<Real>, Self::MotorError> {
+        Ok(self.load)
+    }
+
+    fn get_temperature_c(&mut self) -> Result<Option<Real>, Self::MotorError> {
+        Ok(self.temperature)
+    }
+
+    fn delay(&mut self, _duration: Duration) {}
+}
+
+/// Records the trajectory motion control reports every update
+#[derive(Clone, Default)]
+pub struct Recorder {
+    pub positions: Rc<RefCell<Vec<Real>>>,
+    pub velocities: Rc<RefCell<Vec<Real>>>,
+}
+
+impl DebugOut for Recorder {
+    fn new_position(&mut self, position: Real) {
+        self.positions.borrow_mut().push(position);
+    }
+
+    fn new_velocity(&mut self, velocity: Real) {
+        self.velocities.borrow_mut().push(velocity);
+    }
+
+    fn new_acceleration(&mut self, _acceleration: Real) {}
+
+    fn new_jerk(&mut self, _jerk: Real) {}
+}
+
+/// Runs `MotionControl` on the host with a deterministic timer and a recording motor
+pub struct Harness {
+    pub motion_control: MotionControl<RecordingMotor, FakeTimer, Recorder>,
+    pub timer: FakeTimer,
+    pub recorder: Recorder,
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        let timer = FakeTimer::default();
+        let recorder = Recorder::default();
+        let motion_control = MotionControl::new_with_debug(
+            RecordingMotor::default(),
+            timer.clone(),
+            recorder.clone(),
+        );
+
+        Self {
+            motion_control,
+            timer,
+            recorder,
+        }
+    }
+
+    /// Advance the time by one update interval and run the update
+    pub fn update(&mut self) {
+        self.timer
+            .advance_ms(MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS);
+        self.motion_control.update_handler();
+    }
+
+    /// Run the updates until the move is finished
+    pub fn finish_move(&mut self) {
+        let mut updates = 0;
+        while motion_control::is_move_in_progress() {
+            assert!(updates < MAX_UPDATES, "The move never finished");
+            self.update();
+            updates += 1;
+        }
+    }
+
+    pub fn motor(&mut self) -> &mut RecordingMotor {
+        self.motion_control.motor_mut()
+    }
+}
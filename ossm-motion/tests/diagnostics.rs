@@ -0,0 +1,34 @@
+use ossm_motion::diagnostics::{ConfigIssue, MotorRatings, check_config, get_diagnostics_json};
+
+const RATINGS: MotorRatings = MotorRatings {
+    max_rpm: 3000.0,
+    min_output: 0.0,
+    max_output: 100.0,
+    output_limit: 100.0,
+};
+
+#[test]
+fn issues_of_the_config_are_reported() {
+    assert_eq!(get_diagnostics_json(), r#"{"issues":[]}"#);
+    assert!(check_config(&RATINGS).is_empty());
+    assert_eq!(get_diagnostics_json(), r#"{"issues":[]}"#);
+
+    let slow_motor = MotorRatings {
+        // Turns the pulley at 400 mm/s
+        max_rpm: 600.0,
+        min_output: 100.0,
+        ..RATINGS
+    };
+    assert_eq!(
+        check_config(&slow_motor).as_slice(),
+        [ConfigIssue::MotorSpeed, ConfigIssue::TorqueRange]
+    );
+    assert_eq!(
+        get_diagnostics_json(),
+        r#"{"issues":["motor_speed","torque_range"]}"#
+    );
+
+    // Replaced by the next check
+    check_config(&RATINGS);
+    assert_eq!(get_diagnostics_json(), r#"{"issues":[]}"#);
+}
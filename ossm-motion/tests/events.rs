@@ -0,0 +1,98 @@
+mod common;
+
+use ossm_motion::{
+    config::MAX_EVENTS,
+    event::{Event, get_event_sequence, next_event, publish_event},
+    fault::FaultCode,
+    motion_control::set_torque,
+};
+
+use common::lock;
+
+/// All the events from `sequence` on
+fn read_events(mut sequence: u32) -> Vec<Event> {
+    let mut events = Vec::new();
+    while let Some((event, next)) = next_event(sequence) {
+        events.push(event);
+        sequence = next;
+    }
+    events
+}
+
+#[test]
+fn readers_get_the_events_published_after_they_started() {
+    let _lock = lock();
+
+    publish_event(Event::HomingComplete);
+    let sequence = get_event_sequence();
+    assert_eq!(read_events(sequence), []);
+
+    publish_event(Event::HeartbeatLost);
+    publish_event(Event::PatternFinished { pattern: 3 });
+    assert_eq!(
+        read_events(sequence),
+        [Event::HeartbeatLost, Event::PatternFinished { pattern: 3 }]
+    );
+    // Another reader from the same point gets them as well
+    assert_eq!(read_events(sequence).len(), 2);
+}
+
+#[test]
+fn readers_that_fall_behind_skip_the_overwritten_events() {
+    let _lock = lock();
+
+    let sequence = get_event_sequence();
+    for pattern in 0..(MAX_EVENTS as u32 + 4) {
+        publish_event(Event::PatternFinished { pattern });
+    }
+
+    let events = read_events(sequence);
+    assert_eq!(events.len(), MAX_EVENTS);
+    assert_eq!(events[0], Event::PatternFinished { pattern: 4 });
+}
+
+#[test]
+fn the_torque_limit_is_published_once_until_the_torque_is_within_it_again() {
+    let _lock = lock();
+
+    set_torque(50.0);
+    let sequence = get_event_sequence();
+    set_torque(150.0);
+    set_torque(120.0);
+    set_torque(80.0);
+    set_torque(130.0);
+
+    assert_eq!(
+        read_events(sequence),
+        [
+            Event::TorqueLimited {
+                requested: 150,
+                limit: 100,
+            },
+            Event::TorqueLimited {
+                requested: 130,
+                limit: 100,
+            },
+        ]
+    );
+}
+
+#[test]
+fn events_are_written_as_json() {
+    assert_eq!(
+        Event::Fault(FaultCode::EStop).as_json(),
+        r#"{"event":"fault","fault":"estop"}"#
+    );
+    assert_eq!(
+        Event::TorqueLimited {
+            requested: 150,
+            limit: 100
+        }
+        .as_json(),
+        r#"{"event":"torque_limited","requested":150,"limit":100}"#
+    );
+    assert_eq!(
+        Event::PlaylistFinished.as_json(),
+        r#"{"event":"playlist_finished"}"#
+    );
+}
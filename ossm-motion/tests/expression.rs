@@ -0,0 +1,130 @@
+mod common;
+
+use embassy_time::Instant;
+use ossm_motion::{
+    float::Real,
+    motion_control::get_min_move_mm,
+    pattern::{
+        PATTERN_ID_CUSTOM, Pattern, PatternExecutor, PatternInput,
+        custom::{CustomPatternError, reset_custom_pattern, set_custom_expression},
+        expression::{Expression, ExpressionError, ExpressionInput},
+    },
+};
+
+use common::lock;
+
+const INPUT: ExpressionInput = ExpressionInput {
+    depth: 100.0,
+    stroke: 80.0,
+    velocity: 400.0,
+    sensation: -50.0,
+    index: 3.0,
+};
+
+fn evaluate(source: &str) -> Real {
+    Expression::compile(source).unwrap().evaluate(&INPUT)
+}
+
+#[test]
+fn expressions_follow_the_usual_precedence() {
+    assert_eq!(evaluate("1 + 2 * 3"), 7.0);
+    assert_eq!(evaluate("(1 + 2) * 3"), 9.0);
+    assert_eq!(evaluate("10 - 4 - 3"), 3.0);
+    assert_eq!(evaluate("-2 * -3"), 6.0);
+    assert_eq!(evaluate("7 % 4 + 1 < 5"), 1.0);
+    assert_eq!(evaluate("depth - stroke * (index % 2)"), 20.0);
+    assert_eq!(evaluate("-sensation / 2.5"), 20.0);
+}
+
+#[test]
+fn expressions_call_functions() {
+    assert_eq!(evaluate("min(depth, velocity)"), 100.0);
+    assert_eq!(evaluate("max(depth, velocity)"), 400.0);
+    assert_eq!(evaluate("abs(sensation)"), 50.0);
+    assert_eq!(evaluate("sin(0)"), 0.0);
+    assert_eq!(evaluate("if(index > 2, stroke, depth)"), 80.0);
+    assert_eq!(evaluate("if(index < 2, stroke, depth)"), 100.0);
+}
+
+#[test]
+fn invalid_expressions_are_rejected() {
+    let cases = [
+        ("depth +", ExpressionError::Syntax),
+        ("(depth", ExpressionError::Syntax),
+        ("depth stroke", ExpressionError::Syntax),
+        ("1..2", ExpressionError::Syntax),
+        ("speed", ExpressionError::UnknownVariable),
+        ("cos(depth)", ExpressionError::UnknownFunction),
+        ("min(depth)", ExpressionError::Arguments),
+        ("if(1, 2)", ExpressionError::Arguments),
+        ("1+(1+(1+(1+(1+(1+(1+(1+1)))))))", ExpressionError::TooDeep),
+    ];
+    for (source, err) in cases {
+        assert_eq!(Expression::compile(source), Err(err), "{source}");
+    }
+
+    let long = ["1"; 30].join("+");
+    assert_eq!(Expression::compile(&long), Err(ExpressionError::TooLong));
+}
+
+#[test]
+fn custom_pattern_evaluates_the_uploaded_expressions() {
+    let _lock = lock();
+    reset_custom_pattern();
+
+    let mut executor = PatternExecutor::new();
+    assert!(executor.has_pattern(PATTERN_ID_CUSTOM));
+    executor.set_pattern(PATTERN_ID_CUSTOM);
+    executor.reset();
+
+    let input = PatternInput {
+        depth: 100.0,
+        motion_length: 80.0,
+        velocity: 400.0,
+        sensation: 0.0,
+        load: 0.0,
+        bpm: 0.0,
+        now: Instant::from_ticks(0),
+        stroke_index: 0,
+        elapsed_ms: 0,
+    };
+    let min_move = get_min_move_mm();
+
+    // In and out like the simple pattern until something is uploaded
+    let positions: Vec<Real> = (0..3)
+        .map(|_| executor.next_move(&input).position - min_move)
+        .collect();
+    assert_eq!(positions, [100.0, 20.0, 100.0]);
+
+    assert_eq!(set_custom_expression("position=depth - index * 10"), Ok(()));
+    assert_eq!(set_custom_expression("velocity=velocity / 2"), Ok(()));
+    assert_eq!(set_custom_expression("delay=100 * index"), Ok(()));
+    assert_eq!(
+        set_custom_expression("torque=100"),
+        Err(CustomPatternError::UnknownOutput)
+    );
+    assert_eq!(
+        set_custom_expression("position"),
+        Err(CustomPatternError::Malformed)
+    );
+    assert_eq!(
+        set_custom_expression("position=depth +"),
+        Err(CustomPatternError::Expression(ExpressionError::Syntax))
+    );
+
+    executor.reset();
+    let first = executor.next_move(&input);
+    let second = executor.next_move(&input);
+    assert_eq!(first.position - min_move, 100.0);
+    assert_eq!(second.position - min_move, 90.0);
+    assert_eq!(second.velocity, 200.0);
+    assert_eq!(second.delay_ms, 100);
+
+    // Saturated to the input like any other pattern
+    set_custom_expression("position=depth * 2").unwrap();
+    assert_eq!(executor.next_move(&input).position - min_move, 100.0);
+    set_custom_expression("position=1 / 0").unwrap();
+    assert_eq!(executor.next_move(&input).position - min_move, 20.0);
+
+    reset_custom_pattern();
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use embassy_time::Instant;
+use ossm_motion::{
+    event::{Event, get_event_sequence, next_event},
+    fault::{
+        FaultCode, Recovery, clear_faults, get_fault, get_fault_count, get_last_fault,
+        is_fault_active, raise_fault,
+    },
+    motion::motion_state::get_motion_state,
+    motion_control::{
+        arming::{arm_at, is_armed},
+        is_faulted,
+    },
+};
+
+use common::lock;
+
+#[test]
+fn the_most_severe_fault_is_reported_until_its_recovery() {
+    let _lock = lock();
+    assert_eq!(get_fault(), None);
+
+    // Faults that keep the machine moving do not stop it
+    raise_fault(FaultCode::Overrun);
+    raise_fault(FaultCode::LimitExceeded);
+    assert!(!is_faulted());
+    assert_eq!(get_fault(), Some(FaultCode::LimitExceeded));
+
+    raise_fault(FaultCode::MotorTimeout);
+    assert_eq!(get_fault(), Some(FaultCode::MotorTimeout));
+    assert!(
+        get_motion_state()
+            .as_json()
+            .contains(r#""fault":"motor_timeout","#)
+    );
+
+    // Each is only cleared by its own recovery
+    clear_faults(Recovery::Rearm);
+    assert!(is_fault_active(FaultCode::MotorTimeout));
+    clear_faults(Recovery::MotorReconnected);
+    assert_eq!(get_fault(), Some(FaultCode::LimitExceeded));
+    clear_faults(Recovery::MotionOff);
+    assert_eq!(get_fault(), None);
+    assert!(get_motion_state().as_json().contains(r#""fault":"none","#));
+}
+
+#[test]
+fn a_fault_is_counted_and_published_once_until_cleared() {
+    let _lock = lock();
+    let count = get_fault_count();
+    let sequence = get_event_sequence();
+
+    raise_fault(FaultCode::Overrun);
+    raise_fault(FaultCode::Overrun);
+    assert_eq!(get_fault_count(), count + 1);
+    assert_eq!(get_last_fault(), Some(FaultCode::Overrun));
+    let (event, sequence) = next_event(sequence).expect("The fault was not published");
+    assert_eq!(event, Event::Fault(FaultCode::Overrun));
+    assert_eq!(next_event(sequence), None);
+
+    clear_faults(Recovery::MotionOff);
+    raise_fault(FaultCode::Overrun);
+    assert_eq!(get_fault_count(), count + 2);
+    clear_faults(Recovery::MotionOff);
+}
+
+#[test]
+fn faults_that_keep_the_machine_moving_leave_it_armed() {
+    let _lock = lock();
+    for code in [
+        FaultCode::Overrun,
+        FaultCode::LimitExceeded,
+        FaultCode::MotorTimeout,
+    ] {
+        arm_at(Instant::from_ticks(0)).unwrap();
+        raise_fault(code);
+        assert!(!is_faulted());
+        assert!(is_armed(), "{} disarmed the machine", code.name());
+        clear_faults(code.recovery());
+    }
+}
+
+#[test]
+fn every_fault_has_its_recovery() {
+    for code in [
+        FaultCode::EStop,
+        FaultCode::Obstruction,
+        FaultCode::HeartbeatLost,
+        FaultCode::LoopStalled,
+        FaultCode::Overheat,
+    ] {
+        assert_eq!(code.recovery(), Recovery::Rearm);
+    }
+    assert_eq!(
+        FaultCode::MotorTimeout.recovery(),
+        Recovery::MotorReconnected
+    );
+    assert_eq!(FaultCode::Overrun.recovery(), Recovery::MotionOff);
+    assert_eq!(FaultCode::LimitExceeded.recovery(), Recovery::MotionOff);
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use embassy_time::{Duration, Instant};
+use ossm_motion::{
+    float::Real,
+    motion::{
+        funscript::{
+            FunscriptError, FunscriptPlayer, add_funscript_actions, clear_funscript,
+            get_funscript_length, is_funscript_playing, play_funscript, seek_funscript,
+            stop_funscript,
+        },
+        stream::get_velocity_envelope,
+    },
+    motion_control::{arming::arm_at, get_target_position},
+};
+
+use common::lock;
+
+const POSITION_TOLERANCE_MM: Real = 0.01;
+
+fn assert_target(position: Real) {
+    let target = get_target_position();
+    assert!(
+        (target - position).abs() < POSITION_TOLERANCE_MM,
+        "Target {target} instead of {position}"
+    );
+}
+
+#[test]
+fn chunks_are_added_completely_or_not_at_all() {
+    let _lock = lock();
+    stop_funscript();
+    clear_funscript().unwrap();
+
+    assert_eq!(add_funscript_actions("0,0;500,100;"), Ok(2));
+    assert_eq!(
+        add_funscript_actions("1000,50;400,50"),
+        Err(FunscriptError::NotAscending)
+    );
+    assert_eq!(
+        add_funscript_actions("1000,50;1500"),
+        Err(FunscriptError::Malformed)
+    );
+    assert_eq!(
+        add_funscript_actions("1000,101"),
+        Err(FunscriptError::Malformed)
+    );
+    assert_eq!(get_funscript_length(), 2);
+    assert_eq!(add_funscript_actions("1000,50"), Ok(3));
+}
+
+#[test]
+fn playback_moves_to_each_action_in_turn() {
+    let _lock = lock();
+    arm_at(Instant::from_ticks(0)).unwrap();
+    stop_funscript();
+    clear_funscript().unwrap();
+    add_funscript_actions("0,0;500,100;1000,0").unwrap();
+    let envelope = get_velocity_envelope();
+    let start = Instant::from_secs(1);
+    let mut player = FunscriptPlayer::new();
+
+    play_funscript().unwrap();
+    assert_eq!(clear_funscript(), Err(FunscriptError::Playing));
+
+    player.update(start);
+    assert_target(envelope.max_position);
+
+    player.update(start + Duration::from_millis(510));
+    assert_target(envelope.min_position);
+
+    player.update(start + Duration::from_millis(1010));
+    player.update(start + Duration::from_millis(1020));
+    assert!(!is_funscript_playing());
+}
+
+#[test]
+fn seeking_continues_from_the_new_position() {
+    let _lock = lock();
+    arm_at(Instant::from_ticks(0)).unwrap();
+    stop_funscript();
+    clear_funscript().unwrap();
+    add_funscript_actions("0,0;500,100;1000,0").unwrap();
+    let envelope = get_velocity_envelope();
+    let start = Instant::from_secs(1);
+    let mut player = FunscriptPlayer::new();
+
+    play_funscript().unwrap();
+    player.update(start);
+    assert_target(envelope.max_position);
+
+    seek_funscript(600);
+    player.update(start + Duration::from_millis(10));
+    assert_target(envelope.min_position);
+
+    stop_funscript();
+    player.update(start + Duration::from_millis(20));
+    assert!(!is_funscript_playing());
+}
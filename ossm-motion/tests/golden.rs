@@ -0,0 +1,208 @@
+use std::{fmt::Write, fs, path::PathBuf};
+
+use embassy_time::{Duration, Instant};
+use ossm_motion::{
+    float::Real,
+    motion_control::get_min_move_mm,
+    pattern::{
+        PATTERN_ID_CUSTOM, PATTERN_ID_DEEPER, PATTERN_ID_EDGING, PATTERN_ID_HALF_HALF,
+        PATTERN_ID_INSIST, PATTERN_ID_JACK_HAMMER, PATTERN_ID_LOAD_ADAPTIVE, PATTERN_ID_SHUFFLE,
+        PATTERN_ID_SIMPLE, PATTERN_ID_STOP_N_GO, PATTERN_ID_TEASING_POUNDING, PATTERN_ID_TORQUE,
+        PATTERN_ID_WARM_UP, Pattern, PatternExecutor, PatternInput,
+    },
+};
+
+const PATTERNS: [u32; 13] = [
+    PATTERN_ID_SIMPLE,
+    PATTERN_ID_TEASING_POUNDING,
+    PATTERN_ID_HALF_HALF,
+    PATTERN_ID_DEEPER,
+    PATTERN_ID_STOP_N_GO,
+    PATTERN_ID_INSIST,
+    PATTERN_ID_TORQUE,
+    PATTERN_ID_JACK_HAMMER,
+    PATTERN_ID_EDGING,
+    PATTERN_ID_WARM_UP,
+    PATTERN_ID_LOAD_ADAPTIVE,
+    PATTERN_ID_SHUFFLE,
+    PATTERN_ID_CUSTOM,
+];
+
+/// Inputs held for a number of moves
+struct Phase {
+    depth: Real,
+    motion_length: Real,
+    velocity: Real,
+    sensation: Real,
+    bpm: Real,
+    moves: usize,
+}
+
+const PHASES: [Phase; 5] = [
+    Phase {
+        depth: 150.0,
+        motion_length: 100.0,
+        velocity: 300.0,
+        sensation: 0.0,
+        bpm: 0.0,
+        moves: 30,
+    },
+    Phase {
+        depth: 150.0,
+        motion_length: 100.0,
+        velocity: 300.0,
+        sensation: -100.0,
+        bpm: 0.0,
+        moves: 30,
+    },
+    Phase {
+        depth: 150.0,
+        motion_length: 100.0,
+        velocity: 300.0,
+        sensation: 100.0,
+        bpm: 0.0,
+        moves: 30,
+    },
+    Phase {
+        depth: 100.0,
+        motion_length: 40.0,
+        velocity: 500.0,
+        sensation: 50.0,
+        bpm: 0.0,
+        moves: 20,
+    },
+    Phase {
+        depth: 150.0,
+        motion_length: 100.0,
+        velocity: 600.0,
+        sensation: 0.0,
+        bpm: 60.0,
+        moves: 10,
+    },
+];
+
+// The time between two moves for the patterns that change over time
+const MOVE_INTERVAL: Duration = Duration::from_millis(500);
+// The files are written with 3 decimals. Leaves room for the f32 feature
+const TOLERANCE: Real = 1e-2;
+
+fn golden_path(id: u32) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("pattern_{id}.txt"))
+}
+
+/// One line per move: velocity, position without the min move, delay and torque
+fn run_pattern(id: u32) -> String {
+    let mut executor = PatternExecutor::new();
+    executor.set_pattern(id);
+    executor.reset();
+    let min_move = get_min_move_mm();
+
+    let mut output = format!("# {}\n", executor.get_current_pattern_name());
+    let mut now = Instant::from_ticks(0);
+    for phase in PHASES.iter() {
+        let mut input = PatternInput {
+            depth: phase.depth,
+            motion_length: phase.motion_length,
+            velocity: phase.velocity,
+            sensation: phase.sensation,
+            load: 0.0,
+            bpm: phase.bpm,
+            now,
+            stroke_index: 0,
+            elapsed_ms: 0,
+        };
+        // Each phase starts the pattern again like enabling the motion
+        let started = now;
+        let mut position = 0.0;
+        executor.on_start(&input);
+        for _ in 0..phase.moves {
+            input.now = now;
+            input.elapsed_ms = (now - started).as_millis();
+            let pattern_move = executor.next_move(&input);
+            if pattern_move.position > position {
+                input.stroke_index += 1;
+            }
+            position = pattern_move.position;
+            writeln!(
+                output,
+                "{:.3} {:.3} {} {:.3}",
+                pattern_move.velocity,
+                pattern_move.position - min_move,
+                pattern_move.delay_ms,
+                pattern_move.torque
+            )
+            .unwrap();
+            now += MOVE_INTERVAL;
+        }
+        executor.on_stop();
+    }
+
+    output
+}
+
+/// The first line that differs by more than the tolerance
+fn first_difference(actual: &str, expected: &str) -> Option<usize> {
+    let parse = |line: &str| -> Vec<Real> {
+        line.split_whitespace()
+            .filter_map(|value| value.parse().ok())
+            .collect()
+    };
+
+    let actual_lines: Vec<_> = actual.lines().collect();
+    let expected_lines: Vec<_> = expected.lines().collect();
+    if actual_lines.len() != expected_lines.len() {
+        return Some(actual_lines.len().min(expected_lines.len()));
+    }
+    actual_lines
+        .iter()
+        .zip(expected_lines.iter())
+        .position(|(a, e)| {
+            if a.starts_with('#') || e.starts_with('#') {
+                return a != e;
+            }
+            let (a, e) = (parse(a), parse(e));
+            a.len() != e.len()
+                || a.iter()
+                    .zip(e.iter())
+                    .any(|(a, e)| (a - e).abs() > TOLERANCE)
+        })
+}
+
+// Keeps a refactor of the patterns or the executor from changing the motion unnoticed
+// After an intended change run with `UPDATE_GOLDEN=1` and review the diff of `tests/golden`
+#[test]
+fn patterns_match_the_golden_files() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+
+    for id in PATTERNS {
+        let actual = run_pattern(id);
+        let path = golden_path(id);
+        if update {
+            fs::write(&path, &actual).unwrap();
+            continue;
+        }
+
+        let Ok(expected) = fs::read_to_string(&path) else {
+            failures.push(format!("{}: missing", path.display()));
+            continue;
+        };
+        if let Some(line) = first_difference(&actual, &expected) {
+            failures.push(format!(
+                "{}:{}: {:?} instead of {:?}",
+                path.display(),
+                line + 1,
+                actual.lines().nth(line).unwrap_or_default(),
+                expected.lines().nth(line).unwrap_or_default()
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "Run with UPDATE_GOLDEN=1 if the change is intended\n{}",
+        failures.join("\n")
+    );
+}
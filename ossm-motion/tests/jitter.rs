@@ -0,0 +1,62 @@
+use embassy_time::Instant;
+use ossm_motion::{
+    motion_control::get_min_move_mm,
+    pattern::{
+        PATTERN_ID_SIMPLE, Pattern, PatternExecutor, PatternInput,
+        jitter::{get_jitter_pct, set_jitter_pct},
+    },
+    validation::ValueError,
+};
+
+const INPUT: PatternInput = PatternInput {
+    depth: 100.0,
+    motion_length: 80.0,
+    velocity: 400.0,
+    sensation: 0.0,
+    load: 0.0,
+    bpm: 0.0,
+    now: Instant::from_ticks(0),
+    stroke_index: 0,
+    elapsed_ms: 0,
+};
+
+// In its own binary. The jitter applies to every executor and would change the other tests
+#[test]
+fn jitter_varies_the_moves_within_the_limits() {
+    let mut executor = PatternExecutor::new();
+    executor.set_pattern(PATTERN_ID_SIMPLE);
+    executor.reset();
+    set_jitter_pct(10).unwrap();
+
+    let min_move = get_min_move_mm();
+    let moves: Vec<_> = (0..100)
+        .map(|_| {
+            let pattern_move = executor.next_move(&INPUT);
+            (pattern_move.velocity, pattern_move.position - min_move)
+        })
+        .collect();
+
+    for (i, (velocity, position)) in moves.iter().enumerate() {
+        assert!((360.0..=400.0).contains(velocity), "{velocity}");
+        // 10% of the stroke either way. Never beyond the depth
+        let range = if i % 2 == 0 {
+            92.0..=100.0
+        } else {
+            12.0..=28.0
+        };
+        assert!(range.contains(position), "{position}");
+    }
+    assert!(moves.windows(3).any(|w| w[0] != w[2]));
+
+    assert_eq!(
+        set_jitter_pct(50),
+        Err(ValueError::OutOfRange { accepted: 25 })
+    );
+    assert_eq!(get_jitter_pct(), 25);
+
+    set_jitter_pct(0).unwrap();
+    let mut executor = PatternExecutor::new();
+    executor.set_pattern(PATTERN_ID_SIMPLE);
+    executor.reset();
+    assert_eq!(executor.next_move(&INPUT).position - min_move, 100.0);
+}
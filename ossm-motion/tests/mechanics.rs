@@ -0,0 +1,45 @@
+use ossm_motion::{
+    config::{MAX_PULLEY_TOOTH_COUNT, STEPS_PER_MM},
+    motion_control::mechanics::{Mechanics, get_steps_per_mm, set_mechanics},
+    runtime_config::get_config_json,
+    validation::ValueError,
+};
+
+#[test]
+fn mechanics_set_the_steps_per_mm() {
+    assert_eq!(get_steps_per_mm(), STEPS_PER_MM);
+
+    let mut mechanics = Mechanics::DEFAULT;
+    assert_eq!(mechanics.set_value("beltPitch", 3.0), Ok(()));
+    assert_eq!(
+        mechanics.set_value("pulleyToothCount", 1000.0),
+        Err(ValueError::OutOfRange {
+            accepted: MAX_PULLEY_TOOTH_COUNT as i32
+        })
+    );
+    assert_eq!(
+        mechanics.set_value("maxJerk", 1.0),
+        Err(ValueError::Unknown)
+    );
+    assert_eq!(mechanics.mm_per_rotation(), 300.0);
+
+    set_mechanics(mechanics).unwrap();
+    assert_eq!(get_steps_per_mm(), 32768.0 / 300.0);
+    let config = get_config_json();
+    assert!(
+        config.contains(
+            r#""pulleyToothCount":100,"beltPitch":3.00,"motorStepsPerRevolution":32768,"#
+        ),
+        "{config}"
+    );
+
+    // Kept as they were
+    let invalid = Mechanics {
+        belt_pitch: 0.0,
+        ..Mechanics::DEFAULT
+    };
+    assert_eq!(set_mechanics(invalid), Err(ValueError::Unknown));
+    assert_eq!(get_steps_per_mm(), 32768.0 / 300.0);
+
+    set_mechanics(Mechanics::DEFAULT).unwrap();
+}
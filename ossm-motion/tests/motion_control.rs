@@ -0,0 +1,688 @@
+mod common;
+
+use embassy_time::Instant;
+use ossm_motion::{
+    config::{
+        ADDITIONAL_AXIS_LIMITS, LOAD_UPDATE_INTERVAL_MS, MIN_MOVE_MM,
+        MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS, MOTION_CONTROL_MAX_VELOCITY,
+        MOTION_CONTROL_WATCHDOG_TIMEOUT_MS, MOTOR_CRITICAL_C, MOTOR_WARNING_C, OBSTRUCTION_TIME_MS,
+        REVERSE_DIRECTION, STEPS_PER_MM, TEMPERATURE_UPDATE_INTERVAL_MS, THERMAL_HYSTERESIS_C,
+        THERMAL_WARNING_LIMIT_PCT, TORQUE_SLEW_TIME_MS,
+    },
+    fault::{FaultCode, get_last_fault, is_fault_active},
+    float::Real,
+    motion::motion_state::get_motion_state,
+    motion_control::{
+        self, AxisTargetError, MotionControl,
+        arming::{ArmError, arm_at, is_armed},
+        motor::Motor,
+        recorder::{self, Record},
+        timer::{Duration, Timer},
+    },
+    validation::ValueError,
+};
+
+use common::{FakeTimer, Harness, lock};
+
+const POSITION_TOLERANCE_MM: Real = 0.05;
+const VELOCITY_TOLERANCE_MM_S: Real = 0.5;
+// Updates before a new velocity is applied. The update it was set in plus the cooldown
+const VELOCITY_COOLDOWN_UPDATES: usize = 4;
+
+/// Records the positions written to the stroke and the twist axis
+#[derive(Default)]
+struct TwoAxisMotor {
+    steps: [Vec<i32>; 2],
+}
+
+impl Motor for TwoAxisMotor {
+    type MotorError = ();
+
+    fn min_consecutive_write_delay() -> Duration {
+        Duration::from_ticks(0)
+    }
+
+    fn set_absolute_position(&mut self, steps: i32) -> Result<(), Self::MotorError> {
+        self.set_axis_position(0, steps)
+    }
+
+    fn set_axis_position(&mut self, axis: usize, steps: i32) -> Result<(), Self::MotorError> {
+        self.steps[axis].push(steps);
+        Ok(())
+    }
+
+    fn set_torque_pct(&mut self, _torque: Real) -> Result<(), Self::MotorError> {
+        Ok(())
+    }
+
+    fn delay(&mut self, _duration: Duration) {}
+}
+
+/// Maps the torque to OUTPUT_MIN_PCT-OUTPUT_MAX_PCT of its output like the 57AIM and reports the
+/// load on the scale of the full output
+#[derive(Default)]
+struct OutputScaledMotor {
+    load: Real,
+}
+
+const OUTPUT_MIN_PCT: Real = 12.0;
+const OUTPUT_MAX_PCT: Real = 60.0;
+
+impl Motor for OutputScaledMotor {
+    type MotorError = ();
+
+    fn min_consecutive_write_delay() -> Duration {
+        Duration::from_ticks(0)
+    }
+
+    fn set_absolute_position(&mut self, _steps: i32) -> Result<(), Self::MotorError> {
+        Ok(())
+    }
+
+    fn set_torque_pct(&mut self, _torque: Real) -> Result<(), Self::MotorError> {
+        Ok(())
+    }
+
+    fn load_limit_pct(torque: Real) -> Real {
+        OUTPUT_MIN_PCT + (OUTPUT_MAX_PCT - OUTPUT_MIN_PCT) * torque / 100.0
+    }
+
+    fn get_load_pct(&mut self) -> Result<Option<Real>, Self::MotorError> {
+        Ok(Some(self.load))
+    }
+
+    fn delay(&mut self, _duration: Duration) {}
+}
+
+/// Takes most of an update interval to read the load like a busy bus
+struct SlowLoadMotor {
+    timer: FakeTimer,
+    // "load" and "temperature" for each read, "update" before each update
+    reads: Vec<&'static str>,
+}
+
+impl Motor for SlowLoadMotor {
+    type MotorError = ();
+
+    fn min_consecutive_write_delay() -> Duration {
+        Duration::from_ticks(0)
+    }
+
+    fn set_absolute_position(&mut self, _steps: i32) -> Result<(), Self::MotorError> {
+        Ok(())
+    }
+
+    fn set_torque_pct(&mut self, _torque: Real) -> Result<(), Self::MotorError> {
+        Ok(())
+    }
+
+    fn get_load_pct(&mut self) -> Result<Option<Real>, Self::MotorError> {
+        self.timer
+            .advance_ms(MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS * 3 / 4);
+        self.reads.push("load");
+        Ok(Some(0.0))
+    }
+
+    fn get_temperature_c(&mut self) -> Result<Option<Real>, Self::MotorError> {
+        self.reads.push("temperature");
+        Ok(Some(25.0))
+    }
+
+    fn delay(&mut self, _duration: Duration) {}
+}
+
+/// Records when it is accessed to check the spacing of the writes
+struct SpacedMotor {
+    timer: FakeTimer,
+    // In µs
+    accesses: Vec<u64>,
+}
+
+impl SpacedMotor {
+    const WRITE_DELAY_MS: u64 = 2;
+
+    fn access(&mut self) {
+        self.accesses.push(self.timer.now().ticks());
+    }
+}
+
+impl Motor for SpacedMotor {
+    type MotorError = ();
+
+    fn min_consecutive_write_delay() -> Duration {
+        Duration::millis(Self::WRITE_DELAY_MS)
+    }
+
+    fn set_absolute_position(&mut self, _steps: i32) -> Result<(), Self::MotorError> {
+        self.access();
+        Ok(())
+    }
+
+    fn set_torque_pct(&mut self, _torque: Real) -> Result<(), Self::MotorError> {
+        self.access();
+        Ok(())
+    }
+
+    fn get_load_pct(&mut self) -> Result<Option<Real>, Self::MotorError> {
+        self.access();
+        Ok(Some(0.0))
+    }
+
+    fn get_temperature_c(&mut self) -> Result<Option<Real>, Self::MotorError> {
+        self.access();
+        Ok(Some(25.0))
+    }
+
+    fn delay(&mut self, duration: Duration) {
+        self.timer.advance_ms(duration.to_millis());
+    }
+}
+
+fn steps_to_mm(steps: i32) -> Real {
+    let mm = steps as Real / STEPS_PER_MM;
+    if REVERSE_DIRECTION { mm } else { -mm }
+}
+
+#[test]
+fn written_positions_stay_within_the_bounds() {
+    let _lock = lock();
+    let mut harness = Harness::new();
+    let min_move = motion_control::get_min_move_mm();
+    let max_move = motion_control::get_max_move_mm();
+
+    motion_control::set_max_velocity(MOTION_CONTROL_MAX_VELOCITY);
+    motion_control::set_target_position(max_move + 100.0);
+    harness.finish_move();
+    motion_control::set_target_position(min_move - 100.0);
+    harness.finish_move();
+
+    let steps = harness.motor().steps.clone();
+    assert!(!steps.is_empty());
+    for position in steps.into_iter().map(steps_to_mm) {
+        assert!(
+            position >= MIN_MOVE_MM - POSITION_TOLERANCE_MM
+                && position <= max_move + POSITION_TOLERANCE_MM,
+            "Wrote {position} outside of {MIN_MOVE_MM}-{max_move}"
+        );
+    }
+
+    let positions = harness.recorder.positions.borrow();
+    let peak = positions.iter().copied().fold(Real::MIN, Real::max);
+    assert!((peak - max_move).abs() < POSITION_TOLERANCE_MM);
+    let last = *positions.last().unwrap();
+    assert!((last - min_move).abs() < POSITION_TOLERANCE_MM);
+}
+
+#[test]
+fn velocity_is_capped_at_the_max() {
+    let _lock = lock();
+    let mut harness = Harness::new();
+
+    motion_control::set_max_velocity(MOTION_CONTROL_MAX_VELOCITY * 10.0);
+    motion_control::set_target_position(motion_control::get_max_move_mm());
+    harness.finish_move();
+
+    let velocities = harness.recorder.velocities.borrow();
+    let peak = velocities.iter().copied().fold(0.0, Real::max);
+    assert!(
+        peak <= MOTION_CONTROL_MAX_VELOCITY + VELOCITY_TOLERANCE_MM_S,
+        "Velocity {peak} exceeds {MOTION_CONTROL_MAX_VELOCITY}"
+    );
+}
+
+#[test]
+fn velocity_updates_wait_for_the_cooldown() {
+    let _lock = lock();
+    let mut harness = Harness::new();
+    let initial_velocity = 50.0;
+
+    motion_control::set_max_velocity(initial_velocity);
+    motion_control::set_target_position(motion_control::get_max_move_mm());
+    // Cruising well before the end of the move
+    for _ in 0..100 {
+        harness.update();
+    }
+    assert!(motion_control::is_move_in_progress());
+
+    motion_control::set_max_velocity(initial_velocity * 4.0);
+    harness.recorder.velocities.borrow_mut().clear();
+    for _ in 0..VELOCITY_COOLDOWN_UPDATES {
+        harness.update();
+    }
+    for velocity in harness.recorder.velocities.borrow().iter() {
+        assert!(
+            (velocity - initial_velocity).abs() < VELOCITY_TOLERANCE_MM_S,
+            "Velocity changed to {velocity} during the cooldown"
+        );
+    }
+
+    for _ in 0..20 {
+        harness.update();
+    }
+    let velocity = *harness.recorder.velocities.borrow().last().unwrap();
+    assert!(
+        velocity > initial_velocity + VELOCITY_TOLERANCE_MM_S,
+        "Velocity {velocity} was not raised after the cooldown"
+    );
+
+    harness.finish_move();
+}
+
+#[test]
+fn torque_is_capped_by_the_active_profile() {
+    let _lock = lock();
+    let mut harness = Harness::new();
+    motion_control::set_torque_slew_ms(0).unwrap();
+
+    motion_control::set_torque(50.0);
+    harness.update();
+    assert_eq!(harness.motor().torques.last(), Some(&50.0));
+
+    motion_control::set_torque(0.0);
+    harness.update();
+    assert_eq!(harness.motor().torques.last(), Some(&0.0));
+
+    motion_control::set_torque(150.0);
+    harness.update();
+    assert_eq!(harness.motor().torques.last(), Some(&100.0));
+    assert_eq!(get_motion_state().torque, 100.0);
+}
+
+#[test]
+fn torque_rises_with_the_slew_rate_and_drops_at_once() {
+    let _lock = lock();
+    let mut harness = Harness::new();
+    let slew_ms = 100;
+    let max_step = 100.0 * MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS as Real / slew_ms as Real;
+
+    motion_control::set_torque_slew_ms(slew_ms).unwrap();
+    motion_control::set_torque(0.0);
+    harness.update();
+    motion_control::set_torque(100.0);
+    for _ in 0..slew_ms as u64 / MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS + 1 {
+        harness.update();
+    }
+
+    let torques = harness.motor().torques.clone();
+    assert_eq!(torques.first(), Some(&0.0));
+    assert_eq!(torques.last(), Some(&100.0));
+    for step in torques.windows(2) {
+        assert!(step[1] > step[0] && step[1] - step[0] <= max_step + Real::EPSILON);
+    }
+
+    motion_control::set_torque(0.0);
+    harness.update();
+    assert_eq!(harness.motor().torques.last(), Some(&0.0));
+
+    motion_control::set_torque_slew_ms(TORQUE_SLEW_TIME_MS).unwrap();
+}
+
+#[test]
+fn ramped_torque_writes_keep_the_write_delay() {
+    let _lock = lock();
+    let timer = FakeTimer::default();
+    let motor = SpacedMotor {
+        timer: timer.clone(),
+        accesses: Vec::new(),
+    };
+    let mut motion_control: MotionControl<_, _, _> = MotionControl::new(motor, timer.clone());
+    motion_control::set_torque_slew_ms(TORQUE_SLEW_TIME_MS).unwrap();
+    motion_control::set_torque(0.0);
+    motion_control.update_handler();
+
+    // The torque ramps up while the machine moves
+    motion_control::set_torque(100.0);
+    motion_control::set_max_velocity(MOTION_CONTROL_MAX_VELOCITY);
+    motion_control::set_target_position(motion_control::get_max_move_mm());
+    for _ in 0..TORQUE_SLEW_TIME_MS as u64 / MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS {
+        timer.advance_ms(MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS);
+        motion_control.update_handler();
+    }
+
+    let accesses = &motion_control.motor_mut().accesses;
+    assert!(accesses.len() > 2);
+    for pair in accesses.windows(2) {
+        assert!(
+            pair[1] - pair[0] >= SpacedMotor::WRITE_DELAY_MS * 1000,
+            "Accessed the motor {} µs after the previous access",
+            pair[1] - pair[0]
+        );
+    }
+
+    while motion_control::is_move_in_progress() {
+        timer.advance_ms(MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS);
+        motion_control.update_handler();
+    }
+}
+
+#[test]
+fn recorder_keeps_the_trajectory_and_the_emergency_stop() {
+    let _lock = lock();
+    let mut harness = Harness::new();
+
+    motion_control::set_max_velocity(MOTION_CONTROL_MAX_VELOCITY);
+    motion_control::set_target_position(motion_control::get_max_move_mm());
+    for _ in 0..20 {
+        harness.update();
+    }
+    motion_control::emergency_stop();
+    harness.finish_move();
+
+    let count = recorder::freeze();
+    let records: Vec<Record> = (0..count).filter_map(recorder::get_record).collect();
+    recorder::unfreeze();
+    // Reported in the state until re-armed
+    assert_eq!(get_motion_state().fault, Some(FaultCode::EStop));
+    assert!(motion_control::rearm());
+    assert!(!is_fault_active(FaultCode::EStop));
+
+    let stop = records
+        .iter()
+        .rposition(|record| {
+            matches!(
+                record,
+                Record::Fault {
+                    fault: FaultCode::EStop,
+                    ..
+                }
+            )
+        })
+        .expect("The emergency stop was not recorded");
+    let samples_after_stop = records[stop..]
+        .iter()
+        .filter(|record| matches!(record, Record::Sample { .. }))
+        .count();
+    assert!(samples_after_stop > 0);
+
+    let Some(Record::Sample { position, .. }) = records.last() else {
+        panic!("The last record is not a sample");
+    };
+    assert!(*position > MIN_MOVE_MM && *position < motion_control::get_max_move_mm());
+}
+
+#[test]
+fn estop_input_stops_drops_the_torque_and_latches() {
+    let _lock = lock();
+    let mut harness = Harness::new();
+    motion_control::set_torque_slew_ms(0).unwrap();
+    motion_control::set_torque(100.0);
+
+    motion_control::set_max_velocity(MOTION_CONTROL_MAX_VELOCITY);
+    motion_control::set_target_position(motion_control::get_max_move_mm());
+    for _ in 0..20 {
+        harness.update();
+    }
+    motion_control::set_estop_input(true);
+    harness.finish_move();
+    harness.update();
+
+    assert_eq!(get_last_fault(), Some(FaultCode::EStop));
+    assert_eq!(get_motion_state().fault, Some(FaultCode::EStop));
+    assert!(motion_control::is_estop_latched());
+    assert_eq!(harness.motor().torques.last(), Some(&0.0));
+    // Not until the input clears
+    assert!(!motion_control::rearm());
+    motion_control::set_estop_input(false);
+    harness.update();
+    assert_eq!(harness.motor().torques.last(), Some(&0.0));
+
+    assert!(motion_control::rearm());
+    assert!(!motion_control::is_estop_latched());
+    harness.update();
+    assert_eq!(harness.motor().torques.last(), Some(&100.0));
+
+    motion_control::set_torque_slew_ms(TORQUE_SLEW_TIME_MS).unwrap();
+}
+
+#[test]
+fn emergency_stop_disarms_until_armed_again() {
+    let _lock = lock();
+    let mut harness = Harness::new();
+    arm_at(Instant::from_ticks(0)).unwrap();
+
+    motion_control::set_max_velocity(MOTION_CONTROL_MAX_VELOCITY);
+    motion_control::set_target_position(motion_control::get_max_move_mm());
+    for _ in 0..20 {
+        harness.update();
+    }
+    motion_control::emergency_stop();
+    assert!(!is_armed());
+    assert_eq!(arm_at(Instant::from_ticks(0)), Err(ArmError::Stopping));
+    harness.finish_move();
+
+    arm_at(Instant::from_ticks(0)).unwrap();
+    assert!(is_armed());
+}
+
+#[test]
+fn emergency_stop_while_the_motor_is_disconnected_can_be_rearmed() {
+    let _lock = lock();
+    let mut harness = Harness::new();
+    arm_at(Instant::from_ticks(0)).unwrap();
+
+    motion_control::pause_for_reconnect();
+    motion_control::emergency_stop();
+    assert_eq!(arm_at(Instant::from_ticks(0)), Err(ArmError::Stopping));
+    // Nothing is moving, so the next update completes the stop
+    harness.update();
+    arm_at(Instant::from_ticks(0)).unwrap();
+    assert!(is_armed());
+
+    harness.motion_control.resume_after_reconnect(MIN_MOVE_MM);
+}
+
+#[test]
+fn stalled_loop_drops_the_torque_without_the_loop() {
+    let _lock = lock();
+    let mut harness = Harness::new();
+    arm_at(Instant::from_ticks(0)).unwrap();
+    motion_control::set_torque_slew_ms(0).unwrap();
+    motion_control::set_torque(100.0);
+
+    motion_control::set_max_velocity(MOTION_CONTROL_MAX_VELOCITY);
+    motion_control::set_target_position(motion_control::get_max_move_mm());
+    for _ in 0..20 {
+        harness.update();
+    }
+    assert!(!harness.motion_control.check_loop_watchdog());
+
+    // The loop stops being run
+    let steps = harness.motor().steps.len();
+    harness.timer.advance_ms(MOTION_CONTROL_WATCHDOG_TIMEOUT_MS);
+    assert!(harness.motion_control.check_loop_watchdog());
+    assert!(is_fault_active(FaultCode::LoopStalled));
+    assert!(!is_armed());
+    assert_eq!(harness.motor().torques.last(), Some(&0.0));
+    assert_eq!(harness.motor().steps.len(), steps);
+    // Only trips once
+    assert!(!harness.motion_control.check_loop_watchdog());
+
+    harness.finish_move();
+    arm_at(Instant::from_ticks(0)).unwrap();
+    motion_control::set_torque_slew_ms(TORQUE_SLEW_TIME_MS).unwrap();
+}
+
+#[test]
+fn obstruction_stops_the_machine_until_rearmed() {
+    let _lock = lock();
+    let mut harness = Harness::new();
+    motion_control::set_torque(100.0);
+    harness.motor().load = Some(100.0);
+
+    // Slow enough to still be moving when the obstruction is detected
+    motion_control::set_max_velocity(50.0);
+    motion_control::set_target_position(motion_control::get_max_move_mm());
+    let updates = (OBSTRUCTION_TIME_MS + 2 * LOAD_UPDATE_INTERVAL_MS)
+        / MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS;
+    for _ in 0..updates {
+        harness.update();
+    }
+    assert!(motion_control::is_faulted());
+    assert_eq!(get_motion_state().fault, Some(FaultCode::Obstruction));
+
+    harness.motor().load = None;
+    harness.finish_move();
+    harness.update();
+    assert_eq!(motion_control::get_load_pct(), 0);
+    assert!(motion_control::rearm());
+    assert!(!is_fault_active(FaultCode::Obstruction));
+}
+
+#[test]
+fn obstruction_compares_the_load_with_the_output_the_torque_allows() {
+    let _lock = lock();
+    let timer = FakeTimer::default();
+    let mut motion_control = MotionControl::new(OutputScaledMotor::default(), timer.clone());
+    motion_control::set_torque_slew_ms(0).unwrap();
+    let updates = (OBSTRUCTION_TIME_MS + 2 * LOAD_UPDATE_INTERVAL_MS)
+        / MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS;
+    let run = |motion_control: &mut MotionControl<OutputScaledMotor, FakeTimer, _>, load: Real| {
+        motion_control.motor_mut().load = load;
+        for _ in 0..updates {
+            timer.advance_ms(MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS);
+            motion_control.update_handler();
+        }
+    };
+
+    // Slow enough to still be moving after both
+    motion_control::set_max_velocity(20.0);
+    motion_control::set_target_position(motion_control::get_max_move_mm());
+    // A weak setting reaches its limit at a low load. Below it is a normal move
+    motion_control::set_torque(20.0);
+    run(
+        &mut motion_control,
+        OutputScaledMotor::load_limit_pct(20.0) * 0.9,
+    );
+    assert!(!motion_control::is_faulted());
+
+    // Full torque never reports more than the max output
+    motion_control::set_torque(100.0);
+    run(&mut motion_control, OUTPUT_MAX_PCT);
+    assert!(motion_control::is_faulted());
+    assert_eq!(get_motion_state().fault, Some(FaultCode::Obstruction));
+
+    motion_control.motor_mut().load = 0.0;
+    while motion_control::is_move_in_progress() {
+        timer.advance_ms(MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS);
+        motion_control.update_handler();
+    }
+    assert!(motion_control::rearm());
+    motion_control::set_torque_slew_ms(TORQUE_SLEW_TIME_MS).unwrap();
+}
+
+#[test]
+fn temperature_is_read_during_a_move_only_in_updates_with_time_left() {
+    let _lock = lock();
+    let timer = FakeTimer::default();
+    let motor = SlowLoadMotor {
+        timer: timer.clone(),
+        reads: Vec::new(),
+    };
+    let mut motion_control: MotionControl<_, _, _> = MotionControl::new(motor, timer.clone());
+
+    // Slow enough to still be moving after all the updates
+    motion_control::set_max_velocity(20.0);
+    motion_control::set_target_position(motion_control::get_max_move_mm());
+    for _ in 0..3 * TEMPERATURE_UPDATE_INTERVAL_MS / MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS {
+        motion_control.motor_mut().reads.push("update");
+        timer.advance_ms(MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS);
+        motion_control.update_handler();
+    }
+    assert!(motion_control::is_move_in_progress());
+
+    let reads = &motion_control.motor_mut().reads;
+    let updates: Vec<&[&str]> = reads.split(|read| *read == "update").collect();
+    assert!(updates.iter().any(|update| update.contains(&"temperature")));
+    assert!(
+        updates
+            .iter()
+            .all(|update| !(update.contains(&"load") && update.contains(&"temperature")))
+    );
+
+    while motion_control::is_move_in_progress() {
+        timer.advance_ms(MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS);
+        motion_control.update_handler();
+    }
+}
+
+#[test]
+fn overheating_caps_the_torque_and_stops_until_cooled_down() {
+    let _lock = lock();
+    let mut harness = Harness::new();
+    motion_control::set_torque_slew_ms(0).unwrap();
+    motion_control::set_torque(100.0);
+    // Until the temperature was read and the torque written with the next update
+    let updates = TEMPERATURE_UPDATE_INTERVAL_MS / MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS + 1;
+
+    harness.motor().temperature = Some(MOTOR_WARNING_C + 1.0);
+    for _ in 0..updates {
+        harness.update();
+    }
+    assert_eq!(
+        harness.motor().torques.last(),
+        Some(&THERMAL_WARNING_LIMIT_PCT)
+    );
+    assert!(!motion_control::is_faulted());
+
+    harness.motor().temperature = Some(MOTOR_CRITICAL_C);
+    for _ in 0..updates {
+        harness.update();
+    }
+    assert!(motion_control::is_faulted());
+    assert_eq!(get_motion_state().fault, Some(FaultCode::Overheat));
+    harness.finish_move();
+    assert_eq!(arm_at(Instant::from_ticks(0)), Err(ArmError::Overheated));
+
+    harness.motor().temperature = Some(MOTOR_WARNING_C - THERMAL_HYSTERESIS_C);
+    for _ in 0..updates {
+        harness.update();
+    }
+    arm_at(Instant::from_ticks(0)).unwrap();
+    assert!(!is_fault_active(FaultCode::Overheat));
+    harness.update();
+    assert_eq!(harness.motor().torques.last(), Some(&100.0));
+
+    motion_control::set_torque_slew_ms(TORQUE_SLEW_TIME_MS).unwrap();
+}
+
+#[test]
+fn additional_axes_reach_their_target_and_are_rejected_while_faulted() {
+    let _lock = lock();
+    let timer = FakeTimer::default();
+    let mut motion_control: MotionControl<_, _, _, 2> =
+        MotionControl::new(TwoAxisMotor::default(), timer.clone());
+    let finish_move = |motion_control: &mut MotionControl<_, _, _, 2>| {
+        while motion_control::is_move_in_progress() {
+            timer.advance_ms(MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS);
+            motion_control.update_handler();
+        }
+    };
+    let twist = &ADDITIONAL_AXIS_LIMITS[0];
+    arm_at(Instant::from_ticks(0)).unwrap();
+
+    motion_control::set_axis_target_position(1, 90.0).unwrap();
+    assert_eq!(
+        motion_control::set_axis_target_position(2, 0.0),
+        Err(AxisTargetError::Value(ValueError::Unknown))
+    );
+    finish_move(&mut motion_control);
+    assert_eq!(
+        motion_control.motor_mut().steps[1].last(),
+        Some(&((90.0 * twist.steps_per_unit) as i32))
+    );
+
+    motion_control::emergency_stop();
+    assert_eq!(
+        motion_control::set_axis_target_position(1, 0.0),
+        Err(AxisTargetError::Faulted)
+    );
+    assert_eq!(
+        motion_control::set_axis_target_position(0, MIN_MOVE_MM),
+        Err(AxisTargetError::Faulted)
+    );
+    finish_move(&mut motion_control);
+    arm_at(Instant::from_ticks(0)).unwrap();
+
+    motion_control::set_axis_target_position(1, 0.0).unwrap();
+    finish_move(&mut motion_control);
+}
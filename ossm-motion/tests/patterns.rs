@@ -0,0 +1,409 @@
+mod common;
+
+use embassy_time::{Duration, Instant};
+use ossm_motion::{
+    config::{EDGING_BUILD_STROKES, MAX_BPM},
+    float::Real,
+    motion_control::{get_max_acceleration, get_min_move_mm},
+    pattern::{
+        PATTERN_ID_EDGING, PATTERN_ID_INSIST, PATTERN_ID_JACK_HAMMER, PATTERN_ID_LOAD_ADAPTIVE,
+        PATTERN_ID_SIMPLE, PATTERN_ID_TORQUE, PATTERN_ID_WARM_UP, Pattern, PatternExecutor,
+        PatternInput,
+        custom::Custom,
+        dwell::{Dwell, get_dwell, set_dwell_depth_ms, set_dwell_ms, set_dwell_retract_ms},
+        m5_index_from_pattern_id,
+        params::{get_pattern_params_json, reset_pattern_params, set_pattern_param},
+        pattern_id_from_m5_index,
+        registry::{RegistryError, register_pattern},
+    },
+    validation::ValueError,
+};
+
+use common::lock;
+
+const INPUT: PatternInput = PatternInput {
+    depth: 100.0,
+    motion_length: 80.0,
+    velocity: 400.0,
+    sensation: 0.0,
+    load: 0.0,
+    bpm: 0.0,
+    now: Instant::from_ticks(0),
+    stroke_index: 0,
+    elapsed_ms: 0,
+};
+
+// For values that are calculated rather than set
+const MOVE_TOLERANCE: Real = 1e-3;
+
+/// The next `count` moves of a pattern as (velocity, position) with the min move taken out
+fn moves(executor: &mut PatternExecutor, input: &PatternInput, count: usize) -> Vec<(Real, Real)> {
+    let min_move = get_min_move_mm();
+    (0..count)
+        .map(|_| {
+            let pattern_move = executor.next_move(input);
+            (pattern_move.velocity, pattern_move.position - min_move)
+        })
+        .collect()
+}
+
+fn assert_moves_close(actual: &[(Real, Real)], expected: &[(Real, Real)]) {
+    let close = actual.len() == expected.len()
+        && actual
+            .iter()
+            .zip(expected)
+            .all(|(a, e)| (a.0 - e.0).abs() < MOVE_TOLERANCE && (a.1 - e.1).abs() < MOVE_TOLERANCE);
+    assert!(close, "{actual:?} instead of {expected:?}");
+}
+
+fn executor_with(id: u32) -> PatternExecutor {
+    let mut executor = PatternExecutor::new();
+    assert!(executor.has_pattern(id));
+    executor.set_pattern(id);
+    executor.reset();
+    executor
+}
+
+#[test]
+fn insist_lengthens_the_strokes_in_the_same_time() {
+    let mut executor = executor_with(PATTERN_ID_INSIST);
+    let input = PatternInput {
+        sensation: 100.0,
+        ..INPUT
+    };
+
+    assert_eq!(
+        moves(&mut executor, &input, 6),
+        [
+            (200.0, 100.0),
+            (200.0, 60.0),
+            (400.0, 100.0),
+            (400.0, 20.0),
+            (200.0, 100.0),
+            (200.0, 60.0),
+        ]
+    );
+
+    // Lower sensation takes more strokes to reach the full length
+    let input = PatternInput {
+        sensation: -100.0,
+        ..INPUT
+    };
+    let strokes = moves(&mut executor, &input, 40);
+    assert_eq!(strokes[1], (20.0, 96.0));
+    assert_eq!(strokes[39], (400.0, 20.0));
+    for (velocity, position) in strokes.iter().skip(1).step_by(2) {
+        // Duration of the full stroke at the set velocity
+        let duration = (INPUT.depth - position) / velocity;
+        assert!((duration - 0.2).abs() < 1e-6, "{duration}");
+    }
+}
+
+#[test]
+fn jack_hammer_thrusts_at_the_depth_then_withdraws_slowly() {
+    let _lock = lock();
+    reset_pattern_params();
+    let mut executor = executor_with(PATTERN_ID_JACK_HAMMER);
+    let input = PatternInput {
+        sensation: -100.0,
+        ..INPUT
+    };
+
+    let series = [(400.0, 100.0), (400.0, 84.0), (400.0, 100.0), (100.0, 20.0)];
+    assert_eq!(moves(&mut executor, &input, 8), [series, series].concat());
+
+    // Higher sensation thrusts more often before withdrawing
+    let input = PatternInput {
+        sensation: 100.0,
+        ..INPUT
+    };
+    let series = moves(&mut executor, &input, 20);
+    let thrusts = series.iter().filter(|(_, position)| *position == 100.0);
+    assert_eq!(thrusts.count(), 10);
+    assert_eq!(series[19], (100.0, 20.0));
+}
+
+#[test]
+fn jack_hammer_approaches_the_depth_slowly_when_started() {
+    let _lock = lock();
+    reset_pattern_params();
+    let mut executor = executor_with(PATTERN_ID_JACK_HAMMER);
+    let input = PatternInput {
+        sensation: -100.0,
+        ..INPUT
+    };
+
+    executor.on_start(&input);
+    assert_eq!(
+        moves(&mut executor, &input, 5),
+        [
+            (100.0, 100.0),
+            (400.0, 84.0),
+            (400.0, 100.0),
+            (100.0, 20.0),
+            (400.0, 100.0)
+        ]
+    );
+
+    // Stopped before the first move
+    executor.reset();
+    executor.on_start(&input);
+    executor.on_stop();
+    assert_eq!(moves(&mut executor, &input, 1), [(400.0, 100.0)]);
+}
+
+#[test]
+fn edging_builds_up_the_velocity_before_pausing() {
+    // No dwell adds to the delays
+    let _lock = lock();
+    let mut executor = executor_with(PATTERN_ID_EDGING);
+    // Always pauses
+    let input = PatternInput {
+        sensation: 100.0,
+        ..INPUT
+    };
+
+    for _ in 0..2 {
+        let strokes = EDGING_BUILD_STROKES as usize;
+        let series: Vec<_> = (0..2 * strokes)
+            .map(|_| executor.next_move(&input))
+            .collect();
+
+        assert_eq!(series[0].velocity, 120.0);
+        assert!(
+            series[..2 * strokes - 1]
+                .windows(2)
+                .all(|w| w[0].velocity <= w[1].velocity)
+        );
+        assert!(series[..2 * strokes - 2].iter().all(|m| m.delay_ms == 0));
+
+        // The pause is at the depth after the last stroke
+        let pause = series[2 * strokes - 2];
+        assert_eq!(pause.velocity, 400.0);
+        assert_eq!(pause.position - get_min_move_mm(), 100.0);
+        assert!(
+            (7500..22500).contains(&pause.delay_ms),
+            "{}",
+            pause.delay_ms
+        );
+    }
+}
+
+#[test]
+fn warm_up_grows_to_the_setpoints_over_time() {
+    let mut executor = executor_with(PATTERN_ID_WARM_UP);
+    let at = |seconds| PatternInput {
+        now: INPUT.now + Duration::from_secs(seconds),
+        elapsed_ms: seconds * 1000,
+        ..INPUT
+    };
+
+    // 330 s at the default sensation
+    assert_moves_close(
+        &moves(&mut executor, &at(0), 2),
+        &[(80.0, 44.0), (80.0, 20.0)],
+    );
+    assert_moves_close(
+        &moves(&mut executor, &at(165), 2),
+        &[(240.0, 72.0), (240.0, 20.0)],
+    );
+    assert_moves_close(
+        &moves(&mut executor, &at(330), 2),
+        &[(400.0, 100.0), (400.0, 20.0)],
+    );
+    assert_moves_close(&moves(&mut executor, &at(1000), 1), &[(400.0, 100.0)]);
+
+    // Starts over when the pattern is started again
+    executor.reset();
+    assert_moves_close(&moves(&mut executor, &at(2000), 1), &[(80.0, 44.0)]);
+}
+
+#[test]
+fn load_adaptive_backs_off_against_resistance() {
+    let mut executor = executor_with(PATTERN_ID_LOAD_ADAPTIVE);
+    let with_load = |load| PatternInput { load, ..INPUT };
+
+    // The target at the default sensation is 50%
+    assert_eq!(
+        moves(&mut executor, &with_load(100.0), 6),
+        [
+            (400.0, 100.0),
+            (400.0, 20.0),
+            (400.0, 96.0),
+            (400.0, 20.0),
+            (400.0, 92.0),
+            (400.0, 20.0),
+        ]
+    );
+    // Holds within the deadband and pushes deeper slowly when the load drops
+    assert_eq!(moves(&mut executor, &with_load(55.0), 2)[0], (400.0, 88.0));
+    assert_eq!(moves(&mut executor, &with_load(0.0), 2)[0], (400.0, 88.0));
+    assert_eq!(moves(&mut executor, &with_load(0.0), 2)[0], (400.0, 89.0));
+
+    // Never backs off more than half the stroke
+    let strokes = moves(&mut executor, &with_load(100.0), 40);
+    assert_eq!(strokes[38], (400.0, 60.0));
+}
+
+#[test]
+fn tempo_stays_the_same_when_the_stroke_changes() {
+    let mut executor = executor_with(PATTERN_ID_SIMPLE);
+    let acceleration = get_max_acceleration();
+
+    for motion_length in [20.0, 40.0, 80.0] {
+        let input = PatternInput {
+            motion_length,
+            bpm: 60.0,
+            ..INPUT
+        };
+        let (velocity, _) = moves(&mut executor, &input, 1)[0];
+        // A move in and one out per stroke. Accelerating to the velocity and back adds v/a
+        let move_time = motion_length / velocity + velocity / acceleration;
+        assert!((move_time - 0.5).abs() < 1e-6, "{move_time}");
+    }
+
+    // Never faster than the velocity limit
+    let input = PatternInput {
+        velocity: 100.0,
+        bpm: MAX_BPM as Real,
+        ..INPUT
+    };
+    assert_eq!(moves(&mut executor, &input, 1)[0].0, 100.0);
+}
+
+#[test]
+fn dwell_holds_at_the_ends_of_the_strokes() {
+    let _lock = lock();
+    set_dwell_depth_ms(300).unwrap();
+    set_dwell_retract_ms(100).unwrap();
+
+    let mut executor = executor_with(PATTERN_ID_SIMPLE);
+    let delays: Vec<_> = (0..4)
+        .map(|_| executor.next_move(&INPUT).delay_ms)
+        .collect();
+    assert_eq!(delays, [300, 100, 300, 100]);
+
+    // Jack hammer only holds after the withdrawal
+    let mut executor = executor_with(PATTERN_ID_JACK_HAMMER);
+    let input = PatternInput {
+        sensation: -100.0,
+        ..INPUT
+    };
+    let delays: Vec<_> = (0..4)
+        .map(|_| executor.next_move(&input).delay_ms)
+        .collect();
+    assert_eq!(delays, [0, 0, 0, 100]);
+
+    assert_eq!(
+        set_dwell_ms(10_000),
+        Err(ValueError::OutOfRange { accepted: 5000 })
+    );
+    assert_eq!(
+        get_dwell(),
+        Dwell {
+            depth_ms: 5000,
+            retract_ms: 5000
+        }
+    );
+
+    set_dwell_ms(0).unwrap();
+}
+
+#[test]
+fn pattern_params_are_passed_to_the_pattern() {
+    let _lock = lock();
+    reset_pattern_params();
+    let mut executor = executor_with(PATTERN_ID_JACK_HAMMER);
+
+    assert_eq!(set_pattern_param(PATTERN_ID_JACK_HAMMER, 0, 40.0), Ok(()));
+    assert_eq!(moves(&mut executor, &INPUT, 2)[1], (400.0, 68.0));
+
+    // Clamped to the range of the parameter
+    assert_eq!(
+        set_pattern_param(PATTERN_ID_JACK_HAMMER, 0, 80.0),
+        Err(ValueError::OutOfRange { accepted: 50 })
+    );
+    assert_eq!(moves(&mut executor, &INPUT, 2)[1], (400.0, 60.0));
+    assert!(
+        get_pattern_params_json(PATTERN_ID_JACK_HAMMER)
+            .contains(r#"{"name":"thrustLength","min":5,"max":50,"default":20,"value":50}"#)
+    );
+
+    assert_eq!(
+        set_pattern_param(PATTERN_ID_JACK_HAMMER, 1, 10.0),
+        Err(ValueError::Unknown)
+    );
+    assert_eq!(
+        set_pattern_param(PATTERN_ID_INSIST, 0, 10.0),
+        Err(ValueError::Unknown)
+    );
+    assert_eq!(
+        get_pattern_params_json(PATTERN_ID_INSIST),
+        r#"{"pattern":7,"params":[]}"#
+    );
+
+    reset_pattern_params();
+}
+
+#[test]
+fn patterns_can_be_registered_at_runtime() {
+    // Not used by any built-in pattern
+    const ID: u32 = 1000;
+    let mut executor = PatternExecutor::new();
+    assert!(!executor.has_pattern(ID));
+
+    assert_eq!(register_pattern(ID, || Custom::new().into()), Ok(()));
+    assert!(PatternExecutor::new().has_pattern(ID));
+    assert!(
+        PatternExecutor::new()
+            .get_all_patterns_json()
+            .ends_with(r#"{"name":"Custom","idx":1000}]"#)
+    );
+    // Executors that already exist pick it up when it is selected
+    executor.set_pattern(ID);
+    assert!(executor.has_pattern(ID));
+    assert_eq!(executor.get_current_pattern_name(), "Custom");
+
+    assert_eq!(
+        register_pattern(ID, || Custom::new().into()),
+        Err(RegistryError::DuplicateId)
+    );
+    assert_eq!(
+        register_pattern(PATTERN_ID_SIMPLE, || Custom::new().into()),
+        Err(RegistryError::DuplicateId)
+    );
+}
+
+#[test]
+fn the_pattern_count_matches_the_pattern_list() {
+    let mut executor = PatternExecutor::new();
+    let json = executor.get_all_patterns_json();
+    assert_eq!(
+        executor.get_pattern_count(),
+        json.matches(r#""idx":"#).count()
+    );
+}
+
+#[test]
+fn the_pattern_list_has_the_ids_and_names() {
+    let mut executor = PatternExecutor::new();
+    let mut list = executor.get_pattern_list();
+    assert_eq!(list.next(), Some((PATTERN_ID_SIMPLE, "Simple Stroke")));
+    assert_eq!(list.count() + 1, executor.get_pattern_count());
+}
+
+#[test]
+fn all_patterns_fit_into_the_pattern_list() {
+    let json = PatternExecutor::new().get_all_patterns_json();
+    assert!(json.ends_with("}]"), "{json}");
+}
+
+#[test]
+fn m5_menu_indices_keep_their_patterns() {
+    assert_eq!(pattern_id_from_m5_index(0), Some(PATTERN_ID_SIMPLE));
+    assert_eq!(pattern_id_from_m5_index(6), Some(PATTERN_ID_TORQUE));
+    assert_eq!(m5_index_from_pattern_id(PATTERN_ID_TORQUE), Some(6));
+    // Appended after the patterns the M5 menu already had
+    assert_eq!(m5_index_from_pattern_id(PATTERN_ID_INSIST), Some(7));
+    assert_eq!(m5_index_from_pattern_id(PATTERN_ID_JACK_HAMMER), None);
+}
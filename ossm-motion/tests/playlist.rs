@@ -0,0 +1,109 @@
+mod common;
+
+use embassy_time::Instant;
+use ossm_motion::{
+    motion::playlist::{
+        PlaylistEntry, PlaylistError, PlaylistRunner, add_playlist_entry, clear_playlist,
+        get_current_playlist_entry, is_playlist_active, remove_playlist_entry, set_playlist_repeat,
+        skip_playlist_entry, start_playlist,
+    },
+    pattern::{PATTERN_ID_DEEPER, PATTERN_ID_SIMPLE, PATTERN_ID_TORQUE},
+};
+
+use common::{lock, motion_state};
+
+fn entry(pattern: u32, duration_s: u32, sensation: u32) -> PlaylistEntry {
+    PlaylistEntry {
+        pattern,
+        duration_s,
+        sensation,
+    }
+}
+
+#[test]
+fn invalid_entries_are_rejected() {
+    let _lock = lock();
+    clear_playlist();
+
+    assert_eq!(start_playlist(), Err(PlaylistError::Empty));
+    assert_eq!(
+        add_playlist_entry(entry(1000, 10, 50)),
+        Err(PlaylistError::UnknownPattern)
+    );
+    assert_eq!(
+        add_playlist_entry(entry(PATTERN_ID_DEEPER, 0, 50)),
+        Err(PlaylistError::InvalidEntry)
+    );
+    assert_eq!(
+        add_playlist_entry(entry(PATTERN_ID_DEEPER, 10, 101)),
+        Err(PlaylistError::InvalidEntry)
+    );
+    assert_eq!(add_playlist_entry(entry(PATTERN_ID_DEEPER, 10, 50)), Ok(1));
+    assert_eq!(remove_playlist_entry(1), Err(PlaylistError::NoSuchEntry));
+    assert_eq!(remove_playlist_entry(0), Ok(()));
+}
+
+#[test]
+fn playlist_advances_while_the_motion_is_enabled() {
+    let _lock = lock();
+    clear_playlist();
+    set_playlist_repeat(false);
+
+    add_playlist_entry(entry(PATTERN_ID_DEEPER, 10, 20)).unwrap();
+    add_playlist_entry(entry(PATTERN_ID_TORQUE, 30, 80)).unwrap();
+    add_playlist_entry(entry(PATTERN_ID_SIMPLE, 30, 50)).unwrap();
+    start_playlist().unwrap();
+
+    let mut runner = PlaylistRunner::new();
+    let mut apply = |motion_enabled: bool, at_s: u64| {
+        let mut state = motion_state(PATTERN_ID_SIMPLE, motion_enabled);
+        runner.apply(&mut state, Instant::from_secs(at_s));
+        state
+    };
+
+    let state = apply(true, 0);
+    assert_eq!((state.pattern, state.sensation), (PATTERN_ID_DEEPER, 20));
+    assert_eq!(apply(true, 5).pattern, PATTERN_ID_DEEPER);
+
+    // The time with the motion disabled does not count
+    apply(false, 20);
+    assert_eq!(apply(true, 24).pattern, PATTERN_ID_DEEPER);
+
+    let state = apply(true, 26);
+    assert_eq!((state.pattern, state.sensation), (PATTERN_ID_TORQUE, 80));
+    assert_eq!(get_current_playlist_entry(), 1);
+
+    skip_playlist_entry();
+    assert_eq!(apply(true, 27).pattern, PATTERN_ID_SIMPLE);
+
+    // Done after the last entry
+    let state = apply(true, 57);
+    assert!(!state.motion_enabled);
+    assert!(!is_playlist_active());
+}
+
+#[test]
+fn playlist_repeats_from_the_first_entry() {
+    let _lock = lock();
+    clear_playlist();
+    set_playlist_repeat(true);
+
+    add_playlist_entry(entry(PATTERN_ID_DEEPER, 10, 20)).unwrap();
+    add_playlist_entry(entry(PATTERN_ID_TORQUE, 10, 80)).unwrap();
+    start_playlist().unwrap();
+
+    let mut runner = PlaylistRunner::new();
+    let mut state = motion_state(PATTERN_ID_SIMPLE, true);
+    runner.apply(&mut state, Instant::from_secs(0));
+    runner.apply(&mut state, Instant::from_secs(10));
+    assert_eq!(state.pattern, PATTERN_ID_TORQUE);
+
+    let mut state = motion_state(PATTERN_ID_SIMPLE, true);
+    runner.apply(&mut state, Instant::from_secs(20));
+    assert_eq!(state.pattern, PATTERN_ID_DEEPER);
+    assert!(state.motion_enabled);
+    assert!(is_playlist_active());
+
+    set_playlist_repeat(false);
+    clear_playlist();
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use std::f64::consts::PI;
+
+use ossm_motion::{
+    config::{
+        MAX_CALIBRATED_TRAVEL_MM, MIN_MOVE_MM, MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS,
+        MOTION_CONTROL_MAX_ACCELERATION, STEPS_PER_MM,
+    },
+    float::Real,
+    motion_control::{self, sinusoid::Sinusoid},
+};
+
+use common::{Harness, lock};
+
+const POSITION_TOLERANCE_MM: Real = 0.05;
+const VELOCITY_TOLERANCE_MM_S: Real = 0.5;
+
+// Runs with both f64 and the `f32` feature. The planner is f64 either way, so this checks the
+// conversions around it
+#[test]
+fn move_reaches_the_target_within_tolerance() {
+    let _lock = lock();
+    let mut harness = Harness::new();
+
+    let max_velocity = 200.0;
+    let target = MIN_MOVE_MM + 60.0;
+    motion_control::set_max_velocity(max_velocity);
+    motion_control::set_target_position(target);
+    harness.finish_move();
+
+    let positions = harness.recorder.positions.borrow().clone();
+    assert!(!positions.is_empty());
+    for pair in positions.windows(2) {
+        assert!(
+            pair[1] >= pair[0] - POSITION_TOLERANCE_MM,
+            "Moved backwards from {} to {}",
+            pair[0],
+            pair[1]
+        );
+    }
+
+    let last = *positions.last().unwrap();
+    assert!(
+        (last - target).abs() < POSITION_TOLERANCE_MM,
+        "Ended at {last} instead of {target}"
+    );
+    assert!(
+        (motion_control::get_actual_position_mm() - target).abs() < POSITION_TOLERANCE_MM,
+        "Reported {} instead of {target}",
+        motion_control::get_actual_position_mm()
+    );
+
+    for velocity in harness.recorder.velocities.borrow().iter() {
+        assert!(
+            *velocity <= max_velocity + VELOCITY_TOLERANCE_MM_S,
+            "Velocity {velocity} exceeds {max_velocity}"
+        );
+    }
+
+    // The motor gets the same position as the planner in steps
+    let steps = *harness.motor().steps.last().unwrap() as Real;
+    let written = steps.abs() / STEPS_PER_MM;
+    assert!(
+        (written - target).abs() < 1.0 / STEPS_PER_MM + POSITION_TOLERANCE_MM,
+        "Wrote {written} instead of {target}"
+    );
+}
+
+#[test]
+// `Real` is f64 unless the `f32` feature is on
+#[allow(clippy::unnecessary_cast)]
+fn sinusoid_matches_the_f64_reference() {
+    // Slow across the longest rail so that the time adds up over thousands of updates
+    let start = MIN_MOVE_MM;
+    let target = MIN_MOVE_MM + MAX_CALIBRATED_TRAVEL_MM;
+    let max_velocity = 20.0;
+    let sinusoid = Sinusoid::new(start, target, max_velocity, MOTION_CONTROL_MAX_ACCELERATION);
+
+    // The velocity bounds the duration of a move this slow
+    let half_distance = (target - start) as f64 / 2.0;
+    let duration = PI * half_distance / max_velocity as f64;
+    assert!(
+        (sinusoid.duration() as f64 - duration).abs() < 1e-3,
+        "Lasts {} s instead of {duration} s",
+        sinusoid.duration()
+    );
+
+    // Stepped like motion control does it
+    let interval = MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS as Real / 1000.0;
+    let mut time: Real = 0.0;
+    let mut update = 0;
+    while (time as f64) < duration {
+        update += 1;
+        time += interval;
+
+        let exact_time = update as f64 * MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS as f64 / 1000.0;
+        let expected = if exact_time >= duration {
+            target as f64
+        } else {
+            start as f64 + half_distance * (1.0 - (PI / duration * exact_time).cos())
+        };
+        let position = sinusoid.at(time).position;
+        assert!(
+            (position as f64 - expected).abs() < POSITION_TOLERANCE_MM as f64,
+            "At update {update} the position is {position} instead of {expected}"
+        );
+
+        // What the motor gets
+        let steps = (position * STEPS_PER_MM) as i32;
+        let expected_steps = (expected * STEPS_PER_MM as f64) as i32;
+        assert!(
+            ((steps - expected_steps).abs() as Real) < POSITION_TOLERANCE_MM * STEPS_PER_MM + 1.0,
+            "At update {update} {steps} steps are written instead of {expected_steps}"
+        );
+    }
+    assert_eq!(sinusoid.at(time).position, target);
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use ossm_motion::{
+    config::MAX_PRESETS,
+    motion::motion_state::{
+        get_motion_state, get_motion_torque_pct, set_motion_depth_pct, set_motion_length_pct,
+        set_motion_torque_forward_pct, set_motion_velocity_pct,
+    },
+    preset::{
+        PresetError, delete_preset, get_presets, get_presets_json, load_next_preset, load_preset,
+        save_preset, set_presets,
+    },
+};
+
+use common::lock;
+
+#[test]
+fn presets_recall_the_saved_settings() {
+    let _lock = lock();
+    set_presets(&[]);
+
+    set_motion_depth_pct(80).unwrap();
+    set_motion_length_pct(40).unwrap();
+    set_motion_velocity_pct(20).unwrap();
+    set_motion_torque_forward_pct(60).unwrap();
+    save_preset("slow").unwrap();
+    set_motion_velocity_pct(90).unwrap();
+    save_preset("fast").unwrap();
+
+    load_preset("slow").unwrap();
+    let state = get_motion_state();
+    assert_eq!(
+        (state.depth, state.motion_length, state.velocity),
+        (80, 40, 20)
+    );
+    assert_eq!(get_motion_torque_pct(true), 60);
+
+    // Continues after the one loaded last
+    load_next_preset().unwrap();
+    assert_eq!(get_motion_state().velocity, 90);
+    load_next_preset().unwrap();
+    assert_eq!(get_motion_state().velocity, 20);
+
+    // Saving under the same name replaces it
+    set_motion_velocity_pct(50).unwrap();
+    save_preset("fast").unwrap();
+    assert_eq!(get_presets().len(), 2);
+    let json = get_presets_json();
+    assert!(
+        json.contains(r#"{"name":"fast","depth":80,"stroke":40,"speed":50,"#),
+        "{json}"
+    );
+
+    delete_preset("slow").unwrap();
+    assert_eq!(delete_preset("slow"), Err(PresetError::Unknown));
+    assert_eq!(load_preset("slow"), Err(PresetError::Unknown));
+    set_motion_velocity_pct(0).unwrap();
+    load_next_preset().unwrap();
+    assert_eq!(get_motion_state().velocity, 50);
+
+    set_presets(&[]);
+    assert_eq!(load_next_preset(), Err(PresetError::Unknown));
+    set_motion_torque_forward_pct(100).unwrap();
+}
+
+#[test]
+fn preset_names_and_count_are_limited() {
+    let _lock = lock();
+    set_presets(&[]);
+
+    assert_eq!(save_preset(""), Err(PresetError::InvalidName));
+    assert_eq!(save_preset("a:b"), Err(PresetError::InvalidName));
+    assert_eq!(save_preset(r#"a"b"#), Err(PresetError::InvalidName));
+    assert_eq!(
+        save_preset("a name that is too long"),
+        Err(PresetError::InvalidName)
+    );
+
+    for i in 0..MAX_PRESETS {
+        save_preset(&format!("preset {i}")).unwrap();
+    }
+    assert_eq!(save_preset("one more"), Err(PresetError::Full));
+    assert_eq!(get_presets_json().matches("name").count(), MAX_PRESETS);
+
+    set_presets(&[]);
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use embassy_time::Instant;
+use ossm_motion::{
+    motion::motion_state::{
+        get_motion_state, set_motion_enabled, set_motion_pattern, set_motion_velocity_pct,
+    },
+    motion_control::{
+        arming::{arm_at, disarm},
+        get_torque_pct, set_torque,
+    },
+    profile::{
+        ProfileError, ProfileLimits, get_active_limits, get_active_profile, get_profiles,
+        restore_profiles, select_profile, set_profile_limits, set_profile_name, set_profile_pin,
+        set_profile_preferences,
+    },
+    validation::ValueError,
+};
+
+use common::lock;
+
+const GUEST_LIMITS: ProfileLimits = ProfileLimits {
+    velocity: 40,
+    depth: 60,
+    torque: 30,
+};
+
+#[test]
+fn switching_profiles_applies_their_limits_and_preferences() {
+    let _lock = lock();
+    select_profile(0, None).unwrap();
+    set_motion_velocity_pct(100).unwrap();
+    set_motion_pattern(0).unwrap();
+    set_profile_limits(1, GUEST_LIMITS, None).unwrap();
+    set_profile_preferences(1, 70, 1, None).unwrap();
+
+    select_profile(1, None).unwrap();
+    assert_eq!(get_active_profile(), 1);
+    let state = get_motion_state();
+    assert_eq!(
+        (state.velocity, state.sensation, state.pattern),
+        (40, 70, 1)
+    );
+    assert_eq!(
+        set_motion_velocity_pct(80),
+        Err(ValueError::OutOfRange { accepted: 40 })
+    );
+    set_torque(100.0);
+    assert_eq!(get_torque_pct(), 30.0);
+
+    // Back to the unrestricted default
+    select_profile(0, None).unwrap();
+    assert_eq!(get_active_limits(), ProfileLimits::unrestricted());
+    set_motion_velocity_pct(80).unwrap();
+    set_torque(100.0);
+    assert_eq!(get_torque_pct(), 100.0);
+}
+
+#[test]
+fn profiles_are_locked_with_a_pin_and_kept_while_moving() {
+    let _lock = lock();
+    select_profile(0, None).unwrap();
+    set_profile_pin(1, Some(4321), None).unwrap();
+
+    assert_eq!(select_profile(1, None), Err(ProfileError::WrongPin));
+    assert_eq!(
+        set_profile_limits(1, ProfileLimits::unrestricted(), Some(1)),
+        Err(ProfileError::WrongPin)
+    );
+    assert_eq!(select_profile(7, None), Err(ProfileError::InvalidIndex));
+
+    arm_at(Instant::from_ticks(0)).unwrap();
+    set_motion_enabled(true);
+    assert_eq!(
+        select_profile(1, Some(4321)),
+        Err(ProfileError::MotionEnabled)
+    );
+    set_motion_enabled(false);
+    disarm();
+
+    select_profile(1, Some(4321)).unwrap();
+    select_profile(0, None).unwrap();
+    set_profile_pin(1, None, Some(4321)).unwrap();
+}
+
+#[test]
+fn new_profiles_take_the_limits_and_pin_of_the_active_one() {
+    let _lock = lock();
+    select_profile(0, None).unwrap();
+    let saved = get_profiles();
+    set_profile_limits(1, GUEST_LIMITS, None).unwrap();
+    set_profile_pin(1, Some(4321), None).unwrap();
+    select_profile(1, Some(4321)).unwrap();
+
+    let index = saved.len();
+    assert_eq!(
+        set_profile_name(index, "Friend", None),
+        Err(ProfileError::WrongPin)
+    );
+    assert_eq!(
+        set_profile_name(index, "Fr\"end", Some(4321)),
+        Err(ProfileError::InvalidName)
+    );
+    set_profile_name(index, "Friend", Some(4321)).unwrap();
+    let created = &get_profiles()[index];
+    assert_eq!(created.name.as_str(), "Friend");
+    assert_eq!((created.limits, created.pin), (GUEST_LIMITS, Some(4321)));
+
+    restore_profiles(&saved, 0);
+}
+
+#[test]
+fn restored_profiles_activate_the_stored_one() {
+    let _lock = lock();
+    select_profile(0, None).unwrap();
+    let saved = get_profiles();
+
+    let mut stored = saved.clone();
+    stored[1].limits = GUEST_LIMITS;
+    stored[1].pin = Some(99);
+    restore_profiles(&stored, 1);
+    assert_eq!(get_profiles(), stored);
+    assert_eq!(get_active_profile(), 1);
+    assert_eq!(get_active_limits(), GUEST_LIMITS);
+    assert!(get_motion_state().velocity <= GUEST_LIMITS.velocity);
+
+    // An index that does not exist anymore falls back to the first profile
+    restore_profiles(&stored, 5);
+    assert_eq!(get_active_profile(), 0);
+    assert_eq!(get_active_limits(), ProfileLimits::unrestricted());
+
+    // Nothing stored keeps the defaults
+    restore_profiles(&[], 1);
+    assert_eq!(get_active_profile(), 0);
+
+    restore_profiles(&saved, 0);
+}
@@ -0,0 +1,90 @@
+use ossm_motion::{
+    config::{
+        CALIBRATION_STALL_CURRENT_A, MAX_NO_REMOTE_HEARTBEAT_MS, RETRACT_VELOCITY,
+        REVERSE_DIRECTION, STATE_NOTIFY_INTERVAL_MS,
+    },
+    float::Real,
+    motion_control::set_direction_reversed,
+    runtime_config::{
+        get_config_json, get_heartbeat_timeout_ms, get_retract_on_disable, get_retract_velocity,
+        get_stall_current_a, get_state_interval_ms, get_state_on_change, set_config_value,
+    },
+    validation::ValueError,
+};
+
+#[test]
+fn config_values_are_validated_and_applied() {
+    assert_eq!(set_config_value("retractOnDisable", 0.0), Ok(()));
+    assert!(!get_retract_on_disable());
+    assert_eq!(
+        set_config_value("retractOnDisable", 2.0),
+        Err(ValueError::Unknown)
+    );
+    assert!(!get_retract_on_disable());
+
+    assert_eq!(set_config_value("retractVelocity", 100.0), Ok(()));
+    assert_eq!(get_retract_velocity(), 100.0);
+    assert_eq!(
+        set_config_value("retractVelocity", 1000.0),
+        Err(ValueError::OutOfRange { accepted: 600 })
+    );
+
+    assert_eq!(
+        set_config_value("heartbeatTimeoutMs", 500.0),
+        Err(ValueError::OutOfRange { accepted: 2000 })
+    );
+    assert_eq!(get_heartbeat_timeout_ms(), 2000);
+    assert_eq!(
+        set_config_value("maxAcceleration", Real::NAN),
+        Err(ValueError::NotANumber)
+    );
+    assert_eq!(
+        set_config_value("unknownKey", 1.0),
+        Err(ValueError::Unknown)
+    );
+
+    assert_eq!(set_config_value("logLevel", 2.0), Ok(()));
+    assert_eq!(log::max_level(), log::LevelFilter::Warn);
+    assert_eq!(set_config_value("logLevel", 6.0), Err(ValueError::Unknown));
+    assert_eq!(log::max_level(), log::LevelFilter::Warn);
+
+    assert_eq!(
+        set_config_value("stateIntervalMs", 10.0),
+        Err(ValueError::OutOfRange { accepted: 50 })
+    );
+    assert_eq!(get_state_interval_ms(), 50);
+    assert_eq!(set_config_value("stateOnChange", 1.0), Ok(()));
+    assert!(get_state_on_change());
+
+    assert_eq!(set_config_value("stallCurrentA", 1.5), Ok(()));
+    assert_eq!(get_stall_current_a(), 1.5);
+    assert_eq!(
+        set_config_value("stallCurrentA", 20.0),
+        Err(ValueError::OutOfRange { accepted: 10 })
+    );
+
+    let config = get_config_json();
+    assert!(
+        config.contains(r#""retractOnDisable":0,"retractVelocity":600.0,"#),
+        "{config}"
+    );
+    assert!(config.contains(r#""heartbeatTimeoutMs":2000,"#), "{config}");
+    assert!(config.contains(r#""logLevel":2,"#), "{config}");
+    assert!(
+        config.contains(r#""stateIntervalMs":50,"stateOnChange":1,"#),
+        "{config}"
+    );
+
+    // Set by the firmware as it has to home again
+    set_direction_reversed(true);
+    assert!(get_config_json().ends_with(r#""reverseDirection":1}"#));
+    set_direction_reversed(REVERSE_DIRECTION);
+
+    set_config_value("retractOnDisable", 1.0).unwrap();
+    set_config_value("retractVelocity", RETRACT_VELOCITY).unwrap();
+    set_config_value("heartbeatTimeoutMs", MAX_NO_REMOTE_HEARTBEAT_MS as Real).unwrap();
+    set_config_value("stateIntervalMs", STATE_NOTIFY_INTERVAL_MS as Real).unwrap();
+    set_config_value("stateOnChange", 0.0).unwrap();
+    set_config_value("logLevel", 3.0).unwrap();
+    set_config_value("stallCurrentA", CALIBRATION_STALL_CURRENT_A).unwrap();
+}
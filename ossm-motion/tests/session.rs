@@ -0,0 +1,42 @@
+mod common;
+
+use ossm_motion::{
+    config::{MAX_TRAVEL_MM, MIN_MOVE_MM},
+    motion::motion_state::{
+        set_motion_depth_pct, set_motion_length_pct, set_motion_pattern, set_motion_velocity_pct,
+    },
+    motion_control::{get_max_move_mm, set_max_move_mm, set_max_travel_mm, set_min_move_mm},
+    session::Session,
+};
+
+use common::lock;
+
+#[test]
+fn session_is_restored_within_the_travel() {
+    let _lock = lock();
+    set_max_travel_mm(MAX_TRAVEL_MM).unwrap();
+    set_min_move_mm(MIN_MOVE_MM + 10.0).unwrap();
+    set_max_move_mm(MIN_MOVE_MM + 150.0).unwrap();
+    set_motion_depth_pct(70).unwrap();
+    set_motion_length_pct(30).unwrap();
+    set_motion_velocity_pct(40).unwrap();
+    set_motion_pattern(3).unwrap();
+    let session = Session::current();
+
+    // Like after a reboot
+    set_max_travel_mm(MAX_TRAVEL_MM).unwrap();
+    set_motion_depth_pct(0).unwrap();
+    set_motion_length_pct(0).unwrap();
+    set_motion_velocity_pct(0).unwrap();
+    set_motion_pattern(0).unwrap();
+    session.restore();
+    assert_eq!(Session::current(), session);
+
+    // A soft limit beyond a shorter calibrated travel
+    set_max_travel_mm(100.0).unwrap();
+    session.restore();
+    assert_eq!(get_max_move_mm(), MIN_MOVE_MM + 100.0);
+
+    set_max_travel_mm(MAX_TRAVEL_MM).unwrap();
+    set_motion_velocity_pct(0).unwrap();
+}
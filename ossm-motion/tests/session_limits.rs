@@ -0,0 +1,95 @@
+mod common;
+
+use ossm_motion::{
+    motion::motion_state::{
+        MachineMotionState, get_max_depth_mm, get_motion_state, set_motion_depth_pct,
+        set_motion_velocity_pct,
+    },
+    motion_control::set_max_move_mm,
+    profile::{ProfileLimits, set_profile_limits},
+    session_limits::{
+        SessionLimits, SessionLimitsError, restore_session_limits, set_session_limits,
+        set_session_limits_pin,
+    },
+    validation::ValueError,
+};
+
+use common::lock;
+
+#[test]
+fn session_limits_cap_the_speed_and_depth() {
+    let _lock = lock();
+    set_motion_velocity_pct(80).unwrap();
+    set_motion_depth_pct(100).unwrap();
+
+    // Half of the travel of 180 mm
+    let limits = SessionLimits {
+        velocity: 50,
+        depth_mm: 90.0,
+    };
+    set_session_limits(limits, None).unwrap();
+    let state = get_motion_state();
+    assert_eq!((state.velocity, state.depth), (50, 50));
+    assert_eq!(
+        set_motion_velocity_pct(70),
+        Err(ValueError::OutOfRange { accepted: 50 })
+    );
+    assert_eq!(get_max_depth_mm(), 90.0);
+
+    // The lower of the profile and the session limits applies
+    let profile_limits = ProfileLimits {
+        velocity: 30,
+        ..ProfileLimits::unrestricted()
+    };
+    set_profile_limits(0, profile_limits, None).unwrap();
+    assert_eq!(get_motion_state().velocity, 30);
+    set_profile_limits(0, ProfileLimits::unrestricted(), None).unwrap();
+    assert_eq!(
+        set_motion_velocity_pct(70),
+        Err(ValueError::OutOfRange { accepted: 50 })
+    );
+
+    // The depth in mm holds when the travel shrinks and grows again
+    set_max_move_mm(100.0).unwrap();
+    set_motion_depth_pct(100).unwrap();
+    set_max_move_mm(190.0).unwrap();
+    let machine_state = MachineMotionState::from(get_motion_state());
+    assert_eq!(machine_state.depth, 90.0);
+
+    restore_session_limits(SessionLimits::UNRESTRICTED, None).unwrap();
+    set_motion_velocity_pct(70).unwrap();
+    set_motion_depth_pct(100).unwrap();
+    assert_eq!(get_motion_state().depth, 100);
+}
+
+#[test]
+fn session_limits_can_be_locked_with_a_pin() {
+    let _lock = lock();
+    let limits = SessionLimits {
+        velocity: 40,
+        depth_mm: 50.0,
+    };
+
+    set_session_limits_pin(Some(1234), None).unwrap();
+    assert_eq!(
+        set_session_limits(SessionLimits::UNRESTRICTED, None),
+        Err(SessionLimitsError::WrongPin)
+    );
+    assert_eq!(
+        set_session_limits_pin(None, Some(1)),
+        Err(SessionLimitsError::WrongPin)
+    );
+    set_session_limits(limits, Some(1234)).unwrap();
+
+    let invalid = SessionLimits {
+        depth_mm: -1.0,
+        ..limits
+    };
+    assert_eq!(
+        set_session_limits(invalid, Some(1234)),
+        Err(SessionLimitsError::InvalidDepth)
+    );
+
+    set_session_limits_pin(None, Some(1234)).unwrap();
+    set_session_limits(SessionLimits::UNRESTRICTED, None).unwrap();
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use embassy_time::Instant;
+use ossm_motion::{
+    config::SHUFFLE_INTERVAL_MIN,
+    motion::shuffle::{ShuffleRunner, set_shuffle_interval_min},
+    pattern::{PATTERN_ID_DEEPER, PATTERN_ID_SHUFFLE, PatternExecutor},
+    validation::ValueError,
+};
+
+use common::{lock, motion_state};
+
+#[test]
+fn shuffle_switches_patterns_after_the_interval() {
+    let _lock = lock();
+    set_shuffle_interval_min(1).unwrap();
+
+    let mut runner = ShuffleRunner::new();
+    let mut apply = |pattern: u32, motion_enabled: bool, at_s: u64| {
+        let mut state = motion_state(pattern, motion_enabled);
+        runner.apply(&mut state, Instant::from_secs(at_s));
+        state.pattern
+    };
+
+    // Other patterns are left alone
+    assert_eq!(apply(PATTERN_ID_DEEPER, true, 0), PATTERN_ID_DEEPER);
+
+    let first = apply(PATTERN_ID_SHUFFLE, true, 0);
+    assert_ne!(first, PATTERN_ID_SHUFFLE);
+    assert!(PatternExecutor::new().has_pattern(first));
+    assert_eq!(apply(PATTERN_ID_SHUFFLE, true, 50), first);
+
+    // The time with the motion disabled does not count
+    apply(PATTERN_ID_SHUFFLE, false, 100);
+    assert_eq!(apply(PATTERN_ID_SHUFFLE, true, 105), first);
+
+    let second = apply(PATTERN_ID_SHUFFLE, true, 115);
+    assert_ne!(second, first);
+    assert_ne!(second, PATTERN_ID_SHUFFLE);
+
+    set_shuffle_interval_min(SHUFFLE_INTERVAL_MIN).unwrap();
+}
+
+#[test]
+fn shuffle_interval_is_clamped() {
+    let _lock = lock();
+
+    assert_eq!(
+        set_shuffle_interval_min(0),
+        Err(ValueError::OutOfRange { accepted: 1 })
+    );
+    assert_eq!(
+        set_shuffle_interval_min(1000),
+        Err(ValueError::OutOfRange { accepted: 60 })
+    );
+
+    set_shuffle_interval_min(SHUFFLE_INTERVAL_MIN).unwrap();
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use embassy_time::{Duration, Instant};
+use ossm_motion::{
+    config::MAX_POSITION_INTERVAL_MS,
+    float::Real,
+    motion::stream::{StreamError, get_velocity_envelope, stream_position_at},
+    motion_control::{arming::arm_at, get_target_position},
+};
+
+use common::lock;
+
+const POSITION_TOLERANCE_MM: Real = 0.01;
+
+fn assert_target(position: Real) {
+    let target = get_target_position();
+    assert!(
+        (target - position).abs() < POSITION_TOLERANCE_MM,
+        "Target {target} instead of {position}"
+    );
+}
+
+#[test]
+fn positions_are_paced_by_their_velocity_or_interval() {
+    let _lock = lock();
+    arm_at(Instant::from_ticks(0)).unwrap();
+    let envelope = get_velocity_envelope();
+    let start = Instant::from_secs(40);
+    let at = |ms: u64| start + Duration::from_millis(ms);
+
+    stream_position_at(envelope.min_position, None, start).unwrap();
+    assert_target(envelope.min_position);
+
+    // Too fast for the envelope
+    let feedback = stream_position_at(
+        envelope.max_position,
+        Some(envelope.max_velocity * 2.0),
+        at(100),
+    )
+    .unwrap();
+    assert_eq!(feedback.clamped_velocity, Some(envelope.max_velocity));
+    assert_target(envelope.max_position);
+
+    // Reached by the next position 20 ms later at 50 Hz
+    let step = envelope.max_velocity * 0.01;
+    let feedback = stream_position_at(envelope.max_position - step, None, at(120)).unwrap();
+    assert_eq!(feedback.clamped_velocity, None);
+    assert_target(envelope.max_position - step);
+
+    // A pause is not taken as the pace of the next position
+    let distance = envelope.max_velocity * MAX_POSITION_INTERVAL_MS as Real / 1000.0 * 2.0;
+    let feedback =
+        stream_position_at(envelope.max_position - step - distance, Some(0.0), at(5000)).unwrap();
+    assert_eq!(feedback.clamped_velocity, Some(envelope.max_velocity));
+
+    assert_eq!(
+        stream_position_at(Real::NAN, None, at(5100)),
+        Err(StreamError::NotANumber)
+    );
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use embassy_time::{Duration, Instant};
+use ossm_motion::{
+    config::{MAX_SYNC_OFFSET_MS, SYNC_DELAY_MS},
+    float::Real,
+    motion::{
+        stream::get_velocity_envelope,
+        sync::{
+            SyncMove, SyncRole, follow, lead_move, run_due_moves, set_sync_role, take_lead_move,
+        },
+    },
+    motion_control::{arming::arm_at, get_target_position, set_target_position},
+    runtime_config::{get_config_json, set_config_value},
+    validation::ValueError,
+};
+
+use common::lock;
+
+const POSITION_TOLERANCE_MM: Real = 0.01;
+const LEADER: [u8; 6] = [1, 2, 3, 4, 5, 6];
+const OTHER_LEADER: [u8; 6] = [6, 5, 4, 3, 2, 1];
+
+fn assert_target(position: Real) {
+    let target = get_target_position();
+    assert!(
+        (target - position).abs() < POSITION_TOLERANCE_MM,
+        "Target {target} instead of {position}"
+    );
+}
+
+#[test]
+fn leader_moves_are_fractions_of_the_stroke() {
+    let _lock = lock();
+    let envelope = get_velocity_envelope();
+    let stroke = envelope.max_position - envelope.min_position;
+    let now = Instant::from_secs(10);
+
+    assert_eq!(set_config_value("syncRole", 1.0), Ok(()));
+    set_target_position(envelope.min_position);
+    lead_move(envelope.min_position + stroke / 2.0, stroke, now);
+    let sync_move = take_lead_move().expect("Queued by the leader");
+    assert_eq!(sync_move.at, now);
+    assert!((sync_move.fraction - 0.5).abs() < 0.001);
+    assert_eq!(sync_move.duration_ms, 500);
+    assert_eq!(take_lead_move(), None);
+
+    set_sync_role(SyncRole::Off);
+    lead_move(envelope.max_position, stroke, now);
+    assert_eq!(take_lead_move(), None);
+}
+
+#[test]
+fn followers_play_the_moves_later_on_their_own_clock() {
+    let _lock = lock();
+    arm_at(Instant::from_ticks(0)).unwrap();
+    let envelope = get_velocity_envelope();
+    set_target_position(envelope.min_position);
+    // The leader booted long before the follower
+    let leader_start = Instant::from_secs(1000);
+    let follower_start = Instant::from_secs(50);
+    let at = |start: Instant, ms: u64| start + Duration::from_millis(ms);
+
+    assert!(!follow(LEADER, leader_start, None, follower_start));
+    set_sync_role(SyncRole::Follower);
+
+    // The quickest packet took 10 ms
+    assert!(follow(LEADER, leader_start, None, at(follower_start, 30)));
+    assert!(follow(
+        LEADER,
+        at(leader_start, 100),
+        None,
+        at(follower_start, 110)
+    ));
+    let sync_move = SyncMove {
+        at: at(leader_start, 200),
+        fraction: 1.0,
+        duration_ms: 500,
+    };
+    assert!(follow(
+        LEADER,
+        at(leader_start, 200),
+        Some(sync_move),
+        at(follower_start, 250)
+    ));
+    assert!(!follow(
+        OTHER_LEADER,
+        at(leader_start, 200),
+        Some(sync_move),
+        at(follower_start, 220)
+    ));
+
+    let due = 210 + SYNC_DELAY_MS;
+    run_due_moves(at(follower_start, due - 1));
+    assert_target(envelope.min_position);
+    run_due_moves(at(follower_start, due));
+    assert_target(envelope.max_position);
+
+    assert_eq!(
+        set_config_value("syncOffsetMs", 5000.0),
+        Err(ValueError::OutOfRange {
+            accepted: MAX_SYNC_OFFSET_MS as i32
+        })
+    );
+    let sync_move = SyncMove {
+        at: at(leader_start, 1000),
+        fraction: 0.0,
+        duration_ms: 500,
+    };
+    follow(
+        LEADER,
+        at(leader_start, 1000),
+        Some(sync_move),
+        at(follower_start, 1020),
+    );
+    let due = 1010 + SYNC_DELAY_MS + MAX_SYNC_OFFSET_MS;
+    run_due_moves(at(follower_start, due - 1));
+    assert_target(envelope.max_position);
+    run_due_moves(at(follower_start, due));
+    assert_target(envelope.min_position);
+
+    let config = get_config_json();
+    assert!(
+        config.contains(r#""syncRole":2,"syncOffsetMs":2000,"#),
+        "{config}"
+    );
+
+    set_config_value("syncOffsetMs", 0.0).unwrap();
+    set_sync_role(SyncRole::Off);
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use embassy_time::{Duration, Instant};
+use ossm_motion::{
+    float::Real,
+    motion::{stream::get_velocity_envelope, tcode::process_tcode_line_at},
+    motion_control::{arming::arm_at, get_target_position, get_target_velocity, is_velocity_mode},
+};
+
+use common::lock;
+
+const POSITION_TOLERANCE_MM: Real = 0.01;
+
+fn assert_target(position: Real) {
+    let target = get_target_position();
+    assert!(
+        (target - position).abs() < POSITION_TOLERANCE_MM,
+        "Target {target} instead of {position}"
+    );
+}
+
+#[test]
+fn linear_magnitudes_are_fractions_of_the_stroke() {
+    let _lock = lock();
+    arm_at(Instant::from_ticks(0)).unwrap();
+    let envelope = get_velocity_envelope();
+    let stroke = envelope.max_position - envelope.min_position;
+    let start = Instant::from_secs(10);
+
+    assert!(process_tcode_line_at("L00", start).is_empty());
+    assert_target(envelope.min_position);
+
+    process_tcode_line_at("L05I500", start + Duration::from_millis(100));
+    assert_target(envelope.min_position + stroke / 2.0);
+
+    process_tcode_line_at("l0500i500", start + Duration::from_millis(200));
+    assert_target(envelope.min_position + stroke / 2.0);
+
+    process_tcode_line_at("L09999S2000", start + Duration::from_millis(300));
+    assert_target(envelope.min_position + stroke * 0.9999);
+}
+
+#[test]
+fn other_axes_and_malformed_commands_are_ignored() {
+    let _lock = lock();
+    let envelope = get_velocity_envelope();
+    let start = Instant::from_secs(20);
+
+    process_tcode_line_at("L00", start);
+    let later = start + Duration::from_millis(100);
+    assert!(process_tcode_line_at("R0500 V19999 L1500 L0x5 L05Ix V0x5", later).is_empty());
+    assert_target(envelope.min_position);
+}
+
+#[test]
+fn the_first_vibration_axis_streams_a_velocity() {
+    let _lock = lock();
+    arm_at(Instant::from_ticks(0)).unwrap();
+    let max_velocity = get_velocity_envelope().max_velocity;
+    let start = Instant::from_secs(25);
+    let assert_velocity = |velocity: Real| {
+        let target = get_target_velocity();
+        assert!(
+            (target - velocity).abs() < 0.01,
+            "Target velocity {target} instead of {velocity}"
+        );
+    };
+
+    assert!(process_tcode_line_at("V075", start).is_empty());
+    assert!(is_velocity_mode());
+    assert_velocity(max_velocity / 2.0);
+
+    process_tcode_line_at("V00", start);
+    assert_velocity(-max_velocity);
+
+    process_tcode_line_at("v05I200", start);
+    assert_velocity(0.0);
+
+    // A target ends it
+    process_tcode_line_at("L05", start + Duration::from_millis(100));
+    assert!(!is_velocity_mode());
+}
+
+#[test]
+fn device_commands_are_answered_one_line_each() {
+    let _lock = lock();
+    let now = Instant::from_secs(30);
+
+    assert_eq!(process_tcode_line_at("D1", now).as_str(), "TCode v0.3\n");
+    assert_eq!(
+        process_tcode_line_at("D2", now).as_str(),
+        "L0 0 9999 Stroke\n"
+    );
+
+    let response = process_tcode_line_at("D0 D1", now);
+    let mut lines = response.lines();
+    assert!(lines.next().unwrap().starts_with("OSSM-RS "));
+    assert_eq!(lines.next(), Some("TCode v0.3"));
+    assert_eq!(lines.next(), None);
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use ossm_motion::{
+    config::{
+        MAX_THERMAL_THRESHOLD_C, MCU_CRITICAL_C, MCU_WARNING_C, MOTOR_CRITICAL_C, MOTOR_WARNING_C,
+        THERMAL_HYSTERESIS_C, THERMAL_WARNING_LIMIT_PCT,
+    },
+    float::Real,
+    motion_control::is_faulted,
+    runtime_config::{get_config_json, set_config_value},
+    thermal::{
+        ThermalLevel, ThermalLimits, get_mcu_temperature_c, get_thermal_level,
+        get_thermal_limit_pct, get_thermal_limits, set_mcu_temperature_c, set_thermal_limits,
+    },
+    validation::ValueError,
+};
+
+use common::lock;
+
+#[test]
+fn warnings_clear_below_the_hysteresis() {
+    let _lock = lock();
+    set_mcu_temperature_c(MCU_WARNING_C);
+    assert_eq!(get_thermal_level(), ThermalLevel::Warning);
+    assert_eq!(get_thermal_limit_pct(), THERMAL_WARNING_LIMIT_PCT);
+    // Only the critical temperatures stop the machine
+    assert!(!is_faulted());
+
+    set_mcu_temperature_c(MCU_WARNING_C - THERMAL_HYSTERESIS_C + 1.0);
+    assert_eq!(get_thermal_level(), ThermalLevel::Warning);
+    set_mcu_temperature_c(MCU_WARNING_C - THERMAL_HYSTERESIS_C);
+    assert_eq!(get_thermal_level(), ThermalLevel::Normal);
+    assert_eq!(get_thermal_limit_pct(), 100.0);
+    assert_eq!(
+        get_mcu_temperature_c(),
+        Some(MCU_WARNING_C - THERMAL_HYSTERESIS_C)
+    );
+}
+
+#[test]
+fn the_thresholds_are_runtime_config_values() {
+    let _lock = lock();
+    set_config_value("mcuWarningC", 60.0).unwrap();
+    assert_eq!(
+        set_config_value("mcuCriticalC", 500.0),
+        Err(ValueError::OutOfRange {
+            accepted: MAX_THERMAL_THRESHOLD_C as i32
+        })
+    );
+    let config = get_config_json();
+    assert!(
+        config.contains(r#""mcuWarningC":60,"mcuCriticalC":125,"#),
+        "{config}"
+    );
+    assert!(config.ends_with('}'), "{config}");
+
+    // Applied with the next read
+    set_mcu_temperature_c(65.0);
+    assert_eq!(get_thermal_level(), ThermalLevel::Warning);
+    set_mcu_temperature_c(25.0);
+
+    assert_eq!(
+        set_thermal_limits(ThermalLimits {
+            mcu_critical_c: Real::NAN,
+            ..ThermalLimits::DEFAULT
+        }),
+        Err(ValueError::Unknown)
+    );
+    set_thermal_limits(ThermalLimits::DEFAULT).unwrap();
+}
+
+#[test]
+fn a_warning_at_or_above_the_critical_threshold_is_rejected() {
+    let _lock = lock();
+    assert_eq!(
+        set_config_value("motorWarningC", MOTOR_CRITICAL_C),
+        Err(ValueError::OutOfRange {
+            accepted: MOTOR_WARNING_C as i32
+        })
+    );
+    assert_eq!(
+        set_config_value("mcuCriticalC", MCU_WARNING_C - 1.0),
+        Err(ValueError::OutOfRange {
+            accepted: MCU_CRITICAL_C as i32
+        })
+    );
+    assert_eq!(get_thermal_limits(), ThermalLimits::DEFAULT);
+
+    assert_eq!(
+        set_thermal_limits(ThermalLimits {
+            motor_warning_c: MOTOR_CRITICAL_C,
+            ..ThermalLimits::DEFAULT
+        }),
+        Err(ValueError::Unknown)
+    );
+    assert_eq!(get_thermal_limits(), ThermalLimits::DEFAULT);
+}
@@ -0,0 +1,95 @@
+use embassy_time::{Duration, Instant};
+use ossm_motion::{
+    motion_control::timer::{TimerDuration, TimerInstant},
+    time::{AtomicTimestamp, elapsed_between, timer_elapsed},
+};
+
+#[test]
+fn timer_elapsed_is_zero_for_the_same_instant() {
+    let now = TimerInstant::from_ticks(0);
+    assert_eq!(timer_elapsed(now, now), TimerDuration::from_ticks(0));
+}
+
+#[test]
+fn timer_elapsed_saturates_when_since_is_later() {
+    let since = TimerInstant::from_ticks(2_000);
+    let now = TimerInstant::from_ticks(1_000);
+    assert_eq!(timer_elapsed(since, now), TimerDuration::from_ticks(0));
+}
+
+#[test]
+fn timer_elapsed_after_long_uptime() {
+    // Past the u32 range of both microseconds and milliseconds
+    let since = TimerInstant::from_ticks(u32::MAX as u64 * 1_000);
+    let now = since + TimerDuration::millis(1500);
+    assert_eq!(timer_elapsed(since, now).to_millis(), 1500);
+}
+
+#[test]
+fn elapsed_between_saturates_when_since_is_later() {
+    let since = Instant::from_millis(10);
+    let now = Instant::from_millis(5);
+    assert_eq!(elapsed_between(since, now), Duration::from_ticks(0));
+}
+
+#[test]
+fn timestamp_is_unset_at_boot() {
+    let timestamp = AtomicTimestamp::never();
+    let boot = Instant::from_ticks(0);
+
+    assert_eq!(timestamp.load(), None);
+    assert_eq!(timestamp.elapsed_at(boot), None);
+    assert!(!timestamp.is_within_at(Duration::from_secs(8), boot));
+}
+
+#[test]
+fn timestamp_set_at_boot_is_within_timeout() {
+    let timestamp = AtomicTimestamp::never();
+    let boot = Instant::from_ticks(0);
+    timestamp.store(boot);
+
+    assert_eq!(timestamp.elapsed_at(boot), Some(Duration::from_ticks(0)));
+    assert!(timestamp.is_within_at(Duration::from_secs(8), boot));
+    assert!(timestamp.is_within_at(Duration::from_secs(8), Instant::from_secs(8)));
+    assert!(!timestamp.is_within_at(Duration::from_secs(8), Instant::from_secs(9)));
+}
+
+#[test]
+fn timestamp_after_long_uptime() {
+    let timestamp = AtomicTimestamp::never();
+    // More than 49 days, which overflows u32 milliseconds
+    let stored = Instant::from_secs(50 * 24 * 60 * 60);
+    timestamp.store(stored);
+
+    let now = stored + Duration::from_millis(7999);
+    assert_eq!(timestamp.elapsed_at(now), Some(Duration::from_millis(7999)));
+    assert!(timestamp.is_within_at(Duration::from_secs(8), now));
+}
+
+#[test]
+fn timestamp_in_the_future_counts_as_just_now() {
+    let timestamp = AtomicTimestamp::never();
+    timestamp.store(Instant::from_secs(10));
+
+    assert_eq!(
+        timestamp.elapsed_at(Instant::from_secs(5)),
+        Some(Duration::from_ticks(0))
+    );
+}
+
+#[test]
+fn timestamp_can_be_cleared() {
+    let timestamp = AtomicTimestamp::never();
+    timestamp.store(Instant::from_secs(1));
+    timestamp.clear();
+
+    assert_eq!(timestamp.load(), None);
+}
+
+#[test]
+fn timestamp_keeps_the_max_instant_distinct_from_unset() {
+    let timestamp = AtomicTimestamp::never();
+    timestamp.store(Instant::MAX);
+
+    assert!(timestamp.load().is_some());
+}
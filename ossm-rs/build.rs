@@ -1,7 +1,38 @@
+use std::{collections::HashSet, env, fmt::Write, fs, path::Path};
+
+use serde::Deserialize;
 use vergen_gitcl::{Emitter, GitclBuilder};
 
+// Selects the file in `boards` without the extension e.g. `waveshare`. Set by `cargo xtask`
+const BOARD_ENV: &str = "OSSM_BOARD";
+const BOARDS_DIR: &str = "boards";
+
+/// A file in `boards`. `flash_mb` is only read by xtask
+#[derive(Deserialize)]
+struct BoardFile {
+    name: String,
+    mcu: String,
+    pins: BoardPins,
+}
+
+/// The GPIO numbers. The optional ones are left out if the board does not have them
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BoardPins {
+    rs485_rx: u8,
+    rs485_tx: u8,
+    rs485_transmit_enable: Option<u8>,
+    rs485_receive_enable_inv: Option<u8>,
+    i2c_sda: Option<u8>,
+    i2c_scl: Option<u8>,
+    endstop: Option<u8>,
+    pair_button: Option<u8>,
+    estop: Option<u8>,
+}
+
 fn main() {
     linker_be_nice();
+    generate_board();
     // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
     println!("cargo:rustc-link-arg=-Tlinkall.x");
 
@@ -19,6 +50,115 @@ fn main() {
         .unwrap();
 }
 
+/// Write `board.rs` to OUT_DIR with the name of the selected board and the `board_pins!` macro
+/// that takes its pins out of the peripherals
+fn generate_board() {
+    println!("cargo:rerun-if-env-changed={BOARD_ENV}");
+    println!("cargo:rerun-if-changed={BOARDS_DIR}");
+
+    let source = match env::var(BOARD_ENV) {
+        Ok(board) => board_source(&board),
+        Err(_) => {
+            // Dummy board to avoid LSP complaints
+            let mut source = format!(
+                "compile_error!(\"No board selected! Set {BOARD_ENV} to a board in ossm-rs/{BOARDS_DIR} or use cargo xtask run <board_name>\");\n"
+            );
+            source.push_str(&pins_source("None", &BoardPins::dummy()));
+            source
+        }
+    };
+
+    let out_dir = env::var("OUT_DIR").expect("Set by cargo");
+    fs::write(Path::new(&out_dir).join("board.rs"), source).expect("Could not write board.rs");
+}
+
+fn board_source(board: &str) -> String {
+    let path = Path::new(BOARDS_DIR).join(board).with_extension("toml");
+    let file = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("Could not read the board {}: {err}", path.display()));
+    let file: BoardFile = toml::from_str(&file)
+        .unwrap_or_else(|err| panic!("Invalid board {}: {err}", path.display()));
+
+    let feature = format!("CARGO_FEATURE_{}", file.mcu.to_uppercase());
+    if env::var_os(feature).is_none() {
+        panic!("The board {board} needs the {} feature", file.mcu);
+    }
+
+    let mut used = HashSet::new();
+    for (_, pin) in file.pins.all() {
+        if !used.insert(pin) {
+            panic!("GPIO{pin} is used twice by the board {board}");
+        }
+    }
+
+    format!(
+        "// Generated by build.rs from {}\n{}",
+        path.display(),
+        pins_source(&file.name, &file.pins)
+    )
+}
+
+fn pins_source(name: &str, pins: &BoardPins) -> String {
+    let mut source = String::new();
+    writeln!(source, "pub const BOARD_NAME: &str = {name:?};").unwrap();
+    writeln!(source, "macro_rules! board_pins {{").unwrap();
+    writeln!(source, "    ($peripherals:ident) => {{").unwrap();
+    writeln!(
+        source,
+        "        $crate::board::Pins::new($peripherals.GPIO{}.degrade(), $peripherals.GPIO{}.degrade())",
+        pins.rs485_rx, pins.rs485_tx
+    )
+    .unwrap();
+    for (setter, pin) in pins.optional() {
+        writeln!(
+            source,
+            "            .with_{setter}($peripherals.GPIO{pin}.degrade())"
+        )
+        .unwrap();
+    }
+    writeln!(source, "    }};").unwrap();
+    writeln!(source, "}}").unwrap();
+    writeln!(source, "pub(crate) use board_pins;").unwrap();
+    source
+}
+
+impl BoardPins {
+    fn dummy() -> Self {
+        Self {
+            rs485_rx: 35,
+            rs485_tx: 37,
+            rs485_transmit_enable: None,
+            rs485_receive_enable_inv: None,
+            i2c_sda: None,
+            i2c_scl: None,
+            endstop: None,
+            pair_button: None,
+            estop: None,
+        }
+    }
+
+    /// The optional pins the board has with the `Pins` setter without `with_`
+    fn optional(&self) -> impl Iterator<Item = (&'static str, u8)> {
+        [
+            ("rs485_transmit_enable", self.rs485_transmit_enable),
+            ("rs485_receive_enable_inv", self.rs485_receive_enable_inv),
+            ("i2c_sda", self.i2c_sda),
+            ("i2c_scl", self.i2c_scl),
+            ("endstop", self.endstop),
+            ("pair_button", self.pair_button),
+            ("estop", self.estop),
+        ]
+        .into_iter()
+        .filter_map(|(setter, pin)| pin.map(|pin| (setter, pin)))
+    }
+
+    fn all(&self) -> impl Iterator<Item = (&'static str, u8)> {
+        [("rs485_rx", self.rs485_rx), ("rs485_tx", self.rs485_tx)]
+            .into_iter()
+            .chain(self.optional())
+    }
+}
+
 fn linker_be_nice() {
     let args: Vec<String> = std::env::args().collect();
     if args.len() > 1 {
@@ -0,0 +1,245 @@
+use core::{fmt::Write, net::Ipv4Addr};
+
+use heapless::{String, Vec};
+use log::{error, info};
+use ossm_motion::{float::Real, motion::motion_state::get_motion_state};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{
+        MAX_CALIBRATED_TRAVEL_MM, MAX_REMOTES, MAX_SETTINGS_LENGTH, MIN_CALIBRATED_TRAVEL_MM,
+    },
+    error::{BackupError, ConfigError},
+    network::{
+        buttplug::IntifaceServer,
+        mqtt::{MqttBroker, MAX_MQTT_PASSWORD_LENGTH, MAX_MQTT_USER_LENGTH},
+        syslog::SyslogServer,
+        wifi::{WifiCredentials, MAX_SSID_LENGTH, MAX_WIFI_PASSWORD_LENGTH},
+    },
+    storage::{
+        load_intiface_server, load_max_travel_mm, load_mqtt_broker, load_remotes,
+        load_syslog_server, load_wifi_credentials, save_intiface_server, save_max_travel_mm,
+        save_mqtt_broker, save_remotes, save_syslog_server, save_wifi_credentials,
+    },
+};
+
+// Increased when the format changes. Backups of other versions are rejected
+const BACKUP_VERSION: u32 = 1;
+// `aa:bb:cc:dd:ee:ff`
+const ADDRESS_LENGTH: usize = 17;
+// Fits the longest string of a backup once unescaped, the passwords
+const MAX_UNESCAPED_LENGTH: usize = MAX_WIFI_PASSWORD_LENGTH;
+
+/// The persisted settings without the passwords. A backup may carry them as `password` next
+/// to the SSID and the MQTT user. Without one the password stored for the same one is kept
+/// Keys that are missing or `null` are turned off
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct Backup {
+    version: u32,
+    // The usable travel measured by the calibration in mm. None if never calibrated
+    max_travel_mm: Option<f32>,
+    // The addresses of the paired ESP-NOW remotes
+    #[serde(default)]
+    remotes: Vec<String<ADDRESS_LENGTH>, MAX_REMOTES>,
+    wifi: Option<BackupWifi>,
+    mqtt: Option<BackupMqtt>,
+    intiface: Option<BackupServer>,
+    syslog: Option<BackupServer>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BackupWifi {
+    ssid: String<MAX_SSID_LENGTH>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password: Option<String<MAX_WIFI_PASSWORD_LENGTH>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BackupMqtt {
+    address: Ipv4Addr,
+    port: u16,
+    user: String<MAX_MQTT_USER_LENGTH>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password: Option<String<MAX_MQTT_PASSWORD_LENGTH>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BackupServer {
+    address: Ipv4Addr,
+    port: u16,
+}
+
+impl BackupError {
+    /// Reason reported in `fail:<write>:<reason>`
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackupError::Busy => "busy",
+            BackupError::TooLong => "toolong",
+            BackupError::Malformed => "malformed",
+            BackupError::Version => "version",
+            BackupError::Invalid(key) => *key,
+            BackupError::Storage => "storage",
+        }
+    }
+}
+
+impl From<ConfigError> for BackupError {
+    fn from(_err: ConfigError) -> Self {
+        BackupError::Storage
+    }
+}
+
+/// The stored settings as JSON without the passwords
+pub fn get_settings_json() -> String<MAX_SETTINGS_LENGTH> {
+    let backup = Backup {
+        version: BACKUP_VERSION,
+        max_travel_mm: load_max_travel_mm().map(|travel| travel as f32),
+        remotes: load_remotes().iter().map(format_address).collect(),
+        wifi: load_wifi_credentials().map(|credentials| BackupWifi {
+            ssid: credentials.ssid,
+            password: None,
+        }),
+        mqtt: load_mqtt_broker().map(|broker| BackupMqtt {
+            address: broker.address,
+            port: broker.port,
+            user: broker.user,
+            password: None,
+        }),
+        intiface: load_intiface_server().map(|server| BackupServer {
+            address: server.address,
+            port: server.port,
+        }),
+        syslog: load_syslog_server().map(|server| BackupServer {
+            address: server.address,
+            port: server.port,
+        }),
+    };
+
+    let mut buffer = [0u8; MAX_SETTINGS_LENGTH];
+    let json = serde_json_core::to_slice(&backup, &mut buffer)
+        .ok()
+        .and_then(|length| core::str::from_utf8(&buffer[..length]).ok());
+    match json {
+        Some(json) => String::try_from(json).expect("Fits the buffer it was written to"),
+        None => {
+            error!("Could not write the settings. Too long");
+            String::new()
+        }
+    }
+}
+
+/// Check a backup and store all of its settings. Nothing is stored if any of them is rejected
+/// Only while the motion is disabled. They are used after the next boot
+pub fn restore_settings(json: &str) -> Result<(), BackupError> {
+    if get_motion_state().motion_enabled {
+        return Err(BackupError::Busy);
+    }
+
+    let mut unescaped = [0u8; MAX_UNESCAPED_LENGTH];
+    let (backup, _) =
+        serde_json_core::from_str_escaped::<Backup>(json, &mut unescaped).map_err(|err| {
+            // Not logged with the backup as it could contain the passwords
+            error!("Invalid settings backup {:?}", err);
+            BackupError::Malformed
+        })?;
+    if backup.version != BACKUP_VERSION {
+        return Err(BackupError::Version);
+    }
+
+    let travel = match backup.max_travel_mm {
+        Some(travel) => {
+            let travel = travel as Real;
+            if !(MIN_CALIBRATED_TRAVEL_MM..=MAX_CALIBRATED_TRAVEL_MM).contains(&travel) {
+                return Err(BackupError::Invalid("maxTravelMm"));
+            }
+            travel
+        }
+        // Stored as never calibrated
+        None => 0.0,
+    };
+
+    let mut remotes: Vec<[u8; 6], MAX_REMOTES> = Vec::new();
+    for address in &backup.remotes {
+        let address = parse_address(address).ok_or(BackupError::Invalid("remotes"))?;
+        remotes.push(address).expect("Limited by the backup");
+    }
+
+    let wifi = match &backup.wifi {
+        Some(wifi) => {
+            let password = match &wifi.password {
+                Some(password) => password.clone(),
+                None => load_wifi_credentials()
+                    .filter(|credentials| credentials.ssid == wifi.ssid)
+                    .map(|credentials| credentials.password)
+                    .unwrap_or_default(),
+            };
+            let credentials = WifiCredentials::new(&wifi.ssid, &password);
+            Some(credentials.ok_or(BackupError::Invalid("wifi"))?)
+        }
+        None => None,
+    };
+
+    let mqtt = match &backup.mqtt {
+        Some(mqtt) => {
+            let password = match &mqtt.password {
+                Some(password) => password.clone(),
+                None => load_mqtt_broker()
+                    .filter(|broker| broker.user == mqtt.user)
+                    .map(|broker| broker.password)
+                    .unwrap_or_default(),
+            };
+            let broker = MqttBroker::new(mqtt.address, mqtt.port, &mqtt.user, &password);
+            Some(broker.ok_or(BackupError::Invalid("mqtt"))?)
+        }
+        None => None,
+    };
+
+    let intiface = match &backup.intiface {
+        Some(server) => Some(
+            IntifaceServer::new(server.address, server.port)
+                .ok_or(BackupError::Invalid("intiface"))?,
+        ),
+        None => None,
+    };
+
+    let syslog = match &backup.syslog {
+        Some(server) => Some(
+            SyslogServer::new(server.address, server.port).ok_or(BackupError::Invalid("syslog"))?,
+        ),
+        None => None,
+    };
+
+    save_max_travel_mm(travel)?;
+    save_remotes(&remotes)?;
+    save_wifi_credentials(wifi.as_ref())?;
+    save_mqtt_broker(mqtt.as_ref())?;
+    save_intiface_server(intiface.as_ref())?;
+    save_syslog_server(syslog.as_ref())?;
+    info!("Restored the settings from a backup");
+
+    Ok(())
+}
+
+fn format_address(address: &[u8; 6]) -> String<ADDRESS_LENGTH> {
+    let mut output = String::new();
+    let [a, b, c, d, e, f] = address;
+    write!(output, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{f:02x}").expect("Always fits");
+    output
+}
+
+fn parse_address(text: &str) -> Option<[u8; 6]> {
+    let mut address = [0u8; 6];
+    let mut bytes = text.split(':');
+    for byte in &mut address {
+        let part = bytes.next()?;
+        if part.len() != 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    bytes.next().is_none().then_some(address)
+}
@@ -1,5 +1,10 @@
 use esp_hal::gpio::AnyPin;
 
+// Generated by build.rs from the file in `ossm-rs/boards` selected with OSSM_BOARD
+// `BOARD_NAME` is reported as the hardware revision of the device information over BLE
+// `board_pins!(peripherals)` takes the pins of the board out of the peripherals
+include!(concat!(env!("OUT_DIR"), "/board.rs"));
+
 pub struct Pins {
     pub rs485_rx: AnyPin<'static>,
     pub rs485_tx: AnyPin<'static>,
@@ -7,6 +12,12 @@ pub struct Pins {
     pub rs485_receive_enable_inv: Option<AnyPin<'static>>,
     pub i2c_sda: Option<AnyPin<'static>>,
     pub i2c_scl: Option<AnyPin<'static>>,
+    // Limit switch at the home position. Replaces the sensorless homing
+    pub endstop: Option<AnyPin<'static>>,
+    // Opens the pairing window for the M5 remote when pressed. Active low
+    pub pair_button: Option<AnyPin<'static>>,
+    // Hardware e-stop switch. Normally closed to ground
+    pub estop: Option<AnyPin<'static>>,
 }
 
 impl Pins {
@@ -18,8 +29,13 @@ impl Pins {
             rs485_receive_enable_inv: None,
             i2c_sda: None,
             i2c_scl: None,
+            endstop: None,
+            pair_button: None,
+            estop: None,
         }
     }
+    // Not all boards have this
+    #[allow(dead_code)]
     pub fn with_rs485_transmit_enable(mut self, pin: AnyPin<'static>) -> Self {
         self.rs485_transmit_enable = Some(pin);
         self
@@ -30,12 +46,34 @@ impl Pins {
         self.rs485_receive_enable_inv = Some(pin);
         self
     }
+    // Not all boards have this
+    #[allow(dead_code)]
     pub fn with_i2c_sda(mut self, pin: AnyPin<'static>) -> Self {
         self.i2c_sda = Some(pin);
         self
     }
+    // Not all boards have this
+    #[allow(dead_code)]
     pub fn with_i2c_scl(mut self, pin: AnyPin<'static>) -> Self {
         self.i2c_scl = Some(pin);
         self
     }
+    // Not all boards have this
+    #[allow(dead_code)]
+    pub fn with_endstop(mut self, pin: AnyPin<'static>) -> Self {
+        self.endstop = Some(pin);
+        self
+    }
+    // Not all boards have this
+    #[allow(dead_code)]
+    pub fn with_pair_button(mut self, pin: AnyPin<'static>) -> Self {
+        self.pair_button = Some(pin);
+        self
+    }
+    // Not all boards have this
+    #[allow(dead_code)]
+    pub fn with_estop(mut self, pin: AnyPin<'static>) -> Self {
+        self.estop = Some(pin);
+        self
+    }
 }
@@ -0,0 +1,184 @@
+use embassy_net::tcp::{ConnectError, Error as TcpError};
+use esp_radio::esp_now::EspNowError;
+use log::error;
+use ossm_motion::{motion_control::pause_for_reconnect, validation::ValueError};
+
+use crate::{
+    motor::MotorError,
+    network::{buttplug::ButtplugError, mqtt::MqttError},
+};
+
+// The payloads are only read by `Debug` when the error is logged, which the dead code analysis
+// ignores. Only they are allowed to look unused
+#[derive(Debug)]
+pub enum Error {
+    Motor(#[allow(dead_code)] MotorError),
+    Motion(#[allow(dead_code)] MotionError),
+    Remote(#[allow(dead_code)] RemoteError),
+    Config(#[allow(dead_code)] ConfigError),
+    Ota(#[allow(dead_code)] OtaError),
+}
+
+#[derive(Debug)]
+pub enum MotionError {
+    // The operation needs the motion to be disabled
+    MotionEnabled,
+    // The machine was emergency stopped and not re-armed yet
+    Faulted,
+    // Homing and calibrating move the machine, so they wait for it to be armed
+    NotArmed,
+    // Motion control is not initialised or already taken out of the control loop
+    Detached,
+    // The calibration reached the max calibrated travel without the motor stalling
+    NoEndFound,
+    // The calibration did not find the end of the rail within CALIBRATION_TIMEOUT_MS
+    CalibrationTimeout,
+}
+
+#[derive(Debug)]
+pub enum RemoteError {
+    // The BLE host reported an error. The details are logged where it happened
+    Ble,
+    EspNow(#[allow(dead_code)] EspNowError),
+    // The response did not fit into the characteristic
+    ResponseTooLong,
+    // The remote did not acknowledge a command after all the retries
+    NotAcknowledged,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    // A configured or stored value was rejected
+    Value(#[allow(dead_code)] ValueError),
+    // The settings could not be read from or written to the flash
+    Storage,
+}
+
+#[derive(Debug)]
+pub enum NetworkError {
+    // The connection could not be opened
+    Connect(#[allow(dead_code)] ConnectError),
+    // The connection failed while it was open
+    Tcp(#[allow(dead_code)] TcpError),
+    Mqtt(#[allow(dead_code)] MqttError),
+    Buttplug(#[allow(dead_code)] ButtplugError),
+}
+
+#[derive(Debug)]
+pub enum OtaError {
+    // No public key was built in to check the signature with
+    NoKey,
+    // The motion is enabled or another update is being written
+    Busy,
+    // The image does not fit into the spare partition
+    TooLarge,
+    // The image was not signed with the built in key
+    BadSignature,
+    // The signed data is not a firmware image
+    NotAnImage,
+    // The upload ended before all of the image arrived
+    Incomplete,
+    // The partitions could not be read or written. The details are logged where it happened
+    Flash,
+}
+
+#[derive(Debug)]
+pub enum BackupError {
+    // The motion is enabled
+    Busy,
+    // The backup does not fit into MAX_SETTINGS_LENGTH
+    TooLong,
+    // Not JSON of the settings
+    Malformed,
+    // Written by a firmware with another version of the backup
+    Version,
+    // The value of this key was rejected
+    Invalid(&'static str),
+    // The settings could not be written. The details are logged where it happened
+    Storage,
+}
+
+/// Log an error that could not be handled where it happened so that the machine keeps running
+/// A motor error pauses motion control until the motor is reconnected, which raises the
+/// `motor_timeout` fault
+pub fn report_error(err: impl Into<Error>) {
+    let err = err.into();
+    error!("{:?}", err);
+
+    if let Error::Motor(_) = err {
+        // The reconnection re-applies the settings and homes again if the position was lost
+        pause_for_reconnect();
+    }
+}
+
+impl From<MotorError> for Error {
+    fn from(err: MotorError) -> Self {
+        Error::Motor(err)
+    }
+}
+
+impl From<MotionError> for Error {
+    fn from(err: MotionError) -> Self {
+        Error::Motion(err)
+    }
+}
+
+impl From<RemoteError> for Error {
+    fn from(err: RemoteError) -> Self {
+        Error::Remote(err)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Self {
+        Error::Config(err)
+    }
+}
+
+impl From<EspNowError> for RemoteError {
+    fn from(err: EspNowError) -> Self {
+        RemoteError::EspNow(err)
+    }
+}
+
+impl From<EspNowError> for Error {
+    fn from(err: EspNowError) -> Self {
+        Error::Remote(err.into())
+    }
+}
+
+impl From<OtaError> for Error {
+    fn from(err: OtaError) -> Self {
+        Error::Ota(err)
+    }
+}
+
+impl From<ValueError> for ConfigError {
+    fn from(err: ValueError) -> Self {
+        ConfigError::Value(err)
+    }
+}
+
+impl From<ConnectError> for NetworkError {
+    fn from(err: ConnectError) -> Self {
+        NetworkError::Connect(err)
+    }
+}
+
+impl From<TcpError> for NetworkError {
+    fn from(err: TcpError) -> Self {
+        NetworkError::Tcp(err)
+    }
+}
+
+impl From<MqttError> for NetworkError {
+    fn from(err: MqttError) -> Self {
+        NetworkError::Mqtt(err)
+    }
+}
+
+impl From<ButtplugError> for NetworkError {
+    fn from(err: ButtplugError) -> Self {
+        NetworkError::Buttplug(err)
+    }
+}
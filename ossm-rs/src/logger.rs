@@ -0,0 +1,120 @@
+use core::{
+    cell::RefCell,
+    fmt::{self, Write},
+};
+
+use critical_section::Mutex;
+use esp_println::println;
+use heapless::{HistoryBuf, String};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::config::{LOG_BUFFER_LINES, MAX_LOG_LINE_LENGTH};
+
+static LOGGER: RadioLogger = RadioLogger;
+static LINES: Mutex<RefCell<LogBuffer>> = Mutex::new(RefCell::new(LogBuffer {
+    lines: HistoryBuf::new(),
+    next: 0,
+}));
+
+#[derive(Clone, Debug)]
+pub struct LogLine {
+    pub level: Level,
+    // Cut off at `MAX_LOG_LINE_LENGTH`
+    pub text: String<MAX_LOG_LINE_LENGTH>,
+}
+
+/// The recent lines to stream over BLE and syslog once the USB port can not be reached
+struct LogBuffer {
+    lines: HistoryBuf<LogLine, LOG_BUFFER_LINES>,
+    // The sequence number of the next line
+    next: u32,
+}
+
+struct RadioLogger;
+
+impl Log for RadioLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let color = match record.level() {
+            Level::Error => "\x1b[31m",
+            Level::Warn => "\x1b[33m",
+            Level::Info => "\x1b[32m",
+            Level::Debug => "\x1b[34m",
+            Level::Trace => "\x1b[35m",
+        };
+        println!("{}{} - {}\x1b[0m", color, record.level(), record.args());
+
+        // Formatted before taking the lock
+        let mut text = String::new();
+        write!(Truncated(&mut text), "{}", record.args()).ok();
+        let line = LogLine {
+            level: record.level(),
+            text,
+        };
+        critical_section::with(|cs| {
+            let mut buffer = LINES.borrow_ref_mut(cs);
+            buffer.lines.write(line);
+            buffer.next = buffer.next.wrapping_add(1);
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Keeps the start of a line that is too long
+struct Truncated<'a>(&'a mut String<MAX_LOG_LINE_LENGTH>);
+
+impl Write for Truncated<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.0.push(c).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Install the logger with the level set by `ESP_LOG` when building. Info if it is not set
+/// Only a single level is supported, not the filters by module of `esp_println`
+pub fn init() {
+    let level = option_env!("ESP_LOG")
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(LevelFilter::Info);
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// The sequence of the oldest line still kept. Streams start here to send the recent past
+pub fn get_oldest_log_sequence() -> u32 {
+    critical_section::with(|cs| {
+        let buffer = LINES.borrow_ref(cs);
+        buffer.next.wrapping_sub(buffer.lines.len() as u32)
+    })
+}
+
+/// The first line from `sequence` on together with the sequence to read next
+/// Lines that were overwritten before they were read are skipped
+pub fn next_log_line(sequence: u32) -> Option<(LogLine, u32)> {
+    critical_section::with(|cs| {
+        let buffer = LINES.borrow_ref(cs);
+        let unread = buffer.next.wrapping_sub(sequence) as usize;
+        if unread == 0 {
+            return None;
+        }
+        // Fell behind by more than the buffer holds
+        let skip = buffer.lines.len().saturating_sub(unread);
+        let line = buffer.lines.oldest_ordered().nth(skip)?.clone();
+        let read = buffer
+            .next
+            .wrapping_sub((buffer.lines.len() - skip) as u32)
+            .wrapping_add(1);
+        Some((line, read))
+    })
+}
@@ -6,36 +6,76 @@
     holding buffers for the duration of a data transfer."
 )]
 
-#[cfg(not(feature = "board_selected"))]
-compile_error!("No board selected!");
-
+mod backup;
 mod board;
+mod error;
+mod logger;
 mod motion;
 mod motion_control;
 mod motor;
+mod network;
+mod ota;
+mod power;
 mod remote;
+mod storage;
+mod thermal;
 pub use ossm_motion::config;
 pub use ossm_motion::utils;
 
-use crate::board::Pins;
-use crate::motor::m57aimxx::config::{MOTOR_BAUD_RATE, STOCK_MOTOR_BAUD_RATE};
+use crate::board::{board_pins, BOARD_NAME};
+#[cfg(feature = "generic_modbus")]
+use crate::motor::generic_modbus::config::{SERVO_BAUD_RATE, SERVO_RATINGS};
+#[cfg(not(feature = "generic_modbus"))]
+use crate::motor::m57aimxx::config::{MOTOR_BAUD_RATE, MOTOR_RATINGS, STOCK_MOTOR_BAUD_RATE};
+use crate::power::{supply_monitor_task, SupplySense};
 use crate::remote::remote_connection_task;
 use crate::remote::{
     ble::{ble_events_task, ble_runner_task},
-    esp_now::{m5_heartbeat_check_task, m5_heartbeat_task, m5_task},
+    console::console_task,
+    esp_now::{
+        m5_heartbeat_check_task, m5_heartbeat_task, m5_retry_task, m5_state_task, m5_task,
+        pair_button_task,
+    },
+    sync::sync_task,
 };
 
-use crate::motion::{run_motion, set_motor_settings, wait_for_home};
+#[cfg(not(feature = "generic_modbus"))]
+use crate::motion::{calibration::travel_calibration_task, endstop::set_endstop};
+use crate::motion::{
+    estop::estop_task, homing::homing_task, motion_watchdog_task, motor_reconnection_task,
+    run_funscript, run_motion, set_motor_settings, wait_for_home,
+};
 use crate::motion_control::EspMotionControl;
+#[cfg(not(feature = "generic_modbus"))]
 use crate::motor::m57aimxx::{Motor57AIMxx, ReadOnlyMotorRegisters, ReadWriteMotorRegisters};
-use config::{CONNECTIONS_MAX, L2CAP_CHANNELS_MAX};
+#[cfg(feature = "generic_modbus")]
+use crate::motor::MachineMotor;
+#[cfg(feature = "dual_motor")]
+use crate::motor::{dual::DualMotor57AIMxx, MotorGroup};
+use crate::network::{
+    buttplug::buttplug_task,
+    mdns::mdns_task,
+    mqtt::mqtt_task,
+    net_task,
+    syslog::syslog_task,
+    tcode::tcode_task,
+    web::web_task,
+    wifi::{dhcp_task, wifi_task},
+};
+use crate::ota::ota_confirm_task;
+use crate::storage::session_save_task;
+use crate::thermal::mcu_temperature_task;
+use config::{
+    CONNECTIONS_MAX, HOSTNAME, HTTP_CONNECTIONS, L2CAP_CHANNELS_MAX, NETWORK_SOCKETS,
+};
 use log::{error, info};
 use embassy_executor::Spawner;
+use embassy_net::{DhcpConfig, StackResources};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::signal::Signal;
 use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
 use embassy_time::{Duration, Timer};
-use esp_hal::gpio::{Level, Output};
+use esp_hal::gpio::{Input, InputConfig, Level, Output, Pull};
 use esp_hal::{
     clock::CpuClock,
     gpio::Pin,
@@ -43,9 +83,12 @@ use esp_hal::{
     interrupt::software::SoftwareInterruptControl,
     interrupt::Priority,
     peripherals::Peripherals,
+    rng::Rng,
     time::Rate,
     timer::{systimer::SystemTimer, timg::TimerGroup, PeriodicTimer},
+    tsens::{self, TemperatureSensor},
     uart::{self, Instance, Uart},
+    usb_serial_jtag::UsbSerialJtag,
 };
 use esp_radio::{
     ble::controller::BleConnector,
@@ -53,6 +96,17 @@ use esp_radio::{
     Controller,
 };
 use esp_rtos::embassy::InterruptExecutor;
+use ossm_motion::diagnostics::check_config;
+use ossm_motion::motion::motion_state::set_firmware_version;
+use ossm_motion::motion_control::arming::set_arm_button_required;
+use ossm_motion::motion_control::mechanics::set_mechanics;
+use ossm_motion::motion_control::set_direction_reversed;
+use ossm_motion::motion_control::set_max_travel_mm;
+use ossm_motion::preset::set_presets;
+use ossm_motion::profile::restore_profiles;
+use ossm_motion::session_limits::restore_session_limits;
+use ossm_motion::thermal::set_thermal_limits;
+use ossm_motion::utils::rng::seed_rng;
 use static_cell::StaticCell;
 use trouble_host::{
     prelude::{DefaultPacketPool, ExternalController},
@@ -64,6 +118,7 @@ use esp_hal::system::Stack;
 
 use {esp_backtrace as _, esp_println as _};
 
+#[cfg(not(feature = "generic_modbus"))]
 use enum_iterator::all;
 
 // This creates a default app-descriptor required by the esp-idf bootloader.
@@ -82,80 +137,70 @@ macro_rules! mk_static {
 
 #[esp_rtos::main]
 async fn main(spawner: Spawner) {
-    esp_println::logger::init_logger_from_env();
+    logger::init();
 
     let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
     let peripherals = esp_hal::init(config);
+    // Before the motion is started so that the patterns get a different sequence on every boot
+    seed_rng(Rng::new().random());
 
     esp_alloc::heap_allocator!(size: 128 * 1024);
 
     info!("Welcome to ossm-rs");
     info!("Version: {}", env!("VERGEN_GIT_DESCRIBE"));
+    set_firmware_version(env!("VERGEN_GIT_DESCRIBE"));
 
-    // Dummy board to avoid LSP complaints
-    #[cfg(not(feature = "board_selected"))]
-    let pins = Pins::new(peripherals.GPIO35.degrade(), peripherals.GPIO37.degrade());
-
-    #[cfg(feature = "board_waveshare")]
-    let pins = {
-        info!("Board: WaveShare");
-        Pins::new(peripherals.GPIO18.degrade(), peripherals.GPIO17.degrade())
-            .with_rs485_transmit_enable(peripherals.GPIO21.degrade())
-    };
-
-    #[cfg(feature = "board_ossm_v3")]
-    let pins = {
-        info!("Board: OSSM v3");
-        Pins::new(peripherals.GPIO16.degrade(), peripherals.GPIO6.degrade())
-            .with_rs485_transmit_enable(peripherals.GPIO7.degrade())
-            .with_rs485_receive_enable_inv(peripherals.GPIO15.degrade())
-    };
-
-    #[cfg(feature = "board_seeed_xiao_s3")]
-    let pins = {
-        info!("Board: Seed Xiao S3");
-        Pins::new(peripherals.GPIO6.degrade(), peripherals.GPIO5.degrade())
-            .with_rs485_transmit_enable(peripherals.GPIO3.degrade())
-    };
-
-    #[cfg(feature = "board_atom_s3")]
-    let pins = {
-        info!("Board: Atom S3");
-        Pins::new(peripherals.GPIO5.degrade(), peripherals.GPIO6.degrade())
-            .with_rs485_transmit_enable(peripherals.GPIO7.degrade())
-    };
-
-    #[cfg(feature = "board_ossm_alt_v2")]
-    let pins = {
-        info!("Board: OSSM Alt Edition v2");
-        Pins::new(peripherals.GPIO22.degrade(), peripherals.GPIO20.degrade())
-            .with_rs485_transmit_enable(peripherals.GPIO21.degrade())
-            .with_i2c_sda(peripherals.GPIO18.degrade())
-            .with_i2c_scl(peripherals.GPIO19.degrade())
-    };
+    storage::init(peripherals.FLASH);
+    if let Some(travel) = storage::load_max_travel_mm() {
+        match set_max_travel_mm(travel) {
+            Ok(()) => info!("Using the calibrated travel of {} mm", travel),
+            Err(err) => error!("Stored travel {} mm not accepted: {}", travel, err),
+        }
+    }
+    // Before the first homing
+    if let Some(reversed) = storage::load_reverse_direction() {
+        set_direction_reversed(reversed);
+    }
+    if let Some(mechanics) = storage::load_mechanics() {
+        if let Err(err) = set_mechanics(mechanics) {
+            error!("Stored mechanics {:?} not accepted: {}", mechanics, err);
+        }
+    }
+    set_presets(&storage::load_presets());
+    // Before the session so that its speed and depth are capped
+    let (profiles, active_profile) = storage::load_profiles();
+    restore_profiles(&profiles, active_profile);
+    if let Some((limits, pin)) = storage::load_session_limits() {
+        if let Err(err) = restore_session_limits(limits, pin) {
+            error!("Stored session limits {:?} not accepted: {:?}", limits, err);
+        }
+    }
+    if let Some(limits) = storage::load_thermal_limits() {
+        if let Err(err) = set_thermal_limits(limits) {
+            error!("Stored thermal limits {:?} not accepted: {}", limits, err);
+        }
+    }
+    // After the travel that the soft limits are clamped to. The motion stays disabled
+    if let Some(session) = storage::load_session() {
+        session.restore();
+    }
+    // With all of the settings applied. Read over BLE as diagnostics
+    #[cfg(not(feature = "generic_modbus"))]
+    check_config(&MOTOR_RATINGS);
+    #[cfg(feature = "generic_modbus")]
+    check_config(&SERVO_RATINGS);
+    // Rolls back and reboots if an update did not confirm its last boot
+    ota::check_boot();
 
-    #[cfg(feature = "board_ossm_alt_v3")]
-    let pins = {
-        info!("Board: OSSM Alt Edition v3");
-        Pins::new(peripherals.GPIO12.degrade(), peripherals.GPIO10.degrade())
-            .with_rs485_transmit_enable(peripherals.GPIO11.degrade())
-            .with_i2c_sda(peripherals.GPIO8.degrade())
-            .with_i2c_scl(peripherals.GPIO9.degrade())
-    };
+    info!("Board: {}", BOARD_NAME);
+    let pins = board_pins!(peripherals);
 
-    #[cfg(feature = "board_custom_s3")]
-    let pins = {
-        info!("Board: Custom S3");
-        Pins::new(peripherals.GPIO35.degrade(), peripherals.GPIO37.degrade())
-            .with_rs485_transmit_enable(peripherals.GPIO36.degrade())
-    };
+    // Not needed by the motion on the second core
+    let pair_button = pins.pair_button;
+    let estop = pins.estop;
 
-    #[cfg(feature = "board_custom_c6")]
-    let pins = {
-        info!("Board: Custom C6");
-        Pins::new(peripherals.GPIO22.degrade(), peripherals.GPIO20.degrade())
-            .with_rs485_transmit_enable(peripherals.GPIO21.degrade())
-    };
+    // None of the stock boards measure the supply. See docs/supported_boards.md to set it up
+    let supply_sense: Option<&'static mut dyn SupplySense> = None;
 
     let sw_int = SoftwareInterruptControl::new(peripherals.SW_INTERRUPT);
     let systimer = SystemTimer::new(peripherals.SYSTIMER);
@@ -180,9 +225,11 @@ async fn main(spawner: Spawner) {
     // All the peripherals are initialised on the core that they will be used on
     let second_core_function = move || {
         let rs485_rx_confg = uart::RxConfig::default();
-        let rs485_config = uart::Config::default()
-            .with_rx(rs485_rx_confg)
-            .with_baudrate(MOTOR_BAUD_RATE.as_int());
+        let rs485_config = uart::Config::default().with_rx(rs485_rx_confg);
+        #[cfg(not(feature = "generic_modbus"))]
+        let rs485_config = rs485_config.with_baudrate(MOTOR_BAUD_RATE.as_int());
+        #[cfg(feature = "generic_modbus")]
+        let rs485_config = rs485_config.with_baudrate(SERVO_BAUD_RATE);
 
         let mut rs485 = Uart::new(peripherals.UART1, rs485_config)
             .expect("Failed to initialise RS485")
@@ -215,15 +262,26 @@ async fn main(spawner: Spawner) {
                 .with_scl(i2c_scl);
         }
 
+        // The generic servo is zeroed on its own
+        #[cfg(not(feature = "generic_modbus"))]
+        if let Some(endstop) = pins.endstop {
+            let config = InputConfig::default().with_pull(Pull::Up);
+            set_endstop(Input::new(endstop, config));
+        }
+
         let timg0 = TimerGroup::new(peripherals.TIMG0);
         let timg1 = TimerGroup::new(peripherals.TIMG1);
 
         // Wait for the motor to boot up
 
+        #[cfg(not(feature = "generic_modbus"))]
         let mut motor = Motor57AIMxx::new(rs485, timg0.timer0.into());
+        #[cfg(feature = "generic_modbus")]
+        let mut motor = MachineMotor::new(rs485, timg0.timer0.into());
         motor.delay(esp_hal::time::Duration::from_millis(500));
 
         // Try to read a register to see if the motor is online
+        #[cfg(not(feature = "generic_modbus"))]
         if let Err(err) = motor.get_abolute_position() {
             error!(
                 "Failed to communicate with the motor ({:?}). Trying to change baud rate",
@@ -251,19 +309,32 @@ async fn main(spawner: Spawner) {
             loop {}
         }
 
+        #[cfg(not(feature = "generic_modbus"))]
         for x in all::<ReadOnlyMotorRegisters>() {
             let val = motor.read_register(&x).expect("Could not read register");
             info!("Reg {:?} val {}", x, val);
         }
 
+        #[cfg(not(feature = "generic_modbus"))]
         for x in all::<ReadWriteMotorRegisters>() {
             let val = motor.read_register(&x).expect("Could not read register");
             info!("Reg {:?} val {}", x, val);
         }
 
-        wait_for_home(&mut motor);
+        #[cfg(feature = "dual_motor")]
+        let mut motor = {
+            let mut motor = DualMotor57AIMxx::new(motor);
+            motor
+                .try_for_each_motor(|motor| motor.get_abolute_position().map(|_| ()))
+                .expect(
+                    "Failed to communicate with the second motor. Check its address and baud rate",
+                );
+            motor
+        };
+
+        wait_for_home(&mut motor).expect("Failed to home");
 
-        set_motor_settings(&mut motor);
+        set_motor_settings(&mut motor).expect("Failed to set the motor settings");
 
         let update_timer = PeriodicTimer::new(timg1.timer0);
         EspMotionControl::init(update_timer, motor);
@@ -273,6 +344,12 @@ async fn main(spawner: Spawner) {
         let spawner = executor_core1.start(Priority::Priority1);
 
         spawner.must_spawn(run_motion());
+        spawner.must_spawn(run_funscript());
+        spawner.must_spawn(motor_reconnection_task());
+        spawner.must_spawn(motion_watchdog_task());
+        #[cfg(not(feature = "generic_modbus"))]
+        spawner.must_spawn(travel_calibration_task());
+        spawner.must_spawn(homing_task());
 
         MOTION_INIT_SIGNAL.signal(true);
 
@@ -303,6 +380,7 @@ async fn main(spawner: Spawner) {
     );
 
     let wifi = peripherals.WIFI;
+    // Joins the provisioned network in the WiFi task. ESP-NOW needs it started before
     let (mut wifi_controller, interfaces) =
         esp_radio::wifi::new(radio, wifi, Default::default()).unwrap();
     wifi_controller
@@ -310,6 +388,17 @@ async fn main(spawner: Spawner) {
         .unwrap();
     wifi_controller.start().unwrap();
 
+    let mut dhcp_config = DhcpConfig::default();
+    dhcp_config.hostname = Some(HOSTNAME.try_into().expect("Always fits"));
+    let rng = Rng::new();
+    let seed = ((rng.random() as u64) << 32) | rng.random() as u64;
+    let (net_stack, net_runner) = embassy_net::new(
+        interfaces.sta,
+        embassy_net::Config::dhcpv4(dhcp_config),
+        mk_static!(StackResources<NETWORK_SOCKETS>, StackResources::new()),
+        seed,
+    );
+
     let esp_now = interfaces.esp_now;
     info!("esp-now version {}", esp_now.version().unwrap());
 
@@ -339,13 +428,52 @@ async fn main(spawner: Spawner) {
     } = stack.build();
 
     spawner.must_spawn(m5_task(manager, sender, receiver));
-    spawner.must_spawn(m5_heartbeat_task(manager, sender));
+    spawner.must_spawn(m5_heartbeat_task(sender));
+    spawner.must_spawn(m5_state_task(sender));
+    spawner.must_spawn(m5_retry_task(sender));
     spawner.must_spawn(m5_heartbeat_check_task());
+    spawner.must_spawn(sync_task(sender));
+    if let Some(pair_button) = pair_button {
+        set_arm_button_required(config::ARM_REQUIRES_BUTTON);
+        let config = InputConfig::default().with_pull(Pull::Up);
+        spawner.must_spawn(pair_button_task(Input::new(pair_button, config)));
+    } else if config::ARM_REQUIRES_BUTTON {
+        error!("No pair button to press before arming. Arming without it");
+    }
+    if let Some(estop) = estop {
+        let config = InputConfig::default().with_pull(Pull::Up);
+        spawner.must_spawn(estop_task(Input::new(estop, config)));
+    }
 
     spawner.must_spawn(ble_runner_task(runner));
-    spawner.must_spawn(ble_events_task(stack, peripheral));
+    spawner.must_spawn(ble_events_task(spawner, stack, peripheral));
+
+    spawner.must_spawn(wifi_task(wifi_controller));
+    spawner.must_spawn(net_task(net_runner));
+    spawner.must_spawn(dhcp_task(net_stack));
+    spawner.must_spawn(mdns_task(net_stack));
+    for _ in 0..HTTP_CONNECTIONS {
+        spawner.must_spawn(web_task(net_stack));
+    }
+    spawner.must_spawn(mqtt_task(net_stack));
+    spawner.must_spawn(tcode_task(net_stack));
+    spawner.must_spawn(buttplug_task(net_stack));
+    spawner.must_spawn(syslog_task(net_stack));
 
+    spawner.must_spawn(ota_confirm_task());
+    spawner.must_spawn(session_save_task());
     spawner.must_spawn(remote_connection_task());
+    spawner.must_spawn(console_task(
+        UsbSerialJtag::new(peripherals.USB_DEVICE).into_async(),
+    ));
+
+    if let Some(supply_sense) = supply_sense {
+        spawner.must_spawn(supply_monitor_task(supply_sense));
+    }
+    match TemperatureSensor::new(peripherals.TSENS, tsens::Config::default()) {
+        Ok(sensor) => spawner.must_spawn(mcu_temperature_task(sensor)),
+        Err(err) => error!("Could not start the MCU temperature sensor {:?}", err),
+    }
 
     loop {
         // ESP-NOW does not work without this
@@ -0,0 +1,151 @@
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use log::{error, info};
+use ossm_motion::{
+    float::Real,
+    motion::{demo::is_demo_active, motion_state::get_motion_state},
+    motion_control::{arming::is_armed, is_faulted, pause_for_reconnect, set_max_travel_mm},
+    runtime_config::get_stall_current_a,
+};
+
+use crate::{
+    config::{MAX_CALIBRATED_TRAVEL_MM, MIN_MOVE_MM},
+    error::{report_error, ConfigError, Error, MotionError},
+    motion::{
+        mm_to_steps, set_motor_settings, steps_to_mm, stop_if_faulted, wait_for_home,
+        wait_for_target_reached,
+    },
+    motion_control::EspMotionControl,
+    motor::{MachineMotor, MotorGroup},
+    storage::save_max_travel_mm,
+};
+
+// Slow and weak enough to stop at the end of the rail without damage
+const CALIBRATION_SPEED_RPM: u16 = 80;
+const CALIBRATION_MAX_OUTPUT: u16 = 89;
+// Ignore the current spike while accelerating
+const CALIBRATION_SETTLE_MS: u64 = 300;
+const CALIBRATION_POLL_INTERVAL_MS: u64 = 20;
+// Give up if the end was not found by then
+const CALIBRATION_TIMEOUT_MS: u64 = 30_000;
+// Kept free at the far end so that motion never touches the end of the rail
+const CALIBRATION_MARGIN_MM: Real = 5.0;
+
+static CALIBRATION_REQUESTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Measure the usable travel of the machine. Only possible while armed with the motion disabled
+pub fn request_travel_calibration() -> Result<(), MotionError> {
+    if get_motion_state().motion_enabled || is_demo_active() {
+        return Err(MotionError::MotionEnabled);
+    }
+    if is_faulted() {
+        return Err(MotionError::Faulted);
+    }
+    if !is_armed() {
+        return Err(MotionError::NotArmed);
+    }
+
+    CALIBRATION_REQUESTED.signal(());
+    Ok(())
+}
+
+/// Drive away from home until the motor stalls at the end of the rail
+/// Returns the travel from MIN_MOVE_MM to the end minus the margin in mm
+/// Fails if the motor never stalled, so that only a found end is stored
+fn measure_travel(motor: &mut MachineMotor) -> Result<Real, Error> {
+    motor.try_for_each_motor(|motor| {
+        motor.set_target_speed(CALIBRATION_SPEED_RPM)?;
+        motor.set_max_allowed_output(CALIBRATION_MAX_OUTPUT)?;
+        motor.set_absolute_position(mm_to_steps(MIN_MOVE_MM + MAX_CALIBRATED_TRAVEL_MM))
+    })?;
+
+    info!(
+        "Searching for the end of the travel above {} A...",
+        get_stall_current_a()
+    );
+    let mut elapsed_ms = 0;
+    let found = loop {
+        motor.primary().delay(esp_hal::time::Duration::from_millis(
+            CALIBRATION_POLL_INTERVAL_MS,
+        ));
+        elapsed_ms += CALIBRATION_POLL_INTERVAL_MS;
+        stop_if_faulted(motor)?;
+
+        let current = motor.primary().get_current()? as Real;
+        if elapsed_ms > CALIBRATION_SETTLE_MS && current > get_stall_current_a() {
+            info!("End of the travel found at {} A", current);
+            break Ok(());
+        }
+        if motor.primary().get_target_position()?.abs() < 15 {
+            error!("No end found within the max calibrated travel");
+            break Err(MotionError::NoEndFound);
+        }
+        if elapsed_ms > CALIBRATION_TIMEOUT_MS {
+            error!("Timed out searching for the end of the travel");
+            break Err(MotionError::CalibrationTimeout);
+        }
+    };
+
+    // Stop pushing against the end
+    let end_steps = motor.primary().get_abolute_position()?;
+    motor.try_for_each_motor(|motor| motor.set_absolute_position(end_steps))?;
+
+    found?;
+    Ok(steps_to_mm(end_steps) - MIN_MOVE_MM - CALIBRATION_MARGIN_MM)
+}
+
+/// Home, measure the travel and store it. The machine is back at MIN_MOVE_MM afterwards
+fn calibrate_travel(motor: &mut MachineMotor) -> Result<(), Error> {
+    wait_for_home(motor)?;
+
+    let measured = measure_travel(motor);
+
+    // Go back even if the measurement failed, but not after an emergency stop
+    stop_if_faulted(motor)?;
+    motor.try_for_each_motor(|motor| {
+        motor.set_target_speed(CALIBRATION_SPEED_RPM)?;
+        motor.set_absolute_position(mm_to_steps(MIN_MOVE_MM))
+    })?;
+    wait_for_target_reached(motor)?;
+    set_motor_settings(motor)?;
+
+    let travel = measured?;
+    set_max_travel_mm(travel).map_err(ConfigError::from)?;
+    info!("Calibrated travel {} mm", travel);
+
+    if let Err(err) = save_max_travel_mm(travel) {
+        error!("The calibrated travel will be lost after a power cycle");
+        report_error(err);
+    }
+
+    Ok(())
+}
+
+/// Task to run the travel calibration on request
+/// Motion control is paused in the meantime
+#[embassy_executor::task]
+pub async fn travel_calibration_task() {
+    info!("Task Travel Calibration Started");
+
+    loop {
+        CALIBRATION_REQUESTED.wait().await;
+
+        info!("Calibrating the travel");
+        let result = EspMotionControl::with_detached(|motion_control| {
+            let result = calibrate_travel(motion_control.motor_mut());
+            // Motor faults pause motion control again until it is reconnected
+            motion_control.resume_after_reconnect(MIN_MOVE_MM);
+            if let Err(Error::Motion(MotionError::Faulted)) = result {
+                // Stopped somewhere on the rail with the homing settings. The reconnection
+                // homes again once the machine is re-armed
+                pause_for_reconnect();
+            }
+            result
+        });
+
+        match result {
+            Some(Ok(())) => {}
+            Some(Err(err)) => report_error(err),
+            None => report_error(MotionError::Detached),
+        }
+    }
+}
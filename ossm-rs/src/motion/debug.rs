@@ -0,0 +1,109 @@
+use core::{
+    fmt::{self, Display},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use ossm_motion::{
+    config::DEBUG_STREAM_DECIMATION,
+    float::Real,
+    motion_control::{debug::DebugOut, timer::Timer},
+};
+
+use crate::motion::timer::EspTimer;
+
+// Samples waiting to be sent. Dropped while the queue is full
+const DEBUG_QUEUE_LENGTH: usize = 8;
+
+static DEBUG_STREAMING: AtomicBool = AtomicBool::new(false);
+static DEBUG_SAMPLES: Channel<CriticalSectionRawMutex, DebugSample, DEBUG_QUEUE_LENGTH> =
+    Channel::new();
+
+#[derive(Debug, Clone, Copy)]
+pub struct DebugSample {
+    time_ms: u64,
+    position: Real,
+    velocity: Real,
+    acceleration: Real,
+}
+
+/// Formatted as `<time ms>:<position mm>:<velocity mm/s>:<acceleration mm/s²>`
+impl Display for DebugSample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{:.2}:{:.2}:{:.1}",
+            self.time_ms, self.position, self.velocity, self.acceleration
+        )
+    }
+}
+
+/// Queues every DEBUG_STREAM_DECIMATION-th update while streaming
+pub struct StreamDebugOut {
+    timer: EspTimer,
+    updates: u32,
+    position: Real,
+    velocity: Real,
+    acceleration: Real,
+}
+
+impl StreamDebugOut {
+    pub fn new() -> Self {
+        Self {
+            timer: EspTimer::new(),
+            updates: 0,
+            position: 0.0,
+            velocity: 0.0,
+            acceleration: 0.0,
+        }
+    }
+}
+
+impl DebugOut for StreamDebugOut {
+    fn new_position(&mut self, position: Real) {
+        self.position = position;
+    }
+
+    fn new_velocity(&mut self, velocity: Real) {
+        self.velocity = velocity;
+    }
+
+    fn new_acceleration(&mut self, acceleration: Real) {
+        self.acceleration = acceleration;
+    }
+
+    // The last of the values passed every update
+    fn new_jerk(&mut self, _jerk: Real) {
+        if !DEBUG_STREAMING.load(Ordering::Acquire) {
+            return;
+        }
+
+        self.updates += 1;
+        if self.updates < DEBUG_STREAM_DECIMATION {
+            return;
+        }
+        self.updates = 0;
+
+        let sample = DebugSample {
+            time_ms: self.timer.now().duration_since_epoch().to_millis(),
+            position: self.position,
+            velocity: self.velocity,
+            acceleration: self.acceleration,
+        };
+        // Called from the control loop. Never wait for the receiver
+        let _ = DEBUG_SAMPLES.try_send(sample);
+    }
+}
+
+/// Start or stop queueing the samples
+pub fn set_debug_streaming(enabled: bool) {
+    if enabled {
+        DEBUG_SAMPLES.clear();
+    }
+    DEBUG_STREAMING.store(enabled, Ordering::Release);
+}
+
+/// Wait for the next sample
+pub async fn next_debug_sample() -> DebugSample {
+    DEBUG_SAMPLES.receive().await
+}
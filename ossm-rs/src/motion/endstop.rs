@@ -0,0 +1,110 @@
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use esp_hal::gpio::{Input, Level};
+use log::{error, info};
+use ossm_motion::motion_control::is_direction_reversed;
+
+use crate::{
+    config::{MAX_CALIBRATED_TRAVEL_MM, MIN_MOVE_MM},
+    error::{Error, MotionError},
+    motion::{mm_to_steps, steps_to_mm, stop_if_faulted},
+    motor::{MachineMotor, MotorGroup},
+};
+
+// Level of the pin when the switch is closed
+// The pin is pulled up, so a switch that connects it to ground reads low
+pub const ENDSTOP_CLOSED_LEVEL: Level = Level::Low;
+
+// Slow and weak enough to not damage anything if the switch does not trigger
+const ENDSTOP_HOMING_SPEED_RPM: u16 = 80;
+const ENDSTOP_HOMING_MAX_OUTPUT: u16 = 89;
+// How often the switch is checked while moving towards it
+const ENDSTOP_POLL_INTERVAL_MS: u64 = 2;
+
+static ENDSTOP: Mutex<RefCell<Option<Input<'static>>>> = Mutex::new(RefCell::new(None));
+
+/// Home on the switch instead of the internal sensorless homing of the motor
+pub fn set_endstop(endstop: Input<'static>) {
+    critical_section::with(|cs| {
+        ENDSTOP.borrow_ref_mut(cs).replace(endstop);
+    });
+}
+
+/// None if the board has no endstop
+fn is_endstop_closed() -> Option<bool> {
+    critical_section::with(|cs| {
+        ENDSTOP
+            .borrow_ref(cs)
+            .as_ref()
+            .map(|endstop| endstop.level() == ENDSTOP_CLOSED_LEVEL)
+    })
+}
+
+/// Drive towards home until the switch closes and make that the zero position
+/// Returns whether the switch was found
+fn search_endstop(motor: &mut MachineMotor) -> Result<bool, Error> {
+    // Far enough to reach home from anywhere on the rail
+    let start = steps_to_mm(motor.primary().get_abolute_position()?);
+    let target = mm_to_steps(start - MIN_MOVE_MM - MAX_CALIBRATED_TRAVEL_MM);
+
+    motor.try_for_each_motor(|motor| {
+        motor.enable_modbus(true)?;
+        motor.set_dir_polarity(is_direction_reversed())?;
+        motor.set_target_speed(ENDSTOP_HOMING_SPEED_RPM)?;
+        motor.set_max_allowed_output(ENDSTOP_HOMING_MAX_OUTPUT)?;
+        motor.set_absolute_position(target)
+    })?;
+
+    let found = loop {
+        stop_if_faulted(motor)?;
+        if is_endstop_closed() == Some(true) {
+            break true;
+        }
+        if motor.primary().get_target_position()?.abs() < 15 {
+            break false;
+        }
+        motor.primary().delay(esp_hal::time::Duration::from_millis(
+            ENDSTOP_POLL_INTERVAL_MS,
+        ));
+    };
+
+    // Stop where the switch closed
+    let steps = motor.primary().get_abolute_position()?;
+    motor.try_for_each_motor(|motor| motor.set_absolute_position(steps))?;
+
+    if found {
+        motor.try_for_each_motor(|motor| motor.reset_absolute_position())?;
+    }
+
+    Ok(found)
+}
+
+/// Home on the endstop if the board has one
+/// Returns false if the internal sensorless homing has to be used instead
+/// Only an emergency stop fails the homing
+pub fn home_on_endstop(motor: &mut MachineMotor) -> Result<bool, Error> {
+    if is_endstop_closed().is_none() {
+        return Ok(false);
+    }
+
+    info!("Homing on the endstop...");
+    match search_endstop(motor) {
+        Ok(true) => {
+            info!("Endstop reached");
+            Ok(true)
+        }
+        Ok(false) => {
+            error!("The endstop never closed. Check the wiring. Using sensorless homing");
+            Ok(false)
+        }
+        Err(Error::Motion(MotionError::Faulted)) => Err(MotionError::Faulted.into()),
+        Err(err) => {
+            error!(
+                "Homing on the endstop failed {:?}. Using sensorless homing",
+                err
+            );
+            Ok(false)
+        }
+    }
+}
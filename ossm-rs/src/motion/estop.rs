@@ -0,0 +1,64 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_time::{Duration, Instant, Ticker};
+use esp_hal::gpio::{Input, Level};
+use log::{error, info};
+use ossm_motion::motion_control::set_estop_input;
+
+// Level of the pin when the switch is open
+// The pin is pulled up, so an open switch or a broken wire reads high
+pub const ESTOP_ACTIVE_LEVEL: Level = Level::High;
+
+// How often the pin is read
+const ESTOP_POLL_INTERVAL_MS: u64 = 2;
+// How long the pin has to keep a new level until it counts
+const ESTOP_DEBOUNCE_MS: u64 = 10;
+
+// Whether the board has the pin
+static ESTOP_PRESENT: AtomicBool = AtomicBool::new(false);
+// The level of the pin as last read without the debouncing
+static ESTOP_PIN_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// The pin as last read, to check the wiring. None if the board has no e-stop
+pub fn is_estop_pin_active() -> Option<bool> {
+    ESTOP_PRESENT
+        .load(Ordering::Acquire)
+        .then(|| ESTOP_PIN_ACTIVE.load(Ordering::Acquire))
+}
+
+/// Task to stop the machine while the e-stop input is active
+/// A normally closed switch to ground, so that a broken wire stops the machine as well
+#[embassy_executor::task]
+pub async fn estop_task(pin: Input<'static>) {
+    info!("Task E-Stop Started");
+    ESTOP_PRESENT.store(true, Ordering::Release);
+
+    let mut ticker = Ticker::every(Duration::from_millis(ESTOP_POLL_INTERVAL_MS));
+    let mut active = false;
+    // When the pin started to read the other level
+    let mut changed_at: Option<Instant> = None;
+    loop {
+        let pin_active = pin.level() == ESTOP_ACTIVE_LEVEL;
+        ESTOP_PIN_ACTIVE.store(pin_active, Ordering::Release);
+
+        if pin_active == active {
+            changed_at = None;
+        } else if changed_at
+            .get_or_insert_with(Instant::now)
+            .elapsed()
+            .as_millis()
+            >= ESTOP_DEBOUNCE_MS
+        {
+            active = pin_active;
+            changed_at = None;
+            if active {
+                error!("E-stop pressed");
+            } else {
+                info!("E-stop released. Re-arm to move again");
+            }
+            set_estop_input(active);
+        }
+
+        ticker.next().await;
+    }
+}
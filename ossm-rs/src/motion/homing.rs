@@ -0,0 +1,111 @@
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant, Timer};
+use log::{error, info};
+use ossm_motion::{
+    motion::{demo::stop_demo, motion_state::set_motion_enabled},
+    motion_control::{
+        arming::is_armed, is_faulted, is_move_in_progress, pause_for_reconnect,
+        set_direction_reversed,
+    },
+};
+
+use crate::{
+    config::MIN_MOVE_MM,
+    error::{report_error, Error, MotionError},
+    motion::{set_motor_settings, wait_for_home},
+    motion_control::EspMotionControl,
+};
+
+const HOMING_POLL_INTERVAL_MS: u64 = 20;
+// Give up if the machine is still moving by then after the motion was disabled
+const HOMING_STOP_TIMEOUT_MS: u64 = 10_000;
+
+static HOMING_REQUESTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+// The direction to home in next. Applied once motion control stopped using the old one
+static REQUESTED_DIRECTION: Mutex<Cell<Option<bool>>> = Mutex::new(Cell::new(None));
+
+/// Disable the motion and home again once the machine stopped, e.g. after the belt slipped
+/// Only while armed
+pub fn request_homing() -> Result<(), MotionError> {
+    if is_faulted() {
+        return Err(MotionError::Faulted);
+    }
+    if !is_armed() {
+        return Err(MotionError::NotArmed);
+    }
+
+    stop_demo();
+    set_motion_enabled(false);
+    HOMING_REQUESTED.signal(());
+    Ok(())
+}
+
+/// Reverse the way the machine moves and home again with it
+/// Applied by the next homing if the machine can not home right now
+pub fn request_direction_reversed(reversed: bool) -> Result<(), MotionError> {
+    critical_section::with(|cs| REQUESTED_DIRECTION.borrow(cs).set(Some(reversed)));
+    request_homing()
+}
+
+fn take_requested_direction() -> Option<bool> {
+    critical_section::with(|cs| REQUESTED_DIRECTION.borrow(cs).take())
+}
+
+/// Wait for the retract or the stop after the motion was disabled
+/// False if the machine is still moving after `HOMING_STOP_TIMEOUT_MS`
+async fn wait_for_standstill() -> bool {
+    let start = Instant::now();
+    // The motion task needs a moment to start the retract
+    Timer::after(Duration::from_millis(HOMING_POLL_INTERVAL_MS)).await;
+    while is_move_in_progress() {
+        if start.elapsed() > Duration::from_millis(HOMING_STOP_TIMEOUT_MS) {
+            return false;
+        }
+        Timer::after(Duration::from_millis(HOMING_POLL_INTERVAL_MS)).await;
+    }
+    true
+}
+
+/// Task to home again on request
+/// Motion control is paused in the meantime
+#[embassy_executor::task]
+pub async fn homing_task() {
+    info!("Task Homing Started");
+
+    loop {
+        HOMING_REQUESTED.wait().await;
+
+        if !wait_for_standstill().await {
+            error!("The machine did not stop to home again");
+            report_error(MotionError::MotionEnabled);
+            continue;
+        }
+
+        info!("Homing again");
+        let result = EspMotionControl::with_detached(|motion_control| {
+            if let Some(reversed) = take_requested_direction() {
+                set_direction_reversed(reversed);
+            }
+            let motor = motion_control.motor_mut();
+            let result =
+                wait_for_home(motor).and_then(|()| set_motor_settings(motor).map_err(Error::from));
+            // Motor faults pause motion control again until it is reconnected
+            motion_control.resume_after_reconnect(MIN_MOVE_MM);
+            if let Err(Error::Motion(MotionError::Faulted)) = result {
+                // Stopped before home was found. The reconnection homes again once the machine
+                // is re-armed
+                pause_for_reconnect();
+            }
+            result
+        });
+
+        match result {
+            Some(Ok(())) => {}
+            Some(Err(err)) => report_error(err),
+            None => report_error(MotionError::Detached),
+        }
+    }
+}
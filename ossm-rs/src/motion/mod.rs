@@ -1,79 +1,418 @@
+#[cfg(not(feature = "generic_modbus"))]
+pub mod calibration;
+pub mod debug;
+#[cfg(not(feature = "generic_modbus"))]
+pub mod endstop;
+pub mod estop;
+pub mod homing;
 pub mod timer;
 
 use crate::{
-    config::{MIN_MOVE_MM, REVERSE_DIRECTION, STEPS_PER_MM},
-    motor::m57aimxx::{Motor57AIMxx, MAX_MOTOR_SPEED_RPM},
+    config::{MIN_MOVE_MM, MOTION_CONTROL_WATCHDOG_TIMEOUT_MS},
+    error::{report_error, Error, MotionError},
+    motion_control::EspMotionControl,
+    motor::{get_response_error_counts, MachineMotor, MotorError},
+};
+#[cfg(not(feature = "generic_modbus"))]
+use crate::{
+    motion::endstop::home_on_endstop,
+    motor::{
+        m57aimxx::{
+            config::{
+                MOTOR_BAUD_RATE, MOTOR_MAX_ALLOWED_OUTPUT, MOTOR_SETTINGS, STOCK_MOTOR_BAUD_RATE,
+            },
+            ReadWriteMotorRegisters, ReadableMotorRegister,
+        },
+        MotorGroup,
+    },
+};
+use embassy_time::{Duration, Ticker};
+#[cfg(not(feature = "generic_modbus"))]
+use enum_iterator::all;
+use log::{error, info, warn};
+use ossm_motion::{
+    event::{publish_event, Event},
+    fault::get_fault_count,
+    float::Real,
+    motion_control::{
+        is_direction_reversed, is_faulted, is_motor_connected, mechanics::get_steps_per_mm,
+    },
+};
+#[cfg(not(feature = "generic_modbus"))]
+use ossm_motion::{
+    motion::{demo::is_demo_active, motion_state::get_motion_state},
+    motion_control::{is_estop_latched, is_move_in_progress, motor::Motor},
 };
-use log::info;
 
-/// Set the default motor settings
-pub fn set_motor_settings(motor: &mut Motor57AIMxx) {
-    // Set high speed and acceleration since those are controlled by motion control
-    motor
-        .set_target_speed(MAX_MOTOR_SPEED_RPM)
-        .expect("Failed to set target speed");
-    motor
-        .set_target_acceleration(50000)
-        .expect("Failed to set target acceleration");
+// How often to check the motor connection and retry reconnecting
+const MOTOR_RECONNECT_INTERVAL_MS: u64 = 1000;
+// How often to check that the control loop is still running
+const MOTION_WATCHDOG_INTERVAL_MS: u64 = MOTION_CONTROL_WATCHDOG_TIMEOUT_MS / 2;
+// The generic servo can not report reaching the target. Long enough to move across the rail
+#[cfg(feature = "generic_modbus")]
+const GENERIC_SERVO_MOVE_MS: u64 = 3000;
+// Steps from the target at which a motor has reached it
+#[cfg(not(feature = "generic_modbus"))]
+const TARGET_REACHED_STEPS: i32 = 15;
+// How often the homing, the calibration and the reconnection check for an emergency stop while
+// they wait for the motor
+const DETACHED_POLL_INTERVAL_MS: u64 = 20;
 
-    // Defaults from OSSM
-    motor
-        .set_speed_proportional_coefficient(3000)
-        .expect("Failed to set speed proportional coefficient");
-    motor
-        .set_position_proportional_coefficient(3000)
-        .expect("Failed to set position proportional coefficient");
-    motor
-        .set_max_allowed_output(600)
-        .expect("Failed to set max allowed output");
+/// Set the default motor settings and check that the drive took them
+#[cfg(not(feature = "generic_modbus"))]
+pub fn set_motor_settings(motor: &mut MachineMotor) -> Result<(), MotorError> {
+    motor.try_for_each_motor(|motor| {
+        motor.write_settings(&MOTOR_SETTINGS)?;
+        motor.set_max_allowed_output(MOTOR_MAX_ALLOWED_OUTPUT)
+    })?;
+
+    motor.try_for_each_motor(|motor| {
+        // Corrected right away if the drive did not take them
+        motor.restore_settings(&MOTOR_SETTINGS)?;
+
+        let output = motor.get_max_allowed_output()?;
+        if output != MOTOR_MAX_ALLOWED_OUTPUT {
+            error!(
+                "Motor did not retain its max allowed output {} != {}",
+                output, MOTOR_MAX_ALLOWED_OUTPUT
+            );
+            motor.set_max_allowed_output(MOTOR_MAX_ALLOWED_OUTPUT)?;
+        }
+        Ok(())
+    })
 }
 
-/// Home and wait until done
-pub fn wait_for_home(motor: &mut Motor57AIMxx) {
-    // Set slower speed and output for homing
-    motor
-        .set_target_speed(80)
-        .expect("Failed to set target speed");
-    motor
-        .set_max_allowed_output(89)
-        .expect("Failed to set max allowed output");
-    motor
-        .set_dir_polarity(REVERSE_DIRECTION)
-        .expect("Failed to set direction polarity");
+/// Convert a position in mm to the absolute position of the motor in steps
+pub fn mm_to_steps(mm: Real) -> i32 {
+    let steps = mm * get_steps_per_mm();
+    if is_direction_reversed() {
+        steps as i32
+    } else {
+        -steps as i32
+    }
+}
+
+/// Convert the absolute position of the motor in steps to mm
+#[cfg(not(feature = "generic_modbus"))]
+pub fn steps_to_mm(steps: i32) -> Real {
+    let mm = steps as Real / get_steps_per_mm();
+    if is_direction_reversed() {
+        mm
+    } else {
+        -mm
+    }
+}
+
+/// The generic servo keeps the settings made on it. Motion control sets the torque
+#[cfg(feature = "generic_modbus")]
+pub fn set_motor_settings(_motor: &mut MachineMotor) -> Result<(), MotorError> {
+    Ok(())
+}
 
-    motor.home().expect("Failed to start homing");
+/// Stop every motor where it is if the machine was emergency stopped
+/// Motion control does not run while the motor is taken out of the control loop, so the homing,
+/// the calibration and the reconnection call this between their steps and while they wait.
+/// The hardware e-stop input also drops the torque like the control loop does
+#[cfg(not(feature = "generic_modbus"))]
+pub fn stop_if_faulted(motor: &mut MachineMotor) -> Result<(), Error> {
+    if !is_faulted() {
+        return Ok(());
+    }
+
+    error!("Emergency stop while motion control is paused");
+    let steps = motor.primary().get_abolute_position()?;
+    motor.try_for_each_motor(|motor| {
+        motor.set_absolute_position(steps)?;
+        if is_estop_latched() {
+            motor.set_torque_pct(0.0)?;
+        }
+        Ok(())
+    })?;
+    Err(MotionError::Faulted.into())
+}
+
+/// The position of the generic servo can not be read. Its torque is dropped instead
+#[cfg(feature = "generic_modbus")]
+pub fn stop_if_faulted(motor: &mut MachineMotor) -> Result<(), Error> {
+    if !is_faulted() {
+        return Ok(());
+    }
+
+    error!("Emergency stop while motion control is paused");
+    motor.set_torque_pct(0.0)?;
+    Err(MotionError::Faulted.into())
+}
+
+/// Wait until every motor reached its target
+/// Fails with `MotionError::Faulted` if the machine is emergency stopped in the meantime
+#[cfg(not(feature = "generic_modbus"))]
+pub fn wait_for_target_reached(motor: &mut MachineMotor) -> Result<(), Error> {
+    loop {
+        stop_if_faulted(motor)?;
+
+        let mut reached = true;
+        motor.try_for_each_motor(|motor| {
+            reached &= motor.get_target_position()?.abs() < TARGET_REACHED_STEPS;
+            Ok(())
+        })?;
+        if reached {
+            return Ok(());
+        }
+
+        motor.primary().delay(esp_hal::time::Duration::from_millis(
+            DETACHED_POLL_INTERVAL_MS,
+        ));
+    }
+}
+
+/// Home using the internal sensorless homing of the motor
+#[cfg(not(feature = "generic_modbus"))]
+fn home_sensorless(motor: &mut MachineMotor) -> Result<(), Error> {
+    motor.try_for_each_motor(|motor| {
+        // Set slower speed and output for homing
+        motor.set_target_speed(80)?;
+        motor.set_max_allowed_output(89)?;
+        motor.set_dir_polarity(is_direction_reversed())?;
+
+        motor.home()
+    })?;
 
     info!("Homing...");
-    motor.wait_for_target_reached(15);
+    wait_for_target_reached(motor)
+}
+
+/// Home and wait until done
+/// Uses the endstop if the board has one. All the motors home at the same time
+/// Stops and fails with `MotionError::Faulted` if the machine is emergency stopped meanwhile
+#[cfg(not(feature = "generic_modbus"))]
+pub fn wait_for_home(motor: &mut MachineMotor) -> Result<(), Error> {
+    stop_if_faulted(motor)?;
+    if !home_on_endstop(motor)? {
+        home_sensorless(motor)?;
+    }
     info!("Homing Done");
 
-    motor.delay(esp_hal::time::Duration::from_millis(20));
+    motor
+        .primary()
+        .delay(esp_hal::time::Duration::from_millis(20));
 
     // Enabling modbus seems to reset the target speed and the max allowed output to default
-    motor.enable_modbus(true).expect("Failed to enable modbus");
+    motor.try_for_each_motor(|motor| motor.enable_modbus(true))?;
 
-    motor.delay(esp_hal::time::Duration::from_millis(800));
+    motor
+        .primary()
+        .delay(esp_hal::time::Duration::from_millis(800));
+    stop_if_faulted(motor)?;
 
-    let mut new_steps = MIN_MOVE_MM * STEPS_PER_MM;
-    if !REVERSE_DIRECTION {
-        new_steps = -new_steps;
-    }
+    motor.try_for_each_motor(|motor| {
+        motor.set_target_speed(100)?;
+        motor.set_absolute_position(mm_to_steps(MIN_MOVE_MM))
+    })?;
 
     motor
-        .set_target_speed(100)
-        .expect("Failed to set target speed");
-    motor
-        .set_absolute_position(new_steps as i32)
-        .expect("Failed to move to the minimum position");
+        .primary()
+        .delay(esp_hal::time::Duration::from_millis(20));
+
+    wait_for_target_reached(motor)?;
 
-    motor.delay(esp_hal::time::Duration::from_millis(20));
+    info!("Moved to minimum position");
+    publish_event(Event::HomingComplete);
+    Ok(())
+}
 
-    motor.wait_for_target_reached(15);
+/// The generic servo has no homing of its own. Its zero has to be at the retracted end
+/// Only enables it and moves to MIN_MOVE_MM
+#[cfg(feature = "generic_modbus")]
+pub fn wait_for_home(motor: &mut MachineMotor) -> Result<(), Error> {
+    stop_if_faulted(motor)?;
+    motor.enable()?;
+    motor.set_absolute_position(mm_to_steps(MIN_MOVE_MM))?;
+    for _ in 0..GENERIC_SERVO_MOVE_MS / DETACHED_POLL_INTERVAL_MS {
+        motor.delay(esp_hal::time::Duration::from_millis(
+            DETACHED_POLL_INTERVAL_MS,
+        ));
+        stop_if_faulted(motor)?;
+    }
 
     info!("Moved to minimum position");
+    publish_event(Event::HomingComplete);
+    Ok(())
+}
+
+/// Try to bring a motor that stopped responding back
+/// Returns the position of the motor in mm once it is ready for motion again
+/// or None if it is still not responding or has to home while the machine is emergency stopped
+#[cfg(not(feature = "generic_modbus"))]
+pub fn reconnect_motor(motor: &mut MachineMotor) -> Result<Option<Real>, Error> {
+    let mut responding = true;
+    motor.try_for_each_motor(|motor| {
+        if motor.get_abolute_position().is_err() {
+            responding = false;
+
+            // The motor may have been reset to the stock baud rate
+            motor.set_bus_baud_rate(&STOCK_MOTOR_BAUD_RATE)?;
+            let stock_baud_rate = motor.get_abolute_position().is_ok();
+            if stock_baud_rate {
+                motor.set_baud_rate(MOTOR_BAUD_RATE)?;
+                error!("Motor baudrate updated. Please power cycle the motor!");
+            }
+            motor.set_bus_baud_rate(&MOTOR_BAUD_RATE)?;
+        }
+        Ok(())
+    })?;
+
+    if !responding {
+        return Ok(None);
+    }
+
+    // The settings are not saved, so the defaults mean that the motor was power cycled
+    // and lost its position
+    let mut reset = false;
+    motor.try_for_each_motor(|motor| {
+        reset |= motor.get_target_speed()? != MOTOR_SETTINGS.target_speed;
+        Ok(())
+    })?;
+    if reset {
+        info!("Motor was reset. Homing again");
+    }
+
+    let in_sync = motor.is_in_sync()?;
+    if !in_sync {
+        info!("Motors are out of sync. Homing again");
+    }
+
+    if reset || !in_sync {
+        if is_faulted() {
+            warn!("Re-arm to home again");
+            return Ok(None);
+        }
+        wait_for_home(motor)?;
+        set_motor_settings(motor)?;
+
+        return Ok(Some(MIN_MOVE_MM));
+    }
+
+    motor.try_for_each_motor(|motor| motor.enable_modbus(true))?;
+    set_motor_settings(motor)?;
+
+    let steps = motor.primary().get_abolute_position()?;
+
+    Ok(Some(steps_to_mm(steps)))
+}
+
+/// Try to bring a servo that stopped responding back
+/// Its position can not be read, so it is moved back to MIN_MOVE_MM
+#[cfg(feature = "generic_modbus")]
+pub fn reconnect_motor(motor: &mut MachineMotor) -> Result<Option<Real>, Error> {
+    if is_faulted() {
+        warn!("Re-arm to home again");
+        return Ok(None);
+    }
+
+    match wait_for_home(motor) {
+        Ok(()) => Ok(Some(MIN_MOVE_MM)),
+        Err(Error::Motor(MotorError::Timeout)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Any register of the motor by its address
+#[cfg(not(feature = "generic_modbus"))]
+struct RawRegister(u16);
+
+#[cfg(not(feature = "generic_modbus"))]
+impl ReadableMotorRegister for RawRegister {
+    fn addr(&self) -> u16 {
+        self.0
+    }
+}
+
+/// Read a register of the primary motor for debugging
+#[cfg(not(feature = "generic_modbus"))]
+pub fn read_motor_register(address: u16) -> Result<u16, Error> {
+    with_standing_motor(|motor| motor.primary().read_register(&RawRegister(address)))
+}
+
+/// Write a register of every motor for debugging
+/// Only the registers in `ReadWriteMotorRegisters` can be written
+#[cfg(not(feature = "generic_modbus"))]
+pub fn write_motor_register(address: u16, value: u16) -> Result<(), Error> {
+    let register = all::<ReadWriteMotorRegisters>()
+        .find(|register| register.addr() == address)
+        .ok_or(MotorError::ReadOnlyRegister)?;
+
+    with_standing_motor(|motor| {
+        motor.try_for_each_motor(|motor| motor.write_register(&register, value))
+    })
+}
+
+/// Run `f` on the motor with motion control taken out of the control loop
+/// Only while the machine is standing still
+#[cfg(not(feature = "generic_modbus"))]
+fn with_standing_motor<R>(
+    f: impl FnOnce(&mut MachineMotor) -> Result<R, MotorError>,
+) -> Result<R, Error> {
+    if get_motion_state().motion_enabled || is_demo_active() || is_move_in_progress() {
+        return Err(MotionError::MotionEnabled.into());
+    }
+
+    let result = EspMotionControl::with_detached(|motion_control| f(motion_control.motor_mut()))
+        .ok_or(MotionError::Detached)?;
+    Ok(result?)
+}
+
+/// Task to reconnect the motor if it stops responding after boot
+/// Motion is paused in the meantime and resumed afterwards
+#[embassy_executor::task]
+pub async fn motor_reconnection_task() {
+    info!("Task Motor Reconnection Started");
+
+    let mut ticker = Ticker::every(Duration::from_millis(MOTOR_RECONNECT_INTERVAL_MS));
+    loop {
+        ticker.next().await;
+
+        if is_motor_connected() {
+            continue;
+        }
+
+        let errors = get_response_error_counts();
+        info!(
+            "Trying to reconnect the motor. Invalid responses since boot: {} CRC, {} position echo. Faults: {}",
+            errors.crc_mismatches, errors.position_echo_mismatches, get_fault_count()
+        );
+        EspMotionControl::with_detached(|motion_control| {
+            match reconnect_motor(motion_control.motor_mut()) {
+                Ok(Some(position)) => motion_control.resume_after_reconnect(position),
+                Ok(None) => {}
+                // Tried again once re-armed
+                Err(Error::Motion(MotionError::Faulted)) => {}
+                Err(err) => report_error(err),
+            }
+        });
+    }
+}
+
+/// Task to stop the machine with a fault if the control loop stops running during a move
+#[embassy_executor::task]
+pub async fn motion_watchdog_task() {
+    info!("Task Motion Watchdog Started");
+
+    let mut ticker = Ticker::every(Duration::from_millis(MOTION_WATCHDOG_INTERVAL_MS));
+    loop {
+        ticker.next().await;
+
+        // The loop is skipped on purpose while the motor is reconnected or calibrated
+        // Raises the fault and drops the torque itself if it tripped
+        EspMotionControl::with_attached(|motion_control| motion_control.check_loop_watchdog());
+    }
 }
 
 #[embassy_executor::task]
 pub async fn run_motion() {
     ossm_motion::motion::run_motion().await;
 }
+
+#[embassy_executor::task]
+pub async fn run_funscript() {
+    ossm_motion::motion::funscript::run_funscript().await;
+}
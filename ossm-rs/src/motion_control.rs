@@ -4,28 +4,32 @@ use critical_section::Mutex;
 use log::{debug, error, info};
 use esp_hal::{handler, interrupt::Priority, time::Duration, timer::PeriodicTimer, Blocking};
 
-use crate::{motion::timer::EspTimer, motor::m57aimxx::Motor57AIMxx};
-use ossm_motion::{config::MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS, motion_control::{MotionControl, debug::DummyDebugOut}};
+#[cfg(not(feature = "generic_modbus"))]
+use crate::motor::MotorGroup;
+use crate::{
+    motion::{debug::StreamDebugOut, timer::EspTimer},
+    motor::MachineMotor,
+};
+use ossm_motion::{config::MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS, motion_control::MotionControl};
 
 pub static UPDATE_TIMER: Mutex<RefCell<Option<PeriodicTimer<'static, Blocking>>>> =
     Mutex::new(RefCell::new(None));
-static MOTION_CONTROL: Mutex<RefCell<Option<MotionControl<Motor57AIMxx, EspTimer, DummyDebugOut>>>> =
+type EspMotionControlInner = MotionControl<MachineMotor, EspTimer, StreamDebugOut>;
+
+static MOTION_CONTROL: Mutex<RefCell<Option<EspMotionControlInner>>> =
     Mutex::new(RefCell::new(None));
 
 // Timer interrupt
 #[handler(priority = Priority::Priority2)]
 pub fn motion_control_interrupt() {
     critical_section::with(|cs| {
-        UPDATE_TIMER
-            .borrow_ref_mut(cs)
-            .as_mut()
-            .unwrap()
-            .clear_interrupt();
-        MOTION_CONTROL
-            .borrow_ref_mut(cs)
-            .as_mut()
-            .unwrap()
-            .update_handler();
+        if let Some(update_timer) = UPDATE_TIMER.borrow_ref_mut(cs).as_mut() {
+            update_timer.clear_interrupt();
+        }
+        // Taken out while the motor is being reconnected
+        if let Some(motion_control) = MOTION_CONTROL.borrow_ref_mut(cs).as_mut() {
+            motion_control.update_handler();
+        }
     });
 }
 
@@ -33,18 +37,23 @@ pub struct EspMotionControl {}
 
 impl EspMotionControl {
     /// Initialises the MotionControl and allows the use of attached functions
-    pub fn init(mut update_timer: PeriodicTimer<'static, Blocking>, mut motor: Motor57AIMxx) {
+    pub fn init(mut update_timer: PeriodicTimer<'static, Blocking>, mut motor: MachineMotor) {
         info!("ESP Motion Control Init");
 
         // Motion control over modbus
-        motor.enable_modbus(true).expect("Failed to enable modbus");
+        #[cfg(not(feature = "generic_modbus"))]
+        motor
+            .try_for_each_motor(|motor| motor.enable_modbus(true))
+            .expect("Failed to enable modbus");
+        #[cfg(feature = "generic_modbus")]
+        motor.enable().expect("Failed to enable the servo");
 
         update_timer.set_interrupt_handler(motion_control_interrupt);
         update_timer.listen();
 
         let esp_timer = EspTimer::new();
 
-        let motion_control = MotionControl::new(motor, esp_timer);
+        let motion_control = MotionControl::new_with_debug(motor, esp_timer, StreamDebugOut::new());
 
         update_timer
             .start(Duration::from_millis(
@@ -57,4 +66,25 @@ impl EspMotionControl {
             UPDATE_TIMER.borrow_ref_mut(cs).replace(update_timer);
         });
     }
+
+    /// Run a short function on the motion control between two updates of the control loop
+    /// None while it is not initialised or taken out of the control loop
+    pub fn with_attached<R>(f: impl FnOnce(&mut EspMotionControlInner) -> R) -> Option<R> {
+        critical_section::with(|cs| MOTION_CONTROL.borrow_ref_mut(cs).as_mut().map(f))
+    }
+
+    /// Run a blocking function with the motion control taken out of the control loop
+    /// The control loop is skipped until the function returns
+    pub fn with_detached<R>(f: impl FnOnce(&mut EspMotionControlInner) -> R) -> Option<R> {
+        let mut motion_control =
+            critical_section::with(|cs| MOTION_CONTROL.borrow_ref_mut(cs).take())?;
+
+        let result = f(&mut motion_control);
+
+        critical_section::with(|cs| {
+            MOTION_CONTROL.borrow_ref_mut(cs).replace(motion_control);
+        });
+
+        Some(result)
+    }
 }
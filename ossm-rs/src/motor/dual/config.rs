@@ -0,0 +1,6 @@
+use ossm_motion::float::Real;
+
+// Modbus slave address of the second motor. Has to be changed on the motor beforehand
+pub const SECONDARY_MOTOR_ADDRESS: u8 = 2;
+// Motion is paused and the motors are homed again if their positions differ by more than this in mm
+pub const MAX_MOTOR_SKEW_MM: Real = 2.0;
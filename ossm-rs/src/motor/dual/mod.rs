@@ -0,0 +1,134 @@
+pub mod config;
+
+use log::debug;
+use ossm_motion::{
+    float::Real,
+    motion_control::{mechanics::get_steps_per_mm, motor::Motor, timer::Duration},
+};
+
+use config::{MAX_MOTOR_SKEW_MM, SECONDARY_MOTOR_ADDRESS};
+
+use crate::motor::{
+    m57aimxx::{
+        config::{MOTOR_ADDRESS, MOTOR_SETTINGS},
+        Motor57AIMxx, MotorError,
+    },
+    MotorGroup,
+};
+
+/// Two 57AIMxx motors on the same RS485 bus driving one axis
+/// Both motors get the same commands, so they have to turn the same way for the same position
+pub struct DualMotor57AIMxx {
+    motor: Motor57AIMxx,
+}
+
+impl DualMotor57AIMxx {
+    pub fn new(mut motor: Motor57AIMxx) -> Self {
+        motor.set_address(MOTOR_ADDRESS);
+        Self { motor }
+    }
+
+    pub fn release(self) -> Motor57AIMxx {
+        self.motor
+    }
+
+    /// Run a command on the second motor
+    fn with_secondary<R>(&mut self, f: impl FnOnce(&mut Motor57AIMxx) -> R) -> R {
+        self.motor.set_address(SECONDARY_MOTOR_ADDRESS);
+        let result = f(&mut self.motor);
+        self.motor.set_address(MOTOR_ADDRESS);
+        result
+    }
+
+    /// Difference between the absolute positions of the two motors in steps
+    pub fn get_skew(&mut self) -> Result<u32, MotorError> {
+        let primary = self.motor.get_abolute_position()?;
+        let secondary = self.with_secondary(|motor| motor.get_abolute_position())?;
+
+        Ok(primary.abs_diff(secondary))
+    }
+}
+
+impl MotorGroup for DualMotor57AIMxx {
+    fn try_for_each_motor(
+        &mut self,
+        mut f: impl FnMut(&mut Motor57AIMxx) -> Result<(), MotorError>,
+    ) -> Result<(), MotorError> {
+        f(&mut self.motor)?;
+        self.with_secondary(f)
+    }
+
+    fn primary(&mut self) -> &mut Motor57AIMxx {
+        &mut self.motor
+    }
+
+    fn is_in_sync(&mut self) -> Result<bool, MotorError> {
+        let skew = self.get_skew()?;
+        debug!("Motor skew {} steps", skew);
+        Ok(skew <= max_skew_steps())
+    }
+}
+
+/// MAX_MOTOR_SKEW_MM with the mechanics in use
+fn max_skew_steps() -> u32 {
+    (MAX_MOTOR_SKEW_MM * get_steps_per_mm()) as u32
+}
+
+impl Motor for DualMotor57AIMxx {
+    type MotorError = MotorError;
+
+    fn min_consecutive_write_delay() -> Duration {
+        Motor57AIMxx::min_consecutive_write_delay()
+    }
+
+    fn set_absolute_position(&mut self, steps: i32) -> Result<(), Self::MotorError> {
+        self.try_for_each_motor(|motor| motor.set_absolute_position(steps))
+    }
+
+    fn set_torque_pct(&mut self, torque: Real) -> Result<(), Self::MotorError> {
+        self.try_for_each_motor(|motor| motor.set_torque_pct(torque))
+    }
+
+    fn load_limit_pct(torque: Real) -> Real {
+        Motor57AIMxx::load_limit_pct(torque)
+    }
+
+    fn get_load_pct(&mut self) -> Result<Option<Real>, Self::MotorError> {
+        // The motor working harder limits the machine
+        let primary = self.motor.get_load_pct()?;
+        let secondary = self.with_secondary(|motor| motor.get_load_pct())?;
+
+        Ok(match (primary, secondary) {
+            (Some(primary), Some(secondary)) => Some(primary.max(secondary)),
+            (load, None) | (None, load) => load,
+        })
+    }
+
+    fn get_temperature_c(&mut self) -> Result<Option<Real>, Self::MotorError> {
+        // The hotter motor is the one to protect
+        let primary = self.motor.get_temperature_c()?;
+        let secondary = self.with_secondary(|motor| motor.get_temperature_c())?;
+
+        Ok(match (primary, secondary) {
+            (Some(primary), Some(secondary)) => Some(primary.max(secondary)),
+            (temperature, None) | (None, temperature) => temperature,
+        })
+    }
+
+    fn check_sync(&mut self) -> Result<(), Self::MotorError> {
+        let skew = self.get_skew()?;
+        if skew > max_skew_steps() {
+            return Err(MotorError::OutOfSync { skew_steps: skew });
+        }
+
+        Ok(())
+    }
+
+    fn verify_settings(&mut self) -> Result<(), Self::MotorError> {
+        self.try_for_each_motor(|motor| motor.restore_settings(&MOTOR_SETTINGS).map(|_| ()))
+    }
+
+    fn delay(&mut self, duration: Duration) {
+        Motor::delay(&mut self.motor, duration);
+    }
+}
@@ -0,0 +1,35 @@
+use ossm_motion::diagnostics::MotorRatings;
+
+use crate::motor::generic_modbus::{ServoDefinition, ServoRegisterMap, WordOrder};
+
+// The servo driving the machine
+pub type MachineServo = ExampleServo;
+// Baud rate of the RS485 bus. Has to be set on the servo beforehand
+pub const SERVO_BAUD_RATE: u32 = 115200;
+// What the config is checked against at boot. The output is in % of `torque_max`
+pub const SERVO_RATINGS: MotorRatings = MotorRatings {
+    max_rpm: 3000.0,
+    min_output: 0.0,
+    max_output: 100.0,
+    output_limit: 100.0,
+};
+
+/// Template for adding a new servo. Copy it and fill in the values from the manual of your servo
+pub struct ExampleServo;
+
+impl ServoDefinition for ExampleServo {
+    const REGISTERS: ServoRegisterMap = ServoRegisterMap {
+        unit_id: 1,
+        enable_reg: None,
+        enable_value: 1,
+        position_reg: 0x0000,
+        position_word_order: WordOrder::LowFirst,
+        // The motion control steps are 32768 per revolution
+        position_numerator: 1,
+        position_denominator: 1,
+        torque_reg: None,
+        torque_max: 100,
+        min_write_interval_us: 2000,
+        timeout_ms: 10,
+    };
+}
@@ -0,0 +1,252 @@
+pub mod config;
+
+use embedded_io::Write;
+use esp_hal::{
+    time::Duration,
+    timer::{AnyTimer, Timer},
+    uart::{RxError, Uart},
+    Blocking,
+};
+use heapless::Vec;
+use log::{debug, error};
+use ossm_motion::{float::Real, utils::saturate_range};
+use rmodbus::{client::ModbusRequest, guess_response_frame_len, ModbusProto};
+
+const PROTO: ModbusProto = ModbusProto::Rtu;
+const MIN_REG_READ_REQUIRED: usize = 3;
+
+/// Order of the two 16-bit words of a 32-bit value
+// Only the one of the selected servo is used
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WordOrder {
+    LowFirst,
+    HighFirst,
+}
+
+/// Describes how to control a modbus RS485 servo
+#[derive(Debug, Clone, Copy)]
+pub struct ServoRegisterMap {
+    // Modbus unit (slave) id of the servo
+    pub unit_id: u8,
+    // Register written with `enable_value` to let the servo be controlled over modbus
+    pub enable_reg: Option<u16>,
+    pub enable_value: u16,
+    // First of the two registers holding the 32-bit absolute target position
+    pub position_reg: u16,
+    pub position_word_order: WordOrder,
+    // Motor steps are converted to servo units as steps * numerator / denominator
+    pub position_numerator: i32,
+    pub position_denominator: i32,
+    // Register limiting the output (torque). None if the servo has no such register
+    pub torque_reg: Option<u16>,
+    // Value of the torque register at full torque
+    pub torque_max: u16,
+    // Minimum time between two consecutive commands in us
+    pub min_write_interval_us: u64,
+    // Time to wait for a response in ms
+    pub timeout_ms: u64,
+}
+
+/// A servo supported by `GenericModbusServo`
+/// Usually a unit struct with a const register map
+pub trait ServoDefinition {
+    const REGISTERS: ServoRegisterMap;
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum GenericServoError {
+    Rs485Error(RxError),
+    Timeout,
+    // The servo responded with a modbus exception or an invalid frame
+    InvalidResponse,
+    // The request could not be built or sent over RS485
+    InvalidRequest,
+}
+
+/// A motor backend for modbus servos described by a register map
+pub struct GenericModbusServo<S: ServoDefinition> {
+    rs485: Uart<'static, Blocking>,
+    timer: AnyTimer<'static>,
+    _servo: core::marker::PhantomData<S>,
+}
+
+impl<S: ServoDefinition> GenericModbusServo<S> {
+    pub fn new(rs485: Uart<'static, Blocking>, timer: AnyTimer<'static>) -> Self {
+        Self {
+            rs485,
+            timer,
+            _servo: core::marker::PhantomData,
+        }
+    }
+
+    fn start_timer_delay(&mut self, delay: Duration) {
+        if self.timer.is_running() {
+            self.timer.stop();
+        }
+
+        self.timer.clear_interrupt();
+        self.timer.reset();
+
+        self.timer.enable_auto_reload(false);
+        if let Err(err) = self.timer.load_value(delay) {
+            error!("Invalid servo timer delay {:?}", err);
+        }
+        self.timer.start();
+    }
+
+    pub fn delay(&mut self, delay: Duration) {
+        // Nothing to wait for. Avoids arming the timer with a zero period
+        if delay.as_micros() == 0 {
+            return;
+        }
+
+        self.start_timer_delay(delay);
+
+        while !self.timer.is_interrupt_set() {}
+
+        self.timer.stop();
+        self.timer.clear_interrupt();
+    }
+
+    fn read_with_timeout(&mut self, mut buf: &mut [u8]) -> Result<(), GenericServoError> {
+        self.start_timer_delay(Duration::from_millis(S::REGISTERS.timeout_ms));
+
+        while !buf.is_empty() && !self.timer.is_interrupt_set() {
+            match self.rs485.read_buffered(buf) {
+                Ok(n) => buf = &mut buf[n..],
+                Err(e) => return Err(GenericServoError::Rs485Error(e)),
+            }
+        }
+
+        let timeout = self.timer.is_interrupt_set();
+
+        self.timer.stop();
+        self.timer.clear_interrupt();
+
+        if timeout {
+            return Err(GenericServoError::Timeout);
+        }
+
+        Ok(())
+    }
+
+    /// Send a request and wait for the response
+    fn transact(
+        &mut self,
+        modbus_req: &ModbusRequest,
+        request: &[u8],
+    ) -> Result<(), GenericServoError> {
+        self.rs485
+            .write_all(request)
+            .and_then(|_| self.rs485.flush())
+            .map_err(|err| {
+                error!("Failed to write the request to RS485 {:?}", err);
+                GenericServoError::InvalidRequest
+            })?;
+
+        let mut response = [0u8; 32];
+        self.read_with_timeout(&mut response[0..MIN_REG_READ_REQUIRED])?;
+
+        let len = guess_response_frame_len(&response[0..MIN_REG_READ_REQUIRED], PROTO)
+            .map_err(|_| GenericServoError::InvalidResponse)? as usize;
+        if len > response.len() {
+            return Err(GenericServoError::InvalidResponse);
+        }
+        if len > MIN_REG_READ_REQUIRED {
+            self.read_with_timeout(&mut response[MIN_REG_READ_REQUIRED..len])?;
+        }
+        let response = &response[0..len];
+
+        modbus_req.parse_ok(response).map_err(|err| {
+            error!("Modbus error {:?} in response {:x?}", err, response);
+            GenericServoError::InvalidResponse
+        })
+    }
+
+    /// Write one register
+    pub fn write_register(&mut self, reg: u16, val: u16) -> Result<(), GenericServoError> {
+        let mut modbus_req = ModbusRequest::new(S::REGISTERS.unit_id, PROTO);
+        let mut request: Vec<u8, 32> = Vec::new();
+
+        modbus_req
+            .generate_set_holding(reg, val, &mut request)
+            .map_err(|_| GenericServoError::InvalidRequest)?;
+
+        self.transact(&modbus_req, &request)
+    }
+
+    /// Write consecutive registers in one request
+    pub fn write_registers(&mut self, reg: u16, vals: &[u16]) -> Result<(), GenericServoError> {
+        let mut modbus_req = ModbusRequest::new(S::REGISTERS.unit_id, PROTO);
+        let mut request: Vec<u8, 32> = Vec::new();
+
+        modbus_req
+            .generate_set_holdings_bulk(reg, vals, &mut request)
+            .map_err(|_| GenericServoError::InvalidRequest)?;
+
+        self.transact(&modbus_req, &request)
+    }
+
+    /// Let the servo be controlled over modbus if the map has an enable register
+    pub fn enable(&mut self) -> Result<(), GenericServoError> {
+        if let Some(reg) = S::REGISTERS.enable_reg {
+            self.write_register(reg, S::REGISTERS.enable_value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Set the absolute position in motor steps
+    pub fn set_absolute_position(&mut self, steps: i32) -> Result<(), GenericServoError> {
+        let map = S::REGISTERS;
+        let position =
+            steps as i64 * map.position_numerator as i64 / map.position_denominator as i64;
+        let position = position.clamp(i32::MIN as i64, i32::MAX as i64) as u32;
+
+        let low = position as u16;
+        let high = (position >> 16) as u16;
+        let words = match map.position_word_order {
+            WordOrder::LowFirst => [low, high],
+            WordOrder::HighFirst => [high, low],
+        };
+
+        debug!("Set servo position to {}", position as i32);
+
+        self.write_registers(map.position_reg, &words)
+    }
+
+    /// Set the maximum output in % of `torque_max`
+    pub fn set_torque_pct(&mut self, torque: Real) -> Result<(), GenericServoError> {
+        let map = S::REGISTERS;
+        let Some(reg) = map.torque_reg else {
+            return Ok(());
+        };
+
+        let torque_max = map.torque_max as Real;
+        let value = saturate_range(torque / 100.0 * torque_max, 0.0, torque_max) as u16;
+
+        self.write_register(reg, value)
+    }
+}
+
+impl<S: ServoDefinition> ossm_motion::motion_control::motor::Motor for GenericModbusServo<S> {
+    type MotorError = GenericServoError;
+
+    fn min_consecutive_write_delay() -> ossm_motion::motion_control::timer::Duration {
+        ossm_motion::motion_control::timer::Duration::micros(S::REGISTERS.min_write_interval_us)
+    }
+
+    fn set_absolute_position(&mut self, steps: i32) -> Result<(), Self::MotorError> {
+        self.set_absolute_position(steps)
+    }
+
+    fn set_torque_pct(&mut self, torque: Real) -> Result<(), Self::MotorError> {
+        self.set_torque_pct(torque)
+    }
+
+    fn delay(&mut self, duration: ossm_motion::motion_control::timer::Duration) {
+        self.delay(Duration::from_micros(duration.to_micros()));
+    }
+}
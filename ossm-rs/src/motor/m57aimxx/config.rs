@@ -1,6 +1,33 @@
-use crate::motor::m57aimxx::MotorBaudRate;
+use ossm_motion::{diagnostics::MotorRatings, float::Real};
+
+use crate::motor::m57aimxx::{MotorBaudRate, MotorSettings, MAX_MOTOR_SPEED_RPM};
 
 // The baud rate that your motor comes with. Will be automatically changed at startup
 pub const STOCK_MOTOR_BAUD_RATE: MotorBaudRate = MotorBaudRate::Baud19200;
 // Motor baud rate to be used by the firmware
 pub const MOTOR_BAUD_RATE: MotorBaudRate = MotorBaudRate::Baud115200;
+// Modbus slave address of the motor. 1 unless changed on the motor
+pub const MOTOR_ADDRESS: u8 = 1;
+// Applied after homing and restored whenever the drive is found to have reset them
+pub const MOTOR_SETTINGS: MotorSettings = MotorSettings {
+    // High speed and acceleration since those are controlled by motion control
+    target_speed: MAX_MOTOR_SPEED_RPM,
+    acceleration: 50000,
+    // Defaults from OSSM
+    speed_proportional_coefficient: 3000,
+    position_proportional_coefficient: 3000,
+};
+// The max allowed output before motion control takes over the torque
+pub const MOTOR_MAX_ALLOWED_OUTPUT: u16 = 600;
+// Output at 0% torque from motion control. 0-60
+pub const MOTOR_MIN_OUTPUT: Real = 12.0;
+// Output at 100% torque from motion control. 0-60
+pub const MOTOR_MAX_OUTPUT: Real = 60.0;
+// What the config is checked against at boot
+pub const MOTOR_RATINGS: MotorRatings = MotorRatings {
+    max_rpm: MAX_MOTOR_SPEED_RPM as Real,
+    min_output: MOTOR_MIN_OUTPUT,
+    max_output: MOTOR_MAX_OUTPUT,
+    // In tenths like the output written for the torque
+    output_limit: MOTOR_MAX_ALLOWED_OUTPUT as Real / 10.0,
+};
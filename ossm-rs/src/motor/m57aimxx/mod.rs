@@ -1,17 +1,24 @@
 pub mod config;
 
+use core::sync::atomic::{AtomicU32, Ordering};
+
 use log::{debug, error};
 use embedded_io::Write;
 use enum_iterator::Sequence;
 use esp_hal::{
     time::Duration,
     timer::{AnyTimer, Timer},
-    uart::{RxError, Uart},
+    uart::{self, RxError, Uart},
     Blocking,
 };
 use heapless::Vec;
+use ossm_motion::{float::Real, utils::scale};
 use rmodbus::{client::ModbusRequest, guess_response_frame_len, ModbusProto};
 
+use config::{MOTOR_ADDRESS, MOTOR_MAX_OUTPUT, MOTOR_MIN_OUTPUT, MOTOR_SETTINGS};
+
+use crate::motor::ResponseErrorCounts;
+
 const PROTO: ModbusProto = ModbusProto::Rtu;
 const MIN_REG_READ_REQUIRED: usize = 3;
 
@@ -23,6 +30,21 @@ const MAX_REG_READ_AT_ONCE: usize = 8;
 
 pub const MAX_MOTOR_SPEED_RPM: u16 = 3000;
 
+// Full scale of the output PWM register
+// In ‰ like the max allowed output, which is the output in % times 10 with the alarm in the
+// last digit. Derived from the register map, not measured on hardware
+const MAX_OUTPUT_PWM: u16 = 1000;
+
+// Value of the modbus enable register that switches the motor into the speed mode
+// TODO: Verify on hardware
+const MODBUS_SPEED_MODE: u16 = 2;
+
+// Invalid responses to the 0x7b command since boot
+static CRC_MISMATCHES: AtomicU32 = AtomicU32::new(0);
+static POSITION_ECHO_MISMATCHES: AtomicU32 = AtomicU32::new(0);
+// Times the drive was found not to have retained its settings since boot
+static SETTINGS_RESTORES: AtomicU32 = AtomicU32::new(0);
+
 #[derive(Debug, Clone, Copy, PartialEq, Sequence)]
 #[repr(u16)]
 pub enum ReadWriteMotorRegisters {
@@ -100,6 +122,39 @@ impl MotorBaudRate {
 pub enum MotorError {
     Rs485Error(RxError),
     Timeout,
+    // The CRC of the response does not match its contents
+    CrcMismatch,
+    // The response does not echo the command and position that were sent
+    PositionEchoMismatch,
+    // Motors driving the same axis disagree on the position by this many steps
+    OutOfSync { skew_steps: u32 },
+    // The request could not be sent over RS485
+    Rs485Write,
+    // The RS485 bus could not be reconfigured
+    Rs485Config,
+    // The modbus request could not be built or the response was an exception or malformed
+    Modbus,
+    // The value is outside of what the register accepts. Nothing was written
+    OutOfRange,
+    // The register does not exist or can not be written
+    ReadOnlyRegister,
+}
+
+pub fn get_response_error_counts() -> ResponseErrorCounts {
+    ResponseErrorCounts {
+        crc_mismatches: CRC_MISMATCHES.load(Ordering::Relaxed),
+        position_echo_mismatches: POSITION_ECHO_MISMATCHES.load(Ordering::Relaxed),
+    }
+}
+
+/// The registers written at startup that stay the same during motion
+/// The max allowed output is left out since motion control uses it for the torque
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotorSettings {
+    pub target_speed: u16,
+    pub acceleration: u16,
+    pub speed_proportional_coefficient: u16,
+    pub position_proportional_coefficient: u16,
 }
 
 // Taken from the rmodbus crate
@@ -122,17 +177,39 @@ fn calc_crc16(frame: &[u8], data_length: u8) -> u16 {
 pub struct Motor57AIMxx {
     rs485: Uart<'static, Blocking>,
     timer: AnyTimer<'static>,
+    // Modbus slave address of the motor the commands are sent to
+    address: u8,
 }
 
 impl Motor57AIMxx {
     pub fn new(rs485: Uart<'static, Blocking>, timer: AnyTimer<'static>) -> Self {
-        Self { rs485, timer }
+        Self {
+            rs485,
+            timer,
+            address: MOTOR_ADDRESS,
+        }
+    }
+
+    /// Send the following commands to the motor with this address on the same bus
+    pub fn set_address(&mut self, address: u8) {
+        self.address = address;
     }
 
     pub fn release(self) -> (Uart<'static, Blocking>, AnyTimer<'static>) {
         (self.rs485, self.timer)
     }
 
+    /// Change the baud rate of the RS485 bus on the controller side
+    pub fn set_bus_baud_rate(&mut self, baud_rate: &MotorBaudRate) -> Result<(), MotorError> {
+        let config = uart::Config::default()
+            .with_rx(uart::RxConfig::default())
+            .with_baudrate(baud_rate.as_int());
+        self.rs485.apply_config(&config).map_err(|err| {
+            error!("Failed to change RS485 config {:?}", err);
+            MotorError::Rs485Config
+        })
+    }
+
     fn start_timer_delay(&mut self, delay: Duration) {
         if self.timer.is_running() {
             self.timer.stop();
@@ -142,11 +219,18 @@ impl Motor57AIMxx {
         self.timer.reset();
 
         self.timer.enable_auto_reload(false);
-        self.timer.load_value(delay).unwrap();
+        if let Err(err) = self.timer.load_value(delay) {
+            error!("Invalid motor timer delay {:?}", err);
+        }
         self.timer.start();
     }
 
     pub fn delay(&mut self, delay: Duration) {
+        // Nothing to wait for. Avoids arming the timer with a zero period
+        if delay.as_micros() == 0 {
+            return;
+        }
+
         self.start_timer_delay(delay);
 
         while !self.timer.is_interrupt_set() {}
@@ -185,35 +269,73 @@ impl Motor57AIMxx {
         self.read_with_given_timeout(buf, MOTOR_TIMEOUT_MS)
     }
 
+    fn send_request(&mut self, request: &[u8]) -> Result<(), MotorError> {
+        self.rs485
+            .write_all(request)
+            .and_then(|_| self.rs485.flush())
+            .map_err(|err| {
+                error!("Failed to write the request to RS485 {:?}", err);
+                MotorError::Rs485Write
+            })
+    }
+
     /// Write one motor register
     pub fn write_register(
         &mut self,
         reg: &ReadWriteMotorRegisters,
         val: u16,
     ) -> Result<(), MotorError> {
-        let mut modbus_req = ModbusRequest::new(1, PROTO);
+        let mut modbus_req = ModbusRequest::new(self.address, PROTO);
         let mut request: Vec<u8, 32> = Vec::new();
 
         modbus_req
             .generate_set_holding(reg.addr(), val, &mut request)
-            .expect("Failed to generate reg write request");
+            .map_err(|_| MotorError::Modbus)?;
 
-        self.rs485
-            .write_all(&request)
-            .expect("Failed to write the request bytes to RS485");
-        self.rs485.flush().expect("Failed to flush RS485");
+        self.send_write_request(&mut modbus_req, &request)
+    }
+
+    /// Write consecutive motor registers starting at `reg` with one request
+    pub fn write_registers(
+        &mut self,
+        reg: &ReadWriteMotorRegisters,
+        vals: &[u16],
+    ) -> Result<(), MotorError> {
+        let mut modbus_req = ModbusRequest::new(self.address, PROTO);
+        let mut request: Vec<u8, 32> = Vec::new();
+
+        modbus_req
+            .generate_set_holdings_bulk(reg.addr(), vals, &mut request)
+            .map_err(|_| MotorError::Modbus)?;
+
+        self.send_write_request(&mut modbus_req, &request)
+    }
+
+    // Send a write request and check the response confirming it
+    fn send_write_request(
+        &mut self,
+        modbus_req: &mut ModbusRequest,
+        request: &[u8],
+    ) -> Result<(), MotorError> {
+        self.send_request(request)?;
 
         let mut response = [0u8; 32];
         self.read_with_timeout(&mut response[0..MIN_REG_READ_REQUIRED])?;
 
         let len = guess_response_frame_len(&response[0..MIN_REG_READ_REQUIRED], PROTO)
-            .expect("Failed to guess frame len") as usize;
+            .map_err(|_| MotorError::Modbus)? as usize;
+        if len > response.len() {
+            return Err(MotorError::Modbus);
+        }
         if len > MIN_REG_READ_REQUIRED {
             self.read_with_timeout(&mut response[MIN_REG_READ_REQUIRED..len])?;
         }
         let response = &response[0..len];
 
-        modbus_req.parse_ok(response).expect("Modbus error");
+        modbus_req.parse_ok(response).map_err(|err| {
+            error!("Modbus error {:?}", err);
+            MotorError::Modbus
+        })?;
 
         // Make sure that multiple operations in a row can succeed
         self.delay(Duration::from_micros(MOTOR_CONSECUTIVE_READ_DELAY_US));
@@ -227,18 +349,15 @@ impl Motor57AIMxx {
         reg: &T,
         count: u16,
     ) -> Result<Vec<u16, MAX_REG_READ_AT_ONCE>, MotorError> {
-        let mut modbus_req = ModbusRequest::new(1, PROTO);
+        let mut modbus_req = ModbusRequest::new(self.address, PROTO);
         let mut request: Vec<u8, 32> = Vec::new();
 
         modbus_req
             .generate_get_holdings(reg.addr(), count, &mut request)
-            .expect("Failed to generate reg read request");
+            .map_err(|_| MotorError::Modbus)?;
 
         debug!("Req {:x?}", request);
-        self.rs485
-            .write_all(&request)
-            .expect("Failed to write the request bytes to RS485");
-        self.rs485.flush().expect("Failed to flush RS485");
+        self.send_request(&request)?;
 
         // let now = Instant::now();
 
@@ -246,7 +365,10 @@ impl Motor57AIMxx {
         self.read_with_timeout(&mut response[0..MIN_REG_READ_REQUIRED])?;
 
         let len = guess_response_frame_len(&response[0..MIN_REG_READ_REQUIRED], PROTO)
-            .expect("Failed to guess frame len") as usize;
+            .map_err(|_| MotorError::Modbus)? as usize;
+        if len > response.len() {
+            return Err(MotorError::Modbus);
+        }
         if len > MIN_REG_READ_REQUIRED {
             self.read_with_timeout(&mut response[MIN_REG_READ_REQUIRED..len])?;
         }
@@ -256,9 +378,15 @@ impl Motor57AIMxx {
         // info!("Motor responded in {} us", elapsed);
 
         let mut res: Vec<u16, MAX_REG_READ_AT_ONCE> = Vec::new();
-        modbus_req
-            .parse_u16(response, &mut res)
-            .expect("Failed to parse response reg");
+        modbus_req.parse_u16(response, &mut res).map_err(|err| {
+            error!("Failed to parse the response {:?}", err);
+            MotorError::Modbus
+        })?;
+        // The callers index into the registers
+        if res.len() != count as usize {
+            error!("Expected {} registers, got {}", count, res.len());
+            return Err(MotorError::Modbus);
+        }
 
         // Make sure that multiple operations in a row can succeed
         self.delay(Duration::from_micros(MOTOR_CONSECUTIVE_READ_DELAY_US));
@@ -276,7 +404,7 @@ impl Motor57AIMxx {
         let mut request = [0u8; 8];
         let bytes = position.to_be_bytes();
 
-        request[0] = 0x1;
+        request[0] = self.address;
         request[1] = 0x7b;
         request[2..6].copy_from_slice(&bytes);
         let crc = calc_crc16(&request[0..6], 6).to_le_bytes();
@@ -284,19 +412,25 @@ impl Motor57AIMxx {
 
         // info!("Request {:x}", request);
 
-        self.rs485
-            .write_all(&request)
-            .expect("Failed to write the request bytes to RS485");
-        self.rs485.flush().expect("Failed to flush RS485");
+        self.send_request(&request)?;
 
-        let mut response = [0u8; 32];
-        self.read_with_given_timeout(&mut response[0..8], MOTOR_SHORT_TIMEOUT_MS)?;
+        let mut response = [0u8; 8];
+        self.read_with_given_timeout(&mut response, MOTOR_SHORT_TIMEOUT_MS)?;
 
-        if response[0..2] != [0x1, 0x7b] {
+        // The motor echoes the whole request back
+        let crc = calc_crc16(&response[0..6], 6).to_le_bytes();
+        if response[6..8] != crc {
+            CRC_MISMATCHES.fetch_add(1, Ordering::Relaxed);
             error!(
-                "Incorrect response to a 0x7b command: {:x?}",
-                &response[0..8]
+                "Invalid CRC in a response to a 0x7b command: {:x?}",
+                response
             );
+            return Err(MotorError::CrcMismatch);
+        }
+        if response[0..6] != request[0..6] {
+            POSITION_ECHO_MISMATCHES.fetch_add(1, Ordering::Relaxed);
+            error!("Incorrect response to a 0x7b command: {:x?}", response);
+            return Err(MotorError::PositionEchoMismatch);
         }
 
         // Delay not necessary because we prioritise the update rate over missed positions
@@ -319,21 +453,6 @@ impl Motor57AIMxx {
         Ok(())
     }
 
-    /// Wait for the target position to be below the threshold
-    pub fn wait_for_target_reached(&mut self, threshold: i32) {
-        loop {
-            let target_position = self
-                .get_target_position()
-                .expect("Failed to get target position");
-
-            debug!("Target {}", target_position,);
-            if target_position.abs() < threshold {
-                break;
-            }
-            self.delay(Duration::from_micros(MOTOR_CONSECUTIVE_READ_DELAY_US * 2));
-        }
-    }
-
     // ---- RO regs ----
 
     /// Get the current in A
@@ -350,6 +469,17 @@ impl Motor57AIMxx {
         Ok(voltage)
     }
 
+    /// Get the temperature of the drive in °C
+    pub fn get_temperature(&mut self) -> Result<f32, MotorError> {
+        let reg = self.read_register(&ReadOnlyMotorRegisters::SystemTemperature)?;
+        Ok(reg as i16 as f32)
+    }
+
+    /// Get the raw output PWM. Rises with the load on the motor
+    pub fn get_output_pwm(&mut self) -> Result<u16, MotorError> {
+        self.read_register(&ReadOnlyMotorRegisters::SystemOutputPwm)
+    }
+
     /// Get how many steps need to be taken to reach the target
     pub fn get_target_position(&mut self) -> Result<i32, MotorError> {
         let regs = self.read_registers(&ReadOnlyMotorRegisters::TargetPositionLowU16, 2)?;
@@ -371,13 +501,28 @@ impl Motor57AIMxx {
 
     /// Set the target speed in RPM 0-3000
     pub fn set_target_speed(&mut self, speed: u16) -> Result<(), MotorError> {
-        if speed > 3000 {
-            panic!("The speed cannot be more than 3000")
+        if speed > MAX_MOTOR_SPEED_RPM {
+            error!("The speed cannot be more than {}", MAX_MOTOR_SPEED_RPM);
+            return Err(MotorError::OutOfRange);
         }
 
         self.write_register(&ReadWriteMotorRegisters::MotorTargetSpeed, speed)
     }
 
+    /// Set the target velocity in RPM for the speed mode -3000-3000
+    /// The sign sets the direction and is sent as two's complement
+    /// Unlike `set_target_speed` which only limits the speed of position moves
+    /// this makes the motor rotate continuously
+    pub fn set_target_velocity(&mut self, velocity: i16) -> Result<(), MotorError> {
+        if velocity.unsigned_abs() > MAX_MOTOR_SPEED_RPM {
+            error!("The velocity cannot be more than {}", MAX_MOTOR_SPEED_RPM);
+            return Err(MotorError::OutOfRange);
+        }
+
+        self.write_register(&ReadWriteMotorRegisters::ModbusEnable, MODBUS_SPEED_MODE)?;
+        self.write_register(&ReadWriteMotorRegisters::MotorTargetSpeed, velocity as u16)
+    }
+
     /// Get the target acceleration 0-59999. 60000 means disabled
     pub fn get_target_acceleration(&mut self) -> Result<u16, MotorError> {
         self.read_register(&ReadWriteMotorRegisters::MotorAcceleration)
@@ -425,6 +570,14 @@ impl Motor57AIMxx {
         Ok(absolute_position)
     }
 
+    /// Make the current position the zero position
+    pub fn reset_absolute_position(&mut self) -> Result<(), MotorError> {
+        // The low and the high word are consecutive registers (0x16 and 0x17 in
+        // `ReadWriteMotorRegisters`). Both are written at once so the drive never holds a
+        // position made of one old and one new word
+        self.write_registers(&ReadWriteMotorRegisters::AbsolutePositionLowU16, &[0, 0])
+    }
+
     /// Get the maximum allowed output in the standstill
     pub fn get_max_allowed_output(&mut self) -> Result<u16, MotorError> {
         self.read_register(&ReadWriteMotorRegisters::StandstillMaxOutput)
@@ -441,6 +594,46 @@ impl Motor57AIMxx {
     pub fn home(&mut self) -> Result<(), MotorError> {
         self.write_register(&ReadWriteMotorRegisters::SpecificFunction, 1)
     }
+
+    // ---- Settings ----
+
+    pub fn write_settings(&mut self, settings: &MotorSettings) -> Result<(), MotorError> {
+        self.set_target_speed(settings.target_speed)?;
+        self.set_target_acceleration(settings.acceleration)?;
+        self.set_speed_proportional_coefficient(settings.speed_proportional_coefficient)?;
+        self.set_position_proportional_coefficient(settings.position_proportional_coefficient)
+    }
+
+    /// Read all the settings at once
+    pub fn read_settings(&mut self) -> Result<MotorSettings, MotorError> {
+        // MotorTargetSpeed to PositionRingProportionalCoefficient
+        let regs = self.read_registers(&ReadWriteMotorRegisters::MotorTargetSpeed, 6)?;
+
+        Ok(MotorSettings {
+            target_speed: regs[0],
+            acceleration: regs[1],
+            speed_proportional_coefficient: regs[3],
+            position_proportional_coefficient: regs[5],
+        })
+    }
+
+    /// Write the settings again if the drive did not retain them
+    /// Returns whether they had to be restored
+    pub fn restore_settings(&mut self, settings: &MotorSettings) -> Result<bool, MotorError> {
+        let actual = self.read_settings()?;
+        if actual == *settings {
+            return Ok(false);
+        }
+
+        let restores = SETTINGS_RESTORES.fetch_add(1, Ordering::Relaxed) + 1;
+        error!(
+            "Motor {} did not retain its settings ({} times since boot). Restoring {:?} != {:?}",
+            self.address, restores, actual, settings
+        );
+        self.write_settings(settings)?;
+
+        Ok(true)
+    }
 }
 
 impl ossm_motion::motion_control::motor::Motor for Motor57AIMxx {
@@ -454,8 +647,29 @@ impl ossm_motion::motion_control::motor::Motor for Motor57AIMxx {
         self.set_absolute_position(steps)
     }
 
-    fn set_max_allowed_output(&mut self, torque: u16) -> Result<(), MotorError> {
-        self.set_max_allowed_output(torque)
+    fn set_torque_pct(&mut self, torque: Real) -> Result<(), MotorError> {
+        let output = scale(torque, 0.0, 100.0, MOTOR_MIN_OUTPUT, MOTOR_MAX_OUTPUT);
+        // The last digit is 0 for no alarm
+        self.set_max_allowed_output((output * 10.0) as u16)
+    }
+
+    fn load_limit_pct(torque: Real) -> Real {
+        // The max allowed output written by set_torque_pct on the scale of the output PWM
+        let output = scale(torque, 0.0, 100.0, MOTOR_MIN_OUTPUT, MOTOR_MAX_OUTPUT);
+        output * 10.0 / MAX_OUTPUT_PWM as Real * 100.0
+    }
+
+    fn get_load_pct(&mut self) -> Result<Option<Real>, Self::MotorError> {
+        let pwm = self.get_output_pwm()?;
+        Ok(Some(pwm as Real / MAX_OUTPUT_PWM as Real * 100.0))
+    }
+
+    fn get_temperature_c(&mut self) -> Result<Option<Real>, Self::MotorError> {
+        Ok(Some(self.get_temperature()? as Real))
+    }
+
+    fn verify_settings(&mut self) -> Result<(), Self::MotorError> {
+        self.restore_settings(&MOTOR_SETTINGS).map(|_| ())
     }
 
     fn delay(&mut self, duration: ossm_motion::motion_control::timer::Duration) {
@@ -1 +1,71 @@
+#[cfg(all(feature = "dual_motor", feature = "generic_modbus"))]
+compile_error!("dual_motor drives two 57AIMxx motors. It can not be combined with generic_modbus");
+
+#[cfg(feature = "dual_motor")]
+pub mod dual;
+#[cfg(feature = "generic_modbus")]
+pub mod generic_modbus;
+#[cfg(not(feature = "generic_modbus"))]
 pub mod m57aimxx;
+
+#[cfg(feature = "generic_modbus")]
+pub use generic_modbus::GenericServoError as MotorError;
+#[cfg(not(feature = "generic_modbus"))]
+use m57aimxx::Motor57AIMxx;
+#[cfg(not(feature = "generic_modbus"))]
+pub use m57aimxx::{get_response_error_counts, MotorError};
+
+/// The motor or motors driving the machine
+#[cfg(not(any(feature = "dual_motor", feature = "generic_modbus")))]
+pub type MachineMotor = Motor57AIMxx;
+#[cfg(feature = "dual_motor")]
+pub type MachineMotor = dual::DualMotor57AIMxx;
+#[cfg(feature = "generic_modbus")]
+pub type MachineMotor = generic_modbus::GenericModbusServo<generic_modbus::config::MachineServo>;
+
+/// Number of invalid responses to position commands since boot
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResponseErrorCounts {
+    pub crc_mismatches: u32,
+    pub position_echo_mismatches: u32,
+}
+
+/// Only the responses of the 57AIMxx are checked beyond modbus itself
+#[cfg(feature = "generic_modbus")]
+pub fn get_response_error_counts() -> ResponseErrorCounts {
+    ResponseErrorCounts::default()
+}
+
+/// One or more 57AIMxx motors driving the same axis
+#[cfg(not(feature = "generic_modbus"))]
+pub trait MotorGroup {
+    /// Run a command on every motor in turn. Stops at the first error
+    fn try_for_each_motor(
+        &mut self,
+        f: impl FnMut(&mut Motor57AIMxx) -> Result<(), MotorError>,
+    ) -> Result<(), MotorError>;
+
+    /// The motor the position is read from
+    fn primary(&mut self) -> &mut Motor57AIMxx;
+
+    /// Whether all the motors agree on the position
+    fn is_in_sync(&mut self) -> Result<bool, MotorError>;
+}
+
+#[cfg(not(feature = "generic_modbus"))]
+impl MotorGroup for Motor57AIMxx {
+    fn try_for_each_motor(
+        &mut self,
+        mut f: impl FnMut(&mut Motor57AIMxx) -> Result<(), MotorError>,
+    ) -> Result<(), MotorError> {
+        f(self)
+    }
+
+    fn primary(&mut self) -> &mut Motor57AIMxx {
+        self
+    }
+
+    fn is_in_sync(&mut self) -> Result<bool, MotorError> {
+        Ok(true)
+    }
+}
@@ -0,0 +1,401 @@
+use core::{
+    cell::RefCell,
+    fmt::Write as _,
+    net::Ipv4Addr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use embassy_futures::select::{select, Either};
+use embassy_net::{
+    tcp::{TcpReader, TcpSocket, TcpWriter},
+    Stack,
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Timer};
+use embedded_io_async::Write;
+use esp_hal::rng::Rng;
+use heapless::String;
+use log::{error, info, warn};
+use ossm_motion::motion::tcode::process_tcode_line;
+
+use crate::{
+    config::{
+        BUTTPLUG_BUFFER_SIZE, BUTTPLUG_IDENTIFIER, BUTTPLUG_KEEPALIVE_S,
+        BUTTPLUG_RECONNECT_DELAY_MS, MAX_WIFI_COMMAND_LENGTH,
+    },
+    error::{report_error, NetworkError},
+    storage::{load_intiface_server, save_intiface_server},
+};
+
+// The default port of the device websocket server of Intiface Central
+const INTIFACE_DEFAULT_PORT: u16 = 54817;
+// Fits the upgrade request and the handshake message
+const MAX_HANDSHAKE_LENGTH: usize = 192;
+// Any 16 bytes in base64. The server only echoes a hash of it
+const WEBSOCKET_KEY: &str = "b3NzbS1ycy1idXR0cGx1Zw==";
+
+// Opcodes in the lower nibble of the first byte of a frame
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+// The last frame of a message
+const FIN: u8 = 0x80;
+// Every frame from the client is masked
+const MASKED: u8 = 0x80;
+
+// The server to connect to. Loaded from the settings when the Buttplug task starts
+static SERVER: critical_section::Mutex<RefCell<Option<IntifaceServer>>> =
+    critical_section::Mutex::new(RefCell::new(None));
+// Signalled when the server is configured or removed
+static SERVER_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+// The connection as a `ButtplugStatus`
+static STATUS: AtomicU8 = AtomicU8::new(ButtplugStatus::Off as u8);
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum ButtplugError {
+    // The server did not switch to websockets with the HTTP status code
+    Rejected(u16),
+    // The server sent something that is not a websocket frame
+    Malformed,
+    // A message did not fit into `BUTTPLUG_BUFFER_SIZE`
+    TooLong,
+    // The server closed the connection
+    Closed,
+}
+
+#[derive(Clone, Debug)]
+pub struct IntifaceServer {
+    pub address: Ipv4Addr,
+    pub port: u16,
+}
+
+impl IntifaceServer {
+    /// None if the port is 0
+    pub fn new(address: Ipv4Addr, port: u16) -> Option<Self> {
+        (port != 0).then_some(Self { address, port })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum ButtplugStatus {
+    // No server was configured
+    Off = 0,
+    Connecting = 1,
+    Connected = 2,
+}
+
+impl ButtplugStatus {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => ButtplugStatus::Connecting,
+            2 => ButtplugStatus::Connected,
+            _ => ButtplugStatus::Off,
+        }
+    }
+
+    /// Name reported in the WiFi status JSON
+    pub fn name(self) -> &'static str {
+        match self {
+            ButtplugStatus::Off => "off",
+            ButtplugStatus::Connecting => "connecting",
+            ButtplugStatus::Connected => "connected",
+        }
+    }
+}
+
+pub fn get_buttplug_status() -> ButtplugStatus {
+    ButtplugStatus::from_u8(STATUS.load(Ordering::Acquire))
+}
+
+fn set_buttplug_status(status: ButtplugStatus) {
+    STATUS.store(status as u8, Ordering::Release);
+}
+
+fn get_intiface_server() -> Option<IntifaceServer> {
+    critical_section::with(|cs| SERVER.borrow_ref(cs).clone())
+}
+
+fn set_intiface_server(server: Option<IntifaceServer>) {
+    if let Err(err) = save_intiface_server(server.as_ref()) {
+        // Still used until the next boot
+        report_error(err);
+    }
+    critical_section::with(|cs| *SERVER.borrow_ref_mut(cs) = server);
+    SERVER_CHANGED.signal(());
+}
+
+/// `<ip>[:<port>]` sets the Intiface server and `off` removes it
+/// The port defaults to 54817. Written to the WiFi characteristic after `intiface:`
+/// Answers `ok:intiface:<ip>:<port>` or `fail:intiface`
+pub fn process_intiface_command(command: &str) -> String<MAX_WIFI_COMMAND_LENGTH> {
+    let mut output = String::new();
+    if command == "off" {
+        info!("Removing the Intiface server");
+        set_intiface_server(None);
+        write!(output, "ok:intiface:off").expect("Always fits");
+        return output;
+    }
+
+    let (address, port) = match command.split_once(':') {
+        None => (command.parse().ok(), Some(INTIFACE_DEFAULT_PORT)),
+        Some((address, port)) => (address.parse().ok(), port.parse().ok()),
+    };
+    let server = match (address, port) {
+        (Some(address), Some(port)) => IntifaceServer::new(address, port),
+        _ => None,
+    };
+    let result = match server {
+        Some(server) => {
+            info!(
+                "Using the Intiface server {}:{}",
+                server.address, server.port
+            );
+            let result = write!(output, "ok:intiface:{}:{}", server.address, server.port);
+            set_intiface_server(Some(server));
+            result
+        }
+        None => {
+            error!("Invalid Intiface server {}", command);
+            write!(output, "fail:intiface")
+        }
+    };
+    result.expect("Always fits");
+
+    output
+}
+
+/// Task to stay connected to the configured Intiface server
+/// The machine introduces itself as `BUTTPLUG_IDENTIFIER`, which Intiface maps to T-Code v0.3
+/// Speaks just enough of websockets (RFC 6455) for it: unfragmented messages, pings and close
+#[embassy_executor::task]
+pub async fn buttplug_task(stack: Stack<'static>) {
+    info!("Task Buttplug Started");
+
+    let server = load_intiface_server();
+    critical_section::with(|cs| *SERVER.borrow_ref_mut(cs) = server);
+
+    let mut rx_buffer = [0u8; BUTTPLUG_BUFFER_SIZE];
+    let mut tx_buffer = [0u8; BUTTPLUG_BUFFER_SIZE];
+    loop {
+        // Before reading the server so that no change is missed
+        SERVER_CHANGED.reset();
+        let Some(server) = get_intiface_server() else {
+            set_buttplug_status(ButtplugStatus::Off);
+            SERVER_CHANGED.wait().await;
+            continue;
+        };
+
+        set_buttplug_status(ButtplugStatus::Connecting);
+        // The server can not be reached before DHCP assigned an address
+        if let Either::Second(()) = select(stack.wait_config_up(), SERVER_CHANGED.wait()).await {
+            continue;
+        }
+
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        // Intiface only talks when there is something to do. The keep-alive notices a server
+        // that went away in the meantime
+        socket.set_keep_alive(Some(Duration::from_secs(BUTTPLUG_KEEPALIVE_S)));
+        socket.set_timeout(Some(Duration::from_secs(BUTTPLUG_KEEPALIVE_S * 3)));
+        match select(
+            run_session(stack, &mut socket, &server),
+            SERVER_CHANGED.wait(),
+        )
+        .await
+        {
+            Either::First(Err(err)) => warn!(
+                "Intiface connection to {}:{} lost {:?}",
+                server.address, server.port, err
+            ),
+            Either::First(Ok(())) => {}
+            Either::Second(()) => {
+                info!("The Intiface server changed");
+                // Connect to the new server right away
+                SERVER_CHANGED.signal(());
+            }
+        }
+        socket.abort();
+        if let Err(err) = socket.flush().await {
+            warn!("Could not close the Intiface connection {:?}", err);
+        }
+        set_buttplug_status(ButtplugStatus::Connecting);
+
+        let delay = Timer::after(Duration::from_millis(BUTTPLUG_RECONNECT_DELAY_MS));
+        select(delay, SERVER_CHANGED.wait()).await;
+    }
+}
+
+/// Switch to websockets, introduce the machine and run the T-Code until the connection fails
+async fn run_session(
+    stack: Stack<'static>,
+    socket: &mut TcpSocket<'_>,
+    server: &IntifaceServer,
+) -> Result<(), NetworkError> {
+    socket.connect((server.address, server.port)).await?;
+    let (mut reader, mut writer) = socket.split();
+
+    let mut rx = [0u8; BUTTPLUG_BUFFER_SIZE];
+    upgrade(&mut reader, &mut writer, server, &mut rx).await?;
+
+    // Tells the devices apart. The MAC address stays the same across reconnects
+    let mut address: String<12> = String::new();
+    for byte in stack.hardware_address().as_bytes() {
+        write!(address, "{:02x}", byte).map_err(|_| ButtplugError::TooLong)?;
+    }
+    let mut handshake: String<MAX_HANDSHAKE_LENGTH> = String::new();
+    write!(
+        handshake,
+        r#"{{"identifier":"{}","address":"{}","version":0}}"#,
+        BUTTPLUG_IDENTIFIER, address
+    )
+    .map_err(|_| ButtplugError::TooLong)?;
+    write_frame(&mut writer, TEXT, handshake.as_bytes()).await?;
+    info!(
+        "Connected to the Intiface server {}:{}",
+        server.address, server.port
+    );
+    set_buttplug_status(ButtplugStatus::Connected);
+
+    loop {
+        let (opcode, payload) = read_frame(&mut reader, &mut rx).await?;
+        match opcode {
+            TEXT | BINARY => match core::str::from_utf8(payload) {
+                Ok(line) => {
+                    let response = process_tcode_line(line);
+                    if !response.is_empty() {
+                        write_frame(&mut writer, BINARY, response.as_bytes()).await?;
+                    }
+                }
+                Err(_) => warn!("Dropped an Intiface message. Not UTF-8"),
+            },
+            PING => write_frame(&mut writer, PONG, payload).await?,
+            CLOSE => {
+                info!("The Intiface server closed the connection");
+                write_frame(&mut writer, CLOSE, &[]).await?;
+                return Ok(());
+            }
+            // Unsolicited pongs
+            _ => {}
+        }
+    }
+}
+
+/// Send the HTTP upgrade request and wait for the server to switch protocols
+async fn upgrade(
+    reader: &mut TcpReader<'_>,
+    writer: &mut TcpWriter<'_>,
+    server: &IntifaceServer,
+    rx: &mut [u8],
+) -> Result<(), NetworkError> {
+    let mut request: String<MAX_HANDSHAKE_LENGTH> = String::new();
+    write!(
+        request,
+        "GET / HTTP/1.1\r\nHost: {}:{}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        server.address, server.port, WEBSOCKET_KEY
+    )
+    .map_err(|_| ButtplugError::TooLong)?;
+    writer.write_all(request.as_bytes()).await?;
+
+    // Byte by byte so that nothing after the headers is consumed
+    let mut length = 0;
+    while !rx[..length].ends_with(b"\r\n\r\n") {
+        if length == rx.len() {
+            return Err(ButtplugError::TooLong.into());
+        }
+        read_exact(reader, &mut rx[length..length + 1]).await?;
+        length += 1;
+    }
+
+    let status = rx[..length]
+        .split(|byte| *byte == b' ')
+        .nth(1)
+        .and_then(|code| core::str::from_utf8(code).ok())
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or(ButtplugError::Malformed)?;
+    if status != 101 {
+        return Err(ButtplugError::Rejected(status).into());
+    }
+
+    Ok(())
+}
+
+/// Read the next message into `rx`. Fragmented messages are not supported
+async fn read_frame<'a>(
+    reader: &mut TcpReader<'_>,
+    rx: &'a mut [u8],
+) -> Result<(u8, &'a [u8]), NetworkError> {
+    let mut header = [0u8; 2];
+    read_exact(reader, &mut header).await?;
+    let opcode = header[0] & 0x0F;
+    if header[0] & FIN == 0 || opcode == CONTINUATION {
+        return Err(ButtplugError::Malformed.into());
+    }
+
+    let length = match header[1] & 0x7F {
+        126 => {
+            let mut length = [0u8; 2];
+            read_exact(reader, &mut length).await?;
+            u16::from_be_bytes(length) as usize
+        }
+        127 => {
+            let mut length = [0u8; 8];
+            read_exact(reader, &mut length).await?;
+            usize::try_from(u64::from_be_bytes(length)).map_err(|_| ButtplugError::TooLong)?
+        }
+        length => length as usize,
+    };
+    // Servers do not mask but the standard allows it
+    let mut mask = [0u8; 4];
+    if header[1] & MASKED != 0 {
+        read_exact(reader, &mut mask).await?;
+    }
+
+    let payload = rx.get_mut(..length).ok_or(ButtplugError::TooLong)?;
+    read_exact(reader, payload).await?;
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[index % 4];
+    }
+
+    Ok((opcode, payload))
+}
+
+/// Send a single frame message masked with a random key
+async fn write_frame(
+    writer: &mut TcpWriter<'_>,
+    opcode: u8,
+    payload: &[u8],
+) -> Result<(), NetworkError> {
+    // Control frames are at most 125 bytes and the T-Code answers are shorter too
+    let length = u8::try_from(payload.len())
+        .ok()
+        .filter(|length| *length < 126)
+        .ok_or(ButtplugError::TooLong)?;
+    let mask = Rng::new().random().to_le_bytes();
+
+    let mut frame = [0u8; 6 + 125];
+    frame[0] = FIN | opcode;
+    frame[1] = MASKED | length;
+    frame[2..6].copy_from_slice(&mask);
+    for (index, byte) in payload.iter().enumerate() {
+        frame[6 + index] = byte ^ mask[index % 4];
+    }
+
+    writer.write_all(&frame[..6 + payload.len()]).await?;
+    Ok(())
+}
+
+async fn read_exact(reader: &mut TcpReader<'_>, buffer: &mut [u8]) -> Result<(), NetworkError> {
+    let mut length = 0;
+    while length < buffer.len() {
+        match reader.read(&mut buffer[length..]).await? {
+            0 => return Err(ButtplugError::Closed.into()),
+            read => length += read,
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,398 @@
+use core::{fmt::Write, net::Ipv4Addr};
+
+use embassy_futures::select::select;
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpAddress, IpEndpoint, Stack,
+};
+use embassy_time::{Duration, Timer};
+use heapless::{String, Vec};
+use log::{info, warn};
+use ossm_motion::motion::motion_state::get_motion_state;
+
+use crate::config::{HOSTNAME, HTTP_PORT, MDNS_BUFFER_SIZE, MDNS_TTL_S, TCODE_PORT};
+
+const MDNS_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+// Fits the longest name the machine answers for with the dots
+const MAX_NAME_LENGTH: usize = 64;
+// Fits the firmware version in the TXT record
+const MAX_TXT_ENTRY_LENGTH: usize = 64;
+// Pointers to earlier names followed while reading one. More means a loop
+const MAX_NAME_POINTERS: usize = 8;
+// Announcements sent after an address was assigned. The standard asks for at least two
+const ANNOUNCEMENTS: usize = 2;
+const ANNOUNCEMENT_INTERVAL_MS: u64 = 1000;
+
+// Record types
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+// The internet class. With the top bit the record replaces any cached one of the same name
+const CLASS_IN: u16 = 0x0001;
+const CACHE_FLUSH: u16 = 0x8000;
+// Header flags
+const RESPONSE: u16 = 0x8000;
+const AUTHORITATIVE: u16 = 0x0400;
+
+#[derive(Debug)]
+enum MdnsError {
+    // The response did not fit into `MDNS_BUFFER_SIZE`
+    TooLong,
+    // The query is not DNS
+    Malformed,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Service {
+    Ossm,
+    Http,
+}
+
+impl Service {
+    fn label(self) -> &'static str {
+        match self {
+            Service::Ossm => "_ossm",
+            Service::Http => "_http",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Record {
+    // Lists the service for service type enumeration
+    Services(Service),
+    // Points the service type to the instance of the machine
+    Ptr(Service),
+    // The host and port of the instance
+    Srv(Service),
+    Txt(Service),
+    // The address of the host
+    A,
+}
+
+impl Record {
+    const ALL: [Record; 9] = [
+        Record::Services(Service::Ossm),
+        Record::Services(Service::Http),
+        Record::Ptr(Service::Ossm),
+        Record::Ptr(Service::Http),
+        Record::Srv(Service::Ossm),
+        Record::Srv(Service::Http),
+        Record::Txt(Service::Ossm),
+        Record::Txt(Service::Http),
+        Record::A,
+    ];
+
+    fn name(self) -> Vec<&'static str, 4> {
+        let name = match self {
+            Record::Services(_) => Vec::from_slice(&["_services", "_dns-sd", "_udp", "local"]),
+            Record::Ptr(service) => Vec::from_slice(&[service.label(), "_tcp", "local"]),
+            Record::Srv(service) | Record::Txt(service) => {
+                Vec::from_slice(&[HOSTNAME, service.label(), "_tcp", "local"])
+            }
+            Record::A => Vec::from_slice(&[HOSTNAME, "local"]),
+        };
+        name.expect("Always fits")
+    }
+
+    fn record_type(self) -> u16 {
+        match self {
+            Record::Services(_) | Record::Ptr(_) => TYPE_PTR,
+            Record::Srv(_) => TYPE_SRV,
+            Record::Txt(_) => TYPE_TXT,
+            Record::A => TYPE_A,
+        }
+    }
+
+    /// Whether the query asks for this record
+    fn answers(self, name: &str, record_type: u16) -> bool {
+        let matches_type = record_type == TYPE_ANY || record_type == self.record_type();
+        matches_type && dotted(&self.name()).eq_ignore_ascii_case(name)
+    }
+
+    fn write(self, packet: &mut Response, address: Ipv4Addr) -> Result<(), MdnsError> {
+        packet.write_name(&self.name())?;
+        packet.write_u16(self.record_type())?;
+        // Only the machine has these names. The pointers are shared with other devices
+        let class = match self {
+            Record::Services(_) | Record::Ptr(_) => CLASS_IN,
+            _ => CLASS_IN | CACHE_FLUSH,
+        };
+        packet.write_u16(class)?;
+        packet.write_u32(MDNS_TTL_S)?;
+
+        // The length of the data is filled in after it was written
+        let length_at = packet.buffer.len();
+        packet.write_u16(0)?;
+        match self {
+            Record::Services(service) => {
+                packet.write_name(&Record::Ptr(service).name())?;
+            }
+            Record::Ptr(service) => packet.write_name(&Record::Srv(service).name())?,
+            Record::Srv(_) => {
+                // Priority and weight
+                packet.write_u16(0)?;
+                packet.write_u16(0)?;
+                packet.write_u16(HTTP_PORT)?;
+                packet.write_name(&Record::A.name())?;
+            }
+            Record::Txt(Service::Ossm) => {
+                let mut entry: String<MAX_TXT_ENTRY_LENGTH> = String::new();
+                for (key, value) in [("http", HTTP_PORT), ("tcode", TCODE_PORT)] {
+                    entry.clear();
+                    write!(entry, "{}={}", key, value).map_err(|_| MdnsError::TooLong)?;
+                    packet.write_text(&entry)?;
+                }
+                entry.clear();
+                write!(entry, "version={}", get_motion_state().firmware_version)
+                    .map_err(|_| MdnsError::TooLong)?;
+                packet.write_text(&entry)?;
+            }
+            Record::Txt(Service::Http) => packet.write_text("path=/")?,
+            Record::A => packet.write(&address.octets())?,
+        }
+        let length = (packet.buffer.len() - length_at - 2) as u16;
+        packet.buffer[length_at..length_at + 2].copy_from_slice(&length.to_be_bytes());
+
+        Ok(())
+    }
+}
+
+struct Response {
+    buffer: Vec<u8, MDNS_BUFFER_SIZE>,
+}
+
+impl Response {
+    /// A response with `answers` records followed by `additional` ones
+    fn new(answers: usize, additional: usize) -> Result<Self, MdnsError> {
+        let mut response = Self { buffer: Vec::new() };
+        // The ID is 0 for multicast responses
+        response.write_u16(0)?;
+        response.write_u16(RESPONSE | AUTHORITATIVE)?;
+        // Questions, answers, authority and additional records
+        response.write_u16(0)?;
+        response.write_u16(answers as u16)?;
+        response.write_u16(0)?;
+        response.write_u16(additional as u16)?;
+        Ok(response)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), MdnsError> {
+        self.buffer
+            .extend_from_slice(bytes)
+            .map_err(|_| MdnsError::TooLong)
+    }
+
+    fn write_u16(&mut self, value: u16) -> Result<(), MdnsError> {
+        self.write(&value.to_be_bytes())
+    }
+
+    fn write_u32(&mut self, value: u32) -> Result<(), MdnsError> {
+        self.write(&value.to_be_bytes())
+    }
+
+    /// A length prefixed string. Labels of names and entries of TXT records are written alike
+    fn write_text(&mut self, text: &str) -> Result<(), MdnsError> {
+        let length = u8::try_from(text.len()).map_err(|_| MdnsError::TooLong)?;
+        self.write(&[length])?;
+        self.write(text.as_bytes())
+    }
+
+    /// Without compression. The responses are small enough
+    fn write_name(&mut self, labels: &[&str]) -> Result<(), MdnsError> {
+        for label in labels {
+            self.write_text(label)?;
+        }
+        self.write(&[0])
+    }
+}
+
+/// The labels joined with dots as they are compared
+fn dotted(labels: &[&str]) -> String<MAX_NAME_LENGTH> {
+    let mut name = String::new();
+    for (index, label) in labels.iter().enumerate() {
+        if index > 0 {
+            name.push('.').expect("Always fits");
+        }
+        name.push_str(label).expect("Always fits");
+    }
+    name
+}
+
+/// Task to answer the mDNS queries while the machine has an address
+/// Answers are always sent to the multicast group, legacy unicast queries are not supported
+#[embassy_executor::task]
+pub async fn mdns_task(stack: Stack<'static>) {
+    info!("Task mDNS Started");
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; MDNS_BUFFER_SIZE];
+    let mut tx_buffer = [0u8; MDNS_BUFFER_SIZE];
+    loop {
+        stack.wait_config_up().await;
+        let Some(config) = stack.config_v4() else {
+            continue;
+        };
+        let address = config.address.address();
+
+        if let Err(err) = stack.join_multicast_group(MDNS_ADDRESS) {
+            warn!("Could not join the mDNS group {:?}", err);
+        }
+        let mut socket = UdpSocket::new(
+            stack,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        );
+        match socket.bind(MDNS_PORT) {
+            Ok(()) => {
+                info!("Advertising {}.local", HOSTNAME);
+                select(respond(&socket, address), stack.wait_config_down()).await;
+            }
+            Err(err) => {
+                warn!("Could not bind the mDNS socket {:?}", err);
+                stack.wait_config_down().await;
+            }
+        }
+        socket.close();
+        if let Err(err) = stack.leave_multicast_group(MDNS_ADDRESS) {
+            warn!("Could not leave the mDNS group {:?}", err);
+        }
+    }
+}
+
+/// Announce the records and answer the queries for them
+async fn respond(socket: &UdpSocket<'_>, address: Ipv4Addr) {
+    let group = IpEndpoint::new(IpAddress::Ipv4(MDNS_ADDRESS), MDNS_PORT);
+
+    for _ in 0..ANNOUNCEMENTS {
+        match announcement(address) {
+            Ok(response) => {
+                if let Err(err) = socket.send_to(&response.buffer, group).await {
+                    warn!("Could not send the mDNS announcement {:?}", err);
+                }
+            }
+            Err(err) => warn!("Could not write the mDNS announcement {:?}", err),
+        }
+        Timer::after(Duration::from_millis(ANNOUNCEMENT_INTERVAL_MS)).await;
+    }
+
+    let mut query = [0u8; MDNS_BUFFER_SIZE];
+    loop {
+        let length = match socket.recv_from(&mut query).await {
+            Ok((length, _)) => length,
+            Err(err) => {
+                warn!("Could not receive the mDNS query {:?}", err);
+                continue;
+            }
+        };
+
+        match answer(&query[..length], address) {
+            Ok(Some(response)) => {
+                if let Err(err) = socket.send_to(&response.buffer, group).await {
+                    warn!("Could not send the mDNS answer {:?}", err);
+                }
+            }
+            Ok(None) => {}
+            Err(err) => warn!("Could not answer the mDNS query {:?}", err),
+        }
+    }
+}
+
+/// Every record as an answer
+fn announcement(address: Ipv4Addr) -> Result<Response, MdnsError> {
+    let mut response = Response::new(Record::ALL.len(), 0)?;
+    for record in Record::ALL {
+        record.write(&mut response, address)?;
+    }
+    Ok(response)
+}
+
+/// The records asked for as answers and the rest as additional records
+/// None if the query is not about the machine
+fn answer(query: &[u8], address: Ipv4Addr) -> Result<Option<Response>, MdnsError> {
+    let header = query.get(..12).ok_or(MdnsError::Malformed)?;
+    let flags = u16::from_be_bytes([header[2], header[3]]);
+    if flags & RESPONSE != 0 {
+        // Announcements and answers of other devices
+        return Ok(None);
+    }
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+
+    let mut answers: Vec<Record, { Record::ALL.len() }> = Vec::new();
+    let mut offset = 12;
+    for _ in 0..questions {
+        let (end, name) = read_name(query, offset)?;
+        offset = end;
+        let question = query.get(offset..offset + 4).ok_or(MdnsError::Malformed)?;
+        let record_type = u16::from_be_bytes([question[0], question[1]]);
+        offset += 4;
+
+        let Some(name) = name else {
+            continue;
+        };
+        for record in Record::ALL {
+            if record.answers(&name, record_type) && !answers.contains(&record) {
+                answers.push(record).expect("Always fits");
+            }
+        }
+    }
+    if answers.is_empty() {
+        return Ok(None);
+    }
+
+    let additional = Record::ALL
+        .into_iter()
+        .filter(|record| !answers.contains(record));
+    let mut response = Response::new(answers.len(), additional.clone().count())?;
+    for record in answers.iter().copied().chain(additional) {
+        record.write(&mut response, address)?;
+    }
+    Ok(Some(response))
+}
+
+/// Read the name at `offset` with dots between the labels and the offset after it
+/// The name is None if it is longer than any of the machine's
+fn read_name(
+    packet: &[u8],
+    mut offset: usize,
+) -> Result<(usize, Option<String<MAX_NAME_LENGTH>>), MdnsError> {
+    let mut name: Option<String<MAX_NAME_LENGTH>> = Some(String::new());
+    // Where the name ends in the packet. After the first pointer if there is one
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let length = *packet.get(offset).ok_or(MdnsError::Malformed)? as usize;
+        match length {
+            0 => return Ok((end.unwrap_or(offset + 1), name)),
+            // A pointer to the rest of the name earlier in the packet
+            length if length & 0xC0 == 0xC0 => {
+                let low = *packet.get(offset + 1).ok_or(MdnsError::Malformed)? as usize;
+                end.get_or_insert(offset + 2);
+                pointers += 1;
+                if pointers > MAX_NAME_POINTERS {
+                    return Err(MdnsError::Malformed);
+                }
+                offset = ((length & 0x3F) << 8) | low;
+            }
+            length => {
+                let label = packet
+                    .get(offset + 1..offset + 1 + length)
+                    .ok_or(MdnsError::Malformed)?;
+                let label = core::str::from_utf8(label).map_err(|_| MdnsError::Malformed)?;
+                if let Some(dotted) = &mut name {
+                    let separator = if dotted.is_empty() { "" } else { "." };
+                    if dotted.push_str(separator).is_err() || dotted.push_str(label).is_err() {
+                        name = None;
+                    }
+                }
+                offset += 1 + length;
+            }
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use embassy_net::Runner;
+use esp_radio::wifi::WifiDevice;
+use log::info;
+
+pub mod buttplug;
+pub mod mdns;
+pub mod mqtt;
+pub mod syslog;
+pub mod tcode;
+pub mod web;
+pub mod wifi;
+
+/// Task to run the network stack
+#[embassy_executor::task]
+pub async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
+    info!("Task Network Started");
+    runner.run().await
+}
@@ -0,0 +1,706 @@
+use core::{
+    cell::RefCell,
+    fmt::Write as _,
+    net::Ipv4Addr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use embassy_futures::select::{select, Either};
+use embassy_net::{
+    tcp::{TcpSocket, TcpWriter},
+    Stack,
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use embedded_io_async::Write;
+use heapless::{String, Vec};
+use log::{error, info, warn};
+use ossm_motion::{
+    fault::FaultCode,
+    motion::motion_state::{get_motion_state, MotionState},
+    pattern::PatternExecutor,
+};
+
+use crate::{
+    config::{
+        MAX_COMMAND_LENGTH, MAX_WIFI_COMMAND_LENGTH, MQTT_BUFFER_SIZE, MQTT_DEVICE_ID,
+        MQTT_KEEPALIVE_S, MQTT_RECONNECT_DELAY_MS, MQTT_STATE_INTERVAL_MS,
+    },
+    error::{report_error, NetworkError},
+    remote::{ble::process_command, ControlSource},
+    storage::{load_mqtt_broker, save_mqtt_broker},
+};
+
+pub const MAX_MQTT_USER_LENGTH: usize = 32;
+pub const MAX_MQTT_PASSWORD_LENGTH: usize = 64;
+const MQTT_DEFAULT_PORT: u16 = 1883;
+// Fits the longest topic, the Home Assistant config ones
+const MAX_TOPIC_LENGTH: usize = 64;
+
+// Control packet types in the upper nibble of the first byte
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+// With the flags the standard requires
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xC0;
+// Flags of the PUBLISH packet
+const RETAIN: u8 = 0x01;
+// Flags of the CONNECT packet
+const USER_NAME: u8 = 0x80;
+const PASSWORD: u8 = 0x40;
+const WILL_RETAIN: u8 = 0x20;
+const WILL: u8 = 0x04;
+const CLEAN_SESSION: u8 = 0x02;
+
+// The values in % announced to Home Assistant as numbers as (object, name)
+const PERCENT_ENTITIES: [(&str, &str); 4] = [
+    ("speed", "Speed"),
+    ("depth", "Depth"),
+    ("stroke", "Stroke"),
+    ("sensation", "Sensation"),
+];
+
+// The broker to connect to. Loaded from the settings when the MQTT task starts
+static BROKER: critical_section::Mutex<RefCell<Option<MqttBroker>>> =
+    critical_section::Mutex::new(RefCell::new(None));
+// Signalled when the broker is configured or removed
+static BROKER_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+// The connection as an `MqttStatus`
+static STATUS: AtomicU8 = AtomicU8::new(MqttStatus::Off as u8);
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum MqttError {
+    // The broker refused the connection with the return code of the CONNACK
+    Refused(u8),
+    // The broker sent something that is not MQTT
+    Malformed,
+    // A packet did not fit into `MQTT_BUFFER_SIZE`
+    TooLong,
+    // The broker closed the connection
+    Closed,
+}
+
+#[derive(Clone, Debug)]
+pub struct MqttBroker {
+    pub address: Ipv4Addr,
+    pub port: u16,
+    pub user: String<MAX_MQTT_USER_LENGTH>,
+    pub password: String<MAX_MQTT_PASSWORD_LENGTH>,
+}
+
+impl MqttBroker {
+    /// None if the port is 0 or the user or the password are too long
+    /// Without a user the password is ignored
+    pub fn new(address: Ipv4Addr, port: u16, user: &str, password: &str) -> Option<Self> {
+        if port == 0 {
+            return None;
+        }
+
+        Some(Self {
+            address,
+            port,
+            user: String::try_from(user).ok()?,
+            password: String::try_from(password).ok()?,
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum MqttStatus {
+    // No broker was configured
+    Off = 0,
+    Connecting = 1,
+    Connected = 2,
+}
+
+impl MqttStatus {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => MqttStatus::Connecting,
+            2 => MqttStatus::Connected,
+            _ => MqttStatus::Off,
+        }
+    }
+
+    /// Name reported in the WiFi status JSON
+    pub fn name(self) -> &'static str {
+        match self {
+            MqttStatus::Off => "off",
+            MqttStatus::Connecting => "connecting",
+            MqttStatus::Connected => "connected",
+        }
+    }
+}
+
+pub fn get_mqtt_status() -> MqttStatus {
+    MqttStatus::from_u8(STATUS.load(Ordering::Acquire))
+}
+
+fn set_mqtt_status(status: MqttStatus) {
+    STATUS.store(status as u8, Ordering::Release);
+}
+
+/// Whether the machine is connected to the broker. Counts as a connected remote
+pub fn is_mqtt_connected() -> bool {
+    get_mqtt_status() == MqttStatus::Connected
+}
+
+fn get_mqtt_broker() -> Option<MqttBroker> {
+    critical_section::with(|cs| BROKER.borrow_ref(cs).clone())
+}
+
+fn set_mqtt_broker(broker: Option<MqttBroker>) {
+    if let Err(err) = save_mqtt_broker(broker.as_ref()) {
+        // Still used until the next boot
+        report_error(err);
+    }
+    critical_section::with(|cs| *BROKER.borrow_ref_mut(cs) = broker);
+    BROKER_CHANGED.signal(());
+}
+
+/// `<ip>[:<port>[:<user>:<password>]]` sets the broker and `off` removes it
+/// The port defaults to 1883. Written to the WiFi characteristic after `mqtt:`
+/// Answers `ok:mqtt:<ip>:<port>` or `fail:mqtt` without the user and the password
+pub fn process_mqtt_command(command: &str) -> String<MAX_WIFI_COMMAND_LENGTH> {
+    let mut output = String::new();
+    if command == "off" {
+        info!("Removing the MQTT broker");
+        set_mqtt_broker(None);
+        write!(output, "ok:mqtt:off").expect("Always fits");
+        return output;
+    }
+
+    let mut split_command = command.splitn(4, ':');
+    let address = split_command
+        .next()
+        .and_then(|address| address.parse().ok());
+    let port = match split_command.next() {
+        None => Some(MQTT_DEFAULT_PORT),
+        Some(port) => port.parse().ok(),
+    };
+    let user = split_command.next().unwrap_or_default();
+    let password = split_command.next().unwrap_or_default();
+
+    let broker = match (address, port) {
+        (Some(address), Some(port)) => MqttBroker::new(address, port, user, password),
+        _ => None,
+    };
+    let result = match broker {
+        Some(broker) => {
+            info!("Using the MQTT broker {}:{}", broker.address, broker.port);
+            let result = write!(output, "ok:mqtt:{}:{}", broker.address, broker.port);
+            set_mqtt_broker(Some(broker));
+            result
+        }
+        None => {
+            // Not logged as it could contain the password
+            error!("Invalid MQTT broker");
+            write!(output, "fail:mqtt")
+        }
+    };
+    result.expect("Always fits");
+
+    output
+}
+
+/// Task to stay connected to the configured broker
+/// Announces the machine with Home Assistant MQTT discovery. MQTT 3.1.1 with QoS 0 only
+#[embassy_executor::task]
+pub async fn mqtt_task(stack: Stack<'static>) {
+    info!("Task MQTT Started");
+
+    let broker = load_mqtt_broker();
+    critical_section::with(|cs| *BROKER.borrow_ref_mut(cs) = broker);
+
+    let mut rx_buffer = [0u8; MQTT_BUFFER_SIZE];
+    let mut tx_buffer = [0u8; MQTT_BUFFER_SIZE];
+    loop {
+        // Before reading the broker so that no change is missed
+        BROKER_CHANGED.reset();
+        let Some(broker) = get_mqtt_broker() else {
+            set_mqtt_status(MqttStatus::Off);
+            BROKER_CHANGED.wait().await;
+            continue;
+        };
+
+        set_mqtt_status(MqttStatus::Connecting);
+        // The broker can not be reached before DHCP assigned an address
+        if let Either::Second(()) = select(stack.wait_config_up(), BROKER_CHANGED.wait()).await {
+            continue;
+        }
+
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        // Pings go out every half keepalive so something arrives well within this
+        socket.set_timeout(Some(Duration::from_secs(MQTT_KEEPALIVE_S as u64)));
+        match select(run_session(&mut socket, &broker), BROKER_CHANGED.wait()).await {
+            Either::First(Err(err)) => warn!(
+                "MQTT connection to {}:{} lost {:?}",
+                broker.address, broker.port, err
+            ),
+            Either::First(Ok(())) => {}
+            Either::Second(()) => {
+                info!("The MQTT broker changed");
+                // Connect to the new broker right away
+                BROKER_CHANGED.signal(());
+            }
+        }
+        socket.abort();
+        if let Err(err) = socket.flush().await {
+            warn!("Could not close the MQTT connection {:?}", err);
+        }
+        set_mqtt_status(MqttStatus::Connecting);
+
+        let delay = Timer::after(Duration::from_millis(MQTT_RECONNECT_DELAY_MS));
+        select(delay, BROKER_CHANGED.wait()).await;
+    }
+}
+
+/// Connect, announce the entities and publish the state until the connection fails
+async fn run_session(socket: &mut TcpSocket<'_>, broker: &MqttBroker) -> Result<(), NetworkError> {
+    socket.connect((broker.address, broker.port)).await?;
+    let (mut reader, mut writer) = socket.split();
+
+    let mut rx = [0u8; MQTT_BUFFER_SIZE];
+    let mut rx_length = 0;
+
+    send_connect(&mut writer, broker).await?;
+    // The CONNACK is always 4 bytes
+    while rx_length < 4 {
+        match reader.read(&mut rx[rx_length..4]).await? {
+            0 => return Err(MqttError::Closed.into()),
+            read => rx_length += read,
+        }
+    }
+    match rx[..4] {
+        [CONNACK, 2, _, 0] => {}
+        [CONNACK, 2, _, code] => return Err(MqttError::Refused(code).into()),
+        _ => return Err(MqttError::Malformed.into()),
+    }
+    rx_length = 0;
+    info!(
+        "Connected to the MQTT broker {}:{}",
+        broker.address, broker.port
+    );
+
+    send_subscribe(&mut writer).await?;
+    announce(&mut writer).await?;
+    publish(&mut writer, "availability", "online").await?;
+    set_mqtt_status(MqttStatus::Connected);
+
+    let mut published: Option<PublishedState> = None;
+    let mut last_ping = Instant::now();
+    let mut ticker = Ticker::every(Duration::from_millis(MQTT_STATE_INTERVAL_MS));
+    loop {
+        match select(reader.read(&mut rx[rx_length..]), ticker.next()).await {
+            Either::First(read) => {
+                let read = read?;
+                if read == 0 {
+                    return Err(MqttError::Closed.into());
+                }
+                rx_length += read;
+
+                while let Some((header, start, end)) = next_packet(&rx[..rx_length])? {
+                    // The SUBACK and the PINGRESP need no handling
+                    if header & 0xF0 == PUBLISH {
+                        process_publish(header, &rx[start..end])?;
+                    }
+                    rx.copy_within(end..rx_length, 0);
+                    rx_length -= end;
+                }
+                if rx_length == rx.len() {
+                    return Err(MqttError::TooLong.into());
+                }
+            }
+            Either::Second(()) => {
+                let state = PublishedState::new(&get_motion_state());
+                publish_changes(&mut writer, published.as_ref(), &state).await?;
+                published = Some(state);
+
+                if last_ping.elapsed() >= Duration::from_secs(MQTT_KEEPALIVE_S as u64 / 2) {
+                    write_packet(&mut writer, PINGREQ, &[]).await?;
+                    last_ping = Instant::now();
+                }
+            }
+        }
+    }
+}
+
+/// The values of the state topics as last published
+#[derive(PartialEq)]
+struct PublishedState {
+    speed: u32,
+    depth: u32,
+    stroke: u32,
+    sensation: u32,
+    pattern: u32,
+    enabled: bool,
+    fault: &'static str,
+}
+
+impl PublishedState {
+    fn new(state: &MotionState) -> Self {
+        Self {
+            speed: state.velocity,
+            depth: state.depth,
+            stroke: state.motion_length,
+            sensation: state.sensation,
+            pattern: state.pattern,
+            enabled: state.motion_enabled,
+            fault: state.fault.map_or("none", FaultCode::name),
+        }
+    }
+
+    fn percent(&self, object: &str) -> u32 {
+        match object {
+            "speed" => self.speed,
+            "depth" => self.depth,
+            "stroke" => self.stroke,
+            _ => self.sensation,
+        }
+    }
+}
+
+/// Publish the topics that changed since `previous`, all of them without it
+async fn publish_changes(
+    writer: &mut TcpWriter<'_>,
+    previous: Option<&PublishedState>,
+    state: &PublishedState,
+) -> Result<(), NetworkError> {
+    if previous == Some(state) {
+        return Ok(());
+    }
+
+    for (object, _) in PERCENT_ENTITIES {
+        let value = state.percent(object);
+        if previous.map(|previous| previous.percent(object)) != Some(value) {
+            let mut payload: String<10> = String::new();
+            write!(payload, "{}", value).expect("Always fits");
+            publish(writer, object, &payload).await?;
+        }
+    }
+    if previous.map(|previous| previous.pattern) != Some(state.pattern) {
+        let name = PatternExecutor::new()
+            .get_pattern_list()
+            .find(|(id, _)| *id == state.pattern)
+            .map_or("", |(_, name)| name);
+        publish(writer, "pattern", name).await?;
+    }
+    if previous.map(|previous| previous.enabled) != Some(state.enabled) {
+        let enabled = if state.enabled { "ON" } else { "OFF" };
+        publish(writer, "enabled", enabled).await?;
+    }
+    if previous.map(|previous| previous.fault) != Some(state.fault) {
+        publish(writer, "fault", state.fault).await?;
+    }
+
+    Ok(())
+}
+
+/// Turn a message on a command topic into a command like over BLE
+fn process_publish(header: u8, packet: &[u8]) -> Result<(), MqttError> {
+    let (topic, rest) = read_string(packet)?;
+    // QoS 1 and 2 messages have a packet identifier. The subscription asks for QoS 0
+    let payload = if header & 0x06 != 0 {
+        rest.get(2..).ok_or(MqttError::Malformed)?
+    } else {
+        rest
+    };
+    let payload = core::str::from_utf8(payload)
+        .map_err(|_| MqttError::Malformed)?
+        .trim();
+
+    let Some(object) = topic
+        .strip_prefix(MQTT_DEVICE_ID)
+        .and_then(|topic| topic.strip_prefix('/'))
+        .and_then(|topic| topic.strip_suffix("/set"))
+    else {
+        warn!("Message on the unexpected MQTT topic {}", topic);
+        return Ok(());
+    };
+
+    let mut command: String<MAX_COMMAND_LENGTH> = String::new();
+    let result = match object {
+        "speed" | "depth" | "stroke" | "sensation" => match payload.parse::<f32>() {
+            // Home Assistant sends the numbers as floats
+            Ok(value) => write!(command, "set:{}:{}", object, value.max(0.0) as u32),
+            Err(_) => {
+                error!("Could not parse the MQTT {} value {}", object, payload);
+                return Ok(());
+            }
+        },
+        "pattern" => {
+            let id = PatternExecutor::new()
+                .get_pattern_list()
+                .find(|(_, name)| *name == payload)
+                .map(|(id, _)| id);
+            match id {
+                Some(id) => write!(command, "set:pattern:{}", id),
+                None => {
+                    error!("No pattern named {}", payload);
+                    return Ok(());
+                }
+            }
+        }
+        "enabled" if payload == "ON" => write!(command, "go:strokeEngine"),
+        "enabled" if payload == "OFF" => write!(command, "go:menu"),
+        "stop" => write!(command, "go:stop"),
+        "arm" => write!(command, "go:arm"),
+        _ => {
+            error!("Unknown MQTT command {} {}", object, payload);
+            return Ok(());
+        }
+    };
+    result.expect("Always fits");
+
+    let response = process_command(&command, ControlSource::Mqtt);
+    if response.starts_with("fail:") {
+        warn!("MQTT command failed {}", response);
+    }
+
+    Ok(())
+}
+
+/// Announce the entities with Home Assistant MQTT discovery
+async fn announce(writer: &mut TcpWriter<'_>) -> Result<(), NetworkError> {
+    for (object, name) in PERCENT_ENTITIES {
+        let mut config: String<MQTT_BUFFER_SIZE> = String::new();
+        start_entity_config(&mut config, object, name)?;
+        write!(
+            config,
+            r#","state_topic":"{id}/{object}","command_topic":"{id}/{object}/set","min":0,"max":100,"unit_of_measurement":"%"}}"#,
+            id = MQTT_DEVICE_ID
+        )
+        .map_err(|_| MqttError::TooLong)?;
+        publish_config(writer, "number", object, &config).await?;
+    }
+
+    let mut config: String<MQTT_BUFFER_SIZE> = String::new();
+    start_entity_config(&mut config, "pattern", "Pattern")?;
+    write!(
+        config,
+        r#","state_topic":"{id}/pattern","command_topic":"{id}/pattern/set","options":["#,
+        id = MQTT_DEVICE_ID
+    )
+    .map_err(|_| MqttError::TooLong)?;
+    for (index, (_, name)) in PatternExecutor::new().get_pattern_list().enumerate() {
+        let separator = if index == 0 { "" } else { "," };
+        write!(config, r#"{}"{}""#, separator, name).map_err(|_| MqttError::TooLong)?;
+    }
+    config.push_str("]}").map_err(|_| MqttError::TooLong)?;
+    publish_config(writer, "select", "pattern", &config).await?;
+
+    let mut config: String<MQTT_BUFFER_SIZE> = String::new();
+    start_entity_config(&mut config, "enabled", "Motion")?;
+    write!(
+        config,
+        r#","state_topic":"{id}/enabled","command_topic":"{id}/enabled/set"}}"#,
+        id = MQTT_DEVICE_ID
+    )
+    .map_err(|_| MqttError::TooLong)?;
+    publish_config(writer, "switch", "enabled", &config).await?;
+
+    let mut config: String<MQTT_BUFFER_SIZE> = String::new();
+    start_entity_config(&mut config, "stop", "Stop")?;
+    write!(
+        config,
+        r#","command_topic":"{id}/stop/set"}}"#,
+        id = MQTT_DEVICE_ID
+    )
+    .map_err(|_| MqttError::TooLong)?;
+    publish_config(writer, "button", "stop", &config).await?;
+
+    let mut config: String<MQTT_BUFFER_SIZE> = String::new();
+    start_entity_config(&mut config, "arm", "Arm")?;
+    write!(
+        config,
+        r#","command_topic":"{id}/arm/set"}}"#,
+        id = MQTT_DEVICE_ID
+    )
+    .map_err(|_| MqttError::TooLong)?;
+    publish_config(writer, "button", "arm", &config).await?;
+
+    let mut config: String<MQTT_BUFFER_SIZE> = String::new();
+    start_entity_config(&mut config, "fault", "Fault")?;
+    write!(
+        config,
+        r#","state_topic":"{id}/fault"}}"#,
+        id = MQTT_DEVICE_ID
+    )
+    .map_err(|_| MqttError::TooLong)?;
+    publish_config(writer, "sensor", "fault", &config).await
+}
+
+/// The part of the discovery config all entities share. Left open for the rest
+fn start_entity_config(
+    config: &mut String<MQTT_BUFFER_SIZE>,
+    object: &str,
+    name: &str,
+) -> Result<(), MqttError> {
+    write!(
+        config,
+        r#"{{"name":"{name}","unique_id":"{id}_{object}","availability_topic":"{id}/availability","device":{{"identifiers":["{id}"],"name":"OSSM","model":"OSSM","sw_version":"{version}"}}"#,
+        id = MQTT_DEVICE_ID,
+        version = get_motion_state().firmware_version
+    )
+    .map_err(|_| MqttError::TooLong)
+}
+
+async fn publish_config(
+    writer: &mut TcpWriter<'_>,
+    component: &str,
+    object: &str,
+    config: &str,
+) -> Result<(), NetworkError> {
+    let mut topic: String<MAX_TOPIC_LENGTH> = String::new();
+    write!(
+        topic,
+        "homeassistant/{}/{}/{}/config",
+        component, MQTT_DEVICE_ID, object
+    )
+    .map_err(|_| MqttError::TooLong)?;
+    publish_to(writer, &topic, config).await
+}
+
+/// Publish to `<id>/<object>`, retained
+async fn publish(
+    writer: &mut TcpWriter<'_>,
+    object: &str,
+    payload: &str,
+) -> Result<(), NetworkError> {
+    let mut topic: String<MAX_TOPIC_LENGTH> = String::new();
+    write!(topic, "{}/{}", MQTT_DEVICE_ID, object).map_err(|_| MqttError::TooLong)?;
+    publish_to(writer, &topic, payload).await
+}
+
+async fn publish_to(
+    writer: &mut TcpWriter<'_>,
+    topic: &str,
+    payload: &str,
+) -> Result<(), NetworkError> {
+    let mut packet: Vec<u8, MQTT_BUFFER_SIZE> = Vec::new();
+    push_string(&mut packet, topic)?;
+    packet
+        .extend_from_slice(payload.as_bytes())
+        .map_err(|_| MqttError::TooLong)?;
+    write_packet(writer, PUBLISH | RETAIN, &packet).await
+}
+
+async fn send_connect(writer: &mut TcpWriter<'_>, broker: &MqttBroker) -> Result<(), NetworkError> {
+    let mut flags = CLEAN_SESSION | WILL | WILL_RETAIN;
+    if !broker.user.is_empty() {
+        flags |= USER_NAME;
+        if !broker.password.is_empty() {
+            flags |= PASSWORD;
+        }
+    }
+    let mut will_topic: String<MAX_TOPIC_LENGTH> = String::new();
+    write!(will_topic, "{}/availability", MQTT_DEVICE_ID).map_err(|_| MqttError::TooLong)?;
+
+    let mut packet: Vec<u8, MQTT_BUFFER_SIZE> = Vec::new();
+    push_string(&mut packet, "MQTT")?;
+    // Protocol level 4 is MQTT 3.1.1
+    let keepalive = MQTT_KEEPALIVE_S.to_be_bytes();
+    packet
+        .extend_from_slice(&[4, flags, keepalive[0], keepalive[1]])
+        .map_err(|_| MqttError::TooLong)?;
+    push_string(&mut packet, MQTT_DEVICE_ID)?;
+    push_string(&mut packet, &will_topic)?;
+    push_string(&mut packet, "offline")?;
+    if flags & USER_NAME != 0 {
+        push_string(&mut packet, &broker.user)?;
+    }
+    if flags & PASSWORD != 0 {
+        push_string(&mut packet, &broker.password)?;
+    }
+    write_packet(writer, CONNECT, &packet).await
+}
+
+async fn send_subscribe(writer: &mut TcpWriter<'_>) -> Result<(), NetworkError> {
+    let mut filter: String<MAX_TOPIC_LENGTH> = String::new();
+    write!(filter, "{}/+/set", MQTT_DEVICE_ID).map_err(|_| MqttError::TooLong)?;
+
+    let mut packet: Vec<u8, MQTT_BUFFER_SIZE> = Vec::new();
+    // Packet identifier 1. Only one subscription is ever made
+    packet
+        .extend_from_slice(&[0, 1])
+        .map_err(|_| MqttError::TooLong)?;
+    push_string(&mut packet, &filter)?;
+    // QoS 0
+    packet.push(0).map_err(|_| MqttError::TooLong)?;
+    write_packet(writer, SUBSCRIBE, &packet).await
+}
+
+/// Write the fixed header with the remaining length followed by the rest of the packet
+async fn write_packet(
+    writer: &mut TcpWriter<'_>,
+    header: u8,
+    packet: &[u8],
+) -> Result<(), NetworkError> {
+    let mut fixed_header: Vec<u8, 5> = Vec::new();
+    fixed_header.push(header).expect("Always fits");
+    let mut length = packet.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        fixed_header.push(byte).expect("Always fits");
+        if length == 0 {
+            break;
+        }
+    }
+
+    writer.write_all(&fixed_header).await?;
+    writer.write_all(packet).await?;
+    Ok(())
+}
+
+/// Strings are prefixed with their length as a big endian u16
+fn push_string(packet: &mut Vec<u8, MQTT_BUFFER_SIZE>, string: &str) -> Result<(), MqttError> {
+    let length = u16::try_from(string.len()).map_err(|_| MqttError::TooLong)?;
+    packet
+        .extend_from_slice(&length.to_be_bytes())
+        .map_err(|_| MqttError::TooLong)?;
+    packet
+        .extend_from_slice(string.as_bytes())
+        .map_err(|_| MqttError::TooLong)
+}
+
+/// A length prefixed string and what follows it
+fn read_string(packet: &[u8]) -> Result<(&str, &[u8]), MqttError> {
+    let length = match packet {
+        [high, low, ..] => u16::from_be_bytes([*high, *low]) as usize,
+        _ => return Err(MqttError::Malformed),
+    };
+    let string = packet.get(2..2 + length).ok_or(MqttError::Malformed)?;
+    let string = core::str::from_utf8(string).map_err(|_| MqttError::Malformed)?;
+    Ok((string, &packet[2 + length..]))
+}
+
+/// The first byte, the start and the end of the rest of the first complete packet
+/// None until the whole packet was received
+fn next_packet(buffer: &[u8]) -> Result<Option<(u8, usize, usize)>, MqttError> {
+    let mut length = 0;
+    // The remaining length takes at most 4 bytes, 7 bits each
+    for (index, byte) in buffer.iter().skip(1).take(4).enumerate() {
+        length |= ((byte & 0x7F) as usize) << (7 * index);
+        if byte & 0x80 == 0 {
+            let start = index + 2;
+            let end = start + length;
+            if end > MQTT_BUFFER_SIZE {
+                return Err(MqttError::TooLong);
+            }
+            return Ok((end <= buffer.len()).then_some((buffer[0], start, end)));
+        }
+    }
+
+    if buffer.len() >= 5 {
+        Err(MqttError::Malformed)
+    } else {
+        Ok(None)
+    }
+}
@@ -0,0 +1,186 @@
+use core::{cell::RefCell, fmt::Write as _, net::Ipv4Addr};
+
+use embassy_futures::select::{select, Either};
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    Stack,
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Timer};
+use heapless::String;
+use log::{error, info, warn, Level};
+
+use crate::{
+    config::{HOSTNAME, LOG_POLL_MS, MAX_LOG_LINE_LENGTH, MAX_WIFI_COMMAND_LENGTH},
+    error::report_error,
+    logger::{get_oldest_log_sequence, next_log_line, LogLine},
+    storage::{load_syslog_server, save_syslog_server},
+};
+
+const SYSLOG_DEFAULT_PORT: u16 = 514;
+// The user-level messages facility
+const FACILITY: u8 = 1;
+// Fits the header in front of the line
+const MAX_SYSLOG_MESSAGE_LENGTH: usize = MAX_LOG_LINE_LENGTH + 48;
+
+// The server to send to. Loaded from the settings when the syslog task starts
+static SERVER: critical_section::Mutex<RefCell<Option<SyslogServer>>> =
+    critical_section::Mutex::new(RefCell::new(None));
+// Signalled when the server is configured or removed
+static SERVER_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+#[derive(Clone, Debug)]
+pub struct SyslogServer {
+    pub address: Ipv4Addr,
+    pub port: u16,
+}
+
+impl SyslogServer {
+    /// None if the port is 0
+    pub fn new(address: Ipv4Addr, port: u16) -> Option<Self> {
+        (port != 0).then_some(Self { address, port })
+    }
+}
+
+/// Whether the log is sent to a syslog server
+pub fn is_syslog_enabled() -> bool {
+    get_syslog_server().is_some()
+}
+
+fn get_syslog_server() -> Option<SyslogServer> {
+    critical_section::with(|cs| SERVER.borrow_ref(cs).clone())
+}
+
+fn set_syslog_server(server: Option<SyslogServer>) {
+    if let Err(err) = save_syslog_server(server.as_ref()) {
+        // Still used until the next boot
+        report_error(err);
+    }
+    critical_section::with(|cs| *SERVER.borrow_ref_mut(cs) = server);
+    SERVER_CHANGED.signal(());
+}
+
+/// `<ip>[:<port>]` sets the syslog server and `off` removes it
+/// The port defaults to 514. Written to the WiFi characteristic after `syslog:`
+/// Answers `ok:syslog:<ip>:<port>` or `fail:syslog`
+pub fn process_syslog_command(command: &str) -> String<MAX_WIFI_COMMAND_LENGTH> {
+    let mut output = String::new();
+    if command == "off" {
+        info!("Removing the syslog server");
+        set_syslog_server(None);
+        write!(output, "ok:syslog:off").expect("Always fits");
+        return output;
+    }
+
+    let (address, port) = match command.split_once(':') {
+        None => (command.parse().ok(), Some(SYSLOG_DEFAULT_PORT)),
+        Some((address, port)) => (address.parse().ok(), port.parse().ok()),
+    };
+    let server = match (address, port) {
+        (Some(address), Some(port)) => SyslogServer::new(address, port),
+        _ => None,
+    };
+    let result = match server {
+        Some(server) => {
+            info!("Sending the log to {}:{}", server.address, server.port);
+            let result = write!(output, "ok:syslog:{}:{}", server.address, server.port);
+            set_syslog_server(Some(server));
+            result
+        }
+        None => {
+            error!("Invalid syslog server {}", command);
+            write!(output, "fail:syslog")
+        }
+    };
+    result.expect("Always fits");
+
+    output
+}
+
+/// Task to send the log to the configured syslog server
+/// Over UDP (RFC 5424). Failures to send are not logged as that would only add more lines
+#[embassy_executor::task]
+pub async fn syslog_task(stack: Stack<'static>) {
+    info!("Task Syslog Started");
+
+    let server = load_syslog_server();
+    critical_section::with(|cs| *SERVER.borrow_ref_mut(cs) = server);
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; 16];
+    let mut tx_buffer = [0u8; 4 * MAX_SYSLOG_MESSAGE_LENGTH];
+    // Kept across reconnects so that the lines logged while the network was down follow
+    let mut sequence = get_oldest_log_sequence();
+    loop {
+        // Before reading the server so that no change is missed
+        SERVER_CHANGED.reset();
+        let Some(server) = get_syslog_server() else {
+            SERVER_CHANGED.wait().await;
+            continue;
+        };
+
+        // The server can not be reached before DHCP assigned an address
+        if let Either::Second(()) = select(stack.wait_config_up(), SERVER_CHANGED.wait()).await {
+            continue;
+        }
+
+        let mut socket = UdpSocket::new(
+            stack,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        );
+        // Any free port
+        if let Err(err) = socket.bind(0) {
+            warn!("Could not bind the syslog socket {:?}", err);
+            select(stack.wait_config_down(), SERVER_CHANGED.wait()).await;
+            continue;
+        }
+        let stopped = select(stack.wait_config_down(), SERVER_CHANGED.wait());
+        if let Either::Second(Either::Second(())) =
+            select(send_lines(&socket, &server, &mut sequence), stopped).await
+        {
+            info!("The syslog server changed");
+        }
+        socket.close();
+    }
+}
+
+/// Send every new line to the server
+async fn send_lines(socket: &UdpSocket<'_>, server: &SyslogServer, sequence: &mut u32) {
+    loop {
+        while let Some((line, next)) = next_log_line(*sequence) {
+            *sequence = next;
+            let message = format_message(&line);
+            socket
+                .send_to(message.as_bytes(), (server.address, server.port))
+                .await
+                .ok();
+        }
+        Timer::after(Duration::from_millis(LOG_POLL_MS)).await;
+    }
+}
+
+/// e.g. `<14>1 - ossm ossm - - - Motor connected` without a timestamp as the clock is not set
+fn format_message(line: &LogLine) -> String<MAX_SYSLOG_MESSAGE_LENGTH> {
+    let severity = match line.level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    };
+    let mut message = String::new();
+    write!(
+        message,
+        "<{}>1 - {} {} - - - {}",
+        FACILITY * 8 + severity,
+        HOSTNAME,
+        HOSTNAME,
+        line.text
+    )
+    .expect("Sized for the longest line");
+
+    message
+}
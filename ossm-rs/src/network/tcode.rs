@@ -0,0 +1,74 @@
+use embassy_net::{tcp::TcpSocket, Stack};
+use embedded_io_async::Write;
+use heapless::Vec;
+use log::{info, warn};
+use ossm_motion::motion::tcode::process_tcode_line;
+
+use crate::config::{MAX_TCODE_LINE_LENGTH, TCODE_PORT};
+
+// The TCP buffers in each direction. Players send short lines often
+const TCODE_BUFFER_SIZE: usize = 256;
+
+/// Task to take the T-Code of one player after another
+#[embassy_executor::task]
+pub async fn tcode_task(stack: Stack<'static>) {
+    info!("Task T-Code Started");
+
+    let mut rx_buffer = [0u8; TCODE_BUFFER_SIZE];
+    let mut tx_buffer = [0u8; TCODE_BUFFER_SIZE];
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+
+        if let Err(err) = socket.accept(TCODE_PORT).await {
+            warn!("Could not accept the T-Code connection {:?}", err);
+            continue;
+        }
+        info!(
+            "T-Code player connected from {:?}",
+            socket.remote_endpoint()
+        );
+
+        if let Err(err) = serve(&mut socket).await {
+            warn!("The T-Code connection failed {:?}", err);
+        }
+        info!("T-Code player disconnected");
+        socket.close();
+        if let Err(err) = socket.flush().await {
+            warn!("Could not close the T-Code connection {:?}", err);
+        }
+    }
+}
+
+/// Run the lines until the player closes the connection
+async fn serve(socket: &mut TcpSocket<'_>) -> Result<(), embassy_net::tcp::Error> {
+    let mut line: Vec<u8, MAX_TCODE_LINE_LENGTH> = Vec::new();
+    // The line did not fit and is dropped until its end
+    let mut overflow = false;
+    let mut buffer = [0u8; 64];
+    loop {
+        let read = socket.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        for byte in &buffer[..read] {
+            match byte {
+                b'\r' | b'\n' => {
+                    if overflow {
+                        warn!("Dropped a T-Code line. Too long");
+                    } else if let Ok(command) = core::str::from_utf8(&line) {
+                        let response = process_tcode_line(command);
+                        if !response.is_empty() {
+                            socket.write_all(response.as_bytes()).await?;
+                        }
+                    } else {
+                        warn!("Dropped a T-Code line. Not UTF-8");
+                    }
+                    line.clear();
+                    overflow = false;
+                }
+                byte => overflow |= line.push(*byte).is_err(),
+            }
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use core::fmt::Write as _;
+
+use embassy_net::{tcp::TcpSocket, Stack};
+use embassy_time::Duration;
+use embedded_io_async::Write;
+use heapless::String;
+use log::{info, warn};
+use ossm_motion::{
+    motion::motion_state::get_motion_state, pattern::PatternExecutor, time::AtomicTimestamp,
+};
+
+use crate::{
+    config::{
+        HTTP_API_TOKEN, HTTP_BUFFER_SIZE, HTTP_CONNECTIONS, HTTP_PORT, HTTP_TIMEOUT_MS,
+        MAX_HTTP_REQUEST_LENGTH, WEB_TIMEOUT_MS,
+    },
+    error::OtaError,
+    ota::{reboot, OtaUpdate},
+    remote::{ble::process_command, ControlSource},
+};
+
+const INDEX_HTML: &str = include_str!("index.html");
+// Fits the status line and the headers of every response
+const MAX_HEADER_LENGTH: usize = 160;
+// Fits the method and the path of every route
+const MAX_METHOD_LENGTH: usize = 8;
+const MAX_PATH_LENGTH: usize = 32;
+// Fits `fail:ota:<reason>` with the longest reason
+const MAX_OTA_RESPONSE_LENGTH: usize = 24;
+
+// The last authorized request of any browser. The web UI counts as a connected remote for WEB_TIMEOUT_MS
+static LAST_REQUEST: AtomicTimestamp = AtomicTimestamp::never();
+
+/// Whether a request was received within `WEB_TIMEOUT_MS`
+pub fn is_web_connected() -> bool {
+    LAST_REQUEST.is_within(Duration::from_millis(WEB_TIMEOUT_MS))
+}
+
+/// The request line and the headers. The body is read depending on the route
+struct Request {
+    method: String<MAX_METHOD_LENGTH>,
+    path: String<MAX_PATH_LENGTH>,
+    // Whether it carried the API token or none is needed
+    authorized: bool,
+    // Where the body starts in the buffer and how much of it was read with the headers
+    body_start: usize,
+    body_read: usize,
+    body_length: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Status {
+    Ok,
+    BadRequest,
+    Unauthorized,
+    NotFound,
+    PayloadTooLarge,
+}
+
+impl Status {
+    fn line(self) -> &'static str {
+        match self {
+            Status::Ok => "200 OK",
+            Status::BadRequest => "400 Bad Request",
+            Status::Unauthorized => "401 Unauthorized",
+            Status::NotFound => "404 Not Found",
+            Status::PayloadTooLarge => "413 Payload Too Large",
+        }
+    }
+}
+
+/// Task to serve one connection after another. `HTTP_CONNECTIONS` of them listen on the port
+/// Serves the web UI and its JSON API, one request per connection
+#[embassy_executor::task(pool_size = HTTP_CONNECTIONS)]
+pub async fn web_task(stack: Stack<'static>) {
+    info!("Task Web Started");
+
+    let mut rx_buffer = [0u8; HTTP_BUFFER_SIZE];
+    let mut tx_buffer = [0u8; HTTP_BUFFER_SIZE];
+    let mut request = [0u8; MAX_HTTP_REQUEST_LENGTH];
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_millis(HTTP_TIMEOUT_MS)));
+
+        if let Err(err) = socket.accept(HTTP_PORT).await {
+            warn!("Could not accept the HTTP connection {:?}", err);
+            continue;
+        }
+
+        if let Err(err) = serve(&mut socket, &mut request).await {
+            warn!("Could not serve the HTTP request {:?}", err);
+        }
+        socket.close();
+        // Send everything before the socket is dropped
+        if let Err(err) = socket.flush().await {
+            warn!("Could not close the HTTP connection {:?}", err);
+        }
+    }
+}
+
+async fn serve(
+    socket: &mut TcpSocket<'_>,
+    buffer: &mut [u8],
+) -> Result<(), embassy_net::tcp::Error> {
+    let request = match read_request(socket, buffer).await? {
+        Ok(request) => request,
+        Err(status) => return respond(socket, status, "text/plain", status.line()).await,
+    };
+    // The page itself asks for the token
+    if request.path.starts_with("/api/") && !request.authorized {
+        warn!("Unauthorized HTTP request for {}", request.path);
+        let status = Status::Unauthorized;
+        return respond(socket, status, "text/plain", status.line()).await;
+    }
+    LAST_REQUEST.store_now();
+
+    if (request.method.as_str(), request.path.as_str()) == ("POST", "/api/ota") {
+        let result = upload(socket, buffer, &request).await?;
+        let mut response: String<MAX_OTA_RESPONSE_LENGTH> = String::new();
+        let written = match &result {
+            Ok(()) => write!(response, "ok:ota"),
+            Err(err) => write!(response, "fail:ota:{}", err.name()),
+        };
+        written.expect("Always fits");
+        respond(socket, Status::Ok, "text/plain", &response).await?;
+        if result.is_ok() {
+            socket.close();
+            // Send the response before the reboot
+            socket.flush().await?;
+            reboot().await;
+        }
+        return Ok(());
+    }
+
+    let body = match read_body(socket, buffer, &request).await? {
+        Ok(body) => body,
+        Err(status) => return respond(socket, status, "text/plain", status.line()).await,
+    };
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => respond(socket, Status::Ok, "text/html; charset=utf-8", INDEX_HTML).await,
+        ("GET", "/api/state") => {
+            let state = get_motion_state().as_json();
+            respond(socket, Status::Ok, "application/json", &state).await
+        }
+        ("GET", "/api/patterns") => {
+            let patterns = PatternExecutor::new().get_all_patterns_json();
+            respond(socket, Status::Ok, "application/json", &patterns).await
+        }
+        ("POST", "/api/command") => {
+            let response = process_command(body.trim(), ControlSource::Web);
+            respond(socket, Status::Ok, "text/plain", &response).await
+        }
+        _ => {
+            warn!("No HTTP route for {} {}", request.method, request.path);
+            let status = Status::NotFound;
+            respond(socket, status, "text/plain", status.line()).await
+        }
+    }
+}
+
+/// Read the request line and the headers into the buffer
+/// The inner error is the status to answer with if the request can not be served
+async fn read_request(
+    socket: &mut TcpSocket<'_>,
+    buffer: &mut [u8],
+) -> Result<Result<Request, Status>, embassy_net::tcp::Error> {
+    let mut length = 0;
+    let header_end = loop {
+        if length == buffer.len() {
+            return Ok(Err(Status::PayloadTooLarge));
+        }
+        let read = socket.read(&mut buffer[length..]).await?;
+        if read == 0 {
+            return Ok(Err(Status::BadRequest));
+        }
+        length += read;
+
+        if let Some(end) = buffer[..length]
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+        {
+            break end;
+        }
+    };
+
+    let Ok(head) = core::str::from_utf8(&buffer[..header_end]) else {
+        return Ok(Err(Status::BadRequest));
+    };
+    let header = |header: &str| {
+        head.lines()
+            .skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case(header))
+            .map(|(_, value)| value.trim())
+    };
+    let content_length = header("content-length").map(|value| value.parse::<usize>());
+    let body_length = match content_length {
+        None => 0,
+        Some(Ok(body_length)) => body_length,
+        Some(Err(_)) => return Ok(Err(Status::BadRequest)),
+    };
+
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    let (Some(method), Some(path)) = (request_line.next(), request_line.next()) else {
+        return Ok(Err(Status::BadRequest));
+    };
+    let (Ok(method), Ok(path)) = (String::try_from(method), String::try_from(path)) else {
+        // No route is this long
+        return Ok(Err(Status::NotFound));
+    };
+
+    let authorized = match HTTP_API_TOKEN {
+        None => true,
+        Some(token) => header("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|value| token_matches(value.trim(), token)),
+    };
+
+    let body_start = header_end + 4;
+    Ok(Ok(Request {
+        method,
+        path,
+        authorized,
+        body_start,
+        body_read: length - body_start,
+        body_length,
+    }))
+}
+
+/// Compare every byte so that the time taken does not tell how much of the token was right
+fn token_matches(value: &str, token: &str) -> bool {
+    value.len() == token.len()
+        && value
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Read the rest of the body of `Content-Length` into the buffer after the headers
+async fn read_body<'a>(
+    socket: &mut TcpSocket<'_>,
+    buffer: &'a mut [u8],
+    request: &Request,
+) -> Result<Result<&'a str, Status>, embassy_net::tcp::Error> {
+    let mut length = request.body_start + request.body_read;
+    let body_end = request.body_start + request.body_length;
+    if body_end > buffer.len() {
+        return Ok(Err(Status::PayloadTooLarge));
+    }
+    while length < body_end {
+        let read = socket.read(&mut buffer[length..body_end]).await?;
+        if read == 0 {
+            return Ok(Err(Status::BadRequest));
+        }
+        length += read;
+    }
+
+    match core::str::from_utf8(&buffer[request.body_start..body_end]) {
+        Ok(body) => Ok(Ok(body)),
+        Err(_) => Ok(Err(Status::BadRequest)),
+    }
+}
+
+/// Write the body to the spare partition as it arrives. The buffer is reused for reading
+/// The inner error is the reason the update was rejected
+async fn upload(
+    socket: &mut TcpSocket<'_>,
+    buffer: &mut [u8],
+    request: &Request,
+) -> Result<Result<(), OtaError>, embassy_net::tcp::Error> {
+    let mut update = match OtaUpdate::begin() {
+        Ok(update) => update,
+        Err(err) => return Ok(Err(err)),
+    };
+    info!("Receiving an update of {} bytes", request.body_length);
+
+    let start = request.body_start;
+    let mut received = request.body_read.min(request.body_length);
+    if let Err(err) = update.write(&buffer[start..start + received]) {
+        return Ok(Err(err));
+    }
+    while received < request.body_length {
+        let length = (request.body_length - received).min(buffer.len());
+        let read = socket.read(&mut buffer[..length]).await?;
+        if read == 0 {
+            return Ok(Err(OtaError::Incomplete));
+        }
+        received += read;
+        if let Err(err) = update.write(&buffer[..read]) {
+            return Ok(Err(err));
+        }
+    }
+
+    Ok(update.finish())
+}
+
+async fn respond(
+    socket: &mut TcpSocket<'_>,
+    status: Status,
+    content_type: &str,
+    body: &str,
+) -> Result<(), embassy_net::tcp::Error> {
+    let mut header: String<MAX_HEADER_LENGTH> = String::new();
+    write!(
+        header,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status.line(),
+        content_type,
+        body.len()
+    )
+    .expect("Always fits");
+
+    socket.write_all(header.as_bytes()).await?;
+    socket.write_all(body.as_bytes()).await
+}
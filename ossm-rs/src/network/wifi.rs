@@ -0,0 +1,301 @@
+use core::{
+    cell::RefCell,
+    fmt::Write,
+    net::Ipv4Addr,
+    sync::atomic::{AtomicU32, AtomicU8, Ordering},
+};
+
+use embassy_futures::select::{select, Either};
+use embassy_net::Stack;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Timer};
+use esp_radio::wifi::{AuthMethod, ClientConfig, ModeConfig, WifiController, WifiError, WifiEvent};
+use heapless::String;
+use log::{error, info, warn};
+
+use crate::{
+    config::{MAX_WIFI_COMMAND_LENGTH, MAX_WIFI_STATUS_LENGTH, WIFI_RECONNECT_DELAY_MS},
+    error::report_error,
+    network::{
+        buttplug::{get_buttplug_status, process_intiface_command},
+        mqtt::{get_mqtt_status, process_mqtt_command},
+        syslog::{is_syslog_enabled, process_syslog_command},
+    },
+    storage::{load_wifi_credentials, save_wifi_credentials},
+};
+
+// Limits of the 802.11 standard
+pub const MAX_SSID_LENGTH: usize = 32;
+pub const MAX_WIFI_PASSWORD_LENGTH: usize = 64;
+// WPA2 needs at least this many characters. Empty passwords join open networks
+const MIN_WIFI_PASSWORD_LENGTH: usize = 8;
+
+// The network to join. Loaded from the settings when the WiFi task starts
+static CREDENTIALS: critical_section::Mutex<RefCell<Option<WifiCredentials>>> =
+    critical_section::Mutex::new(RefCell::new(None));
+// Signalled when the credentials are provisioned or forgotten
+static CREDENTIALS_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+// The connection as a `WifiStatus`
+static STATUS: AtomicU8 = AtomicU8::new(WifiStatus::Unconfigured as u8);
+// The address assigned by DHCP. 0 while there is none
+static ADDRESS: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone, Debug)]
+pub struct WifiCredentials {
+    pub ssid: String<MAX_SSID_LENGTH>,
+    pub password: String<MAX_WIFI_PASSWORD_LENGTH>,
+}
+
+impl WifiCredentials {
+    /// None if the SSID is empty or does not fit into the status JSON or the password
+    /// is not valid for WPA2
+    pub fn new(ssid: &str, password: &str) -> Option<Self> {
+        let valid_ssid = !ssid.is_empty()
+            && !ssid
+                .chars()
+                .any(|c| c == '"' || c == '\\' || c.is_control());
+        let valid_password = password.is_empty() || password.len() >= MIN_WIFI_PASSWORD_LENGTH;
+        if !valid_ssid || !valid_password {
+            return None;
+        }
+
+        Some(Self {
+            ssid: String::try_from(ssid).ok()?,
+            password: String::try_from(password).ok()?,
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum WifiStatus {
+    // No network was provisioned
+    Unconfigured = 0,
+    Connecting = 1,
+    Connected = 2,
+}
+
+impl WifiStatus {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => WifiStatus::Connecting,
+            2 => WifiStatus::Connected,
+            _ => WifiStatus::Unconfigured,
+        }
+    }
+
+    /// Name reported in the status JSON
+    pub fn name(self) -> &'static str {
+        match self {
+            WifiStatus::Unconfigured => "unconfigured",
+            WifiStatus::Connecting => "connecting",
+            WifiStatus::Connected => "connected",
+        }
+    }
+}
+
+pub fn get_wifi_status() -> WifiStatus {
+    WifiStatus::from_u8(STATUS.load(Ordering::Acquire))
+}
+
+fn set_wifi_status(status: WifiStatus) {
+    STATUS.store(status as u8, Ordering::Release);
+}
+
+/// The address assigned by DHCP if connected
+pub fn get_ip_address() -> Option<Ipv4Addr> {
+    match ADDRESS.load(Ordering::Acquire) {
+        0 => None,
+        address => Some(Ipv4Addr::from(address)),
+    }
+}
+
+fn get_wifi_credentials() -> Option<WifiCredentials> {
+    critical_section::with(|cs| CREDENTIALS.borrow_ref(cs).clone())
+}
+
+/// Store the network to join and join it. None forgets the network and disconnects
+fn set_wifi_credentials(credentials: Option<WifiCredentials>) {
+    if let Err(err) = save_wifi_credentials(credentials.as_ref()) {
+        // Still joined until the next boot
+        report_error(err);
+    }
+    critical_section::with(|cs| *CREDENTIALS.borrow_ref_mut(cs) = credentials);
+    CREDENTIALS_CHANGED.signal(());
+}
+
+/// e.g. `{"status":"connected","ssid":"home","ip":"192.168.1.20","mqtt":"connected","intiface":"off","syslog":false}`
+/// The password is never reported
+pub fn get_wifi_status_json() -> String<MAX_WIFI_STATUS_LENGTH> {
+    let credentials = get_wifi_credentials();
+    let ssid = credentials
+        .as_ref()
+        .map_or("", |credentials| credentials.ssid.as_str());
+
+    let mut output = String::new();
+    let result = match get_ip_address() {
+        Some(address) => write!(
+            output,
+            r#"{{"status":"{}","ssid":"{}","ip":"{}","mqtt":"{}","intiface":"{}","syslog":{}}}"#,
+            get_wifi_status().name(),
+            ssid,
+            address,
+            get_mqtt_status().name(),
+            get_buttplug_status().name(),
+            is_syslog_enabled()
+        ),
+        None => write!(
+            output,
+            r#"{{"status":"{}","ssid":"{}","ip":null,"mqtt":"{}","intiface":"{}","syslog":{}}}"#,
+            get_wifi_status().name(),
+            ssid,
+            get_mqtt_status().name(),
+            get_buttplug_status().name(),
+            is_syslog_enabled()
+        ),
+    };
+    if result.is_err() {
+        error!("Could not write the WiFi status. Too long");
+    }
+
+    output
+}
+
+/// `connect:<ssid>:<password>` joins a network. The password is everything after the
+/// second colon so the SSID can not contain one. `connect:<ssid>` joins an open network
+/// `forget` disconnects and removes the stored network
+/// `mqtt:<broker>` configures the MQTT broker, `intiface:<server>` the Intiface server and
+/// `syslog:<server>` where the log is sent
+/// Answers `ok:<command>` or `fail:<command>` without the password
+pub fn process_wifi_command(command: &str) -> String<MAX_WIFI_COMMAND_LENGTH> {
+    if let Some(broker) = command.strip_prefix("mqtt:") {
+        return process_mqtt_command(broker);
+    }
+    if let Some(server) = command.strip_prefix("intiface:") {
+        return process_intiface_command(server);
+    }
+    if let Some(server) = command.strip_prefix("syslog:") {
+        return process_syslog_command(server);
+    }
+
+    let mut output = String::new();
+    let mut split_command = command.splitn(3, ':');
+    let result = match (
+        split_command.next(),
+        split_command.next(),
+        split_command.next(),
+    ) {
+        (Some("connect"), Some(ssid), password) => {
+            match WifiCredentials::new(ssid, password.unwrap_or_default()) {
+                Some(credentials) => {
+                    info!("Provisioned the WiFi network {}", credentials.ssid);
+                    let result = write!(output, "ok:connect:{}", credentials.ssid);
+                    set_wifi_credentials(Some(credentials));
+                    result
+                }
+                None => {
+                    error!("Invalid WiFi credentials for the network {}", ssid);
+                    write!(output, "fail:connect")
+                }
+            }
+        }
+        (Some("forget"), None, None) => {
+            info!("Forgetting the WiFi network");
+            set_wifi_credentials(None);
+            write!(output, "ok:forget")
+        }
+        _ => {
+            // Not logged as it could contain the password
+            error!("Unknown WiFi command");
+            write!(output, "fail")
+        }
+    };
+    result.expect("Always fits");
+
+    output
+}
+
+/// Task to keep the machine connected to the provisioned network
+/// Joins it again whenever the connection drops
+#[embassy_executor::task]
+pub async fn wifi_task(mut controller: WifiController<'static>) {
+    info!("Task WiFi Started");
+
+    let credentials = load_wifi_credentials();
+    critical_section::with(|cs| *CREDENTIALS.borrow_ref_mut(cs) = credentials);
+
+    loop {
+        // Before reading the credentials so that no change is missed
+        CREDENTIALS_CHANGED.reset();
+        let Some(credentials) = get_wifi_credentials() else {
+            set_wifi_status(WifiStatus::Unconfigured);
+            CREDENTIALS_CHANGED.wait().await;
+            continue;
+        };
+
+        set_wifi_status(WifiStatus::Connecting);
+        match connect(&mut controller, &credentials).await {
+            Ok(()) => {
+                info!("Connected to the WiFi network {}", credentials.ssid);
+                set_wifi_status(WifiStatus::Connected);
+
+                let disconnected = controller.wait_for_event(WifiEvent::StaDisconnected);
+                match select(disconnected, CREDENTIALS_CHANGED.wait()).await {
+                    Either::First(()) => warn!("Lost the WiFi connection"),
+                    Either::Second(()) => {
+                        if let Err(err) = controller.disconnect_async().await {
+                            warn!("Could not disconnect from the WiFi network {:?}", err);
+                        }
+                        // Join the new network right away
+                        CREDENTIALS_CHANGED.signal(());
+                    }
+                }
+                set_wifi_status(WifiStatus::Connecting);
+            }
+            Err(err) => warn!(
+                "Could not connect to the WiFi network {} {:?}",
+                credentials.ssid, err
+            ),
+        }
+
+        let delay = Timer::after(Duration::from_millis(WIFI_RECONNECT_DELAY_MS));
+        select(delay, CREDENTIALS_CHANGED.wait()).await;
+    }
+}
+
+async fn connect(
+    controller: &mut WifiController<'static>,
+    credentials: &WifiCredentials,
+) -> Result<(), WifiError> {
+    let auth_method = if credentials.password.is_empty() {
+        AuthMethod::None
+    } else {
+        AuthMethod::Wpa2Personal
+    };
+    let config = ClientConfig::default()
+        .with_ssid(credentials.ssid.as_str().into())
+        .with_password(credentials.password.as_str().into())
+        .with_auth_method(auth_method);
+    controller.set_config(&ModeConfig::Client(config))?;
+
+    controller.connect_async().await
+}
+
+/// Task to track the address assigned by DHCP
+#[embassy_executor::task]
+pub async fn dhcp_task(stack: Stack<'static>) {
+    info!("Task DHCP Started");
+
+    loop {
+        stack.wait_config_up().await;
+        if let Some(config) = stack.config_v4() {
+            let address = config.address.address();
+            info!("Got the address {}", address);
+            ADDRESS.store(address.into(), Ordering::Release);
+        }
+
+        stack.wait_config_down().await;
+        info!("Lost the address");
+        ADDRESS.store(0, Ordering::Release);
+    }
+}
@@ -0,0 +1,334 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use ed25519_compact::{PublicKey, Signature, VerifyingState};
+use embassy_time::{Duration, Timer};
+use embedded_storage::{ReadStorage, Storage};
+use esp_bootloader_esp_idf::{
+    ota::OtaImageState,
+    ota_updater::OtaUpdater,
+    partitions::{self, AppPartitionSubType, PartitionType, PARTITION_TABLE_MAX_LEN},
+};
+use esp_storage::FlashStorage;
+use heapless::Vec;
+use log::{error, info, warn};
+use ossm_motion::motion::motion_state::get_motion_state;
+
+use crate::{
+    config::{OTA_CONFIRM_DELAY_MS, OTA_PUBLIC_KEY},
+    error::{report_error, OtaError},
+    storage::{load_ota_trial, save_ota_trial, with_flash},
+};
+
+const SIGNATURE_LENGTH: usize = 64;
+// Written a sector at a time so that every sector is erased once
+const FLASH_SECTOR_SIZE: usize = 4096;
+// The first byte of every ESP app image
+const IMAGE_MAGIC: u8 = 0xE9;
+
+// Set while an update is written. One at a time
+static IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u32)]
+pub enum OtaTrial {
+    // Running a confirmed firmware
+    None = 0,
+    // An update was written and is booted next
+    Activated = 1,
+    // The update was booted and not confirmed yet
+    Booted = 2,
+}
+
+impl OtaTrial {
+    pub fn from_u32(value: u32) -> Self {
+        match value {
+            1 => OtaTrial::Activated,
+            2 => OtaTrial::Booted,
+            _ => OtaTrial::None,
+        }
+    }
+}
+
+impl OtaError {
+    /// Reason reported in `fail:ota:<reason>`
+    pub fn name(&self) -> &'static str {
+        match self {
+            OtaError::NoKey => "nokey",
+            OtaError::Busy => "busy",
+            OtaError::TooLarge => "toolarge",
+            OtaError::BadSignature => "signature",
+            OtaError::NotAnImage => "image",
+            OtaError::Incomplete => "incomplete",
+            OtaError::Flash => "flash",
+        }
+    }
+}
+
+/// An update being written to the spare partition, the 64 byte Ed25519 signature of the app
+/// image followed by the image. It is only booted if the signature matches `OTA_PUBLIC_KEY`
+pub struct OtaUpdate {
+    // Where the spare partition is in the flash and how large it is
+    offset: u32,
+    size: u32,
+    // The bytes of the image received so far
+    received: u32,
+    key: PublicKey,
+    signature: Vec<u8, SIGNATURE_LENGTH>,
+    // Created once the signature is complete
+    verifier: Option<VerifyingState>,
+    sector: Vec<u8, FLASH_SECTOR_SIZE>,
+}
+
+impl OtaUpdate {
+    /// Start writing an update. Only while the motion is disabled
+    pub fn begin() -> Result<Self, OtaError> {
+        let key = OTA_PUBLIC_KEY.and_then(parse_key).ok_or(OtaError::NoKey)?;
+        check_motion_disabled()?;
+        if IN_PROGRESS.swap(true, Ordering::AcqRel) {
+            return Err(OtaError::Busy);
+        }
+
+        // Dropping the update clears the flag again, also if there is no spare partition
+        let mut update = Self {
+            offset: 0,
+            size: 0,
+            received: 0,
+            key,
+            signature: Vec::new(),
+            verifier: None,
+            sector: Vec::new(),
+        };
+        (update.offset, update.size) = spare_partition()?;
+        info!(
+            "Writing an update to 0x{:x}, {} bytes at most",
+            update.offset, update.size
+        );
+
+        Ok(update)
+    }
+
+    /// Take the next bytes of the upload
+    pub fn write(&mut self, mut bytes: &[u8]) -> Result<(), OtaError> {
+        // The motion may have been enabled since the upload started
+        check_motion_disabled()?;
+        if self.verifier.is_none() {
+            let missing = (SIGNATURE_LENGTH - self.signature.len()).min(bytes.len());
+            let (signature, rest) = bytes.split_at(missing);
+            self.signature
+                .extend_from_slice(signature)
+                .expect("Limited to the missing bytes");
+            bytes = rest;
+
+            if self.signature.is_full() {
+                let signature =
+                    Signature::from_slice(&self.signature).map_err(|_| OtaError::BadSignature)?;
+                let verifier = self
+                    .key
+                    .verify_incremental(&signature)
+                    .map_err(|_| OtaError::BadSignature)?;
+                self.verifier = Some(verifier);
+            }
+        }
+        let Some(verifier) = &mut self.verifier else {
+            return Ok(());
+        };
+
+        if self.received as usize + bytes.len() > self.size as usize {
+            return Err(OtaError::TooLarge);
+        }
+        verifier.absorb(bytes);
+        self.received += bytes.len() as u32;
+
+        while !bytes.is_empty() {
+            let length = (FLASH_SECTOR_SIZE - self.sector.len()).min(bytes.len());
+            let (chunk, rest) = bytes.split_at(length);
+            self.sector
+                .extend_from_slice(chunk)
+                .expect("Limited to the space left");
+            bytes = rest;
+            if self.sector.is_full() {
+                self.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the signature and boot the update on the next reset
+    pub fn finish(mut self) -> Result<(), OtaError> {
+        self.flush()?;
+        let verifier = self.verifier.take().ok_or(OtaError::Incomplete)?;
+        if verifier.verify().is_err() {
+            error!("The update is not signed with the built in key");
+            return Err(OtaError::BadSignature);
+        }
+
+        let mut magic = [0u8; 1];
+        with_flash(|flash| {
+            flash
+                .read(self.offset, &mut magic)
+                .map_err(|err| error!("Could not read the update back {:?}", err))
+        })
+        .ok_or(OtaError::Flash)?
+        .map_err(|_| OtaError::Flash)?;
+        if magic[0] != IMAGE_MAGIC {
+            return Err(OtaError::NotAnImage);
+        }
+        // Never reboot out of a move
+        check_motion_disabled()?;
+
+        with_ota_updater(|ota| {
+            ota.activate_next_partition()?;
+            ota.set_current_ota_state(OtaImageState::New)
+        })?;
+        save_ota_trial(OtaTrial::Activated).map_err(|_| OtaError::Flash)?;
+        info!("Update of {} bytes written and activated", self.received);
+
+        Ok(())
+    }
+
+    /// Write the buffered bytes to the partition
+    fn flush(&mut self) -> Result<(), OtaError> {
+        if self.sector.is_empty() {
+            return Ok(());
+        }
+
+        // Where the buffered bytes start in the partition
+        let position = self.received - self.sector.len() as u32;
+        with_flash(|flash| {
+            flash
+                .write(self.offset + position, &self.sector)
+                .map_err(|err| error!("Could not write the update {:?}", err))
+        })
+        .ok_or(OtaError::Flash)?
+        .map_err(|_| OtaError::Flash)?;
+        self.sector.clear();
+
+        Ok(())
+    }
+}
+
+impl Drop for OtaUpdate {
+    fn drop(&mut self) {
+        IN_PROGRESS.store(false, Ordering::Release);
+    }
+}
+
+/// Updates are refused while the machine may move
+fn check_motion_disabled() -> Result<(), OtaError> {
+    if get_motion_state().motion_enabled {
+        warn!("Refusing the update while the motion is enabled");
+        return Err(OtaError::Busy);
+    }
+    Ok(())
+}
+
+/// Reboot after the response was sent, e.g. into the activated update
+pub async fn reboot() -> ! {
+    Timer::after(Duration::from_millis(500)).await;
+    info!("Rebooting");
+    esp_hal::system::software_reset()
+}
+
+/// Roll back an update that did not confirm its last boot. Call at boot after the settings
+pub fn check_boot() {
+    match load_ota_trial() {
+        OtaTrial::None => {}
+        OtaTrial::Activated => {
+            info!("Trying out the updated firmware");
+            if let Err(err) = save_ota_trial(OtaTrial::Booted) {
+                report_error(err);
+            }
+        }
+        OtaTrial::Booted => {
+            error!("The updated firmware did not confirm its boot. Rolling back");
+            if let Err(err) = save_ota_trial(OtaTrial::None) {
+                report_error(err);
+            }
+            let result = with_ota_updater(|ota| {
+                ota.set_current_ota_state(OtaImageState::Invalid)?;
+                ota.activate_next_partition()?;
+                ota.set_current_ota_state(OtaImageState::Valid)
+            });
+            match result {
+                Ok(()) => esp_hal::system::software_reset(),
+                Err(err) => report_error(err),
+            }
+        }
+    }
+}
+
+/// Task to confirm an update once it ran long enough
+#[embassy_executor::task]
+pub async fn ota_confirm_task() {
+    if load_ota_trial() != OtaTrial::Booted {
+        return;
+    }
+
+    Timer::after(Duration::from_millis(OTA_CONFIRM_DELAY_MS)).await;
+    if let Err(err) = save_ota_trial(OtaTrial::None) {
+        report_error(err);
+        return;
+    }
+    match with_ota_updater(|ota| ota.set_current_ota_state(OtaImageState::Valid)) {
+        Ok(()) => info!("Confirmed the updated firmware"),
+        Err(err) => report_error(err),
+    }
+}
+
+/// The offset and the size of the app partition that is not running
+fn spare_partition() -> Result<(u32, u32), OtaError> {
+    let spare =
+        with_ota_updater(|ota| ota.selected_partition()).map(|selected| match selected {
+            AppPartitionSubType::Ota0 => AppPartitionSubType::Ota1,
+            _ => AppPartitionSubType::Ota0,
+        })?;
+
+    with_flash(|flash| {
+        let mut table_buffer = [0u8; PARTITION_TABLE_MAX_LEN];
+        let table = partitions::read_partition_table(flash, &mut table_buffer)
+            .map_err(|err| error!("Failed to read the partition table {:?}", err))?;
+        match table.find_partition(PartitionType::App(spare)) {
+            Ok(Some(partition)) => Ok((partition.offset(), partition.len())),
+            Ok(None) => {
+                error!("No spare app partition. Flash with the OTA partition table");
+                Err(())
+            }
+            Err(err) => {
+                error!("Failed to find the spare app partition {:?}", err);
+                Err(())
+            }
+        }
+    })
+    .ok_or(OtaError::Flash)?
+    .map_err(|_| OtaError::Flash)
+}
+
+/// Run `f` with the OTA data of the bootloader
+fn with_ota_updater<R>(
+    f: impl FnOnce(&mut OtaUpdater<'_, FlashStorage<'static>>) -> Result<R, partitions::Error>,
+) -> Result<R, OtaError> {
+    with_flash(|flash| {
+        let mut table_buffer = [0u8; PARTITION_TABLE_MAX_LEN];
+        OtaUpdater::new(flash, &mut table_buffer).and_then(f)
+    })
+    .ok_or(OtaError::Flash)?
+    .map_err(|err| {
+        error!("Failed to update the OTA data {:?}", err);
+        OtaError::Flash
+    })
+}
+
+/// 64 hex digits
+fn parse_key(hex: &str) -> Option<PublicKey> {
+    let mut key = [0u8; PublicKey::BYTES];
+    if hex.len() != key.len() * 2 {
+        warn!("The OTA public key is not {} hex digits", key.len() * 2);
+        return None;
+    }
+    for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(core::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+
+    PublicKey::from_slice(&key).ok()
+}
@@ -0,0 +1,103 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_time::{Duration, Ticker};
+use esp_hal::{
+    analog::adc::{Adc, AdcChannel, AdcConfig, AdcPin, Attenuation, RegisterAccess},
+    Blocking,
+};
+use log::{error, info};
+
+use crate::config::{
+    SUPPLY_DIVIDER_RATIO, SUPPLY_EMPTY_MV, SUPPLY_FULL_MV, SUPPLY_MEASURE_INTERVAL_MS,
+};
+
+// The voltage at the pin for the highest reading with 11 dB attenuation. Not calibrated
+const ADC_FULL_SCALE_MV: u32 = 3100;
+const ADC_MAX_RAW: u32 = 4095;
+// How many times to poll for a conversion before giving up on a measurement
+const ADC_MAX_POLLS: u32 = 1000;
+// The supply was never measured
+const NOT_MEASURED: u32 = 0;
+
+// For the battery service
+static SUPPLY_MV: AtomicU32 = AtomicU32::new(NOT_MEASURED);
+
+/// Something that can measure the supply rail
+/// Lets the task take the ADC without knowing the pin of the board
+pub trait SupplySense {
+    /// The supply voltage in mV. None if the measurement failed
+    fn read_supply_mv(&mut self) -> Option<u32>;
+}
+
+/// Measures the supply through the voltage divider on an ADC pin
+// None of the stock boards have this
+#[allow(dead_code)]
+pub struct AdcSupplySense<'d, PIN, ADCI> {
+    adc: Adc<'d, ADCI, Blocking>,
+    pin: AdcPin<PIN, ADCI>,
+}
+
+#[allow(dead_code)]
+impl<'d, PIN, ADCI> AdcSupplySense<'d, PIN, ADCI>
+where
+    PIN: AdcChannel,
+    ADCI: RegisterAccess + 'd,
+{
+    pub fn new(adc: ADCI, pin: PIN) -> Self {
+        let mut config = AdcConfig::new();
+        let pin = config.enable_pin(pin, Attenuation::_11dB);
+        Self {
+            adc: Adc::new(adc, config),
+            pin,
+        }
+    }
+}
+
+impl<'d, PIN, ADCI> SupplySense for AdcSupplySense<'d, PIN, ADCI>
+where
+    PIN: AdcChannel,
+    ADCI: RegisterAccess + 'd,
+{
+    fn read_supply_mv(&mut self) -> Option<u32> {
+        for _ in 0..ADC_MAX_POLLS {
+            if let Ok(raw) = self.adc.read_oneshot(&mut self.pin) {
+                let pin_mv = raw as u32 * ADC_FULL_SCALE_MV / ADC_MAX_RAW;
+                return Some(pin_mv * SUPPLY_DIVIDER_RATIO);
+            }
+        }
+        None
+    }
+}
+
+/// The last measured supply voltage in mV. None if the board does not measure it
+pub fn get_supply_mv() -> Option<u32> {
+    match SUPPLY_MV.load(Ordering::Acquire) {
+        NOT_MEASURED => None,
+        supply_mv => Some(supply_mv),
+    }
+}
+
+/// The supply voltage between `SUPPLY_EMPTY_MV` and `SUPPLY_FULL_MV` in %
+pub fn get_battery_level_pct() -> Option<u8> {
+    let supply_mv = get_supply_mv()?;
+    let level =
+        supply_mv.saturating_sub(SUPPLY_EMPTY_MV) * 100 / (SUPPLY_FULL_MV - SUPPLY_EMPTY_MV);
+    Some(level.min(100) as u8)
+}
+
+/// Task to measure the supply every `SUPPLY_MEASURE_INTERVAL_MS`
+#[embassy_executor::task]
+pub async fn supply_monitor_task(sense: &'static mut dyn SupplySense) {
+    info!("Task Supply Monitor Started");
+
+    let mut ticker = Ticker::every(Duration::from_millis(SUPPLY_MEASURE_INTERVAL_MS));
+    loop {
+        match sense.read_supply_mv() {
+            // A reading of 0 would look like no measurement
+            Some(supply_mv) => SUPPLY_MV.store(supply_mv.max(1), Ordering::Release),
+            None => error!("Could not measure the supply voltage"),
+        }
+
+        ticker.next().await;
+    }
+}
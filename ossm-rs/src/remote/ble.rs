@@ -1,36 +1,261 @@
 use core::{
-    fmt::Write,
-    sync::atomic::{AtomicBool, Ordering},
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
 };
 
-use crate::config::{MAX_COMMAND_LENGTH, MAX_PATTERN_LENGTH, MAX_STATE_LENGTH};
-use log::{error, info};
-use embassy_futures::select::{select, Either};
-use embassy_time::{Duration, Ticker, Timer};
+use crate::config::{
+    BLE_KEEPALIVE_TIMEOUT_MS, CONNECTIONS_MAX, LOG_POLL_MS, MAX_BINARY_COMMAND_LENGTH,
+    MAX_BINARY_STATE_LENGTH, MAX_BPM, MAX_CAPABILITIES_LENGTH, MAX_COMMAND_LENGTH,
+    MAX_CONFIG_LENGTH, MAX_CUSTOM_PATTERN_LENGTH, MAX_DEBUG_SAMPLE_LENGTH, MAX_DEVICE_INFO_LENGTH,
+    MAX_DIAGNOSTICS_LENGTH, MAX_DWELL_MS, MAX_EVENT_LENGTH, MAX_JITTER_PCT, MAX_LOG_LINE_LENGTH,
+    MAX_PATTERN_LENGTH, MAX_PATTERN_PARAMS_LENGTH, MAX_PLAYLIST_LENGTH, MAX_PRESETS_LENGTH,
+    MAX_PROFILES_LENGTH, MAX_PROTOCOL_LENGTH, MAX_RECORD_LENGTH, MAX_SETTINGS_LENGTH,
+    MAX_SHUFFLE_INTERVAL_MIN, MAX_STATE_LENGTH, MAX_WIFI_COMMAND_LENGTH, MIN_BPM,
+    STATE_KEEPALIVE_MS,
+};
+#[cfg(not(feature = "generic_modbus"))]
+use crate::motion::calibration::request_travel_calibration;
+use crate::{
+    backup::{get_settings_json, restore_settings},
+    board::BOARD_NAME,
+    error::{report_error, BackupError, RemoteError},
+    logger::{get_oldest_log_sequence, next_log_line},
+    motion::{
+        debug::{next_debug_sample, set_debug_streaming},
+        homing::{request_direction_reversed, request_homing},
+    },
+    network::wifi::{get_wifi_status_json, process_wifi_command},
+    ota::reboot,
+    power::get_battery_level_pct,
+    remote::{
+        claim_control,
+        esp_now::{open_pairing_window, unpair_remotes},
+        get_control_source, release_control, ControlSource,
+    },
+    storage::{
+        load_mechanics, save_max_travel_mm, save_mechanics, save_presets, save_profiles,
+        save_reverse_direction, save_session_limits, save_thermal_limits,
+    },
+};
+use log::{debug, error, info};
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, select4, Either, Either4};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant, Timer};
 use esp_radio::ble::controller::BleConnector;
-use heapless::String;
+use heapless::{String, Vec};
+use static_cell::StaticCell;
 use trouble_host::prelude::*;
 
 use ossm_motion::{
-    motion::motion_state::{
-        get_motion_state, set_motion_depth_pct, set_motion_enabled, set_motion_length_pct,
-        set_motion_pattern, set_motion_sensation_pct, set_motion_velocity_pct,
+    binary::{decode_command, encode_response},
+    diagnostics::get_diagnostics_json,
+    event::{get_event_sequence, next_event},
+    float::Real,
+    motion::{
+        demo::start_demo,
+        funscript::{
+            add_funscript_actions, clear_funscript, pause_funscript, play_funscript,
+            seek_funscript, stop_funscript, FunscriptError,
+        },
+        motion_state::{
+            get_motion_state, set_motion_acceleration_pct, set_motion_bpm, set_motion_depth_pct,
+            set_motion_enabled, set_motion_jerk_pct, set_motion_length_pct, set_motion_pattern,
+            set_motion_sensation_pct, set_motion_velocity_pct,
+        },
+        playlist::{
+            add_playlist_entry, clear_playlist, get_playlist_json, remove_playlist_entry,
+            set_playlist_entry, set_playlist_repeat, skip_playlist_entry, start_playlist,
+            stop_playlist, PlaylistEntry, PlaylistError,
+        },
+        shuffle::set_shuffle_interval_min,
+        stream::{get_velocity_envelope, stream_position, stream_target},
+    },
+    motion_control::{
+        arming::{arm, disarm, is_armed},
+        emergency_stop, hold,
+        mechanics::{get_mechanics, MECHANICS_KEYS},
+        pause, recorder, resume,
+    },
+    pattern::{
+        custom::{get_custom_pattern_json, reset_custom_pattern, set_custom_expression},
+        dwell::set_dwell_ms,
+        jitter::set_jitter_pct,
+        params::{get_pattern_params_json, set_pattern_param},
+        PatternExecutor,
     },
-    pattern::PatternExecutor,
+    preset::{
+        delete_preset, get_presets, get_presets_json, load_next_preset, load_preset, save_preset,
+    },
+    profile::{
+        get_active_profile, get_all_profiles_json, get_profiles, select_profile,
+        set_profile_limits, set_profile_name, set_profile_pin, set_profile_preferences,
+        ProfileLimits,
+    },
+    runtime_config::{
+        get_config_json, get_state_interval_ms, get_state_on_change, set_config_value,
+    },
+    session_limits::{
+        get_session_limits, get_session_limits_pin, set_session_limits, set_session_limits_pin,
+        SessionLimits,
+    },
+    thermal::{get_thermal_limits, THERMAL_KEYS},
+    time::AtomicTimestamp,
+    validation::ValueError,
 };
 
 const SERVICE_UUID: Uuid = uuid!("522b443a-4f53-534d-0001-420badbabe69");
 const PRIMARY_COMMAND_UUID: Uuid = uuid!("522b443a-4f53-534d-1000-420badbabe69");
 const SPEED_KNOB_UUID: Uuid = uuid!("522b443a-4f53-534d-1010-420badbabe69");
+const STREAM_UUID: Uuid = uuid!("522b443a-4f53-534d-1020-420badbabe69");
+const FUNSCRIPT_UUID: Uuid = uuid!("522b443a-4f53-534d-1030-420badbabe69");
+const STOP_UUID: Uuid = uuid!("522b443a-4f53-534d-1040-420badbabe69");
+const BINARY_COMMAND_UUID: Uuid = uuid!("522b443a-4f53-534d-1050-420badbabe69");
+const POSITION_UUID: Uuid = uuid!("522b443a-4f53-534d-1060-420badbabe69");
 const CURRENT_STATE_UUID: Uuid = uuid!("522b443a-4f53-534d-2000-420badbabe69");
+const EVENT_UUID: Uuid = uuid!("522b443a-4f53-534d-2010-420badbabe69");
+const BINARY_STATE_UUID: Uuid = uuid!("522b443a-4f53-534d-2020-420badbabe69");
 const PATTERN_LIST_UUID: Uuid = uuid!("522b443a-4f53-534d-3000-420badbabe69");
 const PATTERN_DESCRIPTION_UUID: Uuid = uuid!("522b443a-4f53-534d-3010-420badbabe69");
+const CUSTOM_PATTERN_UUID: Uuid = uuid!("522b443a-4f53-534d-3020-420badbabe69");
+const PLAYLIST_UUID: Uuid = uuid!("522b443a-4f53-534d-3030-420badbabe69");
+const PATTERN_PARAMS_UUID: Uuid = uuid!("522b443a-4f53-534d-3040-420badbabe69");
+const PROFILE_LIST_UUID: Uuid = uuid!("522b443a-4f53-534d-4000-420badbabe69");
+const PRESET_LIST_UUID: Uuid = uuid!("522b443a-4f53-534d-4010-420badbabe69");
+const CAPABILITIES_UUID: Uuid = uuid!("522b443a-4f53-534d-5000-420badbabe69");
+const PROTOCOL_UUID: Uuid = uuid!("522b443a-4f53-534d-5010-420badbabe69");
+const DIAGNOSTICS_UUID: Uuid = uuid!("522b443a-4f53-534d-5020-420badbabe69");
+const CONFIG_UUID: Uuid = uuid!("522b443a-4f53-534d-6000-420badbabe69");
+const SETTINGS_UUID: Uuid = uuid!("522b443a-4f53-534d-6010-420badbabe69");
+const RECORDER_UUID: Uuid = uuid!("522b443a-4f53-534d-7000-420badbabe69");
+const DEBUG_STREAM_UUID: Uuid = uuid!("522b443a-4f53-534d-7010-420badbabe69");
+const LOG_UUID: Uuid = uuid!("522b443a-4f53-534d-7020-420badbabe69");
+const WIFI_UUID: Uuid = uuid!("522b443a-4f53-534d-8000-420badbabe69");
 
-static CONNECTED: AtomicBool = AtomicBool::new(false);
+// How long to wait before advertising again after the BLE host failed
+const BLE_RETRY_DELAY_MS: u64 = 1000;
+// How often a connection checks whether it got the debug or the log stream
+const DEBUG_OWNER_POLL_MS: u64 = 100;
+// How often a connection checks for new events
+const EVENT_POLL_MS: u64 = 50;
+// How often a connection checks whether the state changed when only changes are notified
+const STATE_POLL_MS: u64 = 20;
+// No connection holds the resource
+const NO_OWNER: u32 = 0;
+// Fits any u32
+const SPEED_KNOB_LENGTH: usize = 16;
+// A position in mm optionally followed by a velocity in mm/s, both f32 little endian
+const POSITION_PACKET_LENGTH: usize = 8;
+// Fits the level in front of the longest log line
+const LOG_MESSAGE_LENGTH: usize = MAX_LOG_LINE_LENGTH + 8;
+// Reported by the device information service
+const MODEL_NUMBER: &str = "OSSM";
+// Increased when commands are removed or change their meaning
+// New ones only show up in the lists of the protocol descriptor
+// 2: The machine boots disarmed and the motion commands fail until `go:arm`
+// 3: The `fault` of the state is the most severe active fault code
+const PROTOCOL_VERSION: u32 = 3;
+// The keys of `set:<key>:<value>` and the actions of `go:<action>` handled by `process_command`
+const SET_KEYS: [&str; 12] = [
+    "speed",
+    "stroke",
+    "depth",
+    "sensation",
+    "pattern",
+    "accel",
+    "jerk",
+    "bpm",
+    "shuffle",
+    "dwell",
+    "jitter",
+    "param",
+];
+// `go:disarm` and the older `go:rearm` are handled too but do not fit into the descriptor
+const GO_ACTIONS: [&str; 14] = [
+    "simplePenetration",
+    "strokeEngine",
+    "pause",
+    "resume",
+    "stop",
+    "arm",
+    "menu",
+    "hold",
+    "demo",
+    "calibrate",
+    "home",
+    "pair",
+    "unpair",
+    "keepalive",
+];
+// The optional parts of the protocol with their own characteristics
+// The descriptor is full with them. Remotes find the ones added since by their characteristic
+const FEATURES: [&str; 16] = [
+    "knob",
+    "stream",
+    "funscript",
+    "custom",
+    "playlist",
+    "params",
+    "profiles",
+    "config",
+    "recorder",
+    "debug",
+    "events",
+    "wifi",
+    "log",
+    "stop",
+    "binary",
+    "pos",
+];
+
+type BleStack = Stack<'static, ExternalController<BleConnector<'static>, 20>, DefaultPacketPool>;
+
+static SERVER: StaticCell<Server<'static>> = StaticCell::new();
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+// Signalled when a connection ends so that advertising starts again if all were taken
+static CONNECTION_CLOSED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+// Identifies the connections for the resources only one of them can hold. Never NO_OWNER
+static NEXT_CONNECTION_ID: AtomicU32 = AtomicU32::new(NO_OWNER + 1);
+// Streaming targets, a funscript, the debug stream and the log stream belong to the connection
+// that used them first until it disconnects. Other connections are answered with `busy`
+static STREAM_OWNER: AtomicU32 = AtomicU32::new(NO_OWNER);
+static FUNSCRIPT_OWNER: AtomicU32 = AtomicU32::new(NO_OWNER);
+static DEBUG_STREAM_OWNER: AtomicU32 = AtomicU32::new(NO_OWNER);
+static LOG_STREAM_OWNER: AtomicU32 = AtomicU32::new(NO_OWNER);
+// The connection whose command put BLE in control of the motion
+static CONTROL_OWNER: AtomicU32 = AtomicU32::new(NO_OWNER);
+// Whether it wrote `go:keepalive` and when it last wrote anything
+static CONTROL_OWNER_SUPERVISED: AtomicBool = AtomicBool::new(false);
+static CONTROL_OWNER_LAST_WRITE: AtomicTimestamp = AtomicTimestamp::never();
 
 #[gatt_server]
 struct Server {
     ossm_service: OssmService,
+    device_information: DeviceInformationService,
+    battery_service: BatteryService,
+}
+
+// Lets generic BLE apps identify the machine. Set once when the server is created
+#[gatt_service(uuid = service::DEVICE_INFORMATION)]
+struct DeviceInformationService {
+    #[characteristic(uuid = characteristic::MODEL_NUMBER_STRING, read)]
+    model_number: String<MAX_DEVICE_INFO_LENGTH>,
+
+    // The git version of the firmware
+    #[characteristic(uuid = characteristic::FIRMWARE_REVISION_STRING, read)]
+    firmware_revision: String<MAX_DEVICE_INFO_LENGTH>,
+
+    // The board the firmware was built for
+    #[characteristic(uuid = characteristic::HARDWARE_REVISION_STRING, read)]
+    hardware_revision: String<MAX_DEVICE_INFO_LENGTH>,
+}
+
+// The supply voltage as a battery level in %. Notifies when it changes
+// Reads 100 on boards that do not measure the supply
+#[gatt_service(uuid = service::BATTERY)]
+struct BatteryService {
+    #[characteristic(uuid = characteristic::BATTERY_LEVEL, read, notify)]
+    level: u8,
 }
 
 #[gatt_service(uuid = SERVICE_UUID)]
@@ -38,26 +263,123 @@ struct OssmService {
     #[characteristic(uuid = PRIMARY_COMMAND_UUID, read, write)]
     primary_command: String<MAX_COMMAND_LENGTH>,
 
-    #[characteristic(uuid = SPEED_KNOB_UUID, read, write)]
-    speed_knob_characteristic: String<16>,
+    // The velocity in % as a decimal number. Written without going through the command parser
+    // Notifies the value whenever it changes, also by other remotes
+    #[characteristic(uuid = SPEED_KNOB_UUID, read, write, write_without_response, notify)]
+    speed_knob_characteristic: String<SPEED_KNOB_LENGTH>,
+
+    // Any single byte emergency stops the machine like `go:stop`. Handled as soon as the write
+    // arrives without going through the command parser
+    #[characteristic(uuid = STOP_UUID, write, write_without_response)]
+    stop: u8,
+
+    // The primary command encoded with postcard, see `ossm_motion::binary`
+    // Reads back the response as a postcard string
+    #[characteristic(uuid = BINARY_COMMAND_UUID, read, write)]
+    binary_command: Vec<u8, MAX_BINARY_COMMAND_LENGTH>,
+
+    // Streamed targets as `<position mm>:<duration ms>`
+    // Notifies how a target was adjusted if it could not be executed as sent
+    #[characteristic(uuid = STREAM_UUID, write, write_without_response, notify)]
+    stream: String<MAX_COMMAND_LENGTH>,
+
+    // Streamed positions as binary packets for scrubbing at up to 60 Hz
+    // Shares the owner with the stream characteristic. Only notifies when a position fails
+    #[characteristic(uuid = POSITION_UUID, write, write_without_response, notify)]
+    position: Vec<u8, POSITION_PACKET_LENGTH>,
+
+    // Uploads and controls a funscript. Notifies `ok:<command>` or `fail:<command>:<reason>`
+    // `add:` chunks are acknowledged with the number of actions as `ok:<count>`
+    #[characteristic(uuid = FUNSCRIPT_UUID, write, notify)]
+    funscript: String<MAX_COMMAND_LENGTH>,
 
     #[characteristic(uuid = CURRENT_STATE_UUID, read, notify)]
     current_state: String<MAX_STATE_LENGTH>,
 
+    // The state encoded with postcard. Notified together with the JSON one
+    #[characteristic(uuid = BINARY_STATE_UUID, read, notify)]
+    binary_state: Vec<u8, MAX_BINARY_STATE_LENGTH>,
+
+    // Notifies discrete events as JSON e.g. `{"event":"fault","fault":"estop"}`
+    #[characteristic(uuid = EVENT_UUID, notify)]
+    event: String<MAX_EVENT_LENGTH>,
+
     #[characteristic(uuid = PATTERN_LIST_UUID, read)]
     pattern_list: String<MAX_PATTERN_LENGTH>,
 
     #[characteristic(uuid = PATTERN_DESCRIPTION_UUID, read, write)]
     pattern_description: String<MAX_PATTERN_LENGTH>,
+
+    // Reads as JSON of the expressions of the custom pattern. Written as `<output>=<expression>`
+    // or `clear`. Notifies `ok:<output>` or `fail:<output>:<reason>`
+    #[characteristic(uuid = CUSTOM_PATTERN_UUID, read, write, notify)]
+    custom_pattern: String<MAX_CUSTOM_PATTERN_LENGTH>,
+
+    // Reads as JSON of the queued patterns. Written with the playlist commands
+    // Notifies `ok:<command>` or `fail:<command>:<reason>`
+    #[characteristic(uuid = PLAYLIST_UUID, read, write, notify)]
+    playlist: String<MAX_PLAYLIST_LENGTH>,
+
+    // Reads as JSON of the parameters of the current pattern with their range, default and value
+    // Set with `set:param:<idx>:<value>` on the primary command
+    #[characteristic(uuid = PATTERN_PARAMS_UUID, read)]
+    pattern_params: String<MAX_PATTERN_PARAMS_LENGTH>,
+
+    #[characteristic(uuid = PROFILE_LIST_UUID, read)]
+    profile_list: String<MAX_PROFILES_LENGTH>,
+
+    // Reads as JSON of the saved presets. Managed with `preset:` on the primary command
+    #[characteristic(uuid = PRESET_LIST_UUID, read)]
+    preset_list: String<MAX_PRESETS_LENGTH>,
+
+    #[characteristic(uuid = CAPABILITIES_UUID, read)]
+    capabilities: String<MAX_CAPABILITIES_LENGTH>,
+
+    // Reads as JSON of the protocol version, the commands, the number of patterns, the ranges
+    // of the values that are not in % and the optional features
+    #[characteristic(uuid = PROTOCOL_UUID, read)]
+    protocol: String<MAX_PROTOCOL_LENGTH>,
+
+    // Reads as JSON of the issues the checks of the config found at boot
+    #[characteristic(uuid = DIAGNOSTICS_UUID, read)]
+    diagnostics: String<MAX_DIAGNOSTICS_LENGTH>,
+
+    // Reads as JSON of the runtime tunables. Written as `<key>:<value>`
+    // Notifies `ok:<write>` or `fail:<write>[:<reason>]` like the primary command
+    #[characteristic(uuid = CONFIG_UUID, read, write, notify)]
+    config: String<MAX_CONFIG_LENGTH>,
+
+    // Reads as JSON of the stored settings without the passwords. Written with `clear`,
+    // `add:<part of a backup>` and `apply` to restore one, after which the machine reboots
+    // Notifies `ok:<write>` or `fail:<write>:<reason>`
+    #[characteristic(uuid = SETTINGS_UUID, read, write, notify)]
+    settings: String<MAX_SETTINGS_LENGTH>,
+
+    // Written with `dump` to have the recorded trajectory and faults notified one per line
+    // oldest first and followed by `end:<count>`
+    #[characteristic(uuid = RECORDER_UUID, write, notify)]
+    recorder: String<MAX_RECORD_LENGTH>,
+
+    // Written with `start` or `stop`. Notifies the trajectory while started
+    // as `<time ms>:<position mm>:<velocity mm/s>:<acceleration mm/s²>`
+    #[characteristic(uuid = DEBUG_STREAM_UUID, write, notify)]
+    debug_stream: String<MAX_DEBUG_SAMPLE_LENGTH>,
+
+    // Written with `start` or `stop`. Notifies the log as `<level> - <line>` while started
+    // beginning with the recent lines. The level is the `logLevel` of the config
+    #[characteristic(uuid = LOG_UUID, write, notify)]
+    log: String<LOG_MESSAGE_LENGTH>,
+
+    // Reads as JSON of the connection without the password
+    // Written with `connect:<ssid>:<password>` or `forget`. Notifies `ok:` or `fail:`
+    #[characteristic(uuid = WIFI_UUID, read, write, notify)]
+    wifi: String<MAX_WIFI_COMMAND_LENGTH>,
 }
 
 #[embassy_executor::task]
 pub async fn ble_events_task(
-    stack: &'static Stack<
-        'static,
-        ExternalController<BleConnector<'static>, 20>,
-        DefaultPacketPool,
-    >,
+    spawner: Spawner,
+    stack: &'static BleStack,
     mut peripheral: Peripheral<
         'static,
         ExternalController<BleConnector<'static>, 20>,
@@ -65,80 +387,228 @@ pub async fn ble_events_task(
     >,
 ) {
     info!("Starting advertising and GATT service");
-    let server = Server::new_with_config(GapConfig::Peripheral(PeripheralConfig {
-        name: "OSSM",
-        appearance: &appearance::motorized_device::GENERIC_MOTORIZED_DEVICE,
-    }))
-    .unwrap();
+    let server: &'static Server<'static> = SERVER.init(
+        Server::new_with_config(GapConfig::Peripheral(PeripheralConfig {
+            name: "OSSM",
+            appearance: &appearance::motorized_device::GENERIC_MOTORIZED_DEVICE,
+        }))
+        .expect("Failed to create the GATT server"),
+    );
+    set_device_information(server);
 
     loop {
-        match advertise("OSSM", &mut peripheral).await {
-            Ok(connection) => {
-                Timer::after_millis(100).await;
-
-                connection
-                    .set_phy(stack, PhyKind::Le2M)
-                    .await
-                    .expect("Could not set 2M PHY");
-
-                let connect_params = ConnectParams {
-                    min_connection_interval: Duration::from_micros(7500),
-                    max_connection_interval: Duration::from_micros(7500),
-                    ..Default::default()
-                };
-                connection
-                    .update_connection_params(stack, &connect_params)
-                    .await
-                    .expect("Failed to update connection params");
+        // Advertise again once a connection is free
+        while CONNECTIONS.load(Ordering::Acquire) >= CONNECTIONS_MAX {
+            CONNECTION_CLOSED.wait().await;
+        }
 
-                Timer::after_millis(100).await;
+        let connection = match advertise("OSSM", &mut peripheral).await {
+            Ok(connection) => connection,
+            Err(err) => {
+                error!("[adv] error: {:?}", err);
+                report_error(RemoteError::Ble);
+                Timer::after_millis(BLE_RETRY_DELAY_MS).await;
+                continue;
+            }
+        };
 
-                let phy = connection.read_phy(stack).await.unwrap();
-                let mtu = connection.att_mtu();
-                info!("PHY {:?} MTU {:?}", phy, mtu);
+        CONNECTIONS.fetch_add(1, Ordering::AcqRel);
+        // The pool has a task for each connection
+        if let Err(err) = spawner.spawn(connection_task(stack, server, connection)) {
+            error!("Could not start the connection task {:?}", err);
+            report_error(RemoteError::Ble);
+            CONNECTIONS.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
 
-                let gatt_connection = connection
-                    .with_attribute_server(&server)
-                    .expect("Could not transform connection into GATT connection");
+/// Serve one connected central until it disconnects
+#[embassy_executor::task(pool_size = CONNECTIONS_MAX)]
+async fn connection_task(
+    stack: &'static BleStack,
+    server: &'static Server<'static>,
+    connection: Connection<'static, DefaultPacketPool>,
+) {
+    let id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::AcqRel);
 
-                let events = gatt_events_task(&server, &gatt_connection);
-                let notify = state_notifications(&server, &gatt_connection);
+    Timer::after_millis(100).await;
 
-                match select(events, notify).await {
-                    Either::First(res) => {
-                        if let Err(err) = res {
-                            panic!("[gatt] error in events task: {:?}", err);
-                        }
-                    }
-                    Either::Second(res) => {
-                        if let Err(err) = res {
-                            panic!("[gatt] error in notify task: {:?}", err);
-                        }
-                    }
+    // The connection still works without the faster PHY and parameters
+    if let Err(err) = connection.set_phy(stack, PhyKind::Le2M).await {
+        error!("Could not set 2M PHY {:?}", err);
+    }
+
+    let connect_params = ConnectParams {
+        min_connection_interval: Duration::from_micros(7500),
+        max_connection_interval: Duration::from_micros(7500),
+        ..Default::default()
+    };
+    if let Err(err) = connection
+        .update_connection_params(stack, &connect_params)
+        .await
+    {
+        error!("Failed to update connection params {:?}", err);
+    }
+
+    Timer::after_millis(100).await;
+
+    match connection.read_phy(stack).await {
+        Ok(phy) => info!("PHY {:?} MTU {:?}", phy, connection.att_mtu()),
+        Err(err) => error!("Could not read the PHY {:?}", err),
+    }
+
+    match connection.with_attribute_server(server) {
+        Ok(gatt_connection) => {
+            let events = gatt_events_task(server, &gatt_connection, id);
+            let notify = state_notifications(server, &gatt_connection);
+            let debug = debug_notifications(server, &gatt_connection, id);
+            let log = log_notifications(server, &gatt_connection, id);
+            let streams = async {
+                match select(debug, log).await {
+                    Either::First(result) | Either::Second(result) => result,
                 }
+            };
+            let alerts = event_notifications(server, &gatt_connection);
+
+            match select4(events, notify, streams, alerts).await {
+                Either4::First(Err(err)) => {
+                    error!("[gatt] error in events task: {:?}", err);
+                    report_error(RemoteError::Ble);
+                }
+                Either4::Second(Err(err))
+                | Either4::Third(Err(err))
+                | Either4::Fourth(Err(err)) => {
+                    error!("[gatt] error in notify task: {:?}", err);
+                    report_error(RemoteError::Ble);
+                }
+                _ => {}
             }
-            Err(err) => {
-                panic!("[adv] error: {:?}", err);
-            }
+        }
+        Err(err) => {
+            error!(
+                "Could not transform connection into GATT connection {:?}",
+                err
+            );
+            report_error(RemoteError::Ble);
+        }
+    }
+
+    // The connection may have failed without a disconnect event
+    release_connection(id);
+}
+
+/// Process a command of the connection. `supervised` is set once it wrote `go:keepalive`
+fn run_command(command: &str, id: u32, supervised: &mut bool) -> String<MAX_COMMAND_LENGTH> {
+    *supervised |= command == "go:keepalive";
+    let response = process_command(command, ControlSource::Ble);
+    if changes_motion(command) {
+        took_control(id);
+    }
+    response
+}
+
+/// Remember the connection if its write put BLE in control of the motion
+fn took_control(id: u32) {
+    if get_control_source() == ControlSource::Ble {
+        CONTROL_OWNER.store(id, Ordering::Release);
+    }
+}
+
+/// Any write of the connection in control shows that it is still there
+fn keep_control(id: u32, supervised: bool) {
+    if CONTROL_OWNER.load(Ordering::Acquire) == id {
+        CONTROL_OWNER_SUPERVISED.store(supervised, Ordering::Release);
+        CONTROL_OWNER_LAST_WRITE.store_now();
+    }
+}
+
+/// Take a resource for the connection. True if it already holds it
+fn claim(owner: &AtomicU32, id: u32) -> bool {
+    match owner.compare_exchange(NO_OWNER, id, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => true,
+        Err(current) => current == id,
+    }
+}
+
+/// Give back everything the connection held and free its slot
+fn release_connection(id: u32) {
+    for owner in [
+        &STREAM_OWNER,
+        &FUNSCRIPT_OWNER,
+        &LOG_STREAM_OWNER,
+        &CONTROL_OWNER,
+    ] {
+        owner
+            .compare_exchange(id, NO_OWNER, Ordering::AcqRel, Ordering::Acquire)
+            .ok();
+    }
+    if DEBUG_STREAM_OWNER
+        .compare_exchange(id, NO_OWNER, Ordering::AcqRel, Ordering::Acquire)
+        .is_ok()
+    {
+        set_debug_streaming(false);
+    }
+
+    CONNECTIONS.fetch_sub(1, Ordering::AcqRel);
+    CONNECTION_CLOSED.signal(());
+}
+
+/// Fill in the device information. It does not change while running
+fn set_device_information(server: &Server<'_>) {
+    let service = &server.device_information;
+    let values = [
+        (&service.model_number, MODEL_NUMBER),
+        (&service.firmware_revision, env!("VERGEN_GIT_DESCRIBE")),
+        (&service.hardware_revision, BOARD_NAME),
+    ];
+    for (characteristic, value) in values {
+        let mut string: String<MAX_DEVICE_INFO_LENGTH> = String::new();
+        if string.push_str(value).is_err() {
+            report_error(RemoteError::ResponseTooLong);
+        }
+        if let Err(err) = server.set(characteristic, &string) {
+            error!("Failed to set the device information {:?}", err);
         }
     }
 }
 
+/// The battery level in %. Boards that do not measure the supply are treated as plugged in
+fn battery_level() -> u8 {
+    get_battery_level_pct().unwrap_or(100)
+}
+
+/// `fail:<write>:busy` for a write to a resource another connection holds
+fn busy_response<const N: usize>(write: &str) -> String<N> {
+    let mut response = String::new();
+    if write!(response, "fail:{}:busy", write).is_err() {
+        report_error(RemoteError::ResponseTooLong);
+    }
+    response
+}
+
 #[embassy_executor::task]
 pub async fn ble_runner_task(
     mut runner: Runner<'static, ExternalController<BleConnector<'static>, 20>, DefaultPacketPool>,
 ) {
     loop {
         if let Err(err) = runner.run().await {
-            panic!("[ble_task] error: {:?}", err);
+            error!("[ble_task] error: {:?}", err);
+            report_error(RemoteError::Ble);
+            Timer::after_millis(BLE_RETRY_DELAY_MS).await;
         }
     }
 }
 
+/// `id` identifies the connection for the resources only one connection can hold
 async fn gatt_events_task<P: PacketPool>(
     server: &Server<'_>,
     connection: &GattConnection<'_, '_, P>,
+    id: u32,
 ) -> Result<(), Error> {
+    // Set once the central wrote `go:keepalive`
+    let mut supervised = false;
+    // The settings backup written so far
+    let mut backup: String<MAX_SETTINGS_LENGTH> = String::new();
     let reason = loop {
         match connection.next().await {
             GattConnectionEvent::Disconnected { reason } => break reason,
@@ -151,12 +621,71 @@ async fn gatt_events_task<P: PacketPool>(
                             let state: String<MAX_STATE_LENGTH> = get_motion_state().as_json();
                             server.set(&server.ossm_service.current_state, &state)?;
                         }
+                        if event.handle() == server.ossm_service.binary_state.handle {
+                            let state = get_motion_state().as_binary();
+                            server.set(&server.ossm_service.binary_state, &state)?;
+                        }
+                        if event.handle() == server.ossm_service.speed_knob_characteristic.handle {
+                            let speed = speed_knob_value(get_motion_state().velocity);
+                            server.set(&server.ossm_service.speed_knob_characteristic, &speed)?;
+                        }
                         if event.handle() == server.ossm_service.pattern_list.handle {
                             let patterns = PatternExecutor::new().get_all_patterns_json();
                             server.set(&server.ossm_service.pattern_list, &patterns)?;
                         }
+                        if event.handle() == server.ossm_service.custom_pattern.handle {
+                            let custom_pattern = get_custom_pattern_json();
+                            server.set(&server.ossm_service.custom_pattern, &custom_pattern)?;
+                        }
+                        if event.handle() == server.ossm_service.playlist.handle {
+                            let playlist = get_playlist_json();
+                            server.set(&server.ossm_service.playlist, &playlist)?;
+                        }
+                        if event.handle() == server.ossm_service.pattern_params.handle {
+                            let params = get_pattern_params_json(get_motion_state().pattern);
+                            server.set(&server.ossm_service.pattern_params, &params)?;
+                        }
+                        if event.handle() == server.ossm_service.profile_list.handle {
+                            let profiles = get_all_profiles_json();
+                            server.set(&server.ossm_service.profile_list, &profiles)?;
+                        }
+                        if event.handle() == server.ossm_service.preset_list.handle {
+                            let presets = get_presets_json();
+                            server.set(&server.ossm_service.preset_list, &presets)?;
+                        }
+                        if event.handle() == server.ossm_service.capabilities.handle {
+                            let capabilities = get_velocity_envelope().as_json();
+                            server.set(&server.ossm_service.capabilities, &capabilities)?;
+                        }
+                        if event.handle() == server.ossm_service.protocol.handle {
+                            server.set(&server.ossm_service.protocol, &protocol_json())?;
+                        }
+                        if event.handle() == server.ossm_service.diagnostics.handle {
+                            let diagnostics = get_diagnostics_json();
+                            server.set(&server.ossm_service.diagnostics, &diagnostics)?;
+                        }
+                        if event.handle() == server.ossm_service.config.handle {
+                            let config = get_config_json();
+                            server.set(&server.ossm_service.config, &config)?;
+                        }
+                        if event.handle() == server.ossm_service.settings.handle {
+                            let settings = get_settings_json();
+                            server.set(&server.ossm_service.settings, &settings)?;
+                        }
+                        if event.handle() == server.ossm_service.wifi.handle {
+                            let status = get_wifi_status_json();
+                            server.set(&server.ossm_service.wifi, &status)?;
+                        }
+                        if event.handle() == server.battery_service.level.handle {
+                            server.set(&server.battery_service.level, &battery_level())?;
+                        }
                     }
                     GattEvent::Write(event) => {
+                        if event.handle() == server.ossm_service.stop.handle {
+                            emergency_stop();
+                            release_control();
+                            info!("[gatt] Emergency stop");
+                        }
                         write = true;
                         event_handle = event.handle();
                     }
@@ -177,30 +706,248 @@ async fn gatt_events_task<P: PacketPool>(
                         let command: String<MAX_COMMAND_LENGTH> =
                             server.get(&server.ossm_service.primary_command)?;
 
-                        process_command(&command, server);
+                        let response = run_command(&command, id, &mut supervised);
+                        server.set(&server.ossm_service.primary_command, &response)?;
+                    }
+                    if event_handle == server.ossm_service.binary_command.handle {
+                        let bytes: Vec<u8, MAX_BINARY_COMMAND_LENGTH> =
+                            server.get(&server.ossm_service.binary_command)?;
+
+                        let command = decode_command(&bytes)
+                            .ok()
+                            .and_then(|command| command.to_text());
+                        let response = match command {
+                            Some(command) => run_command(&command, id, &mut supervised),
+                            None => {
+                                error!("Invalid binary command {:?}", bytes);
+                                String::try_from("fail:binary").expect("Fits")
+                            }
+                        };
+                        let response = encode_response(&response);
+                        server.set(&server.ossm_service.binary_command, &response)?;
+                    }
+                    if event_handle == server.ossm_service.speed_knob_characteristic.handle {
+                        let speed: String<SPEED_KNOB_LENGTH> =
+                            server.get(&server.ossm_service.speed_knob_characteristic)?;
+
+                        process_speed_knob(&speed);
+                        took_control(id);
+                        // Reads back the applied value
+                        let speed = speed_knob_value(get_motion_state().velocity);
+                        server.set(&server.ossm_service.speed_knob_characteristic, &speed)?;
+                    }
+                    if event_handle == server.ossm_service.stream.handle {
+                        let target: String<MAX_COMMAND_LENGTH> =
+                            server.get(&server.ossm_service.stream)?;
+
+                        let feedback = if claim(&STREAM_OWNER, id) {
+                            process_stream_target(&target)
+                        } else {
+                            Some(busy_response(&target))
+                        };
+                        if let Some(feedback) = feedback {
+                            server
+                                .ossm_service
+                                .stream
+                                .notify(connection, &feedback)
+                                .await?;
+                        }
+                    }
+                    if event_handle == server.ossm_service.position.handle {
+                        let packet: Vec<u8, POSITION_PACKET_LENGTH> =
+                            server.get(&server.ossm_service.position)?;
+
+                        let failure = if claim(&STREAM_OWNER, id) {
+                            process_position(&packet)
+                        } else {
+                            Some(busy_response("position"))
+                        };
+                        if let Some(failure) = failure {
+                            server
+                                .ossm_service
+                                .position
+                                .notify(connection, &failure)
+                                .await?;
+                        }
+                    }
+                    if event_handle == server.ossm_service.funscript.handle {
+                        let command: String<MAX_COMMAND_LENGTH> =
+                            server.get(&server.ossm_service.funscript)?;
+
+                        let response = if claim(&FUNSCRIPT_OWNER, id) {
+                            process_funscript_command(&command)
+                        } else {
+                            busy_response(&command)
+                        };
+                        server
+                            .ossm_service
+                            .funscript
+                            .notify(connection, &response)
+                            .await?;
+                    }
+                    if event_handle == server.ossm_service.config.handle {
+                        let command: String<MAX_CONFIG_LENGTH> =
+                            server.get(&server.ossm_service.config)?;
+
+                        let response = process_config_command(&command);
+                        server
+                            .ossm_service
+                            .config
+                            .notify(connection, &response)
+                            .await?;
+                    }
+                    if event_handle == server.ossm_service.settings.handle {
+                        let command: String<MAX_SETTINGS_LENGTH> =
+                            server.get(&server.ossm_service.settings)?;
+
+                        let (response, restored) = process_settings_command(&command, &mut backup);
+                        server
+                            .ossm_service
+                            .settings
+                            .notify(connection, &response)
+                            .await?;
+                        if restored {
+                            reboot().await;
+                        }
+                    }
+                    if event_handle == server.ossm_service.wifi.handle {
+                        let command: String<MAX_WIFI_COMMAND_LENGTH> =
+                            server.get(&server.ossm_service.wifi)?;
+
+                        let response = process_wifi_command(&command);
+                        server
+                            .ossm_service
+                            .wifi
+                            .notify(connection, &response)
+                            .await?;
+                    }
+                    if event_handle == server.ossm_service.recorder.handle {
+                        let command: String<MAX_RECORD_LENGTH> =
+                            server.get(&server.ossm_service.recorder)?;
+
+                        dump_recorder(&command, server, connection).await?;
+                    }
+                    if event_handle == server.ossm_service.debug_stream.handle {
+                        let command: String<MAX_DEBUG_SAMPLE_LENGTH> =
+                            server.get(&server.ossm_service.debug_stream)?;
+
+                        let response: Option<String<MAX_DEBUG_SAMPLE_LENGTH>> =
+                            match command.as_str() {
+                                "start" if claim(&DEBUG_STREAM_OWNER, id) => {
+                                    set_debug_streaming(true);
+                                    None
+                                }
+                                "start" => Some(busy_response(&command)),
+                                "stop" => {
+                                    if DEBUG_STREAM_OWNER
+                                        .compare_exchange(
+                                            id,
+                                            NO_OWNER,
+                                            Ordering::AcqRel,
+                                            Ordering::Acquire,
+                                        )
+                                        .is_ok()
+                                    {
+                                        set_debug_streaming(false);
+                                    }
+                                    None
+                                }
+                                _ => {
+                                    error!("Unknown debug stream command {}", command);
+                                    let mut response = String::new();
+                                    if write!(response, "fail:{}", command).is_err() {
+                                        report_error(RemoteError::ResponseTooLong);
+                                    }
+                                    Some(response)
+                                }
+                            };
+                        if let Some(response) = response {
+                            server
+                                .ossm_service
+                                .debug_stream
+                                .notify(connection, &response)
+                                .await?;
+                        }
+                    }
+                    if event_handle == server.ossm_service.log.handle {
+                        let command: String<LOG_MESSAGE_LENGTH> =
+                            server.get(&server.ossm_service.log)?;
+
+                        let response: Option<String<LOG_MESSAGE_LENGTH>> = match command.as_str() {
+                            "start" if claim(&LOG_STREAM_OWNER, id) => None,
+                            "start" => Some(busy_response(&command)),
+                            "stop" => {
+                                LOG_STREAM_OWNER
+                                    .compare_exchange(
+                                        id,
+                                        NO_OWNER,
+                                        Ordering::AcqRel,
+                                        Ordering::Acquire,
+                                    )
+                                    .ok();
+                                None
+                            }
+                            _ => {
+                                error!("Unknown log stream command {}", command);
+                                let mut response = String::new();
+                                if write!(response, "fail:{}", command).is_err() {
+                                    report_error(RemoteError::ResponseTooLong);
+                                }
+                                Some(response)
+                            }
+                        };
+                        if let Some(response) = response {
+                            server
+                                .ossm_service
+                                .log
+                                .notify(connection, &response)
+                                .await?;
+                        }
                     }
                     if event_handle == server.ossm_service.pattern_description.handle {
                         let command: String<MAX_PATTERN_LENGTH> =
                             server.get(&server.ossm_service.pattern_description)?;
 
-                        let description = if let Ok(index) = command.parse::<usize>() {
-                            PatternExecutor::new().get_pattern_description(index)
+                        let description = if let Ok(id) = command.parse::<u32>() {
+                            PatternExecutor::new().get_pattern_description(id)
                         } else {
                             let mut description: String<MAX_PATTERN_LENGTH> = String::new();
                             description
-                                .push_str("Could not parse pattern index")
+                                .push_str("Could not parse pattern ID")
                                 .expect("Always fits");
                             description
                         };
 
                         server.set(&server.ossm_service.pattern_description, &description)?;
                     }
+                    if event_handle == server.ossm_service.custom_pattern.handle {
+                        let command: String<MAX_CUSTOM_PATTERN_LENGTH> =
+                            server.get(&server.ossm_service.custom_pattern)?;
+
+                        let response = process_custom_pattern_command(&command);
+                        server
+                            .ossm_service
+                            .custom_pattern
+                            .notify(connection, &response)
+                            .await?;
+                    }
+                    if event_handle == server.ossm_service.playlist.handle {
+                        let command: String<MAX_PLAYLIST_LENGTH> =
+                            server.get(&server.ossm_service.playlist)?;
+
+                        let response = process_playlist_command(&command);
+                        server
+                            .ossm_service
+                            .playlist
+                            .notify(connection, &response)
+                            .await?;
+                    }
+                    keep_control(id, supervised);
                 }
             }
             _ => {} // ignore other Gatt Connection Events
         }
     };
-    CONNECTED.store(false, Ordering::Release);
     info!("[gatt] disconnected: {:?}", reason);
     Ok(())
 }
@@ -235,60 +982,219 @@ async fn advertise<'values, 'server, C: Controller>(
         .await?;
     info!("[adv] advertising");
     let conn = advertiser.accept().await?;
-    CONNECTED.store(true, Ordering::Release);
     info!("[adv] connection established");
     Ok(conn)
 }
 
+/// Notify the state every `stateIntervalMs` of the config. With `stateOnChange` only when it
+/// changed but at most that often, and every `STATE_KEEPALIVE_MS` otherwise
 async fn state_notifications<P: PacketPool>(
     server: &Server<'_>,
     connection: &GattConnection<'_, '_, P>,
 ) -> Result<(), Error> {
-    let mut ticker = Ticker::every(Duration::from_millis(500));
+    // The state last notified and when
+    let mut prev_state: Option<String<MAX_STATE_LENGTH>> = None;
+    let mut notified_at = Instant::now();
+    // The velocity the speed knob was last notified with
+    let mut prev_speed = None;
+    let mut prev_battery_level = None;
+    loop {
+        let motion_state = get_motion_state();
+        let state: String<MAX_STATE_LENGTH> = motion_state.as_json();
+        let interval = Duration::from_millis(get_state_interval_ms());
+        let due = match &prev_state {
+            None => true,
+            Some(_) if !get_state_on_change() => notified_at.elapsed() >= interval,
+            Some(prev_state) => {
+                notified_at.elapsed() >= Duration::from_millis(STATE_KEEPALIVE_MS)
+                    || (*prev_state != state && notified_at.elapsed() >= interval)
+            }
+        };
+        if due {
+            server
+                .ossm_service
+                .current_state
+                .notify(connection, &state)
+                .await?;
+            server
+                .ossm_service
+                .binary_state
+                .notify(connection, &motion_state.as_binary())
+                .await?;
+            prev_state = Some(state);
+            notified_at = Instant::now();
+        }
+        if prev_speed != Some(motion_state.velocity) {
+            let speed = speed_knob_value(motion_state.velocity);
+            server
+                .ossm_service
+                .speed_knob_characteristic
+                .notify(connection, &speed)
+                .await?;
+            prev_speed = Some(motion_state.velocity);
+        }
+        let level = battery_level();
+        if prev_battery_level != Some(level) {
+            server
+                .battery_service
+                .level
+                .notify(connection, &level)
+                .await?;
+            prev_battery_level = Some(level);
+        }
+
+        if get_state_on_change() {
+            Timer::after_millis(STATE_POLL_MS).await;
+        } else {
+            Timer::at(notified_at + interval).await;
+        }
+    }
+}
+
+/// Notify the trajectory samples of motion control while this connection has the debug stream
+async fn debug_notifications<P: PacketPool>(
+    server: &Server<'_>,
+    connection: &GattConnection<'_, '_, P>,
+    id: u32,
+) -> Result<(), Error> {
     loop {
-        let state: String<MAX_STATE_LENGTH> = get_motion_state().as_json();
+        // Only one connection takes the samples out of the queue
+        if DEBUG_STREAM_OWNER.load(Ordering::Acquire) != id {
+            Timer::after_millis(DEBUG_OWNER_POLL_MS).await;
+            continue;
+        }
+        let sample = next_debug_sample().await;
+        // Stopped while waiting
+        if DEBUG_STREAM_OWNER.load(Ordering::Acquire) != id {
+            continue;
+        }
+        let mut line: String<MAX_DEBUG_SAMPLE_LENGTH> = String::new();
+        if write!(line, "{}", sample).is_err() {
+            report_error(RemoteError::ResponseTooLong);
+            continue;
+        }
         server
             .ossm_service
-            .current_state
-            .notify(connection, &state)
+            .debug_stream
+            .notify(connection, &line)
             .await?;
-        ticker.next().await;
     }
 }
 
-fn process_command(command: &String<MAX_COMMAND_LENGTH>, server: &Server<'_>) {
-    info!("BLE Command {}", command);
+/// Notify the log lines while this connection has the log stream
+async fn log_notifications<P: PacketPool>(
+    server: &Server<'_>,
+    connection: &GattConnection<'_, '_, P>,
+    id: u32,
+) -> Result<(), Error> {
+    loop {
+        if LOG_STREAM_OWNER.load(Ordering::Acquire) != id {
+            Timer::after_millis(DEBUG_OWNER_POLL_MS).await;
+            continue;
+        }
+        // Start with what was logged before the stream was started
+        let mut sequence = get_oldest_log_sequence();
+        while LOG_STREAM_OWNER.load(Ordering::Acquire) == id {
+            while let Some((line, next)) = next_log_line(sequence) {
+                sequence = next;
+                let mut message: String<LOG_MESSAGE_LENGTH> = String::new();
+                write!(message, "{} - {}", line.level, line.text).expect("Sized for the line");
+                server.ossm_service.log.notify(connection, &message).await?;
+            }
+            Timer::after_millis(LOG_POLL_MS).await;
+        }
+    }
+}
+
+/// Notify the events published since the connection was made
+async fn event_notifications<P: PacketPool>(
+    server: &Server<'_>,
+    connection: &GattConnection<'_, '_, P>,
+) -> Result<(), Error> {
+    let mut sequence = get_event_sequence();
+    loop {
+        while let Some((event, next)) = next_event(sequence) {
+            sequence = next;
+            server
+                .ossm_service
+                .event
+                .notify(connection, &event.as_json())
+                .await?;
+        }
+        Timer::after_millis(EVENT_POLL_MS).await;
+    }
+}
+
+/// Apply a `set:`, `go:`, `profile:`, `preset:` or `limits:` command from `source` and return the
+/// response
+/// Also used by the console
+pub fn process_command(command: &str, source: ControlSource) -> String<MAX_COMMAND_LENGTH> {
+    // The log is streamed over BLE and syslog and the response read by every client
+    let shown = without_pins(command);
+    info!("{} command {}", source.name(), shown);
+
+    if changes_motion(command) && !claim_control(source) {
+        error!("The {} remote is in control", get_control_source().name());
+        return busy_response(&shown);
+    }
 
     let mut split_command = command.split(":");
 
     let mut fail = false;
+    // Reported back to the sender. The clamped value is still applied
+    let mut value_error: Option<ValueError> = None;
 
     if let Some(cmd) = split_command.next() {
         if let Some(action) = split_command.next() {
             match cmd {
+                // set:param:<idx>:<value> for the current pattern
+                "set" if action == "param" => {
+                    let index = split_command
+                        .next()
+                        .and_then(|index| index.parse::<usize>().ok());
+                    let value = split_command
+                        .next()
+                        .and_then(|value| value.parse::<Real>().ok());
+                    if let (Some(index), Some(value)) = (index, value) {
+                        let pattern = get_motion_state().pattern;
+                        if let Err(err) = set_pattern_param(pattern, index, value) {
+                            error!(
+                                "Parameter {} of pattern {} not accepted: {}",
+                                index, pattern, err
+                            );
+                            value_error = Some(err);
+                            fail = true;
+                        }
+                    } else {
+                        error!("Could not parse the pattern parameter");
+                        fail = true;
+                    }
+                }
                 "set" => {
                     if let Some(value) = split_command.next() {
                         if let Ok(value) = value.parse::<u32>() {
-                            match action {
-                                "speed" => {
-                                    set_motion_velocity_pct(value);
-                                }
-                                "stroke" => {
-                                    set_motion_length_pct(value);
-                                }
-                                "depth" => {
-                                    set_motion_depth_pct(value);
-                                }
-                                "sensation" => {
-                                    set_motion_sensation_pct(value);
-                                }
-                                "pattern" => {
-                                    set_motion_pattern(value);
-                                }
+                            let result = match action {
+                                "speed" => set_motion_velocity_pct(value),
+                                "stroke" => set_motion_length_pct(value),
+                                "depth" => set_motion_depth_pct(value),
+                                "sensation" => set_motion_sensation_pct(value),
+                                "pattern" => set_motion_pattern(value),
+                                "accel" => set_motion_acceleration_pct(value),
+                                "jerk" => set_motion_jerk_pct(value),
+                                "bpm" => set_motion_bpm(value),
+                                "shuffle" => set_shuffle_interval_min(value),
+                                "dwell" => set_dwell_ms(value),
+                                "jitter" => set_jitter_pct(value),
                                 _ => {
                                     error!("Invalid set command {}", action);
                                     fail = true;
+                                    Ok(())
                                 }
+                            };
+                            if let Err(err) = result {
+                                error!("Value {} for {} not accepted: {}", value, action, err);
+                                value_error = Some(err);
+                                fail = true;
                             }
                         } else {
                             error!("Could not parse set value");
@@ -300,22 +1206,93 @@ fn process_command(command: &String<MAX_COMMAND_LENGTH>, server: &Server<'_>) {
                     }
                 }
                 "go" => match action {
+                    "simplePenetration" | "strokeEngine" if !is_armed() => {
+                        error!("Arm the machine with go:arm first");
+                        fail = true;
+                    }
                     "simplePenetration" => {
                         set_motion_enabled(true);
                     }
                     "strokeEngine" => {
                         set_motion_enabled(true);
                     }
+                    "pause" => {
+                        pause();
+                    }
+                    "resume" => {
+                        resume();
+                    }
+                    "stop" => {
+                        emergency_stop();
+                        release_control();
+                    }
+                    // `rearm` is what older remotes send after an emergency stop
+                    "arm" | "rearm" => {
+                        if let Err(err) = arm() {
+                            error!("Could not arm {:?}", err);
+                            fail = true;
+                        }
+                    }
+                    "disarm" => {
+                        // The motion task turns the motion off
+                        disarm();
+                        release_control();
+                    }
                     "menu" => {
                         set_motion_enabled(false);
+                        release_control();
+                    }
+                    "hold" => {
+                        // Before disabling so that the machine is not retracted
+                        hold();
+                        set_motion_enabled(false);
+                        release_control();
                     }
+                    "demo" => {
+                        if !start_demo() {
+                            error!("The demo can only be started while armed and the motion is disabled");
+                            fail = true;
+                        }
+                    }
+                    // The generic servo can not sense the end of the rail
+                    #[cfg(not(feature = "generic_modbus"))]
+                    "calibrate" => {
+                        if let Err(err) = request_travel_calibration() {
+                            error!("Could not start the calibration {:?}", err);
+                            fail = true;
+                        }
+                    }
+                    "home" => match request_homing() {
+                        Ok(()) => release_control(),
+                        Err(err) => {
+                            error!("Could not home again {:?}", err);
+                            fail = true;
+                        }
+                    },
+                    "pair" => {
+                        open_pairing_window();
+                    }
+                    "unpair" => {
+                        unpair_remotes();
+                    }
+                    // Only keeps the BLE connection in control
+                    "keepalive" => {}
                     _ => {
                         error!("Invalid go command {}", action);
                         fail = true;
                     }
                 },
+                "profile" => {
+                    fail = !process_profile_command(action, split_command);
+                }
+                "preset" => {
+                    fail = !process_preset_command(action, split_command.next());
+                }
+                "limits" => {
+                    fail = !process_limits_command(action, split_command);
+                }
                 _ => {
-                    error!("Command neither set nor go");
+                    error!("Command neither set, go, profile, preset nor limits");
                     fail = true;
                 }
             }
@@ -331,24 +1308,722 @@ fn process_command(command: &String<MAX_COMMAND_LENGTH>, server: &Server<'_>) {
     let mut response_str: String<MAX_COMMAND_LENGTH> = String::new();
     if fail {
         response_str.write_str("fail:").expect("Should always fit");
-        if response_str.write_str(command.as_str()).is_err() {
+        if response_str.write_str(&shown).is_err() {
             response_str
                 .write_str("overflow")
                 .expect("Should always fit");
         }
+        // e.g. fail:set:speed:150:out_of_range:100
+        if let Some(value_error) = value_error {
+            if write!(response_str, ":{}", value_error).is_err() {
+                error!("The value error does not fit into the response");
+            }
+        }
     } else {
         response_str.write_str("ok:").expect("Should always fit");
-        if response_str.write_str(command.as_str()).is_err() {
+        if response_str.write_str(&shown).is_err() {
             response_str
                 .write_str("overflow")
                 .expect("Should always fit");
         }
     }
-    if let Err(err) = server.set(&server.ossm_service.primary_command, &response_str) {
-        error!("Failed to write the response to a set command {:?}", err);
+    response_str
+}
+
+/// The protocol descriptor for remotes to adapt to the firmware instead of assuming
+/// e.g. `{"version":1,"set":["speed",...],"go":[...],"patterns":13,"ranges":{"bpm":[5,240],...},"features":[...]}`
+fn protocol_json() -> String<MAX_PROTOCOL_LENGTH> {
+    let mut output = String::new();
+    if write_protocol(&mut output).is_err() {
+        report_error(RemoteError::ResponseTooLong);
+    }
+    output
+}
+
+fn write_protocol(output: &mut impl Write) -> fmt::Result {
+    write!(output, r#"{{"version":{PROTOCOL_VERSION},"set":"#)?;
+    write_list(output, &SET_KEYS)?;
+    write!(output, r#","go":"#)?;
+    write_list(output, &GO_ACTIONS)?;
+    write!(
+        output,
+        r#","patterns":{},"ranges":{{"bpm":[{MIN_BPM},{MAX_BPM}],"shuffle":[1,{MAX_SHUFFLE_INTERVAL_MIN}],"dwell":[0,{MAX_DWELL_MS}],"jitter":[0,{MAX_JITTER_PCT}]}},"features":"#,
+        PatternExecutor::new().get_pattern_count()
+    )?;
+    write_list(output, &FEATURES)?;
+    output.write_char('}')
+}
+
+/// A JSON array of strings
+fn write_list(output: &mut impl Write, items: &[&str]) -> fmt::Result {
+    output.write_char('[')?;
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            output.write_char(',')?;
+        }
+        write!(output, r#""{item}""#)?;
+    }
+    output.write_char(']')
+}
+
+/// Commands that change the motion and are arbitrated with the M5 remote
+/// Stopping is always accepted
+fn changes_motion(command: &str) -> bool {
+    let mut split_command = command.split(':');
+    match (split_command.next(), split_command.next()) {
+        (Some("set"), Some(_)) => true,
+        (Some("preset"), Some(action)) => matches!(action, "load" | "next"),
+        (Some("go"), Some(action)) => matches!(
+            action,
+            "simplePenetration" | "strokeEngine" | "pause" | "resume" | "demo"
+        ),
+        _ => false,
+    }
+}
+
+/// The value of the speed knob for a velocity in %
+fn speed_knob_value(velocity_pct: u32) -> String<SPEED_KNOB_LENGTH> {
+    let mut value = String::new();
+    write!(value, "{}", velocity_pct).expect("Always fits");
+    value
+}
+
+/// Set the velocity in % written to the speed knob
+/// Out of range values are clamped like `set:speed`. Ignored while the M5 remote is in control
+fn process_speed_knob(speed: &str) {
+    if !claim_control(ControlSource::Ble) {
+        error!("The {} remote is in control", get_control_source().name());
+        return;
+    }
+
+    match speed.trim().parse::<u32>() {
+        Ok(velocity_pct) => {
+            if let Err(err) = set_motion_velocity_pct(velocity_pct) {
+                error!("Speed knob value {} not accepted: {}", velocity_pct, err);
+            }
+        }
+        Err(_) => error!("Could not parse the speed knob value {}", speed),
+    }
+}
+
+/// Apply a `<key>:<value>` write to the config characteristic
+/// Returns the response to notify the client with
+pub fn process_config_command(command: &str) -> String<MAX_CONFIG_LENGTH> {
+    let mut split_command = command.split(":");
+    let key = split_command.next().unwrap_or_default();
+    let value = split_command
+        .next()
+        .and_then(|value| value.parse::<Real>().ok());
+
+    let result = match value {
+        // Saved by the firmware and only applied by homing again
+        Some(value) if key == "reverseDirection" => set_reverse_direction(value),
+        Some(value) if MECHANICS_KEYS.contains(&key) => save_mechanics_value(key, value),
+        Some(value) if THERMAL_KEYS.contains(&key) => save_thermal_value(key, value),
+        Some(value) => set_config_value(key, value),
+        None => {
+            error!("Could not parse the config value {}", command);
+            Err(ValueError::NotANumber)
+        }
+    };
+
+    // e.g. fail:maxPosition:500:out_of_range:190
+    let mut response_str: String<MAX_CONFIG_LENGTH> = String::new();
+    let written = match result {
+        Ok(()) => write!(response_str, "ok:{}", command),
+        Err(err) => {
+            error!("Config {} not accepted: {}", command, err);
+            write!(response_str, "fail:{}:{}", command, err)
+        }
+    };
+    if written.is_err() {
+        report_error(RemoteError::ResponseTooLong);
+    }
+
+    response_str
+}
+
+/// 0 for the direction of the stock machine and 1 to reverse it
+/// Turns the motion off to home again in the new direction
+fn set_reverse_direction(value: Real) -> Result<(), ValueError> {
+    if !value.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    let reversed = match value as u32 {
+        0 => false,
+        1 => true,
+        _ => return Err(ValueError::Unknown),
+    };
+
+    if let Err(err) = save_reverse_direction(reversed) {
+        report_error(err);
+    }
+    if let Err(err) = request_direction_reversed(reversed) {
+        // e.g. until re-armed after an emergency stop
+        error!("Could not home again {:?}. Applied by the next homing", err);
+    }
+    Ok(())
+}
+
+/// Save a value of the mechanics to use after the next reboot
+/// Forgets the calibrated travel if the steps per mm change as it would not fit anymore
+fn save_mechanics_value(key: &str, value: Real) -> Result<(), ValueError> {
+    let saved = load_mechanics().unwrap_or_else(get_mechanics);
+    let mut mechanics = saved;
+    let result = mechanics.set_value(key, value);
+    if let Err(ValueError::NotANumber | ValueError::Unknown) = result {
+        return result;
+    }
+
+    if mechanics.steps_per_mm() != saved.steps_per_mm() {
+        info!("Calibrate the travel again after the reboot");
+        if let Err(err) = save_max_travel_mm(0.0) {
+            report_error(err);
+        }
+    }
+    if let Err(err) = save_mechanics(&mechanics) {
+        report_error(err);
+    }
+    result
+}
+
+/// Apply a thermal threshold and save it to keep it after a reboot
+fn save_thermal_value(key: &str, value: Real) -> Result<(), ValueError> {
+    let result = set_config_value(key, value);
+    if let Err(ValueError::NotANumber | ValueError::Unknown) = result {
+        return result;
+    }
+
+    if let Err(err) = save_thermal_limits(&get_thermal_limits()) {
+        report_error(err);
+    }
+    result
+}
+
+/// Notify the records of the recorder if `command` is `dump`
+/// Recording stops until all of them were sent so that the order stays intact
+async fn dump_recorder<P: PacketPool>(
+    command: &str,
+    server: &Server<'_>,
+    connection: &GattConnection<'_, '_, P>,
+) -> Result<(), Error> {
+    let characteristic = &server.ossm_service.recorder;
+    let mut line: String<MAX_RECORD_LENGTH> = String::new();
+
+    if command != "dump" {
+        error!("Unknown recorder command {}", command);
+        if write!(line, "fail:{}", command).is_err() {
+            report_error(RemoteError::ResponseTooLong);
+        }
+        return characteristic.notify(connection, &line).await;
+    }
+
+    let count = recorder::freeze();
+    info!("Dumping {} records", count);
+    let mut result = Ok(());
+    for index in 0..count {
+        let Some(record) = recorder::get_record(index) else {
+            break;
+        };
+        line.clear();
+        if write!(line, "{}", record).is_err() {
+            report_error(RemoteError::ResponseTooLong);
+            continue;
+        }
+        result = characteristic.notify(connection, &line).await;
+        if result.is_err() {
+            break;
+        }
+    }
+    recorder::unfreeze();
+    result?;
+
+    line.clear();
+    write!(line, "end:{}", count).expect("Always fits");
+    characteristic.notify(connection, &line).await
+}
+
+/// Execute a streamed `<position mm>:<duration ms>` target
+/// Returns the feedback to notify the client with if the target was not executed as sent
+fn process_stream_target(target: &str) -> Option<String<MAX_COMMAND_LENGTH>> {
+    let mut feedback_str: String<MAX_COMMAND_LENGTH> = String::new();
+
+    let mut split_target = target.split(":");
+    let position = split_target
+        .next()
+        .and_then(|value| value.parse::<Real>().ok());
+    let duration_ms = split_target
+        .next()
+        .and_then(|value| value.parse::<u64>().ok());
+    let (Some(position), Some(duration_ms)) = (position, duration_ms) else {
+        error!("Could not parse the streamed target {}", target);
+        feedback_str
+            .write_str("fail:parse")
+            .expect("Should always fit");
+        return Some(feedback_str);
+    };
+
+    // e.g. ok:merged:velocity:450.0 or fail:motion_enabled
+    let written = match stream_target(position, duration_ms) {
+        Ok(feedback) if feedback.is_empty() => return None,
+        Ok(feedback) => write!(feedback_str, "ok:{}", feedback),
+        Err(err) => {
+            error!("Streamed target {} not accepted: {}", target, err);
+            write!(feedback_str, "fail:{}", err)
+        }
+    };
+    if written.is_err() {
+        report_error(RemoteError::ResponseTooLong);
+    }
+
+    Some(feedback_str)
+}
+
+/// Execute a streamed position packet
+/// Returns the failure to notify the client with. Adjusted positions are not reported so that
+/// the notifications do not compete with the positions for the link
+fn process_position(packet: &[u8]) -> Option<String<MAX_COMMAND_LENGTH>> {
+    let value = |bytes: &[u8]| f32::from_le_bytes(bytes.try_into().expect("4 bytes")) as Real;
+    let (position, velocity) = match packet.len() {
+        4 => (value(packet), None),
+        8 => (value(&packet[..4]), Some(value(&packet[4..]))),
+        _ => {
+            error!("Could not parse the streamed position {:?}", packet);
+            return Some(String::try_from("fail:parse").expect("Fits"));
+        }
+    };
+
+    let err = stream_position(position, velocity).err()?;
+    debug!("Streamed position {} mm not accepted: {}", position, err);
+    let mut failure = String::new();
+    if write!(failure, "fail:{}", err).is_err() {
+        report_error(RemoteError::ResponseTooLong);
+    }
+    Some(failure)
+}
+
+/// Execute a write to the funscript characteristic
+/// - `clear`
+/// - `add:<at ms>,<position %>;<at ms>,<position %>...` as many as fit into one write
+/// - `play`, `pause`, `stop`
+/// - `seek:<ms>`
+///
+/// Returns the response to notify the client with
+fn process_funscript_command(command: &str) -> String<MAX_COMMAND_LENGTH> {
+    let (action, args) = command.split_once(':').unwrap_or((command, ""));
+
+    let result = match action {
+        "clear" => clear_funscript().map(|()| None),
+        "add" => add_funscript_actions(args).map(Some),
+        "play" => play_funscript().map(|()| None),
+        "pause" => {
+            pause_funscript();
+            Ok(None)
+        }
+        "stop" => {
+            stop_funscript();
+            Ok(None)
+        }
+        "seek" => args
+            .parse::<u32>()
+            .map(|position_ms| {
+                seek_funscript(position_ms);
+                None
+            })
+            .map_err(|_| FunscriptError::Malformed),
+        _ => Err(FunscriptError::Malformed),
+    };
+
+    // e.g. ok:play, ok:124 or fail:add:not_ascending
+    let mut response_str: String<MAX_COMMAND_LENGTH> = String::new();
+    let written = match result {
+        Ok(Some(count)) => write!(response_str, "ok:{}", count),
+        Ok(None) => write!(response_str, "ok:{}", command),
+        Err(err) => {
+            error!("Funscript command {} failed: {}", command, err);
+            write!(response_str, "fail:{}:{}", action, err)
+        }
+    };
+    if written.is_err() {
+        report_error(RemoteError::ResponseTooLong);
+    }
+
+    response_str
+}
+
+/// Execute a write to the settings characteristic
+/// - `clear` to start a backup over
+/// - `add:<part of the JSON>` as much as fits into one write
+/// - `apply` to check the backup and store it
+///
+/// Returns the response to notify the client with and whether the backup was stored
+fn process_settings_command(
+    command: &str,
+    backup: &mut String<MAX_SETTINGS_LENGTH>,
+) -> (String<MAX_COMMAND_LENGTH>, bool) {
+    let (action, part) = command.split_once(':').unwrap_or((command, ""));
+
+    let result = match action {
+        "clear" => {
+            backup.clear();
+            Ok(None)
+        }
+        "add" => backup
+            .push_str(part)
+            .map(|()| Some(backup.len()))
+            .map_err(|_| BackupError::TooLong),
+        "apply" => restore_settings(backup).map(|()| None),
+        _ => Err(BackupError::Malformed),
+    };
+
+    // e.g. ok:apply, ok:212 or fail:apply:wifi
+    let mut response_str: String<MAX_COMMAND_LENGTH> = String::new();
+    let written = match &result {
+        Ok(Some(length)) => write!(response_str, "ok:{}", length),
+        Ok(None) => write!(response_str, "ok:{}", action),
+        Err(err) => {
+            error!("Settings command {} failed: {}", action, err.name());
+            write!(response_str, "fail:{}:{}", action, err.name())
+        }
+    };
+    if written.is_err() {
+        report_error(RemoteError::ResponseTooLong);
     }
+
+    (response_str, result.is_ok() && action == "apply")
+}
+
+/// Execute a write to the custom pattern characteristic
+/// - `position=<expression>`, `velocity=<expression>` or `delay=<expression>`
+/// - `clear` to go back to the defaults
+///
+/// Returns the response to notify the client with
+fn process_custom_pattern_command(command: &str) -> String<MAX_COMMAND_LENGTH> {
+    let (output, result) = if command == "clear" {
+        reset_custom_pattern();
+        (command, Ok(()))
+    } else {
+        let output = command
+            .split_once('=')
+            .map_or(command, |(output, _)| output);
+        (output.trim(), set_custom_expression(command))
+    };
+
+    // e.g. ok:position or fail:delay:unknown_variable
+    let mut response_str: String<MAX_COMMAND_LENGTH> = String::new();
+    let written = match result {
+        Ok(()) => write!(response_str, "ok:{}", output),
+        Err(err) => {
+            error!("Custom pattern command {} failed: {}", command, err);
+            write!(response_str, "fail:{}:{}", output, err)
+        }
+    };
+    if written.is_err() {
+        report_error(RemoteError::ResponseTooLong);
+    }
+
+    response_str
+}
+
+/// Execute a write to the playlist characteristic
+/// - `add:<pattern id>:<duration s>:<sensation %>`
+/// - `set:<idx>:<pattern id>:<duration s>:<sensation %>`
+/// - `remove:<idx>`, `clear`
+/// - `start`, `stop`, `next`
+/// - `repeat:<0|1>`
+///
+/// Returns the response to notify the client with
+fn process_playlist_command(command: &str) -> String<MAX_COMMAND_LENGTH> {
+    let mut args = command.split(':');
+    let action = args.next().unwrap_or_default();
+
+    let result = match action {
+        "add" => parse_values::<3>(&mut args)
+            .ok_or(PlaylistError::Malformed)
+            .and_then(|[pattern, duration_s, sensation]| {
+                add_playlist_entry(PlaylistEntry {
+                    pattern,
+                    duration_s,
+                    sensation,
+                })
+            })
+            .map(|_| ()),
+        "set" => parse_values::<4>(&mut args)
+            .ok_or(PlaylistError::Malformed)
+            .and_then(|[index, pattern, duration_s, sensation]| {
+                set_playlist_entry(
+                    index as usize,
+                    PlaylistEntry {
+                        pattern,
+                        duration_s,
+                        sensation,
+                    },
+                )
+            }),
+        "remove" => parse_values::<1>(&mut args)
+            .ok_or(PlaylistError::Malformed)
+            .and_then(|[index]| remove_playlist_entry(index as usize)),
+        "clear" => {
+            clear_playlist();
+            Ok(())
+        }
+        "start" => start_playlist(),
+        "stop" => {
+            stop_playlist();
+            Ok(())
+        }
+        "next" => {
+            skip_playlist_entry();
+            Ok(())
+        }
+        "repeat" => match args.next() {
+            Some("0") => {
+                set_playlist_repeat(false);
+                Ok(())
+            }
+            Some("1") => {
+                set_playlist_repeat(true);
+                Ok(())
+            }
+            _ => Err(PlaylistError::Malformed),
+        },
+        _ => Err(PlaylistError::Malformed),
+    };
+
+    // e.g. ok:start or fail:add:unknown_pattern
+    let mut response_str: String<MAX_COMMAND_LENGTH> = String::new();
+    let written = match result {
+        Ok(()) => write!(response_str, "ok:{}", command),
+        Err(err) => {
+            error!("Playlist command {} failed: {}", command, err);
+            write!(response_str, "fail:{}:{}", action, err)
+        }
+    };
+    if written.is_err() {
+        report_error(RemoteError::ResponseTooLong);
+    }
+
+    response_str
+}
+
+/// Parse N consecutive numeric values from the command
+fn parse_values<'a, const N: usize>(args: &mut impl Iterator<Item = &'a str>) -> Option<[u32; N]> {
+    let mut values = [0; N];
+    for value in values.iter_mut() {
+        *value = args.next()?.parse().ok()?;
+    }
+    Some(values)
+}
+
+/// The command with the PINs of the profile commands masked, e.g. `profile:select:1:***`
+fn without_pins(command: &str) -> String<MAX_COMMAND_LENGTH> {
+    let mut fields = command.split(':');
+    // Where the PINs start, see `process_profile_command`
+    let first_pin = match (fields.next(), fields.next()) {
+        (Some("profile"), Some("select" | "pin")) => 3,
+        (Some("profile"), Some("name")) => 4,
+        (Some("profile"), Some("prefs")) => 5,
+        (Some("profile"), Some("limits")) => 6,
+        _ => usize::MAX,
+    };
+
+    let mut shown = String::new();
+    for (i, field) in command.split(':').enumerate() {
+        let field = if i >= first_pin { "***" } else { field };
+        let separator = if i == 0 { "" } else { ":" };
+        if write!(shown, "{}{}", separator, field).is_err() {
+            break;
+        }
+    }
+    shown
+}
+
+/// Parse an optional PIN. A missing or unparsable PIN is treated as no PIN
+fn parse_pin(pin: Option<&str>) -> Option<u32> {
+    pin.and_then(|pin| pin.parse().ok())
+}
+
+/// Process the profile commands. The PIN is always the last optional argument:
+/// - `profile:select:<idx>[:<pin>]`
+/// - `profile:limits:<idx>:<speed>:<depth>:<torque>[:<pin>]`
+/// - `profile:prefs:<idx>:<sensation>:<pattern>[:<pin>]`
+/// - `profile:pin:<idx>:<new pin|none>[:<pin>]`
+/// - `profile:name:<idx>:<name>[:<pin>]` (creates a new profile if idx is one past the last,
+///   with the limits and PIN of the active profile, whose PIN it requires)
+///
+/// Returns true on success
+fn process_profile_command<'a>(action: &str, mut args: impl Iterator<Item = &'a str>) -> bool {
+    let Some(index) = args.next().and_then(|index| index.parse::<usize>().ok()) else {
+        error!("Could not parse profile index");
+        return false;
+    };
+
+    let result = match action {
+        "select" => select_profile(index, parse_pin(args.next())),
+        "limits" => {
+            let Some([velocity, depth, torque]) = parse_values(&mut args) else {
+                error!("Could not parse profile limits");
+                return false;
+            };
+            let limits = ProfileLimits {
+                velocity,
+                depth,
+                torque,
+            };
+            set_profile_limits(index, limits, parse_pin(args.next()))
+        }
+        "prefs" => {
+            let Some([sensation, pattern]) = parse_values(&mut args) else {
+                error!("Could not parse profile preferences");
+                return false;
+            };
+            set_profile_preferences(index, sensation, pattern, parse_pin(args.next()))
+        }
+        "pin" => {
+            let new_pin = match args.next() {
+                Some("none") => None,
+                Some(new_pin) => match new_pin.parse::<u32>() {
+                    Ok(new_pin) => Some(new_pin),
+                    Err(_) => {
+                        error!("Could not parse the new PIN");
+                        return false;
+                    }
+                },
+                None => {
+                    error!("No new PIN given");
+                    return false;
+                }
+            };
+            set_profile_pin(index, new_pin, parse_pin(args.next()))
+        }
+        "name" => {
+            let Some(name) = args.next() else {
+                error!("No profile name given");
+                return false;
+            };
+            set_profile_name(index, name, parse_pin(args.next()))
+        }
+        _ => {
+            error!("Invalid profile command {}", action);
+            return false;
+        }
+    };
+
+    if let Err(err) = result {
+        error!("Profile command failed {:?}", err);
+        return false;
+    }
+
+    if let Err(err) = save_profiles(&get_profiles(), get_active_profile()) {
+        // Still applied until the next boot
+        report_error(err);
+    }
+
+    true
+}
+
+/// Process the preset commands:
+/// - `preset:save:<name>` saves the current settings, replacing the preset with the same name
+/// - `preset:load:<name>`
+/// - `preset:next` loads the one after the preset loaded last
+/// - `preset:delete:<name>`
+///
+/// Returns true on success
+fn process_preset_command(action: &str, name: Option<&str>) -> bool {
+    let result = match (action, name) {
+        ("save", Some(name)) => save_preset(name),
+        ("load", Some(name)) => load_preset(name),
+        ("next", None) => load_next_preset(),
+        ("delete", Some(name)) => delete_preset(name),
+        _ => {
+            error!("Invalid preset command {}", action);
+            return false;
+        }
+    };
+
+    if let Err(err) = result {
+        error!("Preset command failed {:?}", err);
+        return false;
+    }
+
+    if matches!(action, "save" | "delete") {
+        if let Err(err) = save_presets(&get_presets()) {
+            // Still saved until the next boot
+            report_error(err);
+        }
+    }
+
+    true
+}
+
+/// Process the session limit commands. The PIN is always the last optional argument:
+/// - `limits:set:<speed>:<depth mm>[:<pin>]` caps the speed in % and the depth in mm
+/// - `limits:clear[:<pin>]` removes the caps
+/// - `limits:pin:<new pin|none>[:<pin>]`
+///
+/// Returns true on success
+fn process_limits_command<'a>(action: &str, mut args: impl Iterator<Item = &'a str>) -> bool {
+    let result = match action {
+        "set" => {
+            let velocity = args.next().and_then(|velocity| velocity.parse().ok());
+            let depth_mm = args.next().and_then(|depth| depth.parse().ok());
+            let (Some(velocity), Some(depth_mm)) = (velocity, depth_mm) else {
+                error!("Could not parse the session limits");
+                return false;
+            };
+            let limits = SessionLimits { velocity, depth_mm };
+            set_session_limits(limits, parse_pin(args.next()))
+        }
+        "clear" => set_session_limits(SessionLimits::UNRESTRICTED, parse_pin(args.next())),
+        "pin" => {
+            let new_pin = match args.next() {
+                Some("none") => None,
+                Some(new_pin) => match new_pin.parse::<u32>() {
+                    Ok(new_pin) => Some(new_pin),
+                    Err(_) => {
+                        error!("Could not parse the new PIN");
+                        return false;
+                    }
+                },
+                None => {
+                    error!("No new PIN given");
+                    return false;
+                }
+            };
+            set_session_limits_pin(new_pin, parse_pin(args.next()))
+        }
+        _ => {
+            error!("Invalid limits command {}", action);
+            return false;
+        }
+    };
+
+    if let Err(err) = result {
+        error!("Limits command failed {:?}", err);
+        return false;
+    }
+
+    if let Err(err) = save_session_limits(&get_session_limits(), get_session_limits_pin()) {
+        // Still applied until the next boot
+        report_error(err);
+    }
+
+    true
 }
 
 pub fn is_ble_connected() -> bool {
-    CONNECTED.load(Ordering::Acquire)
+    CONNECTIONS.load(Ordering::Acquire) > 0
+}
+
+/// Whether the central in control of the motion is still connected
+/// and kept writing within `BLE_KEEPALIVE_TIMEOUT_MS` if it wrote `go:keepalive`
+pub fn is_ble_controller_connected() -> bool {
+    CONTROL_OWNER.load(Ordering::Acquire) != NO_OWNER
+        && (!CONTROL_OWNER_SUPERVISED.load(Ordering::Acquire)
+            || CONTROL_OWNER_LAST_WRITE.is_within(Duration::from_millis(BLE_KEEPALIVE_TIMEOUT_MS)))
+}
+
+/// How many centrals are connected
+pub fn get_ble_connections() -> usize {
+    CONNECTIONS.load(Ordering::Acquire)
 }
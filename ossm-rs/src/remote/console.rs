@@ -0,0 +1,209 @@
+use core::fmt::Write;
+
+use embassy_time::Duration;
+use embedded_io_async::Read;
+use esp_hal::{usb_serial_jtag::UsbSerialJtag, Async};
+use esp_println::println;
+use heapless::{String, Vec};
+use log::{error, info};
+use ossm_motion::{
+    fault::get_fault_count,
+    motion::motion_state::get_motion_state,
+    motion_control::is_faulted,
+    pattern::PatternExecutor,
+    runtime_config::get_config_json,
+    thermal::{get_mcu_temperature_c, get_motor_temperature_c, get_thermal_level},
+    time::AtomicTimestamp,
+};
+
+#[cfg(not(feature = "generic_modbus"))]
+use crate::motion::{read_motor_register, write_motor_register};
+use crate::{
+    config::{CONSOLE_TIMEOUT_MS, MAX_CONSOLE_LINE_LENGTH, MAX_CONSOLE_RESPONSE_LENGTH},
+    motion::estop::is_estop_pin_active,
+    motor::get_response_error_counts,
+    network::wifi::{get_wifi_status_json, process_wifi_command},
+    power::get_supply_mv,
+    remote::{
+        ble::{get_ble_connections, process_command, process_config_command},
+        esp_now::is_m5_connected,
+        get_control_source, ControlSource,
+    },
+};
+
+// The last line received. The console counts as a connected remote for CONSOLE_TIMEOUT_MS
+static LAST_LINE: AtomicTimestamp = AtomicTimestamp::never();
+
+/// Whether a line was received within `CONSOLE_TIMEOUT_MS`
+pub fn is_console_connected() -> bool {
+    LAST_LINE.is_within(Duration::from_millis(CONSOLE_TIMEOUT_MS))
+}
+
+/// Task to read the commands from the USB Serial/JTAG port, the one the logs are printed to
+/// Answers them like the BLE primary command characteristic
+#[embassy_executor::task]
+pub async fn console_task(usb_serial: UsbSerialJtag<'static, Async>) {
+    info!("Task Console Started");
+
+    // The logs are printed to the same port by esp-println
+    let (mut rx, _tx) = usb_serial.split();
+
+    let mut line: Vec<u8, MAX_CONSOLE_LINE_LENGTH> = Vec::new();
+    // The line did not fit and is dropped until its end
+    let mut overflow = false;
+    let mut buffer = [0u8; 64];
+    loop {
+        let read = match rx.read(&mut buffer).await {
+            Ok(read) => read,
+            Err(err) => {
+                error!("Could not read from the console {:?}", err);
+                continue;
+            }
+        };
+
+        for byte in &buffer[..read] {
+            match byte {
+                b'\r' | b'\n' => {
+                    // Empty lines only keep the console connected
+                    LAST_LINE.store_now();
+                    if overflow {
+                        println!("fail:overflow");
+                    } else if !line.is_empty() {
+                        match core::str::from_utf8(&line) {
+                            Ok(command) => process_console_command(command),
+                            Err(_) => println!("fail:utf8"),
+                        }
+                    }
+                    line.clear();
+                    overflow = false;
+                }
+                byte => overflow |= line.push(*byte).is_err(),
+            }
+        }
+    }
+}
+
+/// Run a command and print the response
+/// - `set:`, `go:`, `profile:`, `preset:` and `limits:` commands like over BLE
+/// - `state`, `patterns` and `config` print the JSON of the BLE characteristics
+/// - `config:<key>:<value>` sets a runtime config value
+/// - `wifi` prints the connection and `wifi:<command>` provisions it like the WiFi
+///   characteristic e.g. `wifi:connect:<ssid>:<password>`
+/// - `diag` prints the fault and error counters, the temperatures and which remotes are connected
+/// - `estop` prints the level of the e-stop pin and whether the machine is faulted to check the
+///   wiring
+/// - `reg:<address>` reads and `reg:<address>:<value>` writes a motor register
+///   while the machine is standing still. The address is in hex e.g. `reg:0x0e`. 57AIMxx only
+fn process_console_command(command: &str) {
+    let mut split_command = command.splitn(2, ':');
+    match (split_command.next(), split_command.next()) {
+        (Some("set" | "go" | "profile" | "preset" | "limits"), Some(_)) => {
+            println!("{}", process_command(command, ControlSource::Console));
+        }
+        (Some("state"), None) => println!("{}", get_motion_state().as_json()),
+        (Some("patterns"), None) => println!("{}", PatternExecutor::new().get_all_patterns_json()),
+        (Some("config"), None) => println!("{}", get_config_json()),
+        (Some("config"), Some(config)) => println!("{}", process_config_command(config)),
+        (Some("wifi"), None) => println!("{}", get_wifi_status_json()),
+        (Some("wifi"), Some(wifi)) => println!("{}", process_wifi_command(wifi)),
+        (Some("diag"), None) => println!("{}", diagnostics()),
+        (Some("estop"), None) => println!("{}", estop_status()),
+        #[cfg(not(feature = "generic_modbus"))]
+        (Some("reg"), Some(register)) => println!("{}", process_register_command(register)),
+        _ => {
+            error!("Unknown console command {}", command);
+            println!("fail:{}", command);
+        }
+    }
+}
+
+/// e.g. `{"faults":0,"crcMismatches":0,"echoMismatches":0,"supplyMv":24000,"motorC":41,"mcuC":38,"thermal":"normal","ble":1,"m5":false,"control":"console"}`
+/// The supply, the temperatures and the response errors of a generic servo are 0 if not measured
+fn diagnostics() -> String<MAX_CONSOLE_RESPONSE_LENGTH> {
+    let errors = get_response_error_counts();
+    let mut output = String::new();
+    if write!(
+        output,
+        r#"{{"faults":{},"crcMismatches":{},"echoMismatches":{},"supplyMv":{},"motorC":{:.0},"mcuC":{:.0},"thermal":"{}","ble":{},"m5":{},"control":"{}"}}"#,
+        get_fault_count(),
+        errors.crc_mismatches,
+        errors.position_echo_mismatches,
+        get_supply_mv().unwrap_or(0),
+        get_motor_temperature_c().unwrap_or(0.0),
+        get_mcu_temperature_c().unwrap_or(0.0),
+        get_thermal_level().name(),
+        get_ble_connections(),
+        is_m5_connected(),
+        get_control_source().name()
+    )
+    .is_err()
+    {
+        error!("Could not write the diagnostics. Too long");
+    }
+
+    output
+}
+
+/// e.g. `{"estop":"active","faulted":true}`. The pin is `none` if the board has no e-stop
+fn estop_status() -> String<MAX_CONSOLE_RESPONSE_LENGTH> {
+    let pin = match is_estop_pin_active() {
+        Some(true) => "active",
+        Some(false) => "clear",
+        None => "none",
+    };
+    let mut output = String::new();
+    if write!(
+        output,
+        r#"{{"estop":"{}","faulted":{}}}"#,
+        pin,
+        is_faulted()
+    )
+    .is_err()
+    {
+        error!("Could not write the e-stop status. Too long");
+    }
+
+    output
+}
+
+/// `<address>` or `<address>:<value>` with the address in hex and the value in decimal
+/// Answers `ok:reg:<address>:<value>` with the value read or written
+#[cfg(not(feature = "generic_modbus"))]
+fn process_register_command(command: &str) -> String<MAX_CONSOLE_RESPONSE_LENGTH> {
+    let mut split_command = command.split(':');
+    let address = split_command.next().unwrap_or_default();
+    let Ok(register) = u16::from_str_radix(address.trim_start_matches("0x"), 16) else {
+        error!("Could not parse the register address {}", address);
+        return register_failure(command);
+    };
+
+    let result = match split_command.next().map(str::parse::<u16>) {
+        None => read_motor_register(register),
+        Some(Ok(value)) => write_motor_register(register, value).map(|()| value),
+        Some(Err(_)) => {
+            error!("Could not parse the register value {}", command);
+            return register_failure(command);
+        }
+    };
+
+    match result {
+        Ok(value) => {
+            let mut output = String::new();
+            write!(output, "ok:reg:{}:{}", address, value).expect("Always fits");
+            output
+        }
+        Err(err) => {
+            error!("Register command {} failed {:?}", command, err);
+            register_failure(command)
+        }
+    }
+}
+
+#[cfg(not(feature = "generic_modbus"))]
+fn register_failure(command: &str) -> String<MAX_CONSOLE_RESPONSE_LENGTH> {
+    let mut output = String::new();
+    if write!(output, "fail:reg:{}", command).is_err() {
+        error!("Could not write the register response. Too long");
+    }
+    output
+}
@@ -1,28 +1,89 @@
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
 
 use log::{error, info};
 use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
-use embassy_time::{Duration, Instant, Ticker};
+use embassy_time::{Duration, Ticker};
+use esp_hal::gpio::Input;
 use esp_radio::esp_now::{
-    EspNowManager, EspNowReceiver, EspNowSender, PeerInfo, BROADCAST_ADDRESS,
+    EspNowError, EspNowManager, EspNowReceiver, EspNowSender, PeerInfo, BROADCAST_ADDRESS,
 };
-use portable_atomic::AtomicU64;
+use heapless::Vec;
 use zerocopy::{Immutable, IntoBytes, KnownLayout, TryFromBytes};
 
-use crate::config::{MAX_NO_REMOTE_HEARTBEAT_MS, MAX_TRAVEL_MM, MOTION_CONTROL_MAX_VELOCITY};
+use crate::{
+    config::{
+        ESP_NOW_ACK_TIMEOUT_MS, ESP_NOW_ENCRYPT, ESP_NOW_LMK, ESP_NOW_MAX_RETRIES, ESP_NOW_PMK,
+        M5_PATTERN_NAME_LENGTH, M5_STATE_INTERVAL_MS, MAX_REMOTES, PAIRING_WINDOW_S,
+        REMOTE_HEARTBEAT_INTERVAL_MS,
+    },
+    error::{report_error, RemoteError},
+    motion::homing::request_homing,
+    remote::{
+        claim_control, get_control_source, release_control, sync::receive_sync_packet,
+        ControlSource,
+    },
+    storage::{load_remotes, save_remotes},
+};
 
-use ossm_motion::motion::motion_state::{
-    set_motion_depth_mm, set_motion_enabled, set_motion_length_mm, set_motion_pattern,
-    set_motion_sensation_neg_pos_100, set_motion_velocity_mm_s,
+use ossm_motion::{
+    event::{publish_event, Event},
+    fault::{raise_fault, FaultCode},
+    motion::motion_state::{
+        get_max_depth_mm, get_max_velocity_mm_s, get_motion_state, set_motion_bpm,
+        set_motion_depth_mm, set_motion_enabled, set_motion_length_mm, set_motion_pattern,
+        set_motion_sensation_neg_pos_100, set_motion_torque_forward_pct,
+        set_motion_torque_reverse_pct, set_motion_velocity_mm_s, MachineMotionState,
+    },
+    motion_control::{
+        arming::{arm, arm_button_pressed, is_armed},
+        emergency_stop, get_actual_position_mm, get_actual_velocity_mm_s, get_torque_pct,
+        is_faulted, is_paused, pause, resume,
+    },
+    pattern::{m5_index_from_pattern_id, pattern_id_from_m5_index, PatternExecutor},
+    preset::load_next_preset,
+    runtime_config::get_heartbeat_timeout_ms,
+    time::AtomicTimestamp,
+    validation::{remote_value_to_i32, remote_value_to_u32, ValueError},
 };
 
 const OSSM_ID: i32 = 1;
 const M5_ID: i32 = 99;
 
-static LAST_HEARTBEAT: AtomicU64 = AtomicU64::new(0);
+static LAST_HEARTBEAT: AtomicTimestamp = AtomicTimestamp::never();
 static CONNECTED: AtomicBool = AtomicBool::new(false);
+// New remotes can pair for PAIRING_WINDOW_S after this
+static PAIRING_OPENED: AtomicTimestamp = AtomicTimestamp::never();
+// The sequence of the next command sent to a remote
+static NEXT_SEQUENCE: AtomicU32 = AtomicU32::new(0);
+// Packets from other addresses are ignored. Loaded from the settings when the task starts
+static PAIRED_REMOTES: critical_section::Mutex<RefCell<Vec<PairedRemote, MAX_REMOTES>>> =
+    critical_section::Mutex::new(RefCell::new(Vec::new()));
+
+struct PairedRemote {
+    address: [u8; 6],
+    // It sent sequenced packets, so it also acknowledges the commands sent to it
+    acks: bool,
+    // The sequence of its last sequenced packet to drop the ones sent again
+    last_sequence: Option<u32>,
+    // The command sent to it that was not acknowledged yet
+    pending: Option<PendingAck>,
+}
 
-#[derive(Default, Debug, TryFromBytes, IntoBytes, Immutable)]
+impl PairedRemote {
+    fn new(address: [u8; 6]) -> Self {
+        Self {
+            address,
+            acks: false,
+            last_sequence: None,
+            pending: None,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, TryFromBytes, IntoBytes, Immutable)]
 #[repr(i32)]
 // The commands are not constructed
 #[allow(dead_code)]
@@ -40,6 +101,24 @@ enum M5Command {
     SetupDI = 12,
     SetupDIF = 13,
     Reboot = 14,
+    // Not sent by the stock M5 firmware
+    Pause = 15,
+    Resume = 16,
+    Bpm = 17,
+    // Sent by the remote to get the pattern list and by the machine with every pattern
+    Patterns = 18,
+    // The full motion state sent every M5_STATE_INTERVAL_MS
+    State = 19,
+    // Turns the motion off and homes again, e.g. after the belt slipped
+    Home = 24,
+    // Emergency stops the machine like `go:stop`
+    Stop = 25,
+    // Loads the preset after the one loaded last like `preset:next`
+    NextPreset = 26,
+    // Arms the machine like `go:arm`. Answered with Arm when armed and Off when not
+    Arm = 27,
+    // Acknowledges the sequenced packet with the same sequence
+    Ack = 30,
 
     CumSpeed = 20,
     CumTime = 21,
@@ -52,7 +131,29 @@ enum M5Command {
     Heartbeat = 99,
 }
 
-#[derive(Default, Debug, TryFromBytes, IntoBytes, Immutable, KnownLayout)]
+impl M5Command {
+    /// Commands that change the motion and are arbitrated with the BLE clients
+    /// Turning off is always accepted
+    fn changes_motion(&self) -> bool {
+        matches!(
+            self,
+            M5Command::Speed
+                | M5Command::Depth
+                | M5Command::Stroke
+                | M5Command::Sensation
+                | M5Command::Pattern
+                | M5Command::TorqueF
+                | M5Command::TorqueR
+                | M5Command::On
+                | M5Command::Pause
+                | M5Command::Resume
+                | M5Command::Bpm
+                | M5Command::NextPreset
+        )
+    }
+}
+
+#[derive(Default, Debug, Clone, TryFromBytes, IntoBytes, Immutable, KnownLayout)]
 #[repr(C)]
 struct M5Packet {
     speed: f32,
@@ -70,30 +171,341 @@ struct M5Packet {
 }
 
 impl M5Packet {
+    /// Speed and depth carry the currently allowed maximums
+    /// The rest reflects the current machine state
     fn heartbeat_packet() -> Self {
+        let state: MachineMotionState = get_motion_state().into();
+
         Self {
             connected: true,
             target: M5_ID,
-            speed: MOTION_CONTROL_MAX_VELOCITY as f32,
-            depth: MAX_TRAVEL_MM as f32,
+            speed: get_max_velocity_mm_s() as f32,
+            depth: get_max_depth_mm() as f32,
+            stroke: state.motion_length as f32,
+            sensation: state.sensation as f32,
+            pattern: m5_pattern_value(state.pattern) as f32,
+            rstate: state.motion_enabled,
             ..Default::default()
         }
     }
 }
 
+/// A packet followed by a sequence number. Acknowledged by the receiver with `M5Command::Ack`
+/// The stock M5 firmware only sends the packet without it, which is never acknowledged
+#[derive(Default, Debug, Clone, TryFromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct M5SequencedPacket {
+    packet: M5Packet,
+    sequence: u32,
+}
+
+impl M5SequencedPacket {
+    fn ack(sequence: u32) -> Self {
+        Self {
+            packet: M5Packet {
+                target: M5_ID,
+                command: M5Command::Ack,
+                ..Default::default()
+            },
+            sequence,
+        }
+    }
+}
+
+struct PendingAck {
+    packet: M5SequencedPacket,
+    retries: u32,
+}
+
+/// One entry of the pattern list
+#[derive(Debug, IntoBytes, Immutable)]
+#[repr(C)]
+struct M5PatternPacket {
+    command: M5Command,
+    target: i32,
+    // The position in the list and the length of it, so that the remote knows when it has all
+    index: i32,
+    count: i32,
+    // The value to select the pattern with `M5Command::Pattern`
+    pattern: i32,
+    // Padded with zeros
+    name: [u8; M5_PATTERN_NAME_LENGTH],
+}
+
+impl M5PatternPacket {
+    fn new(index: usize, count: usize, id: u32, name: &str) -> Self {
+        let mut packet = Self {
+            command: M5Command::Patterns,
+            target: M5_ID,
+            index: index as i32,
+            count: count as i32,
+            pattern: m5_pattern_value(id) as i32,
+            name: [0; M5_PATTERN_NAME_LENGTH],
+        };
+        let length = name.len().min(M5_PATTERN_NAME_LENGTH);
+        packet.name[..length].copy_from_slice(&name.as_bytes()[..length]);
+        packet
+    }
+}
+
+/// The motion state in machine values like the heartbeat, but with the set speed and depth
+#[derive(Debug, IntoBytes, Immutable)]
+#[repr(C)]
+struct M5StatePacket {
+    command: M5Command,
+    target: i32,
+    speed: f32,
+    depth: f32,
+    stroke: f32,
+    sensation: f32,
+    pattern: f32,
+    bpm: f32,
+    load: f32,
+    torque: f32,
+    position: f32,
+    velocity: f32,
+    rstate: bool,
+    paused: bool,
+    faulted: bool,
+    _padding: bool,
+}
+
+impl M5StatePacket {
+    fn new() -> Self {
+        let state: MachineMotionState = get_motion_state().into();
+
+        Self {
+            command: M5Command::State,
+            target: M5_ID,
+            speed: state.velocity as f32,
+            depth: state.depth as f32,
+            stroke: state.motion_length as f32,
+            sensation: state.sensation as f32,
+            pattern: m5_pattern_value(state.pattern) as f32,
+            bpm: state.bpm as f32,
+            load: state.load as f32,
+            torque: get_torque_pct() as f32,
+            position: get_actual_position_mm() as f32,
+            velocity: get_actual_velocity_mm_s() as f32,
+            rstate: state.motion_enabled,
+            paused: is_paused(),
+            faulted: is_faulted(),
+            _padding: false,
+        }
+    }
+}
+
+/// The value the M5 remote selects a pattern with
+/// Patterns missing from the M5 menu are sent with their ID
+fn m5_pattern_value(id: u32) -> u32 {
+    m5_index_from_pattern_id(id).unwrap_or(id)
+}
+
+/// The pattern ID for a value sent with `M5Command::Pattern`
+/// Values past the M5 menu are IDs from the pattern list
+fn pattern_id_from_m5_value(value: u32) -> u32 {
+    pattern_id_from_m5_index(value).unwrap_or(value)
+}
+
+/// Run `f` on the paired remote with this address. None if it is not paired
+fn with_remote<R>(address: &[u8; 6], f: impl FnOnce(&mut PairedRemote) -> R) -> Option<R> {
+    critical_section::with(|cs| {
+        PAIRED_REMOTES
+            .borrow_ref_mut(cs)
+            .iter_mut()
+            .find(|remote| remote.address == *address)
+            .map(f)
+    })
+}
+
+fn is_paired(address: &[u8; 6]) -> bool {
+    with_remote(address, |_| ()).is_some()
+}
+
+fn get_paired_addresses() -> Vec<[u8; 6], MAX_REMOTES> {
+    critical_section::with(|cs| {
+        PAIRED_REMOTES
+            .borrow_ref(cs)
+            .iter()
+            .map(|remote| remote.address)
+            .collect()
+    })
+}
+
 async fn send_heartbeat_packet(
     sender: &'static Mutex<NoopRawMutex, EspNowSender<'static>>,
-    peer: &PeerInfo,
+    address: &[u8; 6],
 ) {
     let mut sender = sender.lock().await;
     if let Err(err) = sender
-        .send_async(&peer.peer_address, M5Packet::heartbeat_packet().as_bytes())
+        .send_async(address, M5Packet::heartbeat_packet().as_bytes())
         .await
     {
         error!("Could not send the heartbeat packet {}", err);
     }
 }
 
+/// Send a packet to a paired remote
+async fn send_to(
+    sender: &'static Mutex<NoopRawMutex, EspNowSender<'static>>,
+    address: &[u8; 6],
+    packet: &(impl IntoBytes + Immutable),
+) {
+    let mut sender = sender.lock().await;
+    if let Err(err) = sender.send_async(address, packet.as_bytes()).await {
+        report_error(err);
+    }
+}
+
+/// Send a command to a paired remote
+/// Sent again until acknowledged if the remote supports it
+async fn send_command(
+    sender: &'static Mutex<NoopRawMutex, EspNowSender<'static>>,
+    address: &[u8; 6],
+    packet: M5Packet,
+) {
+    if !with_remote(address, |remote| remote.acks).unwrap_or(false) {
+        send_to(sender, address, &packet).await;
+        return;
+    }
+
+    let packet = M5SequencedPacket {
+        packet,
+        sequence: NEXT_SEQUENCE.fetch_add(1, Ordering::AcqRel),
+    };
+    send_to(sender, address, &packet).await;
+    // Replaces an older command that is still pending. The newest one is what counts
+    with_remote(address, |remote| {
+        remote.pending = Some(PendingAck { packet, retries: 0 });
+    });
+}
+
+/// Stop sending the command again once the remote acknowledged it
+fn acknowledged(address: &[u8; 6], sequence: u32) {
+    with_remote(address, |remote| {
+        if remote
+            .pending
+            .as_ref()
+            .is_some_and(|pending| pending.packet.sequence == sequence)
+        {
+            remote.pending = None;
+        }
+    });
+}
+
+/// Whether the sequenced packet was already received and only sent again
+/// because the acknowledgement got lost
+/// Also marks the remote as one that acknowledges the commands sent to it
+fn is_duplicate(address: &[u8; 6], sequence: u32) -> bool {
+    with_remote(address, |remote| {
+        remote.acks = true;
+        remote.last_sequence.replace(sequence) == Some(sequence)
+    })
+    .unwrap_or(false)
+}
+
+/// Send every pattern in its own packet
+async fn send_pattern_list(
+    sender: &'static Mutex<NoopRawMutex, EspNowSender<'static>>,
+    address: &[u8; 6],
+) {
+    let mut executor = PatternExecutor::new();
+    let count = executor.get_pattern_count();
+    for (index, (id, name)) in executor.get_pattern_list().enumerate() {
+        let packet = M5PatternPacket::new(index, count, id, name);
+        send_to(sender, address, &packet).await;
+    }
+}
+
+/// Let new remotes pair for the next PAIRING_WINDOW_S
+pub fn open_pairing_window() {
+    info!("Pairing window open for {} s", PAIRING_WINDOW_S);
+    PAIRING_OPENED.store_now();
+}
+
+fn is_pairing() -> bool {
+    PAIRING_OPENED.is_within(Duration::from_secs(PAIRING_WINDOW_S))
+}
+
+/// Forget all the paired remotes. New ones can pair in the next pairing window
+pub fn unpair_remotes() {
+    info!("Forgetting the paired remotes");
+    critical_section::with(|cs| PAIRED_REMOTES.borrow_ref_mut(cs).clear());
+    if let Err(err) = save_remotes(&[]) {
+        report_error(err);
+    }
+}
+
+/// Add the remote to the peers of ESP-NOW and to the paired remotes
+/// Stays a peer after being unpaired, so it might already be one
+fn add_remote(manager: &EspNowManager<'static>, address: [u8; 6]) -> Result<(), EspNowError> {
+    if !manager.peer_exists(&address) {
+        manager.add_peer(PeerInfo {
+            interface: esp_radio::esp_now::EspNowWifiInterface::Sta,
+            peer_address: address,
+            lmk: ESP_NOW_ENCRYPT.then_some(ESP_NOW_LMK),
+            channel: None,
+            encrypt: ESP_NOW_ENCRYPT,
+        })?;
+    }
+
+    critical_section::with(|cs| {
+        let added = PAIRED_REMOTES
+            .borrow_ref_mut(cs)
+            .push(PairedRemote::new(address))
+            .is_ok();
+        assert!(added, "Checked before adding");
+    });
+    Ok(())
+}
+
+/// Add the remote, store it in the settings and let it know that it is paired
+async fn pair(
+    manager: &EspNowManager<'static>,
+    sender: &'static Mutex<NoopRawMutex, EspNowSender<'static>>,
+    address: [u8; 6],
+) {
+    if get_paired_addresses().is_full() {
+        error!(
+            "Can not pair more than {} remotes. Send go:unpair to forget them",
+            MAX_REMOTES
+        );
+        return;
+    }
+
+    if let Err(err) = add_remote(manager, address) {
+        report_error(err);
+        return;
+    }
+    info!("Paired remote {:?}", address);
+    if let Err(err) = save_remotes(&get_paired_addresses()) {
+        report_error(err);
+    }
+
+    // Signal that we are paired
+    send_heartbeat_packet(sender, &address).await;
+}
+
+/// Apply the value of the packet clamping negative values to 0
+fn apply_remote_value(packet: &M5Packet, setter: fn(u32) -> Result<(), ValueError>) {
+    let result = match remote_value_to_u32(packet.value) {
+        Ok(value) => setter(value),
+        Err(err @ ValueError::OutOfRange { accepted }) => setter(accepted as u32).and(Err(err)),
+        Err(err) => Err(err),
+    };
+    log_value_error(packet, result);
+}
+
+/// The M5 protocol has no way of reporting errors back. Log them instead
+fn log_value_error(packet: &M5Packet, result: Result<(), ValueError>) {
+    if let Err(err) = result {
+        error!(
+            "Value {} for {:?} not accepted: {}",
+            packet.value, packet.command, err
+        );
+    }
+}
+
 /// Task to get the motor packets and update the state
 #[embassy_executor::task]
 pub async fn m5_task(
@@ -103,42 +515,117 @@ pub async fn m5_task(
 ) {
     info!("Task M5 Listener Started");
 
+    if ESP_NOW_ENCRYPT {
+        if let Err(err) = manager.set_pmk(&ESP_NOW_PMK) {
+            report_error(err);
+        }
+    }
+    for address in load_remotes() {
+        match add_remote(manager, address) {
+            Ok(()) => info!("Paired remote {:?} loaded", address),
+            Err(err) => report_error(err),
+        }
+    }
+    // The remote is usually turned on together with the machine
+    open_pairing_window();
+
     loop {
         let r = receiver.receive_async().await;
         // info!("Received {:?}", r);
 
         let data = r.data();
-        let packet = match M5Packet::try_ref_from_bytes(data) {
-            Ok(packet) => packet,
-            Err(err) => {
-                error!(
-                    "Failed to parse the M5 Packet {:?}",
-                    err
-                );
+        // Other machines leading do not pair
+        if receive_sync_packet(r.info.src_address, data) {
+            continue;
+        }
+        let (packet, sequence) = match M5SequencedPacket::try_ref_from_bytes(data) {
+            Ok(sequenced) => (&sequenced.packet, Some(sequenced.sequence)),
+            Err(_) => match M5Packet::try_ref_from_bytes(data) {
+                Ok(packet) => (packet, None),
+                Err(err) => {
+                    error!("Failed to parse the M5 Packet {:?}", err);
+                    continue;
+                }
+            },
+        };
+
+        // Anyone in range can send packets. Only the paired remotes are listened to
+        let address = r.info.src_address;
+        if !is_paired(&address) {
+            if packet.target == OSSM_ID && r.info.dst_address == BROADCAST_ADDRESS && is_pairing() {
+                pair(manager, sender, address).await;
+            } else {
+                error!("Ignoring a packet from unknown peer {:?}", address);
+            }
+            continue;
+        }
+
+        if let Some(sequence) = sequence {
+            if let M5Command::Ack = packet.command {
+                acknowledged(&address, sequence);
                 continue;
             }
-        };
+
+            send_to(sender, &address, &M5SequencedPacket::ack(sequence)).await;
+            if is_duplicate(&address, sequence) {
+                continue;
+            }
+        }
 
         if let M5Command::Heartbeat = packet.command {
         } else {
             info!("M5 Packet {:?}", packet);
         }
 
+        if packet.command.changes_motion() && !claim_control(ControlSource::M5) {
+            error!("The {} remote is in control", get_control_source().name());
+            continue;
+        }
+
         match packet.command {
+            M5Command::Stop => {
+                emergency_stop();
+                release_control();
+                let packet = M5Packet {
+                    target: M5_ID,
+                    command: M5Command::Off,
+                    ..Default::default()
+                };
+                send_command(sender, &address, packet).await;
+            }
+            M5Command::Arm => {
+                let command = match arm() {
+                    Ok(()) => M5Command::Arm,
+                    Err(err) => {
+                        error!("Could not arm {:?}", err);
+                        M5Command::Off
+                    }
+                };
+                let packet = M5Packet {
+                    target: M5_ID,
+                    command,
+                    ..Default::default()
+                };
+                send_command(sender, &address, packet).await;
+            }
+            // Turning on never arms. The machine stays off until armed with Arm
+            M5Command::On if !is_armed() => {
+                error!("Not turning on. Arm the machine first");
+                let packet = M5Packet {
+                    target: M5_ID,
+                    command: M5Command::Off,
+                    ..Default::default()
+                };
+                send_command(sender, &address, packet).await;
+                release_control();
+            }
             M5Command::On => {
                 let packet = M5Packet {
                     target: M5_ID,
                     command: M5Command::On,
                     ..Default::default()
                 };
-                let peer = manager
-                    .fetch_peer(true)
-                    .expect("Peer not found even though packet received");
-                let mut sender = sender.lock().await;
-                sender
-                    .send_async(&peer.peer_address, packet.as_bytes())
-                    .await
-                    .expect("Could not send ON packet");
+                send_command(sender, &address, packet).await;
                 set_motion_enabled(true);
             }
             M5Command::Off => {
@@ -147,97 +634,181 @@ pub async fn m5_task(
                     command: M5Command::Off,
                     ..Default::default()
                 };
-                let peer = manager
-                    .fetch_peer(true)
-                    .expect("Peer not found even though packet received");
-                let mut sender = sender.lock().await;
-                sender
-                    .send_async(&peer.peer_address, packet.as_bytes())
-                    .await
-                    .expect("Could not send OFF packet");
+                send_command(sender, &address, packet).await;
                 set_motion_enabled(false);
+                release_control();
+            }
+            M5Command::Home => match request_homing() {
+                Ok(()) => {
+                    // The remote shows the motion as off like after its own Off
+                    let packet = M5Packet {
+                        target: M5_ID,
+                        command: M5Command::Off,
+                        ..Default::default()
+                    };
+                    send_command(sender, &address, packet).await;
+                    release_control();
+                }
+                Err(err) => error!("Could not home again {:?}", err),
+            },
+            M5Command::Pause | M5Command::Resume => {
+                if let M5Command::Pause = packet.command {
+                    pause();
+                } else {
+                    resume();
+                }
+                let packet = M5Packet {
+                    target: M5_ID,
+                    command: packet.command.clone(),
+                    ..Default::default()
+                };
+                send_command(sender, &address, packet).await;
             }
             M5Command::Speed => {
-                set_motion_velocity_mm_s(packet.value as u32);
+                apply_remote_value(packet, set_motion_velocity_mm_s);
             }
             M5Command::Depth => {
-                set_motion_depth_mm(packet.value as u32);
+                apply_remote_value(packet, set_motion_depth_mm);
             }
             M5Command::Stroke => {
-                set_motion_length_mm(packet.value as u32);
+                apply_remote_value(packet, set_motion_length_mm);
+            }
+            M5Command::Bpm => {
+                apply_remote_value(packet, set_motion_bpm);
+            }
+            M5Command::TorqueF => {
+                apply_remote_value(packet, set_motion_torque_forward_pct);
+            }
+            M5Command::TorqueR => {
+                apply_remote_value(packet, set_motion_torque_reverse_pct);
             }
             M5Command::Sensation => {
-                set_motion_sensation_neg_pos_100(packet.value as i32);
+                let result =
+                    remote_value_to_i32(packet.value).and_then(set_motion_sensation_neg_pos_100);
+                log_value_error(packet, result);
             }
             M5Command::Pattern => {
-                set_motion_pattern(packet.value as u32);
+                // The index in the pattern menu of the M5 remote or an ID from the pattern list
+                let result = remote_value_to_u32(packet.value)
+                    .and_then(|value| set_motion_pattern(pattern_id_from_m5_value(value)));
+                log_value_error(packet, result);
+            }
+            M5Command::Patterns => {
+                send_pattern_list(sender, &address).await;
+            }
+            M5Command::NextPreset => {
+                if let Err(err) = load_next_preset() {
+                    error!("Could not load the next preset {:?}", err);
+                }
             }
             M5Command::Heartbeat => {
-                let now = Instant::now().as_millis();
-                LAST_HEARTBEAT.store(now, Ordering::Release);
+                LAST_HEARTBEAT.store_now();
             }
             _ => {}
         }
-
-        if packet.target == OSSM_ID
-            && r.info.dst_address == BROADCAST_ADDRESS
-            && !manager.peer_exists(&r.info.src_address)
-        {
-            let peer = PeerInfo {
-                interface: esp_radio::esp_now::EspNowWifiInterface::Sta,
-                peer_address: r.info.src_address,
-                lmk: None,
-                channel: None,
-                encrypt: false,
-            };
-            manager.add_peer(peer).unwrap();
-            info!("Added new peer {:?}", r.info.src_address);
-
-            // Signal that we are paired
-            send_heartbeat_packet(sender, &peer).await;
-        }
     }
 }
 
 /// Task to check the heartbeats from the remote
-/// and shut the machine off
+/// and emergency stop the machine if they stop during motion
 #[embassy_executor::task]
 pub async fn m5_heartbeat_check_task() {
     info!("Task M5 Heartbeat Check Started");
 
     let mut ticker = Ticker::every(Duration::from_millis(1000));
     loop {
-        let last_heartbeat = Instant::from_millis(LAST_HEARTBEAT.load(Ordering::Acquire));
-        let elapsed = last_heartbeat.elapsed().as_millis();
+        // No heartbeat since boot means not connected
+        let connected = LAST_HEARTBEAT.is_within(Duration::from_millis(get_heartbeat_timeout_ms()));
 
-        CONNECTED.store(elapsed <= MAX_NO_REMOTE_HEARTBEAT_MS, Ordering::Release);
+        let was_connected = CONNECTED.swap(connected, Ordering::AcqRel);
+        if was_connected && !connected && get_motion_state().motion_enabled {
+            error!("Lost the M5 remote during motion");
+            publish_event(Event::HeartbeatLost);
+            raise_fault(FaultCode::HeartbeatLost);
+        }
 
         ticker.next().await;
     }
 }
 
-/// Task to send heartbeats to the remote
+/// Task to send heartbeats to the paired remotes
 #[embassy_executor::task]
-pub async fn m5_heartbeat_task(
-    manager: &'static EspNowManager<'static>,
-    sender: &'static Mutex<NoopRawMutex, EspNowSender<'static>>,
-) {
+pub async fn m5_heartbeat_task(sender: &'static Mutex<NoopRawMutex, EspNowSender<'static>>) {
     info!("Task M5 Heartbeat Started");
 
-    let mut ticker = Ticker::every(Duration::from_millis(5000));
+    let mut ticker = Ticker::every(Duration::from_millis(REMOTE_HEARTBEAT_INTERVAL_MS));
 
     loop {
         ticker.next().await;
 
-        let peer = match manager.fetch_peer(true) {
-            Ok(peer) => peer,
-            Err(_err) => {
-                // Peer not found
-                continue;
+        for address in get_paired_addresses() {
+            send_heartbeat_packet(sender, &address).await;
+        }
+    }
+}
+
+/// Task to send the commands again that the remotes did not acknowledge
+#[embassy_executor::task]
+pub async fn m5_retry_task(sender: &'static Mutex<NoopRawMutex, EspNowSender<'static>>) {
+    info!("Task M5 Retry Started");
+
+    let mut ticker = Ticker::every(Duration::from_millis(ESP_NOW_ACK_TIMEOUT_MS));
+
+    loop {
+        ticker.next().await;
+
+        let mut retries: Vec<([u8; 6], M5SequencedPacket), MAX_REMOTES> = Vec::new();
+        let mut gave_up = false;
+        critical_section::with(|cs| {
+            for remote in PAIRED_REMOTES.borrow_ref_mut(cs).iter_mut() {
+                match remote.pending.as_mut() {
+                    Some(retry) if retry.retries < ESP_NOW_MAX_RETRIES => {
+                        retry.retries += 1;
+                        retries.push((remote.address, retry.packet.clone())).ok();
+                    }
+                    Some(_) => {
+                        remote.pending = None;
+                        gave_up = true;
+                    }
+                    None => {}
+                }
             }
-        };
+        });
+
+        if gave_up {
+            report_error(RemoteError::NotAcknowledged);
+        }
+        for (address, packet) in retries {
+            send_to(sender, &address, &packet).await;
+        }
+    }
+}
 
-        send_heartbeat_packet(sender, &peer).await;
+/// Task to send the full motion state to the paired remotes
+#[embassy_executor::task]
+pub async fn m5_state_task(sender: &'static Mutex<NoopRawMutex, EspNowSender<'static>>) {
+    info!("Task M5 State Started");
+
+    let mut ticker = Ticker::every(Duration::from_millis(M5_STATE_INTERVAL_MS));
+
+    loop {
+        ticker.next().await;
+
+        let packet = M5StatePacket::new();
+        for address in get_paired_addresses() {
+            send_to(sender, &address, &packet).await;
+        }
+    }
+}
+
+/// Task to open the pairing window whenever the pairing button is pressed
+/// Also the button to press before arming if that is required
+#[embassy_executor::task]
+pub async fn pair_button_task(mut button: Input<'static>) {
+    loop {
+        button.wait_for_falling_edge().await;
+        open_pairing_window();
+        arm_button_pressed();
     }
 }
 
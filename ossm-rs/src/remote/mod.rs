@@ -1,21 +1,143 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
 use embassy_time::{Duration, Ticker};
+use log::error;
 
-use ossm_motion::motion::motion_state::set_motion_enabled;
+use ossm_motion::{
+    motion::motion_state::{get_motion_state, set_control_source, set_motion_enabled},
+    time::AtomicTimestamp,
+};
 
-use crate::remote::{ble::is_ble_connected, esp_now::is_m5_connected};
+use crate::{
+    config::CONTROL_TIMEOUT_MS,
+    network::{mqtt::is_mqtt_connected, web::is_web_connected},
+    remote::{
+        ble::{is_ble_connected, is_ble_controller_connected},
+        console::is_console_connected,
+        esp_now::is_m5_connected,
+    },
+};
 
 pub mod ble;
+pub mod console;
 pub mod esp_now;
+pub mod sync;
+
+// How often the connections of the remotes are checked
+const REMOTE_CHECK_INTERVAL_MS: u64 = 250;
+
+// The remote in control of the motion as a `ControlSource`
+static CONTROL: AtomicU8 = AtomicU8::new(ControlSource::None as u8);
+// The last motion command of the remote in control
+static LAST_CONTROL: AtomicTimestamp = AtomicTimestamp::never();
+
+/// The remotes that can control the motion
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum ControlSource {
+    None = 0,
+    M5 = 1,
+    Ble = 2,
+    Console = 3,
+    Web = 4,
+    Mqtt = 5,
+}
+
+impl ControlSource {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => ControlSource::M5,
+            2 => ControlSource::Ble,
+            3 => ControlSource::Console,
+            4 => ControlSource::Web,
+            5 => ControlSource::Mqtt,
+            _ => ControlSource::None,
+        }
+    }
+
+    /// Name reported in the state JSON
+    pub fn name(self) -> &'static str {
+        match self {
+            ControlSource::None => "none",
+            ControlSource::M5 => "m5",
+            ControlSource::Ble => "ble",
+            ControlSource::Console => "console",
+            ControlSource::Web => "web",
+            ControlSource::Mqtt => "mqtt",
+        }
+    }
 
+    fn is_connected(self) -> bool {
+        match self {
+            ControlSource::None => false,
+            ControlSource::M5 => is_m5_connected(),
+            ControlSource::Ble => is_ble_controller_connected(),
+            ControlSource::Console => is_console_connected(),
+            ControlSource::Web => is_web_connected(),
+            ControlSource::Mqtt => is_mqtt_connected(),
+        }
+    }
+}
+
+/// Take the control of the motion before applying a command that changes it
+/// Granted if no remote is in control, the source already is or the one in control
+/// has not sent a command for `CONTROL_TIMEOUT_MS`
+pub fn claim_control(source: ControlSource) -> bool {
+    let holder = get_control_source();
+    if holder != source
+        && holder != ControlSource::None
+        && LAST_CONTROL.is_within(Duration::from_millis(CONTROL_TIMEOUT_MS))
+    {
+        return false;
+    }
+
+    set_control(source);
+    LAST_CONTROL.store_now();
+    true
+}
+
+/// Give up the control so that any remote can take it
+/// Stopping the motion is accepted from every remote and releases the control
+pub fn release_control() {
+    set_control(ControlSource::None);
+    LAST_CONTROL.clear();
+}
+
+pub fn get_control_source() -> ControlSource {
+    ControlSource::from_u8(CONTROL.load(Ordering::Acquire))
+}
+
+fn set_control(source: ControlSource) {
+    CONTROL.store(source as u8, Ordering::Release);
+    set_control_source(source.name());
+}
+
+/// Task to stop the motion when no remote is connected anymore
+/// or the remote in control went silent, even if others are still connected
 #[embassy_executor::task]
 pub async fn remote_connection_task() {
-    let mut ticker = Ticker::every(Duration::from_millis(1000));
+    let mut ticker = Ticker::every(Duration::from_millis(REMOTE_CHECK_INTERVAL_MS));
 
     loop {
-        if !(is_m5_connected() || is_ble_connected()) {
+        if !(is_m5_connected()
+            || is_ble_connected()
+            || is_console_connected()
+            || is_web_connected()
+            || is_mqtt_connected())
+        {
             set_motion_enabled(false);
         }
 
+        // A remote that went away can neither stop the motion nor give the control back
+        let holder = get_control_source();
+        if holder != ControlSource::None && !holder.is_connected() {
+            if get_motion_state().motion_enabled {
+                error!("Lost the {} remote in control of the motion", holder.name());
+                set_motion_enabled(false);
+            }
+            release_control();
+        }
+
         ticker.next().await;
     }
 }
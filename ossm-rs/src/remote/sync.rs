@@ -0,0 +1,119 @@
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
+use embassy_time::{Duration, Instant, Ticker};
+use esp_radio::esp_now::{EspNowSender, BROADCAST_ADDRESS};
+use log::info;
+use ossm_motion::{
+    float::Real,
+    motion::sync::{follow, get_sync_role, run_due_moves, take_lead_move, SyncMove, SyncRole},
+};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+use crate::{
+    config::{MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS, SYNC_CLOCK_INTERVAL_MS},
+    error::report_error,
+};
+
+// Tells the packets of a leader apart from the ones of the M5 remote
+const SYNC_MAGIC: [u8; 4] = *b"OSYN";
+
+/// A move of the leader or only its clock. Broadcast so that followers in range need no
+/// pairing, which can not be encrypted
+#[derive(Debug, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct SyncPacket {
+    magic: [u8; 4],
+    // 1 if the packet carries a move
+    has_move: u32,
+    // The clock of the leader in ms when it was sent
+    sent_ms: u64,
+    // The move as in `SyncMove`
+    at_ms: u64,
+    fraction: f32,
+    duration_ms: u32,
+}
+
+impl SyncPacket {
+    fn new(sync_move: Option<SyncMove>) -> Self {
+        let mut packet = Self {
+            magic: SYNC_MAGIC,
+            has_move: 0,
+            sent_ms: Instant::now().as_millis(),
+            at_ms: 0,
+            fraction: 0.0,
+            duration_ms: 0,
+        };
+        if let Some(sync_move) = sync_move {
+            packet.has_move = 1;
+            packet.at_ms = sync_move.at.as_millis();
+            packet.fraction = sync_move.fraction as f32;
+            packet.duration_ms = sync_move.duration_ms;
+        }
+        packet
+    }
+
+    fn sync_move(&self) -> Option<SyncMove> {
+        (self.has_move == 1).then(|| SyncMove {
+            at: Instant::from_millis(self.at_ms),
+            fraction: self.fraction as Real,
+            duration_ms: self.duration_ms,
+        })
+    }
+}
+
+/// Follow the packet if it is one of a leader. False if it is not
+/// Packets of leaders that are not followed are dropped
+pub fn receive_sync_packet(address: [u8; 6], data: &[u8]) -> bool {
+    let Some(packet) = SyncPacket::read_from_bytes(data)
+        .ok()
+        .filter(|packet| packet.magic == SYNC_MAGIC)
+    else {
+        return false;
+    };
+
+    let sent = Instant::from_millis(packet.sent_ms);
+    follow(address, sent, packet.sync_move(), Instant::now());
+    true
+}
+
+async fn broadcast(
+    sender: &'static Mutex<NoopRawMutex, EspNowSender<'static>>,
+    packet: SyncPacket,
+) {
+    let mut sender = sender.lock().await;
+    if let Err(err) = sender
+        .send_async(&BROADCAST_ADDRESS, packet.as_bytes())
+        .await
+    {
+        report_error(err);
+    }
+}
+
+/// Task to broadcast the moves of a leader and to make the due ones of a follower
+#[embassy_executor::task]
+pub async fn sync_task(sender: &'static Mutex<NoopRawMutex, EspNowSender<'static>>) {
+    info!("Task Sync Started");
+
+    let mut ticker = Ticker::every(Duration::from_millis(
+        MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS,
+    ));
+    let mut clock_sent = Instant::MIN;
+    loop {
+        ticker.next().await;
+
+        match get_sync_role() {
+            SyncRole::Off => {}
+            SyncRole::Leader => {
+                while let Some(sync_move) = take_lead_move() {
+                    broadcast(sender, SyncPacket::new(Some(sync_move))).await;
+                    clock_sent = Instant::now();
+                }
+                // Keeps the clock offset of the followers fresh between moves
+                if clock_sent.elapsed() >= Duration::from_millis(SYNC_CLOCK_INTERVAL_MS) {
+                    broadcast(sender, SyncPacket::new(None)).await;
+                    clock_sent = Instant::now();
+                }
+            }
+            SyncRole::Follower => run_due_moves(Instant::now()),
+        }
+    }
+}
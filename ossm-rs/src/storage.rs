@@ -0,0 +1,755 @@
+use core::{cell::RefCell, net::Ipv4Addr};
+
+use critical_section::Mutex;
+use embassy_time::{Duration, Ticker};
+use embedded_storage::{ReadStorage, Storage};
+use esp_bootloader_esp_idf::partitions::{
+    self, DataPartitionSubType, PartitionType, PARTITION_TABLE_MAX_LEN,
+};
+use esp_hal::peripherals::FLASH;
+use esp_storage::FlashStorage;
+use heapless::{String, Vec};
+use log::{error, info, warn};
+use ossm_motion::{
+    float::Real,
+    motion::motion_state::get_motion_state,
+    motion_control::mechanics::Mechanics,
+    preset::Preset,
+    profile::{Profile, ProfileLimits},
+    session::Session,
+    session_limits::SessionLimits,
+    thermal::ThermalLimits,
+};
+use zerocopy::{FromBytes, FromZeros, Immutable, IntoBytes, KnownLayout};
+
+use crate::{
+    config::{
+        MAX_PRESETS, MAX_PRESET_NAME_LENGTH, MAX_PROFILES, MAX_PROFILE_NAME_LENGTH, MAX_REMOTES,
+        REVERSE_DIRECTION, SESSION_SAVE_INTERVAL_MS,
+    },
+    error::{report_error, ConfigError},
+    network::{
+        buttplug::IntifaceServer,
+        mqtt::{MqttBroker, MAX_MQTT_PASSWORD_LENGTH, MAX_MQTT_USER_LENGTH},
+        syslog::SyslogServer,
+        wifi::{WifiCredentials, MAX_SSID_LENGTH, MAX_WIFI_PASSWORD_LENGTH},
+    },
+    ota::OtaTrial,
+};
+
+// Identifies a record written by this firmware. "OSSM" in ASCII
+const SETTINGS_MAGIC: u32 = 0x4D53_534F;
+// Bump when the layout of StoredSettings changes. Older records are ignored
+const SETTINGS_VERSION: u32 = 14;
+
+static STORAGE: Mutex<RefCell<Option<SettingsStorage>>> = Mutex::new(RefCell::new(None));
+
+/// Stored as a single record at the start of the NVS partition
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct StoredSettings {
+    magic: u32,
+    version: u32,
+    // The usable travel measured by the calibration in mm. 0 if never calibrated
+    max_travel_mm: f32,
+    // How many of the addresses below are paired ESP-NOW remotes
+    remote_count: u32,
+    remotes: [[u8; 6]; MAX_REMOTES],
+    // The WiFi network to connect to. No network if the SSID is empty
+    wifi_ssid_length: u32,
+    wifi_ssid: [u8; MAX_SSID_LENGTH],
+    wifi_password_length: u32,
+    wifi_password: [u8; MAX_WIFI_PASSWORD_LENGTH],
+    // The MQTT broker to connect to. No broker if the port is 0
+    mqtt_address: [u8; 4],
+    mqtt_port: u32,
+    mqtt_user_length: u32,
+    mqtt_user: [u8; MAX_MQTT_USER_LENGTH],
+    mqtt_password_length: u32,
+    mqtt_password: [u8; MAX_MQTT_PASSWORD_LENGTH],
+    // The Intiface server to connect to. No server if the port is 0
+    intiface_address: [u8; 4],
+    intiface_port: u32,
+    // Where the firmware is in trying out an update as an `OtaTrial`
+    ota_trial: u32,
+    // The syslog server to send the log to. No server if the port is 0
+    syslog_address: [u8; 4],
+    syslog_port: u32,
+    // How many of the presets below are saved
+    preset_count: u32,
+    presets: [StoredPreset; MAX_PRESETS],
+    // The motion settings of the last session
+    session: StoredSession,
+    // 1 if positive positions are positive steps of the motor
+    reverse_direction: u32,
+    // How the turns of the motor translate to the stroke
+    pulley_tooth_count: u32,
+    belt_pitch: f32,
+    motor_steps_per_revolution: u32,
+    // Caps of the speed in % and the depth in mm. Infinite if the depth is not capped
+    session_max_velocity: u32,
+    session_max_depth_mm: f32,
+    // The PIN to change the caps. No PIN if session_limits_locked is 0
+    session_limits_locked: u32,
+    session_limits_pin: u32,
+    // The temperatures in °C the motion is limited and stopped at
+    motor_warning_c: f32,
+    motor_critical_c: f32,
+    mcu_warning_c: f32,
+    mcu_critical_c: f32,
+    // How many of the profiles below are saved. The defaults are used if none are
+    profile_count: u32,
+    profiles: [StoredProfile; MAX_PROFILES],
+    active_profile: u32,
+}
+
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct StoredPreset {
+    name_length: u32,
+    name: [u8; MAX_PRESET_NAME_LENGTH],
+    depth: u32,
+    stroke: u32,
+    speed: u32,
+    sensation: u32,
+    pattern: u32,
+    torque_out: u32,
+    torque_in: u32,
+}
+
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct StoredProfile {
+    name_length: u32,
+    name: [u8; MAX_PROFILE_NAME_LENGTH],
+    velocity: u32,
+    depth: u32,
+    torque: u32,
+    sensation: u32,
+    pattern: u32,
+    // No PIN if locked is 0
+    locked: u32,
+    pin: u32,
+}
+
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+struct StoredSession {
+    // 0 if no session was saved yet
+    saved: u32,
+    pattern: u32,
+    depth: u32,
+    stroke: u32,
+    speed: u32,
+    min_position: f32,
+    max_position: f32,
+}
+
+impl Default for StoredSettings {
+    fn default() -> Self {
+        Self {
+            magic: SETTINGS_MAGIC,
+            version: SETTINGS_VERSION,
+            max_travel_mm: 0.0,
+            remote_count: 0,
+            remotes: [[0; 6]; MAX_REMOTES],
+            wifi_ssid_length: 0,
+            wifi_ssid: [0; MAX_SSID_LENGTH],
+            wifi_password_length: 0,
+            wifi_password: [0; MAX_WIFI_PASSWORD_LENGTH],
+            mqtt_address: [0; 4],
+            mqtt_port: 0,
+            mqtt_user_length: 0,
+            mqtt_user: [0; MAX_MQTT_USER_LENGTH],
+            mqtt_password_length: 0,
+            mqtt_password: [0; MAX_MQTT_PASSWORD_LENGTH],
+            intiface_address: [0; 4],
+            intiface_port: 0,
+            ota_trial: OtaTrial::None as u32,
+            syslog_address: [0; 4],
+            syslog_port: 0,
+            preset_count: 0,
+            presets: [StoredPreset::new_zeroed(); MAX_PRESETS],
+            session: StoredSession::new_zeroed(),
+            reverse_direction: REVERSE_DIRECTION as u32,
+            pulley_tooth_count: Mechanics::DEFAULT.pulley_tooth_count,
+            belt_pitch: Mechanics::DEFAULT.belt_pitch as f32,
+            motor_steps_per_revolution: Mechanics::DEFAULT.motor_steps_per_revolution,
+            session_max_velocity: SessionLimits::UNRESTRICTED.velocity,
+            session_max_depth_mm: f32::INFINITY,
+            session_limits_locked: 0,
+            session_limits_pin: 0,
+            motor_warning_c: ThermalLimits::DEFAULT.motor_warning_c as f32,
+            motor_critical_c: ThermalLimits::DEFAULT.motor_critical_c as f32,
+            mcu_warning_c: ThermalLimits::DEFAULT.mcu_warning_c as f32,
+            mcu_critical_c: ThermalLimits::DEFAULT.mcu_critical_c as f32,
+            profile_count: 0,
+            profiles: [StoredProfile::new_zeroed(); MAX_PROFILES],
+            active_profile: 0,
+        }
+    }
+}
+
+struct SettingsStorage {
+    flash: FlashStorage<'static>,
+    // Where the record is in the flash
+    offset: u32,
+}
+
+impl SettingsStorage {
+    fn read(&mut self) -> Option<StoredSettings> {
+        let mut bytes = [0u8; size_of::<StoredSettings>()];
+        if let Err(err) = self.flash.read(self.offset, &mut bytes) {
+            error!("Failed to read the settings {:?}", err);
+            return None;
+        }
+
+        let settings = StoredSettings::read_from_bytes(&bytes).ok()?;
+        if settings.magic != SETTINGS_MAGIC || settings.version != SETTINGS_VERSION {
+            info!("No stored settings found");
+            return None;
+        }
+
+        Some(settings)
+    }
+
+    fn write(&mut self, settings: &StoredSettings) -> Result<(), ConfigError> {
+        self.flash
+            .write(self.offset, settings.as_bytes())
+            .map_err(|err| {
+                error!("Failed to write the settings {:?}", err);
+                ConfigError::Storage
+            })
+    }
+}
+
+/// Find the settings partition. Has to be called before the settings can be loaded or saved
+pub fn init(flash: FLASH<'static>) {
+    let flash = FlashStorage::new(flash);
+    // The other core runs code from the flash that is unavailable while writing
+    #[cfg(feature = "multicore")]
+    let flash = flash.multicore_auto_park();
+    let mut flash = flash;
+
+    let mut table_buffer = [0u8; PARTITION_TABLE_MAX_LEN];
+    let offset = match partitions::read_partition_table(&mut flash, &mut table_buffer) {
+        Ok(table) => match table.find_partition(PartitionType::Data(DataPartitionSubType::Nvs)) {
+            Ok(Some(partition)) => partition.offset(),
+            Ok(None) => {
+                error!("No NVS partition. Settings will not be saved");
+                return;
+            }
+            Err(err) => {
+                error!("Failed to find the NVS partition {:?}", err);
+                return;
+            }
+        },
+        Err(err) => {
+            error!("Failed to read the partition table {:?}", err);
+            return;
+        }
+    };
+
+    info!("Settings stored at 0x{:x}", offset);
+    critical_section::with(|cs| {
+        STORAGE
+            .borrow_ref_mut(cs)
+            .replace(SettingsStorage { flash, offset });
+    });
+}
+
+/// Use the flash for something else than the settings, e.g. writing an update
+/// None if the storage is not available
+pub fn with_flash<R>(f: impl FnOnce(&mut FlashStorage<'static>) -> R) -> Option<R> {
+    with_storage(|storage| f(&mut storage.flash))
+}
+
+fn with_storage<R>(f: impl FnOnce(&mut SettingsStorage) -> R) -> Option<R> {
+    // Taken out so that the flash is not accessed inside of a critical section
+    let mut storage = critical_section::with(|cs| STORAGE.borrow_ref_mut(cs).take())?;
+
+    let result = f(&mut storage);
+
+    critical_section::with(|cs| {
+        STORAGE.borrow_ref_mut(cs).replace(storage);
+    });
+
+    Some(result)
+}
+
+/// The travel measured by the last calibration in mm
+pub fn load_max_travel_mm() -> Option<Real> {
+    let settings = with_storage(|storage| storage.read()).flatten()?;
+
+    if settings.max_travel_mm > 0.0 {
+        Some(settings.max_travel_mm as Real)
+    } else {
+        None
+    }
+}
+
+/// Store the travel measured by the calibration in mm
+pub fn save_max_travel_mm(travel: Real) -> Result<(), ConfigError> {
+    with_storage(|storage| {
+        let mut settings = storage.read().unwrap_or_default();
+        settings.max_travel_mm = travel as f32;
+        storage.write(&settings)
+    })
+    .unwrap_or_else(|| {
+        warn!("The settings storage is not available");
+        Err(ConfigError::Storage)
+    })
+}
+
+/// The addresses of the paired ESP-NOW remotes
+pub fn load_remotes() -> Vec<[u8; 6], MAX_REMOTES> {
+    let Some(settings) = with_storage(|storage| storage.read()).flatten() else {
+        return Vec::new();
+    };
+
+    let count = (settings.remote_count as usize).min(MAX_REMOTES);
+    Vec::from_slice(&settings.remotes[..count]).expect("Sized for all remotes")
+}
+
+/// Store the addresses of the paired ESP-NOW remotes. Replaces the ones stored before
+pub fn save_remotes(remotes: &[[u8; 6]]) -> Result<(), ConfigError> {
+    with_storage(|storage| {
+        let mut settings = storage.read().unwrap_or_default();
+        let count = remotes.len().min(MAX_REMOTES);
+        settings.remote_count = count as u32;
+        settings.remotes = [[0; 6]; MAX_REMOTES];
+        settings.remotes[..count].copy_from_slice(&remotes[..count]);
+        storage.write(&settings)
+    })
+    .unwrap_or_else(|| {
+        warn!("The settings storage is not available");
+        Err(ConfigError::Storage)
+    })
+}
+
+/// The WiFi network to connect to
+pub fn load_wifi_credentials() -> Option<WifiCredentials> {
+    let settings = with_storage(|storage| storage.read()).flatten()?;
+
+    let ssid_length = (settings.wifi_ssid_length as usize).min(MAX_SSID_LENGTH);
+    let password_length = (settings.wifi_password_length as usize).min(MAX_WIFI_PASSWORD_LENGTH);
+    let ssid = core::str::from_utf8(&settings.wifi_ssid[..ssid_length]).ok()?;
+    let password = core::str::from_utf8(&settings.wifi_password[..password_length]).ok()?;
+
+    WifiCredentials::new(ssid, password)
+}
+
+/// Store the WiFi network to connect to. None forgets it
+pub fn save_wifi_credentials(credentials: Option<&WifiCredentials>) -> Result<(), ConfigError> {
+    with_storage(|storage| {
+        let mut settings = storage.read().unwrap_or_default();
+        settings.wifi_ssid = [0; MAX_SSID_LENGTH];
+        settings.wifi_password = [0; MAX_WIFI_PASSWORD_LENGTH];
+        let (ssid, password) = credentials.map_or(("", ""), |credentials| {
+            (credentials.ssid.as_str(), credentials.password.as_str())
+        });
+        settings.wifi_ssid_length = ssid.len() as u32;
+        settings.wifi_ssid[..ssid.len()].copy_from_slice(ssid.as_bytes());
+        settings.wifi_password_length = password.len() as u32;
+        settings.wifi_password[..password.len()].copy_from_slice(password.as_bytes());
+        storage.write(&settings)
+    })
+    .unwrap_or_else(|| {
+        warn!("The settings storage is not available");
+        Err(ConfigError::Storage)
+    })
+}
+
+/// The MQTT broker to connect to
+pub fn load_mqtt_broker() -> Option<MqttBroker> {
+    let settings = with_storage(|storage| storage.read()).flatten()?;
+    if settings.mqtt_port == 0 {
+        return None;
+    }
+
+    let user_length = (settings.mqtt_user_length as usize).min(MAX_MQTT_USER_LENGTH);
+    let password_length = (settings.mqtt_password_length as usize).min(MAX_MQTT_PASSWORD_LENGTH);
+    let user = core::str::from_utf8(&settings.mqtt_user[..user_length]).ok()?;
+    let password = core::str::from_utf8(&settings.mqtt_password[..password_length]).ok()?;
+
+    MqttBroker::new(
+        Ipv4Addr::from(settings.mqtt_address),
+        settings.mqtt_port as u16,
+        user,
+        password,
+    )
+}
+
+/// Store the MQTT broker to connect to. None forgets it
+pub fn save_mqtt_broker(broker: Option<&MqttBroker>) -> Result<(), ConfigError> {
+    with_storage(|storage| {
+        let mut settings = storage.read().unwrap_or_default();
+        settings.mqtt_user = [0; MAX_MQTT_USER_LENGTH];
+        settings.mqtt_password = [0; MAX_MQTT_PASSWORD_LENGTH];
+        let (address, port, user, password) =
+            broker.map_or((Ipv4Addr::UNSPECIFIED, 0, "", ""), |broker| {
+                (
+                    broker.address,
+                    broker.port,
+                    broker.user.as_str(),
+                    broker.password.as_str(),
+                )
+            });
+        settings.mqtt_address = address.octets();
+        settings.mqtt_port = port as u32;
+        settings.mqtt_user_length = user.len() as u32;
+        settings.mqtt_user[..user.len()].copy_from_slice(user.as_bytes());
+        settings.mqtt_password_length = password.len() as u32;
+        settings.mqtt_password[..password.len()].copy_from_slice(password.as_bytes());
+        storage.write(&settings)
+    })
+    .unwrap_or_else(|| {
+        warn!("The settings storage is not available");
+        Err(ConfigError::Storage)
+    })
+}
+
+/// The Intiface server to connect to
+pub fn load_intiface_server() -> Option<IntifaceServer> {
+    let settings = with_storage(|storage| storage.read()).flatten()?;
+    IntifaceServer::new(
+        Ipv4Addr::from(settings.intiface_address),
+        settings.intiface_port as u16,
+    )
+}
+
+/// Store the Intiface server to connect to. None forgets it
+pub fn save_intiface_server(server: Option<&IntifaceServer>) -> Result<(), ConfigError> {
+    with_storage(|storage| {
+        let mut settings = storage.read().unwrap_or_default();
+        let (address, port) = server.map_or((Ipv4Addr::UNSPECIFIED, 0), |server| {
+            (server.address, server.port)
+        });
+        settings.intiface_address = address.octets();
+        settings.intiface_port = port as u32;
+        storage.write(&settings)
+    })
+    .unwrap_or_else(|| {
+        warn!("The settings storage is not available");
+        Err(ConfigError::Storage)
+    })
+}
+
+/// Where the firmware is in trying out an update
+pub fn load_ota_trial() -> OtaTrial {
+    with_storage(|storage| storage.read())
+        .flatten()
+        .map_or(OtaTrial::None, |settings| {
+            OtaTrial::from_u32(settings.ota_trial)
+        })
+}
+
+/// Store where the firmware is in trying out an update
+pub fn save_ota_trial(trial: OtaTrial) -> Result<(), ConfigError> {
+    with_storage(|storage| {
+        let mut settings = storage.read().unwrap_or_default();
+        settings.ota_trial = trial as u32;
+        storage.write(&settings)
+    })
+    .unwrap_or_else(|| {
+        warn!("The settings storage is not available");
+        Err(ConfigError::Storage)
+    })
+}
+
+/// The syslog server to send the log to
+pub fn load_syslog_server() -> Option<SyslogServer> {
+    let settings = with_storage(|storage| storage.read()).flatten()?;
+    SyslogServer::new(
+        Ipv4Addr::from(settings.syslog_address),
+        settings.syslog_port as u16,
+    )
+}
+
+/// Store the syslog server to send the log to. None forgets it
+pub fn save_syslog_server(server: Option<&SyslogServer>) -> Result<(), ConfigError> {
+    with_storage(|storage| {
+        let mut settings = storage.read().unwrap_or_default();
+        let (address, port) = server.map_or((Ipv4Addr::UNSPECIFIED, 0), |server| {
+            (server.address, server.port)
+        });
+        settings.syslog_address = address.octets();
+        settings.syslog_port = port as u32;
+        storage.write(&settings)
+    })
+    .unwrap_or_else(|| {
+        warn!("The settings storage is not available");
+        Err(ConfigError::Storage)
+    })
+}
+
+/// The saved presets of the motion settings
+pub fn load_presets() -> Vec<Preset, MAX_PRESETS> {
+    let Some(settings) = with_storage(|storage| storage.read()).flatten() else {
+        return Vec::new();
+    };
+
+    let count = (settings.preset_count as usize).min(MAX_PRESETS);
+    settings.presets[..count]
+        .iter()
+        .filter_map(|stored| {
+            let name_length = (stored.name_length as usize).min(MAX_PRESET_NAME_LENGTH);
+            let name = core::str::from_utf8(&stored.name[..name_length]).ok()?;
+            Some(Preset {
+                name: String::try_from(name).ok()?,
+                depth: stored.depth,
+                stroke: stored.stroke,
+                speed: stored.speed,
+                sensation: stored.sensation,
+                pattern: stored.pattern,
+                torque_out: stored.torque_out,
+                torque_in: stored.torque_in,
+            })
+        })
+        .collect()
+}
+
+/// Store the presets of the motion settings. Replaces the ones stored before
+pub fn save_presets(presets: &[Preset]) -> Result<(), ConfigError> {
+    with_storage(|storage| {
+        let mut settings = storage.read().unwrap_or_default();
+        let count = presets.len().min(MAX_PRESETS);
+        settings.preset_count = count as u32;
+        settings.presets = [StoredPreset::new_zeroed(); MAX_PRESETS];
+        for (stored, preset) in settings.presets.iter_mut().zip(&presets[..count]) {
+            stored.name_length = preset.name.len() as u32;
+            stored.name[..preset.name.len()].copy_from_slice(preset.name.as_bytes());
+            stored.depth = preset.depth;
+            stored.stroke = preset.stroke;
+            stored.speed = preset.speed;
+            stored.sensation = preset.sensation;
+            stored.pattern = preset.pattern;
+            stored.torque_out = preset.torque_out;
+            stored.torque_in = preset.torque_in;
+        }
+        storage.write(&settings)
+    })
+    .unwrap_or_else(|| {
+        warn!("The settings storage is not available");
+        Err(ConfigError::Storage)
+    })
+}
+
+/// The saved profiles and the index of the active one. Empty if none were saved
+pub fn load_profiles() -> (Vec<Profile, MAX_PROFILES>, usize) {
+    let Some(settings) = with_storage(|storage| storage.read()).flatten() else {
+        return (Vec::new(), 0);
+    };
+
+    let count = (settings.profile_count as usize).min(MAX_PROFILES);
+    let profiles = settings.profiles[..count]
+        .iter()
+        .filter_map(|stored| {
+            let name_length = (stored.name_length as usize).min(MAX_PROFILE_NAME_LENGTH);
+            let name = core::str::from_utf8(&stored.name[..name_length]).ok()?;
+            Some(Profile {
+                name: String::try_from(name).ok()?,
+                limits: ProfileLimits {
+                    velocity: stored.velocity,
+                    depth: stored.depth,
+                    torque: stored.torque,
+                },
+                sensation: stored.sensation,
+                pattern: stored.pattern,
+                pin: (stored.locked != 0).then_some(stored.pin),
+            })
+        })
+        .collect();
+    (profiles, settings.active_profile as usize)
+}
+
+/// Store the profiles and the index of the active one. Replaces the ones stored before
+pub fn save_profiles(profiles: &[Profile], active: usize) -> Result<(), ConfigError> {
+    with_storage(|storage| {
+        let mut settings = storage.read().unwrap_or_default();
+        let count = profiles.len().min(MAX_PROFILES);
+        settings.profile_count = count as u32;
+        settings.profiles = [StoredProfile::new_zeroed(); MAX_PROFILES];
+        for (stored, profile) in settings.profiles.iter_mut().zip(&profiles[..count]) {
+            stored.name_length = profile.name.len() as u32;
+            stored.name[..profile.name.len()].copy_from_slice(profile.name.as_bytes());
+            stored.velocity = profile.limits.velocity;
+            stored.depth = profile.limits.depth;
+            stored.torque = profile.limits.torque;
+            stored.sensation = profile.sensation;
+            stored.pattern = profile.pattern;
+            stored.locked = profile.pin.is_some() as u32;
+            stored.pin = profile.pin.unwrap_or_default();
+        }
+        settings.active_profile = active as u32;
+        storage.write(&settings)
+    })
+    .unwrap_or_else(|| {
+        warn!("The settings storage is not available");
+        Err(ConfigError::Storage)
+    })
+}
+
+/// The motion settings of the last session
+pub fn load_session() -> Option<Session> {
+    let settings = with_storage(|storage| storage.read()).flatten()?;
+    let stored = settings.session;
+    if stored.saved == 0 {
+        return None;
+    }
+
+    Some(Session {
+        pattern: stored.pattern,
+        depth: stored.depth,
+        stroke: stored.stroke,
+        speed: stored.speed,
+        min_position: stored.min_position as Real,
+        max_position: stored.max_position as Real,
+    })
+}
+
+/// Store the motion settings of the session
+pub fn save_session(session: &Session) -> Result<(), ConfigError> {
+    with_storage(|storage| {
+        let mut settings = storage.read().unwrap_or_default();
+        settings.session = StoredSession {
+            saved: 1,
+            pattern: session.pattern,
+            depth: session.depth,
+            stroke: session.stroke,
+            speed: session.speed,
+            min_position: session.min_position as f32,
+            max_position: session.max_position as f32,
+        };
+        storage.write(&settings)
+    })
+    .unwrap_or_else(|| {
+        warn!("The settings storage is not available");
+        Err(ConfigError::Storage)
+    })
+}
+
+/// Whether the motor turns the other way than the stock machine
+pub fn load_reverse_direction() -> Option<bool> {
+    let settings = with_storage(|storage| storage.read()).flatten()?;
+    Some(settings.reverse_direction != 0)
+}
+
+/// Store whether the motor turns the other way than the stock machine
+pub fn save_reverse_direction(reversed: bool) -> Result<(), ConfigError> {
+    with_storage(|storage| {
+        let mut settings = storage.read().unwrap_or_default();
+        settings.reverse_direction = reversed as u32;
+        storage.write(&settings)
+    })
+    .unwrap_or_else(|| {
+        warn!("The settings storage is not available");
+        Err(ConfigError::Storage)
+    })
+}
+
+/// How the turns of the motor translate to the stroke
+pub fn load_mechanics() -> Option<Mechanics> {
+    let settings = with_storage(|storage| storage.read()).flatten()?;
+    Some(Mechanics {
+        pulley_tooth_count: settings.pulley_tooth_count,
+        belt_pitch: settings.belt_pitch as Real,
+        motor_steps_per_revolution: settings.motor_steps_per_revolution,
+    })
+}
+
+/// Store how the turns of the motor translate to the stroke
+pub fn save_mechanics(mechanics: &Mechanics) -> Result<(), ConfigError> {
+    with_storage(|storage| {
+        let mut settings = storage.read().unwrap_or_default();
+        settings.pulley_tooth_count = mechanics.pulley_tooth_count;
+        settings.belt_pitch = mechanics.belt_pitch as f32;
+        settings.motor_steps_per_revolution = mechanics.motor_steps_per_revolution;
+        storage.write(&settings)
+    })
+    .unwrap_or_else(|| {
+        warn!("The settings storage is not available");
+        Err(ConfigError::Storage)
+    })
+}
+
+/// The caps of the speed and the depth and the PIN to change them
+pub fn load_session_limits() -> Option<(SessionLimits, Option<u32>)> {
+    let settings = with_storage(|storage| storage.read()).flatten()?;
+    let limits = SessionLimits {
+        velocity: settings.session_max_velocity,
+        depth_mm: settings.session_max_depth_mm as Real,
+    };
+    let pin = (settings.session_limits_locked != 0).then_some(settings.session_limits_pin);
+    Some((limits, pin))
+}
+
+/// Store the caps of the speed and the depth and the PIN to change them
+pub fn save_session_limits(limits: &SessionLimits, pin: Option<u32>) -> Result<(), ConfigError> {
+    with_storage(|storage| {
+        let mut settings = storage.read().unwrap_or_default();
+        settings.session_max_velocity = limits.velocity;
+        settings.session_max_depth_mm = limits.depth_mm as f32;
+        settings.session_limits_locked = pin.is_some() as u32;
+        settings.session_limits_pin = pin.unwrap_or_default();
+        storage.write(&settings)
+    })
+    .unwrap_or_else(|| {
+        warn!("The settings storage is not available");
+        Err(ConfigError::Storage)
+    })
+}
+
+/// The temperatures the motion is limited and stopped at
+pub fn load_thermal_limits() -> Option<ThermalLimits> {
+    let settings = with_storage(|storage| storage.read()).flatten()?;
+    Some(ThermalLimits {
+        motor_warning_c: settings.motor_warning_c as Real,
+        motor_critical_c: settings.motor_critical_c as Real,
+        mcu_warning_c: settings.mcu_warning_c as Real,
+        mcu_critical_c: settings.mcu_critical_c as Real,
+    })
+}
+
+/// Store the temperatures the motion is limited and stopped at
+pub fn save_thermal_limits(limits: &ThermalLimits) -> Result<(), ConfigError> {
+    with_storage(|storage| {
+        let mut settings = storage.read().unwrap_or_default();
+        settings.motor_warning_c = limits.motor_warning_c as f32;
+        settings.motor_critical_c = limits.motor_critical_c as f32;
+        settings.mcu_warning_c = limits.mcu_warning_c as f32;
+        settings.mcu_critical_c = limits.mcu_critical_c as f32;
+        storage.write(&settings)
+    })
+    .unwrap_or_else(|| {
+        warn!("The settings storage is not available");
+        Err(ConfigError::Storage)
+    })
+}
+
+/// Save the motion settings once they stopped changing
+/// Writing the flash stalls the motion control, so changes made while the motion is enabled
+/// are saved after it was disabled
+#[embassy_executor::task]
+pub async fn session_save_task() {
+    // Started after the stored session was restored
+    let mut saved = Session::current();
+    let mut previous = saved;
+    let mut ticker = Ticker::every(Duration::from_millis(SESSION_SAVE_INTERVAL_MS));
+
+    loop {
+        ticker.next().await;
+        let session = Session::current();
+        let settled = session == previous;
+        previous = session;
+        if !settled || session == saved || get_motion_state().motion_enabled {
+            continue;
+        }
+
+        // Not tried again until the session changes
+        saved = session;
+        if let Err(err) = save_session(&session) {
+            report_error(err);
+        }
+    }
+}
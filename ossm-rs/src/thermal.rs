@@ -0,0 +1,20 @@
+use embassy_time::{Duration, Ticker};
+use esp_hal::tsens::TemperatureSensor;
+use log::info;
+use ossm_motion::{float::Real, thermal::set_mcu_temperature_c};
+
+use crate::config::TEMPERATURE_UPDATE_INTERVAL_MS;
+
+/// Task to read the MCU temperature every `TEMPERATURE_UPDATE_INTERVAL_MS`
+#[embassy_executor::task]
+pub async fn mcu_temperature_task(sensor: TemperatureSensor<'static>) {
+    info!("Task MCU Temperature Started");
+
+    let mut ticker = Ticker::every(Duration::from_millis(TEMPERATURE_UPDATE_INTERVAL_MS));
+    loop {
+        let temperature = sensor.get_temperature().to_celsius();
+        set_mcu_temperature_c(temperature as Real);
+
+        ticker.next().await;
+    }
+}
@@ -1,4 +1,4 @@
-use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, mpsc::Receiver};
 
 use egui::Color32;
 use egui_plot::{Line, Plot, PlotPoint, PlotPoints};
@@ -16,7 +16,10 @@ use ossm_motion::{
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::plotting::PlotMessage;
+use crate::{
+    motor::{MotorModel, SimMotorConfig},
+    plotting::PlotMessage,
+};
 
 static NUM_POINTS: usize = 2000;
 
@@ -27,6 +30,15 @@ pub struct OssmSim {
     #[serde(skip)]
     rx: Option<Receiver<PlotMessage>>,
 
+    #[serde(skip)]
+    motor_model: Option<Arc<Mutex<MotorModel>>>,
+
+    // What the connection to a real machine is doing. None while simulating
+    #[serde(skip)]
+    device_status: Option<Arc<Mutex<String>>>,
+
+    motor_config: SimMotorConfig,
+
     depth: u32,
 
     length: u32,
@@ -45,6 +57,9 @@ pub struct OssmSim {
     #[serde(skip)]
     position_points: Vec<PlotPoint>,
 
+    #[serde(skip)]
+    motor_position_points: Vec<PlotPoint>,
+
     #[serde(skip)]
     velocity_points: Vec<PlotPoint>,
 
@@ -56,6 +71,9 @@ impl Default for OssmSim {
     fn default() -> Self {
         Self {
             rx: None,
+            motor_model: None,
+            device_status: None,
+            motor_config: SimMotorConfig::default(),
             depth: 0,
             length: 0,
             velocity: 0,
@@ -64,6 +82,7 @@ impl Default for OssmSim {
             patterns: vec![],
             selected_pattern: 0,
             position_points: vec![PlotPoint { x: 0.0, y: 0.0 }; NUM_POINTS],
+            motor_position_points: vec![PlotPoint { x: 0.0, y: 0.0 }; NUM_POINTS],
             velocity_points: vec![PlotPoint { x: 0.0, y: 0.0 }; NUM_POINTS],
             acceleration_points: vec![PlotPoint { x: 0.0, y: 0.0 }; NUM_POINTS],
         }
@@ -78,7 +97,12 @@ struct Pattern {
 
 impl OssmSim {
     /// Called once before the first frame.
-    pub fn new(cc: &eframe::CreationContext<'_>, rx: Receiver<PlotMessage>) -> Self {
+    pub fn new(
+        cc: &eframe::CreationContext<'_>,
+        rx: Receiver<PlotMessage>,
+        motor_model: Arc<Mutex<MotorModel>>,
+        device_status: Option<Arc<Mutex<String>>>,
+    ) -> Self {
         // This is also where you can customize the look and feel of egui using
         // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
 
@@ -94,6 +118,10 @@ impl OssmSim {
             app
         };
 
+        motor_model.lock().expect("Motor model poisoned").config = app.motor_config;
+        app.motor_model = Some(motor_model);
+        app.device_status = device_status;
+
         let patterns = PatternExecutor::new().get_all_patterns_json();
         let patterns: Vec<Pattern> =
             serde_json::from_str(&patterns).expect("Could not parse patterns");
@@ -104,11 +132,12 @@ impl OssmSim {
 
         log::info!("Patterns: {:?}", app.patterns);
 
-        set_motion_depth_pct(app.depth);
-        set_motion_length_pct(app.length);
-        set_motion_velocity_pct(app.velocity);
-        set_motion_sensation_pct(app.sensation);
-        set_motion_pattern(app.patterns[app.selected_pattern].1);
+        // The sliders only allow values in range
+        set_motion_depth_pct(app.depth).ok();
+        set_motion_length_pct(app.length).ok();
+        set_motion_velocity_pct(app.velocity).ok();
+        set_motion_sensation_pct(app.sensation).ok();
+        set_motion_pattern(app.patterns[app.selected_pattern].1).ok();
         set_motion_enabled(app.motion_enabled);
 
         app
@@ -131,6 +160,10 @@ impl OssmSim {
                             Line::new("position", self.position_points.as_slice())
                                 .color(Color32::GREEN),
                         );
+                        plot_ui.add(
+                            Line::new("motor position", self.motor_position_points.as_slice())
+                                .color(Color32::YELLOW),
+                        );
                     });
             });
             ui.vertical(|ui| {
@@ -171,6 +204,50 @@ impl OssmSim {
             });
         });
     }
+
+    fn draw_motor_model(&mut self, ui: &mut egui::Ui) {
+        let before = self.motor_config;
+        let config = &mut self.motor_config;
+
+        ui.collapsing("Motor Model", |ui| {
+            ui.add(egui::Slider::new(&mut config.latency_ms, 0..=20).text("Latency (ms)"));
+            ui.add(
+                egui::Slider::new(&mut config.step_quantization, 1..=1024)
+                    .logarithmic(true)
+                    .text("Step Quantization"),
+            );
+            ui.add(egui::Slider::new(&mut config.max_rpm, 100.0..=3000.0).text("Max RPM"));
+            ui.add(
+                egui::Slider::new(&mut config.max_acceleration, 1000.0..=100000.0)
+                    .logarithmic(true)
+                    .text("Max Acceleration (mm/s²)"),
+            );
+            ui.add(egui::Checkbox::new(
+                &mut config.position_lag,
+                "Position Lag",
+            ));
+            ui.add_enabled(
+                config.position_lag,
+                egui::Slider::new(&mut config.position_lag_ms, 0.0..=50.0)
+                    .text("Position Lag (ms)"),
+            );
+            ui.add(
+                egui::Slider::new(&mut config.min_write_interval_us, 0..=10000)
+                    .text("Min Write Interval (us)"),
+            );
+
+            if let Some(model) = &self.motor_model {
+                let dropped_writes = model.lock().expect("Motor model poisoned").dropped_writes;
+                ui.label(format!("Dropped writes: {dropped_writes}"));
+            }
+        });
+
+        if before != *config {
+            if let Some(model) = &self.motor_model {
+                model.lock().expect("Motor model poisoned").config = *config;
+            }
+        }
+    }
 }
 
 impl eframe::App for OssmSim {
@@ -185,6 +262,7 @@ impl eframe::App for OssmSim {
         for msg in rx.try_iter() {
             let point_vec = match msg.name {
                 "position" => &mut self.position_points,
+                "motor_position" => &mut self.motor_position_points,
                 "velocity" => &mut self.velocity_points,
                 "acceleration" => &mut self.acceleration_points,
                 _ => panic!("Unknown plot"),
@@ -228,28 +306,36 @@ impl eframe::App for OssmSim {
             ui.ctx().request_repaint();
             self.draw_plots(ui);
 
+            // The machine is controlled by its remotes
+            if let Some(status) = &self.device_status {
+                ui.label(status.lock().expect("Device status poisoned").as_str());
+                return;
+            }
+
+            self.draw_motor_model(ui);
+
             let before = self.depth;
             ui.add(egui::Slider::new(&mut self.depth, 0..=100).text("Depth"));
             if before != self.depth {
-                set_motion_depth_pct(self.depth);
+                set_motion_depth_pct(self.depth).ok();
             }
 
             let before = self.length;
             ui.add(egui::Slider::new(&mut self.length, 0..=100).text("Length"));
             if before != self.length {
-                set_motion_length_pct(self.length);
+                set_motion_length_pct(self.length).ok();
             }
 
             let before = self.velocity;
             ui.add(egui::Slider::new(&mut self.velocity, 0..=100).text("Velocity"));
             if before != self.velocity {
-                set_motion_velocity_pct(self.velocity);
+                set_motion_velocity_pct(self.velocity).ok();
             }
 
             let before = self.sensation;
             ui.add(egui::Slider::new(&mut self.sensation, 0..=100).text("Sensation"));
             if before != self.sensation {
-                set_motion_sensation_pct(self.sensation);
+                set_motion_sensation_pct(self.sensation).ok();
             }
 
             let before = self.motion_enabled;
@@ -269,7 +355,7 @@ impl eframe::App for OssmSim {
                 |i| &self.patterns[i].0,
             );
             if before != self.selected_pattern {
-                set_motion_pattern(self.patterns[self.selected_pattern].1);
+                set_motion_pattern(self.patterns[self.selected_pattern].1).ok();
             }
         });
     }
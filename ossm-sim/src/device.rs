@@ -0,0 +1,205 @@
+use std::{
+    fmt::{self, Display},
+    sync::{Arc, Mutex, mpsc::Sender},
+};
+
+use btleplug::{
+    api::{Central, Characteristic, Manager as _, Peripheral as _, ScanFilter, WriteType},
+    platform::{Adapter, Manager, Peripheral},
+};
+use embassy_time::{Instant, Timer};
+use futures::StreamExt;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::plotting::PlotMessage;
+
+const SERVICE_UUID: Uuid = Uuid::from_u128(0x522b443a_4f53_534d_0001_420badbabe69);
+const CURRENT_STATE_UUID: Uuid = Uuid::from_u128(0x522b443a_4f53_534d_2000_420badbabe69);
+const DEBUG_STREAM_UUID: Uuid = Uuid::from_u128(0x522b443a_4f53_534d_7010_420badbabe69);
+// How often the found peripherals are checked for a machine while scanning
+const SCAN_POLL_MS: u64 = 500;
+// How long to wait before connecting again after the connection failed or was lost
+const RECONNECT_DELAY_MS: u64 = 2000;
+
+/// What the connection is doing. Shown instead of the simulation controls
+pub type DeviceStatus = Arc<Mutex<String>>;
+
+#[derive(Debug)]
+pub enum DeviceError {
+    Ble(btleplug::Error),
+    NoAdapter,
+    // The machine runs a firmware without the characteristic
+    MissingCharacteristic(Uuid),
+    Disconnected,
+}
+
+impl From<btleplug::Error> for DeviceError {
+    fn from(err: btleplug::Error) -> Self {
+        DeviceError::Ble(err)
+    }
+}
+
+impl Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::Ble(err) => write!(f, "{err}"),
+            DeviceError::NoAdapter => write!(f, "No Bluetooth adapter"),
+            DeviceError::MissingCharacteristic(uuid) => {
+                write!(f, "The machine has no characteristic {uuid}")
+            }
+            DeviceError::Disconnected => write!(f, "Disconnected"),
+        }
+    }
+}
+
+/// `--device [name]` on the command line
+pub struct DeviceArgs {
+    // The local name of the machine to connect to. The first one found if None
+    pub name: Option<String>,
+}
+
+impl DeviceArgs {
+    /// None if the simulation should run instead
+    pub fn from_args() -> Option<Self> {
+        let mut args = std::env::args().skip_while(|arg| arg != "--device");
+        args.next()?;
+        Some(Self {
+            name: args.next().filter(|name| !name.starts_with("--")),
+        })
+    }
+}
+
+/// The part of the state JSON that is plotted
+#[derive(Deserialize)]
+struct State {
+    // The actual position of the motor in mm
+    position: f64,
+}
+
+/// Plot the machine until the app is closed. Connects again when the connection is lost
+/// The machine only streams every `DEBUG_STREAM_DECIMATION`-th update and notifies the state
+/// every `stateIntervalMs`, so the plots are coarser than the simulated ones
+pub async fn run_device(tx: Sender<PlotMessage>, args: DeviceArgs, status: DeviceStatus) {
+    loop {
+        if let Err(err) = plot_device(&tx, args.name.as_deref(), &status).await {
+            log::error!("Device: {err}");
+            set_status(&status, format!("{err}. Connecting again"));
+        }
+        Timer::after_millis(RECONNECT_DELAY_MS).await;
+    }
+}
+
+async fn plot_device(
+    tx: &Sender<PlotMessage>,
+    name: Option<&str>,
+    status: &DeviceStatus,
+) -> Result<(), DeviceError> {
+    let manager = Manager::new().await?;
+    let adapter = manager
+        .adapters()
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(DeviceError::NoAdapter)?;
+
+    set_status(
+        status,
+        format!("Scanning for {}", name.unwrap_or("a machine")),
+    );
+    let machine = find_machine(&adapter, name).await?;
+    machine.connect().await?;
+    machine.discover_services().await?;
+
+    let state = find_characteristic(&machine, CURRENT_STATE_UUID)?;
+    let debug_stream = find_characteristic(&machine, DEBUG_STREAM_UUID)?;
+    let mut notifications = machine.notifications().await?;
+    machine.subscribe(&state).await?;
+    machine.subscribe(&debug_stream).await?;
+    machine
+        .write(&debug_stream, b"start", WriteType::WithResponse)
+        .await?;
+
+    let name = match machine.properties().await? {
+        Some(properties) => properties.local_name.unwrap_or_default(),
+        None => String::new(),
+    };
+    set_status(status, format!("Connected to {name}"));
+
+    // The clock of the machine started when it booted
+    let mut clock_offset_ms = None;
+    while let Some(notification) = notifications.next().await {
+        let Ok(value) = str::from_utf8(&notification.value) else {
+            continue;
+        };
+        let now_s = Instant::now().as_micros() as f64 / 1000000.0;
+
+        match notification.uuid {
+            DEBUG_STREAM_UUID => {
+                // `<time ms>:<position mm>:<velocity mm/s>:<acceleration mm/s²>`
+                let values: Vec<f64> = value
+                    .split(':')
+                    .filter_map(|value| value.parse().ok())
+                    .collect();
+                let [time_ms, position, velocity, acceleration] = values[..] else {
+                    // e.g. `fail:start:busy` when another central has the debug stream
+                    log::warn!("Device: {value}");
+                    continue;
+                };
+                let offset_ms = *clock_offset_ms.get_or_insert(now_s * 1000.0 - time_ms);
+                let time = (time_ms + offset_ms) / 1000.0;
+                tx.send(PlotMessage::new("position", time, position)).ok();
+                tx.send(PlotMessage::new("velocity", time, velocity)).ok();
+                tx.send(PlotMessage::new("acceleration", time, acceleration))
+                    .ok();
+            }
+            CURRENT_STATE_UUID => match serde_json::from_str::<State>(value) {
+                Ok(state) => {
+                    tx.send(PlotMessage::new("motor_position", now_s, state.position))
+                        .ok();
+                }
+                Err(err) => log::warn!("Device: Could not parse the state {err}"),
+            },
+            _ => {}
+        }
+    }
+
+    Err(DeviceError::Disconnected)
+}
+
+/// Scan until a machine with the name is found, or any machine without one
+async fn find_machine(adapter: &Adapter, name: Option<&str>) -> Result<Peripheral, DeviceError> {
+    adapter
+        .start_scan(ScanFilter {
+            services: vec![SERVICE_UUID],
+        })
+        .await?;
+
+    loop {
+        for peripheral in adapter.peripherals().await? {
+            let Some(properties) = peripheral.properties().await? else {
+                continue;
+            };
+            let is_machine = properties.services.contains(&SERVICE_UUID);
+            if is_machine && name.is_none_or(|name| properties.local_name.as_deref() == Some(name))
+            {
+                adapter.stop_scan().await?;
+                return Ok(peripheral);
+            }
+        }
+        Timer::after_millis(SCAN_POLL_MS).await;
+    }
+}
+
+fn find_characteristic(machine: &Peripheral, uuid: Uuid) -> Result<Characteristic, DeviceError> {
+    machine
+        .characteristics()
+        .into_iter()
+        .find(|characteristic| characteristic.uuid == uuid)
+        .ok_or(DeviceError::MissingCharacteristic(uuid))
+}
+
+fn set_status(status: &DeviceStatus, text: String) {
+    log::info!("Device: {text}");
+    *status.lock().expect("Device status poisoned") = text;
+}
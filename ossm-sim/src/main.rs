@@ -1,15 +1,28 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
 mod app;
+#[cfg(all(feature = "device", not(target_arch = "wasm32")))]
+mod device;
 mod motion_control;
+mod motor;
 mod plotting;
 
 use crate::motion_control::run_motion_control;
+use crate::motor::{MotorModel, SimMotorConfig};
 
-use ossm_motion::motion::run_motion;
+use ossm_motion::{motion::run_motion, utils::rng::seed_rng};
 
 use crate::plotting::PlotMessage;
-use std::sync::mpsc::channel;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::mpsc::channel,
+};
+
+/// A seed for the patterns from the random keys std uses for hash maps
+fn random_seed() -> u32 {
+    RandomState::new().build_hasher().finish() as u32
+}
 
 // When compiling natively:
 #[cfg(not(target_arch = "wasm32"))]
@@ -24,8 +37,22 @@ fn main() -> eframe::Result {
         .unwrap();
 
     let (tx, rx) = channel::<PlotMessage>();
-    let _motion_control = runtime.spawn(run_motion_control(tx));
-    let _motion = runtime.spawn(run_motion());
+    let motor_model = MotorModel::new_shared(SimMotorConfig::default());
+
+    #[cfg(feature = "device")]
+    let device_status = device::DeviceArgs::from_args().map(|args| {
+        let status = device::DeviceStatus::default();
+        runtime.spawn(device::run_device(tx.clone(), args, status.clone()));
+        status
+    });
+    #[cfg(not(feature = "device"))]
+    let device_status = None;
+
+    if device_status.is_none() {
+        let _motion_control = runtime.spawn(run_motion_control(tx, motor_model.clone()));
+        seed_rng(random_seed());
+        let _motion = runtime.spawn(run_motion());
+    }
 
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -41,7 +68,14 @@ fn main() -> eframe::Result {
     eframe::run_native(
         "OSSM-SIM",
         native_options,
-        Box::new(|cc| Ok(Box::new(app::OssmSim::new(cc, rx)))),
+        Box::new(|cc| {
+            Ok(Box::new(app::OssmSim::new(
+                cc,
+                rx,
+                motor_model,
+                device_status,
+            )))
+        }),
     )
 }
 
@@ -54,7 +88,10 @@ fn main() {
     eframe::WebLogger::init(log::LevelFilter::Info).ok();
 
     let (tx, rx) = channel::<PlotMessage>();
-    let _motion_control = wasm_bindgen_futures::spawn_local(run_motion_control(tx));
+    let motor_model = MotorModel::new_shared(SimMotorConfig::default());
+    let _motion_control =
+        wasm_bindgen_futures::spawn_local(run_motion_control(tx, motor_model.clone()));
+    seed_rng(random_seed());
     let _motion = wasm_bindgen_futures::spawn_local(run_motion());
 
     let web_options = eframe::WebOptions::default();
@@ -75,7 +112,7 @@ fn main() {
             .start(
                 canvas,
                 web_options,
-                Box::new(|cc| Ok(Box::new(app::OssmSim::new(cc, rx)))),
+                Box::new(|cc| Ok(Box::new(app::OssmSim::new(cc, rx, motor_model, None)))),
             )
             .await;
 
@@ -1,4 +1,4 @@
-use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, mpsc::Sender};
 
 use embassy_time::{Instant, Ticker};
 use ossm_motion::{
@@ -6,57 +6,43 @@ use ossm_motion::{
     motion_control::{
         MotionControl,
         debug::DebugOut,
-        motor::Motor,
-        timer::{Timer, TimerDuration, TimerInstant},
+        timer::{Timer, TimerInstant},
     },
 };
 
-use crate::plotting::PlotMessage;
+use crate::{
+    motor::{MotorModel, SimMotor},
+    plotting::PlotMessage,
+};
 
-pub async fn run_motion_control(tx: Sender<PlotMessage>) {
-    let motor = DummyMotor::new();
+pub async fn run_motion_control(tx: Sender<PlotMessage>, motor_model: Arc<Mutex<MotorModel>>) {
+    let motor = SimMotor::new(motor_model.clone());
     let timer = StdTimer::new();
-    let debug = PlotDebug::new(tx);
-    let mut motion_control = MotionControl::new_with_debug(motor, timer, debug);
+    let debug = PlotDebug::new(tx.clone());
+    let mut motion_control: MotionControl<_, _, _> =
+        MotionControl::new_with_debug(motor, timer, debug);
+
+    let model_timer = StdTimer::new();
 
     let mut ticker = Ticker::every(embassy_time::Duration::from_millis(
         MOTION_CONTROL_LOOP_UPDATE_INTERVAL_MS,
     ));
     loop {
         motion_control.update_handler();
-        ticker.next().await;
-    }
-}
-
-struct DummyMotor {}
-
-impl DummyMotor {
-    pub fn new() -> Self {
-        Self {}
-    }
-}
-
-impl Motor for DummyMotor {
-    type MotorError = ();
-
-    fn min_consecutive_write_delay() -> TimerDuration {
-        TimerDuration::millis(1)
-    }
-
-    fn set_absolute_position(&mut self, _steps: i32) -> Result<(), Self::MotorError> {
-        Ok(())
-    }
 
-    fn set_max_allowed_output(&mut self, _output: u16) -> Result<(), Self::MotorError> {
-        Ok(())
-    }
+        let motor_position = motor_model
+            .lock()
+            .expect("Motor model poisoned")
+            .update(model_timer.now());
+        let time = Instant::now().as_micros() as f64 / 1000000.0;
+        tx.send(PlotMessage::new("motor_position", time, motor_position))
+            .unwrap();
 
-    fn delay(&mut self, duration: TimerDuration) {
-        // TODO: Now noop to be compatible with WASM
+        ticker.next().await;
     }
 }
 
-struct StdTimer {}
+pub struct StdTimer {}
 
 impl StdTimer {
     pub fn new() -> Self {
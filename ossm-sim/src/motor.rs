@@ -0,0 +1,216 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use ossm_motion::{
+    config::{MIN_MOVE_MM, MM_PER_ROTATION, STEPS_PER_MM},
+    motion_control::{
+        is_direction_reversed,
+        motor::Motor,
+        timer::{Timer, TimerDuration, TimerInstant},
+    },
+    time::timer_elapsed,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::motion_control::StdTimer;
+
+// The share of the full output the 57AIMxx still has at 0% torque (12 of 60)
+const MIN_TORQUE_FACTOR: f64 = 0.2;
+
+/// Parameters of the simulated motor
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SimMotorConfig {
+    // Time between a command being sent and the motor acting on it in ms
+    pub latency_ms: u64,
+    // Commanded positions are rounded to a multiple of this many steps
+    pub step_quantization: u32,
+    // Maximum motor speed in RPM
+    pub max_rpm: f64,
+    // Maximum acceleration at full torque in mm/s²
+    pub max_acceleration: f64,
+    // Simulate the motor lagging behind the commanded position
+    pub position_lag: bool,
+    // Time constant of the position lag in ms
+    pub position_lag_ms: f64,
+    // Writes closer together than this are dropped like the real motor would time out
+    pub min_write_interval_us: u64,
+}
+
+impl Default for SimMotorConfig {
+    fn default() -> Self {
+        Self {
+            latency_ms: 2,
+            step_quantization: 1,
+            max_rpm: 3000.0,
+            max_acceleration: 50000.0,
+            position_lag: true,
+            position_lag_ms: 5.0,
+            min_write_interval_us: 1000,
+        }
+    }
+}
+
+/// The physical state of the simulated motor shared between the motor and the UI
+pub struct MotorModel {
+    pub config: SimMotorConfig,
+    // Commands that were sent, but have not reached the motor yet
+    pending: VecDeque<(TimerInstant, i32)>,
+    target_steps: f64,
+    position_steps: f64,
+    velocity_steps: f64,
+    // Share of the full output the motor accelerates with
+    torque_factor: f64,
+    last_update: Option<TimerInstant>,
+    last_write: Option<TimerInstant>,
+    // Number of commands dropped because they were sent too quickly
+    pub dropped_writes: u64,
+}
+
+impl MotorModel {
+    pub fn new(config: SimMotorConfig) -> Self {
+        // The real motor starts at the minimum position after homing
+        let mut start_steps = MIN_MOVE_MM * STEPS_PER_MM;
+        if !is_direction_reversed() {
+            start_steps = -start_steps;
+        }
+
+        Self {
+            config,
+            pending: VecDeque::new(),
+            target_steps: start_steps,
+            position_steps: start_steps,
+            velocity_steps: 0.0,
+            torque_factor: 1.0,
+            last_update: None,
+            last_write: None,
+            dropped_writes: 0,
+        }
+    }
+
+    pub fn new_shared(config: SimMotorConfig) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self::new(config)))
+    }
+
+    fn command(&mut self, now: TimerInstant, steps: i32) -> Result<(), SimMotorError> {
+        if let Some(last_write) = self.last_write {
+            let since_last_write = timer_elapsed(last_write, now);
+            if since_last_write < TimerDuration::micros(self.config.min_write_interval_us) {
+                self.dropped_writes += 1;
+                return Err(SimMotorError::Timeout);
+            }
+        }
+        self.last_write = Some(now);
+
+        let quantization = self.config.step_quantization.max(1) as i32;
+        let steps = (steps / quantization) * quantization;
+
+        let apply_at = now + TimerDuration::millis(self.config.latency_ms);
+        self.pending.push_back((apply_at, steps));
+
+        Ok(())
+    }
+
+    /// Advance the simulation to the given time and return the motor position in mm
+    pub fn update(&mut self, now: TimerInstant) -> f64 {
+        while let Some((apply_at, steps)) = self.pending.front() {
+            if *apply_at > now {
+                break;
+            }
+            self.target_steps = *steps as f64;
+            self.pending.pop_front();
+        }
+
+        let dt = match self.last_update {
+            Some(last_update) => timer_elapsed(last_update, now).to_micros() as f64 / 1_000_000.0,
+            None => 0.0,
+        };
+        self.last_update = Some(now);
+
+        if dt > 0.0 {
+            let max_velocity = self.config.max_rpm / 60.0 * MM_PER_ROTATION * STEPS_PER_MM;
+            let max_acceleration =
+                self.config.max_acceleration * STEPS_PER_MM * self.torque_factor;
+
+            let error = self.target_steps - self.position_steps;
+            let desired_velocity = if self.config.position_lag && self.config.position_lag_ms > 0.0
+            {
+                error / (self.config.position_lag_ms / 1000.0)
+            } else {
+                error / dt
+            };
+            let desired_velocity = desired_velocity.clamp(-max_velocity, max_velocity);
+
+            let max_velocity_change = max_acceleration * dt;
+            self.velocity_steps += (desired_velocity - self.velocity_steps)
+                .clamp(-max_velocity_change, max_velocity_change);
+
+            let step = self.velocity_steps * dt;
+            // Do not overshoot the target. A step away from it still has to brake first
+            if step.abs() >= error.abs() && step.signum() == error.signum() {
+                self.position_steps = self.target_steps;
+                self.velocity_steps = 0.0;
+            } else {
+                self.position_steps += step;
+            }
+        }
+
+        self.position_mm()
+    }
+
+    pub fn position_mm(&self) -> f64 {
+        let mut position = self.position_steps / STEPS_PER_MM;
+        if !is_direction_reversed() {
+            position = -position;
+        }
+        position
+    }
+}
+
+#[derive(Debug)]
+pub enum SimMotorError {
+    Timeout,
+}
+
+/// A simulated motor that approximates the behaviour of the real one
+pub struct SimMotor {
+    model: Arc<Mutex<MotorModel>>,
+    timer: StdTimer,
+}
+
+impl SimMotor {
+    pub fn new(model: Arc<Mutex<MotorModel>>) -> Self {
+        Self {
+            model,
+            timer: StdTimer::new(),
+        }
+    }
+}
+
+impl Motor for SimMotor {
+    type MotorError = SimMotorError;
+
+    fn min_consecutive_write_delay() -> TimerDuration {
+        TimerDuration::millis(1)
+    }
+
+    fn set_absolute_position(&mut self, steps: i32) -> Result<(), Self::MotorError> {
+        let now = self.timer.now();
+        self.model
+            .lock()
+            .expect("Motor model poisoned")
+            .command(now, steps)
+    }
+
+    fn set_torque_pct(&mut self, torque: f64) -> Result<(), Self::MotorError> {
+        self.model.lock().expect("Motor model poisoned").torque_factor =
+            MIN_TORQUE_FACTOR + (1.0 - MIN_TORQUE_FACTOR) * torque / 100.0;
+        Ok(())
+    }
+
+    fn delay(&mut self, _duration: TimerDuration) {
+        // TODO: Now noop to be compatible with WASM
+    }
+}
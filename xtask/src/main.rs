@@ -5,18 +5,19 @@ use std::{
     str::FromStr,
 };
 
+use serde::Deserialize;
+
+mod size;
+
 const PROJECT_NAME: &str = "ossm-rs";
-const BOARDS: [&str; 8] = [
-    "waveshare",
-    "seeed_xiao_s3",
-    "atom_s3",
-    "ossm_v3",
-    "custom_s3",
-    "custom_c6",
-    "ossm_alt_v2",
-    "ossm_alt_v3",
-];
+// Relative to the project. One TOML file per board, see `build.rs` of the project
+const BOARDS_DIR: &str = "boards";
+// Selects the board for `build.rs` of the project
+const BOARD_ENV: &str = "OSSM_BOARD";
 const BINARIES_OUTPUT_DIR: &str = "release_binaries";
+// Relative to the project. Has the same two app partitions for every board
+const PARTITION_TABLE: &str = "partitions.csv";
+const APP_PARTITION_KB: u64 = 1920;
 
 type DynError = Box<dyn std::error::Error>;
 
@@ -26,40 +27,52 @@ struct Board {
     flash_mb: u8,
 }
 
+/// The part of a board file that is needed to build it. The pins are read by `build.rs`
+#[derive(Deserialize)]
+struct BoardFile {
+    mcu: String,
+    flash_mb: u8,
+}
+
 impl FromStr for Board {
     type Err = DynError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (name, mcu) = match s {
-            x @ "waveshare"
-            | x @ "seeed_xiao_s3"
-            | x @ "atom_s3"
-            | x @ "ossm_v3"
-            | x @ "ossm_alt_v3"
-            | x @ "custom_s3" => (x, Mcu::Esp32S3),
-            x @ "custom_c6" | x @ "ossm_alt_v2" => (x, Mcu::Esp32C6),
-            x => Err(format!("Invalid board: {}", x))?,
-        };
-
-        let flash_mb = match s {
-            "waveshare" | "ossm_v3" => 16,
-            "seeed_xiao_s3" | "atom_s3" | "custom_s3" | "ossm_alt_v3" => 8,
-            "custom_c6" | "ossm_alt_v2" => 4,
-            x => Err(format!("Invalid board: {}", x))?,
-        };
+        let path = project_root()
+            .join(BOARDS_DIR)
+            .join(s)
+            .with_extension("toml");
+        let file = fs::read_to_string(&path).map_err(|_| format!("Invalid board: {}", s))?;
+        let file: BoardFile =
+            toml::from_str(&file).map_err(|err| format!("Invalid board file {}: {}", s, err))?;
 
         Ok(Board {
-            name: name.to_string(),
-            mcu,
-            flash_mb,
+            name: s.to_string(),
+            mcu: Mcu::from_str(&file.mcu)?,
+            flash_mb: file.flash_mb,
         })
     }
 }
 
+/// The names of the board files
+fn boards() -> Result<Vec<String>, DynError> {
+    let mut boards = Vec::new();
+    for entry in fs::read_dir(project_root().join(BOARDS_DIR))? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+            && let Some(name) = path.file_stem().and_then(|name| name.to_str())
+        {
+            boards.push(name.to_string());
+        }
+    }
+    boards.sort();
+    Ok(boards)
+}
+
 struct Toolchain {
     channel: String,
-    components: Option<Vec<String>>,
-    targets: Option<Vec<String>>,
 }
 
 enum Mcu {
@@ -67,6 +80,18 @@ enum Mcu {
     Esp32C6,
 }
 
+impl FromStr for Mcu {
+    type Err = DynError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "esp32s3" => Ok(Mcu::Esp32S3),
+            "esp32c6" => Ok(Mcu::Esp32C6),
+            x => Err(format!("Invalid MCU: {}", x))?,
+        }
+    }
+}
+
 impl Mcu {
     fn target_triple(&self) -> &str {
         match self {
@@ -86,16 +111,36 @@ impl Mcu {
         match self {
             Mcu::Esp32S3 => Toolchain {
                 channel: "esp".to_string(),
-                components: None,
-                targets: None,
             },
             Mcu::Esp32C6 => Toolchain {
                 channel: "stable".to_string(),
-                components: Some(vec!["rust-src".to_string()]),
-                targets: Some(vec!["riscv32imac-unknown-none-elf".to_string()]),
             },
         }
     }
+
+    /// Statically allocated RAM (data, bss, heap and code in IRAM) allowed for the firmware
+    /// Leaves room for the stacks and the memory used by the radio at runtime
+    fn ram_budget_kb(&self) -> u64 {
+        match self {
+            Mcu::Esp32S3 => 384,
+            Mcu::Esp32C6 => 384,
+        }
+    }
+}
+
+impl Board {
+    /// Each of the two app partitions of `partitions.csv` for updates over WiFi
+    fn flash_budget_kb(&self) -> u64 {
+        APP_PARTITION_KB
+    }
+
+    fn elf_path(&self) -> PathBuf {
+        project_root()
+            .join("target")
+            .join(self.mcu.target_triple())
+            .join("release")
+            .join(PROJECT_NAME)
+    }
 }
 
 fn main() {
@@ -118,6 +163,14 @@ fn try_main() -> Result<(), DynError> {
             }
         }
         Some("build-all") => build_all()?,
+        Some("size") => {
+            let board_arg = env::args().nth(2);
+            if let Some(board) = board_arg {
+                size(&[board])?
+            } else {
+                size(&boards()?)?
+            }
+        }
         Some("clean") => clean()?,
         _ => print_help(),
     }
@@ -130,14 +183,13 @@ fn print_help() {
 Available Tasks:
 run: builds and runs the firmware
 build-all: builds all the firmware binaries
+size [board]: builds the board (or all boards) and reports the flash and RAM usage
 clean: remove all the built files
 "
     )
 }
 
 fn run_cargo_cmd(cmd: &str, board: &Board) -> Result<(), DynError> {
-    let feature = format!("board_{}", board.name);
-
     println!("Starting the build for {}", board.name);
     println!("Building in {}", project_root().to_str().unwrap());
 
@@ -150,7 +202,8 @@ fn run_cargo_cmd(cmd: &str, board: &Board) -> Result<(), DynError> {
         .arg(cmd)
         .arg("--release")
         .args(["--target", board.mcu.target_triple()])
-        .args(["--features", &feature]);
+        .args(["--features", board.mcu.chip()])
+        .env(BOARD_ENV, &board.name);
 
     let status = command.status()?;
 
@@ -176,22 +229,16 @@ fn build_all() -> Result<(), DynError> {
         fs::create_dir(&bin_dir)?;
     }
 
-    for board_str in BOARDS {
-        let board = Board::from_str(board_str)?;
-        let target_triple = board.mcu.target_triple();
-
-        let build_out_file = project_root()
-            .join("target")
-            .join(target_triple)
-            .join("release")
-            .join(PROJECT_NAME);
+    for board_str in boards()? {
+        let board = Board::from_str(&board_str)?;
+        let build_out_file = board.elf_path();
 
         println!("Build out: {}", build_out_file.to_str().unwrap());
 
         run_cargo_cmd("build", &board)?;
 
-        let elf_path = elf_dir.join(board_str).with_extension("elf");
-        let bin_path = bin_dir.join(board_str).with_extension("bin");
+        let elf_path = elf_dir.join(&board_str).with_extension("elf");
+        let bin_path = bin_dir.join(&board_str).with_extension("bin");
 
         fs::copy(&build_out_file, &elf_path)?;
 
@@ -202,6 +249,7 @@ fn build_all() -> Result<(), DynError> {
             .arg("--merge")
             .args(["--chip", board.mcu.chip()])
             .args(["--flash-size", &format!("{}mb", board.flash_mb)])
+            .args(["--partition-table", PARTITION_TABLE])
             .arg(
                 elf_path
                     .to_str()
@@ -222,13 +270,52 @@ fn build_all() -> Result<(), DynError> {
     Ok(())
 }
 
+fn size(boards: &[String]) -> Result<(), DynError> {
+    let mut over_budget = Vec::new();
+
+    for board_str in boards {
+        let board = Board::from_str(board_str)?;
+
+        run_cargo_cmd("build", &board)?;
+
+        let report = size::analyze(&board.elf_path())?;
+        let flash_budget = board.flash_budget_kb() * 1024;
+        let ram_budget = board.mcu.ram_budget_kb() * 1024;
+
+        println!("\nSize of {}", board.name);
+        report.print();
+        println!(
+            "{:<12} {:>12} {:>12}\n",
+            "budget",
+            size::format_kib(flash_budget),
+            size::format_kib(ram_budget)
+        );
+
+        if report.total.flash > flash_budget {
+            over_budget.push(format!("{}: flash", board.name));
+        }
+        if report.total.ram > ram_budget {
+            over_budget.push(format!("{}: RAM", board.name));
+        }
+    }
+
+    if !over_budget.is_empty() {
+        Err(format!(
+            "Size budget exceeded for {}",
+            over_budget.join(", ")
+        ))?;
+    }
+
+    Ok(())
+}
+
 fn clean() -> Result<(), DynError> {
     let output_dir = project_root().join(BINARIES_OUTPUT_DIR);
 
-    if let Err(err) = fs::remove_dir_all(output_dir) {
-        if err.kind() != std::io::ErrorKind::NotFound {
-            Err(err)?
-        }
+    if let Err(err) = fs::remove_dir_all(output_dir)
+        && err.kind() != std::io::ErrorKind::NotFound
+    {
+        Err(err)?
     }
 
     let status = Command::new("cargo")
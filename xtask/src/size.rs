@@ -0,0 +1,239 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::DynError;
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOBITS: u32 = 8;
+const SHF_WRITE: u32 = 0x1;
+const SHF_ALLOC: u32 = 0x2;
+
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+// Symbols are attributed to the subsystem of the first crate found in their mangled name
+const SUBSYSTEMS: [(&str, &[&str]); 8] = [
+    ("motion", &["ossm_motion", "rsruckig"]),
+    ("firmware", &["ossm_rs"]),
+    ("ble", &["trouble_host", "bt_hci"]),
+    ("radio", &["esp_radio", "esp_wifi_sys"]),
+    ("modbus", &["rmodbus"]),
+    (
+        "embassy",
+        &[
+            "embassy_executor",
+            "embassy_time",
+            "embassy_sync",
+            "embassy_futures",
+        ],
+    ),
+    (
+        "hal",
+        &[
+            "esp_hal",
+            "esp_rtos",
+            "esp_alloc",
+            "esp_backtrace",
+            "esp_println",
+            "esp_bootloader_esp_idf",
+        ],
+    ),
+    (
+        "core",
+        &[
+            "core",
+            "alloc",
+            "compiler_builtins",
+            "libm",
+            "heapless",
+            "num_traits",
+        ],
+    ),
+];
+// Symbols without a known crate e.g. the radio blobs
+const OTHER_SUBSYSTEM: &str = "other";
+
+#[derive(Default, Clone, Copy)]
+pub struct Usage {
+    pub flash: u64,
+    pub ram: u64,
+}
+
+struct Section {
+    name: String,
+    kind: u32,
+    flags: u32,
+    offset: usize,
+    size: u64,
+    link: usize,
+}
+
+impl Section {
+    /// Takes up space in the firmware image
+    fn in_flash(&self) -> bool {
+        self.flags & SHF_ALLOC != 0 && self.kind != SHT_NOBITS
+    }
+
+    /// Takes up space in RAM at runtime. Code placed in IRAM counts as well
+    /// The dummy sections only reserve address space and the stack gets whatever RAM is left
+    fn in_ram(&self) -> bool {
+        if self.name.contains("dummy") || self.name == ".stack" {
+            return false;
+        }
+
+        self.flags & SHF_ALLOC != 0
+            && (self.flags & SHF_WRITE != 0
+                || self.name.contains("rwtext")
+                || self.name.contains("iram"))
+    }
+}
+
+/// Totals and the flash and RAM usage per subsystem read from the firmware ELF
+pub struct SizeReport {
+    pub total: Usage,
+    pub subsystems: BTreeMap<&'static str, Usage>,
+}
+
+impl SizeReport {
+    pub fn print(&self) {
+        println!("{:<12} {:>12} {:>12}", "Subsystem", "Flash", "RAM");
+        for (name, usage) in &self.subsystems {
+            println!(
+                "{:<12} {:>12} {:>12}",
+                name,
+                format_kib(usage.flash),
+                format_kib(usage.ram)
+            );
+        }
+        println!(
+            "{:<12} {:>12} {:>12}",
+            "total",
+            format_kib(self.total.flash),
+            format_kib(self.total.ram)
+        );
+    }
+}
+
+pub fn format_kib(bytes: u64) -> String {
+    format!("{:.1} KiB", bytes as f64 / 1024.0)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, DynError> {
+    let bytes = data.get(offset..offset + 2).ok_or("ELF truncated")?;
+    Ok(u16::from_le_bytes(bytes.try_into()?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, DynError> {
+    let bytes = data.get(offset..offset + 4).ok_or("ELF truncated")?;
+    Ok(u32::from_le_bytes(bytes.try_into()?))
+}
+
+fn read_str(data: &[u8], offset: usize) -> String {
+    let bytes = data.get(offset..).unwrap_or_default();
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Only 32-bit little endian ELFs are supported. Both the ESP32-S3 and C6 use those
+fn read_sections(data: &[u8]) -> Result<Vec<Section>, DynError> {
+    if data.get(0..4) != Some(b"\x7fELF".as_slice()) {
+        Err("Not an ELF file")?;
+    }
+    if data[4] != 1 || data[5] != 1 {
+        Err("Only 32-bit little endian ELF files are supported")?;
+    }
+
+    let section_offset = read_u32(data, 0x20)? as usize;
+    let section_size = read_u16(data, 0x2E)? as usize;
+    let section_count = read_u16(data, 0x30)? as usize;
+    let names_index = read_u16(data, 0x32)? as usize;
+
+    let mut sections = Vec::with_capacity(section_count);
+    let mut name_offsets = Vec::with_capacity(section_count);
+    for i in 0..section_count {
+        let header = section_offset + i * section_size;
+        name_offsets.push(read_u32(data, header)? as usize);
+        sections.push(Section {
+            name: String::new(),
+            kind: read_u32(data, header + 4)?,
+            flags: read_u32(data, header + 8)?,
+            offset: read_u32(data, header + 16)? as usize,
+            size: read_u32(data, header + 20)? as u64,
+            link: read_u32(data, header + 24)? as usize,
+        });
+    }
+
+    let names_offset = sections.get(names_index).ok_or("No section names")?.offset;
+    for (section, name_offset) in sections.iter_mut().zip(name_offsets) {
+        section.name = read_str(data, names_offset + name_offset);
+    }
+
+    Ok(sections)
+}
+
+/// Find the subsystem of the crate that appears first in the mangled symbol name
+/// Both the legacy and the v0 mangling encode identifiers as `<length><identifier>`
+fn subsystem_of(symbol: &str) -> &'static str {
+    let mut best: Option<(usize, &'static str)> = None;
+
+    for (subsystem, crates) in SUBSYSTEMS {
+        for krate in crates {
+            let encoded = format!("{}{}", krate.len(), krate);
+            if let Some(position) = symbol.find(&encoded)
+                && best.is_none_or(|(best_position, _)| position < best_position)
+            {
+                best = Some((position, subsystem));
+            }
+        }
+    }
+
+    best.map_or(OTHER_SUBSYSTEM, |(_, subsystem)| subsystem)
+}
+
+pub fn analyze(elf_path: &Path) -> Result<SizeReport, DynError> {
+    let data = fs::read(elf_path)?;
+    let sections = read_sections(&data)?;
+
+    let mut total = Usage::default();
+    for section in &sections {
+        if section.in_flash() {
+            total.flash += section.size;
+        }
+        if section.in_ram() {
+            total.ram += section.size;
+        }
+    }
+
+    let mut subsystems: BTreeMap<&'static str, Usage> = BTreeMap::new();
+
+    let symtab = sections
+        .iter()
+        .find(|section| section.kind == SHT_SYMTAB)
+        .ok_or("No symbol table. Is the ELF stripped?")?;
+    let strtab = sections.get(symtab.link).ok_or("No symbol names")?;
+
+    let symbol_count = symtab.size as usize / 16;
+    for i in 0..symbol_count {
+        let symbol = symtab.offset + i * 16;
+        let size = read_u32(&data, symbol + 8)? as u64;
+        let symbol_type = data[symbol + 12] & 0xF;
+        let section_index = read_u16(&data, symbol + 14)? as usize;
+
+        if size == 0 || (symbol_type != STT_FUNC && symbol_type != STT_OBJECT) {
+            continue;
+        }
+        let Some(section) = sections.get(section_index) else {
+            continue;
+        };
+
+        let name = read_str(&data, strtab.offset + read_u32(&data, symbol)? as usize);
+        let usage = subsystems.entry(subsystem_of(&name)).or_default();
+        if section.in_flash() {
+            usage.flash += size;
+        }
+        if section.in_ram() {
+            usage.ram += size;
+        }
+    }
+
+    Ok(SizeReport { total, subsystems })
+}